@@ -2,7 +2,7 @@ use std::env;
 use std::path::Path;
 use std::process::Command;
 
-fn build_ffmpeg(dist_dir: &Path, enable_libnpp: bool) {
+fn build_ffmpeg(dist_dir: &Path, enable_libnpp: bool, enable_vaapi: bool, enable_nvenc: bool) {
     if dist_dir.exists() {
         return;
     }
@@ -18,6 +18,8 @@ fn build_ffmpeg(dist_dir: &Path, enable_libnpp: bool) {
         .current_dir("deps")
         .env("DIST", dist_dir)
         .env("ENABLE_LIBNPP", if enable_libnpp { "y" } else { "n" })
+        .env("ENABLE_VAAPI", if enable_vaapi { "y" } else { "n" })
+        .env("ENABLE_NVENC", if enable_nvenc { "y" } else { "n" })
         .status()
         .expect("Failed to run bash!")
         .success()
@@ -27,20 +29,58 @@ fn build_ffmpeg(dist_dir: &Path, enable_libnpp: bool) {
     }
 }
 
+/// Query pkg-config for the paths of the system's ffmpeg, missing pkg-config is not an error as
+/// the compiler's default paths may just work.
+fn pkg_config_paths(flag: &str, prefix: &str) -> Vec<String> {
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    match Command::new("pkg-config")
+        .args([
+            flag,
+            "libavcodec",
+            "libavfilter",
+            "libavformat",
+            "libavutil",
+        ])
+        .output()
+    {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .filter_map(|p| p.strip_prefix(prefix))
+            .map(String::from)
+            .collect(),
+        _ => vec![],
+    }
+}
+
 fn main() {
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
 
-    let dist_dir = Path::new("deps")
-        .canonicalize()
-        .unwrap()
-        .join(format!("dist_{}", target_os));
+    // there are no Nvidia GPUs for Windows on ARM
+    let nvenc_supported =
+        target_os == "linux" || (target_os == "windows" && target_arch == "x86_64");
+    let enable_vaapi = target_os == "linux" && env::var("CARGO_FEATURE_VAAPI").is_ok();
+    let enable_nvenc = nvenc_supported && env::var("CARGO_FEATURE_NVENC").is_ok();
+
+    // builds of ffmpeg without some of the hardware encoders are kept apart from the full one
+    let mut dist_name = format!("dist_{}", target_os);
+    if target_os == "windows" && target_arch != "x86_64" {
+        dist_name.push_str(&format!("_{}", target_arch));
+    }
+    if !enable_vaapi && target_os == "linux" {
+        dist_name.push_str("_novaapi");
+    }
+    if !enable_nvenc && nvenc_supported {
+        dist_name.push_str("_nonvenc");
+    }
+    let dist_dir = Path::new("deps").canonicalize().unwrap().join(dist_name);
 
     let enable_libnpp = env::var("I_AM_BUILDING_THIS_AT_HOME_AND_WANT_LIBNPP").map_or(false, |v| {
         ["y", "yes", "true", "1"].contains(&v.to_lowercase().as_str())
     });
 
     if env::var("CARGO_FEATURE_FFMPEG_SYSTEM").is_err() {
-        build_ffmpeg(&dist_dir, enable_libnpp);
+        build_ffmpeg(&dist_dir, enable_libnpp, enable_vaapi, enable_nvenc);
     }
 
     println!("cargo:rerun-if-changed=ts/lib.ts");
@@ -78,13 +118,21 @@ fn main() {
     }
 
     println!("cargo:rerun-if-changed=lib/encode_video.c");
+    println!("cargo:rerun-if-changed=lib/ffmpeg_compat.h");
     let mut cc_video = cc::Build::new();
     cc_video.file("lib/encode_video.c");
-    cc_video.include(dist_dir.join("include"));
-    if ["linux", "windows"].contains(&target_os.as_str()) {
+    if env::var("CARGO_FEATURE_FFMPEG_SYSTEM").is_ok() {
+        // some distributions install the headers of ffmpeg into a subdirectory
+        for path in pkg_config_paths("--cflags-only-I", "-I") {
+            cc_video.include(path);
+        }
+    } else {
+        cc_video.include(dist_dir.join("include"));
+    }
+    if enable_nvenc {
         cc_video.define("HAS_NVENC", None);
     }
-    if target_os == "linux" {
+    if enable_vaapi {
         cc_video.define("HAS_VAAPI", None);
     }
     if target_os == "macos" {
@@ -139,15 +187,18 @@ fn main() {
             "cargo:rustc-link-search={}",
             dist_dir.join("lib").to_string_lossy()
         );
+    } else {
+        for path in pkg_config_paths("--libs-only-L", "-L") {
+            println!("cargo:rustc-link-search={}", path);
+        }
     }
 
     if target_os == "linux" {
-        linux();
+        linux(enable_vaapi);
     }
 
     if target_os == "macos" {
-        println!("cargo:rustc-link-lib=framework=VideoToolbox");
-        println!("cargo:rustc-link-lib=framework=CoreMedia");
+        macos();
     }
 
     if target_os == "windows" {
@@ -161,16 +212,44 @@ fn main() {
     }
 }
 
-fn linux() {
+fn macos() {
+    println!("cargo:rerun-if-changed=lib/macos/screencapturekit.m");
+    println!("cargo:rerun-if-changed=lib/macos/cursor.m");
+
+    cc::Build::new()
+        .file("lib/macos/screencapturekit.m")
+        .file("lib/macos/cursor.m")
+        .flag("-fobjc-arc")
+        .compile("macos");
+
+    println!("cargo:rustc-link-lib=framework=VideoToolbox");
+    println!("cargo:rustc-link-lib=framework=CoreMedia");
+    println!("cargo:rustc-link-lib=framework=CoreVideo");
+    println!("cargo:rustc-link-lib=framework=Foundation");
+    println!("cargo:rustc-link-lib=framework=AppKit");
+    // weakly linked so Weylus still starts on macOS before 12.3, it falls back to CoreGraphics
+    println!("cargo:rustc-link-arg=-weak_framework");
+    println!("cargo:rustc-link-arg=ScreenCaptureKit");
+}
+
+fn linux(enable_vaapi: bool) {
     println!("cargo:rerun-if-changed=lib/linux/uniput.c");
     println!("cargo:rerun-if-changed=lib/linux/xcapture.c");
     println!("cargo:rerun-if-changed=lib/linux/xhelper.c");
     println!("cargo:rerun-if-changed=lib/linux/xhelper.h");
+    println!("cargo:rerun-if-changed=lib/linux/xdnd.c");
+    println!("cargo:rerun-if-changed=lib/linux/highlight.c");
+    println!("cargo:rerun-if-changed=lib/linux/xwatch.c");
+    println!("cargo:rerun-if-changed=lib/linux/netdev.c");
 
     cc::Build::new()
         .file("lib/linux/uinput.c")
         .file("lib/linux/xcapture.c")
         .file("lib/linux/xhelper.c")
+        .file("lib/linux/xdnd.c")
+        .file("lib/linux/highlight.c")
+        .file("lib/linux/xwatch.c")
+        .file("lib/linux/netdev.c")
         .compile("linux");
 
     println!("cargo:rustc-link-lib=X11");
@@ -178,7 +257,11 @@ fn linux() {
     println!("cargo:rustc-link-lib=Xrandr");
     println!("cargo:rustc-link-lib=Xfixes");
     println!("cargo:rustc-link-lib=Xcomposite");
+    println!("cargo:rustc-link-lib=Xdamage");
     println!("cargo:rustc-link-lib=Xi");
+    if !enable_vaapi {
+        return;
+    }
     let va_link_kind = if env::var("CARGO_FEATURE_VA_STATIC").is_ok() {
         "static"
     } else {
@@ -3,6 +3,7 @@
 set -ex
 
 export TARGET_OS="$CARGO_CFG_TARGET_OS"
+export TARGET_ARCH="$CARGO_CFG_TARGET_ARCH"
 
 if [ "$OSTYPE" == "linux-gnu" ]; then
     export HOST_OS="linux"
@@ -18,23 +19,42 @@ fi
 
 [ -z "$DIST" ] && export DIST="$PWD/dist"
 [ -z "$TARGET_OS" ] && export TARGET_OS="$HOST_OS"
+[ -z "$TARGET_ARCH" ] && export TARGET_ARCH="$(uname -m)"
 
 export NPROCS="$(nproc || echo 4)"
 
+[ -z "$ENABLE_VAAPI" ] && export ENABLE_VAAPI="y"
+[ -z "$ENABLE_NVENC" ] && export ENABLE_NVENC="y"
+if [ "$ENABLE_NVENC" == "y" ]; then
+    export NVENC_ARGS="--enable-nvenc --enable-ffnvcodec --enable-cuda-llvm"
+fi
+
 ./download.sh
 
 if [ "$TARGET_OS" == "windows" ]; then
+    # Windows on ARM is cross compiled with llvm-mingw, which uses the same prefix
+    if [ "$TARGET_ARCH" == "aarch64" ]; then
+        export MINGW_ARCH="aarch64"
+    else
+        export MINGW_ARCH="x86_64"
+    fi
     if [ "$HOST_OS" == "linux" ]; then
-        export CROSS_COMPILE="x86_64-w64-mingw32-"
-        export FFMPEG_EXTRA_ARGS="--arch=x86_64 --target-os=mingw64 \
-            --cross-prefix=x86_64-w64-mingw32- --enable-nvenc --enable-ffnvcodec \
-            --enable-cuda-llvm --enable-mediafoundation --pkg-config=pkg-config --enable-d3d11va"
+        export CROSS_COMPILE="$MINGW_ARCH-w64-mingw32-"
+        export FFMPEG_EXTRA_ARGS="--arch=$MINGW_ARCH --target-os=mingw64 \
+            --cross-prefix=$MINGW_ARCH-w64-mingw32- $NVENC_ARGS \
+            --enable-mediafoundation --pkg-config=pkg-config --enable-d3d11va"
         export FFMPEG_CFLAGS="-I$DIST/include"
         export FFMPEG_LIBRARY_PATH="-L$DIST/lib"
     else
         export CC="cl"
-        export FFMPEG_EXTRA_ARGS="--toolchain=msvc --enable-nvenc --enable-ffnvcodec \
-            --enable-cuda-llvm --enable-mediafoundation --enable-d3d11va"
+        export FFMPEG_EXTRA_ARGS="--toolchain=msvc $NVENC_ARGS \
+            --enable-mediafoundation --enable-d3d11va"
+        # msvc can not assemble the arm64 assembly of ffmpeg and x264 without gas-preprocessor
+        if [ "$TARGET_ARCH" == "aarch64" ]; then
+            export FFMPEG_EXTRA_ARGS="$FFMPEG_EXTRA_ARGS --arch=aarch64 --target-os=win64 \
+                --enable-cross-compile --disable-asm"
+            export X264_EXTRA_ARGS="--host=aarch64-w64-mingw32 --disable-asm"
+        fi
         export FFMPEG_CFLAGS="-I$DIST/include"
         export FFMPEG_LIBRARY_PATH="-LIBPATH:$DIST/lib"
     fi
@@ -42,12 +62,13 @@ else
     export FFMPEG_CFLAGS="-I$DIST/include"
     export FFMPEG_LIBRARY_PATH="-L$DIST/lib"
     if [ "$TARGET_OS" == "linux" ]; then
-        export FFMPEG_EXTRA_ARGS="--enable-nvenc \
-            --enable-ffnvcodec \
-            --enable-cuda-llvm \
-            --enable-vaapi \
-            --enable-libdrm \
-            --enable-xlib"
+        export FFMPEG_EXTRA_ARGS="$NVENC_ARGS"
+        if [ "$ENABLE_VAAPI" == "y" ]; then
+            export FFMPEG_EXTRA_ARGS="$FFMPEG_EXTRA_ARGS \
+                --enable-vaapi \
+                --enable-libdrm \
+                --enable-xlib"
+        fi
     fi
     if [ "$TARGET_OS" == "macos" ]; then
         export FFMPEG_EXTRA_ARGS="--enable-videotoolbox"
@@ -59,15 +80,14 @@ if [ "$ENABLE_LIBNPP" == "y" ]; then
 fi
 
 if [ "$TARGET_OS" == "windows" ] && [ "$HOST_OS" == "linux" ]; then
-    export X264_EXTRA_ARGS="--cross-prefix=x86_64-w64-mingw32- --host=x86_64-w64-mingw32"
+    export X264_EXTRA_ARGS="--cross-prefix=$MINGW_ARCH-w64-mingw32- --host=$MINGW_ARCH-w64-mingw32"
 fi
 ./x264.sh
-if [ "$TARGET_OS" == "linux" ]; then
+if [ "$ENABLE_NVENC" == "y" ] && [ "$TARGET_OS" != "macos" ]; then
     ./nv-codec-headers.sh
-    ./libva.sh
 fi
-if [ "$TARGET_OS" == "windows" ]; then
-    ./nv-codec-headers.sh
+if [ "$ENABLE_VAAPI" == "y" ] && [ "$TARGET_OS" == "linux" ]; then
+    ./libva.sh
 fi
 ./ffmpeg.sh
 
@@ -2,8 +2,9 @@
 
 set -ex
 
-# cross compile windows version
+# cross compile windows versions
 cargo build --target x86_64-pc-windows-gnu --release
+cargo build --target aarch64-pc-windows-gnullvm --release
 
 # cleanup cross compiled windows artifacts
 (cd deps && ./clean.sh)
@@ -28,6 +29,11 @@ PKGDIR="$PWD/packages"
   zip weylus-windows.zip weylus.exe
   mv weylus-windows.zip "$PKGDIR/"
 )
+(
+  cd target/aarch64-pc-windows-gnullvm/release/
+  zip weylus-windows-arm64.zip weylus.exe
+  mv weylus-windows-arm64.zip "$PKGDIR/"
+)
 
 # package linux
 (
@@ -1,4 +1,5 @@
 #include <stdint.h>
+#include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 
@@ -19,9 +20,11 @@
 #include <libavutil/pixfmt.h>
 
 #include "error.h"
+#include "ffmpeg_compat.h"
 #include "log.h"
 
 #ifdef HAS_VAAPI
+#include <libavutil/hwcontext_drm.h>
 #include <libavutil/hwcontext_vaapi.h>
 #include <va/va.h>
 #endif
@@ -46,6 +49,40 @@ typedef struct Scalers
 	AVFrame* frame_out;
 } Scalers;
 
+// frame in GPU memory shared via DMA-BUF, this lives in src/video.rs as well
+typedef struct DmaBufFrame
+{
+	int fd;
+	uint64_t size;
+	uint64_t offset;
+	uint64_t pitch;
+	uint32_t drm_format;
+	uint64_t modifier;
+	int width;
+	int height;
+	int crop_x;
+	int crop_y;
+	int crop_width;
+	int crop_height;
+} DmaBufFrame;
+
+// tradeoff between latency and quality, this lives in src/video.rs as well
+typedef struct EncoderTuning
+{
+	// 0 is the fastest preset of the encoder, 2 a slower one giving better quality
+	int speed;
+	int zerolatency;
+	int lookahead;
+	int b_frames;
+	int gop_size;
+	// upper bound of the bitrate in kbit/s, 0 for no limit
+	int max_bitrate;
+	// H.264 level_idc, e.g. 52 for level 5.2, 0 leaves it to the encoder
+	int level;
+	// refresh the picture column by column over gop_size frames instead of sending keyframes
+	int intra_refresh;
+} EncoderTuning;
+
 typedef struct VideoContext
 {
 	AVFormatContext* oc;
@@ -73,6 +110,19 @@ typedef struct VideoContext
 	int try_nvenc;
 	int try_videotoolbox;
 	int try_mediafoundation;
+	int force_keyframe;
+	int threads;
+	int slices;
+	EncoderTuning tuning;
+#ifdef HAS_VAAPI
+	// DMA-BUF frames are mapped to VAAPI surfaces, converted and scaled without leaving the GPU
+	ScaleContext dmabuf;
+	AVBufferRef* drm_device_ctx;
+	AVBufferRef* drm_frames_ctx;
+	uint32_t dmabuf_format;
+	int dmabuf_width;
+	int dmabuf_height;
+#endif
 } VideoContext;
 
 // this is a rust function and lives in src/video.rs
@@ -139,9 +189,34 @@ void set_codec_params(VideoContext* ctx)
 	ctx->c->time_base = TIME_BASE;
 	ctx->c->framerate = (AVRational){0, 1};
 
-	ctx->c->gop_size = 12;
-	// no B-frames to reduce latency
-	ctx->c->max_b_frames = 0;
+	ctx->c->gop_size = ctx->tuning.gop_size;
+	// B-frames add latency, they are only used if quality matters more
+	ctx->c->max_b_frames = ctx->tuning.b_frames;
+	if (ctx->tuning.max_bitrate > 0)
+	{
+		// constant quality encoders only use the maximum, the others aim for it
+		int64_t max_bitrate = (int64_t)ctx->tuning.max_bitrate * 1000;
+		ctx->c->bit_rate = max_bitrate;
+		ctx->c->rc_max_rate = max_bitrate;
+		// allow bursts of at most a second
+		ctx->c->rc_buffer_size = max_bitrate;
+	}
+	if (ctx->tuning.intra_refresh)
+	{
+		// only libx264 and nvenc support this, the others keep sending keyframes
+		av_opt_set(ctx->c->priv_data, "intra-refresh", "1", 0);
+		// keyframes requested for new clients still have to be IDR frames to start decoding at
+		av_opt_set(ctx->c->priv_data, "forced-idr", "1", 0);
+	}
+	if (ctx->tuning.level > 0)
+	{
+		// signaled in the avcC box of the init segment, clients check it can be decoded
+		char level[8];
+		snprintf(level, sizeof(level), "%d.%d", ctx->tuning.level / 10, ctx->tuning.level % 10);
+		ctx->c->level = ctx->tuning.level;
+		// hardware encoders take the level as option, encoders lacking it pick their own
+		av_opt_set(ctx->c->priv_data, "level", level, 0);
+	}
 	if (ctx->oc->oformat->flags & AVFMT_GLOBALHEADER)
 		ctx->c->flags |= AV_CODEC_FLAG_GLOBAL_HEADER;
 }
@@ -177,6 +252,9 @@ void init_scaler(
 	ctx->frame_in->format = pix_fmt_in;
 	ctx->frame_in->width = width_in;
 	ctx->frame_in->height = height_in;
+	// The pixels are never written to this buffer, fill_* point data[0] at the captured frame with
+	// whatever stride it has. The buffer only makes frame_in reference counted, otherwise the
+	// buffer source would copy every frame.
 	ret = av_frame_get_buffer(ctx->frame_in, 0);
 	if (ret)
 	{
@@ -234,14 +312,7 @@ void init_scaler(
 		goto end;
 	}
 
-	ret = av_opt_set_array(
-		ctx->buffersink_scale_ctx,
-		"pixel_formats",
-		AV_OPT_SEARCH_CHILDREN,
-		0,
-		1,
-		AV_OPT_TYPE_PIXEL_FMT,
-		&pix_fmt_out);
+	ret = compat_buffersink_set_pix_fmt(ctx->buffersink_scale_ctx, pix_fmt_out);
 	if (ret < 0)
 	{
 		log_warn("Cannot set output pixel format: %s", av_err2str(ret));
@@ -474,6 +545,163 @@ void scale_frame(ScaleContext* ctx, Error* err)
 	}
 }
 
+#ifdef HAS_VAAPI
+#define DRM_FOURCC(a, b, c, d) \
+	((uint32_t)(a) | ((uint32_t)(b) << 8) | ((uint32_t)(c) << 16) | ((uint32_t)(d) << 24))
+
+static enum AVPixelFormat drm_format_to_pix_fmt(uint32_t drm_format)
+{
+	switch (drm_format)
+	{
+	case DRM_FOURCC('X', 'R', '2', '4'):
+	case DRM_FOURCC('A', 'R', '2', '4'):
+		return AV_PIX_FMT_BGR0;
+	case DRM_FOURCC('X', 'B', '2', '4'):
+	case DRM_FOURCC('A', 'B', '2', '4'):
+		return AV_PIX_FMT_RGB0;
+	default:
+		return AV_PIX_FMT_NONE;
+	}
+}
+
+void destroy_dmabuf_scaler(VideoContext* ctx)
+{
+	// frame_out is shared with the other scalers and freed by destroy_scalers
+	destroy_scale_ctx(&ctx->dmabuf);
+	av_buffer_unref(&ctx->drm_frames_ctx);
+	av_buffer_unref(&ctx->drm_device_ctx);
+	ctx->dmabuf_format = 0;
+}
+
+void init_dmabuf_scaler(VideoContext* ctx, uint32_t drm_format, int width, int height, Error* err)
+{
+	int ret = 0;
+	ScaleContext* s = &ctx->dmabuf;
+	enum AVPixelFormat sw_format = drm_format_to_pix_fmt(drm_format);
+	if (sw_format == AV_PIX_FMT_NONE)
+		ERROR(err, 1, "Unsupported DMA-BUF format: %.4s!", (char*)&drm_format);
+
+	// the DRM device is derived from the VAAPI device so the frames can be mapped without copies
+	ret = av_hwdevice_ctx_create_derived(
+		&ctx->drm_device_ctx, AV_HWDEVICE_TYPE_DRM, ctx->hw_device_ctx, 0);
+	if (ret < 0)
+		ERROR(err, 1, "Failed to derive DRM device from VAAPI device: %s!", av_err2str(ret));
+
+	ctx->drm_frames_ctx = av_hwframe_ctx_alloc(ctx->drm_device_ctx);
+	if (!ctx->drm_frames_ctx)
+	{
+		destroy_dmabuf_scaler(ctx);
+		ERROR(err, 1, "Failed to create DRM frame context.");
+	}
+	AVHWFramesContext* frames_ctx = (AVHWFramesContext*)ctx->drm_frames_ctx->data;
+	frames_ctx->format = AV_PIX_FMT_DRM_PRIME;
+	frames_ctx->sw_format = sw_format;
+	frames_ctx->width = width;
+	frames_ctx->height = height;
+	if ((ret = av_hwframe_ctx_init(ctx->drm_frames_ctx)) < 0)
+	{
+		destroy_dmabuf_scaler(ctx);
+		ERROR(err, 1, "Failed to initialize DRM frame context: %s!", av_err2str(ret));
+	}
+
+	s->frame_out = ctx->scalers.frame_out;
+	s->frame_in = av_frame_alloc();
+	s->filter_graph_scale = avfilter_graph_alloc();
+	AVFilterInOut* outputs = avfilter_inout_alloc();
+	AVFilterInOut* inputs = avfilter_inout_alloc();
+	AVBufferSrcParameters* params = av_buffersrc_parameters_alloc();
+	if (!s->frame_in || !s->filter_graph_scale || !outputs || !inputs || !params)
+	{
+		ret = AVERROR(ENOMEM);
+		goto end;
+	}
+
+	avfilter_graph_set_auto_convert(s->filter_graph_scale, AVFILTER_AUTO_CONVERT_NONE);
+
+	s->buffersrc_scale_ctx =
+		avfilter_graph_alloc_filter(s->filter_graph_scale, avfilter_get_by_name("buffer"), "in");
+	if (!s->buffersrc_scale_ctx)
+	{
+		ret = AVERROR(ENOMEM);
+		goto end;
+	}
+	params->format = AV_PIX_FMT_DRM_PRIME;
+	params->width = width;
+	params->height = height;
+	params->time_base = TIME_BASE;
+	params->sample_aspect_ratio = (AVRational){1, 1};
+	params->hw_frames_ctx = ctx->drm_frames_ctx;
+	if ((ret = av_buffersrc_parameters_set(s->buffersrc_scale_ctx, params)) < 0 ||
+		(ret = avfilter_init_dict(s->buffersrc_scale_ctx, NULL)) < 0)
+	{
+		log_warn("Cannot init buffer source: %s", av_err2str(ret));
+		goto end;
+	}
+
+	s->buffersink_scale_ctx = avfilter_graph_alloc_filter(
+		s->filter_graph_scale, avfilter_get_by_name("buffersink"), "out");
+	if (!s->buffersink_scale_ctx)
+	{
+		ret = AVERROR(ENOMEM);
+		goto end;
+	}
+	if ((ret = compat_buffersink_set_pix_fmt(s->buffersink_scale_ctx, AV_PIX_FMT_VAAPI)) < 0 ||
+		(ret = avfilter_init_dict(s->buffersink_scale_ctx, NULL)) < 0)
+	{
+		log_warn("Cannot init buffer sink: %s", av_err2str(ret));
+		goto end;
+	}
+
+	outputs->name = av_strdup("in");
+	outputs->filter_ctx = s->buffersrc_scale_ctx;
+	outputs->pad_idx = 0;
+	outputs->next = NULL;
+
+	inputs->name = av_strdup("out");
+	inputs->filter_ctx = s->buffersink_scale_ctx;
+	inputs->pad_idx = 0;
+	inputs->next = NULL;
+
+	char args[512];
+	snprintf(
+		args,
+		sizeof(args),
+		"hwmap=derive_device=vaapi,scale_vaapi=w=%d:h=%d:format=nv12:mode=fast",
+		ctx->width_out,
+		ctx->height_out);
+
+	if ((ret = avfilter_graph_parse_ptr(s->filter_graph_scale, args, &inputs, &outputs, NULL)) < 0)
+	{
+		log_warn("Failed to parse filter");
+		goto end;
+	}
+
+	if ((ret = avfilter_graph_config(s->filter_graph_scale, NULL)) < 0)
+	{
+		log_warn("Failed to configure filter graph");
+		goto end;
+	}
+
+end:
+	av_free(params);
+	avfilter_inout_free(&inputs);
+	avfilter_inout_free(&outputs);
+
+	if (ret != 0)
+	{
+		destroy_dmabuf_scaler(ctx);
+		ERROR(err, 1, "Setting up scale filter for DMA-BUF frames failed: %s!", av_err2str(ret));
+	}
+
+	ctx->dmabuf_format = drm_format;
+	ctx->dmabuf_width = width;
+	ctx->dmabuf_height = height;
+	log_debug(
+		"Scale filter set drm_prime (%s) -> vaapi (sw: nv12) up!",
+		av_get_pix_fmt_name(sw_format));
+}
+#endif
+
 void open_video(VideoContext* ctx, Error* err)
 {
 	if (ctx->width_out <= 1 || ctx->height_out <= 1)
@@ -545,7 +773,11 @@ void open_video(VideoContext* ctx, Error* err)
 					ctx->c->pix_fmt = AV_PIX_FMT_VAAPI;
 					ctx->c->hw_frames_ctx = ctx->scalers.hw_frames_ctx;
 					av_opt_set(ctx->c->priv_data, "quality", "7", 0);
-					av_opt_set(ctx->c->priv_data, "qp", "23", 0);
+					// a constant quantizer ignores the maximum bitrate
+					if (ctx->tuning.max_bitrate > 0)
+						av_opt_set(ctx->c->priv_data, "rc_mode", "VBR", 0);
+					else
+						av_opt_set(ctx->c->priv_data, "qp", "23", 0);
 					set_codec_params(ctx);
 
 					if ((ret = avcodec_open2(ctx->c, codec, NULL) == 0))
@@ -596,6 +828,10 @@ void open_video(VideoContext* ctx, Error* err)
 					av_opt_set(ctx->c->priv_data, "rate_control", "ld_vbr", 0);
 					av_opt_set(ctx->c->priv_data, "scenario", "display_remoting", 0);
 					av_opt_set(ctx->c->priv_data, "quality", "100", 0);
+#if defined(_M_ARM64) || defined(__aarch64__)
+					// use the encoder of the SoC, Windows on ARM otherwise picks the software MFT
+					av_opt_set(ctx->c->priv_data, "hw_encoding", "1", 0);
+#endif
 					set_codec_params(ctx);
 					int ret = avcodec_open2(ctx->c, codec, NULL);
 					if (ret == 0)
@@ -650,12 +886,21 @@ void open_video(VideoContext* ctx, Error* err)
 				{
 					ctx->c->pix_fmt = AV_PIX_FMT_CUDA;
 					ctx->c->hw_frames_ctx = ctx->scalers.hw_frames_ctx;
-					av_opt_set(ctx->c->priv_data, "preset", "p1", 0);
-					av_opt_set(ctx->c->priv_data, "zerolatency", "1", 0);
-					av_opt_set(ctx->c->priv_data, "tune", "ull", 0);
+					const char* presets[] = {"p1", "p3", "p5"};
+					const char* tunes[] = {"ull", "ll", "hq"};
+					av_opt_set(ctx->c->priv_data, "preset", presets[ctx->tuning.speed], 0);
+					av_opt_set(ctx->c->priv_data, "tune", tunes[ctx->tuning.speed], 0);
 					av_opt_set(ctx->c->priv_data, "rc", "cbr", 0);
 					av_opt_set(ctx->c->priv_data, "cq", "21", 0);
-					av_opt_set(ctx->c->priv_data, "delay", "0", 0);
+					if (ctx->tuning.zerolatency)
+					{
+						av_opt_set(ctx->c->priv_data, "zerolatency", "1", 0);
+						av_opt_set(ctx->c->priv_data, "delay", "0", 0);
+					}
+					if (ctx->tuning.lookahead)
+						av_opt_set_int(
+							ctx->c->priv_data, "rc-lookahead", ctx->tuning.lookahead, 0);
+					av_opt_set(ctx->c->priv_data, "forced-idr", "1", 0);
 					set_codec_params(ctx);
 
 					int ret = avcodec_open2(ctx->c, codec, NULL);
@@ -705,7 +950,11 @@ void open_video(VideoContext* ctx, Error* err)
 				else
 				{
 					ctx->c->pix_fmt = AV_PIX_FMT_YUV420P;
-					av_opt_set(ctx->c->priv_data, "realtime", "true", 0);
+					av_opt_set(
+						ctx->c->priv_data,
+						"realtime",
+						ctx->tuning.zerolatency ? "true" : "false",
+						0);
 					av_opt_set(ctx->c->priv_data, "allow_sw", "true", 0);
 					av_opt_set(ctx->c->priv_data, "profile", "extended", 0);
 					av_opt_set(ctx->c->priv_data, "level", "5.2", 0);
@@ -755,9 +1004,18 @@ void open_video(VideoContext* ctx, Error* err)
 		}
 
 		ctx->c->pix_fmt = AV_PIX_FMT_YUV420P;
-		av_opt_set(ctx->c->priv_data, "preset", "ultrafast", 0);
-		av_opt_set(ctx->c->priv_data, "tune", "zerolatency", 0);
+		const char* presets[] = {"ultrafast", "superfast", "veryfast"};
+		av_opt_set(ctx->c->priv_data, "preset", presets[ctx->tuning.speed], 0);
+		if (ctx->tuning.zerolatency)
+			av_opt_set(ctx->c->priv_data, "tune", "zerolatency", 0);
+		else
+			av_opt_set_int(ctx->c->priv_data, "rc-lookahead", ctx->tuning.lookahead, 0);
 		av_opt_set(ctx->c->priv_data, "crf", "23", 0);
+		// slice based threading adds no latency, unlike frame based threading, hardware encoders
+		// are left alone as they may not support the requested number of slices
+		ctx->c->thread_count = ctx->threads;
+		ctx->c->thread_type = FF_THREAD_SLICE;
+		ctx->c->slices = ctx->slices;
 		set_codec_params(ctx);
 
 		ret = avcodec_open2(ctx->c, codec, NULL);
@@ -815,6 +1073,17 @@ void open_video(VideoContext* ctx, Error* err)
 	ctx->initialized = 1;
 }
 
+void request_keyframe(VideoContext* ctx) { ctx->force_keyframe = 1; }
+
+int h264_encoder_available() { return avcodec_find_encoder(AV_CODEC_ID_H264) != NULL; }
+
+const char* get_video_encoder_name(VideoContext* ctx)
+{
+	if (!ctx->initialized)
+		return NULL;
+	return ctx->c->codec->name;
+}
+
 void destroy_video_encoder(VideoContext* ctx)
 {
 	if (ctx->initialized)
@@ -827,24 +1096,17 @@ void destroy_video_encoder(VideoContext* ctx)
 		av_free(ctx->buf);
 		destroy_scalers(&ctx->scalers);
 	}
+#ifdef HAS_VAAPI
+	destroy_dmabuf_scaler(ctx);
+#endif
 	if (ctx->hw_device_ctx)
 		av_buffer_unref(&ctx->hw_device_ctx);
 	free(ctx);
 }
 
-void encode_video_frame(VideoContext* ctx, int millis, Error* err)
+void write_packets(VideoContext* ctx, Error* err)
 {
-	int ret;
-	AVFrame* frame = ctx->frame;
-	if (!frame)
-		ERROR(err, 1, "Frame not initialized!");
-
-	frame->pts = millis;
-
-	ret = avcodec_send_frame(ctx->c, frame);
-	if (ret < 0)
-		ERROR(err, 1, "Error sending a frame for encoding: %s", av_err2str(ret));
-
+	int ret = 0;
 	while (ret >= 0)
 	{
 		ret = avcodec_receive_packet(ctx->c, ctx->pkt);
@@ -864,6 +1126,38 @@ void encode_video_frame(VideoContext* ctx, int millis, Error* err)
 	}
 }
 
+void encode_video_frame(VideoContext* ctx, int millis, Error* err)
+{
+	int ret;
+	AVFrame* frame = ctx->frame;
+	if (!frame)
+		ERROR(err, 1, "Frame not initialized!");
+
+	frame->pts = millis;
+	compat_frame_set_keyframe(frame, ctx->force_keyframe);
+	ctx->force_keyframe = 0;
+
+	ret = avcodec_send_frame(ctx->c, frame);
+	if (ret < 0)
+		ERROR(err, 1, "Error sending a frame for encoding: %s", av_err2str(ret));
+
+	write_packets(ctx, err);
+}
+
+void flush_video_encoder(VideoContext* ctx, Error* err)
+{
+	int ret;
+	if (!ctx->initialized)
+		return;
+
+	// entering draining mode, the encoder returns all frames it still holds
+	ret = avcodec_send_frame(ctx->c, NULL);
+	if (ret < 0)
+		ERROR(err, 1, "Error flushing encoder: %s", av_err2str(ret));
+
+	write_packets(ctx, err);
+}
+
 VideoContext* init_video_encoder(
 	void* rust_ctx,
 	int width_in,
@@ -873,7 +1167,10 @@ VideoContext* init_video_encoder(
 	int try_vaapi,
 	int try_nvenc,
 	int try_videotoolbox,
-	int try_mediafoundation)
+	int try_mediafoundation,
+	int threads,
+	int slices,
+	const EncoderTuning* tuning)
 {
 	VideoContext* ctx = malloc(sizeof(VideoContext));
 	ctx->rust_ctx = rust_ctx;
@@ -888,10 +1185,24 @@ VideoContext* init_video_encoder(
 	ctx->try_nvenc = try_nvenc;
 	ctx->try_videotoolbox = try_videotoolbox;
 	ctx->try_mediafoundation = try_mediafoundation;
+	ctx->force_keyframe = 0;
+	ctx->threads = threads;
+	ctx->slices = slices;
+	ctx->tuning = *tuning;
+	if (ctx->tuning.speed < 0 || ctx->tuning.speed > 2)
+		ctx->tuning.speed = 0;
 	ctx->hw_device_ctx = NULL;
 
 	// make sure all scalers are zero initialized so that destroy can always be called
 	memset(&ctx->scalers, 0, sizeof(Scalers));
+#ifdef HAS_VAAPI
+	memset(&ctx->dmabuf, 0, sizeof(ScaleContext));
+	ctx->drm_device_ctx = NULL;
+	ctx->drm_frames_ctx = NULL;
+	ctx->dmabuf_format = 0;
+	ctx->dmabuf_width = 0;
+	ctx->dmabuf_height = 0;
+#endif
 	return ctx;
 }
 
@@ -920,14 +1231,72 @@ void fill_rgb(VideoContext* ctx, const void* data, Error* err)
 	ctx->frame = scaler->frame_out;
 }
 
-void fill_rgb0(VideoContext* ctx, const void* data, Error* err)
+void fill_rgb0(VideoContext* ctx, const void* data, int stride, Error* err)
 {
 	ctx->frame = NULL;
 	ScaleContext* scaler = &ctx->scalers.rgb0;
 	scaler->frame_in->data[0] = (uint8_t*)data;
-	scaler->frame_in->linesize[0] = ctx->width_in * 4;
+	scaler->frame_in->linesize[0] = stride;
 
 	scale_frame(scaler, err);
 	OK_OR_ABORT(err)
 	ctx->frame = scaler->frame_out;
 }
+
+void fill_dmabuf(VideoContext* ctx, const DmaBufFrame* dmabuf, Error* err)
+{
+	ctx->frame = NULL;
+#ifdef HAS_VAAPI
+	if (!ctx->initialized || ctx->c->pix_fmt != AV_PIX_FMT_VAAPI)
+		ERROR(err, 1, "DMA-BUF frames are only supported when encoding via VAAPI.");
+
+	if (ctx->dmabuf_format != dmabuf->drm_format || ctx->dmabuf_width != dmabuf->width ||
+		ctx->dmabuf_height != dmabuf->height)
+	{
+		destroy_dmabuf_scaler(ctx);
+		init_dmabuf_scaler(ctx, dmabuf->drm_format, dmabuf->width, dmabuf->height, err);
+		OK_OR_ABORT(err)
+	}
+
+	AVDRMFrameDescriptor* desc = av_mallocz(sizeof(AVDRMFrameDescriptor));
+	if (!desc)
+		ERROR(err, 1, "Failed to allocate DRM frame descriptor!");
+	desc->nb_objects = 1;
+	desc->objects[0].fd = dmabuf->fd;
+	desc->objects[0].size = dmabuf->size;
+	desc->objects[0].format_modifier = dmabuf->modifier;
+	desc->nb_layers = 1;
+	desc->layers[0].format = dmabuf->drm_format;
+	desc->layers[0].nb_planes = 1;
+	desc->layers[0].planes[0].object_index = 0;
+	desc->layers[0].planes[0].offset = dmabuf->offset;
+	desc->layers[0].planes[0].pitch = dmabuf->pitch;
+
+	// the fd is owned by the capturable, freeing the frame only frees the descriptor
+	AVFrame* frame_in = ctx->dmabuf.frame_in;
+	frame_in->buf[0] =
+		av_buffer_create((uint8_t*)desc, sizeof(*desc), av_buffer_default_free, NULL, 0);
+	if (!frame_in->buf[0])
+	{
+		av_free(desc);
+		ERROR(err, 1, "Failed to allocate buffer for DRM frame descriptor!");
+	}
+	frame_in->data[0] = (uint8_t*)desc;
+	frame_in->format = AV_PIX_FMT_DRM_PRIME;
+	frame_in->width = dmabuf->width;
+	frame_in->height = dmabuf->height;
+	frame_in->hw_frames_ctx = av_buffer_ref(ctx->drm_frames_ctx);
+	frame_in->crop_left = dmabuf->crop_x;
+	frame_in->crop_top = dmabuf->crop_y;
+	frame_in->crop_right = dmabuf->width - dmabuf->crop_x - dmabuf->crop_width;
+	frame_in->crop_bottom = dmabuf->height - dmabuf->crop_y - dmabuf->crop_height;
+
+	scale_frame(&ctx->dmabuf, err);
+	av_frame_unref(frame_in);
+	OK_OR_ABORT(err)
+	ctx->frame = ctx->dmabuf.frame_out;
+#else
+	(void)dmabuf;
+	ERROR(err, 1, "DMA-BUF frames are only supported when encoding via VAAPI.");
+#endif
+}
@@ -0,0 +1,61 @@
+#pragma once
+
+/*
+ * Abstracts over the differences between the supported versions of ffmpeg (5 to 8), this allows
+ * building against the version packaged by the distribution instead of the vendored one. The
+ * version is detected at build time from the headers.
+ *
+ * Only video is encoded, changes to the audio API like the new channel layouts do not matter.
+ */
+
+#include <libavcodec/avcodec.h>
+#include <libavfilter/avfilter.h>
+#include <libavutil/frame.h>
+#include <libavutil/opt.h>
+#include <libavutil/pixfmt.h>
+#include <libavutil/version.h>
+
+// ffmpeg 5
+#if LIBAVCODEC_VERSION_MAJOR < 59
+#error "ffmpeg 5 or newer is required."
+#endif
+
+// ffmpeg 7.1 added array options, the buffersink's pix_fmts option has been replaced by
+// pixel_formats and is removed in ffmpeg 8
+#define FFMPEG_HAS_OPT_ARRAY (LIBAVUTIL_VERSION_INT >= AV_VERSION_INT(59, 39, 100))
+
+// ffmpeg 6.1 replaced AVFrame.key_frame by AV_FRAME_FLAG_KEY
+#define FFMPEG_HAS_FRAME_FLAG_KEY (LIBAVUTIL_VERSION_INT >= AV_VERSION_INT(58, 29, 100))
+
+static inline int compat_buffersink_set_pix_fmt(
+	AVFilterContext* buffersink_ctx, enum AVPixelFormat pix_fmt)
+{
+#if FFMPEG_HAS_OPT_ARRAY
+	return av_opt_set_array(
+		buffersink_ctx,
+		"pixel_formats",
+		AV_OPT_SEARCH_CHILDREN,
+		0,
+		1,
+		AV_OPT_TYPE_PIXEL_FMT,
+		&pix_fmt);
+#else
+	enum AVPixelFormat pix_fmts[] = {pix_fmt, AV_PIX_FMT_NONE};
+	return av_opt_set_int_list(
+		buffersink_ctx, "pix_fmts", pix_fmts, AV_PIX_FMT_NONE, AV_OPT_SEARCH_CHILDREN);
+#endif
+}
+
+// ask the encoder to encode the frame as keyframe
+static inline void compat_frame_set_keyframe(AVFrame* frame, int keyframe)
+{
+	frame->pict_type = keyframe ? AV_PICTURE_TYPE_I : AV_PICTURE_TYPE_NONE;
+#if FFMPEG_HAS_FRAME_FLAG_KEY
+	if (keyframe)
+		frame->flags |= AV_FRAME_FLAG_KEY;
+	else
+		frame->flags &= ~AV_FRAME_FLAG_KEY;
+#else
+	frame->key_frame = keyframe;
+#endif
+}
@@ -0,0 +1,231 @@
+#include <X11/Xlib.h>
+#include <X11/Xutil.h>
+#include <X11/extensions/Xfixes.h>
+#include <X11/extensions/shape.h>
+#include <stdlib.h>
+
+// A ring following the pointer of a client in presentation mode. The window is shaped to the ring
+// and ignores input, so it neither hides nor catches anything below it.
+
+#define HIGHLIGHT_SIZE 48
+#define HIGHLIGHT_WIDTH 6
+
+typedef struct Highlight
+{
+	Display* disp;
+	Window win;
+	int mapped;
+} Highlight;
+
+Highlight* create_highlight()
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (disp == NULL)
+		return NULL;
+	int event_base, error_base;
+	if (!XShapeQueryExtension(disp, &event_base, &error_base) ||
+		!XFixesQueryExtension(disp, &event_base, &error_base))
+	{
+		XCloseDisplay(disp);
+		return NULL;
+	}
+
+	int screen = DefaultScreen(disp);
+	Window root = RootWindow(disp, screen);
+	XColor color;
+	Colormap colormap = DefaultColormap(disp, screen);
+	if (!XParseColor(disp, colormap, "#ff3030", &color) || !XAllocColor(disp, colormap, &color))
+		color.pixel = WhitePixel(disp, screen);
+
+	XSetWindowAttributes attrs;
+	attrs.override_redirect = True;
+	attrs.background_pixel = color.pixel;
+	attrs.save_under = True;
+	Window win = XCreateWindow(
+		disp,
+		root,
+		0,
+		0,
+		HIGHLIGHT_SIZE,
+		HIGHLIGHT_SIZE,
+		0,
+		CopyFromParent,
+		InputOutput,
+		CopyFromParent,
+		CWOverrideRedirect | CWBackPixel | CWSaveUnder,
+		&attrs);
+
+	// cut the window to a ring
+	Pixmap mask = XCreatePixmap(disp, win, HIGHLIGHT_SIZE, HIGHLIGHT_SIZE, 1);
+	GC gc = XCreateGC(disp, mask, 0, NULL);
+	XSetForeground(disp, gc, 0);
+	XFillRectangle(disp, mask, gc, 0, 0, HIGHLIGHT_SIZE, HIGHLIGHT_SIZE);
+	XSetForeground(disp, gc, 1);
+	XFillArc(disp, mask, gc, 0, 0, HIGHLIGHT_SIZE, HIGHLIGHT_SIZE, 0, 360 * 64);
+	XSetForeground(disp, gc, 0);
+	XFillArc(
+		disp,
+		mask,
+		gc,
+		HIGHLIGHT_WIDTH,
+		HIGHLIGHT_WIDTH,
+		HIGHLIGHT_SIZE - 2 * HIGHLIGHT_WIDTH,
+		HIGHLIGHT_SIZE - 2 * HIGHLIGHT_WIDTH,
+		0,
+		360 * 64);
+	XShapeCombineMask(disp, win, ShapeBounding, 0, 0, mask, ShapeSet);
+	XFreeGC(disp, gc);
+	XFreePixmap(disp, mask);
+
+	// let input pass through
+	XserverRegion region = XFixesCreateRegion(disp, NULL, 0);
+	XFixesSetWindowShapeRegion(disp, win, ShapeInput, 0, 0, region);
+	XFixesDestroyRegion(disp, region);
+	XFlush(disp);
+
+	Highlight* highlight = malloc(sizeof(Highlight));
+	highlight->disp = disp;
+	highlight->win = win;
+	highlight->mapped = 0;
+	return highlight;
+}
+
+// move the highlight to x, y given relative to the size of the root window
+void move_highlight(Highlight* highlight, double x, double y)
+{
+	Display* disp = highlight->disp;
+	int screen = DefaultScreen(disp);
+	int x_root = x * DisplayWidth(disp, screen) - HIGHLIGHT_SIZE / 2;
+	int y_root = y * DisplayHeight(disp, screen) - HIGHLIGHT_SIZE / 2;
+	XMoveWindow(disp, highlight->win, x_root, y_root);
+	if (!highlight->mapped)
+	{
+		XMapRaised(disp, highlight->win);
+		highlight->mapped = 1;
+	}
+	else
+		XRaiseWindow(disp, highlight->win);
+	XFlush(disp);
+}
+
+void hide_highlight(Highlight* highlight)
+{
+	if (!highlight->mapped)
+		return;
+	XUnmapWindow(highlight->disp, highlight->win);
+	highlight->mapped = 0;
+	XFlush(highlight->disp);
+}
+
+void destroy_highlight(Highlight* highlight)
+{
+	XDestroyWindow(highlight->disp, highlight->win);
+	XCloseDisplay(highlight->disp);
+	free(highlight);
+}
+
+// A border around the screen or a badge in its top right corner, shown on the host while a
+// client has control. Like the highlight it is shaped and ignores input.
+
+#define INDICATOR_BORDER_WIDTH 4
+#define INDICATOR_BADGE_SIZE 16
+#define INDICATOR_BADGE_MARGIN 8
+
+Highlight* create_indicator(const char* color_name, int badge)
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (disp == NULL)
+		return NULL;
+	int event_base, error_base;
+	if (!XShapeQueryExtension(disp, &event_base, &error_base) ||
+		!XFixesQueryExtension(disp, &event_base, &error_base))
+	{
+		XCloseDisplay(disp);
+		return NULL;
+	}
+
+	int screen = DefaultScreen(disp);
+	Window root = RootWindow(disp, screen);
+	int screen_width = DisplayWidth(disp, screen);
+	int screen_height = DisplayHeight(disp, screen);
+	XColor color;
+	Colormap colormap = DefaultColormap(disp, screen);
+	if (!XParseColor(disp, colormap, color_name, &color) || !XAllocColor(disp, colormap, &color))
+		color.pixel = WhitePixel(disp, screen);
+
+	int x = 0, y = 0, width = screen_width, height = screen_height;
+	if (badge)
+	{
+		x = screen_width - INDICATOR_BADGE_SIZE - INDICATOR_BADGE_MARGIN;
+		y = INDICATOR_BADGE_MARGIN;
+		width = INDICATOR_BADGE_SIZE;
+		height = INDICATOR_BADGE_SIZE;
+	}
+
+	XSetWindowAttributes attrs;
+	attrs.override_redirect = True;
+	attrs.background_pixel = color.pixel;
+	attrs.save_under = True;
+	Window win = XCreateWindow(
+		disp,
+		root,
+		x,
+		y,
+		width,
+		height,
+		0,
+		CopyFromParent,
+		InputOutput,
+		CopyFromParent,
+		CWOverrideRedirect | CWBackPixel | CWSaveUnder,
+		&attrs);
+
+	// cut the window to a frame or a dot
+	Pixmap mask = XCreatePixmap(disp, win, width, height, 1);
+	GC gc = XCreateGC(disp, mask, 0, NULL);
+	XSetForeground(disp, gc, 0);
+	XFillRectangle(disp, mask, gc, 0, 0, width, height);
+	XSetForeground(disp, gc, 1);
+	if (badge)
+		XFillArc(disp, mask, gc, 0, 0, width, height, 0, 360 * 64);
+	else
+	{
+		XFillRectangle(disp, mask, gc, 0, 0, width, height);
+		XSetForeground(disp, gc, 0);
+		XFillRectangle(
+			disp,
+			mask,
+			gc,
+			INDICATOR_BORDER_WIDTH,
+			INDICATOR_BORDER_WIDTH,
+			width - 2 * INDICATOR_BORDER_WIDTH,
+			height - 2 * INDICATOR_BORDER_WIDTH);
+	}
+	XShapeCombineMask(disp, win, ShapeBounding, 0, 0, mask, ShapeSet);
+	XFreeGC(disp, gc);
+	XFreePixmap(disp, mask);
+
+	// let input pass through
+	XserverRegion region = XFixesCreateRegion(disp, NULL, 0);
+	XFixesSetWindowShapeRegion(disp, win, ShapeInput, 0, 0, region);
+	XFixesDestroyRegion(disp, region);
+	XFlush(disp);
+
+	Highlight* indicator = malloc(sizeof(Highlight));
+	indicator->disp = disp;
+	indicator->win = win;
+	indicator->mapped = 0;
+	return indicator;
+}
+
+void show_indicator(Highlight* indicator)
+{
+	if (!indicator->mapped)
+	{
+		XMapRaised(indicator->disp, indicator->win);
+		indicator->mapped = 1;
+	}
+	else
+		XRaiseWindow(indicator->disp, indicator->win);
+	XFlush(indicator->disp);
+}
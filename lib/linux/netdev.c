@@ -0,0 +1,13 @@
+#include <errno.h>
+#include <string.h>
+#include <sys/socket.h>
+
+#include "../error.h"
+
+// Restrict the socket to the given network interface, it then only receives broadcasts arriving
+// on that interface and sends broadcasts out of it, regardless of the routing table.
+void bind_to_device(int fd, const char* name, Error* err)
+{
+	if (setsockopt(fd, SOL_SOCKET, SO_BINDTODEVICE, name, strlen(name)) != 0)
+		ERROR(err, 1, "Failed to bind socket to %s: %s", name, strerror(errno));
+}
@@ -1,3 +1,5 @@
+// struct ucred
+#define _GNU_SOURCE
 #include <errno.h>
 #include <fcntl.h>
 #include <limits.h>
@@ -9,6 +11,7 @@
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <sys/socket.h>
 #include <unistd.h>
 
 #include "../error.h"
@@ -154,6 +157,8 @@ void init_stylus(int fd, const char* name, Error* err)
 		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOOL_RUBBER");
 	if (ioctl(fd, UI_SET_KEYBIT, BTN_TOUCH) < 0)
 		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOUCH");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_STYLUS2) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_STYLUS2");
 
 	// setup sending timestamps
 	if (ioctl(fd, UI_SET_EVBIT, EV_MSC) < 0)
@@ -246,6 +251,33 @@ void init_touch(int fd, const char* name, Error* err)
 		ERROR(err, 1, "error: ioctl");
 }
 
+void init_joystick(int fd, const char* name, Error* err)
+{
+	// enable synchronization
+	if (ioctl(fd, UI_SET_EVBIT, EV_SYN) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_SYN");
+
+	// games only recognize joysticks that have at least one button
+	if (ioctl(fd, UI_SET_EVBIT, EV_KEY) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_KEY");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_SOUTH) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_SOUTH");
+
+	if (ioctl(fd, UI_SET_EVBIT, EV_ABS) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_ABS");
+
+	setup_abs(fd, ABS_X, -32767, 32767, 0, err);
+	OK_OR_ABORT(err);
+	setup_abs(fd, ABS_Y, -32767, 32767, 0, err);
+	OK_OR_ABORT(err);
+
+	setup(fd, name, err);
+	OK_OR_ABORT(err);
+
+	if (ioctl(fd, UI_DEV_CREATE) < 0)
+		ERROR(err, 1, "error: ioctl");
+}
+
 int init_uinput_keyboard(const char* name, Error* err)
 {
 	int device;
@@ -298,12 +330,142 @@ int init_uinput_touch(const char* name, Error* err)
 	return device;
 }
 
+int init_uinput_joystick(const char* name, Error* err)
+{
+	int device;
+
+	if ((device = open("/dev/uinput", O_WRONLY | O_NONBLOCK)) < 0)
+		fill_error(err, 101, "error: failed to open /dev/uinput");
+	else
+	{
+		init_joystick(device, name, err);
+	}
+	return device;
+}
+
 void destroy_uinput_device(int fd)
 {
 	ioctl(fd, UI_DEV_DESTROY);
 	close(fd);
 }
 
+// close the file descriptor without destroying the device, it lives on as long as another process
+// holds the device open
+void close_uinput_device(int fd)
+{
+	close(fd);
+}
+
+// release all keys, buttons and touches of the device, events of keys that are not pressed or not
+// supported by the device are dropped by the kernel
+void release_uinput_device(int fd)
+{
+	struct input_event ev;
+	memset(&ev, 0, sizeof(ev));
+	for (int slot = 0; slot < 10; ++slot)
+	{
+		ev.type = EV_ABS;
+		ev.code = ABS_MT_SLOT;
+		ev.value = slot;
+		write(fd, &ev, sizeof(ev));
+		ev.code = ABS_MT_TRACKING_ID;
+		ev.value = -1;
+		write(fd, &ev, sizeof(ev));
+	}
+	ev.type = EV_KEY;
+	ev.value = 0;
+	for (int code = 0; code < KEY_CNT; ++code)
+	{
+		ev.code = code;
+		write(fd, &ev, sizeof(ev));
+	}
+	ev.type = EV_SYN;
+	ev.code = SYN_REPORT;
+	write(fd, &ev, sizeof(ev));
+}
+
+// send a single byte over the unix socket, along with the file descriptors if n > 0
+void send_uinput_fds(int sock, const int* fds, int n, Error* err)
+{
+	char byte = n > 0;
+	struct iovec iov = {.iov_base = &byte, .iov_len = 1};
+	char control[CMSG_SPACE(sizeof(int) * 8)];
+	memset(control, 0, sizeof(control));
+	struct msghdr msg;
+	memset(&msg, 0, sizeof(msg));
+	msg.msg_iov = &iov;
+	msg.msg_iovlen = 1;
+	if (n > 0)
+	{
+		if (n > 8)
+			ERROR(err, 1, "error: can not send more than 8 file descriptors");
+		msg.msg_control = control;
+		msg.msg_controllen = CMSG_SPACE(sizeof(int) * n);
+		struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg);
+		cmsg->cmsg_level = SOL_SOCKET;
+		cmsg->cmsg_type = SCM_RIGHTS;
+		cmsg->cmsg_len = CMSG_LEN(sizeof(int) * n);
+		memcpy(CMSG_DATA(cmsg), fds, sizeof(int) * n);
+	}
+	if (sendmsg(sock, &msg, MSG_NOSIGNAL) < 0)
+		ERROR(err, 1, "error: failed to send file descriptors: %s", strerror(errno));
+}
+
+// uid of the process connected to the unix socket
+unsigned int uinput_peer_uid(int sock, Error* err)
+{
+	struct ucred cred;
+	socklen_t len = sizeof(cred);
+	if (getsockopt(sock, SOL_SOCKET, SO_PEERCRED, &cred, &len) < 0)
+	{
+		fill_error(err, 1, "error: failed to get credentials of peer: %s", strerror(errno));
+		return 0;
+	}
+	return cred.uid;
+}
+
+// receive the byte sent by send_uinput_fds, returns the number of file descriptors received
+int recv_uinput_fds(int sock, int* fds, int n, Error* err)
+{
+	char byte;
+	struct iovec iov = {.iov_base = &byte, .iov_len = 1};
+	char control[CMSG_SPACE(sizeof(int) * 8)];
+	memset(control, 0, sizeof(control));
+	struct msghdr msg;
+	memset(&msg, 0, sizeof(msg));
+	msg.msg_iov = &iov;
+	msg.msg_iovlen = 1;
+	msg.msg_control = control;
+	msg.msg_controllen = sizeof(control);
+	ssize_t len = recvmsg(sock, &msg, MSG_CMSG_CLOEXEC);
+	if (len < 0)
+	{
+		fill_error(err, 1, "error: failed to receive file descriptors");
+		return 0;
+	}
+	if (len == 0)
+	{
+		fill_error(err, 1, "error: connection closed");
+		return 0;
+	}
+	int received = 0;
+	for (struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg); cmsg != NULL; cmsg = CMSG_NXTHDR(&msg, cmsg))
+	{
+		if (cmsg->cmsg_level != SOL_SOCKET || cmsg->cmsg_type != SCM_RIGHTS)
+			continue;
+		int num = (cmsg->cmsg_len - CMSG_LEN(0)) / sizeof(int);
+		int* data = (int*)CMSG_DATA(cmsg);
+		for (int i = 0; i < num; ++i)
+		{
+			if (received < n)
+				fds[received++] = data[i];
+			else
+				close(data[i]);
+		}
+	}
+	return received;
+}
+
 void send_uinput_event(int device, int type, int code, int value, Error* err)
 {
 	struct input_event ev;
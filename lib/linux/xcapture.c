@@ -4,6 +4,7 @@
 
 #include <X11/extensions/XShm.h>
 #include <X11/extensions/Xcomposite.h>
+#include <X11/extensions/Xdamage.h>
 #include <X11/extensions/Xfixes.h>
 #include <stdlib.h>
 #include <string.h>
@@ -30,10 +31,23 @@ struct CaptureContext
 	Capturable cap;
 	XImage* ximg;
 	XShmSegmentInfo shminfo;
+	// shared memory is not available if the X server runs on another machine, the image is
+	// copied over the connection then
+	int use_shm;
 	int has_xfixes;
 	int has_offscreen;
 	int wayland;
 	Bool last_img_return;
+	// XDamage tells which parts of the screen changed, unchanged frames are not grabbed again
+	int has_damage;
+	int damage_event_base;
+	Damage root_damage;
+	// damage of the captured window itself, this covers the window while it is off screen
+	Damage window_damage;
+	XserverRegion damage_region;
+	// position of the cursor when damage was collected last, it is drawn into the image
+	int cursor_x;
+	int cursor_y;
 };
 
 typedef struct CaptureContext CaptureContext;
@@ -45,18 +59,74 @@ struct Image
 	unsigned int height;
 };
 
-void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
+// whether the display is connected via a local socket, MIT-SHM requires sharing memory with the
+// X server
+static int is_local_display(Display* disp)
+{
+	const char* name = DisplayString(disp);
+	return name[0] == ':' || name[0] == '/' || strncmp(name, "unix:", 5) == 0;
+}
+
+static void create_image(CaptureContext* ctx, unsigned int width, unsigned int height)
+{
+	Capturable* cap = &ctx->cap;
+	int depth = DefaultDepthOfScreen(cap->screen);
+	ctx->ximg = XCreateImage(
+		cap->disp,
+		DefaultVisualOfScreen(cap->screen),
+		depth,
+		ZPixmap,
+		0,
+		NULL,
+		width,
+		height,
+		32,
+		0);
+	ctx->ximg->data = malloc(ctx->ximg->bytes_per_line * height);
+}
+
+static void destroy_image(CaptureContext* ctx)
 {
-	if (XShmQueryExtension(cap->disp) != True)
+	if (ctx->use_shm)
 	{
-		fill_error(err, 1, "XShmExtension is not available but required!");
-		return NULL;
+		XShmDetach(ctx->cap.disp, &ctx->shminfo);
+		XDestroyImage(ctx->ximg);
+		shmdt(ctx->shminfo.shmaddr);
+		shmctl(ctx->shminfo.shmid, IPC_RMID, NULL);
 	}
+	else
+		// frees the data as well
+		XDestroyImage(ctx->ximg);
+}
+
+static Bool get_image(CaptureContext* ctx, Drawable d, int x, int y)
+{
+	if (ctx->use_shm)
+		return XShmGetImage(ctx->cap.disp, d, ctx->ximg, x, y, 0x00ffffff);
+	return XGetSubImage(
+			   ctx->cap.disp,
+			   d,
+			   x,
+			   y,
+			   ctx->ximg->width,
+			   ctx->ximg->height,
+			   0x00ffffff,
+			   ZPixmap,
+			   ctx->ximg,
+			   0,
+			   0) != NULL;
+}
 
+void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
+{
 	if (!ctx)
 	{
 		ctx = malloc(sizeof(CaptureContext));
 
+		ctx->use_shm = XShmQueryExtension(cap->disp) == True && is_local_display(cap->disp);
+		if (!ctx->use_shm)
+			log_info("MIT-SHM is not available, copying images over the X11 connection.");
+
 		int major, minor;
 		Bool pixmaps = False;
 		XShmQueryVersion(cap->disp, &major, &minor, &pixmaps);
@@ -67,13 +137,35 @@ void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
 			ctx->has_offscreen =
 				XCompositeQueryExtension(cap->disp, &event_base, &error_base) == True;
 			if (ctx->has_offscreen)
-				XCompositeRedirectWindow(cap->disp, cap->c.winfo.win, False);
+				XCompositeRedirectWindow(cap->disp, capture_target(&cap->c.winfo), False);
 		}
+		// the session type describes the local session, not the one of a remote display
 		const char* session_type = getenv("XDG_SESSION_TYPE");
-		if (session_type && strcmp(session_type, "wayland") == 0)
+		if (session_type && strcmp(session_type, "wayland") == 0 && is_local_display(cap->disp))
 			ctx->wayland = 1;
 		else
 			ctx->wayland = 0;
+
+		int damage_error_base, fixes_event_base, fixes_error_base;
+		ctx->has_damage =
+			XDamageQueryExtension(cap->disp, &ctx->damage_event_base, &damage_error_base) ==
+				True &&
+			XFixesQueryExtension(cap->disp, &fixes_event_base, &fixes_error_base) == True;
+		ctx->root_damage = None;
+		ctx->window_damage = None;
+		if (ctx->has_damage)
+		{
+			ctx->damage_region = XFixesCreateRegion(cap->disp, NULL, 0);
+			ctx->root_damage =
+				XDamageCreate(cap->disp, DefaultRootWindow(cap->disp), XDamageReportNonEmpty);
+			if (cap->type == WINDOW)
+				ctx->window_damage = XDamageCreate(
+					cap->disp, capture_target(&cap->c.winfo), XDamageReportNonEmpty);
+		}
+		else
+			log_debug("XDamage is not available, every frame is grabbed.");
+		ctx->cursor_x = -1;
+		ctx->cursor_y = -1;
 	}
 	ctx->cap = *cap;
 	ctx->last_img_return = True;
@@ -87,6 +179,11 @@ void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
 	int x, y;
 	unsigned int width, height;
 	get_geometry(cap, &x, &y, &width, &height, err);
+	if (!ctx->use_shm)
+	{
+		create_image(ctx, width, height);
+		return ctx;
+	}
 	ctx->ximg = XShmCreateImage(
 		cap->disp,
 		DefaultVisualOfScreen(cap->screen),
@@ -119,18 +216,236 @@ void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
 
 void stop_capture(CaptureContext* ctx, Error* err)
 {
-	XShmDetach(ctx->cap.disp, &ctx->shminfo);
-	XDestroyImage(ctx->ximg);
-	if (shmdt(ctx->shminfo.shmaddr) != 0)
+	if (ctx->use_shm)
 	{
-		fill_error(err, 1, "Failed to detach shared memory!");
+		XShmDetach(ctx->cap.disp, &ctx->shminfo);
+		XDestroyImage(ctx->ximg);
+		if (shmdt(ctx->shminfo.shmaddr) != 0)
+		{
+			fill_error(err, 1, "Failed to detach shared memory!");
+		}
+		shmctl(ctx->shminfo.shmid, IPC_RMID, NULL);
 	}
-	shmctl(ctx->shminfo.shmid, IPC_RMID, NULL);
+	else
+		XDestroyImage(ctx->ximg);
 	if (ctx->has_offscreen && ctx->cap.type == WINDOW && ctx->cap.c.winfo.is_regular_window)
-		XCompositeUnredirectWindow(ctx->cap.disp, ctx->cap.c.winfo.win, False);
+		XCompositeUnredirectWindow(ctx->cap.disp, capture_target(&ctx->cap.c.winfo), False);
+	if (ctx->has_damage)
+	{
+		XDamageDestroy(ctx->cap.disp, ctx->root_damage);
+		if (ctx->window_damage != None)
+			XDamageDestroy(ctx->cap.disp, ctx->window_damage);
+		XFixesDestroyRegion(ctx->cap.disp, ctx->damage_region);
+	}
 	free(ctx);
 }
 
+typedef struct DamageRect
+{
+	int x;
+	int y;
+	unsigned int width;
+	unsigned int height;
+} DamageRect;
+
+// the cursor image is not known when collecting damage, this area around its hotspot is assumed
+#define CURSOR_DAMAGE_SIZE 128
+
+static Bool is_damage_event(Display* disp, XEvent* ev, XPointer arg)
+{
+	CaptureContext* ctx = (CaptureContext*)arg;
+	if (ev->type != ctx->damage_event_base + XDamageNotify)
+		return False;
+	Damage damage = ((XDamageNotifyEvent*)ev)->damage;
+	return damage == ctx->root_damage || damage == ctx->window_damage;
+}
+
+// add the part of the rectangle that is within width x height, all rectangles beyond max_rects
+// are merged into the last one
+static int add_rect(
+	DamageRect* rects,
+	int max_rects,
+	int n,
+	int x,
+	int y,
+	int w,
+	int h,
+	unsigned int width,
+	unsigned int height)
+{
+	int x0 = clamp(x, 0, width);
+	int y0 = clamp(y, 0, height);
+	int x1 = clamp(x + w, 0, width);
+	int y1 = clamp(y + h, 0, height);
+	if (x0 >= x1 || y0 >= y1)
+		return n;
+	if (n < max_rects)
+	{
+		rects[n] = (DamageRect){x0, y0, x1 - x0, y1 - y0};
+		return n + 1;
+	}
+	DamageRect* last = &rects[max_rects - 1];
+	int lx1 = last->x + last->width;
+	int ly1 = last->y + last->height;
+	last->x = x0 < last->x ? x0 : last->x;
+	last->y = y0 < last->y ? y0 : last->y;
+	last->width = (x1 > lx1 ? x1 : lx1) - last->x;
+	last->height = (y1 > ly1 ? y1 : ly1) - last->y;
+	return n;
+}
+
+// move the damage collected by the damage object into rects, offset by dx, dy
+static int take_damage(
+	CaptureContext* ctx,
+	Damage damage,
+	int dx,
+	int dy,
+	unsigned int width,
+	unsigned int height,
+	DamageRect* rects,
+	int max_rects,
+	int n)
+{
+	XDamageSubtract(ctx->cap.disp, damage, None, ctx->damage_region);
+	int count = 0;
+	XRectangle* region = XFixesFetchRegion(ctx->cap.disp, ctx->damage_region, &count);
+	if (!region)
+		return n;
+	for (int i = 0; i < count; ++i)
+		n = add_rect(
+			rects,
+			max_rects,
+			n,
+			region[i].x + dx,
+			region[i].y + dy,
+			region[i].width,
+			region[i].height,
+			width,
+			height);
+	XFree(region);
+	return n;
+}
+
+// Collect the parts of the captured area that changed since the last call, in pixels relative to
+// the capturable. Returns the number of rectangles written to rects, or -1 if XDamage is not
+// available and changes can not be told.
+int capture_damage(
+	CaptureContext* ctx, DamageRect* rects, int max_rects, int capture_cursor, Error* err)
+{
+	if (!ctx->has_damage || max_rects < 1)
+		return -1;
+	Display* disp = ctx->cap.disp;
+	// damage is fetched below, the events only need to be taken off the queue
+	XEvent ev;
+	while (XCheckIfEvent(disp, &ev, is_damage_event, (XPointer)ctx))
+		;
+
+	int x, y;
+	unsigned int width, height;
+	get_geometry(&ctx->cap, &x, &y, &width, &height, err);
+	if (err->code)
+		return -1;
+	int n = 0;
+	// the image is grabbed anew once the window is resized
+	if (width != (unsigned int)ctx->ximg->width || height != (unsigned int)ctx->ximg->height)
+		n = add_rect(rects, max_rects, n, 0, 0, width, height, width, height);
+	if (ctx->window_damage != None)
+		n = take_damage(ctx, ctx->window_damage, 0, 0, width, height, rects, max_rects, n);
+	// damage of the root window covers popups and windows overlapping the captured one
+	n = take_damage(ctx, ctx->root_damage, -x, -y, width, height, rects, max_rects, n);
+
+	if (capture_cursor && ctx->has_xfixes)
+	{
+		Window root, child;
+		int root_x, root_y, win_x, win_y;
+		unsigned int mask;
+		if (XQueryPointer(
+				disp,
+				DefaultRootWindow(disp),
+				&root,
+				&child,
+				&root_x,
+				&root_y,
+				&win_x,
+				&win_y,
+				&mask) &&
+			(root_x != ctx->cursor_x || root_y != ctx->cursor_y))
+		{
+			// the cursor leaves its old position and appears at the new one
+			int half = CURSOR_DAMAGE_SIZE / 2;
+			n = add_rect(
+				rects,
+				max_rects,
+				n,
+				ctx->cursor_x - x - half,
+				ctx->cursor_y - y - half,
+				CURSOR_DAMAGE_SIZE,
+				CURSOR_DAMAGE_SIZE,
+				width,
+				height);
+			n = add_rect(
+				rects,
+				max_rects,
+				n,
+				root_x - x - half,
+				root_y - y - half,
+				CURSOR_DAMAGE_SIZE,
+				CURSOR_DAMAGE_SIZE,
+				width,
+				height);
+			ctx->cursor_x = root_x;
+			ctx->cursor_y = root_y;
+		}
+	}
+	return n;
+}
+
+// Menus and tooltips are override-redirect windows of their own instead of children of the window
+// they belong to. Draw those that are stacked above the captured window and overlap it over the
+// captured image.
+static void composite_popups(
+	CaptureContext* ctx, Window toplevel, int x, int y, unsigned int width, unsigned int height)
+{
+	Display* disp = ctx->cap.disp;
+	Window root, parent;
+	Window* children;
+	unsigned int num_children;
+	if (!XQueryTree(disp, DefaultRootWindow(disp), &root, &parent, &children, &num_children))
+		return;
+	// children are in stacking order from bottom to top
+	unsigned int i = 0;
+	while (i < num_children && children[i] != toplevel)
+		++i;
+	for (++i; i < num_children; ++i)
+	{
+		XWindowAttributes attr;
+		if (!XGetWindowAttributes(disp, children[i], &attr) || !attr.override_redirect ||
+			attr.map_state != IsViewable)
+			continue;
+		// part of the popup that is within the captured area and on screen
+		int x0 = clamp(attr.x, x > 0 ? x : 0, x + (int)width);
+		int y0 = clamp(attr.y, y > 0 ? y : 0, y + (int)height);
+		int x1 = clamp(attr.x + attr.width, x0, x + (int)width);
+		int y1 = clamp(attr.y + attr.height, y0, y + (int)height);
+		x1 = clamp(x1, x0, ctx->cap.screen->width);
+		y1 = clamp(y1, y0, ctx->cap.screen->height);
+		if (x0 >= x1 || y0 >= y1)
+			continue;
+		XImage* popup = XGetImage(
+			disp, children[i], x0 - attr.x, y0 - attr.y, x1 - x0, y1 - y0, AllPlanes, ZPixmap);
+		if (!popup)
+			continue;
+		if (popup->bits_per_pixel == 32)
+			for (int row = 0; row < y1 - y0; ++row)
+				memcpy(
+					ctx->ximg->data + (y0 - y + row) * ctx->ximg->bytes_per_line + (x0 - x) * 4,
+					popup->data + row * popup->bytes_per_line,
+					(x1 - x0) * 4);
+		XDestroyImage(popup);
+	}
+	XFree(children);
+}
+
 void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor, Error* err)
 {
 	Window root = DefaultRootWindow(ctx->cap.disp);
@@ -141,10 +456,7 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 	// if window resized, create new cap...
 	if (width != (unsigned int)ctx->ximg->width || height != (unsigned int)ctx->ximg->height)
 	{
-		XShmDetach(ctx->cap.disp, &ctx->shminfo);
-		XDestroyImage(ctx->ximg);
-		shmdt(ctx->shminfo.shmaddr);
-		shmctl(ctx->shminfo.shmid, IPC_RMID, NULL);
+		destroy_image(ctx);
 		CaptureContext* new_ctx = start_capture(&ctx->cap, ctx, err);
 		if (!new_ctx)
 		{
@@ -160,6 +472,8 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 	{
 		Window* active_window;
 		unsigned long size;
+		Window target = capture_target(&ctx->cap.c.winfo);
+		int include_popups = ctx->cap.c.winfo.include_popups;
 
 		int is_offscreen = ctx->cap.c.winfo.is_regular_window &&
 						   (x < 0 || y < 0 || x + (int)width > ctx->cap.screen->width ||
@@ -167,12 +481,15 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 
 		active_window =
 			(Window*)get_property(ctx->cap.disp, root, XA_WINDOW, "_NET_ACTIVE_WINDOW", &size, err);
-		if (!ctx->wayland && *active_window == ctx->cap.c.winfo.win && !is_offscreen)
+		if (!ctx->wayland && !include_popups && *active_window == ctx->cap.c.winfo.win &&
+			!is_offscreen)
 		{
 			// cap window within its root so menus are visible as strictly speaking menus do not
 			// belong to the window itself ...
 			// But don't do this on (X)Wayland as the root window is just black in that case.
-			get_img_ret = XShmGetImage(ctx->cap.disp, root, ctx->ximg, x, y, 0x00ffffff);
+			// If popups are included they are drawn over the window itself instead, which also
+			// keeps other windows out.
+			get_img_ret = get_image(ctx, root, x, y);
 		}
 		else
 		{
@@ -184,8 +501,8 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 			{
 				if (ctx->has_offscreen)
 				{
-					Pixmap pm = XCompositeNameWindowPixmap(ctx->cap.disp, ctx->cap.c.winfo.win);
-					get_img_ret = XShmGetImage(ctx->cap.disp, pm, ctx->ximg, 0, 0, 0x00ffffff);
+					Pixmap pm = XCompositeNameWindowPixmap(ctx->cap.disp, target);
+					get_img_ret = get_image(ctx, pm, 0, 0);
 					XFreePixmap(ctx->cap.disp, pm);
 				}
 				else
@@ -196,30 +513,31 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 						"unavailable!");
 			}
 			else
-				get_img_ret =
-					XShmGetImage(ctx->cap.disp, ctx->cap.c.winfo.win, ctx->ximg, 0, 0, 0x00ffffff);
+				get_img_ret = get_image(ctx, target, 0, 0);
+			if (include_popups && get_img_ret == True)
+				composite_popups(ctx, ctx->cap.c.winfo.toplevel, x, y, width, height);
 		}
 		free(active_window);
 		break;
 	}
 	case RECT:
-		get_img_ret = XShmGetImage(ctx->cap.disp, root, ctx->ximg, x, y, 0x00ffffff);
+		get_img_ret = get_image(ctx, root, x, y);
 		break;
 	}
 
 	Bool last_img_return = ctx->last_img_return;
 	ctx->last_img_return = get_img_ret;
-	// only print an error once and do not repeat this message if consecutive calls to XShmGetImage
+	// only print an error once and do not repeat this message if consecutive calls to get_image
 	// fail to avoid spamming the logs.
 	if (get_img_ret != True)
 	{
 		if (last_img_return != get_img_ret)
 		{
-			ERROR(err, 1, "XShmGetImage failed!");
+			ERROR(err, 1, "Failed to get image from X server!");
 		}
 		else
 		{
-			ERROR(err, 2, "XShmGetImage failed!");
+			ERROR(err, 2, "Failed to get image from X server!");
 		}
 	}
 
@@ -278,3 +596,86 @@ void capture_screen(CaptureContext* ctx, struct Image* img, int capture_cursor,
 	img->height = ctx->ximg->height;
 	img->data = ctx->ximg->data;
 }
+
+#define MAX_CURSOR_SIZE 256
+
+struct CursorImage
+{
+	// position of the cursor's hotspot relative to the capturable
+	int x;
+	int y;
+	unsigned int capturable_width;
+	unsigned int capturable_height;
+	// width/height are set to 0 if the cursor is too large to be copied
+	unsigned int width;
+	unsigned int height;
+	int xhot;
+	int yhot;
+	unsigned long serial;
+	uint32_t pixels[MAX_CURSOR_SIZE * MAX_CURSOR_SIZE];
+};
+
+void get_cursor(Capturable* cap, struct CursorImage* cursor, Error* err)
+{
+	int event_base, error_base;
+	if (XFixesQueryExtension(cap->disp, &event_base, &error_base) != True)
+		ERROR(err, 1, "XFixes is not available, can not track cursor!");
+
+	int x, y;
+	unsigned int width, height;
+	get_geometry(cap, &x, &y, &width, &height, err);
+	OK_OR_ABORT(err);
+
+	XFixesCursorImage* cursor_img = XFixesGetCursorImage(cap->disp);
+	if (cursor_img == NULL)
+		ERROR(
+			err,
+			1,
+			"Failed to obtain cursor image, XFixesGetCursorImage has returned a null pointer.");
+
+	cursor->x = cursor_img->x - x;
+	cursor->y = cursor_img->y - y;
+	cursor->capturable_width = width;
+	cursor->capturable_height = height;
+	cursor->xhot = cursor_img->xhot;
+	cursor->yhot = cursor_img->yhot;
+	cursor->serial = cursor_img->cursor_serial;
+	if (cursor_img->width <= MAX_CURSOR_SIZE && cursor_img->height <= MAX_CURSOR_SIZE)
+	{
+		cursor->width = cursor_img->width;
+		cursor->height = cursor_img->height;
+		// XFixes stores pixels as unsigned long, which may be 64 bits wide
+		for (unsigned int i = 0; i < cursor->width * cursor->height; ++i)
+			cursor->pixels[i] = cursor_img->pixels[i];
+	}
+	else
+	{
+		cursor->width = 0;
+		cursor->height = 0;
+	}
+	XFree(cursor_img);
+}
+
+// The cursor stays hidden as long as the returned connection is open, closing it restores the
+// cursor, this also happens if Weylus crashes.
+Display* hide_cursor()
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (disp == NULL)
+		return NULL;
+	int event_base, error_base;
+	if (XFixesQueryExtension(disp, &event_base, &error_base) != True)
+	{
+		XCloseDisplay(disp);
+		return NULL;
+	}
+	XFixesHideCursor(disp, DefaultRootWindow(disp));
+	XFlush(disp);
+	return disp;
+}
+
+void show_cursor(Display* disp)
+{
+	XFixesShowCursor(disp, DefaultRootWindow(disp));
+	XCloseDisplay(disp);
+}
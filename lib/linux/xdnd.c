@@ -0,0 +1,284 @@
+#include <X11/X.h>
+#include <X11/Xatom.h>
+#include <X11/Xlib.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/select.h>
+#include <time.h>
+
+#include "../error.h"
+#include "../log.h"
+
+// Drops files onto the window under the pointer by acting as the source of an XDND operation,
+// see: https://www.freedesktop.org/wiki/Specifications/XDND/
+// There is no actual drag, the drop happens right away at the current position of the pointer.
+
+#define XDND_VERSION 5
+// time to wait for the target to respond to each message
+#define XDND_TIMEOUT_MS 2000
+
+typedef struct XdndAtoms
+{
+	Atom aware;
+	Atom proxy;
+	Atom selection;
+	Atom enter;
+	Atom position;
+	Atom status;
+	Atom drop;
+	Atom leave;
+	Atom finished;
+	Atom action_copy;
+	Atom uri_list;
+} XdndAtoms;
+
+static void intern_atoms(Display* disp, XdndAtoms* atoms)
+{
+	atoms->aware = XInternAtom(disp, "XdndAware", False);
+	atoms->proxy = XInternAtom(disp, "XdndProxy", False);
+	atoms->selection = XInternAtom(disp, "XdndSelection", False);
+	atoms->enter = XInternAtom(disp, "XdndEnter", False);
+	atoms->position = XInternAtom(disp, "XdndPosition", False);
+	atoms->status = XInternAtom(disp, "XdndStatus", False);
+	atoms->drop = XInternAtom(disp, "XdndDrop", False);
+	atoms->leave = XInternAtom(disp, "XdndLeave", False);
+	atoms->finished = XInternAtom(disp, "XdndFinished", False);
+	atoms->action_copy = XInternAtom(disp, "XdndActionCopy", False);
+	atoms->uri_list = XInternAtom(disp, "text/uri-list", False);
+}
+
+// read a property consisting of a single 32 bit value, returns 0 if it is not set
+static unsigned long get_long_property(Display* disp, Window win, Atom property, Atom type)
+{
+	Atom ret_type;
+	int ret_format;
+	unsigned long nitems, bytes_after;
+	unsigned char* data = NULL;
+	unsigned long value = 0;
+	if (XGetWindowProperty(
+			disp,
+			win,
+			property,
+			0,
+			1,
+			False,
+			type,
+			&ret_type,
+			&ret_format,
+			&nitems,
+			&bytes_after,
+			&data) == Success &&
+		ret_type == type && ret_format == 32 && nitems == 1)
+		// properties of format 32 are returned as array of long
+		value = *(unsigned long*)data;
+	if (data)
+		XFree(data);
+	return value;
+}
+
+// find the topmost XDND aware window under the pointer
+static Window find_target(Display* disp, XdndAtoms* atoms, int* x_root, int* y_root, int* version)
+{
+	Window root = DefaultRootWindow(disp);
+	Window win = root;
+	Window root_ret, child;
+	int x, y;
+	unsigned int mask;
+	if (!XQueryPointer(disp, root, &root_ret, &child, x_root, y_root, &x, &y, &mask))
+		return None;
+	while (child != None)
+	{
+		win = child;
+		*version = get_long_property(disp, win, atoms->aware, XA_ATOM);
+		if (*version)
+			return win;
+		if (!XQueryPointer(disp, win, &root_ret, &child, x_root, y_root, &x, &y, &mask))
+			return None;
+	}
+	return None;
+}
+
+static void send_message(
+	Display* disp,
+	Window source,
+	Window target,
+	Window dest,
+	Atom type,
+	long l1,
+	long l2,
+	long l3,
+	long l4)
+{
+	XEvent ev;
+	memset(&ev, 0, sizeof(ev));
+	ev.xclient.type = ClientMessage;
+	ev.xclient.display = disp;
+	ev.xclient.window = target;
+	ev.xclient.message_type = type;
+	ev.xclient.format = 32;
+	ev.xclient.data.l[0] = source;
+	ev.xclient.data.l[1] = l1;
+	ev.xclient.data.l[2] = l2;
+	ev.xclient.data.l[3] = l3;
+	ev.xclient.data.l[4] = l4;
+	XSendEvent(disp, dest, False, NoEventMask, &ev);
+}
+
+static long elapsed_ms(struct timespec* start)
+{
+	struct timespec now;
+	clock_gettime(CLOCK_MONOTONIC, &now);
+	return (now.tv_sec - start->tv_sec) * 1000 + (now.tv_nsec - start->tv_nsec) / 1000000;
+}
+
+// wait for the next event, returns 0 on timeout
+static int next_event(Display* disp, XEvent* ev, struct timespec* start)
+{
+	while (!XPending(disp))
+	{
+		long remaining = XDND_TIMEOUT_MS - elapsed_ms(start);
+		if (remaining <= 0)
+			return 0;
+		int fd = ConnectionNumber(disp);
+		fd_set fds;
+		FD_ZERO(&fds);
+		FD_SET(fd, &fds);
+		struct timeval timeout = {remaining / 1000, (remaining % 1000) * 1000};
+		select(fd + 1, &fds, NULL, NULL, &timeout);
+	}
+	XNextEvent(disp, ev);
+	return 1;
+}
+
+// answer requests for the dropped data
+static void handle_selection_request(
+	Display* disp, XSelectionRequestEvent* req, XdndAtoms* atoms, const char* uri_list)
+{
+	XEvent reply;
+	memset(&reply, 0, sizeof(reply));
+	reply.xselection.type = SelectionNotify;
+	reply.xselection.display = disp;
+	reply.xselection.requestor = req->requestor;
+	reply.xselection.selection = req->selection;
+	reply.xselection.target = req->target;
+	reply.xselection.time = req->time;
+	reply.xselection.property = None;
+	if (req->selection == atoms->selection && req->target == atoms->uri_list)
+	{
+		Atom property = req->property != None ? req->property : req->target;
+		XChangeProperty(
+			disp,
+			req->requestor,
+			property,
+			atoms->uri_list,
+			8,
+			PropModeReplace,
+			(const unsigned char*)uri_list,
+			strlen(uri_list));
+		reply.xselection.property = property;
+	}
+	XSendEvent(disp, req->requestor, False, NoEventMask, &reply);
+}
+
+// get the current server time by waiting for the PropertyNotify caused by a zero length append
+static Time server_time(Display* disp, Window win)
+{
+	XEvent ev;
+	unsigned char dummy = 0;
+	XChangeProperty(disp, win, XA_WM_NAME, XA_STRING, 8, PropModeAppend, &dummy, 0);
+	XWindowEvent(disp, win, PropertyChangeMask, &ev);
+	return ev.xproperty.time;
+}
+
+void xdnd_drop_uri_list(const char* uri_list, Error* err)
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (!disp)
+		ERROR(err, 1, "Failed to open display.");
+
+	XdndAtoms atoms;
+	intern_atoms(disp, &atoms);
+
+	int x_root, y_root, version = 0;
+	Window target = find_target(disp, &atoms, &x_root, &y_root, &version);
+	if (target == None)
+	{
+		XCloseDisplay(disp);
+		ERROR(err, 1, "No window under the pointer accepts dropped files.");
+	}
+	if (version > XDND_VERSION)
+		version = XDND_VERSION;
+
+	// messages go to the proxy if there is one, see the section on XdndProxy of the specification
+	Window dest = get_long_property(disp, target, atoms.proxy, XA_WINDOW);
+	if (dest == None)
+		dest = target;
+
+	Window source = XCreateSimpleWindow(disp, DefaultRootWindow(disp), -1, -1, 1, 1, 0, 0, 0);
+	XSelectInput(disp, source, PropertyChangeMask);
+	Time time = server_time(disp, source);
+	XSetSelectionOwner(disp, atoms.selection, source, time);
+
+	send_message(
+		disp, source, target, dest, atoms.enter, version << 24, atoms.uri_list, None, None);
+	send_message(
+		disp,
+		source,
+		target,
+		dest,
+		atoms.position,
+		0,
+		(x_root << 16) | y_root,
+		time,
+		atoms.action_copy);
+	XFlush(disp);
+
+	struct timespec start;
+	clock_gettime(CLOCK_MONOTONIC, &start);
+	int dropped = 0;
+	int finished = 0;
+	const char* error = NULL;
+	XEvent ev;
+	while (!finished && !error)
+	{
+		if (!next_event(disp, &ev, &start))
+		{
+			error = "Timeout waiting for the window under the pointer.";
+			break;
+		}
+		switch (ev.type)
+		{
+		case SelectionRequest:
+			handle_selection_request(disp, &ev.xselectionrequest, &atoms, uri_list);
+			break;
+		case ClientMessage:
+			if (ev.xclient.message_type == atoms.status && !dropped)
+			{
+				if (ev.xclient.data.l[1] & 1)
+				{
+					send_message(disp, source, target, dest, atoms.drop, 0, time, 0, 0);
+					dropped = 1;
+					// give the target time to fetch the data
+					clock_gettime(CLOCK_MONOTONIC, &start);
+				}
+				else
+					error = "The window under the pointer does not accept the dropped files.";
+			}
+			else if (ev.xclient.message_type == atoms.finished)
+				finished = 1;
+			break;
+		}
+		XFlush(disp);
+	}
+	if (error && !dropped)
+		send_message(disp, source, target, dest, atoms.leave, 0, 0, 0, 0);
+	// XDND versions before 2 do not send XdndFinished
+	else if (error && version < 2)
+		error = NULL;
+
+	XDestroyWindow(disp, source);
+	XCloseDisplay(disp);
+	if (error)
+		ERROR(err, 1, "%s", error);
+	log_debug("Dropped files onto window 0x%lx.", target);
+}
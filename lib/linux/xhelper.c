@@ -177,8 +177,38 @@ Window* get_client_list(Display* disp, unsigned long* size, Error* err)
 	return client_list;
 }
 
+// Reparenting window managers put each client window into a frame window holding the
+// decorations, this returns the child of the root window win is part of.
+Window get_toplevel(Display* disp, Window win)
+{
+	Window root, parent;
+	Window* children;
+	unsigned int num_children;
+	while (XQueryTree(disp, win, &root, &parent, &children, &num_children))
+	{
+		if (children)
+			XFree(children);
+		if (parent == root || parent == None)
+			break;
+		win = parent;
+	}
+	return win;
+}
+
+// the window that is captured
+Window capture_target(WindowInfo* winfo)
+{
+	return winfo->include_decorations ? winfo->toplevel : winfo->win;
+}
+
 int create_capturables(
-	Display* disp, Capturable** capturables, int* num_monitors, int size, Error* err)
+	Display* disp,
+	Capturable** capturables,
+	int* num_monitors,
+	int size,
+	int include_decorations,
+	int include_popups,
+	Error* err)
 {
 	if (size <= 0)
 		return 0;
@@ -219,6 +249,9 @@ int create_capturables(
 	c->type = WINDOW;
 	c->c.winfo.win = root;
 	c->c.winfo.is_regular_window = 0;
+	c->c.winfo.toplevel = root;
+	c->c.winfo.include_decorations = 0;
+	c->c.winfo.include_popups = 0;
 	++i;
 
 	for (; i < (size_t)*num_monitors + 1 && i < (size_t)size; ++i)
@@ -256,6 +289,9 @@ int create_capturables(
 		strncpy(c->name, title_utf8, sizeof(c->name) - 1);
 		c->c.winfo.win = client_list[j];
 		c->c.winfo.is_regular_window = 1;
+		c->c.winfo.toplevel = get_toplevel(disp, client_list[j]);
+		c->c.winfo.include_decorations = include_decorations;
+		c->c.winfo.include_popups = include_popups;
 		free(title_utf8);
 	}
 	free(client_list);
@@ -298,7 +334,7 @@ void get_geometry(
 	switch (cap->type)
 	{
 	case WINDOW:
-		get_window_geometry(cap->disp, cap->c.winfo.win, x, y, width, height, err);
+		get_window_geometry(cap->disp, capture_target(&cap->c.winfo), x, y, width, height, err);
 		return;
 	case RECT:
 		*x = cap->c.rinfo.x;
@@ -407,6 +443,44 @@ void capturable_before_input(Capturable* cap, Error* err)
 
 const char* get_capturable_name(Capturable* c) { return c->name; }
 
+// write the class of the captured window or, for screens, of the active window to dest
+void get_capturable_application(Capturable* cap, char* dest, size_t size, Error* err)
+{
+	Window win;
+	switch (cap->type)
+	{
+	case WINDOW:
+		win = cap->c.winfo.win;
+		break;
+	case RECT:
+	{
+		unsigned long size_active;
+		Window* active = (Window*)get_property(
+			cap->disp,
+			DefaultRootWindow(cap->disp),
+			XA_WINDOW,
+			"_NET_ACTIVE_WINDOW",
+			&size_active,
+			err);
+		OK_OR_ABORT(err);
+		win = size_active >= sizeof(Window) ? *active : None;
+		free(active);
+		break;
+	}
+	}
+	XClassHint hint;
+	if (win == None || !XGetClassHint(cap->disp, win, &hint))
+	{
+		ERROR(err, 1, "Failed to get window class!");
+	}
+	strncpy(dest, hint.res_class ? hint.res_class : "", size - 1);
+	dest[size - 1] = '\0';
+	if (hint.res_name)
+		XFree(hint.res_name);
+	if (hint.res_class)
+		XFree(hint.res_class);
+}
+
 void map_input_device_to_entire_screen(Display* disp, const char* device_name, int pen, Error* err)
 {
 
@@ -503,3 +577,92 @@ void map_input_device_to_entire_screen(Display* disp, const char* device_name, i
 
 	XFree(data.c);
 }
+
+int create_master_device(Display* disp, const char* name, Error* err)
+{
+	XIAddMasterInfo add;
+	add.type = XIAddMaster;
+	add.name = (char*)name;
+	add.send_core = True;
+	add.enable = True;
+	if (XIChangeHierarchy(disp, (XIAnyHierarchyChangeInfo*)&add, 1) != Success)
+	{
+		fill_error(err, 1, "Failed to create master device %s.", name);
+		return -1;
+	}
+	XSync(disp, False);
+
+	// the X server appends " pointer" and " keyboard" to the name of the new master devices
+	char pointer_name[256];
+	snprintf(pointer_name, sizeof(pointer_name), "%s pointer", name);
+	int num_devices = 0;
+	XIDeviceInfo* devices = XIQueryDevice(disp, XIAllMasterDevices, &num_devices);
+	int id = -1;
+	for (int i = 0; i < num_devices; ++i)
+	{
+		if (devices[i].use == XIMasterPointer && strcmp(devices[i].name, pointer_name) == 0)
+		{
+			id = devices[i].deviceid;
+			break;
+		}
+	}
+	XIFreeDeviceInfo(devices);
+	if (id < 0)
+		fill_error(err, 1, "Master device %s not found after creating it.", name);
+	return id;
+}
+
+void remove_master_device(Display* disp, int master_pointer, Error* err)
+{
+	XIRemoveMasterInfo remove;
+	remove.type = XIRemoveMaster;
+	remove.deviceid = master_pointer;
+	// give the slave devices back to the core pointer and keyboard
+	remove.return_mode = XIAttachToMaster;
+	remove.return_pointer = 2;
+	remove.return_keyboard = 3;
+	if (XIChangeHierarchy(disp, (XIAnyHierarchyChangeInfo*)&remove, 1) != Success)
+		ERROR(err, 1, "Failed to remove master device %d.", master_pointer);
+	XSync(disp, False);
+}
+
+int attach_to_master_device(Display* disp, const char* device_name, int master_pointer, Error* err)
+{
+	int num_devices = 0;
+	XIDeviceInfo* devices = XIQueryDevice(disp, XIAllDevices, &num_devices);
+
+	int master_keyboard = -1;
+	for (int i = 0; i < num_devices; ++i)
+		if (devices[i].deviceid == master_pointer)
+			master_keyboard = devices[i].attachment;
+
+	// like in map_input_device_to_entire_screen a stylus shows up as several devices with " Pen"
+	// or " Eraser" appended to the name
+	size_t len = strlen(device_name);
+	int num_attached = 0;
+	for (int i = 0; i < num_devices; ++i)
+	{
+		const char* name = devices[i].name;
+		if (strncmp(name, device_name, len) != 0 ||
+			(name[len] != '\0' && strncmp(name + len, " Pen", 4) != 0 &&
+			 strncmp(name + len, " Eraser", 7) != 0))
+			continue;
+		XIAttachSlaveInfo attach;
+		attach.type = XIAttachSlave;
+		attach.deviceid = devices[i].deviceid;
+		if (devices[i].use == XISlavePointer || devices[i].use == XIFloatingSlave)
+			attach.new_master = master_pointer;
+		else if (devices[i].use == XISlaveKeyboard && master_keyboard >= 0)
+			attach.new_master = master_keyboard;
+		else
+			continue;
+		if (XIChangeHierarchy(disp, (XIAnyHierarchyChangeInfo*)&attach, 1) == Success)
+			++num_attached;
+	}
+	XIFreeDeviceInfo(devices);
+	XSync(disp, False);
+
+	if (num_attached == 0)
+		fill_error(err, 2, "Device with name: %s not found!", device_name);
+	return num_attached;
+}
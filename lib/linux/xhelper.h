@@ -17,6 +17,12 @@ typedef struct WindowInfo
 {
 	Window win;
 	int is_regular_window;
+	// child of the root window containing win, the frame of reparenting window managers
+	Window toplevel;
+	// capture toplevel including the decorations instead of win
+	int include_decorations;
+	// draw menus and tooltips over the window
+	int include_popups;
 } WindowInfo;
 
 typedef struct RectInfo
@@ -49,6 +55,10 @@ typedef struct Capturable
 char* get_property(
 	Display* disp, Window win, Atom xa_prop_type, char* prop_name, unsigned long* size, Error* err);
 
+Window get_toplevel(Display* disp, Window win);
+
+Window capture_target(WindowInfo* winfo);
+
 void get_geometry(
 	Capturable* cap, int* x, int* y, unsigned int* width, unsigned int* height, Error* err);
 
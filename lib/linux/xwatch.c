@@ -0,0 +1,67 @@
+#include <X11/Xlib.h>
+#include <X11/extensions/Xrandr.h>
+#include <stdlib.h>
+
+#include "../error.h"
+
+// Waits for monitors to be added, removed or rearranged via RandR and for windows to appear or
+// disappear from the _NET_CLIENT_LIST of the window manager, which is where the windows offered
+// to clients come from. The watch has a connection of its own, as it blocks while waiting.
+
+typedef struct CapturableWatch
+{
+	Display* disp;
+	Atom client_list;
+	int randr_event_base;
+} CapturableWatch;
+
+CapturableWatch* create_capturable_watch(Error* err)
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (disp == NULL)
+	{
+		fill_error(err, 1, "Failed to open display.");
+		return NULL;
+	}
+	Window root = DefaultRootWindow(disp);
+
+	CapturableWatch* watch = malloc(sizeof(CapturableWatch));
+	watch->disp = disp;
+	watch->client_list = XInternAtom(disp, "_NET_CLIENT_LIST", False);
+	int error_base;
+	if (XRRQueryExtension(disp, &watch->randr_event_base, &error_base))
+		XRRSelectInput(
+			disp,
+			root,
+			RRScreenChangeNotifyMask | RRCrtcChangeNotifyMask | RROutputChangeNotifyMask);
+	else
+		watch->randr_event_base = -1;
+	XSelectInput(disp, root, PropertyChangeMask);
+	return watch;
+}
+
+// Block until monitors or the list of windows changed.
+void wait_capturable_change(CapturableWatch* watch)
+{
+	XEvent event;
+	for (;;)
+	{
+		XNextEvent(watch->disp, &event);
+		if (event.type == PropertyNotify && event.xproperty.atom == watch->client_list)
+			return;
+		if (watch->randr_event_base < 0)
+			continue;
+		if (event.type == watch->randr_event_base + RRScreenChangeNotify ||
+			event.type == watch->randr_event_base + RRNotify)
+		{
+			XRRUpdateConfiguration(&event);
+			return;
+		}
+	}
+}
+
+void destroy_capturable_watch(CapturableWatch* watch)
+{
+	XCloseDisplay(watch->disp);
+	free(watch);
+}
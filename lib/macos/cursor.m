@@ -0,0 +1,63 @@
+#import <AppKit/AppKit.h>
+#import <CoreGraphics/CoreGraphics.h>
+
+#include <stdint.h>
+#include <string.h>
+
+#include "../error.h"
+
+typedef struct CursorInfo
+{
+	// size of the image in pixels
+	size_t width;
+	size_t height;
+	// size and hotspot in points of the global display coordinates
+	double points_width;
+	double points_height;
+	double xhot;
+	double yhot;
+} CursorInfo;
+
+// Copy the image of the cursor the system currently shows into pixels, which has room for
+// max_size x max_size pixels, as RGBA with premultiplied alpha.
+void current_cursor(uint8_t* pixels, size_t max_size, CursorInfo* info, Error* err)
+{
+	@autoreleasepool
+	{
+		NSCursor* cursor = [NSCursor currentSystemCursor];
+		if (cursor == nil)
+			ERROR(err, 1, "Failed to get the cursor of the system.");
+		NSImage* image = [cursor image];
+		CGImageRef cg_image = [image CGImageForProposedRect:NULL context:nil hints:nil];
+		if (cg_image == NULL)
+			ERROR(err, 1, "Failed to get the image of the cursor.");
+		size_t width = CGImageGetWidth(cg_image);
+		size_t height = CGImageGetHeight(cg_image);
+		if (width == 0 || height == 0 || width > max_size || height > max_size)
+			ERROR(err, 1, "Cursor has unsupported size: %zux%zu.", width, height);
+
+		memset(pixels, 0, width * height * 4);
+		CGColorSpaceRef color_space = CGColorSpaceCreateDeviceRGB();
+		CGContextRef ctx = CGBitmapContextCreate(
+			pixels,
+			width,
+			height,
+			8,
+			width * 4,
+			color_space,
+			kCGImageAlphaPremultipliedLast | kCGBitmapByteOrder32Big);
+		CGColorSpaceRelease(color_space);
+		if (ctx == NULL)
+			ERROR(err, 1, "Failed to create bitmap context for the cursor.");
+		CGContextDrawImage(ctx, CGRectMake(0, 0, width, height), cg_image);
+		CGContextRelease(ctx);
+
+		NSPoint hotspot = [cursor hotSpot];
+		info->width = width;
+		info->height = height;
+		info->points_width = image.size.width;
+		info->points_height = image.size.height;
+		info->xhot = hotspot.x;
+		info->yhot = hotspot.y;
+	}
+}
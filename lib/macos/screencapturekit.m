@@ -0,0 +1,340 @@
+#import <CoreGraphics/CoreGraphics.h>
+#import <CoreMedia/CoreMedia.h>
+#import <CoreVideo/CoreVideo.h>
+#import <Foundation/Foundation.h>
+#import <ScreenCaptureKit/ScreenCaptureKit.h>
+
+#include <stdint.h>
+#include <unistd.h>
+
+#include "../error.h"
+#include "../log.h"
+
+// time to wait for ScreenCaptureKit to list the shareable content or start the stream
+#define SCK_TIMEOUT_SECS 5
+
+API_AVAILABLE(macos(12.3))
+@interface WeylusStreamOutput : NSObject <SCStreamOutput, SCStreamDelegate>
+{
+@public
+	// the frame received last and not yet taken, guarded by @synchronized(self)
+	CVPixelBufferRef latest;
+	BOOL stopped;
+}
+@end
+
+@implementation WeylusStreamOutput
+
+- (void)stream:(SCStream*)stream
+	didOutputSampleBuffer:(CMSampleBufferRef)sampleBuffer
+				   ofType:(SCStreamOutputType)type
+{
+	if (type != SCStreamOutputTypeScreen || !CMSampleBufferIsValid(sampleBuffer))
+		return;
+	// if nothing changed frames without pixels are delivered, they have another status
+	CFArrayRef attachments = CMSampleBufferGetSampleAttachmentsArray(sampleBuffer, false);
+	if (!attachments || CFArrayGetCount(attachments) == 0)
+		return;
+	NSDictionary* info = (__bridge NSDictionary*)CFArrayGetValueAtIndex(attachments, 0);
+	NSNumber* status = info[SCStreamFrameInfoStatus];
+	if (!status || status.integerValue != SCFrameStatusComplete)
+		return;
+	CVPixelBufferRef buf = CMSampleBufferGetImageBuffer(sampleBuffer);
+	if (!buf)
+		return;
+	CVPixelBufferRetain(buf);
+	@synchronized(self)
+	{
+		if (latest)
+			CVPixelBufferRelease(latest);
+		latest = buf;
+	}
+}
+
+- (void)stream:(SCStream*)stream didStopWithError:(NSError*)error
+{
+	log_warn("ScreenCaptureKit stopped the stream: %s", error.localizedDescription.UTF8String);
+	@synchronized(self)
+	{
+		stopped = YES;
+	}
+}
+
+- (void)dealloc
+{
+	if (latest)
+		CVPixelBufferRelease(latest);
+}
+
+@end
+
+API_AVAILABLE(macos(12.3))
+@interface WeylusCapture : NSObject
+{
+@public
+	SCStream* stream;
+	SCStreamConfiguration* config;
+	WeylusStreamOutput* output;
+	dispatch_queue_t queue;
+	// the frame handed out last, its base address is locked while it is read
+	CVPixelBufferRef front;
+}
+@end
+
+@implementation WeylusCapture
+
+- (void)dealloc
+{
+	if (front)
+	{
+		CVPixelBufferUnlockBaseAddress(front, kCVPixelBufferLock_ReadOnly);
+		CVPixelBufferRelease(front);
+	}
+}
+
+@end
+
+int sck_available()
+{
+	if (@available(macOS 12.3, *))
+		return 1;
+	return 0;
+}
+
+// ratio of pixels to points of the display, 2 on retina displays
+static double display_scale(CGDirectDisplayID display)
+{
+	CGDisplayModeRef mode = CGDisplayCopyDisplayMode(display);
+	if (!mode)
+		return 1.0;
+	double scale = (double)CGDisplayModeGetPixelWidth(mode) / (double)CGDisplayModeGetWidth(mode);
+	CGDisplayModeRelease(mode);
+	return scale > 0 ? scale : 1.0;
+}
+
+// scale of the display showing the center of the rect
+static double rect_scale(CGRect rect)
+{
+	CGDirectDisplayID display;
+	uint32_t count = 0;
+	CGPoint center = CGPointMake(CGRectGetMidX(rect), CGRectGetMidY(rect));
+	if (CGGetDisplaysWithPoint(center, 1, &display, &count) != kCGErrorSuccess || count == 0)
+		display = CGMainDisplayID();
+	return display_scale(display);
+}
+
+// start capturing the window if window_id is not 0, the display otherwise; windows of Weylus are
+// left out of captured displays if exclude_self is set
+void* sck_start(
+	uint32_t display_id, uint32_t window_id, int capture_cursor, int exclude_self, Error* err)
+{
+	if (@available(macOS 12.3, *))
+	{
+		dispatch_semaphore_t sem = dispatch_semaphore_create(0);
+		dispatch_time_t timeout = dispatch_time(DISPATCH_TIME_NOW, SCK_TIMEOUT_SECS * NSEC_PER_SEC);
+
+		__block SCShareableContent* content = nil;
+		__block NSError* content_err = nil;
+		[SCShareableContent
+			getShareableContentExcludingDesktopWindows:NO
+								   onScreenWindowsOnly:NO
+									 completionHandler:^(SCShareableContent* c, NSError* e) {
+									   content = c;
+									   content_err = e;
+									   dispatch_semaphore_signal(sem);
+									 }];
+		if (dispatch_semaphore_wait(sem, timeout))
+		{
+			fill_error(err, 1, "Timed out listing the content ScreenCaptureKit can capture.");
+			return NULL;
+		}
+		if (!content)
+		{
+			fill_error(
+				err,
+				1,
+				"Failed to list the content ScreenCaptureKit can capture: %s",
+				content_err.localizedDescription.UTF8String);
+			return NULL;
+		}
+
+		SCContentFilter* filter = nil;
+		CGSize size;
+		double scale;
+		if (window_id)
+		{
+			for (SCWindow* w in content.windows)
+			{
+				if (w.windowID == window_id)
+				{
+					filter = [[SCContentFilter alloc] initWithDesktopIndependentWindow:w];
+					size = w.frame.size;
+					scale = rect_scale(w.frame);
+					break;
+				}
+			}
+		}
+		else
+		{
+			NSMutableArray<SCRunningApplication*>* excluded = [NSMutableArray array];
+			if (exclude_self)
+			{
+				pid_t pid = getpid();
+				for (SCRunningApplication* app in content.applications)
+				{
+					if (app.processID == pid)
+						[excluded addObject:app];
+				}
+			}
+			for (SCDisplay* d in content.displays)
+			{
+				if (d.displayID == display_id)
+				{
+					filter = [[SCContentFilter alloc] initWithDisplay:d
+											excludingApplications:excluded
+												 exceptingWindows:@[]];
+					size = CGSizeMake(d.width, d.height);
+					scale = display_scale(d.displayID);
+					break;
+				}
+			}
+		}
+		if (!filter)
+		{
+			fill_error(err, 1, "ScreenCaptureKit can not capture this window or display.");
+			return NULL;
+		}
+		if (@available(macOS 14.0, *))
+			scale = filter.pointPixelScale;
+
+		SCStreamConfiguration* config = [[SCStreamConfiguration alloc] init];
+		config.width = (size_t)(size.width * scale);
+		config.height = (size_t)(size.height * scale);
+		config.pixelFormat = kCVPixelFormatType_32BGRA;
+		config.showsCursor = capture_cursor ? YES : NO;
+		config.minimumFrameInterval = CMTimeMake(1, 60);
+		config.queueDepth = 3;
+
+		WeylusCapture* capture = [[WeylusCapture alloc] init];
+		capture->config = config;
+		capture->output = [[WeylusStreamOutput alloc] init];
+		capture->queue = dispatch_queue_create("weylus.screencapturekit", DISPATCH_QUEUE_SERIAL);
+		capture->stream = [[SCStream alloc] initWithFilter:filter
+											configuration:config
+												 delegate:capture->output];
+		NSError* add_err = nil;
+		if (![capture->stream addStreamOutput:capture->output
+										 type:SCStreamOutputTypeScreen
+						   sampleHandlerQueue:capture->queue
+										error:&add_err])
+		{
+			fill_error(
+				err,
+				1,
+				"Failed to add output to the stream: %s",
+				add_err.localizedDescription.UTF8String);
+			return NULL;
+		}
+
+		__block NSError* start_err = nil;
+		[capture->stream startCaptureWithCompletionHandler:^(NSError* e) {
+		  start_err = e;
+		  dispatch_semaphore_signal(sem);
+		}];
+		if (dispatch_semaphore_wait(sem, timeout))
+		{
+			fill_error(err, 1, "Timed out starting the stream.");
+			return NULL;
+		}
+		if (start_err)
+		{
+			fill_error(
+				err,
+				1,
+				"Failed to start the stream: %s",
+				start_err.localizedDescription.UTF8String);
+			return NULL;
+		}
+		return (__bridge_retained void*)capture;
+	}
+	fill_error(err, 1, "ScreenCaptureKit requires macOS 12.3 or later.");
+	return NULL;
+}
+
+// adapt the size of the frames to a captured window that now has the given frame in points of the
+// global display coordinates, e.g. after it has been resized or moved to a display with another
+// scale; the stream keeps running, frames of the new size arrive once ScreenCaptureKit applied it
+void sck_resize(void* handle, double x, double y, double width, double height)
+{
+	if (@available(macOS 12.3, *))
+	{
+		WeylusCapture* capture = (__bridge WeylusCapture*)handle;
+		CGRect frame = CGRectMake(x, y, width, height);
+		double scale = rect_scale(frame);
+		size_t w = (size_t)(width * scale);
+		size_t h = (size_t)(height * scale);
+		if (w == 0 || h == 0 || (w == capture->config.width && h == capture->config.height))
+			return;
+		capture->config.width = w;
+		capture->config.height = h;
+		[capture->stream updateConfiguration:capture->config
+						   completionHandler:^(NSError* e) {
+							 if (e)
+								 log_warn(
+									 "Failed to resize the stream: %s",
+									 e.localizedDescription.UTF8String);
+						   }];
+	}
+}
+
+// take the frame received last, returns 1 if there is a new frame, 0 if the content did not change
+// and -1 if the stream stopped; the pixels stay valid until the next call or until the capture is
+// stopped
+int sck_frame(
+	void* handle, uint8_t** data, size_t* width, size_t* height, size_t* stride, Error* err)
+{
+	if (@available(macOS 12.3, *))
+	{
+		WeylusCapture* capture = (__bridge WeylusCapture*)handle;
+		CVPixelBufferRef latest;
+		@synchronized(capture->output)
+		{
+			if (capture->output->stopped)
+			{
+				fill_error(err, 1, "The stream stopped.");
+				return -1;
+			}
+			latest = capture->output->latest;
+			capture->output->latest = NULL;
+		}
+		if (!latest)
+			return 0;
+		if (capture->front)
+		{
+			CVPixelBufferUnlockBaseAddress(capture->front, kCVPixelBufferLock_ReadOnly);
+			CVPixelBufferRelease(capture->front);
+		}
+		capture->front = latest;
+		CVPixelBufferLockBaseAddress(latest, kCVPixelBufferLock_ReadOnly);
+		*data = CVPixelBufferGetBaseAddress(latest);
+		*width = CVPixelBufferGetWidth(latest);
+		*height = CVPixelBufferGetHeight(latest);
+		*stride = CVPixelBufferGetBytesPerRow(latest);
+		return 1;
+	}
+	fill_error(err, 1, "ScreenCaptureKit requires macOS 12.3 or later.");
+	return -1;
+}
+
+void sck_stop(void* handle)
+{
+	if (@available(macOS 12.3, *))
+	{
+		WeylusCapture* capture = (__bridge_transfer WeylusCapture*)handle;
+		dispatch_semaphore_t sem = dispatch_semaphore_create(0);
+		[capture->stream stopCaptureWithCompletionHandler:^(NSError* e) {
+		  dispatch_semaphore_signal(sem);
+		}];
+		dispatch_semaphore_wait(sem, dispatch_time(DISPATCH_TIME_NOW, SCK_TIMEOUT_SECS * NSEC_PER_SEC));
+	}
+}
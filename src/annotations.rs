@@ -0,0 +1,118 @@
+//! Laser pointers of viewers. A viewer that enables the laser pointer in its settings points at
+//! things instead of sending input, its pointer is drawn as a dot into the video of everyone
+//! watching the same capturable, or the broadcast, each client in a color of its own.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::frame_stamp::Dot;
+
+/// A laser pointer vanishes if it does not move for this long.
+const LASER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Colors of the laser pointers, assigned to clients in this order.
+const PALETTE: [[u8; 3]; 8] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+    [255, 225, 25],
+];
+
+/// What a laser pointer points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The video of the controller that is broadcast.
+    Broadcast,
+    /// Capturables of this name.
+    Capturable(String),
+}
+
+struct Laser {
+    target: Target,
+    x: f64,
+    y: f64,
+    color: usize,
+    moved: Instant,
+    visible: bool,
+}
+
+/// The laser pointers of all clients of an instance of the web server.
+#[derive(Default)]
+pub struct Annotations {
+    lasers: Mutex<HashMap<SocketAddr, Laser>>,
+    /// Changes whenever a laser pointer moves, appears or vanishes.
+    version: AtomicU64,
+}
+
+impl Annotations {
+    /// Move the laser pointer of the client to the given position, relative to the target.
+    pub fn point(&self, addr: SocketAddr, target: Target, x: f64, y: f64) {
+        let mut lasers = self.lasers.lock().unwrap();
+        let color = match lasers.get(&addr) {
+            Some(laser) => laser.color,
+            // the first color no other client uses, colors repeat beyond the palette
+            None => (0..)
+                .find(|i| !lasers.values().any(|l| l.color == *i))
+                .unwrap(),
+        };
+        lasers.insert(
+            addr,
+            Laser {
+                target,
+                x: x.clamp(0.0, 1.0),
+                y: y.clamp(0.0, 1.0),
+                color,
+                moved: Instant::now(),
+                visible: true,
+            },
+        );
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Hide the laser pointer of the client, it keeps its color.
+    pub fn hide(&self, addr: SocketAddr) {
+        if let Some(laser) = self.lasers.lock().unwrap().get_mut(&addr) {
+            if laser.visible {
+                laser.visible = false;
+                self.version.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn remove(&self, addr: SocketAddr) {
+        if self.lasers.lock().unwrap().remove(&addr).is_some() {
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Dots of the visible laser pointers of the targets, the broadcast is only included if
+    /// `broadcast` is set.
+    pub fn dots(&self, capturable: Option<&str>, broadcast: bool) -> Vec<Dot> {
+        self.lasers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|l| l.visible && l.moved.elapsed() < LASER_TIMEOUT)
+            .filter(|l| match &l.target {
+                Target::Broadcast => broadcast,
+                Target::Capturable(name) => Some(name.as_str()) == capturable,
+            })
+            .map(|l| Dot {
+                x: l.x,
+                y: l.y,
+                color: PALETTE[l.color % PALETTE.len()],
+            })
+            .collect()
+    }
+}
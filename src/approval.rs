@@ -0,0 +1,299 @@
+//! Approval of devices connecting for the first time.
+//!
+//! With `--approve-new-clients` the host has to approve every device that has not connected
+//! before, in the GUI or via `weylus approve`, even if it knows the access code. A leaked access
+//! code alone then does not grant access on a shared network. Devices are recognized by a random
+//! token kept in a cookie, or by their IP address if the browser did not keep the cookie. Devices
+//! the host chose to remember are stored in `known_devices.toml` next to the configuration file,
+//! others are allowed until Weylus exits. Devices recognized by their address only are never
+//! stored, as on a shared network the address is handed to someone else sooner or later.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::oidc::random_string;
+
+pub const DEVICE_COOKIE: &str = "weylus_device";
+
+/// The device cookie is kept for ten years.
+const COOKIE_MAX_AGE: u64 = 3600 * 24 * 365 * 10;
+
+/// Requests the host did not decide on within this time are denied.
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Deny,
+    /// Allow the device until Weylus exits.
+    Allow,
+    /// Allow the device now and whenever it connects again, devices without cookie are allowed
+    /// until Weylus exits.
+    Remember,
+}
+
+/// A device waiting for the host to approve it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingApproval {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub user_agent: Option<String>,
+    /// Name of the instance the device connects to.
+    pub instance: String,
+}
+
+/// How a device is recognized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Device {
+    Token(String),
+    Address(IpAddr),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct KnownDevices {
+    #[serde(default)]
+    tokens: BTreeSet<String>,
+    /// Only used for devices allowed until Weylus exits.
+    #[serde(default, skip_serializing)]
+    addresses: BTreeSet<IpAddr>,
+}
+
+impl KnownDevices {
+    const fn new() -> Self {
+        Self {
+            tokens: BTreeSet::new(),
+            addresses: BTreeSet::new(),
+        }
+    }
+
+    fn contains(&self, device: &Device) -> bool {
+        match device {
+            Device::Token(token) => self.tokens.contains(token),
+            Device::Address(ip) => self.addresses.contains(ip),
+        }
+    }
+
+    fn insert(&mut self, device: &Device) {
+        match device {
+            Device::Token(token) => self.tokens.insert(token.clone()),
+            Device::Address(ip) => self.addresses.insert(*ip),
+        };
+    }
+}
+
+struct Pending {
+    approval: PendingApproval,
+    device: Device,
+    decide: oneshot::Sender<Decision>,
+}
+
+struct State {
+    /// Devices allowed until Weylus exits.
+    allowed: KnownDevices,
+    /// Devices remembered across restarts, loaded on first use.
+    remembered: Option<KnownDevices>,
+    pending: Vec<Pending>,
+    next_id: u64,
+}
+
+impl State {
+    fn remembered(&mut self) -> &mut KnownDevices {
+        self.remembered.get_or_insert_with(load)
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    allowed: KnownDevices::new(),
+    remembered: None,
+    pending: Vec::new(),
+    next_id: 0,
+});
+
+fn known_devices_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("weylus");
+    path.push("known_devices.toml");
+    Some(path)
+}
+
+fn load() -> KnownDevices {
+    let Some(s) = known_devices_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return KnownDevices::default();
+    };
+    let mut devices: KnownDevices = toml::from_str(&s).unwrap_or_else(|err| {
+        warn!("Failed to read known devices: {err}");
+        KnownDevices::default()
+    });
+    // stored by earlier versions
+    devices.addresses.clear();
+    devices
+}
+
+fn store(devices: &KnownDevices) {
+    let Some(path) = known_devices_path() else {
+        warn!("Failed to find configuration directory, devices are not remembered!");
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(err) = fs::create_dir_all(dir) {
+            warn!("Failed to create directory for known devices: {err}");
+            return;
+        }
+    }
+    let s = toml::to_string_pretty(devices).expect("Failed to encode known devices.");
+    if let Err(err) = fs::write(path, s) {
+        warn!("Failed to store known devices: {err}");
+    }
+}
+
+fn device_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == DEVICE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// The cookie to set if the device has not been given a token yet.
+pub fn device_cookie(headers: &HeaderMap) -> Option<String> {
+    if device_token(headers).is_some() {
+        return None;
+    }
+    Some(format!(
+        "{DEVICE_COOKIE}={}; Max-Age={COOKIE_MAX_AGE}; Path=/; HttpOnly; SameSite=Lax",
+        random_string(32)
+    ))
+}
+
+impl Device {
+    pub fn from_request(headers: &HeaderMap, addr: SocketAddr) -> Self {
+        match device_token(headers) {
+            Some(token) => Self::Token(token.to_string()),
+            None => Self::Address(addr.ip()),
+        }
+    }
+
+    /// Whether the device has been approved before.
+    pub fn is_approved(&self) -> bool {
+        let mut state = STATE.lock().unwrap();
+        state.allowed.contains(self) || state.remembered().contains(self)
+    }
+
+    /// Ask the host to approve the device and wait for the decision, returns whether the device
+    /// may connect.
+    pub async fn request_approval(
+        &self,
+        address: SocketAddr,
+        user_agent: Option<String>,
+        instance: &str,
+    ) -> bool {
+        let (decide, decision) = oneshot::channel();
+        let id = {
+            let mut state = STATE.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.pending.push(Pending {
+                approval: PendingApproval {
+                    id,
+                    address,
+                    user_agent,
+                    instance: instance.to_string(),
+                },
+                device: self.clone(),
+                decide,
+            });
+            id
+        };
+        info!(
+            address = %address,
+            "A new device connects and waits for approval, approve it in the GUI or via \
+            `weylus approve {id}`."
+        );
+        match tokio::time::timeout(APPROVAL_TIMEOUT, decision).await {
+            Ok(Ok(decision)) => decision != Decision::Deny,
+            Ok(Err(_)) => false,
+            Err(_) => {
+                warn!(address = %address, "The device was not approved in time.");
+                STATE
+                    .lock()
+                    .unwrap()
+                    .pending
+                    .retain(|p| p.approval.id != id);
+                false
+            }
+        }
+    }
+}
+
+/// The devices currently waiting for approval.
+pub fn pending() -> Vec<PendingApproval> {
+    STATE
+        .lock()
+        .unwrap()
+        .pending
+        .iter()
+        .map(|p| p.approval.clone())
+        .collect()
+}
+
+/// Decide on the request with the given id, other requests of the same device are decided
+/// alike. Returns false if there is no such request.
+pub fn decide(id: u64, decision: Decision) -> bool {
+    let mut state = STATE.lock().unwrap();
+    let Some(device) = state
+        .pending
+        .iter()
+        .find(|p| p.approval.id == id)
+        .map(|p| p.device.clone())
+    else {
+        return false;
+    };
+    match decision {
+        Decision::Deny => info!("Denied device access."),
+        Decision::Allow => {
+            info!("Allowed device access until Weylus exits.");
+            state.allowed.insert(&device);
+        }
+        Decision::Remember => {
+            state.allowed.insert(&device);
+            if let Device::Token(_) = device {
+                info!("Allowed device access, it is remembered.");
+                state.remembered().insert(&device);
+                store(state.remembered());
+            } else {
+                info!(
+                    "Allowed device access until Weylus exits, devices without cookie are not \
+                    remembered."
+                );
+            }
+        }
+    }
+    let (decided, pending) = std::mem::take(&mut state.pending)
+        .into_iter()
+        .partition::<Vec<_>, _>(|p| p.device == device);
+    state.pending = pending;
+    for p in decided {
+        let _ = p.decide.send(decision);
+    }
+    true
+}
+
+/// Forget all approved devices, they need to be approved again the next time they connect.
+pub fn forget_all() {
+    let mut state = STATE.lock().unwrap();
+    state.allowed = KnownDevices::new();
+    state.remembered = Some(KnownDevices::new());
+    store(&KnownDevices::new());
+    info!("Forgot all approved devices.");
+}
@@ -0,0 +1,52 @@
+//! Hard cap of the bandwidth the video of a session may use, for metered or shared links where
+//! predictable usage matters more than quality. The encoder is told to stay below the cap, as
+//! encoders overshoot now and then, e.g. on keyframes, frames are skipped whenever more has been
+//! sent than the cap allows.
+
+use std::time::Instant;
+
+/// Seconds worth of video that may be sent in a burst.
+const BURST: f64 = 0.5;
+
+pub struct BandwidthCap {
+    /// Bytes per second.
+    rate: f64,
+    /// Bytes that may be sent right now, negative if more has been sent than allowed.
+    budget: f64,
+    last_update: Instant,
+    /// Total of the bytes sent at the last update.
+    sent: u64,
+}
+
+impl BandwidthCap {
+    /// Cap the video to the given kbit/s, `sent` is the total of the bytes sent so far.
+    pub fn new(kbits: u32, sent: u64) -> Self {
+        let rate = kbits as f64 * 1000.0 / 8.0;
+        Self {
+            rate,
+            budget: rate * BURST,
+            last_update: Instant::now(),
+            sent,
+        }
+    }
+
+    /// Whether another frame may be sent, `sent` is the total of the bytes sent so far.
+    pub fn allows_frame(&mut self, sent: u64) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_update).as_secs_f64() * self.rate;
+        self.budget = (self.budget + refill).min(self.rate * BURST);
+        self.budget -= sent.saturating_sub(self.sent) as f64;
+        self.last_update = now;
+        self.sent = sent;
+        self.budget >= 0.0
+    }
+}
+
+/// The lower of two caps in kbit/s, 0 meaning no cap.
+pub fn lower_cap(a: u32, b: u32) -> u32 {
+    match (a, b) {
+        (0, b) => b,
+        (a, 0) => a,
+        (a, b) => a.min(b),
+    }
+}
@@ -0,0 +1,73 @@
+//! Battery levels reported by clients. The host is warned once the tablet or its stylus runs low,
+//! so the session can be wrapped up or the device plugged in before it dies mid-drawing.
+
+use crate::protocol::DeviceStatus;
+
+/// Charge below which the host is warned.
+pub const LOW_BATTERY: f64 = 0.15;
+
+/// A charge as text, e.g. 42%.
+pub fn percent(level: f64) -> String {
+    format!("{:.0}%", level.clamp(0.0, 1.0) * 100.0)
+}
+
+impl DeviceStatus {
+    /// The device, unless it is charging, and the stylus whose battery is low.
+    pub fn low_batteries(&self) -> Vec<(&'static str, f64)> {
+        let mut low = Vec::new();
+        if let Some(level) = self.battery {
+            if level < LOW_BATTERY && self.charging != Some(true) {
+                low.push(("device", level));
+            }
+        }
+        if let Some(level) = self.stylus_battery {
+            if level < LOW_BATTERY {
+                low.push(("stylus", level));
+            }
+        }
+        low
+    }
+
+    /// Battery levels as text, e.g. "device 42% (charging), stylus 80%", None if the client
+    /// reported none.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(level) = self.battery {
+            let charging = if self.charging == Some(true) {
+                " (charging)"
+            } else {
+                ""
+            };
+            parts.push(format!("device {}{charging}", percent(level)));
+        }
+        if let Some(level) = self.stylus_battery {
+            parts.push(format!("stylus {}", percent(level)));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// Remembers which batteries have been warned about, so the host is warned once each time one of
+/// them runs low instead of whenever the level drops a bit further.
+#[derive(Default)]
+pub struct BatteryWatch {
+    warned: Vec<&'static str>,
+}
+
+impl BatteryWatch {
+    /// Batteries that just ran low.
+    pub fn update(&mut self, status: &DeviceStatus) -> Vec<(&'static str, f64)> {
+        let low = status.low_batteries();
+        self.warned
+            .retain(|battery| low.iter().any(|(b, _)| b == battery));
+        low.into_iter()
+            .filter(|(battery, _)| {
+                let new = !self.warned.contains(battery);
+                if new {
+                    self.warned.push(battery);
+                }
+                new
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,181 @@
+//! Broadcast mode: the video of a controller is encoded once and fanned out to all viewers, which
+//! neither capture nor encode anything of their own. This way a teacher can control the computer
+//! from one tablet while a whole class watches at hardly any extra CPU. Every viewer has a queue of
+//! its own, a viewer that can not keep up skips fragments until the next keyframe instead of
+//! holding back the others.
+
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+
+use tracing::{debug, info, warn};
+
+use crate::preroll::{is_keyframe, Preroll};
+use crate::protocol::{MessageOutbound, WeylusSender};
+
+/// Fragments queued per viewer before it is considered lagging, about two seconds of video.
+const QUEUE_LENGTH: usize = 64;
+
+enum Packet {
+    NewVideo,
+    Video(Arc<[u8]>),
+    /// Start decoding anew: a new video, followed by the part of the video that can be decoded
+    /// on its own.
+    Restart(Vec<Arc<[u8]>>),
+}
+
+struct Viewer {
+    id: u64,
+    queue: SyncSender<Packet>,
+    /// Fragments are skipped until the next keyframe.
+    lagging: bool,
+}
+
+impl Viewer {
+    /// Queue the packet, marks the viewer lagging if its queue is full. Returns false once the
+    /// viewer is gone.
+    fn send(&mut self, packet: Packet) -> bool {
+        match self.queue.try_send(packet) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if !self.lagging {
+                    debug!("Viewer of the broadcast lags behind, skipping to the next keyframe.");
+                }
+                self.lagging = true;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    /// The controller whose video is broadcast.
+    source: Option<SocketAddr>,
+    preroll: Preroll,
+    viewers: Vec<Viewer>,
+    next_id: u64,
+}
+
+impl Inner {
+    fn replay(&self) -> Vec<Arc<[u8]>> {
+        self.preroll.replay().map(Arc::from).collect()
+    }
+}
+
+/// The broadcast of an instance of the web server.
+#[derive(Default)]
+pub struct Broadcast {
+    inner: Mutex<Inner>,
+}
+
+impl Broadcast {
+    /// Become the source of the broadcast unless another controller is already, returns whether
+    /// the video of `addr` is broadcast.
+    pub fn claim_source(&self, addr: SocketAddr) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.source {
+            Some(source) => source == addr,
+            None => {
+                info!(address = %addr, "Broadcasting video to viewers.");
+                inner.source = Some(addr);
+                true
+            }
+        }
+    }
+
+    /// Stop broadcasting the video of `addr`, viewers keep waiting for the next source.
+    pub fn release_source(&self, addr: SocketAddr) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.source == Some(addr) {
+            inner.source = None;
+            inner.preroll = Preroll::new();
+        }
+    }
+
+    /// The source started a new video, its initialization segment follows.
+    pub fn new_video(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .viewers
+            .retain_mut(|v| v.lagging || v.send(Packet::NewVideo));
+    }
+
+    /// Send video of the source to all viewers.
+    pub fn publish(&self, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.preroll.push(data);
+        if inner.viewers.is_empty() {
+            return;
+        }
+        let keyframe = is_keyframe(data);
+        let data: Arc<[u8]> = Arc::from(data);
+        // lagging viewers start decoding anew at the keyframe, they may have missed a new video
+        let restart = (keyframe && inner.viewers.iter().any(|v| v.lagging)).then(|| inner.replay());
+        inner.viewers.retain_mut(|v| {
+            if !v.lagging {
+                return v.send(Packet::Video(data.clone()));
+            }
+            let Some(restart) = restart.as_ref() else {
+                return true;
+            };
+            v.lagging = false;
+            v.send(Packet::Restart(restart.clone()))
+        });
+    }
+
+    /// Send the broadcast to a viewer until the returned guard is dropped. The viewer joins the
+    /// video that is currently broadcast, if any.
+    pub fn subscribe<S: WeylusSender + Send + 'static>(
+        self: &Arc<Self>,
+        mut sender: S,
+    ) -> BroadcastViewer {
+        let (queue, packets) = mpsc::sync_channel(QUEUE_LENGTH);
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let replay = inner.replay();
+        if !replay.is_empty() {
+            let _ = queue.try_send(Packet::Restart(replay));
+        }
+        inner.viewers.push(Viewer {
+            id,
+            queue,
+            lagging: false,
+        });
+        spawn(move || {
+            for packet in packets {
+                let res = match packet {
+                    Packet::NewVideo => sender.send_message(MessageOutbound::NewVideo),
+                    Packet::Video(data) => sender.send_video(&data),
+                    Packet::Restart(data) => sender
+                        .send_message(MessageOutbound::NewVideo)
+                        .and_then(|()| data.iter().try_for_each(|d| sender.send_video(d))),
+                };
+                if let Err(err) = res {
+                    warn!("Failed to send broadcast to viewer: {err}");
+                    break;
+                }
+            }
+        });
+        BroadcastViewer {
+            broadcast: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Keeps a viewer subscribed to the broadcast.
+pub struct BroadcastViewer {
+    broadcast: Arc<Broadcast>,
+    id: u64,
+}
+
+impl Drop for BroadcastViewer {
+    fn drop(&mut self) {
+        let mut inner = self.broadcast.inner.lock().unwrap();
+        inner.viewers.retain(|v| v.id != self.id);
+    }
+}
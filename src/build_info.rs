@@ -0,0 +1,54 @@
+//! Cargo features Weylus was built with, see the features section of Cargo.toml. They are
+//! reported by `weylus --version --verbose` and `weylus selftest`, so users of a slimmed down
+//! binary can tell why e.g. VAAPI is not offered.
+
+/// The features that apply to the target platform and whether they were compiled in.
+pub fn features() -> Vec<(&'static str, bool)> {
+    let mut features = vec![("gui", cfg!(feature = "gui"))];
+    if cfg!(target_os = "linux") {
+        features.push(("vaapi", cfg!(feature = "vaapi")));
+    }
+    // there are no Nvidia GPUs for Windows on ARM
+    if cfg!(any(
+        target_os = "linux",
+        all(target_os = "windows", target_arch = "x86_64")
+    )) {
+        features.push(("nvenc", cfg!(feature = "nvenc")));
+    }
+    if cfg!(target_os = "linux") {
+        features.push(("wayland", cfg!(feature = "wayland")));
+    }
+    features
+}
+
+/// The features as text, e.g. "+gui +vaapi -nvenc +wayland".
+pub fn describe() -> String {
+    features()
+        .iter()
+        .map(|(name, enabled)| format!("{}{name}", if *enabled { '+' } else { '-' }))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Where ffmpeg comes from, it is linked statically unless built with `ffmpeg-system`.
+pub fn ffmpeg() -> &'static str {
+    if cfg!(feature = "ffmpeg-system") {
+        "system"
+    } else {
+        "bundled"
+    }
+}
+
+/// `weylus --version`, with `verbose` the platform and features are printed as well.
+pub fn print_version(verbose: bool) {
+    println!("weylus {}", env!("CARGO_PKG_VERSION"));
+    if verbose {
+        println!(
+            "target: {}-{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        );
+        println!("features: {}", describe());
+        println!("ffmpeg: {}", ffmpeg());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::capturable::{Capturable, Recorder};
-use captrs::Capturer;
+use crate::capturable::cursor::DesktopCursorTracker;
+use crate::capturable::{Capturable, CursorTracker, Recorder};
+use captrs::{CaptureError, Capturer};
 use std::boxed::Box;
 use std::error::Error;
 use winapi::shared::windef::RECT;
@@ -29,12 +30,20 @@ impl Capturable for CaptrsCapturable {
     fn name(&self) -> String {
         format!("Desktop {} (captrs)", self.name).into()
     }
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Only whole desktops are captured on Windows, there is no window to activate.
+    fn before_input(&mut self, _activate: bool) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
     fn recorder(&self, _capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
         Ok(Box::new(CaptrsRecorder::new(self.id)?))
     }
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(DesktopCursorTracker::new(Box::new(self.clone()))))
+    }
+    /// Executable of the window in the foreground, e.g. krita.exe.
+    fn application(&self) -> Option<String> {
+        foreground_executable()
+    }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         Ok(Geometry::VirtualScreen(
             self.screen.left - self.virtual_screen.left,
@@ -46,6 +55,42 @@ impl Capturable for CaptrsCapturable {
         ))
     }
 }
+
+fn foreground_executable() -> Option<String> {
+    window_executable(unsafe { winapi::um::winuser::GetForegroundWindow() })
+}
+
+/// Executable of the process owning the window.
+pub fn window_executable(hwnd: winapi::shared::windef::HWND) -> Option<String> {
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::winbase::QueryFullProcessImageNameW;
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    use winapi::um::winuser::GetWindowThreadProcessId;
+
+    unsafe {
+        if hwnd.is_null() {
+            return None;
+        }
+        let mut pid = 0;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return None;
+        }
+        let mut buf = [0u16; 1024];
+        let mut len = buf.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, 0, buf.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        if ok == 0 {
+            return None;
+        }
+        let path = std::path::PathBuf::from(std::ffi::OsString::from_wide(&buf[..len as usize]));
+        path.file_name().map(|n| n.to_string_lossy().into_owned())
+    }
+}
+
 #[derive(Debug)]
 pub struct CaptrsError(String);
 
@@ -59,21 +104,31 @@ impl std::fmt::Display for CaptrsError {
 impl Error for CaptrsError {}
 pub struct CaptrsRecorder {
     capturer: Capturer,
+    /// Result of capturing a frame in frame_changed, consumed by the next capture.
+    pending: Option<Result<(), CaptureError>>,
 }
 
 impl CaptrsRecorder {
     pub fn new(id: u8) -> Result<CaptrsRecorder, Box<dyn Error>> {
         Ok(CaptrsRecorder {
             capturer: Capturer::new(id.into())?,
+            pending: None,
         })
     }
 }
 
 impl Recorder for CaptrsRecorder {
     fn capture(&mut self) -> Result<crate::video::PixelProvider, Box<dyn Error>> {
-        self.capturer
-            .capture_store_frame()
-            .map_err(|_e| CaptrsError("Captrs failed to capture frame".into()))?;
+        let res = self
+            .pending
+            .take()
+            .unwrap_or_else(|| self.capturer.capture_store_frame());
+        match res {
+            Ok(()) => (),
+            // DXGI only provides a new frame if the content changed, reuse the last one
+            Err(CaptureError::Timeout) if self.capturer.get_stored_frame().is_some() => (),
+            Err(_) => return Err(CaptrsError("Captrs failed to capture frame".into()).into()),
+        }
         let (w, h) = self.capturer.geometry();
         Ok(crate::video::PixelProvider::BGR0(
             w as usize,
@@ -81,4 +136,12 @@ impl Recorder for CaptrsRecorder {
             unsafe { std::mem::transmute(self.capturer.get_stored_frame().unwrap()) },
         ))
     }
+
+    fn frame_changed(&mut self) -> bool {
+        let res = self.capturer.capture_store_frame();
+        let changed = !matches!(res, Err(CaptureError::Timeout))
+            || self.capturer.get_stored_frame().is_none();
+        self.pending = Some(res);
+        changed
+    }
 }
@@ -0,0 +1,222 @@
+//! A capturable stitching all monitors into one large frame, so the whole desktop can be seen and
+//! controlled from a single client. Monitors are placed according to their geometry and keep
+//! their resolution, see [`Layout`].
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use crate::capturable::cursor::DesktopCursorTracker;
+use crate::capturable::transform::{bounding_rect, geometry_rect, Layout};
+use crate::capturable::{Capturable, CursorTracker, DirtyRect, Geometry, Recorder};
+use crate::video::PixelProvider;
+
+/// Geometry covering all the given geometries.
+fn union(geometries: &[Geometry]) -> Result<Geometry, Box<dyn Error>> {
+    let Some(first) = geometries.first() else {
+        return Err("There are no monitors.".into());
+    };
+    let rect = bounding_rect(geometries.iter().map(geometry_rect)).unwrap();
+    match *first {
+        Geometry::Relative(..) => Ok(Geometry::Relative(rect.x, rect.y, rect.width, rect.height)),
+        #[cfg(target_os = "windows")]
+        Geometry::VirtualScreen(offset_x, offset_y, _, _, left, top) => {
+            // the offsets are relative to the virtual screen, which all monitors share
+            let (x, y) = (rect.x as i32, rect.y as i32);
+            Ok(Geometry::VirtualScreen(
+                x,
+                y,
+                rect.width as u32,
+                rect.height as u32,
+                left - offset_x + x,
+                top - offset_y + y,
+            ))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CompositeCapturable {
+    monitors: Vec<Box<dyn Capturable>>,
+    /// Shared with the recorder, which knows the sizes of the frames of the monitors.
+    layout: Arc<Mutex<Option<Layout>>>,
+}
+
+impl CompositeCapturable {
+    pub fn new(monitors: Vec<Box<dyn Capturable>>) -> Self {
+        Self {
+            monitors,
+            layout: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn geometries(monitors: &[Box<dyn Capturable>]) -> Result<Vec<Geometry>, Box<dyn Error>> {
+        monitors.iter().map(|m| m.geometry()).collect()
+    }
+}
+
+impl Capturable for CompositeCapturable {
+    fn name(&self) -> String {
+        format!("All Monitors ({})", self.monitors.len())
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        union(&Self::geometries(&self.monitors)?)
+    }
+
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        for m in &mut self.monitors {
+            m.refresh_geometry()?;
+        }
+        Ok(())
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        for m in &mut self.monitors {
+            m.before_input(activate)?;
+        }
+        Ok(())
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        let recorders = self
+            .monitors
+            .iter()
+            .map(|m| m.recorder(capture_cursor))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Box::new(CompositeRecorder {
+            monitors: self.monitors.clone(),
+            recorders,
+            layout: self.layout.clone(),
+            buf: Vec::new(),
+            complete: false,
+        }))
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        let tracker = DesktopCursorTracker::new(Box::new(self.clone()));
+        Some(Box::new(tracker.with_layout(self.layout.clone())))
+    }
+
+    fn application(&self) -> Option<String> {
+        self.monitors.iter().find_map(|m| m.application())
+    }
+
+    fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        // until the first frame is captured the frame is taken to be the desktop scaled
+        match self.layout.lock().unwrap().as_ref() {
+            Some(layout) => layout.map_position(x, y),
+            None => (x, y),
+        }
+    }
+}
+
+pub struct CompositeRecorder {
+    monitors: Vec<Box<dyn Capturable>>,
+    recorders: Vec<Box<dyn Recorder>>,
+    layout: Arc<Mutex<Option<Layout>>>,
+    buf: Vec<u8>,
+    /// The frames of all monitors were copied by the last capture.
+    complete: bool,
+}
+
+/// Copy the frame into the BGR0 buffer of the given width at x, y.
+fn copy_frame(
+    frame: &PixelProvider,
+    buf: &mut [u8],
+    buf_width: usize,
+    x: usize,
+    y: usize,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height, stride, bgr, bpp, data) = match *frame {
+        PixelProvider::RGB(w, h, data) => (w, h, w * 3, false, 3, data),
+        PixelProvider::RGB0(w, h, data) => (w, h, w * 4, false, 4, data),
+        PixelProvider::BGR0(w, h, data) => (w, h, w * 4, true, 4, data),
+        PixelProvider::BGR0S(w, h, stride, data) => (w, h, stride, true, 4, data),
+        PixelProvider::RGB0S(w, h, stride, data) => (w, h, stride, false, 4, data),
+        PixelProvider::DmaBuf(_) => {
+            return Err("Frames in GPU memory can not be stitched together.".into())
+        }
+    };
+    for row in 0..height {
+        let src = data
+            .get(row * stride..row * stride + width * bpp)
+            .ok_or("Frame is smaller than its size.")?;
+        let start = ((y + row) * buf_width + x) * 4;
+        let dst = buf
+            .get_mut(start..start + width * 4)
+            .ok_or("Frame does not fit into the composite frame.")?;
+        if bgr {
+            dst.copy_from_slice(src);
+            continue;
+        }
+        for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(bpp)) {
+            d[0] = s[2];
+            d[1] = s[1];
+            d[2] = s[0];
+            d[3] = 0;
+        }
+    }
+    Ok(())
+}
+
+impl Recorder for CompositeRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        let geometries = CompositeCapturable::geometries(&self.monitors)?;
+        let layout = self.layout.lock().unwrap().clone();
+        let mut sizes = Vec::with_capacity(self.recorders.len());
+        let mut complete = true;
+        for (i, recorder) in self.recorders.iter_mut().enumerate() {
+            let frame = recorder.capture()?;
+            let (width, height) = frame.size();
+            sizes.push((width, height));
+            // frames of monitors that changed their size are dropped until the layout is updated
+            match layout
+                .as_ref()
+                .and_then(|l| Some((l, l.tile(i, width, height)?)))
+            {
+                Some((layout, (x, y))) => copy_frame(&frame, &mut self.buf, layout.width, x, y)?,
+                None => complete = false,
+            }
+        }
+        let new_layout = Layout::new(&geometries, &sizes);
+        let (width, height) = (new_layout.width, new_layout.height);
+        if layout.as_ref() != Some(&new_layout) {
+            // the monitors are copied into the new layout by the next capture, the space between
+            // them stays black
+            self.buf = vec![0; width * height * 4];
+            *self.layout.lock().unwrap() = Some(new_layout);
+            complete = false;
+        }
+        self.complete = complete;
+        Ok(PixelProvider::BGR0(width, height, &self.buf))
+    }
+
+    fn frame_changed(&mut self) -> bool {
+        // every recorder has to be asked, some take their frame while doing so
+        let changed = self
+            .recorders
+            .iter_mut()
+            .fold(false, |changed, r| r.frame_changed() || changed);
+        changed || !self.complete
+    }
+
+    fn reconnecting(&self) -> bool {
+        self.recorders.iter().any(|r| r.reconnecting())
+    }
+
+    fn dirty_rects(&mut self) -> Option<Vec<DirtyRect>> {
+        if !self.complete {
+            return None;
+        }
+        let layout = self.layout.lock().unwrap().clone()?;
+        let mut dirty = Vec::new();
+        for (recorder, (_, tile)) in self.recorders.iter_mut().zip(&layout.tiles) {
+            dirty.extend(recorder.dirty_rects()?.into_iter().map(|r| DirtyRect {
+                x: r.x + tile.x as usize,
+                y: r.y + tile.y as usize,
+                ..r
+            }));
+        }
+        Some(dirty)
+    }
+}
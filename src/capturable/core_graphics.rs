@@ -14,12 +14,36 @@ use core_foundation::{
 use core_graphics::{
     display,
     display::{CGDisplay, CGRect},
+    event::CGEvent,
+    event_source::{CGEventSource, CGEventSourceStateID},
     image::CGImage,
     window,
     window::CGWindowID,
 };
 
-use crate::capturable::{Capturable, Geometry, Recorder};
+use tracing::warn;
+
+use crate::capturable::cursor::DesktopCursorTracker;
+use crate::capturable::transform::{bounding_rect, Rect};
+use crate::capturable::{Capturable, Cursor, CursorShape, CursorTracker, Geometry, Recorder};
+use crate::cerror::CError;
+
+const MAX_CURSOR_SIZE: usize = 256;
+
+#[repr(C)]
+#[derive(Default)]
+struct CCursorInfo {
+    width: usize,
+    height: usize,
+    points_width: f64,
+    points_height: f64,
+    xhot: f64,
+    yhot: f64,
+}
+
+extern "C" {
+    fn current_cursor(pixels: *mut u8, max_size: usize, info: *mut CCursorInfo, err: *mut CError);
+}
 
 #[derive(Debug)]
 pub struct CGError(String);
@@ -42,6 +66,10 @@ impl CGDisplayCapturable {
     pub fn new(display: CGDisplay) -> Self {
         Self { display }
     }
+
+    pub fn display_id(&self) -> u32 {
+        self.display.id
+    }
 }
 
 impl Capturable for CGDisplayCapturable {
@@ -53,24 +81,22 @@ impl Capturable for CGDisplayCapturable {
         )
     }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
-        let bounds = self.display.bounds();
-        let (x0, y0, w, h) = screen_coordsys()?;
-        Ok(Geometry::Relative(
-            (bounds.origin.x - x0) / w,
-            (bounds.origin.y - y0) / h,
-            bounds.size.width / w,
-            bounds.size.height / h,
-        ))
+        let r = cg_rect(&self.display.bounds()).relative_to(&screen_coordsys()?);
+        Ok(Geometry::Relative(r.x, r.y, r.width, r.height))
     }
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    fn before_input(&mut self, _activate: bool) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
     fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        crate::permissions::require_screen_recording()?;
         Ok(Box::new(RecorderCGDisplay::new(
             self.display,
             capture_cursor,
         )))
     }
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(DesktopCursorTracker::new(Box::new(self.clone()))))
+    }
 }
 
 pub struct RecorderCGDisplay {
@@ -139,36 +165,75 @@ impl Recorder for RecorderCGDisplay {
 pub struct CGWindowCapturable {
     id: CGWindowID,
     name: String,
+    /// Process id of the application owning the window.
+    pid: i64,
+    /// Name of the application owning the window.
+    owner: String,
     cursor_id: CGWindowID,
     bounds: CGRect,
-    geometry_relative: (f64, f64, f64, f64),
+    geometry_relative: Rect,
     last_geometry_update: Instant,
+    last_activation: Instant,
 }
 
 impl CGWindowCapturable {
+    pub fn window_id(&self) -> CGWindowID {
+        self.id
+    }
+
+    /// Bounds of the window in points of the global display coordinates, see
+    /// [`refresh_geometry`](Capturable::refresh_geometry).
+    pub fn bounds(&self) -> CGRect {
+        self.bounds
+    }
+
     fn update_geometry(&mut self) -> Result<(), Box<dyn Error>> {
         if Instant::now() - self.last_geometry_update > Duration::from_secs(1) {
-            self.bounds = get_window_infos()
-                .iter()
-                .find(|w| w.id == self.id)
-                .ok_or_else(|| {
-                    CGError(format!(
-                        "Could not find information for current window {}.",
-                        self.id
-                    ))
-                })?
-                .bounds;
-            let (x0, y0, w, h) = screen_coordsys()?;
-            self.geometry_relative = (
-                (self.bounds.origin.x - x0) / w,
-                (self.bounds.origin.y - y0) / h,
-                self.bounds.size.width / w,
-                self.bounds.size.height / h,
-            );
-            self.last_geometry_update = Instant::now();
+            self.fetch_geometry()?;
         }
         Ok(())
     }
+
+    fn fetch_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.bounds = window_info(self.id)
+            .ok_or_else(|| {
+                CGError(format!(
+                    "Could not find information for current window {}.",
+                    self.id
+                ))
+            })?
+            .bounds;
+        self.geometry_relative = cg_rect(&self.bounds).relative_to(&screen_coordsys()?);
+        self.last_geometry_update = Instant::now();
+        Ok(())
+    }
+
+    /// Bring the application owning the window to the front, at most once per second as this
+    /// starts osascript.
+    fn activate(&mut self) {
+        if Instant::now() - self.last_activation < Duration::from_secs(1) {
+            return;
+        }
+        self.last_activation = Instant::now();
+        let script = format!(
+            "tell application \"System Events\" to set frontmost of \
+                (first process whose unix id is {}) to true",
+            self.pid
+        );
+        std::thread::spawn(move || {
+            match std::process::Command::new("osascript")
+                .args(["-e", &script])
+                .output()
+            {
+                Ok(output) if !output.status.success() => warn!(
+                    "Failed to activate window: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(err) => warn!("Failed to run osascript to activate window: {err}"),
+                _ => (),
+            }
+        });
+    }
 }
 
 impl Capturable for CGWindowCapturable {
@@ -176,19 +241,32 @@ impl Capturable for CGWindowCapturable {
         self.name.clone()
     }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
-        let (x, y, w, h) = self.geometry_relative;
-        Ok(Geometry::Relative(x, y, w, h))
+        let r = self.geometry_relative;
+        Ok(Geometry::Relative(r.x, r.y, r.width, r.height))
     }
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.fetch_geometry()
+    }
+    fn application(&self) -> Option<String> {
+        Some(self.owner.clone())
+    }
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        if activate {
+            self.activate();
+        }
         self.update_geometry()
     }
     fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        crate::permissions::require_screen_recording()?;
         Ok(Box::new(RecorderCGWindow {
             img_data: None,
             capture_cursor,
             win: self.clone(),
         }))
     }
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(DesktopCursorTracker::new(Box::new(self.clone()))))
+    }
 }
 pub struct RecorderCGWindow {
     img_data: Option<CFData>,
@@ -198,7 +276,9 @@ pub struct RecorderCGWindow {
 
 impl Recorder for RecorderCGWindow {
     fn capture(&mut self) -> Result<crate::video::PixelProvider, Box<dyn Error>> {
-        self.win.update_geometry()?;
+        // the window may have been moved or resized since the last frame, a stale rectangle cuts
+        // it off or shows what is next to it
+        self.win.fetch_geometry()?;
         let img = CGDisplay::screenshot_from_windows(
             self.win.bounds,
             if self.capture_cursor {
@@ -236,15 +316,31 @@ impl Recorder for RecorderCGWindow {
 struct WindowInfo {
     pub id: CGWindowID,
     pub name: String,
+    pub pid: i64,
+    pub owner: String,
     pub bounds: CGRect,
 }
 
 fn get_window_infos() -> Vec<WindowInfo> {
-    let mut win_infos = vec![];
-    let wins = CGDisplay::window_list_info(
+    window_infos(
         display::kCGWindowListExcludeDesktopElements | display::kCGWindowListOptionOnScreenOnly,
         None,
-    );
+    )
+}
+
+/// Information of just the given window, cheap enough to be queried for every frame.
+fn window_info(id: CGWindowID) -> Option<WindowInfo> {
+    window_infos(display::kCGWindowListOptionIncludingWindow, Some(id))
+        .into_iter()
+        .find(|w| w.id == id)
+}
+
+fn window_infos(
+    option: window::CGWindowListOption,
+    relative_to: Option<CGWindowID>,
+) -> Vec<WindowInfo> {
+    let mut win_infos = vec![];
+    let wins = CGDisplay::window_list_info(option, relative_to);
     if let Some(wins) = wins {
         for w in wins.iter() {
             let w: CFDictionary<*const c_void, *const c_void> =
@@ -254,6 +350,11 @@ fn get_window_infos() -> Vec<WindowInfo> {
                 .to_i64()
                 .unwrap() as CGWindowID;
 
+            let pid = w.get(unsafe { window::kCGWindowOwnerPID }.to_void());
+            let pid = unsafe { CFNumber::wrap_under_get_rule(*pid as CFNumberRef) }
+                .to_i64()
+                .unwrap();
+
             let bounds = w.get(unsafe { window::kCGWindowBounds }.to_void());
             let bounds = unsafe { CFDictionary::wrap_under_get_rule(*bounds as CFDictionaryRef) };
             let bounds = CGRect::from_dict_representation(&bounds).unwrap();
@@ -264,9 +365,15 @@ fn get_window_infos() -> Vec<WindowInfo> {
             };
 
             let name = unsafe { CFString::wrap_under_get_rule(*name as CFStringRef) };
+            let owner = w
+                .find(unsafe { window::kCGWindowOwnerName }.to_void())
+                .map(|o| unsafe { CFString::wrap_under_get_rule(*o as CFStringRef) }.to_string())
+                .unwrap_or_default();
             win_infos.push(WindowInfo {
                 id,
                 name: name.to_string(),
+                pid,
+                owner,
                 bounds,
             });
         }
@@ -274,28 +381,97 @@ fn get_window_infos() -> Vec<WindowInfo> {
     win_infos
 }
 
-pub fn screen_coordsys() -> Result<(f64, f64, f64, f64), Box<dyn Error>> {
+fn cg_rect(r: &CGRect) -> Rect {
+    Rect::new(r.origin.x, r.origin.y, r.size.width, r.size.height)
+}
+
+/// Bounds of all displays in the global coordinate system, the main display is at the origin.
+pub fn screen_coordsys() -> Result<Rect, Box<dyn Error>> {
     let display_ids = CGDisplay::active_displays()
         .map_err(|err| CGError(format!("Failed to obtain displays, CGError code: {}", err)))?;
-    let rects: Vec<CGRect> = display_ids
-        .iter()
-        .map(|id| CGDisplay::new(*id).bounds())
-        .collect();
-    let mut x0 = 0.0;
-    let mut x1 = 0.0;
-    let mut y0 = 0.0;
-    let mut y1 = 0.0;
-    for r in rects.iter() {
-        let r_x0 = r.origin.x;
-        let r_x1 = r_x0 + r.size.width;
-        let r_y0 = r.origin.y;
-        let r_y1 = r_y0 + r.size.height;
-        x0 = f64::min(x0, r_x0);
-        x1 = f64::max(x1, r_x1);
-        y0 = f64::min(y0, r_y0);
-        y1 = f64::max(y1, r_y1);
+    // the origin is where the main display is
+    let main = Rect::new(0.0, 0.0, 0.0, 0.0);
+    Ok(bounding_rect(
+        display_ids
+            .iter()
+            .map(|id| cg_rect(&CGDisplay::new(*id).bounds()))
+            .chain([main]),
+    )
+    .unwrap())
+}
+
+/// Reports the cursor relative to the bounds of all displays, like the geometries of capturables.
+pub struct CGCursorTracker {
+    pixels: Vec<u8>,
+    /// Pixels of the shape sent last, the shape is only sent again if they change.
+    last: Vec<u8>,
+}
+
+impl Default for CGCursorTracker {
+    fn default() -> Self {
+        Self {
+            pixels: vec![0; MAX_CURSOR_SIZE * MAX_CURSOR_SIZE * 4],
+            last: Vec::new(),
+        }
+    }
+}
+
+impl CursorTracker for CGCursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        let location = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+            .and_then(CGEvent::new)
+            .map_err(|_| CGError("Failed to get the position of the cursor.".into()))?
+            .location();
+        let coordsys = screen_coordsys()?;
+        let (x, y) = coordsys.to_inner(location.x, location.y);
+
+        let mut info = CCursorInfo::default();
+        let mut err = CError::new();
+        unsafe {
+            current_cursor(
+                self.pixels.as_mut_ptr(),
+                MAX_CURSOR_SIZE,
+                &mut info,
+                &mut err,
+            )
+        };
+        if err.is_err() {
+            return Err(err.into());
+        }
+        let pixels = &self.pixels[..info.width * info.height * 4];
+        let shape = if pixels != self.last.as_slice() {
+            self.last = pixels.to_vec();
+            // unpremultiply the alpha
+            let rgba = pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let unpremultiply = |c: u8| match p[3] {
+                        0 => 0,
+                        a => (c as u32 * 255 / a as u32).min(255) as u8,
+                    };
+                    [
+                        unpremultiply(p[0]),
+                        unpremultiply(p[1]),
+                        unpremultiply(p[2]),
+                        p[3],
+                    ]
+                })
+                .collect();
+            // sizes are in points like the position
+            image::RgbaImage::from_raw(info.width as u32, info.height as u32, rgba).map(|image| {
+                CursorShape {
+                    width: info.points_width / coordsys.width,
+                    height: info.points_height / coordsys.height,
+                    xhot: info.xhot / coordsys.width,
+                    yhot: info.yhot / coordsys.height,
+                    image,
+                }
+            })
+        } else {
+            None
+        };
+        Ok(Cursor { x, y, shape })
     }
-    Ok((x0, y0, x1 - x0, y1 - y0))
 }
 
 pub fn get_displays() -> Result<Vec<CGDisplayCapturable>, Box<dyn Error>> {
@@ -307,6 +483,13 @@ pub fn get_displays() -> Result<Vec<CGDisplayCapturable>, Box<dyn Error>> {
         .collect())
 }
 
+/// Ids of the windows on screen, sorted.
+pub fn window_ids() -> Vec<CGWindowID> {
+    let mut ids: Vec<CGWindowID> = get_window_infos().iter().map(|w| w.id).collect();
+    ids.sort_unstable();
+    ids
+}
+
 pub fn get_windows() -> Result<Vec<CGWindowCapturable>, Box<dyn Error>> {
     let window_infos = get_window_infos();
     let cursor_id = window_infos
@@ -320,10 +503,13 @@ pub fn get_windows() -> Result<Vec<CGWindowCapturable>, Box<dyn Error>> {
         .map(|w| CGWindowCapturable {
             id: w.id,
             name: w.name.clone(),
+            pid: w.pid,
+            owner: w.owner.clone(),
             cursor_id,
             bounds: w.bounds,
-            geometry_relative: (0.0, 0.0, 1.0, 1.0),
+            geometry_relative: Rect::new(0.0, 0.0, 1.0, 1.0),
             last_geometry_update: Instant::now() - Duration::from_secs(2),
+            last_activation: Instant::now() - Duration::from_secs(2),
         })
         .collect())
 }
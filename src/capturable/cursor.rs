@@ -0,0 +1,92 @@
+//! Cursor tracking for backends that only know where the cursor is on the whole desktop, like
+//! Windows and macOS. The cursor of the desktop is reported relative to the capturable through its
+//! geometry, so it follows windows that move and monitors that are rearranged.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capturable::transform::{geometry_rect, Layout};
+use crate::capturable::{Capturable, Cursor, CursorShape, CursorTracker};
+
+/// Geometries some capturables cache, like windows on macOS, are refreshed this often.
+const GEOMETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracker of the cursor on the desktop, positions and sizes are in the units of the geometries
+/// of capturables, pixels of the virtual screen on Windows.
+#[cfg(target_os = "windows")]
+fn desktop_tracker() -> Box<dyn CursorTracker> {
+    Box::new(crate::capturable::win_cursor::WinCursorTracker::default())
+}
+
+/// Tracker of the cursor on the desktop, positions and sizes are relative to the bounds of all
+/// displays like the geometries of capturables.
+#[cfg(target_os = "macos")]
+fn desktop_tracker() -> Box<dyn CursorTracker> {
+    Box::new(crate::capturable::core_graphics::CGCursorTracker::default())
+}
+
+pub struct DesktopCursorTracker {
+    desktop: Box<dyn CursorTracker>,
+    capturable: Box<dyn Capturable>,
+    /// Set for monitors stitched together, the frame does not scale the desktop evenly then.
+    layout: Option<Arc<Mutex<Option<Layout>>>>,
+    last_refresh: Instant,
+    /// Shape in the units of the desktop, sent again if the capturable changes its size.
+    shape: Option<CursorShape>,
+    scale: (f64, f64),
+}
+
+impl DesktopCursorTracker {
+    pub fn new(capturable: Box<dyn Capturable>) -> Self {
+        Self {
+            desktop: desktop_tracker(),
+            capturable,
+            layout: None,
+            last_refresh: Instant::now(),
+            shape: None,
+            scale: (0.0, 0.0),
+        }
+    }
+
+    /// Track the cursor in frames of the given layout, see
+    /// [`CompositeCapturable`](crate::capturable::composite::CompositeCapturable).
+    pub fn with_layout(mut self, layout: Arc<Mutex<Option<Layout>>>) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+}
+
+impl CursorTracker for DesktopCursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        if self.last_refresh.elapsed() > GEOMETRY_INTERVAL {
+            self.capturable.refresh_geometry()?;
+            self.last_refresh = Instant::now();
+        }
+        let mut cursor = self.desktop.poll()?;
+        if let Some(shape) = cursor.shape.take() {
+            self.shape = Some(shape);
+            self.scale = (0.0, 0.0);
+        }
+        let rect = geometry_rect(&self.capturable.geometry()?);
+        let (x, y) = rect.to_inner(cursor.x, cursor.y);
+        let layout = self.layout.as_ref().and_then(|l| l.lock().unwrap().clone());
+        let ((x, y), (scale_x, scale_y)) = match layout {
+            Some(layout) => layout.to_frame(x, y),
+            None => ((x, y), (1.0, 1.0)),
+        };
+        (cursor.x, cursor.y) = (x, y);
+        let scale = (scale_x / rect.width, scale_y / rect.height);
+        if scale != self.scale {
+            self.scale = scale;
+            cursor.shape = self.shape.as_ref().map(|shape| CursorShape {
+                width: shape.width * scale.0,
+                height: shape.height * scale.1,
+                xhot: shape.xhot * scale.0,
+                yhot: shape.yhot * scale.1,
+                image: shape.image.clone(),
+            });
+        }
+        Ok(cursor)
+    }
+}
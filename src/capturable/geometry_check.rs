@@ -0,0 +1,210 @@
+//! Cross-checks the geometry the video is captured with against the geometry input is mapped to.
+//!
+//! The video thread and the input device each query the geometry of their own copy of the
+//! capturable. Some implementations cache it, so after a window has been dragged input may still
+//! be mapped to where the window used to be while the video already shows it at its new position
+//! and strokes land offset. The video thread reports the geometry whenever the size of the frames
+//! changes or the window moves, the input side compares it with its own geometry and refreshes the
+//! latter on mismatch.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use crate::capturable::{Capturable, Geometry, Recorder};
+
+/// How often the video thread looks for window moves, frame size changes are checked every frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Geometries closer than this are considered equal, about a pixel on an 8K screen.
+const TOLERANCE: f64 = 1e-4;
+
+fn approx_eq(a: &Geometry, b: &Geometry) -> bool {
+    match (a, b) {
+        (Geometry::Relative(x0, y0, w0, h0), Geometry::Relative(x1, y1, w1, h1)) => {
+            [(x0, x1), (y0, y1), (w0, w1), (h0, h1)]
+                .iter()
+                .all(|(a, b)| (*a - *b).abs() < TOLERANCE)
+        }
+        #[cfg(target_os = "windows")]
+        (a, b) => a == b,
+    }
+}
+
+struct VideoGeometry {
+    /// Incremented whenever the frame size or the geometry changes.
+    generation: u64,
+    frame_size: Option<(usize, usize)>,
+    geometry: Option<Geometry>,
+    last_poll: Instant,
+}
+
+/// Geometry the video is currently captured with, shared by the video thread and the input
+/// device of a client.
+pub struct GeometryCheck {
+    video: Mutex<VideoGeometry>,
+}
+
+impl GeometryCheck {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            video: Mutex::new(VideoGeometry {
+                generation: 0,
+                frame_size: None,
+                geometry: None,
+                last_poll: Instant::now(),
+            }),
+        })
+    }
+
+    /// Forget the geometry, called whenever capturing starts anew.
+    pub fn reset(&self) {
+        let mut video = self.video.lock().unwrap();
+        video.generation += 1;
+        video.frame_size = None;
+        video.geometry = None;
+    }
+
+    /// Called by the video thread for every captured frame, the geometry of the capturable is only
+    /// queried if the frame size changed or the last check is a while ago.
+    pub fn frame_captured(&self, capturable: &mut dyn Capturable, frame_size: (usize, usize)) {
+        let size_changed = {
+            let mut video = self.video.lock().unwrap();
+            let size_changed = video.frame_size != Some(frame_size);
+            if !size_changed && video.last_poll.elapsed() < POLL_INTERVAL {
+                return;
+            }
+            video.last_poll = Instant::now();
+            size_changed
+        };
+        // query the geometry without holding the lock, this may take a round trip to the X server
+        let geometry = capturable
+            .refresh_geometry()
+            .and_then(|_| capturable.geometry());
+        let geometry = match geometry {
+            Ok(geometry) => geometry,
+            Err(err) => {
+                debug!("Failed to get geometry of capturable for video: {err}");
+                return;
+            }
+        };
+        let mut video = self.video.lock().unwrap();
+        let moved = !video
+            .geometry
+            .as_ref()
+            .is_some_and(|g| approx_eq(g, &geometry));
+        if size_changed || moved {
+            debug!(
+                "Video geometry changed, frame size: {}x{}, geometry: {geometry:?}.",
+                frame_size.0, frame_size.1
+            );
+            video.generation += 1;
+            video.frame_size = Some(frame_size);
+            video.geometry = Some(geometry);
+        }
+    }
+
+    /// The video geometry if it changed since the given generation.
+    fn changed_since(&self, generation: u64) -> Option<(u64, Geometry)> {
+        let video = self.video.lock().unwrap();
+        if video.generation == generation {
+            return None;
+        }
+        video.geometry.map(|g| (video.generation, g))
+    }
+}
+
+/// Wraps the capturable input is mapped to and makes sure its geometry agrees with the video
+/// before any input is sent.
+#[derive(Clone)]
+pub struct CheckedCapturable {
+    inner: Box<dyn Capturable>,
+    check: Arc<GeometryCheck>,
+    generation: u64,
+}
+
+impl CheckedCapturable {
+    pub fn new(inner: Box<dyn Capturable>, check: Arc<GeometryCheck>) -> Self {
+        Self {
+            inner,
+            check,
+            generation: 0,
+        }
+    }
+
+    fn check_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((generation, video_geometry)) = self.check.changed_since(self.generation) else {
+            return Ok(());
+        };
+        self.generation = generation;
+        let geometry = self.inner.geometry()?;
+        if approx_eq(&geometry, &video_geometry) {
+            return Ok(());
+        }
+        self.inner.refresh_geometry()?;
+        let refreshed = self.inner.geometry()?;
+        if approx_eq(&refreshed, &video_geometry) {
+            info!(
+                "Corrected geometry used for input from {geometry:?} to {refreshed:?} to match \
+                the video."
+            );
+        } else if !approx_eq(&refreshed, &geometry) {
+            info!(
+                "Corrected geometry used for input from {geometry:?} to {refreshed:?}, the video \
+                geometry {video_geometry:?} is outdated."
+            );
+        } else {
+            // the window is most likely still moving, the next check will tell
+            debug!(
+                "Geometry used for input {geometry:?} differs from the video geometry \
+                {video_geometry:?}."
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Capturable for CheckedCapturable {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        self.inner.geometry()
+    }
+
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.refresh_geometry()
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.before_input(activate)?;
+        if let Err(err) = self.check_geometry() {
+            warn!("Failed to check geometry used for input: {err}");
+        }
+        Ok(())
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        self.inner.recorder(capture_cursor)
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn crate::capturable::CursorTracker>> {
+        self.inner.cursor_tracker()
+    }
+
+    fn application(&self) -> Option<String> {
+        self.inner.application()
+    }
+
+    fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        self.inner.map_position(x, y)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    fn remote_desktop_session(&self) -> Option<crate::capturable::pipewire::RemoteDesktopSession> {
+        self.inner.remote_desktop_session()
+    }
+}
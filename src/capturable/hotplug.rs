@@ -0,0 +1,230 @@
+//! Notice monitors and windows appearing or disappearing, so the list of capturables of clients
+//! can be updated without them asking for it.
+//!
+//! On X11 RandR reports changes of monitors and the window manager the list of windows, on Windows
+//! WM_DISPLAYCHANGE and events of top level windows being shown or hidden are used. On macOS
+//! displays are reconfigured with a callback, windows of other applications can only be polled.
+//! The capturables offered via PipeWire are chosen by the user in a dialog of the desktop, there
+//! is nothing to watch.
+
+#[cfg(target_os = "linux")]
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+use crate::cerror::CError;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn create_capturable_watch(err: *mut CError) -> *mut c_void;
+    fn wait_capturable_change(watch: *mut c_void);
+}
+
+type Callback = Box<dyn FnMut() + Send>;
+
+static SUBSCRIBERS: Mutex<Vec<(u64, Callback)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static MONITOR: Once = Once::new();
+
+/// Keeps calling the callback passed to [`subscribe`] until dropped.
+pub struct Subscription(u64);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().unwrap().retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Call the callback whenever monitors or windows may have appeared or disappeared, changes often
+/// come in bursts and the callback is called for each of them. Watching starts once the first
+/// subscriber subscribes.
+pub fn subscribe(callback: impl FnMut() + Send + 'static) -> Subscription {
+    MONITOR.call_once(|| {
+        std::thread::spawn(monitor);
+    });
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS.lock().unwrap().push((id, Box::new(callback)));
+    Subscription(id)
+}
+
+fn publish() {
+    for (_, callback) in SUBSCRIBERS.lock().unwrap().iter_mut() {
+        callback();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn monitor() {
+    // only PipeWire is used in the sandbox
+    if crate::sandbox::is_sandboxed() {
+        return;
+    }
+    let mut err = CError::new();
+    let watch = unsafe { create_capturable_watch(&mut err) };
+    if err.is_err() {
+        warn!("Failed to watch for monitors and windows: {err}");
+        return;
+    }
+    loop {
+        unsafe { wait_capturable_change(watch) };
+        publish();
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn monitor() {
+    use std::ptr::null_mut;
+
+    use winapi::shared::minwindef::{DWORD, LPARAM, LRESULT, UINT, WPARAM};
+    use winapi::shared::windef::{HWINEVENTHOOK, HWND};
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::winnt::LONG;
+    use winapi::um::winuser::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetAncestor, GetMessageW,
+        RegisterClassW, SetWinEventHook, TranslateMessage, CHILDID_SELF, EVENT_OBJECT_DESTROY,
+        EVENT_OBJECT_HIDE, GA_ROOT, MSG, OBJID_WINDOW, WINEVENT_OUTOFCONTEXT,
+        WINEVENT_SKIPOWNPROCESS, WM_DISPLAYCHANGE, WNDCLASSW,
+    };
+
+    unsafe extern "system" fn window_proc(
+        hwnd: HWND,
+        msg: UINT,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        if msg == WM_DISPLAYCHANGE {
+            publish();
+        }
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    unsafe extern "system" fn window_event(
+        _hook: HWINEVENTHOOK,
+        _event: DWORD,
+        hwnd: HWND,
+        id_object: LONG,
+        id_child: LONG,
+        _thread: DWORD,
+        _time: DWORD,
+    ) {
+        // events of the contents of windows are reported as well
+        if id_object == OBJID_WINDOW
+            && id_child == CHILDID_SELF
+            && !hwnd.is_null()
+            && GetAncestor(hwnd, GA_ROOT) == hwnd
+        {
+            publish();
+        }
+    }
+
+    let class_name: Vec<u16> = "WeylusCapturableWatch\0".encode_utf16().collect();
+    unsafe {
+        let instance = GetModuleHandleW(null_mut());
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: class_name.as_ptr(),
+            ..std::mem::zeroed()
+        };
+        if RegisterClassW(&class) == 0 {
+            warn!(
+                "Failed to watch for monitors: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        // WM_DISPLAYCHANGE is only sent to top level windows, this one is never shown
+        let hwnd = CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            null_mut(),
+            null_mut(),
+            instance,
+            null_mut(),
+        );
+        if hwnd.is_null() {
+            warn!(
+                "Failed to watch for monitors: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        // covers EVENT_OBJECT_SHOW, which lies in between
+        let hook = SetWinEventHook(
+            EVENT_OBJECT_DESTROY,
+            EVENT_OBJECT_HIDE,
+            null_mut(),
+            Some(window_event),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+        );
+        if hook.is_null() {
+            warn!("Failed to watch for windows, new windows are only listed on refresh.");
+        }
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, null_mut(), 0, 0) > 0 {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn monitor() {
+    use std::ffi::c_void;
+    use std::time::Duration;
+
+    use core_foundation::runloop::CFRunLoop;
+
+    use crate::capturable::core_graphics::window_ids;
+
+    /// Windows are polled this often.
+    const WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(2);
+    const BEGIN_CONFIGURATION_FLAG: u32 = 1;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGDisplayRegisterReconfigurationCallback(
+            callback: extern "C" fn(u32, u32, *mut c_void),
+            user_info: *mut c_void,
+        ) -> i32;
+    }
+
+    extern "C" fn reconfigured(_display: u32, flags: u32, _user_info: *mut c_void) {
+        // called before and after displays are reconfigured
+        if flags & BEGIN_CONFIGURATION_FLAG == 0 {
+            publish();
+        }
+    }
+
+    // the callback is run by the run loop of the thread registering it
+    std::thread::spawn(|| {
+        let err =
+            unsafe { CGDisplayRegisterReconfigurationCallback(reconfigured, std::ptr::null_mut()) };
+        if err != 0 {
+            warn!("Failed to watch for displays, CGError code: {err}");
+            return;
+        }
+        CFRunLoop::run_current();
+    });
+
+    let mut windows = window_ids();
+    loop {
+        std::thread::sleep(WINDOW_POLL_INTERVAL);
+        let current = window_ids();
+        if current != windows {
+            windows = current;
+            publish();
+        }
+    }
+}
@@ -2,23 +2,94 @@ use std::boxed::Box;
 use std::error::Error;
 use tracing::warn;
 
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub mod composite;
 #[cfg(target_os = "macos")]
 pub mod core_graphics;
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub mod cursor;
+pub mod geometry_check;
+pub mod hotplug;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
 pub mod pipewire;
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod portal_quirks;
+pub mod region;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
 #[allow(dead_code)]
 pub mod remote_desktop_dbus;
+pub mod rotation;
+#[cfg(target_os = "macos")]
+pub mod screencapturekit;
 pub mod testsrc;
+// monitors are only stitched together where there is no capturable for the whole desktop
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+pub mod transform;
 
 #[cfg(target_os = "windows")]
 pub mod captrs_capture;
 #[cfg(target_os = "windows")]
 pub mod win_ctx;
+#[cfg(target_os = "windows")]
+pub mod win_cursor;
+#[cfg(target_os = "windows")]
+pub mod win_graphics_capture;
+#[cfg(target_os = "windows")]
+pub mod win_window;
 #[cfg(target_os = "linux")]
 pub mod x11;
 pub trait Recorder {
     fn capture(&mut self) -> Result<crate::video::PixelProvider<'_>, Box<dyn Error>>;
+
+    /// Whether the content changed since the last call to capture, unchanged frames are not
+    /// encoded. Recorders that can not tell always return true.
+    fn frame_changed(&mut self) -> bool {
+        true
+    }
+
+    /// Whether the recorder lost its source, e.g. because the compositor restarted, and is trying
+    /// to get it back. Capturing fails meanwhile.
+    fn reconnecting(&self) -> bool {
+        false
+    }
+
+    /// Areas of the frame that changed since the last call to capture, None if the recorder can
+    /// not tell. An empty list means capture would return the same frame again.
+    fn dirty_rects(&mut self) -> Option<Vec<DirtyRect>> {
+        None
+    }
+}
+
+/// Area of a frame in pixels, (0, 0) is the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Shape of the cursor, width, height and the hotspot are relative to the size of the
+/// Capturable.
+#[derive(Clone)]
+pub struct CursorShape {
+    pub width: f64,
+    pub height: f64,
+    pub xhot: f64,
+    pub yhot: f64,
+    pub image: image::RgbaImage,
+}
+
+/// Position of the cursor relative to the Capturable, (0, 0) is the top left and (1, 1) the
+/// bottom right corner. The shape is only set if it changed since the last call to poll.
+pub struct Cursor {
+    pub x: f64,
+    pub y: f64,
+    pub shape: Option<CursorShape>,
+}
+
+pub trait CursorTracker: Send {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>>;
 }
 
 pub trait BoxCloneCapturable {
@@ -36,6 +107,7 @@ where
 /// Relative: x, y, width, height of the Capturable as floats relative to the absolute size of the
 /// screen. For example x=0.5, y=0.0, width=0.5, height=1.0 means the right half of the screen.
 /// VirtualScreen: offset_x, offset_y, width, height for a capturable using a virtual screen. (Windows)
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Geometry {
     Relative(f64, f64, f64, f64),
     #[cfg(target_os = "windows")]
@@ -49,12 +121,64 @@ pub trait Capturable: Send + BoxCloneCapturable {
     /// Return Geometry of the Capturable.
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>>;
 
+    /// Capturables that cache their geometry update it right away.
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
     /// Callback that is called right before input is simulated.
-    /// Useful to focus the window on input.
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>>;
+    /// Brings the window to the front if `activate` is set, see
+    /// [`ActivationPolicy`](crate::input::device::ActivationPolicy).
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>>;
 
     /// Return a Recorder that can record the current capturable.
     fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>>;
+
+    /// Return a CursorTracker that reports the cursor of the current capturable, if supported.
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        None
+    }
+
+    /// Name of the application owning the window or, for screens, the window in the foreground,
+    /// e.g. the window class on X11.
+    fn application(&self) -> Option<String> {
+        None
+    }
+
+    /// Map a position relative to the frames of the recorder to a position relative to the
+    /// geometry. They only differ if the frames are stitched together from several monitors.
+    fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        (x, y)
+    }
+
+    /// The portal session input for this capturable can be sent through, if any.
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    fn remote_desktop_session(&self) -> Option<pipewire::RemoteDesktopSession> {
+        None
+    }
+}
+
+/// Offer the monitors and, if there are several, all of them stitched together. X11 offers the
+/// whole desktop as the root window already.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn push_monitors(capturables: &mut Vec<Box<dyn Capturable>>, monitors: Vec<Box<dyn Capturable>>) {
+    let composite =
+        (monitors.len() > 1).then(|| composite::CompositeCapturable::new(monitors.clone()));
+    capturables.extend(monitors);
+    if let Some(composite) = composite {
+        capturables.push(Box::new(composite));
+    }
+}
+
+/// Whether Weylus runs in a Wayland session and screens are captured via PipeWire. X11 in a
+/// Wayland session is Xwayland, which shows neither the desktop nor windows of Wayland clients, so
+/// it is not offered then. An explicitly configured remote X display is still captured via X11.
+#[cfg(target_os = "linux")]
+pub fn is_wayland_session() -> bool {
+    cfg!(feature = "wayland")
+        && (std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland")
+            || std::env::var_os("WAYLAND_DISPLAY").is_some())
+        && !x11::is_remote_display()
 }
 
 impl Clone for Box<dyn Capturable> {
@@ -66,10 +190,16 @@ impl Clone for Box<dyn Capturable> {
 pub fn get_capturables(
     #[cfg(target_os = "linux")] wayland_support: bool,
     #[cfg(target_os = "linux")] capture_cursor: bool,
+    #[cfg(target_os = "linux")] x11_windows: x11::X11WindowOptions,
 ) -> Vec<Box<dyn Capturable>> {
     let mut capturables: Vec<Box<dyn Capturable>> = vec![];
     #[cfg(target_os = "linux")]
     {
+        #[cfg(not(feature = "wayland"))]
+        if wayland_support {
+            warn!("Weylus was built without the wayland feature, PipeWire is not available.");
+        }
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
         if wayland_support {
             use crate::capturable::pipewire::get_capturables as get_capturables_pw;
             match get_capturables_pw(capture_cursor) {
@@ -85,10 +215,16 @@ pub fn get_capturables(
             }
         }
 
+        // X11 in the sandbox or a Wayland session is at most Xwayland, which would neither show
+        // the desktop nor be able to receive input
+        if crate::sandbox::is_sandboxed() || (wayland_support && is_wayland_session()) {
+            return capturables;
+        }
+
         use crate::capturable::x11::X11Context;
         let x11ctx = X11Context::new();
         if let Some(mut x11ctx) = x11ctx {
-            match x11ctx.capturables() {
+            match x11ctx.capturables(x11_windows) {
                 Ok(captrs) => {
                     for c in captrs {
                         capturables.push(Box::new(c));
@@ -103,11 +239,22 @@ pub fn get_capturables(
     {
         use crate::capturable::core_graphics::get_displays as get_displays_cg;
         use crate::capturable::core_graphics::get_windows as get_windows_cg;
+        use crate::capturable::screencapturekit::SCKCapturable;
+        // CoreGraphics only records if ScreenCaptureKit is not available, before macOS 12.3
+        let sck = crate::capturable::screencapturekit::is_available();
         match get_displays_cg() {
             Ok(captrs) => {
-                for c in captrs {
-                    capturables.push(Box::new(c));
-                }
+                let displays: Vec<Box<dyn Capturable>> = captrs
+                    .into_iter()
+                    .map(|c| -> Box<dyn Capturable> {
+                        if sck {
+                            Box::new(SCKCapturable::display(c))
+                        } else {
+                            Box::new(c)
+                        }
+                    })
+                    .collect();
+                push_monitors(&mut capturables, displays);
             }
             Err(err) => warn!("Failed to get list of displays via CoreGraphics: {}", err),
         }
@@ -116,7 +263,11 @@ pub fn get_capturables(
             Ok(mut captrs) => {
                 captrs.sort_by(|a, b| a.name().to_lowercase().cmp(&b.name().to_lowercase()));
                 for c in captrs {
-                    capturables.push(Box::new(c));
+                    if sck {
+                        capturables.push(Box::new(SCKCapturable::window(c)));
+                    } else {
+                        capturables.push(Box::new(c));
+                    }
                 }
             }
             Err(err) => warn!("Failed to get list of windows via CoreGraphics: {}", err),
@@ -124,18 +275,54 @@ pub fn get_capturables(
     }
 
     #[cfg(target_os = "windows")]
-    {
+    if crate::capturable::win_graphics_capture::is_supported() {
+        use crate::capturable::win_ctx::WinCtx;
+        use crate::capturable::win_graphics_capture::GraphicsCaptureCapturable;
+        let winctx = WinCtx::new();
+        let monitors = winctx
+            .get_outputs()
+            .iter()
+            .map(|o| -> Box<dyn Capturable> {
+                Box::new(GraphicsCaptureCapturable::monitor(
+                    o.Monitor,
+                    String::from_utf16_lossy(o.DeviceName.as_ref()),
+                    o.DesktopCoordinates,
+                ))
+            })
+            .collect();
+        push_monitors(&mut capturables, monitors);
+        let mut windows: Vec<_> = crate::capturable::win_window::top_level_windows()
+            .into_iter()
+            .map(|(hwnd, title)| GraphicsCaptureCapturable::window(hwnd, title))
+            .collect();
+        windows.sort_by(|a, b| a.name().to_lowercase().cmp(&b.name().to_lowercase()));
+        for c in windows {
+            capturables.push(Box::new(c));
+        }
+    } else {
+        // DXGI and GDI for versions of Windows before 10 1903
         use crate::capturable::captrs_capture::CaptrsCapturable;
         use crate::capturable::win_ctx::WinCtx;
         let winctx = WinCtx::new();
-        for (i, o) in winctx.get_outputs().iter().enumerate() {
-            let captr = CaptrsCapturable::new(
-                i as u8,
-                String::from_utf16_lossy(o.DeviceName.as_ref()),
-                o.DesktopCoordinates,
-                winctx.get_union_rect().clone(),
-            );
-            capturables.push(Box::new(captr));
+        let monitors = winctx
+            .get_outputs()
+            .iter()
+            .enumerate()
+            .map(|(i, o)| -> Box<dyn Capturable> {
+                Box::new(CaptrsCapturable::new(
+                    i as u8,
+                    String::from_utf16_lossy(o.DeviceName.as_ref()),
+                    o.DesktopCoordinates,
+                    winctx.get_union_rect().clone(),
+                ))
+            })
+            .collect();
+        push_monitors(&mut capturables, monitors);
+
+        let mut windows = crate::capturable::win_window::get_windows();
+        windows.sort_by(|a, b| a.name().to_lowercase().cmp(&b.name().to_lowercase()));
+        for c in windows {
+            capturables.push(Box::new(c));
         }
     }
 
@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::ops::Range;
 use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tracing::{debug, trace, warn};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, trace, warn};
 
 use dbus::{
     arg::{OwnedFd, PropMap, RefArg, Variant},
@@ -14,20 +17,48 @@ use dbus::{
 
 use gstreamer as gst;
 use gstreamer::prelude::*;
+use gstreamer_allocators::DmaBufMemory;
 use gstreamer_app::AppSink;
 
+use crate::capturable::portal_quirks::PortalInfo;
 use crate::capturable::{Capturable, Geometry, Recorder};
-use crate::video::PixelProvider;
+use crate::video::{DmaBufFrame, PixelProvider};
 
 use crate::capturable::remote_desktop_dbus::{
     OrgFreedesktopPortalRemoteDesktop, OrgFreedesktopPortalRequestResponse,
     OrgFreedesktopPortalScreenCast,
 };
 
+/// Accept frames shared via DMA-BUF, these stay in GPU memory and are converted by VAAPI.
+static DMABUF: AtomicBool = AtomicBool::new(false);
+
+/// The modifier is implicit, older versions of GStreamer do not report it.
+const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// Wait this long between attempts to set up the portal session again after the stream was lost.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+
+pub fn set_dmabuf(enabled: bool) {
+    DMABUF.store(enabled, Ordering::Relaxed);
+}
+
 #[derive(Debug, Clone, Copy)]
 struct PwStreamInfo {
     path: u64,
     source_type: u64,
+    size: Option<(f64, f64)>,
+}
+
+/// Session of the RemoteDesktop portal, input can be sent through it without access to uinput,
+/// e.g. if Weylus runs in a sandbox.
+#[derive(Clone)]
+pub struct RemoteDesktopSession {
+    pub conn: Arc<SyncConnection>,
+    pub session: dbus::Path<'static>,
+    /// PipeWire node absolute pointer and touch coordinates refer to.
+    pub stream: u32,
+    /// Size of the stream in the logical coordinates of the compositor.
+    pub size: Option<(f64, f64)>,
 }
 
 #[derive(Debug)]
@@ -54,34 +85,125 @@ impl std::fmt::Display for GStreamerError {
 
 impl Error for GStreamerError {}
 
+/// A portal session along with everything that changes when it is set up anew after the
+/// compositor or xdg-desktop-portal restarted.
+struct SessionState {
+    // connection needs to be kept alive for recording
+    conn: Arc<SyncConnection>,
+    fd: OwnedFd,
+    streams: Vec<PwStreamInfo>,
+    /// Set if the streams belong to a remote desktop session.
+    session: Option<dbus::Path<'static>>,
+    restore_token: Option<String>,
+    /// Incremented whenever the session is set up anew.
+    generation: u64,
+    reconnect: Option<JoinHandle<Result<PortalSession, String>>>,
+    last_reconnect: Option<Instant>,
+}
+
+/// Shared by all capturables of a portal session, so the session is only set up again once if
+/// it is lost.
+struct SharedSession {
+    capture_cursor: bool,
+    state: Mutex<SessionState>,
+}
+
+impl SharedSession {
+    /// Called by recorders that lost their stream, returns the generation of the session once a
+    /// new session is available.
+    fn reconnect(&self, generation: u64) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return Some(state.generation);
+        }
+        if let Some(reconnect) = state.reconnect.take() {
+            if !reconnect.is_finished() {
+                state.reconnect = Some(reconnect);
+                return None;
+            }
+            match reconnect.join() {
+                Ok(Ok((conn, fd, streams, session, restore_token))) => {
+                    info!("PipeWire screen cast set up again.");
+                    state.conn = Arc::new(conn);
+                    state.fd = fd;
+                    state.streams = streams;
+                    state.session = session;
+                    if restore_token.is_some() {
+                        state.restore_token = restore_token;
+                    }
+                    state.generation += 1;
+                    return Some(state.generation);
+                }
+                Ok(Err(err)) => warn!("Failed to set up PipeWire screen cast again: {err}"),
+                Err(_) => warn!("Setting up PipeWire screen cast again panicked."),
+            }
+        }
+        if state
+            .last_reconnect
+            .is_some_and(|t| t.elapsed() < RECONNECT_INTERVAL)
+        {
+            return None;
+        }
+        state.last_reconnect = Some(Instant::now());
+        debug!("Setting up PipeWire screen cast again.");
+        let capture_cursor = self.capture_cursor;
+        // restore tokens can only be used once
+        let restore_token = state.restore_token.take();
+        // this may wait for the user to pick what to share again, so it happens in the background
+        state.reconnect = Some(std::thread::spawn(move || {
+            request_remote_desktop(capture_cursor, restore_token).map_err(|err| err.to_string())
+        }));
+        None
+    }
+}
+
 #[derive(Clone)]
 pub struct PipeWireCapturable {
-    // connection needs to be kept alive for recording
-    dbus_conn: Arc<SyncConnection>,
+    shared: Arc<SharedSession>,
+    /// Position of the stream within the streams of the session, the streams keep their order
+    /// when the session is restored.
+    index: usize,
+    source_type: u64,
+}
+
+/// Everything a recorder needs to connect to the stream.
+struct StreamRemote {
     fd: OwnedFd,
     path: u64,
-    source_type: u64,
+    generation: u64,
 }
 
 impl PipeWireCapturable {
-    fn new(conn: Arc<SyncConnection>, fd: OwnedFd, stream: PwStreamInfo) -> Self {
-        Self {
-            dbus_conn: conn,
-            fd,
+    fn remote(&self) -> Result<StreamRemote, Box<dyn Error>> {
+        let state = self.shared.state.lock().unwrap();
+        let stream = state.streams.get(self.index).ok_or_else(|| {
+            DBusError(format!(
+                "Stream {} is not part of the screen cast anymore.",
+                self.index
+            ))
+        })?;
+        Ok(StreamRemote {
+            fd: state.fd.clone(),
             path: stream.path,
-            source_type: stream.source_type,
-        }
+            generation: state.generation,
+        })
+    }
+
+    fn path(&self) -> Option<u64> {
+        let state = self.shared.state.lock().unwrap();
+        state.streams.get(self.index).map(|s| s.path)
     }
 }
 
 impl std::fmt::Debug for PipeWireCapturable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.shared.state.lock().unwrap();
         write!(
             f,
-            "PipeWireCapturable {{dbus: {}, fd: {}, path: {}, source_type: {}}}",
-            self.dbus_conn.unique_name(),
-            self.fd.as_raw_fd(),
-            self.path,
+            "PipeWireCapturable {{dbus: {}, fd: {}, path: {:?}, source_type: {}}}",
+            state.conn.unique_name(),
+            state.fd.as_raw_fd(),
+            state.streams.get(self.index).map(|s| s.path),
             self.source_type
         )
     }
@@ -94,44 +216,91 @@ impl Capturable for PipeWireCapturable {
             2 => "Window",
             _ => "Unknown",
         };
-        format!("Pipewire {}, path: {}", type_str, self.path)
+        match self.path() {
+            Some(path) => format!("Pipewire {}, path: {}", type_str, path),
+            None => format!("Pipewire {}", type_str),
+        }
     }
 
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         Ok(Geometry::Relative(0.0, 0.0, 1.0, 1.0))
     }
 
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    fn before_input(&mut self, _activate: bool) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
 
     fn recorder(&self, _capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
         Ok(Box::new(PipeWireRecorder::new(self.clone())?))
     }
+
+    fn remote_desktop_session(&self) -> Option<RemoteDesktopSession> {
+        let state = self.shared.state.lock().unwrap();
+        let stream = state.streams.get(self.index)?;
+        Some(RemoteDesktopSession {
+            conn: state.conn.clone(),
+            session: state.session.clone()?,
+            stream: stream.path as u32,
+            size: stream.size,
+        })
+    }
 }
 
 pub struct PipeWireRecorder {
     buffer: Option<gst::MappedBuffer<gst::buffer::Readable>>,
-    buffer_cropped: Vec<u8>,
+    /// The buffer is kept so the file descriptor of the frame stays valid.
+    dmabuf: Option<(gst::Buffer, DmaBufFrame)>,
+    /// Result of pulling a sample in frame_changed, consumed by the next capture.
+    pending_sample: Option<Option<gst::Sample>>,
     pix_fmt: String,
-    is_cropped: bool,
+    /// Part of the buffer holding the frame, rows may be padded and the frame may be cropped.
+    frame_range: Range<usize>,
+    stride: usize,
     pipeline: gst::Pipeline,
     appsink: AppSink,
     width: usize,
     height: usize,
+    capturable: PipeWireCapturable,
+    /// Generation of the session the pipeline is connected to.
+    generation: u64,
+    /// Set once the stream failed or ended, until the session has been set up again.
+    lost: bool,
 }
 
 impl PipeWireRecorder {
     pub fn new(capturable: PipeWireCapturable) -> Result<Self, Box<dyn Error>> {
+        let remote = capturable.remote()?;
+        let (pipeline, appsink) = Self::start_pipeline(&remote)?;
+        Ok(Self {
+            pipeline,
+            appsink,
+            buffer: None,
+            dmabuf: None,
+            pending_sample: None,
+            pix_fmt: "".into(),
+            width: 0,
+            height: 0,
+            frame_range: 0..0,
+            stride: 0,
+            capturable,
+            generation: remote.generation,
+            lost: false,
+        })
+    }
+
+    fn start_pipeline(remote: &StreamRemote) -> Result<(gst::Pipeline, AppSink), Box<dyn Error>> {
         let pipeline = gst::Pipeline::new();
 
         let src = gst::ElementFactory::make("pipewiresrc").build()?;
-        src.set_property("fd", &capturable.fd.as_raw_fd());
-        src.set_property("path", &format!("{}", capturable.path));
+        src.set_property("fd", &remote.fd.as_raw_fd());
+        src.set_property("path", &format!("{}", remote.path));
+
+        let dmabuf = DMABUF.load(Ordering::Relaxed);
 
         // For some reason pipewire blocks on destruction of AppSink if this is not set to true,
         // see: https://gitlab.freedesktop.org/pipewire/pipewire/-/issues/982
-        src.set_property("always-copy", &true);
+        // Copying prevents sharing buffers via DMA-BUF though.
+        src.set_property("always-copy", &!dmabuf);
 
         let sink = gst::ElementFactory::make("appsink").build()?;
         sink.set_property("drop", &true);
@@ -142,7 +311,15 @@ impl PipeWireRecorder {
         let appsink = sink
             .dynamic_cast::<AppSink>()
             .map_err(|_| GStreamerError("Sink element is expected to be an appsink!".into()))?;
-        let mut caps = gst::Caps::new_empty();
+        // DMA-BUF is preferred but falls back to system memory
+        let mut caps = if dmabuf {
+            gst::Caps::builder("video/x-raw")
+                .features([gstreamer_allocators::CAPS_FEATURE_MEMORY_DMABUF])
+                .field("format", gst::List::new(["BGRx", "RGBx", "DMA_DRM"]))
+                .build()
+        } else {
+            gst::Caps::new_empty()
+        };
         caps.merge_structure(gst::structure::Structure::from_iter(
             "video/x-raw",
             [("format", "BGRx".into())],
@@ -154,29 +331,67 @@ impl PipeWireRecorder {
         appsink.set_caps(Some(&caps));
 
         pipeline.set_state(gst::State::Playing)?;
-        Ok(Self {
-            pipeline,
-            appsink,
-            buffer: None,
-            pix_fmt: "".into(),
-            width: 0,
-            height: 0,
-            buffer_cropped: vec![],
-            is_cropped: false,
-        })
+        Ok((pipeline, appsink))
+    }
+}
+
+impl PipeWireRecorder {
+    fn pull_sample(&self) -> Option<gst::Sample> {
+        self.appsink
+            .try_pull_sample(gst::ClockTime::from_mseconds(16))
+    }
+
+    /// Notice if the stream failed or ended, e.g. because the compositor restarted, and connect to
+    /// the stream again once the session has been set up anew. Fails as long as the stream is
+    /// lost.
+    fn check_stream(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.lost {
+            let Some(bus) = self.pipeline.bus() else {
+                return Ok(());
+            };
+            let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error, gst::MessageType::Eos])
+            else {
+                return Ok(());
+            };
+            match msg.view() {
+                gst::MessageView::Error(err) => warn!("PipeWire stream failed: {}", err.error()),
+                _ => warn!("PipeWire stream ended."),
+            }
+            self.lost = true;
+            self.buffer = None;
+            self.dmabuf = None;
+            self.pending_sample = None;
+            // only a single pipeline may exist at a time, see handle_video
+            if let Err(err) = self.pipeline.set_state(gst::State::Null) {
+                warn!("Failed to stop GStreamer pipeline: {}.", err);
+            }
+        }
+        let lost = || GStreamerError("PipeWire stream lost, reconnecting.".into());
+        let Some(generation) = self.capturable.shared.reconnect(self.generation) else {
+            return Err(lost().into());
+        };
+        self.generation = generation;
+        let remote = self.capturable.remote()?;
+        let (pipeline, appsink) = Self::start_pipeline(&remote)?;
+        self.pipeline = pipeline;
+        self.appsink = appsink;
+        self.lost = false;
+        info!("Reconnected to PipeWire stream.");
+        Ok(())
     }
 }
 
 impl Recorder for PipeWireRecorder {
     fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
-        if let Some(sample) = self
-            .appsink
-            .try_pull_sample(gst::ClockTime::from_mseconds(16))
-        {
+        self.check_stream()?;
+        let sample = self
+            .pending_sample
+            .take()
+            .unwrap_or_else(|| self.pull_sample());
+        if let Some(sample) = sample {
             let cap = sample.caps().unwrap().structure(0).unwrap();
             let w: i32 = cap.value("width")?.get()?;
             let h: i32 = cap.value("height")?.get()?;
-            self.pix_fmt = cap.value("format")?.get()?;
             let w = w as usize;
             let h = h as usize;
             let buf = sample
@@ -189,64 +404,157 @@ impl Recorder for PipeWireRecorder {
             if Some((0, 0, w as u32, h as u32)) == crop {
                 crop = None;
             }
+            if buf.n_memory() > 0 && buf.peek_memory(0).is_memory_type::<DmaBufMemory>() {
+                let frame = dmabuf_frame(&buf, cap, w, h, crop)?;
+                self.dmabuf = Some((buf, frame));
+                return Ok(PixelProvider::DmaBuf(frame));
+            }
+            self.dmabuf = None;
+            self.pix_fmt = cap.value("format")?.get()?;
+            // rows are padded to the alignment the compositor uses, the buffer's meta
+            // describes its layout
+            let (offset, stride) = match buf.meta::<gstreamer_video::VideoMeta>() {
+                Some(meta) => (meta.offset()[0], meta.stride()[0] as usize),
+                None => (0, w * 4),
+            };
             let buf = buf
                 .into_mapped_buffer_readable()
                 .map_err(|_| GStreamerError("Failed to map buffer.".into()))?;
-            let buf_size = buf.size();
+            let (x_off, y_off, w_crop, h_crop) = crop.map_or((0, 0, w, h), |(x, y, w, h)| {
+                (x as usize, y as usize, w as usize, h as usize)
+            });
+            // the cropped region is encoded right from the buffer, it merely starts further in
+            let start = offset + y_off * stride + x_off * 4;
+            let end = start + stride * h_crop.saturating_sub(1) + w_crop * 4;
             // BGRx is 4 bytes per pixel
-            if buf_size != (w * h * 4) {
+            if w_crop == 0
+                || h_crop == 0
+                || x_off + w_crop > w
+                || y_off + h_crop > h
+                || stride < w * 4
+                || end > buf.size()
+            {
                 // for some reason the width and height of the caps do not guarantee correct buffer
                 // size, so ignore those buffers, see:
                 // https://gitlab.freedesktop.org/pipewire/pipewire/-/issues/985
                 trace!(
-                    "Size of mapped buffer: {} does NOT match size of capturable {}x{}@BGRx, \
-                    dropping it!",
-                    buf_size,
+                    "Size of mapped buffer: {} does NOT match size of capturable {}x{}@BGRx with \
+                    stride {}, dropping it!",
+                    buf.size(),
                     w,
-                    h
+                    h,
+                    stride
                 );
             } else {
-                // Copy region specified by crop into self.buffer_cropped
-                // TODO: Figure out if ffmpeg provides a zero copy alternative
-                if let Some((x_off, y_off, w_crop, h_crop)) = crop {
-                    let x_off = x_off as usize;
-                    let y_off = y_off as usize;
-                    let w_crop = w_crop as usize;
-                    let h_crop = h_crop as usize;
-                    self.buffer_cropped.clear();
-                    let data = buf.as_slice();
-                    // BGRx is 4 bytes per pixel
-                    self.buffer_cropped.reserve(w_crop * h_crop * 4);
-                    for y in y_off..(y_off + h_crop) {
-                        let i = 4 * (w * y + x_off);
-                        self.buffer_cropped.extend(&data[i..i + 4 * w_crop]);
-                    }
-                    self.width = w_crop;
-                    self.height = h_crop;
-                } else {
-                    self.width = w;
-                    self.height = h;
-                }
-                self.is_cropped = crop.is_some();
+                self.width = w_crop;
+                self.height = h_crop;
+                self.stride = stride;
+                self.frame_range = start..end;
                 self.buffer = Some(buf);
             }
         } else {
             trace!("No new buffer available, falling back to previous one.");
         }
+        if let Some((_, frame)) = self.dmabuf {
+            return Ok(PixelProvider::DmaBuf(frame));
+        }
         if self.buffer.is_none() {
             return Err(Box::new(GStreamerError("No buffer available!".into())));
         }
-        let buf = if self.is_cropped {
-            self.buffer_cropped.as_slice()
-        } else {
-            self.buffer.as_ref().unwrap().as_slice()
-        };
+        let buf = &self.buffer.as_ref().unwrap().as_slice()[self.frame_range.clone()];
         match self.pix_fmt.as_str() {
-            "BGRx" => Ok(PixelProvider::BGR0(self.width, self.height, buf)),
-            "RGBx" => Ok(PixelProvider::RGB0(self.width, self.height, buf)),
+            "BGRx" => Ok(PixelProvider::BGR0S(
+                self.width,
+                self.height,
+                self.stride,
+                buf,
+            )),
+            "RGBx" => Ok(PixelProvider::RGB0S(
+                self.width,
+                self.height,
+                self.stride,
+                buf,
+            )),
             _ => unreachable!(),
         }
     }
+
+    // PipeWire only sends new buffers if the content changed
+    fn frame_changed(&mut self) -> bool {
+        if self.lost {
+            return true;
+        }
+        if self.pending_sample.is_none() {
+            self.pending_sample = Some(self.pull_sample());
+        }
+        matches!(self.pending_sample, Some(Some(_)))
+            || (self.buffer.is_none() && self.dmabuf.is_none())
+    }
+
+    fn reconnecting(&self) -> bool {
+        self.lost
+    }
+}
+
+/// DRM fourcc and modifier of a frame. Since GStreamer 1.24 DMA-BUF frames are described via
+/// drm-format, e.g. XR24:0x0100000000000001, older versions only set format.
+fn drm_format(cap: &gst::StructureRef) -> Result<(u32, u64), Box<dyn Error>> {
+    let fourcc = |code: &str| -> Result<u32, Box<dyn Error>> {
+        let code: [u8; 4] = code
+            .as_bytes()
+            .try_into()
+            .map_err(|_| GStreamerError(format!("Invalid DRM format: {}", code)))?;
+        Ok(u32::from_le_bytes(code))
+    };
+    if let Ok(drm_format) = cap.get::<&str>("drm-format") {
+        return match drm_format.split_once(':') {
+            Some((code, modifier)) => Ok((
+                fourcc(code)?,
+                u64::from_str_radix(modifier.trim_start_matches("0x"), 16)?,
+            )),
+            // no modifier means linear
+            None => Ok((fourcc(drm_format)?, 0)),
+        };
+    }
+    match cap.get::<&str>("format")? {
+        "BGRx" => Ok((fourcc("XR24")?, DRM_FORMAT_MOD_INVALID)),
+        "RGBx" => Ok((fourcc("XB24")?, DRM_FORMAT_MOD_INVALID)),
+        format => Err(GStreamerError(format!("Unsupported DMA-BUF format: {}", format)).into()),
+    }
+}
+
+fn dmabuf_frame(
+    buf: &gst::Buffer,
+    cap: &gst::StructureRef,
+    width: usize,
+    height: usize,
+    crop: Option<(u32, u32, u32, u32)>,
+) -> Result<DmaBufFrame, Box<dyn Error>> {
+    let memory = buf
+        .peek_memory(0)
+        .downcast_memory_ref::<DmaBufMemory>()
+        .ok_or_else(|| GStreamerError("Buffer is not a DMA-BUF.".into()))?;
+    let (drm_format, modifier) = drm_format(cap)?;
+    let (offset, pitch) = match buf.meta::<gstreamer_video::VideoMeta>() {
+        Some(meta) => (meta.offset()[0], meta.stride()[0] as usize),
+        None => (0, width * 4),
+    };
+    let (crop_x, crop_y, crop_width, crop_height) =
+        crop.unwrap_or((0, 0, width as u32, height as u32));
+    Ok(DmaBufFrame {
+        fd: memory.fd(),
+        size: memory.maxsize() as u64,
+        offset: (memory.offset() + offset) as u64,
+        pitch: pitch as u64,
+        drm_format,
+        modifier,
+        width: width as i32,
+        height: height as i32,
+        crop_x: crop_x as i32,
+        crop_y: crop_y as i32,
+        crop_width: crop_width as i32,
+        crop_height: crop_height as i32,
+    })
 }
 
 impl Drop for PipeWireRecorder {
@@ -305,7 +613,7 @@ where
         })
 }
 
-fn get_portal(conn: &SyncConnection) -> Proxy<'_, &SyncConnection> {
+pub fn get_portal(conn: &SyncConnection) -> Proxy<'_, &SyncConnection> {
     conn.with_proxy(
         "org.freedesktop.portal.Desktop",
         "/org/freedesktop/portal/desktop",
@@ -345,6 +653,10 @@ fn streams_from_response(response: &OrgFreedesktopPortalRequestResponse) -> Vec<
                         source_type: attributes
                             .get("source_type")
                             .map_or(Some(0), |v| v.as_u64())?,
+                        size: attributes.get("size").and_then(|v| {
+                            let mut size = v.as_iter()?;
+                            Some((size.next()?.as_i64()? as f64, size.next()?.as_i64()? as f64))
+                        }),
                     })
                 })
                 .collect::<Vec<PwStreamInfo>>(),
@@ -361,6 +673,7 @@ struct CallBackContext {
     streams: Vec<PwStreamInfo>,
     fd: Option<OwnedFd>,
     restore_token: Option<String>,
+    portal: PortalInfo,
     has_remote_desktop: bool,
     failure: bool,
 }
@@ -405,17 +718,17 @@ fn select_devices(
         Variant(Box::new(format!("weylus{t}"))),
     );
 
-    // TODO
-    //args.insert(
-    //    "restore_token".to_string(),
-    //    Variant(Box::new(format!("weylus{t}"))),
-    //);
-
     // persist modes:
     // 0: Do not persist (default)
     // 1: Permissions persist as long as the application is running
     // 2: Permissions persist until explicitly revoked
-    args.insert("persist_mode".to_string(), Variant(Box::new(2 as u32)));
+    if context.lock().unwrap().portal.supports_device_persistence() {
+        args.insert("persist_mode".to_string(), Variant(Box::new(2 as u32)));
+        // restores the devices and sources chosen previously without asking the user again
+        if let Some(token) = context.lock().unwrap().restore_token.clone() {
+            args.insert("restore_token".to_string(), Variant(Box::new(token)));
+        }
+    }
 
     // device types
     // 1: KEYBOARD
@@ -448,31 +761,38 @@ fn select_sources(
     // allow multiple sources
     args.insert("multiple".into(), Variant(Box::new(true)));
 
+    let (portal_info, capture_cursor, has_remote_desktop) = {
+        let context = context.lock().unwrap();
+        (
+            context.portal,
+            context.capture_cursor,
+            context.has_remote_desktop,
+        )
+    };
+
     // 1: MONITOR
     // 2: WINDOW
     // 4: VIRTUAL
-    let source_types = portal.available_source_types()?;
-    debug!("Available source types: {source_types}.");
-    args.insert("types".into(), Variant(Box::new(source_types)));
+    // some versions of xdg-desktop-portal-wlr fail on source types they do not support, so only
+    // the available ones are requested
+    if portal_info.source_types != 0 {
+        args.insert("types".into(), Variant(Box::new(portal_info.source_types)));
+    }
 
-    let capture_cursor = context.lock().unwrap().capture_cursor;
     // 1: Hidden. The cursor is not part of the screen cast stream.
     // 2: Embedded: The cursor is embedded as part of the stream buffers.
     // 4: Metadata: The cursor is not part of the screen cast stream, but sent as PipeWire stream metadata.
-    let cursor_mode = if capture_cursor { 2u32 } else { 1u32 };
+    if let Some(cursor_mode) = portal_info.cursor_mode(capture_cursor) {
+        args.insert("cursor_mode".into(), Variant(Box::new(cursor_mode)));
+    }
 
-    let is_plasma = std::env::var("DESKTOP_SESSION").map_or(false, |s| s.contains("plasma"));
-    if is_plasma && capture_cursor {
-        // Warn the user if capturing the cursor is tried on kde as this can crash
-        // kwin_wayland and tear down the plasma desktop, see:
-        // https://bugs.kde.org/show_bug.cgi?id=435042
-        warn!(
-            "You are attempting to capture the cursor under KDE Plasma, this may crash your \
-                    desktop, see https://bugs.kde.org/show_bug.cgi?id=435042 for details! \
-                    You have been warned."
-        );
+    // remote desktop sessions are persisted via select_devices
+    if !has_remote_desktop && portal_info.supports_restore_token() {
+        args.insert("persist_mode".into(), Variant(Box::new(2u32)));
+        if let Some(token) = context.lock().unwrap().restore_token.clone() {
+            args.insert("restore_token".into(), Variant(Box::new(token)));
+        }
     }
-    args.insert("cursor_mode".into(), Variant(Box::new(cursor_mode)));
 
     let path = portal.select_sources(context.lock().unwrap().session.clone(), args)?;
     handle_response(portal, path, context, on_select_sources_response)?;
@@ -527,7 +847,9 @@ fn on_start_response(
     if let Some(Some(t)) = r.results.get("restore_token").map(|t| t.as_str()) {
         context.restore_token = Some(t.to_string());
     }
-    dbg!(&context.restore_token);
+    if context.restore_token.is_some() {
+        debug!("Got restore token.");
+    }
     if context.has_remote_desktop {
         debug!("Remote Desktop Session started");
     } else {
@@ -536,23 +858,43 @@ fn on_start_response(
     Ok(())
 }
 
+/// The connection, the PipeWire remote, the streams, the session if input can be sent through
+/// it and the token to restore the session with.
+type PortalSession = (
+    SyncConnection,
+    OwnedFd,
+    Vec<PwStreamInfo>,
+    Option<dbus::Path<'static>>,
+    Option<String>,
+);
+
+/// Set up a portal session, the restore token, if any, keeps the user from being asked again.
 fn request_remote_desktop(
     capture_cursor: bool,
-) -> Result<(SyncConnection, OwnedFd, Vec<PwStreamInfo>), Box<dyn Error>> {
+    restore_token: Option<String>,
+) -> Result<PortalSession, Box<dyn Error>> {
     let conn = SyncConnection::new_session()?;
     let portal = get_portal(&conn);
 
-    // Disabled for KDE plasma due to https://bugs.kde.org/show_bug.cgi?id=484996
     // List of supported DEs: https://wiki.archlinux.org/title/XDG_Desktop_Portal#List_of_backends_and_interfaces
-    let has_remote_desktop =
-        std::env::var("DESKTOP_SESSION").map_or(false, |s| s.contains("gnome"));
+    let portal_info = PortalInfo::detect(&conn, &portal);
+    portal_info.log_summary();
+    if portal_info.screen_cast_version.is_none() {
+        return Err(Box::new(DBusError(format!(
+            "The {} portal does not support screen casts.",
+            portal_info.backend
+        ))));
+    }
+    let has_remote_desktop = portal_info.use_remote_desktop();
 
     let context = CallBackContext {
         capture_cursor,
         session: Default::default(),
         streams: Default::default(),
         fd: None,
-        restore_token: None,
+        // the start response replaces the token with a new one
+        restore_token,
+        portal: portal_info,
         has_remote_desktop,
         failure: false,
     };
@@ -591,19 +933,45 @@ fn request_remote_desktop(
     }
     let context = context.lock().unwrap();
     if context.fd.is_some() && !context.streams.is_empty() {
-        Ok((conn, context.fd.clone().unwrap(), context.streams.clone()))
+        let session = context.has_remote_desktop.then(|| context.session.clone());
+        Ok((
+            conn,
+            context.fd.clone().unwrap(),
+            context.streams.clone(),
+            session,
+            context.restore_token.clone(),
+        ))
     } else {
-        Err(Box::new(DBusError(
-            "Failed to obtain screen capture.".into(),
-        )))
+        Err(Box::new(DBusError(format!(
+            "Failed to obtain screen capture via the {} portal.",
+            context.portal.backend
+        ))))
     }
 }
 
 pub fn get_capturables(capture_cursor: bool) -> Result<Vec<PipeWireCapturable>, Box<dyn Error>> {
-    let (conn, fd, streams) = request_remote_desktop(capture_cursor)?;
-    let conn = Arc::new(conn);
-    Ok(streams
+    let (conn, fd, streams, session, restore_token) = request_remote_desktop(capture_cursor, None)?;
+    let source_types: Vec<u64> = streams.iter().map(|s| s.source_type).collect();
+    let shared = Arc::new(SharedSession {
+        capture_cursor,
+        state: Mutex::new(SessionState {
+            conn: Arc::new(conn),
+            fd,
+            streams,
+            session,
+            restore_token,
+            generation: 0,
+            reconnect: None,
+            last_reconnect: None,
+        }),
+    });
+    Ok(source_types
         .into_iter()
-        .map(|s| PipeWireCapturable::new(conn.clone(), fd.clone(), s))
+        .enumerate()
+        .map(|(index, source_type)| PipeWireCapturable {
+            shared: shared.clone(),
+            index,
+            source_type,
+        })
         .collect())
 }
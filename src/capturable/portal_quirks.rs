@@ -0,0 +1,221 @@
+//! Detect the backend implementing the xdg-desktop-portal and what it supports, so screen casts
+//! can be requested with parameters the backend actually handles. Backends differ in the
+//! interfaces they implement and some have bugs that are better avoided.
+
+use std::time::Duration;
+
+use dbus::blocking::{Proxy, SyncConnection};
+use tracing::{info, warn};
+
+use crate::capturable::remote_desktop_dbus::{
+    OrgFreedesktopPortalRemoteDesktop, OrgFreedesktopPortalScreenCast,
+};
+
+// cursor modes of the ScreenCast portal
+pub const CURSOR_MODE_HIDDEN: u32 = 1;
+pub const CURSOR_MODE_EMBEDDED: u32 = 2;
+pub const CURSOR_MODE_METADATA: u32 = 4;
+
+/// Restore tokens and persist_mode were added in version 4 of the ScreenCast portal.
+const SCREEN_CAST_RESTORE_VERSION: u32 = 4;
+
+/// persist_mode was added in version 2 of the RemoteDesktop portal.
+const REMOTE_DESKTOP_PERSIST_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalBackend {
+    Gnome,
+    Kde,
+    Wlr,
+    Lxqt,
+    Unknown,
+}
+
+impl PortalBackend {
+    /// Bus name the backend registers, this is more reliable than the desktop session as
+    /// portals of other desktops can be configured.
+    fn bus_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Gnome => Some("org.freedesktop.impl.portal.desktop.gnome"),
+            Self::Kde => Some("org.freedesktop.impl.portal.desktop.kde"),
+            Self::Wlr => Some("org.freedesktop.impl.portal.desktop.wlr"),
+            Self::Lxqt => Some("org.freedesktop.impl.portal.desktop.lxqt"),
+            Self::Unknown => None,
+        }
+    }
+
+    fn from_desktop() -> Self {
+        let desktop = std::env::var("XDG_CURRENT_DESKTOP")
+            .or_else(|_| std::env::var("DESKTOP_SESSION"))
+            .unwrap_or_default()
+            .to_lowercase();
+        // XDG_CURRENT_DESKTOP is a colon separated list
+        for desktop in desktop.split(':') {
+            match desktop {
+                d if d.contains("gnome") => return Self::Gnome,
+                d if d.contains("kde") || d.contains("plasma") => return Self::Kde,
+                d if d.contains("lxqt") => return Self::Lxqt,
+                "sway" | "hyprland" | "river" | "wayfire" | "labwc" | "wlroots" => {
+                    return Self::Wlr
+                }
+                _ => (),
+            }
+        }
+        Self::Unknown
+    }
+
+    fn detect(conn: &SyncConnection) -> Self {
+        let proxy = conn.with_proxy(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            Duration::from_millis(1000),
+        );
+        for backend in [Self::Gnome, Self::Kde, Self::Wlr, Self::Lxqt] {
+            let name = backend.bus_name().unwrap();
+            let res: Result<(bool,), _> =
+                proxy.method_call("org.freedesktop.DBus", "NameHasOwner", (name,));
+            if let Ok((true,)) = res {
+                return backend;
+            }
+        }
+        // backends are started on demand, they may not run yet
+        Self::from_desktop()
+    }
+}
+
+impl std::fmt::Display for PortalBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gnome => write!(f, "GNOME"),
+            Self::Kde => write!(f, "KDE"),
+            Self::Wlr => write!(f, "wlroots"),
+            Self::Lxqt => write!(f, "LXQt"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// What the portal in use supports.
+#[derive(Debug, Clone, Copy)]
+pub struct PortalInfo {
+    pub backend: PortalBackend,
+    /// Version of the ScreenCast portal, None if it is not implemented.
+    pub screen_cast_version: Option<u32>,
+    /// Version of the RemoteDesktop portal, None if it is not implemented.
+    pub remote_desktop_version: Option<u32>,
+    pub source_types: u32,
+    pub cursor_modes: u32,
+}
+
+impl PortalInfo {
+    pub fn detect(conn: &SyncConnection, portal: &Proxy<&SyncConnection>) -> Self {
+        let screen_cast_version = OrgFreedesktopPortalScreenCast::version(portal).ok();
+        Self {
+            backend: PortalBackend::detect(conn),
+            screen_cast_version,
+            remote_desktop_version: OrgFreedesktopPortalRemoteDesktop::version(portal).ok(),
+            source_types: portal.available_source_types().unwrap_or(0),
+            // version 1 of the portal did not support selecting the cursor mode
+            cursor_modes: portal.available_cursor_modes().unwrap_or(0),
+        }
+    }
+
+    /// Whether to use the RemoteDesktop portal, which is required for input on Wayland. The
+    /// sessions of KDE fail to start, see https://bugs.kde.org/show_bug.cgi?id=484996 and
+    /// xdg-desktop-portal-wlr and the LXQt portal do not implement it at all. In a sandbox the
+    /// portal is the only way to send input, so it is tried with any backend implementing it.
+    pub fn use_remote_desktop(&self) -> bool {
+        if self.remote_desktop_version.is_none() {
+            return false;
+        }
+        if self.backend == PortalBackend::Gnome {
+            return true;
+        }
+        if crate::sandbox::is_sandboxed() {
+            if self.backend == PortalBackend::Kde {
+                warn!(
+                    "Using the RemoteDesktop portal of KDE, starting the session may fail, see \
+                    https://bugs.kde.org/show_bug.cgi?id=484996."
+                );
+            }
+            return true;
+        }
+        false
+    }
+
+    pub fn supports_restore_token(&self) -> bool {
+        self.screen_cast_version
+            .is_some_and(|v| v >= SCREEN_CAST_RESTORE_VERSION)
+    }
+
+    pub fn supports_device_persistence(&self) -> bool {
+        self.remote_desktop_version
+            .is_some_and(|v| v >= REMOTE_DESKTOP_PERSIST_VERSION)
+    }
+
+    /// Whether frames are only sent once the content changed, otherwise the stream keeps
+    /// sending frames at a constant rate.
+    pub fn sends_damage_only(&self) -> bool {
+        matches!(self.backend, PortalBackend::Gnome | PortalBackend::Kde)
+    }
+
+    /// The cursor mode to request, None if the portal does not support choosing one.
+    pub fn cursor_mode(&self, capture_cursor: bool) -> Option<u32> {
+        if self.cursor_modes == 0 {
+            return None;
+        }
+        let preferred = if capture_cursor {
+            if self.backend == PortalBackend::Kde {
+                // capturing the cursor can crash kwin_wayland and tear down the plasma desktop,
+                // see: https://bugs.kde.org/show_bug.cgi?id=435042
+                warn!(
+                    "You are attempting to capture the cursor under KDE Plasma, this may crash \
+                    your desktop, see https://bugs.kde.org/show_bug.cgi?id=435042 for details! \
+                    You have been warned."
+                );
+            }
+            CURSOR_MODE_EMBEDDED
+        } else {
+            CURSOR_MODE_HIDDEN
+        };
+        if self.cursor_modes & preferred != 0 {
+            return Some(preferred);
+        }
+        // the cursor is not drawn by Weylus, so metadata is as good as hidden
+        let fallback = [
+            CURSOR_MODE_HIDDEN,
+            CURSOR_MODE_METADATA,
+            CURSOR_MODE_EMBEDDED,
+        ]
+        .into_iter()
+        .find(|mode| self.cursor_modes & mode != 0);
+        warn!(
+            "The {} portal does not support the requested cursor mode {preferred}, using \
+            {fallback:?} instead.",
+            self.backend
+        );
+        fallback
+    }
+
+    pub fn log_summary(&self) {
+        info!(
+            backend = %self.backend,
+            screen_cast_version = ?self.screen_cast_version,
+            remote_desktop_version = ?self.remote_desktop_version,
+            source_types = self.source_types,
+            cursor_modes = self.cursor_modes,
+            remote_desktop = self.use_remote_desktop(),
+            restore_token = self.supports_restore_token(),
+            damage_only = self.sends_damage_only(),
+            "Using xdg-desktop-portal."
+        );
+        if self.screen_cast_version.is_none() {
+            warn!(
+                "The {} portal does not implement screen casts, make sure a portal backend with \
+                ScreenCast support is installed, e.g. xdg-desktop-portal-wlr or \
+                xdg-desktop-portal-gnome.",
+                self.backend
+            );
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! A rectangle cut out of another capturable, e.g. the part of a large monitor an application is
+//! shown in. The region is given in pixels of the frames of the capturable it is cut out of and
+//! clipped to them. Positions in the video of the region are mapped back to the capturable, so
+//! input lands within the region.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::capturable::transform::{region_in_frame, Rect};
+use crate::capturable::{Capturable, Cursor, CursorTracker, DirtyRect, Geometry, Recorder};
+use crate::video::PixelProvider;
+
+/// Rectangle in pixels, x and y are the offset from the top left corner.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CaptureRegion {
+    /// The part of the region within a frame of the given size, None if nothing is left.
+    fn clip(&self, width: usize, height: usize) -> Option<CaptureRegion> {
+        let right = (self.x + self.width).min(width);
+        let bottom = (self.y + self.height).min(height);
+        (right > self.x && bottom > self.y).then(|| CaptureRegion {
+            x: self.x,
+            y: self.y,
+            width: right - self.x,
+            height: bottom - self.y,
+        })
+    }
+
+    fn rect(&self) -> Rect {
+        Rect::new(
+            self.x as f64,
+            self.y as f64,
+            self.width as f64,
+            self.height as f64,
+        )
+    }
+}
+
+impl FromStr for CaptureRegion {
+    type Err = String;
+
+    /// Parse `X,Y,W,H`, e.g. `0,0,1280,720`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid region {s}, expected X,Y,WIDTH,HEIGHT in pixels.");
+        let parts = s
+            .split(',')
+            .map(|p| p.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let [x, y, width, height] = parts[..] else {
+            return Err(invalid());
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("The region {s} is empty."));
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for CaptureRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{} at {},{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+#[derive(Clone)]
+pub struct RegionCapturable {
+    inner: Box<dyn Capturable>,
+    region: CaptureRegion,
+    /// Size of the frames of the inner capturable, shared with the recorders.
+    frame_size: Arc<Mutex<Option<(usize, usize)>>>,
+}
+
+impl RegionCapturable {
+    pub fn new(inner: Box<dyn Capturable>, region: CaptureRegion) -> Self {
+        Self {
+            inner,
+            region,
+            frame_size: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Size of the frames of the inner capturable. Input may arrive before any frame has been
+    /// captured, e.g. in input only mode, a frame is taken to find out then.
+    fn frame_size(&self) -> Result<(usize, usize), Box<dyn Error>> {
+        if let Some(size) = *self.frame_size.lock().unwrap() {
+            return Ok(size);
+        }
+        let size = self.inner.recorder(false)?.capture()?.size();
+        *self.frame_size.lock().unwrap() = Some(size);
+        Ok(size)
+    }
+}
+
+impl Capturable for RegionCapturable {
+    fn name(&self) -> String {
+        format!("{} of {}", self.region, self.inner.name())
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        // positions are mapped to the inner capturable, see map_position
+        self.inner.geometry()
+    }
+
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.refresh_geometry()
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.before_input(activate)
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        Ok(Box::new(RegionRecorder {
+            inner: self.inner.recorder(capture_cursor)?,
+            region: self.region,
+            frame_size: self.frame_size.clone(),
+            clipped: None,
+            buf: Vec::new(),
+        }))
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        let inner = self.inner.cursor_tracker()?;
+        Some(Box::new(RegionCursorTracker {
+            inner,
+            region: self.region,
+            frame_size: self.frame_size.clone(),
+        }))
+    }
+
+    fn application(&self) -> Option<String> {
+        self.inner.application()
+    }
+
+    fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        let (width, height) = match self.frame_size() {
+            Ok(size) => size,
+            Err(err) => {
+                debug!("Failed to get size of {}: {err}", self.inner.name());
+                return self.inner.map_position(x, y);
+            }
+        };
+        let Some(r) = self.region.clip(width, height) else {
+            return self.inner.map_position(x, y);
+        };
+        let (x, y) = region_in_frame(&r.rect(), width, height).to_outer(x, y);
+        self.inner.map_position(x, y)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    fn remote_desktop_session(&self) -> Option<super::pipewire::RemoteDesktopSession> {
+        self.inner.remote_desktop_session()
+    }
+}
+
+pub struct RegionRecorder {
+    inner: Box<dyn Recorder>,
+    region: CaptureRegion,
+    frame_size: Arc<Mutex<Option<(usize, usize)>>>,
+    /// The region clipped to the last frame.
+    clipped: Option<CaptureRegion>,
+    /// Rows of the region for frames without stride.
+    buf: Vec<u8>,
+}
+
+/// The data of the frame starting at the given offset.
+fn skip(data: &[u8], offset: usize) -> Result<&[u8], Box<dyn Error>> {
+    data.get(offset..)
+        .ok_or_else(|| "Frame is smaller than its size.".into())
+}
+
+impl Recorder for RegionRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        let frame = self.inner.capture()?;
+        let (width, height) = frame.size();
+        *self.frame_size.lock().unwrap() = Some((width, height));
+        let r = self
+            .region
+            .clip(width, height)
+            .ok_or_else(|| format!("The region {} lies outside of the frame.", self.region))?;
+        self.clipped = Some(r);
+        // frames with stride are cropped without copying them
+        Ok(match frame {
+            PixelProvider::RGB(w, _, data) => {
+                self.buf.clear();
+                for row in r.y..r.y + r.height {
+                    let start = (row * w + r.x) * 3;
+                    let src = data
+                        .get(start..start + r.width * 3)
+                        .ok_or("Frame is smaller than its size.")?;
+                    self.buf.extend_from_slice(src);
+                }
+                PixelProvider::RGB(r.width, r.height, &self.buf)
+            }
+            PixelProvider::RGB0(w, _, data) => {
+                PixelProvider::RGB0S(r.width, r.height, w * 4, skip(data, (r.y * w + r.x) * 4)?)
+            }
+            PixelProvider::BGR0(w, _, data) => {
+                PixelProvider::BGR0S(r.width, r.height, w * 4, skip(data, (r.y * w + r.x) * 4)?)
+            }
+            PixelProvider::RGB0S(_, _, stride, data) => PixelProvider::RGB0S(
+                r.width,
+                r.height,
+                stride,
+                skip(data, r.y * stride + r.x * 4)?,
+            ),
+            PixelProvider::BGR0S(_, _, stride, data) => PixelProvider::BGR0S(
+                r.width,
+                r.height,
+                stride,
+                skip(data, r.y * stride + r.x * 4)?,
+            ),
+            PixelProvider::DmaBuf(mut frame) => {
+                frame.crop_x += r.x as i32;
+                frame.crop_y += r.y as i32;
+                frame.crop_width = r.width as i32;
+                frame.crop_height = r.height as i32;
+                PixelProvider::DmaBuf(frame)
+            }
+        })
+    }
+
+    fn frame_changed(&mut self) -> bool {
+        self.inner.frame_changed()
+    }
+
+    fn reconnecting(&self) -> bool {
+        self.inner.reconnecting()
+    }
+
+    fn dirty_rects(&mut self) -> Option<Vec<DirtyRect>> {
+        let r = self.clipped?;
+        let dirty = self.inner.dirty_rects()?;
+        Some(
+            dirty
+                .into_iter()
+                .filter_map(|d| {
+                    let x = d.x.max(r.x);
+                    let y = d.y.max(r.y);
+                    let right = (d.x + d.width).min(r.x + r.width);
+                    let bottom = (d.y + d.height).min(r.y + r.height);
+                    (right > x && bottom > y).then(|| DirtyRect {
+                        x: x - r.x,
+                        y: y - r.y,
+                        width: right - x,
+                        height: bottom - y,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Reports the cursor relative to the region, it may be outside of it.
+struct RegionCursorTracker {
+    inner: Box<dyn CursorTracker>,
+    region: CaptureRegion,
+    frame_size: Arc<Mutex<Option<(usize, usize)>>>,
+}
+
+impl CursorTracker for RegionCursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        let mut cursor = self.inner.poll()?;
+        // the size is known once the recorder captured a frame
+        let Some((width, height)) = *self.frame_size.lock().unwrap() else {
+            return Ok(cursor);
+        };
+        let Some(r) = self.region.clip(width, height) else {
+            return Ok(cursor);
+        };
+        let region = region_in_frame(&r.rect(), width, height);
+        (cursor.x, cursor.y) = region.to_inner(cursor.x, cursor.y);
+        if let Some(shape) = cursor.shape.as_mut() {
+            shape.width /= region.width;
+            shape.height /= region.height;
+            shape.xhot /= region.width;
+            shape.yhot /= region.height;
+        }
+        Ok(cursor)
+    }
+}
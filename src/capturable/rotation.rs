@@ -0,0 +1,283 @@
+//! Another capturable turned by a multiple of 90 degrees, e.g. to show a landscape desktop on a
+//! tablet held in portrait orientation without letterboxing. Frames are rotated before they are
+//! encoded, positions in the video are turned back so input lands where it is shown.
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capturable::transform::{rotate, rotate_vector};
+use crate::capturable::{
+    Capturable, Cursor, CursorShape, CursorTracker, DirtyRect, Geometry, Recorder,
+};
+use crate::protocol::{PointerEvent, WheelEvent};
+use crate::video::PixelProvider;
+
+/// Clockwise rotation of the video.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    #[serde(rename = "0")]
+    #[value(name = "0")]
+    Deg0,
+    #[serde(rename = "90")]
+    #[value(name = "90")]
+    Deg90,
+    #[serde(rename = "180")]
+    #[value(name = "180")]
+    Deg180,
+    #[serde(rename = "270")]
+    #[value(name = "270")]
+    Deg270,
+}
+
+impl Rotation {
+    pub fn quarter_turns(self) -> u8 {
+        match self {
+            Self::Deg0 => 0,
+            Self::Deg90 => 1,
+            Self::Deg180 => 2,
+            Self::Deg270 => 3,
+        }
+    }
+
+    /// Quarter turns back from the video to the capturable.
+    pub fn inverse_turns(self) -> u8 {
+        (4 - self.quarter_turns()) % 4
+    }
+
+    /// Turn the directions of a pointer event in the video back to the capturable, its position
+    /// is mapped by [`RotatedCapturable`].
+    pub fn unrotate_event(self, event: &mut PointerEvent) {
+        let turns = self.inverse_turns();
+        (event.tilt_x, event.tilt_y) = rotate_vector(turns, event.tilt_x, event.tilt_y);
+        (event.movement_x, event.movement_y) =
+            rotate_vector(turns, event.movement_x, event.movement_y);
+        if turns % 2 == 1 {
+            (event.width, event.height) = (event.height, event.width);
+        }
+        // the twist is clockwise in degrees
+        event.twist = (event.twist + 90 * turns as i32).rem_euclid(360);
+    }
+
+    /// Turn a scroll in the video back to the capturable.
+    pub fn unrotate_wheel(self, event: &mut WheelEvent) {
+        (event.dx, event.dy) = rotate_vector(self.inverse_turns(), event.dx, event.dy);
+    }
+}
+
+#[derive(Clone)]
+pub struct RotatedCapturable {
+    inner: Box<dyn Capturable>,
+    rotation: Rotation,
+}
+
+impl RotatedCapturable {
+    pub fn new(inner: Box<dyn Capturable>, rotation: Rotation) -> Self {
+        Self { inner, rotation }
+    }
+}
+
+impl Capturable for RotatedCapturable {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        // positions are mapped to the inner capturable, see map_position
+        self.inner.geometry()
+    }
+
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.refresh_geometry()
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.before_input(activate)
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        Ok(Box::new(RotatedRecorder {
+            inner: self.inner.recorder(capture_cursor)?,
+            turns: self.rotation.quarter_turns(),
+            frame_size: None,
+            buf: Vec::new(),
+        }))
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        let inner = self.inner.cursor_tracker()?;
+        Some(Box::new(RotatedCursorTracker {
+            inner,
+            turns: self.rotation.quarter_turns(),
+        }))
+    }
+
+    fn application(&self) -> Option<String> {
+        self.inner.application()
+    }
+
+    fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        let (x, y) = rotate(self.rotation.inverse_turns(), x, y);
+        self.inner.map_position(x, y)
+    }
+
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
+    fn remote_desktop_session(&self) -> Option<super::pipewire::RemoteDesktopSession> {
+        self.inner.remote_desktop_session()
+    }
+}
+
+pub struct RotatedRecorder {
+    inner: Box<dyn Recorder>,
+    turns: u8,
+    /// Size of the last frame of the inner recorder.
+    frame_size: Option<(usize, usize)>,
+    /// Pixels of the rotated frame.
+    buf: Vec<u8>,
+}
+
+/// Copy the pixels of a frame of the given size into `dst` rotated clockwise by the given number
+/// of quarter turns, rows of the result are not padded. Returns the size of the result.
+fn rotate_pixels(
+    src: &[u8],
+    (width, height): (usize, usize),
+    stride: usize,
+    bytes_per_pixel: usize,
+    turns: u8,
+    dst: &mut Vec<u8>,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    // the last row of cropped frames is not padded
+    if height > 0 && src.len() < stride * (height - 1) + width * bytes_per_pixel {
+        return Err("Frame is smaller than its size.".into());
+    }
+    let (w, h) = if turns % 2 == 1 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    dst.clear();
+    dst.resize(w * h * bytes_per_pixel, 0);
+    for y in 0..height {
+        let row = &src[y * stride..y * stride + width * bytes_per_pixel];
+        for (x, pixel) in row.chunks_exact(bytes_per_pixel).enumerate() {
+            let (dx, dy) = match turns % 4 {
+                1 => (height - 1 - y, x),
+                2 => (width - 1 - x, height - 1 - y),
+                3 => (y, width - 1 - x),
+                _ => (x, y),
+            };
+            let i = (dy * w + dx) * bytes_per_pixel;
+            dst[i..i + bytes_per_pixel].copy_from_slice(pixel);
+        }
+    }
+    Ok((w, h))
+}
+
+impl Recorder for RotatedRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        let frame = self.inner.capture()?;
+        let size = frame.size();
+        self.frame_size = Some(size);
+        let (bgr, bytes_per_pixel, stride, data) = match frame {
+            PixelProvider::RGB(w, _, data) => (false, 3, w * 3, data),
+            PixelProvider::RGB0(w, _, data) => (false, 4, w * 4, data),
+            PixelProvider::BGR0(w, _, data) => (true, 4, w * 4, data),
+            PixelProvider::RGB0S(_, _, stride, data) => (false, 4, stride, data),
+            PixelProvider::BGR0S(_, _, stride, data) => (true, 4, stride, data),
+            PixelProvider::DmaBuf(_) => {
+                return Err(
+                    "Frames in GPU memory can not be rotated, run without --pipewire-dmabuf."
+                        .into(),
+                )
+            }
+        };
+        let (w, h) = rotate_pixels(
+            data,
+            size,
+            stride,
+            bytes_per_pixel,
+            self.turns,
+            &mut self.buf,
+        )?;
+        Ok(match (bytes_per_pixel, bgr) {
+            (3, _) => PixelProvider::RGB(w, h, &self.buf),
+            (_, true) => PixelProvider::BGR0(w, h, &self.buf),
+            (_, false) => PixelProvider::RGB0(w, h, &self.buf),
+        })
+    }
+
+    fn frame_changed(&mut self) -> bool {
+        self.inner.frame_changed()
+    }
+
+    fn reconnecting(&self) -> bool {
+        self.inner.reconnecting()
+    }
+
+    fn dirty_rects(&mut self) -> Option<Vec<DirtyRect>> {
+        let (width, height) = self.frame_size?;
+        let dirty = self.inner.dirty_rects()?;
+        Some(
+            dirty
+                .into_iter()
+                .map(|d| match self.turns % 4 {
+                    1 => DirtyRect {
+                        x: height.saturating_sub(d.y + d.height),
+                        y: d.x,
+                        width: d.height,
+                        height: d.width,
+                    },
+                    2 => DirtyRect {
+                        x: width.saturating_sub(d.x + d.width),
+                        y: height.saturating_sub(d.y + d.height),
+                        ..d
+                    },
+                    3 => DirtyRect {
+                        x: d.y,
+                        y: width.saturating_sub(d.x + d.width),
+                        width: d.height,
+                        height: d.width,
+                    },
+                    _ => d,
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Reports the cursor in the rotated video, its image is turned along.
+struct RotatedCursorTracker {
+    inner: Box<dyn CursorTracker>,
+    turns: u8,
+}
+
+impl CursorTracker for RotatedCursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        let mut cursor = self.inner.poll()?;
+        (cursor.x, cursor.y) = rotate(self.turns, cursor.x, cursor.y);
+        cursor.shape = cursor.shape.map(|s| match self.turns % 4 {
+            1 => CursorShape {
+                width: s.height,
+                height: s.width,
+                xhot: s.height - s.yhot,
+                yhot: s.xhot,
+                image: image::imageops::rotate90(&s.image),
+            },
+            2 => CursorShape {
+                xhot: s.width - s.xhot,
+                yhot: s.height - s.yhot,
+                image: image::imageops::rotate180(&s.image),
+                ..s
+            },
+            3 => CursorShape {
+                width: s.height,
+                height: s.width,
+                xhot: s.yhot,
+                yhot: s.width - s.xhot,
+                image: image::imageops::rotate270(&s.image),
+            },
+            _ => s,
+        });
+        Ok(cursor)
+    }
+}
@@ -0,0 +1,237 @@
+//! Capture of displays and windows via ScreenCaptureKit, available since macOS 12.3. Frames are
+//! delivered by the window server as they change and in the resolution of the display, i.e. twice
+//! the size in points on retina displays, which takes considerably less CPU than taking
+//! screenshots via CoreGraphics. Geometry and input are handled by the CoreGraphics capturables,
+//! just the recorder differs.
+//!
+//! The size of the frames is fixed when the stream starts, so the recorder of a window watches its
+//! bounds and resizes the stream if the window is resized or moved to a display with another scale.
+
+use std::error::Error;
+use std::ffi::c_void;
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::capturable::core_graphics::{CGDisplayCapturable, CGWindowCapturable};
+use crate::capturable::{Capturable, CursorTracker, Geometry, Recorder};
+use crate::cerror::CError;
+use crate::video::PixelProvider;
+
+extern "C" {
+    fn sck_available() -> c_int;
+    fn sck_start(
+        display_id: u32,
+        window_id: u32,
+        capture_cursor: c_int,
+        exclude_self: c_int,
+        err: *mut CError,
+    ) -> *mut c_void;
+    fn sck_frame(
+        handle: *mut c_void,
+        data: *mut *const u8,
+        width: *mut usize,
+        height: *mut usize,
+        stride: *mut usize,
+        err: *mut CError,
+    ) -> c_int;
+    fn sck_resize(handle: *mut c_void, x: f64, y: f64, width: f64, height: f64);
+    fn sck_stop(handle: *mut c_void);
+}
+
+/// Leave the windows of Weylus out of captured displays.
+static EXCLUDE_SELF: AtomicBool = AtomicBool::new(true);
+
+/// How often the recorder of a window checks whether the window changed its size.
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether ScreenCaptureKit is available, it is not before macOS 12.3.
+pub fn is_available() -> bool {
+    unsafe { sck_available() != 0 }
+}
+
+pub fn set_exclude_self(exclude: bool) {
+    EXCLUDE_SELF.store(exclude, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Display(u32),
+    Window(u32),
+}
+
+#[derive(Clone)]
+pub struct SCKCapturable {
+    inner: Box<dyn Capturable>,
+    target: Target,
+    /// Set for windows, followed by the recorder.
+    window: Option<CGWindowCapturable>,
+}
+
+impl SCKCapturable {
+    pub fn display(display: CGDisplayCapturable) -> Self {
+        Self {
+            target: Target::Display(display.display_id()),
+            inner: Box::new(display),
+            window: None,
+        }
+    }
+
+    pub fn window(window: CGWindowCapturable) -> Self {
+        Self {
+            target: Target::Window(window.window_id()),
+            inner: Box::new(window.clone()),
+            window: Some(window),
+        }
+    }
+}
+
+impl Capturable for SCKCapturable {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        self.inner.geometry()
+    }
+
+    fn refresh_geometry(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.refresh_geometry()
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.before_input(activate)
+    }
+
+    fn application(&self) -> Option<String> {
+        self.inner.application()
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        self.inner.cursor_tracker()
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        crate::permissions::require_screen_recording()?;
+        let (display_id, window_id) = match self.target {
+            Target::Display(id) => (id, 0),
+            Target::Window(id) => (0, id),
+        };
+        let mut err = CError::new();
+        let exclude_self = EXCLUDE_SELF.load(Ordering::Relaxed);
+        let handle = unsafe {
+            sck_start(
+                display_id,
+                window_id,
+                capture_cursor.into(),
+                exclude_self.into(),
+                &mut err,
+            )
+        };
+        if err.is_err() || handle.is_null() {
+            return Err(Box::new(err));
+        }
+        Ok(Box::new(SCKRecorder {
+            handle,
+            frame: None,
+            pending: false,
+            window: self.window.clone(),
+            last_window_check: Instant::now(),
+        }))
+    }
+}
+
+pub struct SCKRecorder {
+    handle: *mut c_void,
+    /// Pixels, width, height and stride of the frame taken last, owned by the handle.
+    frame: Option<(*const u8, usize, usize, usize)>,
+    /// A new frame has been taken in frame_changed and not been captured yet.
+    pending: bool,
+    window: Option<CGWindowCapturable>,
+    last_window_check: Instant,
+}
+
+impl SCKRecorder {
+    /// Resize the stream if the window captured changed its size or scale.
+    fn follow_window(&mut self) {
+        let Some(window) = self.window.as_mut() else {
+            return;
+        };
+        if self.last_window_check.elapsed() < WINDOW_CHECK_INTERVAL {
+            return;
+        }
+        self.last_window_check = Instant::now();
+        if let Err(err) = window.refresh_geometry() {
+            debug!("Failed to get the bounds of the window captured: {err}");
+            return;
+        }
+        let bounds = window.bounds();
+        unsafe {
+            sck_resize(
+                self.handle,
+                bounds.origin.x,
+                bounds.origin.y,
+                bounds.size.width,
+                bounds.size.height,
+            )
+        };
+    }
+
+    /// Take the latest frame, returns whether there is a new one.
+    fn take_frame(&mut self) -> Result<bool, Box<dyn Error>> {
+        let (mut data, mut width, mut height, mut stride) = (std::ptr::null(), 0, 0, 0);
+        let mut err = CError::new();
+        let res = unsafe {
+            sck_frame(
+                self.handle,
+                &mut data,
+                &mut width,
+                &mut height,
+                &mut stride,
+                &mut err,
+            )
+        };
+        if res < 0 {
+            return Err(Box::new(err));
+        }
+        if res > 0 {
+            self.frame = Some((data, width, height, stride));
+        }
+        Ok(res > 0)
+    }
+}
+
+impl Recorder for SCKRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        self.follow_window();
+        if !std::mem::take(&mut self.pending) {
+            self.take_frame()?;
+        }
+        // frames only arrive if the content changed, the last one is reused otherwise
+        let Some((data, width, height, stride)) = self.frame else {
+            return Err("No frame captured yet.".into());
+        };
+        let pixels = unsafe { std::slice::from_raw_parts(data, stride * height) };
+        Ok(PixelProvider::BGR0S(width, height, stride, pixels))
+    }
+
+    fn frame_changed(&mut self) -> bool {
+        self.follow_window();
+        if !self.pending {
+            match self.take_frame() {
+                Ok(new) => self.pending = new,
+                // reported by capture, which takes the frame again
+                Err(_) => return true,
+            }
+        }
+        self.pending || self.frame.is_none()
+    }
+}
+
+impl Drop for SCKRecorder {
+    fn drop(&mut self) {
+        unsafe { sck_stop(self.handle) };
+    }
+}
@@ -71,7 +71,7 @@ impl Capturable for TestCapturable {
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         Ok(Geometry::Relative(0.0, 0.0, 1.0, 1.0))
     }
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    fn before_input(&mut self, _activate: bool) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
     fn recorder(&self, _: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
@@ -0,0 +1,434 @@
+//! Mapping of positions between the video shown by a client, the capturable and the desktop.
+//! Positions in the video and in capturables are relative, from 0 to 1, geometries of capturables
+//! are relative to the desktop, except for the virtual screen on Windows, which is in pixels.
+//! Nothing here talks to a capture backend, so all of it can be tested with made up layouts.
+
+use crate::capturable::Geometry;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// Distance of the point to the rect, 0 if the point is inside.
+    pub fn distance(&self, x: f64, y: f64) -> f64 {
+        let dx = (self.x - x).max(x - self.x - self.width).max(0.0);
+        let dy = (self.y - y).max(y - self.y - self.height).max(0.0);
+        dx.hypot(dy)
+    }
+
+    /// Map a position relative to the rect to the coordinates the rect is given in.
+    pub fn to_outer(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.x + x * self.width, self.y + y * self.height)
+    }
+
+    /// Map a position in the coordinates the rect is given in to a position relative to the rect,
+    /// the inverse of [`Rect::to_outer`].
+    pub fn to_inner(&self, x: f64, y: f64) -> (f64, f64) {
+        ((x - self.x) / self.width, (y - self.y) / self.height)
+    }
+
+    /// The rect relative to `outer`, e.g. a monitor given in points relative to the desktop.
+    pub fn relative_to(&self, outer: &Rect) -> Rect {
+        Rect {
+            x: (self.x - outer.x) / outer.width,
+            y: (self.y - outer.y) / outer.height,
+            width: self.width / outer.width,
+            height: self.height / outer.height,
+        }
+    }
+}
+
+/// The smallest rect containing all the given ones, None if there are none.
+pub fn bounding_rect(rects: impl IntoIterator<Item = Rect>) -> Option<Rect> {
+    rects.into_iter().reduce(|a, b| a.union(&b))
+}
+
+pub fn geometry_rect(geometry: &Geometry) -> Rect {
+    match *geometry {
+        Geometry::Relative(x, y, width, height) => Rect::new(x, y, width, height),
+        #[cfg(target_os = "windows")]
+        Geometry::VirtualScreen(offset_x, offset_y, width, height, _, _) => Rect::new(
+            offset_x.into(),
+            offset_y.into(),
+            width.into(),
+            height.into(),
+        ),
+    }
+}
+
+/// Where monitors stitched into one frame are, on the desktop and in the frame. Monitors keep
+/// their resolution, the frame is scaled so the monitor with the most pixels per point is not
+/// scaled at all. Monitors with fewer pixels per point do not fill all of their place then, so
+/// positions in the frame are mapped back to the desktop through the monitor below them instead of
+/// just being scaled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub desktop: Rect,
+    /// Geometry of each monitor and its place in the frame in pixels.
+    pub tiles: Vec<(Rect, Rect)>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Layout {
+    /// Layout for monitors with the given geometries and sizes of their frames.
+    pub fn new(geometries: &[Geometry], sizes: &[(usize, usize)]) -> Self {
+        let rects: Vec<Rect> = geometries.iter().map(geometry_rect).collect();
+        let desktop = bounding_rect(rects.iter().copied()).unwrap_or(Rect::new(0.0, 0.0, 1.0, 1.0));
+        // pixels per unit of the geometry of the sharpest monitor, per axis as relative geometries
+        // are not square unless the desktop is
+        let scale = |size: fn(&Rect, &(usize, usize)) -> f64| {
+            let scale = rects
+                .iter()
+                .zip(sizes)
+                .map(|(r, s)| size(r, s))
+                .filter(|s| s.is_finite())
+                .fold(0.0, f64::max);
+            if scale > 0.0 {
+                scale
+            } else {
+                1.0
+            }
+        };
+        let scale_x = scale(|r, (w, _)| *w as f64 / r.width);
+        let scale_y = scale(|r, (_, h)| *h as f64 / r.height);
+        let (mut width, mut height) = (0, 0);
+        let tiles = rects
+            .iter()
+            .zip(sizes)
+            .map(|(r, (w, h))| {
+                let x = ((r.x - desktop.x) * scale_x).round().max(0.0);
+                let y = ((r.y - desktop.y) * scale_y).round().max(0.0);
+                width = width.max(x as usize + w);
+                height = height.max(y as usize + h);
+                (*r, Rect::new(x, y, *w as f64, *h as f64))
+            })
+            .collect();
+        Self {
+            desktop,
+            tiles,
+            width,
+            height,
+        }
+    }
+
+    /// Position of the frame of the i-th monitor, None if it does not have the given size.
+    pub fn tile(&self, i: usize, width: usize, height: usize) -> Option<(usize, usize)> {
+        let (_, frame) = self.tiles.get(i)?;
+        if (frame.width, frame.height) != (width as f64, height as f64) {
+            return None;
+        }
+        Some((frame.x as usize, frame.y as usize))
+    }
+
+    /// Map a position relative to the frame to a position relative to the desktop, through the
+    /// monitor nearest to it.
+    pub fn map_position(&self, x: f64, y: f64) -> (f64, f64) {
+        let (px, py) = (x * self.width as f64, y * self.height as f64);
+        let Some((geometry, frame)) = self
+            .tiles
+            .iter()
+            .min_by(|(_, a), (_, b)| a.distance(px, py).total_cmp(&b.distance(px, py)))
+        else {
+            return (x, y);
+        };
+        let (local_x, local_y) = frame.to_inner(px, py);
+        let (x, y) = geometry.to_outer(local_x.clamp(0.0, 1.0), local_y.clamp(0.0, 1.0));
+        self.desktop.to_inner(x, y)
+    }
+
+    /// Map a position relative to the desktop to a position relative to the frame through the
+    /// monitor nearest to it, the inverse of [`Layout::map_position`] on monitors. Also returns the
+    /// factors lengths relative to the desktop are scaled by in the frame at that position, e.g.
+    /// the size of the cursor, as monitors may have different pixels per point.
+    pub fn to_frame(&self, x: f64, y: f64) -> ((f64, f64), (f64, f64)) {
+        let (dx, dy) = self.desktop.to_outer(x, y);
+        let Some((geometry, frame)) = self
+            .tiles
+            .iter()
+            .min_by(|(a, _), (b, _)| a.distance(dx, dy).total_cmp(&b.distance(dx, dy)))
+        else {
+            return ((x, y), (1.0, 1.0));
+        };
+        let (local_x, local_y) = geometry.to_inner(dx, dy);
+        let (px, py) = frame.to_outer(local_x, local_y);
+        let (width, height) = (self.width as f64, self.height as f64);
+        let scale_x = self.desktop.width / geometry.width * frame.width / width;
+        let scale_y = self.desktop.height / geometry.height * frame.height / height;
+        ((px / width, py / height), (scale_x, scale_y))
+    }
+}
+
+/// Rect of a region given in pixels relative to a frame of the given size.
+pub fn region_in_frame(region: &Rect, width: usize, height: usize) -> Rect {
+    region.relative_to(&Rect::new(0.0, 0.0, width as f64, height as f64))
+}
+
+/// Map a position relative to a frame to the frame rotated clockwise by the given number of
+/// quarter turns. Turning by `4 - turns` maps it back.
+pub fn rotate(turns: u8, x: f64, y: f64) -> (f64, f64) {
+    match turns % 4 {
+        1 => (1.0 - y, x),
+        2 => (1.0 - x, 1.0 - y),
+        3 => (y, 1.0 - x),
+        _ => (x, y),
+    }
+}
+
+/// Rotate a direction, e.g. the tilt of a pen or a scroll, clockwise by the given number of
+/// quarter turns, like positions in [`rotate`].
+pub fn rotate_vector<T: std::ops::Neg<Output = T>>(turns: u8, dx: T, dy: T) -> (T, T) {
+    match turns % 4 {
+        1 => (-dy, dx),
+        2 => (-dx, -dy),
+        3 => (dy, -dx),
+        _ => (dx, dy),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close((x, y): (f64, f64), (expected_x, expected_y): (f64, f64)) {
+        assert!(
+            (x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9,
+            "got ({x}, {y}), expected ({expected_x}, {expected_y})"
+        );
+    }
+
+    fn relative(rects: &[Rect]) -> Vec<Geometry> {
+        let desktop = bounding_rect(rects.iter().copied()).unwrap();
+        rects
+            .iter()
+            .map(|r| {
+                let r = r.relative_to(&desktop);
+                Geometry::Relative(r.x, r.y, r.width, r.height)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window_on_desktop() {
+        let window = geometry_rect(&Geometry::Relative(0.25, 0.5, 0.5, 0.25));
+        assert_close(window.to_outer(0.0, 0.0), (0.25, 0.5));
+        assert_close(window.to_outer(0.5, 0.5), (0.5, 0.625));
+        assert_close(window.to_outer(1.0, 1.0), (0.75, 0.75));
+        assert_close(window.to_inner(0.5, 0.625), (0.5, 0.5));
+    }
+
+    #[test]
+    fn monitors_left_of_and_above_the_primary() {
+        // like CoreGraphics, the primary monitor is at the origin
+        let primary = Rect::new(0.0, 0.0, 1440.0, 900.0);
+        let left = Rect::new(-1920.0, -180.0, 1920.0, 1080.0);
+        let desktop = bounding_rect([primary, left]).unwrap();
+        assert_eq!(desktop, Rect::new(-1920.0, -180.0, 3360.0, 1080.0));
+        let r = primary.relative_to(&desktop);
+        assert_close((r.x, r.y), (1920.0 / 3360.0, 180.0 / 1080.0));
+        assert_close((r.width, r.height), (1440.0 / 3360.0, 900.0 / 1080.0));
+        assert_close(left.relative_to(&desktop).to_outer(0.0, 0.0), (0.0, 0.0));
+        assert!(bounding_rect([]).is_none());
+    }
+
+    #[test]
+    fn side_by_side_monitors() {
+        let geometries = relative(&[
+            Rect::new(0.0, 0.0, 1920.0, 1080.0),
+            Rect::new(1920.0, 0.0, 1920.0, 1080.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(1920, 1080), (1920, 1080)]);
+        assert_eq!((layout.width, layout.height), (3840, 1080));
+        assert_eq!(layout.tile(0, 1920, 1080), Some((0, 0)));
+        assert_eq!(layout.tile(1, 1920, 1080), Some((1920, 0)));
+        assert_eq!(layout.tile(1, 1280, 720), None);
+        // same pixels per point everywhere, mapping is the identity
+        for (x, y) in [(0.0, 0.0), (0.25, 0.5), (0.5, 0.5), (0.75, 0.1), (1.0, 1.0)] {
+            assert_close(layout.map_position(x, y), (x, y));
+        }
+    }
+
+    #[test]
+    fn scaled_monitor_next_to_unscaled() {
+        // a HiDPI monitor with 2 pixels per point left of a regular one of the same size in points
+        let geometries = relative(&[
+            Rect::new(0.0, 0.0, 1920.0, 1080.0),
+            Rect::new(1920.0, 0.0, 1920.0, 1080.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(3840, 2160), (1920, 1080)]);
+        assert_eq!((layout.width, layout.height), (5760, 2160));
+        assert_eq!(layout.tile(0, 3840, 2160), Some((0, 0)));
+        assert_eq!(layout.tile(1, 1920, 1080), Some((3840, 0)));
+        // center of each monitor
+        assert_close(layout.map_position(1920.0 / 5760.0, 0.5), (0.25, 0.5));
+        assert_close(layout.map_position(4800.0 / 5760.0, 0.25), (0.75, 0.5));
+        // the space below the unscaled monitor belongs to its bottom edge
+        assert_close(layout.map_position(4800.0 / 5760.0, 0.75), (0.75, 1.0));
+        // outside of the frame positions are clamped to the nearest monitor
+        assert_close(layout.map_position(1.5, -1.0), (1.0, 0.0));
+    }
+
+    #[test]
+    fn cursor_on_scaled_monitor() {
+        let geometries = relative(&[
+            Rect::new(0.0, 0.0, 1920.0, 1080.0),
+            Rect::new(1920.0, 0.0, 1920.0, 1080.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(3840, 2160), (1920, 1080)]);
+        let (position, scale) = layout.to_frame(0.25, 0.5);
+        assert_close(position, (1920.0 / 5760.0, 0.5));
+        // a cursor a tenth of the desktop wide covers 384 of the 5760 pixels of the frame
+        assert_close(scale, (3840.0 / 5760.0 * 2.0, 1.0));
+        let (position, scale) = layout.to_frame(0.75, 0.5);
+        assert_close(position, (4800.0 / 5760.0, 0.25));
+        assert_close(scale, (1920.0 / 5760.0 * 2.0, 0.5));
+        for (x, y) in [(0.1, 0.2), (0.25, 0.5), (0.6, 0.9), (0.99, 0.01)] {
+            let (position, _) = layout.to_frame(x, y);
+            assert_close(layout.map_position(position.0, position.1), (x, y));
+        }
+    }
+
+    #[test]
+    fn rotated_monitor() {
+        // a monitor rotated to portrait right of a landscape one, aligned at the top
+        let geometries = relative(&[
+            Rect::new(0.0, 0.0, 1920.0, 1080.0),
+            Rect::new(1920.0, 0.0, 1080.0, 1920.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(1920, 1080), (1080, 1920)]);
+        assert_eq!((layout.width, layout.height), (3000, 1920));
+        assert_eq!(layout.tile(1, 1080, 1920), Some((1920, 0)));
+        assert_eq!(layout.tile(1, 1920, 1080), None);
+        assert_close(layout.map_position(0.5, 0.25), (0.5, 0.25));
+        assert_close(
+            layout.map_position(2460.0 / 3000.0, 0.5),
+            (2460.0 / 3000.0, 0.5),
+        );
+        // below the landscape monitor there is no monitor, its bottom edge is nearest
+        assert_close(
+            layout.map_position(0.32, 1500.0 / 1920.0),
+            (0.32, 1080.0 / 1920.0),
+        );
+    }
+
+    #[test]
+    fn monitors_stacked_with_offset() {
+        // a smaller monitor above and right of the primary one, e.g. a laptop below an external
+        // monitor placed off center
+        let geometries = relative(&[
+            Rect::new(0.0, 1440.0, 1280.0, 800.0),
+            Rect::new(640.0, 0.0, 2560.0, 1440.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(2560, 1600), (2560, 1440)]);
+        assert_eq!(layout.tile(0, 2560, 1600), Some((0, 2880)));
+        assert_eq!(layout.tile(1, 2560, 1440), Some((1280, 0)));
+        assert_eq!((layout.width, layout.height), (3840, 4480));
+        // center of the laptop
+        assert_close(
+            layout.map_position(1280.0 / 3840.0, 3680.0 / 4480.0),
+            (640.0 / 3200.0, 1840.0 / 2240.0),
+        );
+        // center of the external monitor
+        assert_close(
+            layout.map_position(2560.0 / 3840.0, 720.0 / 4480.0),
+            (1920.0 / 3200.0, 720.0 / 2240.0),
+        );
+    }
+
+    #[test]
+    fn no_monitors() {
+        let layout = Layout::new(&[], &[]);
+        assert_eq!((layout.width, layout.height), (0, 0));
+        assert_eq!(layout.tile(0, 1920, 1080), None);
+        assert_close(layout.map_position(0.3, 0.7), (0.3, 0.7));
+        assert_eq!(layout.to_frame(0.3, 0.7), ((0.3, 0.7), (1.0, 1.0)));
+    }
+
+    #[test]
+    fn region_of_frame() {
+        let region = region_in_frame(&Rect::new(100.0, 200.0, 640.0, 360.0), 1920, 1080);
+        assert_close(region.to_outer(0.0, 0.0), (100.0 / 1920.0, 200.0 / 1080.0));
+        assert_close(region.to_outer(1.0, 1.0), (740.0 / 1920.0, 560.0 / 1080.0));
+        assert_close(region.to_inner(420.0 / 1920.0, 380.0 / 1080.0), (0.5, 0.5));
+        // the cursor may be outside of the region
+        assert_close(region.to_inner(0.0, 0.0), (-100.0 / 640.0, -200.0 / 360.0));
+    }
+
+    #[test]
+    fn region_of_stitched_monitors() {
+        // a region covering the right monitor of two stitched together maps to that monitor
+        let geometries = relative(&[
+            Rect::new(0.0, 0.0, 1920.0, 1080.0),
+            Rect::new(1920.0, 0.0, 1920.0, 1080.0),
+        ]);
+        let layout = Layout::new(&geometries, &[(3840, 2160), (1920, 1080)]);
+        let region = region_in_frame(
+            &Rect::new(3840.0, 0.0, 1920.0, 1080.0),
+            layout.width,
+            layout.height,
+        );
+        let (x, y) = region.to_outer(0.5, 0.5);
+        assert_close(layout.map_position(x, y), (0.75, 0.5));
+    }
+
+    #[test]
+    fn rotated_frame() {
+        // the top left corner of a frame turned clockwise ends up at the top right
+        assert_close(rotate(1, 0.0, 0.0), (1.0, 0.0));
+        assert_close(rotate(1, 1.0, 0.0), (1.0, 1.0));
+        assert_close(rotate(2, 0.25, 0.0), (0.75, 1.0));
+        assert_close(rotate(3, 0.0, 0.0), (0.0, 1.0));
+        for turns in 0..4 {
+            let (x, y) = rotate(turns, 0.2, 0.7);
+            assert_close(rotate(4 - turns, x, y), (0.2, 0.7));
+        }
+    }
+
+    #[test]
+    fn rotated_vector() {
+        // moving right in the frame is moving down once it is turned clockwise
+        assert_eq!(rotate_vector(1, 1, 0), (0, 1));
+        assert_eq!(rotate_vector(2, 3, -2), (-3, 2));
+        assert_eq!(rotate_vector(3, 0, 1), (1, 0));
+        for turns in 0..4 {
+            // a vector turns like the difference of two positions
+            let (x0, y0) = rotate(turns, 0.1, 0.2);
+            let (x1, y1) = rotate(turns, 0.4, 0.6);
+            assert_close(rotate_vector(turns, 0.3, 0.4), (x1 - x0, y1 - y0));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn virtual_screen() {
+        let geometry = Geometry::VirtualScreen(1920, 0, 2560, 1440, 0, 0);
+        let r = geometry_rect(&geometry);
+        assert_eq!(r, Rect::new(1920.0, 0.0, 2560.0, 1440.0));
+        assert_close(r.to_outer(0.5, 0.5), (3200.0, 720.0));
+    }
+}
@@ -0,0 +1,151 @@
+//! The cursor of the Windows desktop. Color cursors come with an alpha channel or a mask telling
+//! which pixels are transparent, monochrome cursors are made of a mask that is ANDed with the
+//! screen and one that is XORed with it.
+
+use std::error::Error;
+use std::mem;
+use std::ptr;
+
+use winapi::shared::windef::{HBITMAP, HCURSOR};
+use winapi::um::wingdi::{
+    DeleteObject, GetDIBits, GetObjectW, BITMAP, BITMAPINFO, BITMAPINFOHEADER, BI_RGB,
+    DIB_RGB_COLORS,
+};
+use winapi::um::winuser::{GetCursorInfo, GetDC, GetIconInfo, ReleaseDC, CURSORINFO, ICONINFO};
+
+use crate::capturable::win_window::virtual_screen_origin;
+use crate::capturable::{Cursor, CursorShape, CursorTracker};
+
+/// Reports the cursor in pixels relative to the virtual screen, like the geometries of
+/// capturables.
+#[derive(Default)]
+pub struct WinCursorTracker {
+    /// Handle of the cursor sent last, the shape is only read again if it changes.
+    cursor: usize,
+}
+
+/// Size of the bitmap and its pixels as BGRA, rows from top to bottom.
+fn bitmap_pixels(bitmap: HBITMAP) -> Result<(usize, usize, Vec<u8>), Box<dyn Error>> {
+    unsafe {
+        let mut bm: BITMAP = mem::zeroed();
+        if GetObjectW(
+            bitmap as _,
+            mem::size_of::<BITMAP>() as i32,
+            &mut bm as *mut BITMAP as _,
+        ) == 0
+        {
+            return Err("Failed to get size of cursor bitmap.".into());
+        }
+        let (width, height) = (bm.bmWidth as usize, bm.bmHeight as usize);
+        let mut info: BITMAPINFO = mem::zeroed();
+        info.bmiHeader.biSize = mem::size_of::<BITMAPINFOHEADER>() as u32;
+        info.bmiHeader.biWidth = bm.bmWidth;
+        // negative height for rows from top to bottom
+        info.bmiHeader.biHeight = -bm.bmHeight;
+        info.bmiHeader.biPlanes = 1;
+        info.bmiHeader.biBitCount = 32;
+        info.bmiHeader.biCompression = BI_RGB;
+        let mut pixels = vec![0u8; width * height * 4];
+        let dc = GetDC(ptr::null_mut());
+        let lines = GetDIBits(
+            dc,
+            bitmap,
+            0,
+            height as u32,
+            pixels.as_mut_ptr() as _,
+            &mut info,
+            DIB_RGB_COLORS,
+        );
+        ReleaseDC(ptr::null_mut(), dc);
+        if lines == 0 {
+            return Err("Failed to read cursor bitmap.".into());
+        }
+        Ok((width, height, pixels))
+    }
+}
+
+fn shape_from_icon(icon: &ICONINFO) -> Result<CursorShape, Box<dyn Error>> {
+    let (width, mask_height, mask) = bitmap_pixels(icon.hbmMask)?;
+    let (height, rgba): (usize, Vec<u8>) = if !icon.hbmColor.is_null() {
+        let (_, height, color) = bitmap_pixels(icon.hbmColor)?;
+        let has_alpha = color.chunks_exact(4).any(|p| p[3] != 0);
+        let rgba = color
+            .chunks_exact(4)
+            .zip(mask.chunks_exact(4))
+            .flat_map(|(c, m)| {
+                // without alpha channel the mask tells which pixels are transparent
+                let a = match (has_alpha, m[0]) {
+                    (true, _) => c[3],
+                    (false, 0) => 255,
+                    (false, _) => 0,
+                };
+                [c[2], c[1], c[0], a]
+            })
+            .collect();
+        (height, rgba)
+    } else {
+        let height = mask_height / 2;
+        let (and, xor) = mask.split_at(width * height * 4);
+        let rgba = and
+            .chunks_exact(4)
+            .zip(xor.chunks_exact(4))
+            .flat_map(|(a, x)| match (a[0] != 0, x[0] != 0) {
+                (true, false) => [0, 0, 0, 0],
+                (false, false) => [0, 0, 0, 255],
+                (false, true) => [255, 255, 255, 255],
+                // inverts the screen, black shows on the usual light background
+                (true, true) => [0, 0, 0, 255],
+            })
+            .collect();
+        (height, rgba)
+    };
+    let image = image::RgbaImage::from_raw(width as u32, height as u32, rgba)
+        .ok_or("Cursor bitmap is smaller than its size.")?;
+    Ok(CursorShape {
+        width: width as f64,
+        height: height as f64,
+        xhot: icon.xHotspot as f64,
+        yhot: icon.yHotspot as f64,
+        image,
+    })
+}
+
+fn cursor_shape(cursor: HCURSOR) -> Result<CursorShape, Box<dyn Error>> {
+    unsafe {
+        let mut icon: ICONINFO = mem::zeroed();
+        if GetIconInfo(cursor, &mut icon) == 0 {
+            return Err("Failed to get cursor bitmaps.".into());
+        }
+        let shape = shape_from_icon(&icon);
+        // the bitmaps are copies owned by the caller
+        if !icon.hbmColor.is_null() {
+            DeleteObject(icon.hbmColor as _);
+        }
+        if !icon.hbmMask.is_null() {
+            DeleteObject(icon.hbmMask as _);
+        }
+        shape
+    }
+}
+
+impl CursorTracker for WinCursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        let mut info: CURSORINFO = unsafe { mem::zeroed() };
+        info.cbSize = mem::size_of::<CURSORINFO>() as u32;
+        if unsafe { GetCursorInfo(&mut info) } == 0 {
+            return Err("Failed to get cursor.".into());
+        }
+        let shape = if !info.hCursor.is_null() && info.hCursor as usize != self.cursor {
+            self.cursor = info.hCursor as usize;
+            Some(cursor_shape(info.hCursor)?)
+        } else {
+            None
+        };
+        let (left, top) = virtual_screen_origin();
+        Ok(Cursor {
+            x: (info.ptScreenPos.x - left) as f64,
+            y: (info.ptScreenPos.y - top) as f64,
+            shape,
+        })
+    }
+}
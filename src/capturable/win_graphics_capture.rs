@@ -0,0 +1,407 @@
+//! Capture of screens and windows via Windows.Graphics.Capture, available since Windows 10 version
+//! 1903. Frames are composed by DWM on the GPU, so windows drawn via DirectComposition or the GPU
+//! are captured as well as windows covered by others. Frames only arrive if the content changed,
+//! which spares encoding the same frame again.
+//!
+//! Frames are in physical pixels, Weylus therefore runs per monitor DPI aware, see
+//! [`set_dpi_aware`], and places windows via the bounds DWM draws them at, which leave out the
+//! invisible resize borders Windows adds around windows.
+
+use std::error::Error;
+use std::mem;
+
+use tracing::{debug, warn};
+use windows::core::{factory, Interface};
+use windows::Graphics::Capture::{
+    Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession,
+};
+use windows::Graphics::DirectX::Direct3D11::IDirect3DDevice;
+use windows::Graphics::DirectX::DirectXPixelFormat;
+use windows::Graphics::SizeInt32;
+use windows::Win32::Foundation::{HMODULE, HWND};
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_HARDWARE;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_CPU_ACCESS_READ,
+    D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_SDK_VERSION,
+    D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+};
+use windows::Win32::Graphics::Dxgi::IDXGIDevice;
+use windows::Win32::Graphics::Gdi::HMONITOR;
+use windows::Win32::System::WinRT::Direct3D11::{
+    CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+};
+use windows::Win32::System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop;
+use windows::Win32::System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED};
+
+use winapi::shared::windef::RECT;
+use winapi::um::dwmapi::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS};
+use winapi::um::winuser::{GetForegroundWindow, IsIconic, IsWindow, SetForegroundWindow};
+
+use crate::capturable::captrs_capture::window_executable;
+use crate::capturable::cursor::DesktopCursorTracker;
+use crate::capturable::win_window::virtual_screen_origin;
+use crate::capturable::{Capturable, CursorTracker, Geometry, Recorder};
+use crate::video::PixelProvider;
+
+/// Frames buffered by the frame pool.
+const NUM_BUFFERS: i32 = 2;
+
+#[derive(Debug)]
+pub struct GraphicsCaptureError(String);
+
+impl std::fmt::Display for GraphicsCaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for GraphicsCaptureError {}
+
+/// Make coordinates of screens and windows physical pixels, like the frames captured. Must be
+/// called before any window is created, the gui can not change it anymore afterwards.
+pub fn set_dpi_aware() {
+    use winapi::shared::windef::DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2;
+    use winapi::um::winuser::SetProcessDpiAwarenessContext;
+
+    if unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) } == 0 {
+        debug!(
+            "Failed to make Weylus DPI aware: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Whether Windows.Graphics.Capture is available, it is not before Windows 10 version 1903.
+pub fn is_supported() -> bool {
+    GraphicsCaptureSession::IsSupported().unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    /// The HMONITOR as integer, raw pointers are not Send.
+    Monitor(usize),
+    /// The HWND as integer.
+    Window(usize),
+}
+
+#[derive(Clone)]
+pub struct GraphicsCaptureCapturable {
+    target: Target,
+    name: String,
+    /// Position of the screen, windows are placed via DWM.
+    screen: RECT,
+}
+
+impl GraphicsCaptureCapturable {
+    /// A screen, `screen` is its position.
+    pub fn monitor(monitor: winapi::shared::windef::HMONITOR, name: String, screen: RECT) -> Self {
+        Self {
+            target: Target::Monitor(monitor as usize),
+            name,
+            screen,
+        }
+    }
+
+    /// A top level window.
+    pub fn window(hwnd: winapi::shared::windef::HWND, title: String) -> Self {
+        Self {
+            target: Target::Window(hwnd as usize),
+            name: title,
+            screen: unsafe { mem::zeroed() },
+        }
+    }
+
+    fn rect(&self) -> Result<RECT, Box<dyn Error>> {
+        match self.target {
+            Target::Monitor(_) => Ok(self.screen),
+            Target::Window(hwnd) => frame_bounds(hwnd as winapi::shared::windef::HWND),
+        }
+    }
+
+    fn capture_item(&self) -> Result<GraphicsCaptureItem, Box<dyn Error>> {
+        let interop = factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+        let item = unsafe {
+            match self.target {
+                Target::Monitor(monitor) => interop.CreateForMonitor(HMONITOR(monitor as _))?,
+                Target::Window(hwnd) => interop.CreateForWindow(HWND(hwnd as _))?,
+            }
+        };
+        Ok(item)
+    }
+}
+
+/// The bounds DWM draws the window at, excluding its invisible resize borders.
+fn frame_bounds(hwnd: winapi::shared::windef::HWND) -> Result<RECT, Box<dyn Error>> {
+    unsafe {
+        if IsWindow(hwnd) == 0 {
+            return Err(GraphicsCaptureError("Window has been closed.".into()).into());
+        }
+        let mut rect: RECT = mem::zeroed();
+        let hr = DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut RECT as *mut _,
+            mem::size_of::<RECT>() as u32,
+        );
+        if hr < 0 {
+            return Err(
+                GraphicsCaptureError("Failed to get the position of the window.".into()).into(),
+            );
+        }
+        Ok(rect)
+    }
+}
+
+impl Capturable for GraphicsCaptureCapturable {
+    fn name(&self) -> String {
+        match self.target {
+            Target::Monitor(_) => format!("Desktop {}", self.name),
+            Target::Window(_) => self.name.clone(),
+        }
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        let Target::Window(hwnd) = self.target else {
+            return Ok(());
+        };
+        let hwnd = hwnd as winapi::shared::windef::HWND;
+        frame_bounds(hwnd)?;
+        if activate {
+            unsafe {
+                if GetForegroundWindow() != hwnd {
+                    SetForegroundWindow(hwnd);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn recorder(&self, capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        Ok(Box::new(GraphicsCaptureRecorder::new(
+            self,
+            capture_cursor,
+        )?))
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(DesktopCursorTracker::new(Box::new(self.clone()))))
+    }
+
+    /// Executable of the window or, for screens, of the window in the foreground.
+    fn application(&self) -> Option<String> {
+        match self.target {
+            Target::Monitor(_) => window_executable(unsafe { GetForegroundWindow() }),
+            Target::Window(hwnd) => window_executable(hwnd as winapi::shared::windef::HWND),
+        }
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        let rect = self.rect()?;
+        let (left, top) = virtual_screen_origin();
+        Ok(Geometry::VirtualScreen(
+            rect.left - left,
+            rect.top - top,
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+            rect.left,
+            rect.top,
+        ))
+    }
+}
+
+pub struct GraphicsCaptureRecorder {
+    /// The window captured, if any, minimized windows deliver no frames.
+    hwnd: Option<winapi::shared::windef::HWND>,
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    winrt_device: IDirect3DDevice,
+    pool: Direct3D11CaptureFramePool,
+    session: GraphicsCaptureSession,
+    size: SizeInt32,
+    /// Texture the frames are copied to for reading them back.
+    staging: Option<(ID3D11Texture2D, D3D11_TEXTURE2D_DESC)>,
+    /// Frame received in frame_changed, consumed by the next capture.
+    pending: Option<Direct3D11CaptureFrame>,
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl GraphicsCaptureRecorder {
+    fn new(
+        capturable: &GraphicsCaptureCapturable,
+        capture_cursor: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        // fails if the thread is initialized already, which is just as good
+        let _ = unsafe { RoInitialize(RO_INIT_MULTITHREADED) };
+
+        let mut device = None;
+        let mut context = None;
+        unsafe {
+            D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE_HARDWARE,
+                HMODULE::default(),
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                None,
+                D3D11_SDK_VERSION,
+                Some(&mut device),
+                None,
+                Some(&mut context),
+            )?
+        };
+        let (Some(device), Some(context)) = (device, context) else {
+            return Err(GraphicsCaptureError("Failed to create Direct3D device.".into()).into());
+        };
+        let dxgi_device: IDXGIDevice = device.cast()?;
+        let winrt_device: IDirect3DDevice =
+            unsafe { CreateDirect3D11DeviceFromDXGIDevice(&dxgi_device)? }.cast()?;
+
+        let item = capturable.capture_item()?;
+        let size = item.Size()?;
+        let pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+            &winrt_device,
+            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            NUM_BUFFERS,
+            size,
+        )?;
+        let session = pool.CreateCaptureSession(&item)?;
+        if let Err(err) = session.SetIsCursorCaptureEnabled(capture_cursor) {
+            debug!("Failed to set whether the cursor is captured: {err}");
+        }
+        // the yellow border around captures can only be turned off since Windows 11
+        let _ = session.SetIsBorderRequired(false);
+        session.StartCapture()?;
+
+        Ok(Self {
+            hwnd: match capturable.target {
+                Target::Monitor(_) => None,
+                Target::Window(hwnd) => Some(hwnd as winapi::shared::windef::HWND),
+            },
+            device,
+            context,
+            winrt_device,
+            pool,
+            session,
+            size,
+            staging: None,
+            pending: None,
+            pixels: Vec::new(),
+            width: 0,
+            height: 0,
+            stride: 0,
+        })
+    }
+
+    /// The next frame, None if the content did not change.
+    fn next_frame(&mut self) -> Option<Direct3D11CaptureFrame> {
+        self.pending
+            .take()
+            .or_else(|| self.pool.TryGetNextFrame().ok())
+    }
+
+    /// Copy the frame to the CPU.
+    fn read_frame(&mut self, frame: &Direct3D11CaptureFrame) -> Result<(), Box<dyn Error>> {
+        let content_size = frame.ContentSize()?;
+        let access: IDirect3DDxgiInterfaceAccess = frame.Surface()?.cast()?;
+        let texture: ID3D11Texture2D = unsafe { access.GetInterface()? };
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+
+        if self.staging.as_ref().map_or(true, |(_, d)| {
+            (d.Width, d.Height) != (desc.Width, desc.Height)
+        }) {
+            let mut staging_desc = desc;
+            staging_desc.Usage = D3D11_USAGE_STAGING;
+            staging_desc.BindFlags = 0;
+            staging_desc.CPUAccessFlags = D3D11_CPU_ACCESS_READ.0 as u32;
+            staging_desc.MiscFlags = 0;
+            let mut staging = None;
+            unsafe {
+                self.device
+                    .CreateTexture2D(&staging_desc, None, Some(&mut staging))?
+            };
+            let Some(staging) = staging else {
+                return Err(GraphicsCaptureError("Failed to create texture.".into()).into());
+            };
+            self.staging = Some((staging, desc));
+        }
+        let (staging, _) = self.staging.as_ref().unwrap();
+
+        unsafe {
+            self.context.CopyResource(staging, &texture);
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.context
+                .Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            let stride = mapped.RowPitch as usize;
+            let data = std::slice::from_raw_parts(
+                mapped.pData as *const u8,
+                stride * desc.Height as usize,
+            );
+            self.pixels.clear();
+            self.pixels.extend_from_slice(data);
+            self.context.Unmap(staging, 0);
+            self.stride = stride;
+        }
+        // the frame may be smaller than the texture while the window is resized
+        self.width = (content_size.Width.max(0) as u32).min(desc.Width) as usize;
+        self.height = (content_size.Height.max(0) as u32).min(desc.Height) as usize;
+
+        if content_size != self.size {
+            self.size = content_size;
+            if let Err(err) = self.pool.Recreate(
+                &self.winrt_device,
+                DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                NUM_BUFFERS,
+                content_size,
+            ) {
+                warn!("Failed to resize the frame pool: {err}");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Recorder for GraphicsCaptureRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        if let Some(hwnd) = self.hwnd {
+            frame_bounds(hwnd)?;
+            if unsafe { IsIconic(hwnd) } != 0 {
+                return Err(GraphicsCaptureError("Window is minimized.".into()).into());
+            }
+        }
+        match self.next_frame() {
+            Some(frame) => self.read_frame(&frame)?,
+            None if self.pixels.is_empty() => {
+                return Err(GraphicsCaptureError("No frame captured yet.".into()).into())
+            }
+            // frames only arrive if the content changed, reuse the last one
+            None => (),
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(GraphicsCaptureError("Nothing to capture.".into()).into());
+        }
+        Ok(PixelProvider::BGR0S(
+            self.width,
+            self.height,
+            self.stride,
+            &self.pixels,
+        ))
+    }
+
+    fn frame_changed(&mut self) -> bool {
+        if self.pending.is_none() {
+            self.pending = self.pool.TryGetNextFrame().ok();
+        }
+        self.pending.is_some() || self.pixels.is_empty()
+    }
+}
+
+impl Drop for GraphicsCaptureRecorder {
+    fn drop(&mut self) {
+        let _ = self.session.Close();
+        let _ = self.pool.Close();
+    }
+}
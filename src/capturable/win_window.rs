@@ -0,0 +1,302 @@
+//! Capture of single windows on Windows. Windows are rendered with PrintWindow and
+//! PW_RENDERFULLCONTENT, which makes DWM draw the full content of the window, so windows drawn via
+//! DirectComposition or the GPU, like Chrome, Electron apps and video players, do not end up as
+//! black frames as they do when copying from the device context of the window.
+//!
+//! For windows PrintWindow can not render, e.g. some with protected content, the area of the window
+//! is copied from the screen as composed by DWM instead. The thumbnails DWM shows of windows can not
+//! be read back, so this is as close to them as it gets, it requires the window to be visible
+//! though.
+
+use std::error::Error;
+use std::mem;
+use std::ptr;
+
+use winapi::shared::minwindef::{BOOL, LPARAM, TRUE, UINT};
+use winapi::shared::windef::{HBITMAP, HDC, HGDIOBJ, HWND, RECT};
+use winapi::um::wingdi::{
+    BitBlt, CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, GdiFlush, SelectObject,
+    BITMAPINFO, BITMAPINFOHEADER, BI_RGB, CAPTUREBLT, DIB_RGB_COLORS, SRCCOPY,
+};
+use winapi::um::winuser::{
+    EnumWindows, GetDC, GetForegroundWindow, GetSystemMetrics, GetWindow, GetWindowLongW,
+    GetWindowRect, GetWindowTextLengthW, GetWindowTextW, IsIconic, IsWindow, IsWindowVisible,
+    PrintWindow, ReleaseDC, SetForegroundWindow, GWL_EXSTYLE, GW_OWNER, SM_XVIRTUALSCREEN,
+    SM_YVIRTUALSCREEN, WS_EX_TOOLWINDOW,
+};
+
+use crate::capturable::captrs_capture::window_executable;
+use crate::capturable::cursor::DesktopCursorTracker;
+use crate::capturable::{Capturable, CursorTracker, Geometry, Recorder};
+use crate::video::PixelProvider;
+
+/// Not defined by winapi, supported since Windows 8.1.
+const PW_RENDERFULLCONTENT: UINT = 0x2;
+
+#[derive(Debug)]
+pub struct WinWindowError(String);
+
+impl std::fmt::Display for WinWindowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for WinWindowError {}
+
+#[derive(Clone)]
+pub struct WinWindowCapturable {
+    /// The HWND as integer, raw pointers are not Send.
+    hwnd: usize,
+    title: String,
+}
+
+impl WinWindowCapturable {
+    fn hwnd(&self) -> HWND {
+        self.hwnd as HWND
+    }
+}
+
+/// Top left corner of the virtual screen, the union of all screens. Windows report their position
+/// relative to it, it is queried anew as screens may be added or rearranged while a window is
+/// captured.
+pub fn virtual_screen_origin() -> (i32, i32) {
+    unsafe {
+        (
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+        )
+    }
+}
+
+fn window_rect(hwnd: HWND) -> Result<RECT, Box<dyn Error>> {
+    unsafe {
+        if IsWindow(hwnd) == 0 {
+            return Err(WinWindowError("Window has been closed.".into()).into());
+        }
+        let mut rect: RECT = mem::zeroed();
+        if GetWindowRect(hwnd, &mut rect) == 0 {
+            return Err(WinWindowError("Failed to get the position of the window.".into()).into());
+        }
+        Ok(rect)
+    }
+}
+
+impl Capturable for WinWindowCapturable {
+    fn name(&self) -> String {
+        self.title.clone()
+    }
+
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        window_rect(self.hwnd())?;
+        if activate {
+            unsafe {
+                if GetForegroundWindow() != self.hwnd() {
+                    SetForegroundWindow(self.hwnd());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn recorder(&self, _capture_cursor: bool) -> Result<Box<dyn Recorder>, Box<dyn Error>> {
+        Ok(Box::new(WinWindowRecorder::new(self.hwnd())?))
+    }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(DesktopCursorTracker::new(Box::new(self.clone()))))
+    }
+
+    /// Executable of the window, e.g. chrome.exe.
+    fn application(&self) -> Option<String> {
+        window_executable(self.hwnd())
+    }
+
+    fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
+        let rect = window_rect(self.hwnd())?;
+        let (left, top) = virtual_screen_origin();
+        Ok(Geometry::VirtualScreen(
+            rect.left - left,
+            rect.top - top,
+            (rect.right - rect.left) as u32,
+            (rect.bottom - rect.top) as u32,
+            rect.left,
+            rect.top,
+        ))
+    }
+}
+
+unsafe extern "system" fn collect_window(hwnd: HWND, windows: LPARAM) -> BOOL {
+    let windows = &mut *(windows as *mut Vec<HWND>);
+    windows.push(hwnd);
+    TRUE
+}
+
+/// Top level windows as shown on the taskbar.
+pub fn get_windows() -> Vec<WinWindowCapturable> {
+    top_level_windows()
+        .into_iter()
+        .map(|(hwnd, title)| WinWindowCapturable {
+            hwnd: hwnd as usize,
+            title,
+        })
+        .collect()
+}
+
+/// Handles and titles of the top level windows shown on the taskbar.
+pub fn top_level_windows() -> Vec<(HWND, String)> {
+    let mut hwnds: Vec<HWND> = Vec::new();
+    unsafe {
+        EnumWindows(Some(collect_window), &mut hwnds as *mut Vec<HWND> as LPARAM);
+    }
+    hwnds
+        .into_iter()
+        .filter_map(|hwnd| unsafe {
+            // skip hidden windows, dialogs owned by other windows and tool windows
+            if IsWindowVisible(hwnd) == 0
+                || !GetWindow(hwnd, GW_OWNER).is_null()
+                || GetWindowLongW(hwnd, GWL_EXSTYLE) as u32 & WS_EX_TOOLWINDOW != 0
+            {
+                return None;
+            }
+            let len = GetWindowTextLengthW(hwnd);
+            if len <= 0 {
+                return None;
+            }
+            let mut buf = vec![0u16; len as usize + 1];
+            let len = GetWindowTextW(hwnd, buf.as_mut_ptr(), buf.len() as i32);
+            Some((hwnd, String::from_utf16_lossy(&buf[..len.max(0) as usize])))
+        })
+        .collect()
+}
+
+pub struct WinWindowRecorder {
+    hwnd: HWND,
+    /// Memory device context the window is rendered to.
+    dc: HDC,
+    bitmap: HBITMAP,
+    /// Pixels of the bitmap, owned by the bitmap.
+    bits: *mut u8,
+    width: usize,
+    height: usize,
+}
+
+impl WinWindowRecorder {
+    pub fn new(hwnd: HWND) -> Result<Self, Box<dyn Error>> {
+        let dc = unsafe { CreateCompatibleDC(ptr::null_mut()) };
+        if dc.is_null() {
+            return Err(WinWindowError("Failed to create device context.".into()).into());
+        }
+        Ok(Self {
+            hwnd,
+            dc,
+            bitmap: ptr::null_mut(),
+            bits: ptr::null_mut(),
+            width: 0,
+            height: 0,
+        })
+    }
+
+    /// Replace the bitmap by one of the given size.
+    fn resize(&mut self, width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            let mut info: BITMAPINFO = mem::zeroed();
+            info.bmiHeader.biSize = mem::size_of::<BITMAPINFOHEADER>() as u32;
+            info.bmiHeader.biWidth = width as i32;
+            // negative to get the rows top to bottom
+            info.bmiHeader.biHeight = -(height as i32);
+            info.bmiHeader.biPlanes = 1;
+            info.bmiHeader.biBitCount = 32;
+            info.bmiHeader.biCompression = BI_RGB;
+            let mut bits = ptr::null_mut();
+            let bitmap = CreateDIBSection(
+                self.dc,
+                &info,
+                DIB_RGB_COLORS,
+                &mut bits,
+                ptr::null_mut(),
+                0,
+            );
+            if bitmap.is_null() {
+                return Err(WinWindowError("Failed to create bitmap.".into()).into());
+            }
+            SelectObject(self.dc, bitmap as HGDIOBJ);
+            if !self.bitmap.is_null() {
+                DeleteObject(self.bitmap as HGDIOBJ);
+            }
+            self.bitmap = bitmap;
+            self.bits = bits as *mut u8;
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    fn pixels(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.bits, self.width * self.height * 4) }
+    }
+
+    /// Copy the area of the window from the screen.
+    fn copy_from_screen(&mut self, rect: &RECT) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            let screen = GetDC(ptr::null_mut());
+            let ok = BitBlt(
+                self.dc,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                screen,
+                rect.left,
+                rect.top,
+                SRCCOPY | CAPTUREBLT,
+            );
+            ReleaseDC(ptr::null_mut(), screen);
+            GdiFlush();
+            if ok == 0 {
+                return Err(WinWindowError("Failed to copy window from the screen.".into()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Recorder for WinWindowRecorder {
+    fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        let rect = window_rect(self.hwnd)?;
+        if unsafe { IsIconic(self.hwnd) } != 0 {
+            return Err(WinWindowError("Window is minimized.".into()).into());
+        }
+        let width = (rect.right - rect.left).max(0) as usize;
+        let height = (rect.bottom - rect.top).max(0) as usize;
+        if width == 0 || height == 0 {
+            return Err(WinWindowError("Window has no size.".into()).into());
+        }
+        if (width, height) != (self.width, self.height) {
+            self.resize(width, height)?;
+        }
+        let rendered = unsafe {
+            let ok = PrintWindow(self.hwnd, self.dc, PW_RENDERFULLCONTENT) != 0;
+            GdiFlush();
+            ok
+        };
+        let black = || self.pixels().chunks_exact(4).all(|p| p[..3] == [0, 0, 0]);
+        if !rendered || black() {
+            self.copy_from_screen(&rect)?;
+        }
+        Ok(PixelProvider::BGR0(width, height, self.pixels()))
+    }
+}
+
+impl Drop for WinWindowRecorder {
+    fn drop(&mut self) {
+        unsafe {
+            // the bitmap can only be deleted once it is no longer selected into the device context
+            DeleteDC(self.dc);
+            if !self.bitmap.is_null() {
+                DeleteObject(self.bitmap as HGDIOBJ);
+            }
+        }
+    }
+}
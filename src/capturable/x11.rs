@@ -1,8 +1,10 @@
-use crate::capturable::{Capturable, Geometry, Recorder};
+use crate::capturable::{
+    Capturable, Cursor, CursorShape, CursorTracker, DirtyRect, Geometry, Recorder,
+};
 use crate::cerror::CError;
 use crate::video::PixelProvider;
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_float, c_int, c_uint, c_void};
+use std::os::raw::{c_char, c_float, c_int, c_uint, c_ulong, c_void};
 use std::slice::from_raw_parts;
 use std::sync::Arc;
 use std::{error::Error, fmt};
@@ -23,12 +25,20 @@ extern "C" {
         handles: *mut *mut c_void,
         num_monitors: *mut c_int,
         size: c_int,
+        include_decorations: c_int,
+        include_popups: c_int,
         err: *mut CError,
     ) -> c_int;
 
     fn clone_capturable(handle: *const c_void) -> *mut c_void;
     fn destroy_capturable(handle: *mut c_void);
     fn get_capturable_name(handle: *const c_void) -> *const c_char;
+    fn get_capturable_application(
+        handle: *const c_void,
+        dest: *mut c_char,
+        size: usize,
+        err: *mut CError,
+    );
     fn capturable_before_input(handle: *mut c_void, err: *mut CError);
     fn get_geometry_relative(
         handle: *const c_void,
@@ -45,6 +55,14 @@ extern "C" {
         libinput: c_int,
         err: *mut CError,
     );
+    fn create_master_device(disp: *mut c_void, name: *const c_char, err: *mut CError) -> c_int;
+    fn remove_master_device(disp: *mut c_void, master_pointer: c_int, err: *mut CError);
+    fn attach_to_master_device(
+        disp: *mut c_void,
+        device_name: *const c_char,
+        master_pointer: c_int,
+        err: *mut CError,
+    ) -> c_int;
     fn start_capture(handle: *const c_void, ctx: *mut c_void, err: *mut CError) -> *mut c_void;
     fn capture_screen(
         handle: *mut c_void,
@@ -53,6 +71,25 @@ extern "C" {
         err: *mut CError,
     );
     fn stop_capture(handle: *mut c_void, err: *mut CError);
+    fn capture_damage(
+        handle: *mut c_void,
+        rects: *mut CDamageRect,
+        max_rects: c_int,
+        capture_cursor: c_int,
+        err: *mut CError,
+    ) -> c_int;
+
+    fn get_cursor(handle: *const c_void, cursor: *mut CCursorImage, err: *mut CError);
+}
+
+/// Whether $DISPLAY refers to an X server reached via the network, e.g. in a VM or container.
+/// Such servers can neither share memory with Weylus nor receive input from uinput devices.
+pub fn is_remote_display() -> bool {
+    let display = std::env::var("DISPLAY").unwrap_or_default();
+    match display.split_once(':') {
+        Some((host, _)) => !host.is_empty() && host != "unix" && !host.starts_with('/'),
+        None => false,
+    }
 }
 
 pub fn x11_init() {
@@ -124,7 +161,10 @@ impl Capturable for X11Capturable {
         ))
     }
 
-    fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
+    fn before_input(&mut self, activate: bool) -> Result<(), Box<dyn Error>> {
+        if !activate {
+            return Ok(());
+        }
         let mut err = CError::new();
         self.disp.lock();
         unsafe { capturable_before_input(self.handle, &mut err) };
@@ -142,6 +182,27 @@ impl Capturable for X11Capturable {
             Err(err) => Err(Box::new(err)),
         }
     }
+
+    fn cursor_tracker(&self) -> Option<Box<dyn CursorTracker>> {
+        Some(Box::new(X11CursorTracker::new(self.clone())))
+    }
+
+    fn application(&self) -> Option<String> {
+        let mut buf = [0 as c_char; 256];
+        let mut err = CError::new();
+        self.disp.lock();
+        unsafe { get_capturable_application(self.handle, buf.as_mut_ptr(), buf.len(), &mut err) };
+        self.disp.unlock();
+        if err.is_err() {
+            debug!("Failed to get application of {}: {err}", self.name());
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(buf.as_ptr()) }
+                .to_string_lossy()
+                .into(),
+        )
+    }
 }
 
 impl fmt::Display for X11Capturable {
@@ -188,6 +249,16 @@ impl Drop for XDisplay {
     }
 }
 
+/// How windows are captured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct X11WindowOptions {
+    /// Capture the decorations reparenting window managers draw around windows.
+    pub decorations: bool,
+    /// Draw menus and tooltips, which are override-redirect windows of their own, over the
+    /// window.
+    pub popups: bool,
+}
+
 pub struct X11Context {
     disp: Arc<XDisplay>,
 }
@@ -200,7 +271,7 @@ impl X11Context {
         })
     }
 
-    pub fn capturables(&mut self) -> Result<Vec<X11Capturable>, CError> {
+    pub fn capturables(&mut self, options: X11WindowOptions) -> Result<Vec<X11Capturable>, CError> {
         let mut err = CError::new();
         let mut handles = [std::ptr::null_mut::<c_void>(); 128];
         let mut num_monitors: c_int = 0;
@@ -211,6 +282,8 @@ impl X11Context {
                 handles.as_mut_ptr(),
                 &mut num_monitors,
                 handles.len() as c_int,
+                options.decorations.into(),
+                options.popups.into(),
                 &mut err,
             )
         };
@@ -254,6 +327,48 @@ impl X11Context {
         }
         err
     }
+
+    /// Create an XInput2 master pointer and keyboard pair and return the id of the pointer.
+    pub fn create_master_device(&mut self, name: &str) -> Result<c_int, CError> {
+        let mut err = CError::new();
+        let name_c_str = CString::new(name).unwrap();
+        self.disp.lock();
+        let id = unsafe { create_master_device(self.disp.handle, name_c_str.as_ptr(), &mut err) };
+        self.disp.unlock();
+        if err.is_err() {
+            Err(err)
+        } else {
+            Ok(id)
+        }
+    }
+
+    /// Remove the master devices with the given pointer, their slaves return to the core pointer
+    /// and keyboard.
+    pub fn remove_master_device(&mut self, master_pointer: c_int) -> CError {
+        let mut err = CError::new();
+        self.disp.lock();
+        unsafe { remove_master_device(self.disp.handle, master_pointer, &mut err) };
+        self.disp.unlock();
+        err
+    }
+
+    /// Attach all slave devices named after the given device to the master devices of the given
+    /// pointer.
+    pub fn attach_to_master_device(&mut self, device_name: &str, master_pointer: c_int) -> CError {
+        let mut err = CError::new();
+        let device_name_c_str = CString::new(device_name).unwrap();
+        self.disp.lock();
+        unsafe {
+            attach_to_master_device(
+                self.disp.handle,
+                device_name_c_str.as_ptr(),
+                master_pointer,
+                &mut err,
+            )
+        };
+        self.disp.unlock();
+        err
+    }
 }
 
 #[repr(C)]
@@ -273,7 +388,7 @@ impl CImage {
     }
 
     pub fn size(&self) -> usize {
-        (self.width * self.height * 4) as usize
+        self.width as usize * self.height as usize * 4
     }
 
     pub fn data(&self) -> &[u8] {
@@ -281,6 +396,18 @@ impl CImage {
     }
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CDamageRect {
+    x: c_int,
+    y: c_int,
+    width: c_uint,
+    height: c_uint,
+}
+
+/// Damage beyond this many rectangles is merged into the last one.
+const MAX_DAMAGE_RECTS: usize = 16;
+
 pub struct RecorderX11 {
     handle: *mut c_void,
     // keep a reference to the capturable so it is not destroyed until we are done
@@ -288,6 +415,8 @@ pub struct RecorderX11 {
     capturable: X11Capturable,
     img: CImage,
     capture_cursor: bool,
+    /// Damage collected since the last capture, None if XDamage is not available.
+    dirty: Option<Vec<DirtyRect>>,
 }
 
 impl RecorderX11 {
@@ -304,9 +433,47 @@ impl RecorderX11 {
                 capturable,
                 img: CImage::new(),
                 capture_cursor,
+                dirty: Some(Vec::new()),
             })
         }
     }
+
+    /// Add the damage reported by the X server since the last call to the collected damage.
+    fn collect_damage(&mut self) {
+        if self.dirty.is_none() {
+            return;
+        }
+        let mut rects = [CDamageRect::default(); MAX_DAMAGE_RECTS];
+        let mut err = CError::new();
+        self.capturable.disp.lock();
+        let n = unsafe {
+            capture_damage(
+                self.handle,
+                rects.as_mut_ptr(),
+                MAX_DAMAGE_RECTS as c_int,
+                self.capture_cursor.into(),
+                &mut err,
+            )
+        };
+        self.capturable.disp.unlock();
+        if err.is_err() {
+            // the window may be gone, capture reports that
+            debug!("Failed to collect damage: {err}");
+            return;
+        }
+        let Ok(n) = usize::try_from(n) else {
+            self.dirty = None;
+            return;
+        };
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.extend(rects[..n].iter().map(|r| DirtyRect {
+                x: r.x as usize,
+                y: r.y as usize,
+                width: r.width as usize,
+                height: r.height as usize,
+            }));
+        }
+    }
 }
 
 impl Drop for RecorderX11 {
@@ -322,6 +489,19 @@ impl Drop for RecorderX11 {
 
 impl Recorder for RecorderX11 {
     fn capture(&mut self) -> Result<PixelProvider<'_>, Box<dyn Error>> {
+        // damage is taken before grabbing, changes while grabbing are reported the next time
+        self.collect_damage();
+        let unchanged = self.dirty.as_ref().is_some_and(|d| d.is_empty());
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.clear();
+        }
+        if unchanged && !self.img.data.is_null() {
+            return Ok(PixelProvider::BGR0(
+                self.img.width as usize,
+                self.img.height as usize,
+                self.img.data(),
+            ));
+        }
         let mut err = CError::new();
         self.capturable.disp.lock();
         unsafe {
@@ -344,4 +524,105 @@ impl Recorder for RecorderX11 {
             ))
         }
     }
+
+    fn frame_changed(&mut self) -> bool {
+        self.collect_damage();
+        self.img.data.is_null() || self.dirty.as_ref().map_or(true, |d| !d.is_empty())
+    }
+
+    fn dirty_rects(&mut self) -> Option<Vec<DirtyRect>> {
+        self.collect_damage();
+        if self.img.data.is_null() {
+            return None;
+        }
+        self.dirty.clone()
+    }
+}
+
+const MAX_CURSOR_SIZE: usize = 256;
+
+#[repr(C)]
+struct CCursorImage {
+    x: c_int,
+    y: c_int,
+    capturable_width: c_uint,
+    capturable_height: c_uint,
+    width: c_uint,
+    height: c_uint,
+    xhot: c_int,
+    yhot: c_int,
+    serial: c_ulong,
+    pixels: [u32; MAX_CURSOR_SIZE * MAX_CURSOR_SIZE],
+}
+
+pub struct X11CursorTracker {
+    capturable: X11Capturable,
+    img: Box<CCursorImage>,
+    serial: Option<c_ulong>,
+}
+
+impl X11CursorTracker {
+    fn new(capturable: X11Capturable) -> Self {
+        Self {
+            capturable,
+            img: Box::new(CCursorImage {
+                x: 0,
+                y: 0,
+                capturable_width: 0,
+                capturable_height: 0,
+                width: 0,
+                height: 0,
+                xhot: 0,
+                yhot: 0,
+                serial: 0,
+                pixels: [0; MAX_CURSOR_SIZE * MAX_CURSOR_SIZE],
+            }),
+            serial: None,
+        }
+    }
+}
+
+impl CursorTracker for X11CursorTracker {
+    fn poll(&mut self) -> Result<Cursor, Box<dyn Error>> {
+        let mut err = CError::new();
+        self.capturable.disp.lock();
+        unsafe { get_cursor(self.capturable.handle, self.img.as_mut(), &mut err) };
+        self.capturable.disp.unlock();
+        if err.is_err() {
+            return Err(err.into());
+        }
+        let img = &self.img;
+        let cap_width = img.capturable_width.max(1) as f64;
+        let cap_height = img.capturable_height.max(1) as f64;
+        let shape = if self.serial != Some(img.serial) && img.width > 0 && img.height > 0 {
+            self.serial = Some(img.serial);
+            // XFixes provides ARGB pixels with premultiplied alpha
+            let mut rgba = Vec::with_capacity((img.width * img.height * 4) as usize);
+            for pixel in &img.pixels[0..(img.width * img.height) as usize] {
+                let [a, r, g, b] = pixel.to_be_bytes();
+                let unpremultiply = |c: u8| {
+                    if a == 0 {
+                        0
+                    } else {
+                        (c as u32 * 255 / a as u32).min(255) as u8
+                    }
+                };
+                rgba.extend_from_slice(&[unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+            }
+            image::RgbaImage::from_raw(img.width, img.height, rgba).map(|image| CursorShape {
+                width: img.width as f64 / cap_width,
+                height: img.height as f64 / cap_height,
+                xhot: img.xhot as f64 / cap_width,
+                yhot: img.yhot as f64 / cap_height,
+                image,
+            })
+        } else {
+            None
+        };
+        Ok(Cursor {
+            x: img.x as f64 / cap_width,
+            y: img.y as f64 / cap_height,
+            shape,
+        })
+    }
 }
@@ -0,0 +1,148 @@
+//! Settings of individual clients, kept across sessions.
+//!
+//! Browsers generate a random id for themselves and keep it in their local storage, settings are
+//! stored per id in `client_settings.toml` next to the configuration file and applied whenever a
+//! client with that id connects. The host edits the file, Weylus only fills in the names of
+//! clients so they can be told apart:
+//!
+//! ```toml
+//! [clients.3f9c0b6e2d7a41c8]
+//! name = "Drawing tablet"
+//! role = "Viewer"
+//! stylus_only = true
+//! pressure_curve = 0.8
+//! hardware_encoding = false
+//! bandwidth_cap = 20.0
+//! calibration = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::input::profiles::InputProfile;
+use crate::protocol::PointerEvent;
+use crate::websocket::ClientRole;
+
+/// Ids are generated by the client, longer ones or ones with other characters are ignored.
+const MAX_ID_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClientSettings {
+    /// Name the client last sent, filled in by Weylus.
+    pub name: Option<String>,
+    /// A client stored as viewer connects as viewer even with the access code of controllers,
+    /// the role can only be restricted this way.
+    pub role: Option<ClientRole>,
+    /// Ignore touch and mouse input of the client, only the pen is passed on.
+    #[serde(default)]
+    pub stylus_only: bool,
+    /// Exponent applied to the pressure of the pen, input profiles matching the application in
+    /// the foreground take precedence.
+    pub pressure_curve: Option<f64>,
+    /// Set to false to encode the video of the client in software, e.g. if its decoder has
+    /// trouble with the video of the hardware encoder.
+    pub hardware_encoding: Option<bool>,
+    /// Used unless the client chooses a cap itself, in Mbit/s.
+    pub bandwidth_cap: Option<f64>,
+    /// Applied to the positions the client sends, like the calibration matrix of libinput:
+    /// x' = a * x + b * y + c, y' = d * x + e * y + f for [a, b, c, d, e, f].
+    pub calibration: Option<[f64; 6]>,
+}
+
+impl ClientSettings {
+    /// The input settings as a profile for all applications.
+    pub fn input_profile(&self) -> Option<InputProfile> {
+        (self.stylus_only || self.pressure_curve.is_some()).then(|| InputProfile {
+            application: String::new(),
+            pressure_curve: self.pressure_curve,
+            pen_scroll_button: None,
+            precision_button: None,
+            eraser: None,
+            stylus_only: self.stylus_only,
+        })
+    }
+
+    pub fn calibrate(&self, event: &mut PointerEvent) {
+        if let Some([a, b, c, d, e, f]) = self.calibration {
+            (event.x, event.y) = (a * event.x + b * event.y + c, d * event.x + e * event.y + f);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoredSettings {
+    #[serde(default)]
+    clients: BTreeMap<String, ClientSettings>,
+}
+
+/// Held while the file is read and written again.
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+fn settings_path() -> Option<PathBuf> {
+    let mut path = dirs::config_dir()?;
+    path.push("weylus");
+    path.push("client_settings.toml");
+    Some(path)
+}
+
+/// None if the file could not be parsed, it must not be overwritten then.
+fn load() -> Option<StoredSettings> {
+    let Some(s) = settings_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Some(StoredSettings::default());
+    };
+    toml::from_str(&s)
+        .map_err(|err| warn!("Failed to read client settings: {err}"))
+        .ok()
+}
+
+fn store(settings: &StoredSettings) {
+    let Some(path) = settings_path() else {
+        warn!("Failed to find configuration directory, client settings are not stored!");
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(err) = fs::create_dir_all(dir) {
+            warn!("Failed to create directory for client settings: {err}");
+            return;
+        }
+    }
+    let s = toml::to_string_pretty(settings).expect("Failed to encode client settings.");
+    if let Err(err) = fs::write(path, s) {
+        warn!("Failed to store client settings: {err}");
+    }
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The settings of the client with the given id. Clients connecting for the first time are added
+/// to the file and names are updated, the file is read again each time, so changes of the host
+/// apply once the client reconnects.
+pub fn load_for(id: &str, name: Option<&str>) -> ClientSettings {
+    let _lock = STORE_LOCK.lock().unwrap();
+    let Some(mut stored) = load() else {
+        return ClientSettings::default();
+    };
+    let new = !stored.clients.contains_key(id);
+    let settings = stored.clients.entry(id.to_string()).or_default();
+    let renamed = name.is_some() && settings.name.as_deref() != name;
+    if renamed {
+        settings.name = name.map(str::to_string);
+    }
+    let settings = settings.clone();
+    if new || renamed {
+        debug!("Storing settings of client {id}.");
+        store(&stored);
+    }
+    settings
+}
@@ -1,82 +1,118 @@
 use std::net::IpAddr;
+use std::time::Duration;
 use std::{fs, path::PathBuf};
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+use crate::capturable::region::CaptureRegion;
+use crate::capturable::rotation::Rotation;
+use crate::frame_stamp::TextPosition;
+use crate::h264_level::H264Level;
+use crate::hooks::Hook;
+use crate::input::device::{ActivationPolicy, EraserPolicy, InputBackend};
+use crate::input::dwell_click::DwellAction;
+use crate::input::indicator::IndicatorStyle;
+use crate::input::keypad::Keypad;
+use crate::input::pointer_filter::PenButton;
+use crate::input::profiles::InputProfile;
+use crate::input::sensors::SensorMode;
+use crate::input::shortcuts::KeyCombo;
+use crate::input::shortcuts::ShortcutSet;
+use crate::input::switch_control::SwitchZone;
+use crate::input::text_filter::TextInjectionPolicy;
+use crate::power::PowerAction;
+use crate::schedule::AvailabilityWindow;
+use crate::video::LatencyPreset;
+use crate::wake::UnlockMethod;
+
+/// Color scheme of the gui, the names of the former FLTK themes are still accepted.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ThemeType {
-    Aero,
-    AquaClassic,
-    Blue,
-    Classic,
+    /// Follow the color scheme of the desktop.
+    #[default]
+    #[serde(alias = "Greybird")]
+    #[value(alias = "greybird")]
+    System,
+    #[serde(
+        alias = "Aero",
+        alias = "AquaClassic",
+        alias = "Blue",
+        alias = "Classic",
+        alias = "Metro"
+    )]
+    #[value(
+        alias = "aero",
+        alias = "aqua-classic",
+        alias = "blue",
+        alias = "classic",
+        alias = "metro"
+    )]
+    Light,
+    #[serde(alias = "HighContrast")]
+    #[value(alias = "high-contrast")]
     Dark,
-    Greybird,
-    HighContrast,
-    Metro,
 }
 
-const THEME_LIST: [ThemeType; 8] = [
-    ThemeType::Aero,
-    ThemeType::AquaClassic,
-    ThemeType::Blue,
-    ThemeType::Classic,
-    ThemeType::Dark,
-    ThemeType::Greybird,
-    ThemeType::HighContrast,
-    ThemeType::Metro,
-];
-
-impl Default for ThemeType {
-    fn default() -> Self {
-        Self::Greybird
-    }
-}
+const THEME_LIST: [ThemeType; 3] = [ThemeType::System, ThemeType::Light, ThemeType::Dark];
 
 impl ThemeType {
-    pub fn apply(&self) {
-        let theme = match self {
-            ThemeType::Classic => fltk_theme::ThemeType::Classic,
-            ThemeType::Aero => fltk_theme::ThemeType::Aero,
-            ThemeType::Metro => fltk_theme::ThemeType::Metro,
-            ThemeType::AquaClassic => fltk_theme::ThemeType::AquaClassic,
-            ThemeType::Greybird => fltk_theme::ThemeType::Greybird,
-            ThemeType::Blue => fltk_theme::ThemeType::Blue,
-            ThemeType::Dark => fltk_theme::ThemeType::Dark,
-            ThemeType::HighContrast => fltk_theme::ThemeType::HighContrast,
-        };
-        let theme = fltk_theme::WidgetTheme::new(theme);
-        theme.apply();
-    }
-
     pub fn name(&self) -> String {
         format!("{self:?}")
     }
 
-    pub fn to_index(&self) -> i32 {
-        THEME_LIST.iter().position(|th| th == self).unwrap() as i32
-    }
-
-    pub fn from_index(i: i32) -> Self {
-        let i = i.clamp(0, THEME_LIST.len() as i32 - 1) as usize;
-        THEME_LIST[i]
-    }
-
     pub fn themes() -> &'static [ThemeType] {
         &THEME_LIST
     }
 }
 
 #[derive(Serialize, Deserialize, Parser, Debug, Clone)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, disable_version_flag = true)]
 pub struct Config {
+    #[arg(short = 'V', long, help = "Print version")]
+    #[serde(skip)]
+    pub version: bool,
+    #[arg(
+        long,
+        requires = "version",
+        help = "With --version, also print the platform and the features Weylus was built with."
+    )]
+    #[serde(skip)]
+    pub verbose: bool,
     #[arg(long, help = "Access code")]
     pub access_code: Option<String>,
+    #[arg(
+        long,
+        help = "Keep secrets like the access code in the configuration file instead of the \
+            keyring of the operating system, e.g. on headless servers without a keyring."
+    )]
+    #[serde(default)]
+    pub no_keyring: bool,
     #[arg(long, default_value = "0.0.0.0", help = "Bind address")]
     pub bind_address: IpAddr,
     #[arg(long, default_value = "1701", help = "Web port")]
     pub web_port: u16,
+    #[arg(
+        long,
+        help = "If the web port or the WebTransport port is in use, listen on the next free port \
+            instead of failing to start."
+    )]
+    #[serde(default)]
+    pub find_free_port: bool,
+    #[arg(
+        long,
+        help = "Only accept connections during this time window, e.g. \"Mon-Fri 08:00-15:30\" or \
+            \"22:00-02:00\" in local time. Can be given several times, the web server is closed \
+            outside of all windows."
+    )]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<AvailabilityWindow>,
+    /// Set by the GUI to keep the web server open (true) or closed (false) regardless of the
+    /// schedule, not persisted.
+    #[arg(skip)]
+    #[serde(skip)]
+    pub schedule_override: Option<bool>,
     #[cfg(target_os = "linux")]
     #[arg(
         long,
@@ -101,6 +137,105 @@ pub struct Config {
     )]
     #[serde(default)]
     pub try_mediafoundation: bool,
+    #[arg(
+        long,
+        help = "Number of threads used by the software encoder, 0 lets the encoder decide. \
+            Defaults to half the number of CPUs, at most 8."
+    )]
+    pub encoder_threads: Option<u32>,
+    #[arg(
+        long,
+        help = "Number of slices each frame is split into by the software encoder, more slices \
+            reduce latency but increase the bitrate. Defaults to the number of threads."
+    )]
+    pub encoder_slices: Option<u32>,
+    #[arg(
+        long,
+        help = "Open the video encoder before the first client connects and keep it open after \
+            a client disconnects, this shortens the time until the first frame appears on \
+            connect. Hardware encoders may support only a limited number of open encoders."
+    )]
+    #[serde(default)]
+    pub encoder_standby: bool,
+    #[arg(
+        long,
+        help = "Tune the encoder and the buffering of clients for the lowest latency (default), \
+            a balance of latency and quality or the best quality."
+    )]
+    pub latency_preset: Option<LatencyPreset>,
+    #[arg(
+        long,
+        help = "Upper bound of the bandwidth the video of each client may use in Mbit/s, e.g. 8 \
+            for metered or shared links. Clients may choose a lower cap."
+    )]
+    pub bandwidth_cap: Option<f64>,
+    #[arg(
+        long,
+        help = "Highest H.264 level the video may use, captures too large for it are scaled \
+            down. Defaults to 5.2, which fits 4K and 5120x1440, 8K requires 6.0 and a client \
+            decoding it."
+    )]
+    pub max_h264_level: Option<H264Level>,
+    #[arg(
+        long,
+        help = "Frames between keyframes, e.g. 300 for one every 10 seconds at 30 fps. Defaults \
+            to the one of the latency preset."
+    )]
+    pub keyframe_interval: Option<u32>,
+    #[arg(
+        long,
+        help = "Refresh the picture gradually over the keyframe interval instead of sending \
+            keyframes, which avoids periodic spikes in bitrate and latency on constrained links. \
+            Supported by the software encoder and NVENC, others send keyframes as usual."
+    )]
+    #[serde(default)]
+    pub intra_refresh: bool,
+    #[arg(
+        long,
+        help = "Do not benchmark the video encoders on the first start. The results are used to \
+            pick the frame rate offered to clients and to warn about frame rates the encoder can \
+            not sustain."
+    )]
+    #[serde(default)]
+    pub no_encoder_probe: bool,
+    #[arg(
+        long,
+        help = "Write a summary of each session to a JSON file in this directory once the client \
+            disconnects: its duration, the frames sent and dropped, the latency of the connection \
+            and the input received. Summaries are logged regardless."
+    )]
+    pub session_summary_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write all messages clients send to this file, to be attached to bug reports about \
+            input. Typed text and the names of clients are left out, keyboard shortcuts are kept."
+    )]
+    #[serde(skip)]
+    pub record_protocol: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "record_protocol",
+        help = "Replay a file written with --record-protocol instead of starting the web server. \
+            The input is logged by mock devices instead of being sent to this computer."
+    )]
+    #[serde(skip)]
+    pub replay_protocol: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Burn this text into the video sent to clients, e.g. \"CONFIDENTIAL {host}\". \
+            {host}, {client}, {date} and {time} are replaced by the name of this computer, the \
+            name or address of the client, the current date and time, \\n starts a new line."
+    )]
+    pub watermark: Option<String>,
+    #[arg(
+        long,
+        requires = "watermark",
+        help = "Corner of the video the watermark is drawn into or the center, defaults to \
+            bottom-right."
+    )]
+    pub watermark_position: Option<TextPosition>,
     #[arg(long, help = "Start Weylus server immediately on program start.")]
     #[serde(default)]
     pub auto_start: bool,
@@ -109,10 +244,506 @@ pub struct Config {
     #[arg(long, help = "Run Weylus without gui and start immediately.")]
     #[serde(default)]
     pub no_gui: bool,
+    #[arg(
+        long,
+        help = "Do not leave the window of Weylus out of captures, only Windows and macOS 12.3 \
+            or later support leaving it out."
+    )]
+    #[serde(default)]
+    pub capture_self: bool,
+    #[cfg(target_os = "windows")]
+    #[arg(
+        long,
+        help = "Do not offer to create rules in the Windows Firewall for the ports Weylus listens \
+            on."
+    )]
+    #[serde(default)]
+    pub no_firewall_prompt: bool,
     #[cfg(target_os = "linux")]
-    #[arg(long, help = "Wayland/PipeWire Support.")]
+    #[arg(
+        long,
+        help = "Wayland/PipeWire Support, enabled automatically in Wayland sessions."
+    )]
     #[serde(default)]
     pub wayland_support: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Include the decorations the window manager draws around windows when capturing \
+            windows on X11."
+    )]
+    #[serde(default)]
+    pub x11_window_decorations: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Draw menus and tooltips over windows captured on X11 instead of capturing the \
+            screen area of the active window, this keeps other windows out of the video."
+    )]
+    #[serde(default)]
+    pub x11_window_popups: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Let PipeWire share frames as DMA-BUF and convert and scale them on the GPU \
+            instead of copying them into system memory. Requires --try-vaapi, this mostly helps \
+            with high resolution captures."
+    )]
+    #[serde(default)]
+    pub pipewire_dmabuf: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "X display to capture and send input to instead of the one in $DISPLAY, e.g. \
+            vm:0 for an X server running in a VM. Input is sent via XTest as uinput devices \
+            only reach the local machine. The gui is shown on this display, too."
+    )]
+    pub display: Option<String>,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Run without access to uinput or the X server, e.g. in a Flatpak: screens are \
+            captured and input is sent via xdg-desktop-portal. Enabled automatically inside a \
+            Flatpak, implies --wayland-support."
+    )]
+    #[serde(default)]
+    pub sandboxed: bool,
+    #[arg(
+        long,
+        help = "Announce Weylus via mDNS and list other Weylus instances found on the local \
+            network on the web page."
+    )]
+    #[serde(default)]
+    pub mdns: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Hand out IPv4 addresses on this network interface via DHCP, e.g. on an ad hoc \
+            network or a cable to the tablet without a router. Requires root or \
+            CAP_NET_BIND_SERVICE."
+    )]
+    pub dhcp_interface: Option<String>,
+
+    #[arg(
+        long,
+        help = "URL of an OpenID Connect provider, enables logging in via OIDC as an alternative \
+            to the access code."
+    )]
+    pub oidc_issuer: Option<String>,
+    #[arg(long, help = "Client id registered at the OpenID Connect provider.")]
+    pub oidc_client_id: Option<String>,
+    #[arg(
+        long,
+        help = "Client secret registered at the OpenID Connect provider."
+    )]
+    pub oidc_client_secret: Option<String>,
+    #[arg(
+        long,
+        help = "URL the OpenID Connect provider redirects to after logging in, it has to point \
+            to /oidc/callback, e.g. https://weylus.example.com/oidc/callback."
+    )]
+    pub oidc_redirect_url: Option<String>,
+    #[arg(long, help = "Scopes to request, defaults to \"openid profile\".")]
+    pub oidc_scopes: Option<String>,
+    #[arg(
+        long,
+        help = "Claim of the ID token that lists the groups of a user, defaults to \"groups\"."
+    )]
+    pub oidc_groups_claim: Option<String>,
+    #[arg(
+        long,
+        help = "Only members of this group may control the computer. If unset, all users that \
+            are not just viewers may."
+    )]
+    pub oidc_controller_group: Option<String>,
+    #[arg(long, help = "Members of this group may only watch the screen.")]
+    pub oidc_viewer_group: Option<String>,
+
+    #[arg(
+        long,
+        help = "Deliver the video via WebTransport (HTTP/3) on the given UDP port if supported by \
+            the browser, this avoids latency spikes on lossy networks."
+    )]
+    pub webtransport_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Encrypt the websocket with keys derived from the access code, so the screen and \
+            input can not be read on the network without TLS. The access code is entered on the \
+            client and never sent, logging in via the access code page is disabled."
+    )]
+    #[serde(default)]
+    pub encrypt_websocket: bool,
+
+    #[arg(
+        long,
+        help = "Devices connecting for the first time have to be approved in the GUI or via \
+            `weylus approve`, even if they know the access code."
+    )]
+    #[serde(default)]
+    pub approve_new_clients: bool,
+    #[arg(
+        long,
+        help = "Connections from this computer need no approval. Do not use this behind a reverse \
+            proxy, all clients connect from this computer then."
+    )]
+    #[serde(default)]
+    pub approve_loopback: bool,
+
+    #[arg(
+        long,
+        help = "Save files dropped onto the web page in this directory and drop them onto the \
+            window under the pointer of the host. Uploading files is disabled if not set."
+    )]
+    pub upload_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Let clients browse and download the files in this directory, e.g. to fetch \
+            exported images. Subdirectories are included, hidden files are not."
+    )]
+    pub shared_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Export tracing spans via OTLP/HTTP to the given endpoint, for example \
+            http://localhost:4318/v1/traces."
+    )]
+    pub otlp_endpoint: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only offer screens and windows to clients whose name contains the given string."
+    )]
+    pub capturable: Option<String>,
+    #[arg(
+        long = "capture-region",
+        value_name = "X,Y,WIDTH,HEIGHT",
+        help = "Offer this rectangle of the first screen to clients as well, in pixels. With \
+            --capturable it is cut out of the first screen or window matching. Can be given \
+            several times."
+    )]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capture_regions: Vec<CaptureRegion>,
+    #[arg(
+        long,
+        help = "Rotate the video clockwise by this many degrees, e.g. to show a landscape screen \
+            on a tablet held upright. Clients may choose another rotation in their settings."
+    )]
+    pub rotation: Option<Rotation>,
+
+    #[arg(
+        long,
+        help = "Wake the display once a client with control permissions connects."
+    )]
+    #[serde(default)]
+    pub wake_on_connect: bool,
+    #[arg(
+        long,
+        requires = "wake_on_connect",
+        help = "Also unlock the session after waking the display."
+    )]
+    pub unlock_on_connect: Option<UnlockMethod>,
+    #[arg(
+        long,
+        help = "Secret typed into the lock screen by `--unlock-on-connect secret`."
+    )]
+    pub unlock_secret: Option<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Power actions clients with control permissions may request, e.g. lock,suspend. \
+            Each request has to be confirmed in a dialog on this computer."
+    )]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub power_actions: Vec<PowerAction>,
+    #[arg(
+        long,
+        help = "Show desktop notifications of this computer on clients with control permissions, \
+            only supported on Linux."
+    )]
+    #[serde(default)]
+    pub mirror_notifications: bool,
+
+    #[arg(
+        long,
+        help = "Hide the cursor of this computer while the pen is used, not supported on Windows \
+            and Wayland."
+    )]
+    #[serde(default)]
+    pub hide_cursor_during_pen: bool,
+    #[arg(
+        long,
+        help = "Clamp the pointer to the captured screen or window, even if a client sends \
+            coordinates outside of it. Keeps a misbehaving client from clicking on other screens."
+    )]
+    #[serde(default)]
+    pub confine_pointer: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        help = "Give the devices created via uinput a cursor and keyboard focus of their own via \
+            an XInput2 master device, so the pen does not move the mouse cursor of this \
+            computer. Only supported on X11."
+    )]
+    #[serde(default)]
+    pub xi2_master: bool,
+    #[cfg(target_os = "linux")]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Keep the devices created via uinput alive in a helper process for this many \
+            seconds after the client disconnected or Weylus exited, so a quick restart reuses them \
+            and settings of the desktop for them, like the monitor the stylus is mapped to, are \
+            kept."
+    )]
+    pub persist_uinput: Option<u64>,
+    #[arg(
+        long,
+        help = "Inject the pointer events predicted by the browser while hovering to reduce the \
+            perceived latency, predictions are corrected by the actual events. Predictions are \
+            only sent by clients with \"Send Predicted Events\" enabled."
+    )]
+    #[serde(default)]
+    pub inject_predicted_events: bool,
+
+    #[arg(
+        long,
+        help = "Ignore new touch contacts for the given number of milliseconds after the last pen \
+            event, this prevents stray input from resting the palm on the screen while drawing."
+    )]
+    pub palm_rejection_ms: Option<u64>,
+    #[arg(
+        long,
+        requires = "palm_rejection_ms",
+        help = "Only ignore touch contacts within this distance of the pen, relative to the size \
+            of the screen, e.g. 0.3. By default touch is ignored everywhere."
+    )]
+    pub palm_rejection_radius: Option<f64>,
+    #[arg(
+        long,
+        help = "Minimum pressure between 0 and 1 for the pen to click, lighter touches are \
+            treated as hovering."
+    )]
+    pub pen_down_pressure: Option<f64>,
+    #[arg(
+        long,
+        help = "Pressure between 0 and 1 below which the pressure of the pen is reported as \
+            zero, for styluses that report noise while hovering."
+    )]
+    pub pen_hover_pressure: Option<f64>,
+    #[arg(
+        long,
+        help = "Holding this button of the pen turns vertical movement of the pen into scroll \
+            wheel events."
+    )]
+    pub pen_scroll_button: Option<PenButton>,
+    #[arg(
+        long,
+        help = "Pressing this button of the pen toggles precision mode, which maps the whole \
+            tablet to a small region around the pointer. Clients can toggle it too."
+    )]
+    pub precision_button: Option<PenButton>,
+    #[arg(
+        long,
+        help = "Size of the region the tablet is mapped to in precision mode, relative to the \
+            size of the captured screen or window. Defaults to 0.25."
+    )]
+    pub precision_region: Option<f64>,
+    #[arg(
+        long,
+        help = "Accessibility mode: taps on the client act as switches that step a highlight \
+            across the screen and select where to click, instead of regular input."
+    )]
+    #[serde(default)]
+    pub switch_control: bool,
+    #[arg(
+        long,
+        help = "Move the highlight of switch control on by itself every this many milliseconds, \
+            any tap selects then."
+    )]
+    pub switch_scan_interval_ms: Option<u64>,
+    #[arg(
+        long,
+        help = "Milliseconds a switch has to be held before it acts, shorter taps are ignored. \
+            Defaults to 0."
+    )]
+    pub switch_dwell_ms: Option<u64>,
+    #[arg(
+        long,
+        help = "Number of rows and columns of the grid switch control lays over the screen, \
+            defaults to 3."
+    )]
+    pub switch_grid: Option<u32>,
+    #[arg(
+        long,
+        help = "How often switch control zooms into the selected cell before clicking it, \
+            defaults to 3."
+    )]
+    pub switch_levels: Option<u32>,
+    #[arg(
+        long,
+        help = "Click once the pointer rested for this many milliseconds while hovering, for \
+            users who find it hard to press the pen onto the screen."
+    )]
+    pub dwell_click_ms: Option<u64>,
+    #[arg(
+        long,
+        requires = "dwell_click_ms",
+        help = "Distance the pointer may move while resting for a dwell click, relative to the \
+            size of the screen. Defaults to 0.01."
+    )]
+    pub dwell_click_radius: Option<f64>,
+    #[arg(
+        long,
+        requires = "dwell_click_ms",
+        help = "What resting the pointer does: a click (default), a double click or pressing \
+            the button until the pointer rests again, for dragging."
+    )]
+    pub dwell_click_action: Option<DwellAction>,
+    #[arg(
+        long,
+        help = "Let clients with control permissions be tilted for input: as a joystick (only \
+            supported on Linux) or to orbit the 3D viewport by pressing shortcuts."
+    )]
+    pub sensors: Option<SensorMode>,
+    #[arg(
+        long,
+        requires = "sensors",
+        help = "Degrees clients have to be tilted to move the joystick all the way, defaults to \
+            30."
+    )]
+    pub sensor_range: Option<f64>,
+    #[arg(
+        long,
+        requires = "sensors",
+        help = "Degrees clients have to be tilted to orbit the viewport by one step, defaults to \
+            15."
+    )]
+    pub sensor_step: Option<f64>,
+    #[arg(
+        long,
+        requires = "sensors",
+        value_delimiter = ',',
+        help = "Shortcuts orbiting the viewport left, right, up and down, defaults to \
+            Numpad4,Numpad6,Numpad8,Numpad2 as used by Blender."
+    )]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensor_orbit_keys: Vec<KeyCombo>,
+
+    #[arg(
+        long,
+        help = "How the eraser end of the pen is passed to applications: as a distinct tool \
+            (default), as a button of the pen or by pressing a keyboard shortcut when switching \
+            to and from the eraser."
+    )]
+    pub eraser: Option<EraserPolicy>,
+    #[arg(
+        long,
+        help = "Code of the key pressed by the shortcut eraser policy, defaults to KeyE."
+    )]
+    pub eraser_shortcut: Option<String>,
+    #[arg(
+        long,
+        help = "When the captured window is brought to the front: before any input (default), \
+            only when clicking or never. Clients can choose a different policy for their session."
+    )]
+    pub window_activation: Option<ActivationPolicy>,
+    #[arg(
+        long,
+        help = "How input is sent on Linux: via uinput if the client enables it, the \
+            RemoteDesktop portal while running sandboxed and XTest otherwise (auto, default), or \
+            always via uinput, the portal or XTest. Can be switched for connected clients from \
+            the GUI or with `weylus input-backend`."
+    )]
+    pub input_backend: Option<InputBackend>,
+    #[arg(
+        long,
+        help = "What to do with Enter within text typed faster than humanly possible, e.g. \
+            pasted by a keyboard app, as this could run commands in a terminal: allow it, block \
+            it or hold the text back until confirmed on the tablet. Defaults to allow."
+    )]
+    pub text_injection_filter: Option<TextInjectionPolicy>,
+    #[arg(
+        long,
+        help = "Start clients in presentation mode: they can only watch, keyboard input is \
+            ignored and the pointer is shown as a highlight instead of moving the cursor. This \
+            can be switched per client from the GUI."
+    )]
+    #[serde(default)]
+    pub presentation_mode: bool,
+    #[arg(
+        long,
+        help = "Broadcast the video of the first controller to all viewers: it is encoded once \
+            and sent to every viewer, regardless of what they chose to watch. Viewers that can \
+            not keep up skip ahead to the next keyframe."
+    )]
+    #[serde(default)]
+    pub broadcast: bool,
+    #[arg(
+        long,
+        help = "Do not draw the laser pointers of viewers into the video. By default viewers \
+            can enable a laser pointer in their settings, shown in a color of its own to \
+            everyone watching the same screen."
+    )]
+    #[serde(default)]
+    pub no_laser_pointers: bool,
+    #[arg(
+        long,
+        help = "Maximum number of input events per second accepted from each client, excess \
+            events are dropped. By default input is not limited."
+    )]
+    pub input_rate_limit: Option<f64>,
+    #[arg(
+        long,
+        requires = "input_rate_limit",
+        help = "Number of input events a client may send in a burst above the rate limit, \
+            defaults to one second worth of events."
+    )]
+    pub input_rate_burst: Option<f64>,
+    #[arg(
+        long,
+        help = "Show a border around the screen or a badge in its corner while a client has \
+            control, so anyone looking at the screen knows remote input is active. Only \
+            supported on X11."
+    )]
+    pub control_indicator: Option<IndicatorStyle>,
+    #[arg(
+        long,
+        help = "Color of the control indicator, e.g. \"#ff3030\" (default) or \"orange\"."
+    )]
+    pub control_indicator_color: Option<String>,
+
+    /// Additional instances of the web server, these can only be configured via the
+    /// configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub instances: Vec<InstanceConfig>,
+    /// Input profiles switched to depending on the application in the foreground, these can
+    /// only be configured via the configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_profiles: Vec<InputProfile>,
+    /// Sets of keyboard shortcuts clients can show as a bar of buttons, these can only be
+    /// configured via the configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shortcut_sets: Vec<ShortcutSet>,
+    /// Zones of the client's screen mapped to actions of switch control, these can only be
+    /// configured via the configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub switch_zones: Vec<SwitchZone>,
+    /// Keys of the page served at /keypad, a numpad if not set. This can only be configured via
+    /// the configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keypad: Option<Keypad>,
+    /// Commands run and webhooks called on events of sessions, these can only be configured via
+    /// the configuration file.
+    #[arg(skip)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
 
     #[arg(long, help = "Print template of index.html served by Weylus.")]
     #[serde(skip)]
@@ -143,9 +774,160 @@ pub struct Config {
     #[serde(skip)]
     pub custom_lib_js: Option<PathBuf>,
 
-    #[arg(long, help = "Print shell completions for given shell.")]
+    // superseded by `weylus completions <shell>`, kept for compatibility with existing packages
+    #[arg(long, hide = true)]
     #[serde(skip)]
     pub completions: Option<clap_complete::Shell>,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+}
+
+/// Settings of an additional instance of the web server, everything not set here is taken from the
+/// main configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceConfig {
+    pub name: String,
+    pub web_port: u16,
+    pub bind_address: Option<IpAddr>,
+    pub access_code: Option<String>,
+    pub webtransport_port: Option<u16>,
+    pub capturable: Option<String>,
+    pub eraser: Option<EraserPolicy>,
+    pub text_injection_filter: Option<TextInjectionPolicy>,
+}
+
+/// Name of the instance configured by the top level settings.
+pub const MAIN_INSTANCE: &str = "main";
+
+impl Config {
+    /// Returns the configuration of the main instance followed by the configurations of all
+    /// additional instances.
+    pub fn instance_configs(&self) -> Vec<(String, Config)> {
+        let mut main = self.clone();
+        main.instances.clear();
+        let mut configs = vec![(MAIN_INSTANCE.to_string(), main.clone())];
+        for instance in &self.instances {
+            let mut config = main.clone();
+            config.web_port = instance.web_port;
+            if let Some(bind_address) = instance.bind_address {
+                config.bind_address = bind_address;
+            }
+            if instance.access_code.is_some() {
+                config.access_code = instance.access_code.clone();
+            }
+            // the WebTransport port of the main instance can not be shared
+            config.webtransport_port = instance.webtransport_port;
+            if instance.capturable.is_some() {
+                config.capturable = instance.capturable.clone();
+            }
+            if instance.eraser.is_some() {
+                config.eraser = instance.eraser;
+            }
+            if instance.text_injection_filter.is_some() {
+                config.text_injection_filter = instance.text_injection_filter;
+            }
+            configs.push((instance.name.clone(), config));
+        }
+        configs
+    }
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Query the status of the instance of Weylus running on the configured web port.
+    Status {
+        #[arg(long, help = "Print the status as JSON.")]
+        json: bool,
+    },
+    /// Create an invite link for the running instance of Weylus, it grants access without
+    /// revealing the access code.
+    Invite {
+        #[arg(
+            long,
+            default_value_t = 60,
+            help = "Minutes the link and sessions started with it are valid for, 0 means the \
+            link does not expire."
+        )]
+        minutes: u64,
+        #[arg(
+            long,
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "How often the link can be used."
+        )]
+        uses: u32,
+        #[arg(
+            long,
+            conflicts_with = "uses",
+            help = "The link can be used any number of times."
+        )]
+        unlimited: bool,
+        #[arg(long, help = "Guests may only watch and can not send any input.")]
+        viewer: bool,
+        #[arg(
+            long,
+            help = "Name of the instance to invite to if several are running."
+        )]
+        instance: Option<String>,
+    },
+    /// Invalidate all invite links and end the sessions of invited guests.
+    RevokeInvites {
+        #[arg(long, help = "Only revoke the invites of the instance with this name.")]
+        instance: Option<String>,
+    },
+    /// Switch the input backend of the clients connected to the running instance of Weylus,
+    /// their input devices are replaced without disconnecting them.
+    InputBackend {
+        backend: InputBackend,
+        #[arg(
+            long,
+            help = "Only switch the client with this address, e.g. 192.168.1.20:51234."
+        )]
+        client: Option<std::net::SocketAddr>,
+    },
+    /// List the devices waiting for approval, or approve or deny one of them. Only needed with
+    /// `--approve-new-clients`.
+    Approve {
+        #[arg(help = "Number of the request to decide on, as listed without it.")]
+        id: Option<u64>,
+        #[arg(
+            long,
+            help = "Remember the device, so it does not need to be approved again."
+        )]
+        remember: bool,
+        #[arg(long, conflicts_with = "remember", help = "Deny the device access.")]
+        deny: bool,
+    },
+    /// Allow the configured ports in the Windows Firewall or remove the rules created for them,
+    /// e.g. when uninstalling Weylus. Windows asks for administrator rights.
+    #[cfg(target_os = "windows")]
+    Firewall { action: FirewallAction },
+    /// Keep the devices created via uinput alive across restarts, started by Weylus itself if
+    /// `--persist-uinput` is set.
+    #[cfg(target_os = "linux")]
+    #[command(hide = true)]
+    UinputHolder,
+    /// Replace the access code in the configuration file with a new random one and print it.
+    /// Instances running without GUI pick it up immediately.
+    RotateAccessCode,
+    /// Print shell completions for the given shell.
+    Completions { shell: clap_complete::Shell },
+    /// Print the man page in roff format.
+    Manpage,
+    /// Capture a frame with each capture backend, open each video encoder, create the virtual
+    /// input devices and bind the configured ports, then print which of these work.
+    Selftest,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum FirewallAction {
+    /// Create inbound rules for the ports of all configured instances.
+    Add,
+    /// Remove all rules created by Weylus.
+    Remove,
 }
 
 pub fn read_config() -> Option<Config> {
@@ -175,7 +957,15 @@ pub fn read_config() -> Option<Config> {
     }
 }
 
+/// Write the configuration file, secrets go to the keyring unless `no_keyring` is set.
 pub fn write_config(conf: &Config) {
+    let stored;
+    let conf = if conf.no_keyring {
+        conf
+    } else {
+        stored = crate::secrets::store(conf);
+        &stored
+    };
     match dirs::config_dir() {
         Some(mut config_path) => {
             config_path.push("weylus");
@@ -199,14 +989,48 @@ pub fn write_config(conf: &Config) {
     }
 }
 
+/// Call on_change whenever the configuration file is modified.
+pub fn watch_config(on_change: impl Fn() + Send + 'static) {
+    let Some(mut config_path) = dirs::config_dir() else {
+        return;
+    };
+    config_path.push("weylus");
+    config_path.push("weylus.toml");
+    std::thread::spawn(move || {
+        let modified = || fs::metadata(&config_path).and_then(|m| m.modified()).ok();
+        let mut last_modified = modified();
+        loop {
+            std::thread::sleep(Duration::from_secs(2));
+            let m = modified();
+            if m != last_modified {
+                last_modified = m;
+                debug!("Configuration file changed.");
+                on_change();
+            }
+        }
+    });
+}
+
 pub fn get_config() -> Config {
     let args = std::env::args();
-    if let Some(mut config) = read_config() {
+    let mut config = if let Some(mut config) = read_config() {
+        let file_config = config.clone();
         if args.len() > 1 {
             config.update_from(args);
         }
+        if !config.no_keyring && crate::secrets::has_plaintext(&file_config) {
+            info!("Moving secrets from the configuration file to the keyring.");
+            let mut file_config = file_config;
+            file_config.no_keyring = false;
+            crate::secrets::load(&mut file_config);
+            write_config(&file_config);
+        }
         config
     } else {
         Config::parse()
+    };
+    if !config.no_keyring {
+        crate::secrets::load(&mut config);
     }
+    config
 }
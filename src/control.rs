@@ -0,0 +1,481 @@
+//! Control socket used by command line tools to talk to a running instance of Weylus.
+//!
+//! Requests and responses are JSON objects, one per line. The socket is created in the user's
+//! runtime directory and named after the port of the main web server, so several processes can
+//! run side by side. A single socket serves all instances hosted by the process, these register
+//! themselves via [`register_instance`].
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::approval::{Decision, PendingApproval};
+use crate::frame_stats::FrameStats;
+use crate::input::device::InputBackend;
+use crate::invite::{InviteOptions, Invites};
+use crate::permissions::Permissions;
+use crate::protocol::DeviceStatus;
+use crate::websocket::ClientRole;
+
+#[derive(Debug)]
+pub struct ControlError(String);
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for ControlError {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ControlRequest {
+    Status,
+    /// Create an invite link for the instance with the given name, the name may be omitted if
+    /// only one instance is running.
+    CreateInvite {
+        instance: Option<String>,
+        options: InviteOptions,
+    },
+    /// Invalidate all invites of the given instance or of all instances if None.
+    RevokeInvites {
+        instance: Option<String>,
+    },
+    /// Switch the input backend of the client with the given address or of all clients if None.
+    SetInputBackend {
+        backend: InputBackend,
+        client: Option<SocketAddr>,
+    },
+    /// List the devices waiting for approval.
+    PendingApprovals,
+    /// Approve or deny the device waiting for approval with the given id.
+    DecideApproval {
+        id: u64,
+        decision: Decision,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ControlResponse {
+    Status(Status),
+    /// Url of the created invite.
+    Invite(String),
+    /// Number of invites that were revoked.
+    InvitesRevoked(usize),
+    /// Number of clients whose input backend was switched.
+    InputBackendSet(usize),
+    PendingApprovals(Vec<PendingApproval>),
+    /// Whether there was a request with the given id.
+    ApprovalDecided(bool),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Status {
+    pub version: String,
+    pub pid: u32,
+    pub instances: Vec<InstanceStatus>,
+    /// Permissions granted to Weylus, only set on macOS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Permissions>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceStatus {
+    pub name: String,
+    pub bind_address: SocketAddr,
+    pub webtransport_address: Option<SocketAddr>,
+    pub clients: Vec<ClientStatus>,
+    /// No new connections are accepted as the current time is outside of the schedule or the GUI
+    /// closed the web server.
+    #[serde(default)]
+    pub closed_by_schedule: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientStatus {
+    pub address: SocketAddr,
+    pub role: ClientRole,
+    pub name: Option<String>,
+    pub capturable: Option<String>,
+    pub encoder: Option<String>,
+    /// The client may only point at things, its pointer is shown as a highlight on the host and
+    /// keyboard input is ignored. Switched by the GUI.
+    #[serde(default)]
+    pub presentation: bool,
+    /// Input events per second received recently.
+    #[serde(default)]
+    pub input_rate: f64,
+    /// Input events dropped as they exceeded the rate limit.
+    #[serde(default)]
+    pub dropped_input_events: u64,
+    /// Frames passing through the video pipeline recently.
+    #[serde(default)]
+    pub video: FrameStats,
+    /// Frames of the whole session so far, updated along with `video`.
+    #[serde(default)]
+    pub session_video: FrameStats,
+    /// Bytes of video sent in this session.
+    #[serde(default)]
+    pub video_bytes: u64,
+    /// Backend input of the client is sent with, switched by the GUI and the control socket. The
+    /// client's handler replaces its input device once it notices the change.
+    #[serde(default)]
+    pub input_backend: InputBackend,
+    /// Name of the input device currently used, e.g. uinput.
+    #[serde(default)]
+    pub input_device: Option<String>,
+    /// The video of the client is broadcast to viewers.
+    #[serde(default)]
+    pub broadcasting: bool,
+    /// The client is a viewer watching the broadcast instead of a video of its own.
+    #[serde(default)]
+    pub watching_broadcast: bool,
+    /// Battery levels reported by the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceStatus>,
+}
+
+/// Status of a client, shared between the client's handler, its video thread and the web server.
+pub type SharedClientStatus = Arc<Mutex<ClientStatus>>;
+
+type StatusProvider = Box<dyn Fn() -> InstanceStatus + Send>;
+type ClientsProvider = Box<dyn Fn() -> Vec<SharedClientStatus> + Send>;
+
+struct RegisteredInstance {
+    status: StatusProvider,
+    clients: ClientsProvider,
+    invites: Arc<Invites>,
+}
+
+static INSTANCES: Mutex<BTreeMap<u64, RegisteredInstance>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps an instance registered, dropping the guard unregisters it.
+pub struct InstanceGuard {
+    id: u64,
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        INSTANCES.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Register a running instance of the web server, its status is reported in responses to
+/// [`ControlRequest::Status`], its clients are listed by [`clients`] and invites to it are
+/// created by [`create_invite`].
+pub fn register_instance(
+    status: impl Fn() -> InstanceStatus + Send + 'static,
+    clients: impl Fn() -> Vec<SharedClientStatus> + Send + 'static,
+    invites: Arc<Invites>,
+) -> InstanceGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    INSTANCES.lock().unwrap().insert(
+        id,
+        RegisteredInstance {
+            status: Box::new(status),
+            clients: Box::new(clients),
+            invites,
+        },
+    );
+    InstanceGuard { id }
+}
+
+/// Status of all instances hosted by this process.
+pub fn instance_statuses() -> Vec<InstanceStatus> {
+    INSTANCES
+        .lock()
+        .unwrap()
+        .values()
+        .map(|i| (i.status)())
+        .collect()
+}
+
+/// The clients connected to any of the instances hosted by this process, this lets the GUI
+/// change per client settings.
+pub fn clients() -> Vec<SharedClientStatus> {
+    INSTANCES
+        .lock()
+        .unwrap()
+        .values()
+        .flat_map(|i| (i.clients)())
+        .collect()
+}
+
+/// Create an invite to the instance with the given name and return its url, the name may be
+/// omitted if only one instance is running.
+pub fn create_invite(
+    instance: Option<&str>,
+    options: &InviteOptions,
+) -> Result<String, ControlError> {
+    let instances = INSTANCES.lock().unwrap();
+    let mut matching = instances
+        .values()
+        .map(|i| ((i.status)(), i))
+        .filter(|(status, _)| instance.map_or(true, |name| status.name == name));
+    let Some((status, i)) = matching.next() else {
+        return Err(ControlError(match instance {
+            Some(name) => format!("No instance named {name} is running."),
+            None => "No instance is running.".into(),
+        }));
+    };
+    if matching.next().is_some() {
+        return Err(ControlError(
+            "Several instances are running, choose one by its name.".into(),
+        ));
+    }
+    let Some(token) = i.invites.create(options) else {
+        return Err(ControlError(
+            "An invite has to be usable at least once.".into(),
+        ));
+    };
+    Ok(format!(
+        "{}/invite?token={token}",
+        crate::web::server_url(status.bind_address)
+    ))
+}
+
+/// Invalidate the invites of the instance with the given name or of all instances if None,
+/// returns the number of invites that were still valid.
+pub fn revoke_invites(instance: Option<&str>) -> usize {
+    INSTANCES
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|i| instance.map_or(true, |name| (i.status)().name == name))
+        .map(|i| i.invites.revoke_all())
+        .sum()
+}
+
+/// Switch the input backend of the client with the given address or of all clients if None,
+/// returns the number of clients switched.
+pub fn set_input_backend(backend: InputBackend, client: Option<SocketAddr>) -> usize {
+    clients()
+        .into_iter()
+        .filter(|c| {
+            let mut status = c.lock().unwrap();
+            let matches = client.map_or(true, |addr| status.address == addr);
+            if matches {
+                status.input_backend = backend;
+            }
+            matches
+        })
+        .count()
+}
+
+#[cfg(unix)]
+fn handle_request(request: ControlRequest) -> ControlResponse {
+    match request {
+        ControlRequest::Status => ControlResponse::Status(Status {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            instances: instance_statuses(),
+            permissions: crate::permissions::check(),
+        }),
+        ControlRequest::CreateInvite { instance, options } => {
+            match create_invite(instance.as_deref(), &options) {
+                Ok(url) => ControlResponse::Invite(url),
+                Err(err) => ControlResponse::Error(err.to_string()),
+            }
+        }
+        ControlRequest::RevokeInvites { instance } => {
+            ControlResponse::InvitesRevoked(revoke_invites(instance.as_deref()))
+        }
+        ControlRequest::SetInputBackend { backend, client } => {
+            ControlResponse::InputBackendSet(set_input_backend(backend, client))
+        }
+        ControlRequest::PendingApprovals => {
+            ControlResponse::PendingApprovals(crate::approval::pending())
+        }
+        ControlRequest::DecideApproval { id, decision } => {
+            ControlResponse::ApprovalDecided(crate::approval::decide(id, decision))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn socket_path(port: u16) -> std::io::Result<PathBuf> {
+    crate::private_socket::path(&format!("weylus-{port}.sock"))
+}
+
+#[cfg(unix)]
+pub use unix::{request, ControlServer};
+
+#[cfg(not(unix))]
+pub fn request(_port: u16, _request: &ControlRequest) -> Result<ControlResponse, ControlError> {
+    Err(ControlError(
+        "The control socket is not supported on this platform.".into(),
+    ))
+}
+
+/// The control socket is not supported on this platform, this does nothing.
+#[cfg(not(unix))]
+pub struct ControlServer;
+
+#[cfg(not(unix))]
+impl ControlServer {
+    pub fn start(_port: u16) -> Self {
+        Self
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+    use tokio::net::UnixListener;
+    use tokio::sync::Notify;
+    use tracing::{debug, error, warn};
+
+    use super::{handle_request, socket_path, ControlError, ControlRequest, ControlResponse};
+    use crate::private_socket;
+
+    /// Send a request to the instance of Weylus listening on the given port.
+    pub fn request(port: u16, request: &ControlRequest) -> Result<ControlResponse, ControlError> {
+        let path = socket_path(port)
+            .map_err(|err| ControlError(format!("Failed to locate the control socket: {err}")))?;
+        let mut stream = UnixStream::connect(&path).map_err(|err| {
+            ControlError(format!(
+                "Failed to connect to {}, is Weylus running? ({err})",
+                path.display()
+            ))
+        })?;
+        let mut line = serde_json::to_string(request).unwrap();
+        line.push('\n');
+        stream
+            .write_all(line.as_bytes())
+            .map_err(|err| ControlError(format!("Failed to send request: {err}")))?;
+        let mut response = String::new();
+        BufReader::new(stream)
+            .read_line(&mut response)
+            .map_err(|err| ControlError(format!("Failed to read response: {err}")))?;
+        serde_json::from_str(&response)
+            .map_err(|err| ControlError(format!("Invalid response: {err}")))
+    }
+
+    /// Answers requests on the control socket in a separate thread until dropped.
+    pub struct ControlServer {
+        notify_shutdown: Arc<Notify>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl ControlServer {
+        pub fn start(port: u16) -> Self {
+            let notify_shutdown = Arc::new(Notify::new());
+            let thread = {
+                let notify_shutdown = notify_shutdown.clone();
+                std::thread::spawn(move || {
+                    match tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                    {
+                        Ok(runtime) => runtime.block_on(serve(port, notify_shutdown)),
+                        Err(err) => error!("Failed to start control socket: {err}."),
+                    }
+                })
+            };
+            Self {
+                notify_shutdown,
+                thread: Some(thread),
+            }
+        }
+    }
+
+    impl Drop for ControlServer {
+        fn drop(&mut self) {
+            // notify_one stores a permit, so the shutdown is not missed if the server is not
+            // waiting yet
+            self.notify_shutdown.notify_one();
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    error!("Control socket thread panicked.");
+                }
+            }
+        }
+    }
+
+    async fn serve(port: u16, notify_shutdown: Arc<Notify>) {
+        let path = match socket_path(port) {
+            Ok(path) => path,
+            Err(err) => {
+                warn!("Failed to create control socket: {err}.");
+                return;
+            }
+        };
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                warn!(
+                    "Control socket {} is in use by another instance of Weylus.",
+                    path.display()
+                );
+                return;
+            }
+            // left behind by an instance that did not shut down properly
+            std::fs::remove_file(&path).ok();
+        }
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!("Failed to create control socket {}: {err}.", path.display());
+                return;
+            }
+        };
+        if let Err(err) = private_socket::restrict(&path) {
+            warn!(
+                "Failed to restrict control socket {}: {err}.",
+                path.display()
+            );
+            std::fs::remove_file(&path).ok();
+            return;
+        }
+        debug!("Listening for control requests on {}.", path.display());
+
+        loop {
+            let stream = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        warn!("Failed to accept connection on control socket: {err}.");
+                        continue;
+                    }
+                },
+                _ = notify_shutdown.notified() => break,
+            };
+            if let Err(err) = stream
+                .peer_cred()
+                .and_then(|cred| private_socket::check_peer(cred.uid()))
+            {
+                warn!("Refused connection on control socket: {err}.");
+                continue;
+            }
+            tokio::spawn(async move {
+                let (rx, mut tx) = stream.into_split();
+                let mut lines = AsyncBufReader::new(rx).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let response = match serde_json::from_str(&line) {
+                        Ok(request) => handle_request(request),
+                        Err(err) => ControlResponse::Error(format!("Invalid request: {err}")),
+                    };
+                    let mut line = serde_json::to_string(&response).unwrap();
+                    line.push('\n');
+                    if tx.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        std::fs::remove_file(&path).ok();
+    }
+}
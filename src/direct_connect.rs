@@ -0,0 +1,560 @@
+//! Connections between a tablet and this computer without a router in between: USB tethering, a
+//! hotspot opened by either device or a cable with link-local addresses. The tablet is sure to
+//! reach the computer through such a link, so its address is preferred for the url shown to users.
+//! On Linux a minimal DHCP server can hand out addresses on a network the computer set up itself,
+//! e.g. an ad hoc Wi-Fi network or a plain cable.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// How a link to the tablet was set up, links are preferred in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkKind {
+    UsbTethering,
+    Hotspot,
+    LinkLocal,
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LinkKind::UsbTethering => "USB tethering",
+            LinkKind::Hotspot => "hotspot",
+            LinkKind::LinkLocal => "link-local network",
+        })
+    }
+}
+
+/// Networks set up by common tethering and hotspot implementations.
+const KNOWN_NETWORKS: &[(Ipv4Addr, u8, LinkKind)] = &[
+    // USB tethering of Android
+    (Ipv4Addr::new(192, 168, 42, 0), 24, LinkKind::UsbTethering),
+    // personal hotspot of iPhones and iPads, via USB or Wi-Fi
+    (Ipv4Addr::new(172, 20, 10, 0), 28, LinkKind::Hotspot),
+    // Wi-Fi hotspot of Android
+    (Ipv4Addr::new(192, 168, 43, 0), 24, LinkKind::Hotspot),
+    // hotspots and shared connections of NetworkManager
+    (Ipv4Addr::new(10, 42, 0, 0), 16, LinkKind::Hotspot),
+    // mobile hotspot of Windows
+    (Ipv4Addr::new(192, 168, 137, 0), 24, LinkKind::Hotspot),
+];
+
+fn in_network(ip: Ipv4Addr, network: Ipv4Addr, prefix: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    u32::from(ip) & mask == u32::from(network) & mask
+}
+
+/// The kind of link the address of the interface belongs to, None for ordinary networks. IPv6
+/// link-local addresses are left out, browsers can not open urls with their zone id.
+pub fn classify(interface: &str, ip: IpAddr) -> Option<LinkKind> {
+    let IpAddr::V4(ip) = ip else {
+        return None;
+    };
+    if ip.is_link_local() {
+        return Some(LinkKind::LinkLocal);
+    }
+    if ["usb", "rndis"].iter().any(|p| interface.starts_with(p)) {
+        return Some(LinkKind::UsbTethering);
+    }
+    KNOWN_NETWORKS
+        .iter()
+        .find(|(network, prefix, _)| in_network(ip, *network, *prefix))
+        .map(|(_, _, kind)| *kind)
+}
+
+#[cfg(target_os = "linux")]
+pub use dhcp::serve_dhcp;
+
+#[cfg(target_os = "linux")]
+mod dhcp {
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::ffi::CString;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::os::raw::{c_char, c_int};
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    use pnet_datalink as datalink;
+    use tracing::{debug, info, warn};
+
+    use super::in_network;
+    use crate::cerror::CError;
+
+    extern "C" {
+        fn bind_to_device(fd: c_int, name: *const c_char, err: *mut CError);
+    }
+
+    const SERVER_PORT: u16 = 67;
+    const CLIENT_PORT: u16 = 68;
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+    /// Size of the fixed part of a message, options follow.
+    const HEADER_SIZE: usize = 240;
+    /// Replies are padded to the minimum size of a BOOTP message.
+    const MIN_SIZE: usize = 300;
+    const LEASE_SECONDS: u32 = 3600;
+
+    const OPTION_SUBNET_MASK: u8 = 1;
+    const OPTION_REQUESTED_IP: u8 = 50;
+    const OPTION_LEASE_TIME: u8 = 51;
+    const OPTION_MESSAGE_TYPE: u8 = 53;
+    const OPTION_SERVER_ID: u8 = 54;
+    const OPTION_END: u8 = 255;
+
+    const DISCOVER: u8 = 1;
+    const OFFER: u8 = 2;
+    const REQUEST: u8 = 3;
+    const ACK: u8 = 5;
+    const NAK: u8 = 6;
+
+    /// Hands out the addresses of the network of the interface, the computer keeps its own. No
+    /// router or DNS server is announced, so tablets keep using their mobile data for everything
+    /// else.
+    struct DhcpServer {
+        ip: Ipv4Addr,
+        prefix: u8,
+        /// Lease of each client by hardware address.
+        leases: HashMap<[u8; 16], Lease>,
+    }
+
+    struct Lease {
+        ip: Ipv4Addr,
+        expires: Instant,
+    }
+
+    /// The parts of a message of a client the server cares about.
+    struct ClientMessage<'a> {
+        message_type: u8,
+        hardware_address: [u8; 16],
+        requested_ip: Option<Ipv4Addr>,
+        server_id: Option<Ipv4Addr>,
+        packet: &'a [u8],
+    }
+
+    fn parse(packet: &[u8]) -> Option<ClientMessage<'_>> {
+        // only requests of clients with the magic cookie of DHCP
+        if packet.len() < HEADER_SIZE || packet[0] != 1 || packet[236..240] != MAGIC_COOKIE {
+            return None;
+        }
+        let mut message_type = None;
+        let mut requested_ip = None;
+        let mut server_id = None;
+        let mut options = &packet[HEADER_SIZE..];
+        while let [code, rest @ ..] = options {
+            match *code {
+                0 => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let [len, rest @ ..] = rest else {
+                break;
+            };
+            let data = rest.get(..*len as usize)?;
+            let ip = <[u8; 4]>::try_from(data).ok().map(Ipv4Addr::from);
+            match *code {
+                OPTION_MESSAGE_TYPE => message_type = data.first().copied(),
+                OPTION_REQUESTED_IP => requested_ip = ip,
+                OPTION_SERVER_ID => server_id = ip,
+                _ => {}
+            }
+            options = &rest[*len as usize..];
+        }
+        Some(ClientMessage {
+            message_type: message_type?,
+            hardware_address: packet[28..44].try_into().unwrap(),
+            requested_ip,
+            server_id,
+            packet,
+        })
+    }
+
+    impl DhcpServer {
+        fn mask(&self) -> u32 {
+            u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+        }
+
+        /// Whether the address may be handed to the client, expired leases have to be reclaimed
+        /// first.
+        fn is_available(&self, ip: Ipv4Addr, client: &[u8; 16]) -> bool {
+            let host = u32::from(ip) & !self.mask();
+            in_network(ip, self.ip, self.prefix)
+                && ip != self.ip
+                && host != 0
+                && host != !self.mask()
+                && self
+                    .leases
+                    .iter()
+                    .all(|(c, lease)| lease.ip != ip || c == client)
+        }
+
+        /// The address of the client, the one it asks for if it is free, e.g. after Weylus has
+        /// been restarted. The lease is renewed, addresses of other clients whose leases expired
+        /// are handed out again.
+        fn lease(
+            &mut self,
+            client: [u8; 16],
+            requested: Option<Ipv4Addr>,
+            now: Instant,
+        ) -> Option<Ipv4Addr> {
+            self.leases
+                .retain(|c, lease| lease.expires > now || *c == client);
+            let expires = now + Duration::from_secs(LEASE_SECONDS.into());
+            if let Some(lease) = self.leases.get_mut(&client) {
+                lease.expires = expires;
+                return Some(lease.ip);
+            }
+            let network = u32::from(self.ip) & self.mask();
+            let ip = requested
+                .filter(|ip| self.is_available(*ip, &client))
+                .or_else(|| {
+                    (network + 1..network | !self.mask())
+                        .map(Ipv4Addr::from)
+                        .find(|ip| self.is_available(*ip, &client))
+                })?;
+            self.leases.insert(client, Lease { ip, expires });
+            Some(ip)
+        }
+
+        /// The reply to a message of a client, if any.
+        fn handle(&mut self, packet: &[u8], now: Instant) -> Option<Vec<u8>> {
+            let message = parse(packet)?;
+            let requested = message
+                .requested_ip
+                // clients renewing their lease send their address in ciaddr
+                .or_else(|| Some(Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).ok()?)))
+                .filter(|ip| !ip.is_unspecified());
+            match message.message_type {
+                DISCOVER => {
+                    let Some(ip) = self.lease(message.hardware_address, requested, now) else {
+                        warn!("No addresses left to hand out via DHCP.");
+                        return None;
+                    };
+                    Some(self.reply(&message, OFFER, ip))
+                }
+                REQUEST => {
+                    // the client chose another server
+                    if message.server_id.is_some_and(|id| id != self.ip) {
+                        return None;
+                    }
+                    match self.lease(message.hardware_address, requested, now) {
+                        Some(ip) if Some(ip) == requested => {
+                            info!("Handed out {ip} via DHCP.");
+                            Some(self.reply(&message, ACK, ip))
+                        }
+                        _ => Some(self.reply(&message, NAK, Ipv4Addr::UNSPECIFIED)),
+                    }
+                }
+                t => {
+                    debug!("Ignoring DHCP message of type {t}.");
+                    None
+                }
+            }
+        }
+
+        fn reply(&self, message: &ClientMessage, message_type: u8, ip: Ipv4Addr) -> Vec<u8> {
+            let request = message.packet;
+            let mut reply = vec![0; HEADER_SIZE];
+            reply[0] = 2;
+            // hardware type and length
+            reply[1..3].copy_from_slice(&request[1..3]);
+            // transaction id
+            reply[4..8].copy_from_slice(&request[4..8]);
+            // flags
+            reply[10..12].copy_from_slice(&request[10..12]);
+            reply[16..20].copy_from_slice(&ip.octets());
+            reply[20..24].copy_from_slice(&self.ip.octets());
+            reply[28..44].copy_from_slice(&message.hardware_address);
+            reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+            reply.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+            reply.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+            reply.extend_from_slice(&self.ip.octets());
+            if message_type != NAK {
+                reply.extend_from_slice(&[OPTION_LEASE_TIME, 4]);
+                reply.extend_from_slice(&LEASE_SECONDS.to_be_bytes());
+                reply.extend_from_slice(&[OPTION_SUBNET_MASK, 4]);
+                reply.extend_from_slice(&self.mask().to_be_bytes());
+            }
+            reply.push(OPTION_END);
+            reply.resize(reply.len().max(MIN_SIZE), 0);
+            reply
+        }
+    }
+
+    /// The IPv4 address of the interface and the length of its network prefix.
+    fn interface_network(interface: &str) -> Result<(Ipv4Addr, u8), Box<dyn Error>> {
+        let iface = datalink::interfaces()
+            .into_iter()
+            .find(|iface| iface.name == interface)
+            .ok_or_else(|| format!("There is no network interface named {interface}."))?;
+        iface
+            .ips
+            .iter()
+            .find_map(|ipnetw| match ipnetw.ip() {
+                std::net::IpAddr::V4(ip) if ipnetw.prefix() <= 30 => Some((ip, ipnetw.prefix())),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                format!(
+                    "{interface} has no IPv4 network to hand out addresses from, assign an \
+                     address like 10.43.0.1/24 to it first."
+                )
+                .into()
+            })
+    }
+
+    /// Hand out addresses on the given interface in the background. Binding to the port of DHCP
+    /// servers requires root or CAP_NET_BIND_SERVICE.
+    pub fn serve_dhcp(interface: &str) -> Result<(), Box<dyn Error>> {
+        let (ip, prefix) = interface_network(interface)?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SERVER_PORT))?;
+        let name = CString::new(interface)?;
+        let mut err = CError::new();
+        unsafe { bind_to_device(socket.as_raw_fd(), name.as_ptr(), &mut err) };
+        if err.is_err() {
+            return Err(err.into());
+        }
+        socket.set_broadcast(true)?;
+        info!("Handing out addresses of {ip}/{prefix} on {interface} via DHCP.");
+        let mut server = DhcpServer {
+            ip,
+            prefix,
+            leases: HashMap::new(),
+        };
+        std::thread::spawn(move || {
+            let mut buf = [0; 1500];
+            loop {
+                let n = match socket.recv(&mut buf) {
+                    Ok(n) => n,
+                    Err(err) => {
+                        warn!("Failed to receive DHCP message, stopping DHCP server: {err}");
+                        return;
+                    }
+                };
+                let Some(reply) = server.handle(&buf[..n], Instant::now()) else {
+                    continue;
+                };
+                // the client has no address yet
+                if let Err(err) = socket.send_to(&reply, (Ipv4Addr::BROADCAST, CLIENT_PORT)) {
+                    warn!("Failed to send DHCP reply: {err}");
+                }
+            }
+        });
+        Ok(())
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SERVER: Ipv4Addr = Ipv4Addr::new(10, 43, 0, 1);
+
+        fn server(prefix: u8) -> DhcpServer {
+            DhcpServer {
+                ip: SERVER,
+                prefix,
+                leases: HashMap::new(),
+            }
+        }
+
+        fn hardware_address(client: u8) -> [u8; 16] {
+            let mut address = [0; 16];
+            address[..6].copy_from_slice(&[2, 0, 0, 0, 0, client]);
+            address
+        }
+
+        /// A message of the client with the given options after its type.
+        fn message(client: u8, message_type: u8, options: &[u8]) -> Vec<u8> {
+            let mut packet = vec![0; HEADER_SIZE];
+            packet[0] = 1;
+            packet[1] = 1;
+            packet[2] = 6;
+            packet[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, client]);
+            packet[28..44].copy_from_slice(&hardware_address(client));
+            packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+            packet.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+            packet.extend_from_slice(options);
+            packet.push(OPTION_END);
+            packet
+        }
+
+        fn requesting(ip: Ipv4Addr) -> Vec<u8> {
+            let mut option = vec![OPTION_REQUESTED_IP, 4];
+            option.extend_from_slice(&ip.octets());
+            option
+        }
+
+        /// The type of the reply and the address it hands out.
+        fn answer(reply: &[u8]) -> (u8, Ipv4Addr) {
+            assert!(reply.len() >= MIN_SIZE);
+            assert_eq!(reply[0], 2);
+            assert_eq!(reply[236..240], MAGIC_COOKIE);
+            assert_eq!(
+                reply[HEADER_SIZE..HEADER_SIZE + 2],
+                [OPTION_MESSAGE_TYPE, 1]
+            );
+            let ip: [u8; 4] = reply[16..20].try_into().unwrap();
+            (reply[HEADER_SIZE + 2], ip.into())
+        }
+
+        /// Discover and request an address like a client does, returns the address acknowledged.
+        fn join(server: &mut DhcpServer, client: u8, now: Instant) -> Option<Ipv4Addr> {
+            let offer = server.handle(&message(client, DISCOVER, &[]), now)?;
+            let (message_type, ip) = answer(&offer);
+            assert_eq!(message_type, OFFER);
+            let reply = server
+                .handle(&message(client, REQUEST, &requesting(ip)), now)
+                .unwrap();
+            assert_eq!(answer(&reply), (ACK, ip));
+            Some(ip)
+        }
+
+        #[test]
+        fn hands_out_addresses() {
+            let mut server = server(24);
+            let now = Instant::now();
+            let offer = server.handle(&message(1, DISCOVER, &[]), now).unwrap();
+            assert_eq!(answer(&offer), (OFFER, Ipv4Addr::new(10, 43, 0, 2)));
+            // transaction id and hardware address are echoed
+            assert_eq!(offer[4..8], [0xde, 0xad, 0xbe, 1]);
+            assert_eq!(offer[28..44], hardware_address(1));
+            assert_eq!(join(&mut server, 1, now), Some(Ipv4Addr::new(10, 43, 0, 2)));
+            assert_eq!(join(&mut server, 2, now), Some(Ipv4Addr::new(10, 43, 0, 3)));
+            // the same client keeps its address
+            assert_eq!(join(&mut server, 1, now), Some(Ipv4Addr::new(10, 43, 0, 2)));
+        }
+
+        #[test]
+        fn requested_address() {
+            let mut server = server(24);
+            let now = Instant::now();
+            // e.g. after Weylus has been restarted
+            let wanted = Ipv4Addr::new(10, 43, 0, 77);
+            let reply = server
+                .handle(&message(1, REQUEST, &requesting(wanted)), now)
+                .unwrap();
+            assert_eq!(answer(&reply), (ACK, wanted));
+            // renewing with the address in ciaddr
+            let mut renew = message(1, REQUEST, &[]);
+            renew[12..16].copy_from_slice(&wanted.octets());
+            assert_eq!(answer(&server.handle(&renew, now).unwrap()), (ACK, wanted));
+            // taken by another client, the server itself or the broadcast address
+            for taken in [wanted, SERVER, Ipv4Addr::new(10, 43, 0, 255)] {
+                let offer = server
+                    .handle(&message(2, DISCOVER, &requesting(taken)), now)
+                    .unwrap();
+                assert_eq!(answer(&offer), (OFFER, Ipv4Addr::new(10, 43, 0, 2)));
+            }
+        }
+
+        #[test]
+        fn nak_on_foreign_address() {
+            let mut server = server(24);
+            let now = Instant::now();
+            let foreign = Ipv4Addr::new(192, 168, 1, 50);
+            let reply = server
+                .handle(&message(1, REQUEST, &requesting(foreign)), now)
+                .unwrap();
+            assert_eq!(answer(&reply), (NAK, Ipv4Addr::UNSPECIFIED));
+            // no lease time or subnet mask in a NAK
+            assert_eq!(
+                reply[HEADER_SIZE + 3..HEADER_SIZE + 9],
+                [OPTION_SERVER_ID, 4, 10, 43, 0, 1]
+            );
+            assert_eq!(reply[HEADER_SIZE + 9], OPTION_END);
+            // a request without any address
+            let reply = server.handle(&message(2, REQUEST, &[]), now).unwrap();
+            assert_eq!(answer(&reply).0, NAK);
+        }
+
+        #[test]
+        fn ignores_requests_to_other_servers() {
+            let mut server = server(24);
+            let mut options = requesting(Ipv4Addr::new(10, 43, 0, 2));
+            options.extend_from_slice(&[OPTION_SERVER_ID, 4, 10, 43, 0, 254]);
+            let request = message(1, REQUEST, &options);
+            assert!(server.handle(&request, Instant::now()).is_none());
+        }
+
+        #[test]
+        fn pool_exhaustion_and_expiry() {
+            // only 10.43.0.2 is left besides the address of the server
+            let mut server = server(30);
+            let now = Instant::now();
+            assert_eq!(join(&mut server, 1, now), Some(Ipv4Addr::new(10, 43, 0, 2)));
+            assert!(server.handle(&message(2, DISCOVER, &[]), now).is_none());
+            let reply = server
+                .handle(
+                    &message(2, REQUEST, &requesting(Ipv4Addr::new(10, 43, 0, 2))),
+                    now,
+                )
+                .unwrap();
+            assert_eq!(answer(&reply).0, NAK);
+            // renewing keeps the lease
+            let later = now + Duration::from_secs(LEASE_SECONDS.into()) / 2;
+            assert_eq!(
+                join(&mut server, 1, later),
+                Some(Ipv4Addr::new(10, 43, 0, 2))
+            );
+            let expired = now + Duration::from_secs(LEASE_SECONDS.into());
+            assert!(server.handle(&message(2, DISCOVER, &[]), expired).is_none());
+            // once the lease expired the address goes to the next client
+            let expired = later + Duration::from_secs(LEASE_SECONDS.into());
+            assert_eq!(
+                join(&mut server, 2, expired),
+                Some(Ipv4Addr::new(10, 43, 0, 2))
+            );
+            assert!(server.handle(&message(1, DISCOVER, &[]), expired).is_none());
+        }
+
+        #[test]
+        fn padded_options() {
+            let mut server = server(24);
+            let mut packet = message(1, DISCOVER, &[]);
+            // pad options before, between and after the others
+            packet.truncate(HEADER_SIZE);
+            packet.extend_from_slice(&[0, 0, OPTION_MESSAGE_TYPE, 1, DISCOVER, 0]);
+            packet.extend_from_slice(&requesting(Ipv4Addr::new(10, 43, 0, 9)));
+            packet.extend_from_slice(&[0, OPTION_END, 0, 0, 0]);
+            let offer = server.handle(&packet, Instant::now()).unwrap();
+            assert_eq!(answer(&offer), (OFFER, Ipv4Addr::new(10, 43, 0, 9)));
+            // options after the end are ignored
+            let mut packet = message(2, DISCOVER, &[]);
+            packet.extend_from_slice(&[OPTION_REQUESTED_IP, 200]);
+            assert!(server.handle(&packet, Instant::now()).is_some());
+        }
+
+        #[test]
+        fn invalid_messages() {
+            let mut server = server(24);
+            let now = Instant::now();
+            let discover = message(1, DISCOVER, &[]);
+            // truncated header
+            assert!(server.handle(&discover[..HEADER_SIZE - 1], now).is_none());
+            assert!(server.handle(&[], now).is_none());
+            // truncated option
+            let mut truncated = discover[..HEADER_SIZE].to_vec();
+            truncated.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1]);
+            assert!(server.handle(&truncated, now).is_none());
+            let mut truncated = discover[..HEADER_SIZE + 3].to_vec();
+            truncated.extend_from_slice(&[OPTION_REQUESTED_IP, 4, 10, 43]);
+            assert!(server.handle(&truncated, now).is_none());
+            // no message type
+            let mut untyped = discover[..HEADER_SIZE].to_vec();
+            untyped.push(OPTION_END);
+            assert!(server.handle(&untyped, now).is_none());
+            // a reply instead of a request
+            let mut reply = discover.clone();
+            reply[0] = 2;
+            assert!(server.handle(&reply, now).is_none());
+            // BOOTP without the magic cookie of DHCP
+            let mut bootp = discover.clone();
+            bootp[236..240].copy_from_slice(&[0; 4]);
+            assert!(server.handle(&bootp, now).is_none());
+            // messages the server does not answer
+            assert!(server.handle(&message(1, 7, &[]), now).is_none());
+            assert!(server.leases.is_empty());
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+const SERVICE_TYPE: &str = "_weylus._tcp.local.";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Peer {
+    pub name: String,
+    pub url: String,
+}
+
+/// Announces this instance of Weylus via mDNS and keeps track of other instances found on the
+/// local network.
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    fullname: String,
+    peers: Arc<Mutex<HashMap<String, Peer>>>,
+}
+
+impl Discovery {
+    pub fn new(port: u16) -> Result<Self, mdns_sd::Error> {
+        let daemon = ServiceDaemon::new()?;
+        let instance_name = gethostname::gethostname().to_string_lossy().to_string();
+        let host_name = format!("{}.local.", instance_name);
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance_name,
+            &host_name,
+            "",
+            port,
+            HashMap::<String, String>::new(),
+        )?
+        .enable_addr_auto();
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info)?;
+
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        {
+            let peers = peers.clone();
+            let fullname = fullname.clone();
+            std::thread::spawn(move || {
+                while let Ok(event) = receiver.recv() {
+                    match event {
+                        ServiceEvent::ServiceResolved(info) => {
+                            if info.get_fullname() == fullname {
+                                continue;
+                            }
+                            // prefer IPv4 addresses, they are more likely to be reachable from
+                            // the browser
+                            let ip = info
+                                .get_addresses()
+                                .iter()
+                                .map(|ip| IpAddr::from(*ip))
+                                .min_by_key(|ip| ip.is_ipv6());
+                            if let Some(ip) = ip {
+                                let name = info
+                                    .get_fullname()
+                                    .trim_end_matches(SERVICE_TYPE)
+                                    .trim_end_matches('.')
+                                    .to_string();
+                                let url =
+                                    format!("http://{}/", SocketAddr::new(ip, info.get_port()));
+                                debug!(name = %name, url = %url, "Found Weylus instance.");
+                                peers
+                                    .lock()
+                                    .unwrap()
+                                    .insert(info.get_fullname().to_string(), Peer { name, url });
+                            }
+                        }
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            debug!(fullname = %fullname, "Weylus instance disappeared.");
+                            peers.lock().unwrap().remove(&fullname);
+                        }
+                        _ => (),
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            daemon,
+            fullname,
+            peers,
+        })
+    }
+
+    pub fn peers(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = self.peers.lock().unwrap().values().cloned().collect();
+        peers.sort_by(|a, b| a.name.cmp(&b.name));
+        peers
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        if let Err(err) = self.daemon.unregister(&self.fullname) {
+            warn!("Failed to unregister mDNS service: {err}.");
+        }
+        if let Err(err) = self.daemon.shutdown() {
+            warn!("Failed to shut down mDNS daemon: {err}.");
+        }
+    }
+}
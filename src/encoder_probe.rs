@@ -0,0 +1,354 @@
+//! Benchmark the available video encoders once per machine and remember how fast they are at the
+//! resolution of the host. The results are used to offer clients a frame rate the hardware can
+//! sustain and to warn if a client asks for more.
+//!
+//! The encoders are configured for constant quality, the bitrate they produce is measured for
+//! reference only.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::capturable::get_capturables;
+use crate::h264_level::H264Level;
+use crate::video::{EncoderOptions, PixelProvider, VideoEncoder};
+
+/// Number of frames encoded per encoder.
+const PROBE_FRAMES: usize = 60;
+
+/// Frame rates offered to clients, the highest one the default encoder sustains with some
+/// headroom is picked. This never exceeds the frame rate clients used by default before.
+const FRAME_RATES: [u32; 4] = [30, 24, 15, 10];
+
+/// Share of the measured frame rate that is considered sustainable, capturing and sending frames
+/// takes time as well.
+const HEADROOM: f64 = 0.75;
+
+/// Size of the video used if the size of the screen can not be determined.
+const FALLBACK_SIZE: (usize, usize) = (1920, 1080);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    /// Name of the ffmpeg encoder, e.g. libx264 or h264_vaapi.
+    pub encoder: String,
+    /// Frames per second the encoder managed to encode.
+    pub fps: f64,
+    /// Average size of an encoded frame in bytes.
+    pub bytes_per_frame: f64,
+}
+
+impl ProbeResult {
+    /// Bitrate in Mbit/s at the given frame rate.
+    pub fn mbit_per_second(&self, frame_rate: f64) -> f64 {
+        self.bytes_per_frame * 8.0 * frame_rate / 1e6
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EncoderProbe {
+    pub width: usize,
+    pub height: usize,
+    /// Hardware encoders that were enabled, the encoders are probed again if this changes.
+    pub hardware: Vec<String>,
+    /// The first result is the encoder clients get by default.
+    pub results: Vec<ProbeResult>,
+}
+
+impl EncoderProbe {
+    /// Frame rate the encoder sustains for a video of the given size, None if it has not been
+    /// probed.
+    pub fn sustainable_frame_rate(
+        &self,
+        encoder: &str,
+        width: usize,
+        height: usize,
+    ) -> Option<f64> {
+        let result = self.results.iter().find(|r| r.encoder == encoder)?;
+        // the time it takes to encode a frame is roughly proportional to the number of pixels
+        let pixels = (width * height).max(1) as f64;
+        Some(result.fps * HEADROOM * (self.width * self.height) as f64 / pixels)
+    }
+
+    /// Frame rate offered to clients by default.
+    pub fn default_frame_rate(&self) -> u32 {
+        let Some(result) = self.results.first() else {
+            return FRAME_RATES[0];
+        };
+        let sustainable = result.fps * HEADROOM;
+        FRAME_RATES
+            .into_iter()
+            .find(|&r| r as f64 <= sustainable)
+            .unwrap_or(FRAME_RATES[FRAME_RATES.len() - 1])
+    }
+}
+
+static PROBE: RwLock<Option<EncoderProbe>> = RwLock::new(None);
+/// Set while the encoders are being probed, starting Weylus again meanwhile does not start
+/// another probe.
+static PROBING: AtomicBool = AtomicBool::new(false);
+
+/// Frame rate offered to clients by default.
+pub fn default_frame_rate() -> u32 {
+    PROBE
+        .read()
+        .unwrap()
+        .as_ref()
+        .map_or(FRAME_RATES[0], EncoderProbe::default_frame_rate)
+}
+
+/// Warn if the encoder is known to not sustain the frame rate for a video of the given size.
+pub fn check_frame_rate(encoder: &str, width: usize, height: usize, frame_rate: f64) {
+    let Some(probe) = PROBE.read().unwrap().clone() else {
+        return;
+    };
+    let Some(sustainable) = probe.sustainable_frame_rate(encoder, width, height) else {
+        return;
+    };
+    if frame_rate > sustainable {
+        warn!(
+            encoder,
+            requested = %format_args!("{frame_rate:.0}"),
+            sustainable = %format_args!("{sustainable:.0}"),
+            "The video encoder can not sustain the requested frame rate at {width}x{height}, \
+            expect dropped frames."
+        );
+    }
+}
+
+fn probe_path() -> Option<PathBuf> {
+    let mut path = dirs::cache_dir()?;
+    path.push("weylus");
+    path.push("encoder_probe.toml");
+    Some(path)
+}
+
+fn load() -> Option<EncoderProbe> {
+    let s = fs::read_to_string(probe_path()?).ok()?;
+    match toml::from_str(&s) {
+        Ok(probe) => Some(probe),
+        Err(err) => {
+            warn!("Failed to read results of the encoder probe: {err}");
+            None
+        }
+    }
+}
+
+fn store(probe: &EncoderProbe) {
+    let Some(path) = probe_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        if let Err(err) = fs::create_dir_all(dir) {
+            warn!("Failed to create directory for the results of the encoder probe: {err}");
+            return;
+        }
+    }
+    let s = toml::to_string_pretty(probe).expect("Failed to encode results of encoder probe.");
+    if let Err(err) = fs::write(path, s) {
+        warn!("Failed to store results of the encoder probe: {err}");
+    }
+}
+
+/// The options each enabled encoder is probed with, in the order the encoders are tried when
+/// opening a video, the software encoder comes last.
+pub fn variants(options: EncoderOptions) -> Vec<(&'static str, EncoderOptions)> {
+    let none = EncoderOptions {
+        try_vaapi: false,
+        try_nvenc: false,
+        try_videotoolbox: false,
+        try_mediafoundation: false,
+        ..options
+    };
+    let mut variants = Vec::new();
+    if options.try_vaapi {
+        let o = EncoderOptions {
+            try_vaapi: true,
+            ..none
+        };
+        variants.push(("vaapi", o));
+    }
+    if options.try_mediafoundation {
+        let o = EncoderOptions {
+            try_mediafoundation: true,
+            ..none
+        };
+        variants.push(("mediafoundation", o));
+    }
+    if options.try_nvenc {
+        let o = EncoderOptions {
+            try_nvenc: true,
+            ..none
+        };
+        variants.push(("nvenc", o));
+    }
+    if options.try_videotoolbox {
+        let o = EncoderOptions {
+            try_videotoolbox: true,
+            ..none
+        };
+        variants.push(("videotoolbox", o));
+    }
+    variants.push(("software", none));
+    variants
+}
+
+/// Size of the first screen, scaled down to fit the default H.264 level like the video sent to
+/// clients.
+fn host_size() -> (usize, usize) {
+    let capturables = get_capturables(
+        // probing must not ask the user to pick a screen via the desktop portal
+        #[cfg(target_os = "linux")]
+        false,
+        #[cfg(target_os = "linux")]
+        false,
+        #[cfg(target_os = "linux")]
+        Default::default(),
+    );
+    let Some(capturable) = capturables.into_iter().next() else {
+        return FALLBACK_SIZE;
+    };
+    let size = capturable
+        .recorder(false)
+        .and_then(|mut r| r.capture().map(|p| p.size()));
+    let (width, height) = match size {
+        Ok(size) => size,
+        Err(err) => {
+            debug!("Failed to determine the size of the screen: {err}");
+            return FALLBACK_SIZE;
+        }
+    };
+    let scale = H264Level::default_max().scale_to_fit(width, height);
+    if scale < 1.0 {
+        (
+            (width as f64 * scale) as usize,
+            (height as f64 * scale) as usize,
+        )
+    } else {
+        (width, height)
+    }
+}
+
+/// Fill the frame with something resembling a desktop: a flat background with a window moving
+/// across it.
+fn draw_frame(buf: &mut [u8], width: usize, height: usize, i: usize) {
+    let w = width / 4;
+    let h = height / 4;
+    let x0 = i * (width - w) / PROBE_FRAMES;
+    let y0 = height / 3;
+    for (y, row) in buf.chunks_exact_mut(width * 4).enumerate() {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let inside = (x0..x0 + w).contains(&x) && (y0..y0 + h).contains(&y);
+            let value = if inside {
+                // some text-like stripes in the window
+                if (y - y0) % 16 < 2 {
+                    0x20
+                } else {
+                    0xf0
+                }
+            } else {
+                (0x40 + y * 0x40 / height) as u8
+            };
+            pixel.copy_from_slice(&[value, value, value, 0]);
+        }
+    }
+}
+
+fn probe_encoder(width: usize, height: usize, options: EncoderOptions) -> Option<ProbeResult> {
+    let bytes = Arc::new(AtomicUsize::new(0));
+    let mut encoder = {
+        let bytes = bytes.clone();
+        let write_data = move |data: &[u8]| {
+            bytes.fetch_add(data.len(), Ordering::Relaxed);
+        };
+        match VideoEncoder::new(width, height, width, height, write_data, options) {
+            Ok(encoder) => encoder,
+            Err(err) => {
+                debug!("Failed to open video encoder for probing: {err}");
+                return None;
+            }
+        }
+    };
+    let name = encoder.name()?;
+    // the header of the video does not count
+    bytes.store(0, Ordering::Relaxed);
+    let mut buf = vec![0u8; width * height * 4];
+    let mut elapsed = Duration::ZERO;
+    for i in 0..PROBE_FRAMES {
+        draw_frame(&mut buf, width, height, i);
+        let start = Instant::now();
+        encoder.encode(PixelProvider::BGR0(width, height, &buf));
+        elapsed += start.elapsed();
+    }
+    let start = Instant::now();
+    encoder.flush();
+    let elapsed = (elapsed + start.elapsed()).as_secs_f64();
+    Some(ProbeResult {
+        encoder: name,
+        fps: PROBE_FRAMES as f64 / elapsed,
+        bytes_per_frame: bytes.load(Ordering::Relaxed) as f64 / PROBE_FRAMES as f64,
+    })
+}
+
+fn probe(options: EncoderOptions, hardware: Vec<String>) -> EncoderProbe {
+    let (width, height) = host_size();
+    info!("Probing video encoders at {width}x{height}, this takes a moment.");
+    let mut results: Vec<ProbeResult> = Vec::new();
+    for (kind, options) in variants(options) {
+        let Some(result) = probe_encoder(width, height, options) else {
+            continue;
+        };
+        // hardware encoders that are not available fall back to the software encoder
+        if results.iter().any(|r| r.encoder == result.encoder) {
+            debug!("No {kind} encoder available.");
+            continue;
+        }
+        info!(
+            encoder = result.encoder,
+            fps = %format_args!("{:.0}", result.fps),
+            mbit_per_second = %format_args!("{:.1}", result.mbit_per_second(30.0)),
+            "Probed video encoder."
+        );
+        results.push(result);
+    }
+    EncoderProbe {
+        width,
+        height,
+        hardware,
+        results,
+    }
+}
+
+/// Load the results of an earlier probe or probe the encoders in the background if there are
+/// none for the current configuration.
+pub fn start(options: EncoderOptions) {
+    let hardware: Vec<String> = variants(options)
+        .into_iter()
+        .map(|(kind, _)| kind.to_string())
+        .filter(|kind| kind != "software")
+        .collect();
+    if let Some(probe) = load() {
+        if probe.hardware == hardware {
+            debug!("Using results of earlier encoder probe.");
+            *PROBE.write().unwrap() = Some(probe);
+            return;
+        }
+    }
+    if PROBING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    std::thread::spawn(move || {
+        let probe = probe(options, hardware);
+        info!(
+            frame_rate = probe.default_frame_rate(),
+            "Finished probing video encoders."
+        );
+        store(&probe);
+        *PROBE.write().unwrap() = Some(probe);
+        PROBING.store(false, Ordering::Relaxed);
+    });
+}
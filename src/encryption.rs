@@ -0,0 +1,386 @@
+//! Encryption of the websocket for deployments without TLS. Client and server agree on keys via
+//! SPAKE2 (RFC 9382) over edwards25519 using the access code as password, so the access code
+//! never leaves the client and eavesdroppers can neither read the stream nor guess the access code
+//! offline. Afterwards every message is sealed with ChaCha20-Poly1305.
+//!
+//! The handshake:
+//! 1. The client sends `pA = x*B + w*M` with a random scalar `x` and `w` derived from the access
+//!    code.
+//! 2. The server answers with `pB = y*B + w*N` followed by its confirmation, which lets the client
+//!    detect a wrong access code.
+//! 3. The client answers with its confirmation, which lets the server detect a wrong access code.
+//!
+//! Both derive `K = 8*x*(pB - w*N) = 8*y*(pA - w*M)` and hash the transcript
+//! `TT = len(A) || A || len(B) || B || len(pA) || pA || len(pB) || pB || len(K) || K || len(w) || w`
+//! with the fixed identities `A` and `B` and 8 byte little endian lengths to `Ke || Ka`. As in
+//! RFC 9382 HKDF of `Ka` gives the keys of the confirmations, which are HMACs of `TT`, HKDF of `Ke`
+//! gives a key for each direction. `M` and `N` are hashes of labels, not the points of RFC 9382.
+//!
+//! Nonces are counters, one per direction, messages have to be opened in the order they were
+//! sealed. The first byte of each plaintext tells text (0) and binary (1) messages apart. These
+//! steps are mirrored by lib.ts, the known answer in the tests below is checked by
+//! `spake2_self_test` there as well.
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+use subtle::ConstantTimeEq;
+
+const PASSWORD_LABEL: &[u8] = b"weylus-spake2-password";
+const M_LABEL: &[u8] = b"weylus-spake2-M";
+const N_LABEL: &[u8] = b"weylus-spake2-N";
+const CONFIRMATION_KEYS_INFO: &[u8] = b"ConfirmationKeys";
+const TRAFFIC_KEYS_INFO: &[u8] = b"weylus-spake2-traffic-keys";
+const CLIENT_IDENTITY: &[u8] = b"weylus-client";
+const SERVER_IDENTITY: &[u8] = b"weylus-server";
+
+/// Size of the handshake message of the client.
+pub const CLIENT_MESSAGE_SIZE: usize = 32;
+
+/// Size of the confirmations, HMAC-SHA512.
+pub const CONFIRMATION_SIZE: usize = 64;
+
+/// Kind of a message, the first byte of the plaintext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Text = 0,
+    Binary = 1,
+}
+
+/// A point nobody knows the discrete logarithm of: the first hash of the label and a counter that
+/// is a valid point, multiplied by the cofactor.
+fn hash_to_point(label: &[u8]) -> EdwardsPoint {
+    for i in 0u8..=255 {
+        let hash = Sha512::new()
+            .chain_update(label)
+            .chain_update([i])
+            .finalize();
+        let mut y = [0u8; 32];
+        y.copy_from_slice(&hash[..32]);
+        if let Some(point) = CompressedEdwardsY(y).decompress() {
+            let point = point.mul_by_cofactor();
+            if !point.is_identity() {
+                return point;
+            }
+        }
+    }
+    unreachable!("About half of all hashes are valid points.")
+}
+
+fn password_scalar(access_code: &str) -> Scalar {
+    let hash = Sha512::new()
+        .chain_update(PASSWORD_LABEL)
+        .chain_update(access_code.as_bytes())
+        .finalize();
+    let mut wide = [0u8; 64];
+    wide.copy_from_slice(&hash);
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+fn random_scalar() -> Scalar {
+    let mut wide = [0u8; 64];
+    rand::thread_rng().fill_bytes(&mut wide);
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = <Hmac<Sha512> as Mac>::new_from_slice(key).expect("HMAC takes keys of any size.");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// HKDF-SHA512 without salt, 64 bytes of output.
+fn hkdf(ikm: &[u8], info: &[u8]) -> [u8; 64] {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha512>::new(None, ikm)
+        .expand(info, &mut okm)
+        .expect("64 bytes are a valid length for HKDF-SHA512.");
+    okm
+}
+
+/// Keys derived from the transcript of the handshake.
+struct Keys {
+    client_confirmation: [u8; 64],
+    server_confirmation: [u8; 64],
+    client_key: [u8; 32],
+    server_key: [u8; 32],
+}
+
+impl Keys {
+    fn derive(w: &Scalar, p_a: &[u8; 32], p_b: &[u8; 32], k: &EdwardsPoint) -> Self {
+        let k = k.compress();
+        let parts: [&[u8]; 6] = [
+            CLIENT_IDENTITY,
+            SERVER_IDENTITY,
+            p_a,
+            p_b,
+            k.as_bytes(),
+            w.as_bytes(),
+        ];
+        let mut tt = Vec::new();
+        for part in parts {
+            tt.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            tt.extend_from_slice(part);
+        }
+        let hash = Sha512::digest(&tt);
+        let (ke, ka) = hash.split_at(32);
+        let confirmation_keys = hkdf(ka, CONFIRMATION_KEYS_INFO);
+        let traffic_keys = hkdf(ke, TRAFFIC_KEYS_INFO);
+        Self {
+            client_confirmation: hmac(&confirmation_keys[..32], &tt),
+            server_confirmation: hmac(&confirmation_keys[32..], &tt),
+            client_key: traffic_keys[..32].try_into().unwrap(),
+            server_key: traffic_keys[32..].try_into().unwrap(),
+        }
+    }
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Seals the messages of one direction.
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            counter: 0,
+        }
+    }
+
+    pub fn seal(&mut self, kind: MessageKind, data: &[u8]) -> Vec<u8> {
+        let mut plaintext = Vec::with_capacity(data.len() + 1);
+        plaintext.push(kind as u8);
+        plaintext.extend_from_slice(data);
+        let nonce = nonce(self.counter);
+        self.counter += 1;
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("Encrypting a message in memory can not fail.")
+    }
+}
+
+/// Opens the messages of one direction.
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Opener {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            counter: 0,
+        }
+    }
+
+    /// Returns None if the message has been tampered with, or was sealed with another key or out
+    /// of order.
+    pub fn open(&mut self, data: &[u8]) -> Option<(MessageKind, Vec<u8>)> {
+        let nonce = nonce(self.counter);
+        let mut plaintext = self.cipher.decrypt(Nonce::from_slice(&nonce), data).ok()?;
+        self.counter += 1;
+        let kind = match plaintext.first()? {
+            0 => MessageKind::Text,
+            1 => MessageKind::Binary,
+            _ => return None,
+        };
+        plaintext.remove(0);
+        Some((kind, plaintext))
+    }
+}
+
+/// Server side of a handshake waiting for the confirmation of the client.
+pub struct PendingHandshake {
+    client_confirmation: [u8; 64],
+    sealer: Sealer,
+    opener: Opener,
+}
+
+impl PendingHandshake {
+    /// Check the confirmation of the client, returns the sealer and opener for the rest of the
+    /// connection if the client used the same access code.
+    pub fn finish(self, confirmation: &[u8]) -> Result<(Sealer, Opener), String> {
+        if bool::from(confirmation.ct_eq(&self.client_confirmation)) {
+            Ok((self.sealer, self.opener))
+        } else {
+            Err("Client used a wrong access code.".into())
+        }
+    }
+}
+
+/// Answer the handshake message of a client, returns the answer to send and the handshake
+/// waiting for the confirmation of the client. A client with a wrong access code gets an answer
+/// as well, it just can not verify it.
+pub fn accept(
+    access_code: &str,
+    client_message: &[u8],
+) -> Result<(Vec<u8>, PendingHandshake), String> {
+    accept_with(access_code, client_message, random_scalar())
+}
+
+fn accept_with(
+    access_code: &str,
+    client_message: &[u8],
+    y: Scalar,
+) -> Result<(Vec<u8>, PendingHandshake), String> {
+    let p_a: [u8; 32] = client_message
+        .try_into()
+        .map_err(|_| "Handshake message of client has an invalid size.".to_string())?;
+    let t = CompressedEdwardsY(p_a)
+        .decompress()
+        .ok_or_else(|| "Handshake message of client is no valid point.".to_string())?;
+    let w = password_scalar(access_code);
+    let s = EdwardsPoint::mul_base(&y) + w * hash_to_point(N_LABEL);
+    let k = (y * (t - w * hash_to_point(M_LABEL))).mul_by_cofactor();
+    if k.is_identity() {
+        return Err("Handshake message of client is degenerate.".into());
+    }
+    let p_b = s.compress().to_bytes();
+    let keys = Keys::derive(&w, &p_a, &p_b, &k);
+    let mut answer = p_b.to_vec();
+    answer.extend_from_slice(&keys.server_confirmation);
+    Ok((
+        answer,
+        PendingHandshake {
+            client_confirmation: keys.client_confirmation,
+            sealer: Sealer::new(&keys.server_key),
+            opener: Opener::new(&keys.client_key),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_message(access_code: &str, x: &Scalar) -> [u8; 32] {
+        (EdwardsPoint::mul_base(x) + password_scalar(access_code) * hash_to_point(M_LABEL))
+            .compress()
+            .to_bytes()
+    }
+
+    /// Client side of the handshake as implemented by lib.ts, the keys are derived without
+    /// checking the confirmation of the server.
+    fn client(access_code: &str, x: &Scalar, answer: &[u8]) -> Keys {
+        let w = password_scalar(access_code);
+        let p_b: [u8; 32] = answer[..32].try_into().unwrap();
+        let s = CompressedEdwardsY(p_b).decompress().unwrap();
+        let k = (x * (s - w * hash_to_point(N_LABEL))).mul_by_cofactor();
+        Keys::derive(&w, &client_message(access_code, x), &p_b, &k)
+    }
+
+    fn scalar(label: &str) -> Scalar {
+        let mut wide = [0u8; 64];
+        wide.copy_from_slice(&Sha512::digest(label.as_bytes()));
+        Scalar::from_bytes_mod_order_wide(&wide)
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn matching_codes_agree() {
+        let x = random_scalar();
+        let (answer, handshake) = accept("1234", &client_message("1234", &x)).unwrap();
+        let keys = client("1234", &x, &answer);
+        assert_eq!(answer[32..], keys.server_confirmation);
+        let (mut server_sealer, mut server_opener) =
+            handshake.finish(&keys.client_confirmation).unwrap();
+        let mut client_sealer = Sealer::new(&keys.client_key);
+        let mut client_opener = Opener::new(&keys.server_key);
+
+        for i in 0..3u8 {
+            let sealed = client_sealer.seal(MessageKind::Text, &[i]);
+            assert_eq!(
+                server_opener.open(&sealed),
+                Some((MessageKind::Text, vec![i]))
+            );
+            let sealed = server_sealer.seal(MessageKind::Binary, &[i; 100]);
+            assert_eq!(
+                client_opener.open(&sealed),
+                Some((MessageKind::Binary, vec![i; 100]))
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_code_fails() {
+        let x = random_scalar();
+        let (answer, handshake) = accept("1234", &client_message("4321", &x)).unwrap();
+        let keys = client("4321", &x, &answer);
+        // the client can not verify the answer and the server refuses the client
+        assert_ne!(answer[32..], keys.server_confirmation);
+        assert!(handshake.finish(&keys.client_confirmation).is_err());
+    }
+
+    #[test]
+    fn invalid_messages_fail() {
+        assert!(accept("1234", &[0; 31]).is_err());
+        // a valid point, but it cancels out the password and leads to a degenerate key
+        let w = password_scalar("1234");
+        let degenerate = (w * hash_to_point(M_LABEL)).compress().to_bytes();
+        assert!(accept("1234", &degenerate).is_err());
+    }
+
+    #[test]
+    fn tampered_or_reordered_messages_fail() {
+        let mut sealer = Sealer::new(&[7; 32]);
+        let first = sealer.seal(MessageKind::Text, b"first");
+        let mut second = sealer.seal(MessageKind::Text, b"second");
+
+        let mut opener = Opener::new(&[7; 32]);
+        assert_eq!(opener.open(&second), None);
+        assert!(opener.open(&first).is_some());
+        second[0] ^= 1;
+        assert_eq!(opener.open(&second), None);
+        second[0] ^= 1;
+        assert!(opener.open(&second).is_some());
+    }
+
+    /// Primitives of lib.ts are implemented by hand, this ties them to the ones used here.
+    #[test]
+    fn known_answer() {
+        const CLIENT_MESSAGE: &str =
+            "aec72d8bbaf8d1d40e24a0cba8ae1c8ac1763fff9030ba0c4d3048167482391a";
+        const ANSWER: &str = concat!(
+            "fde2783c1e329fcd6db362e2de4bc82096d486c8054604d9625f7e5c652f3b4d",
+            "204d099f814dc7bbddec4b1ca7a48a6373c8b86be234c1b38be3b285dc00f21a",
+            "dab49b2f40a5c39969552b904a1851158536c4504c4537dad54596e03e2cd21a"
+        );
+        const CLIENT_CONFIRMATION: &str = concat!(
+            "3b7b63a7f8b7871472d74b138550fb6c30bb69d9d4673b293c8da6fa91c24702",
+            "dea46cde0efbffcbefcaab5f127753de6c098db43f06bd192725ffcab3c2ee71"
+        );
+        const SEALED: &str = "7793b1e3d4856489b789411b7b2cb21520611c2630c6";
+
+        let x = scalar("x");
+        let p_a = client_message("weylus", &x);
+        assert_eq!(hex(&p_a), CLIENT_MESSAGE);
+        let (answer, handshake) = accept_with("weylus", &p_a, scalar("y")).unwrap();
+        assert_eq!(hex(&answer), ANSWER);
+        let keys = client("weylus", &x, &answer);
+        assert_eq!(hex(&keys.client_confirmation), CLIENT_CONFIRMATION);
+        let sealed = Sealer::new(&keys.client_key).seal(MessageKind::Text, b"hello");
+        assert_eq!(hex(&sealed), SEALED);
+        let (_, mut opener) = handshake.finish(&keys.client_confirmation).unwrap();
+        assert_eq!(
+            opener.open(&sealed),
+            Some((MessageKind::Text, b"hello".to_vec()))
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Read only access to a directory of the host, this lets clients download files produced on the
+//! host, like exported images or PDFs, without setting up a separate file share.
+
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures_util::{future, StreamExt};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use hyper::{Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+/// Resolve the path of a request relative to the shared directory. Anything outside of it is
+/// rejected, this includes symlinks pointing outside. Hidden files are not shared.
+fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let root = dir.canonicalize().ok()?;
+    let mut resolved = root.clone();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        if segment.starts_with('.') || segment.contains(['/', '\\']) {
+            return None;
+        }
+        resolved.push(segment.as_ref());
+    }
+    let resolved = resolved.canonicalize().ok()?;
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn response(
+    status: StatusCode,
+    content_type: &str,
+    body: impl Into<Bytes>,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", content_type)
+        .body(Full::from(body.into()).boxed())
+        .unwrap()
+}
+
+async fn listing(dir: &Path, path: &str, query: &str) -> std::io::Result<String> {
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        entries.push((!entry.file_type().await?.is_dir(), name));
+    }
+    // directories first
+    entries.sort();
+
+    // path has been validated by resolve, links are built from its decoded segments so nothing
+    // taken from the request ends up in the page unescaped
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .collect();
+    let dir_url = |segments: &[String]| {
+        let mut url = "/files/".to_string();
+        for segment in segments {
+            url.extend(utf8_percent_encode(segment, NON_ALPHANUMERIC));
+            url.push('/');
+        }
+        url
+    };
+    let mut items = String::new();
+    if let Some((_, parent)) = segments.split_last() {
+        items.push_str(&format!(
+            "<li><a href=\"{}{query}\">..</a></li>\n",
+            dir_url(parent)
+        ));
+    }
+    for (is_file, name) in entries {
+        let href = utf8_percent_encode(&name, NON_ALPHANUMERIC);
+        let slash = if is_file { "" } else { "/" };
+        items.push_str(&format!(
+            "<li><a href=\"{}{href}{slash}{query}\">{}{slash}</a></li>\n",
+            dir_url(&segments),
+            handlebars::html_escape(&name)
+        ));
+    }
+    let title = handlebars::html_escape(&format!("/{}", segments.join("/")));
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n\
+        <meta charset=\"utf-8\">\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+        <title>Weylus - Files</title>\n</head>\n\
+        <body>\n<h1>{title}</h1>\n<ul>\n{items}</ul>\n</body>\n</html>\n"
+    ))
+}
+
+/// Stream the content of a file, it is not read into memory as a whole. The content length is set
+/// so clients notice if reading fails midway and the download ends early.
+async fn stream_file(path: &Path) -> std::io::Result<Response<BoxBody<Bytes, Infallible>>> {
+    let file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let chunks = ReaderStream::new(file).scan((), |_, chunk| {
+        future::ready(match chunk {
+            Ok(data) => Some(Ok(Frame::data(data))),
+            Err(err) => {
+                warn!("Failed to read shared file: {err}.");
+                None
+            }
+        })
+    });
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", content_type(path))
+        .header("content-length", len)
+        .header(
+            "content-disposition",
+            format!(
+                "attachment; filename*=UTF-8''{}",
+                utf8_percent_encode(&name, NON_ALPHANUMERIC)
+            ),
+        )
+        .body(StreamBody::new(chunks).boxed())
+        .unwrap())
+}
+
+/// Serve a listing of a directory or the content of a file below the shared directory, path is
+/// taken from the URL and relative to the shared directory. The access code is forwarded to the
+/// links of the listing.
+pub async fn serve_shared(
+    dir: &Path,
+    path: &str,
+    access_code: Option<&str>,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let Some(resolved) = resolve(dir, path) else {
+        return response(StatusCode::NOT_FOUND, "text/plain", "not found");
+    };
+    let query = access_code
+        .map(|code| {
+            format!(
+                "?access_code={}",
+                utf8_percent_encode(code, NON_ALPHANUMERIC)
+            )
+        })
+        .unwrap_or_default();
+    if resolved.is_dir() {
+        return match listing(&resolved, path, &query).await {
+            Ok(html) => response(StatusCode::OK, "text/html; charset=utf-8", html),
+            Err(err) => {
+                warn!("Failed to list shared directory: {err}.");
+                response(StatusCode::NOT_FOUND, "text/plain", "not found")
+            }
+        };
+    }
+    match stream_file(&resolved).await {
+        Ok(res) => res,
+        Err(err) => {
+            warn!("Failed to read shared file: {err}.");
+            response(StatusCode::NOT_FOUND, "text/plain", "not found")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory below the temporary directory, removed again once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("weylus-file-share-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Shared directory with a file and a subdirectory, next to a file that is not shared.
+    fn setup(name: &str) -> (TempDir, PathBuf) {
+        let tmp = TempDir::new(name);
+        let shared = tmp.0.join("shared");
+        std::fs::create_dir_all(shared.join("sub")).unwrap();
+        std::fs::write(shared.join("a.txt"), "a").unwrap();
+        std::fs::write(shared.join("sub").join("b.txt"), "b").unwrap();
+        std::fs::write(shared.join(".hidden"), "hidden").unwrap();
+        std::fs::write(tmp.0.join("secret.txt"), "secret").unwrap();
+        (tmp, shared)
+    }
+
+    #[test]
+    fn resolves_paths_below_the_root() {
+        let (_tmp, shared) = setup("below");
+        let root = shared.canonicalize().unwrap();
+        assert_eq!(resolve(&shared, ""), Some(root.clone()));
+        assert_eq!(resolve(&shared, "/a.txt"), Some(root.join("a.txt")));
+        assert_eq!(
+            resolve(&shared, "sub//b.txt"),
+            Some(root.join("sub").join("b.txt"))
+        );
+        assert_eq!(resolve(&shared, "%61.txt"), Some(root.join("a.txt")));
+    }
+
+    #[test]
+    fn rejects_parent_segments() {
+        let (_tmp, shared) = setup("parent");
+        assert_eq!(resolve(&shared, "../secret.txt"), None);
+        assert_eq!(resolve(&shared, "sub/../../secret.txt"), None);
+        assert_eq!(resolve(&shared, "%2e%2e/secret.txt"), None);
+        assert_eq!(resolve(&shared, "sub/%2E%2E/a.txt"), None);
+    }
+
+    #[test]
+    fn rejects_encoded_separators() {
+        let (_tmp, shared) = setup("separators");
+        assert_eq!(resolve(&shared, "sub%2Fb.txt"), None);
+        assert_eq!(resolve(&shared, "sub%5Cb.txt"), None);
+        assert_eq!(resolve(&shared, "%2e%2e%2Fsecret.txt"), None);
+        assert_eq!(resolve(&shared, "%2e%2e%5Csecret.txt"), None);
+    }
+
+    #[test]
+    fn rejects_dotfiles() {
+        let (_tmp, shared) = setup("dotfiles");
+        assert_eq!(resolve(&shared, ".hidden"), None);
+        assert_eq!(resolve(&shared, "%2ehidden"), None);
+        assert_eq!(resolve(&shared, "."), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_pointing_outside() {
+        let (tmp, shared) = setup("symlink");
+        std::os::unix::fs::symlink(tmp.0.join("secret.txt"), shared.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&tmp.0, shared.join("outside")).unwrap();
+        std::os::unix::fs::symlink(shared.join("a.txt"), shared.join("inside.txt")).unwrap();
+        assert_eq!(resolve(&shared, "link.txt"), None);
+        assert_eq!(resolve(&shared, "outside/secret.txt"), None);
+        assert_eq!(
+            resolve(&shared, "inside.txt"),
+            Some(shared.canonicalize().unwrap().join("a.txt"))
+        );
+    }
+
+    #[test]
+    fn rejects_nonexistent_paths() {
+        let (_tmp, shared) = setup("nonexistent");
+        assert_eq!(resolve(&shared, "missing.txt"), None);
+        assert_eq!(resolve(&shared, "sub/missing/b.txt"), None);
+        assert_eq!(resolve(&shared.join("missing"), "a.txt"), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Inbound rules of the Windows Firewall for the ports Weylus listens on. Without them Windows
+//! asks the first time Weylus listens whether it may accept connections; once that popup has been
+//! dismissed clients silently fail to connect. Weylus therefore offers to create the rules itself,
+//! via the NetSecurity module of PowerShell behind a single UAC prompt, and `weylus firewall
+//! remove` removes them again, e.g. when uninstalling.
+
+use std::io;
+use std::os::windows::process::CommandExt;
+use std::process::{Command, Output};
+
+use base64::Engine;
+use tracing::warn;
+
+use crate::config::Config;
+
+/// Group of all rules created by Weylus, used to find and remove them.
+const GROUP: &str = "Weylus";
+
+/// Keeps PowerShell from flashing up a console window.
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Tcp => "TCP",
+            Self::Udp => "UDP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+impl Rule {
+    /// Name shown in the Windows Firewall settings, it identifies the rule.
+    fn display_name(&self) -> String {
+        format!("Weylus ({} {})", self.protocol.name(), self.port)
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.protocol.name(), self.port)
+    }
+}
+
+/// The rules required by the ports of all configured instances.
+pub fn required_rules(config: &Config) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for (web_config, _) in crate::weylus::all_web_configs(config).unwrap_or_default() {
+        rules.push(Rule {
+            port: web_config.bind_addr.port(),
+            protocol: Protocol::Tcp,
+        });
+        if let Some(port) = web_config.webtransport_port {
+            rules.push(Rule {
+                port,
+                protocol: Protocol::Udp,
+            });
+        }
+    }
+    rules.dedup();
+    rules
+}
+
+/// The required rules that do not exist yet. If the firewall can not be queried none are
+/// reported missing, so users are not bothered for nothing.
+pub fn missing_rules(config: &Config) -> Vec<Rule> {
+    let output = powershell(&format!(
+        "Get-NetFirewallRule -Group '{GROUP}' -Direction Inbound -Enabled True \
+        -ErrorAction SilentlyContinue | ForEach-Object {{ $_.DisplayName }}"
+    ));
+    let existing = match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|l| l.trim().to_string())
+            .collect::<Vec<_>>(),
+        Ok(output) => {
+            warn!(
+                "Failed to query the Windows Firewall: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Vec::new();
+        }
+        Err(err) => {
+            warn!("Failed to query the Windows Firewall: {err}");
+            return Vec::new();
+        }
+    };
+    required_rules(config)
+        .into_iter()
+        .filter(|rule| !existing.contains(&rule.display_name()))
+        .collect()
+}
+
+/// Create inbound rules allowing connections to the ports, this asks for elevation.
+pub fn add_rules(rules: &[Rule]) -> io::Result<()> {
+    let program = std::env::current_exe()?;
+    let script = rules
+        .iter()
+        .map(|rule| {
+            format!(
+                "New-NetFirewallRule -DisplayName {} -Group '{GROUP}' -Direction Inbound \
+                -Action Allow -Protocol {} -LocalPort {} -Program {} | Out-Null",
+                quote(&rule.display_name()),
+                rule.protocol.name(),
+                rule.port,
+                quote(&program.to_string_lossy())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    run_elevated(&script)
+}
+
+/// Remove all rules created by Weylus, this asks for elevation.
+pub fn remove_rules() -> io::Result<()> {
+    run_elevated(&format!(
+        "Remove-NetFirewallRule -Group '{GROUP}' -ErrorAction SilentlyContinue"
+    ))
+}
+
+/// Warn about missing rules if there is no GUI to offer creating them.
+pub fn warn_missing(config: &Config) {
+    let missing = missing_rules(config);
+    if !missing.is_empty() {
+        warn!(
+            "The Windows Firewall may block connections to {}, run `weylus firewall add` to \
+            allow them.",
+            describe(&missing)
+        );
+    }
+}
+
+/// The rules as text, e.g. "TCP 1701, UDP 1703".
+pub fn describe(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Quote a string for PowerShell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn powershell(script: &str) -> io::Result<Output> {
+    Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+}
+
+/// Run the script in an elevated PowerShell, the script is passed encoded to get around quoting
+/// the arguments of Start-Process.
+fn run_elevated(script: &str) -> io::Result<()> {
+    let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(utf16);
+    let output = powershell(&format!(
+        "$p = Start-Process powershell -Verb RunAs -Wait -PassThru -WindowStyle Hidden \
+        -ArgumentList '-NoProfile','-NonInteractive','-EncodedCommand','{encoded}'; \
+        exit $p.ExitCode"
+    ))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(io::Error::other(if stderr.trim().is_empty() {
+            "elevation was denied or PowerShell failed".to_string()
+        } else {
+            stderr.trim().to_string()
+        }))
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::video::PixelProvider;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+const SCALE: usize = 4;
+
+/// Where text is drawn into the frame.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+/// Lines of text drawn into a frame at the given position.
+pub struct TextOverlay<'a> {
+    pub position: TextPosition,
+    pub lines: &'a [String],
+}
+
+/// A dot drawn into a frame, e.g. a laser pointer. The position is relative to the frame.
+pub struct Dot {
+    pub x: f64,
+    pub y: f64,
+    pub color: [u8; 3],
+}
+
+/// 3x5 bitmap font, each row is stored in the 3 least significant bits. Letters are drawn in
+/// upper case.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b111, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b111, 0b100, 0b100],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b111],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+        'Q' => [0b111, 0b101, 0b101, 0b111, 0b001],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Time of day in UTC formatted as HH:MM:SS.mmm.
+pub fn time_of_day() -> String {
+    let t = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let ms = t % 1000;
+    let s = (t / 1000) % 60;
+    let m = (t / 60_000) % 60;
+    let h = (t / 3_600_000) % 24;
+    format!("{h:02}:{m:02}:{s:02}.{ms:03}")
+}
+
+fn draw_text(
+    data: &mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    bytes_per_pixel: usize,
+    text: &TextOverlay,
+) {
+    let lines = text.lines;
+    let line_height = (GLYPH_HEIGHT + 1) * SCALE;
+    let max_chars = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let box_width = ((max_chars * (GLYPH_WIDTH + 1) + 1) * SCALE).min(width);
+    let box_height = (lines.len() * line_height + SCALE).min(height);
+    let (left, top) = match text.position {
+        TextPosition::TopLeft => (0, 0),
+        TextPosition::TopRight => (width - box_width, 0),
+        TextPosition::BottomLeft => (0, height - box_height),
+        TextPosition::BottomRight => (width - box_width, height - box_height),
+        TextPosition::Center => ((width - box_width) / 2, (height - box_height) / 2),
+    };
+
+    for y in top..top + box_height {
+        let start = y * stride + left * bytes_per_pixel;
+        data[start..start + box_width * bytes_per_pixel].fill(0);
+    }
+
+    for (l, line) in lines.iter().enumerate() {
+        for (i, c) in line.chars().enumerate() {
+            let g = glyph(c);
+            for (gy, row) in g.iter().enumerate() {
+                for gx in 0..GLYPH_WIDTH {
+                    if (row >> (GLYPH_WIDTH - 1 - gx)) & 1 == 0 {
+                        continue;
+                    }
+                    let x0 = SCALE + (i * (GLYPH_WIDTH + 1) + gx) * SCALE;
+                    let y0 = SCALE + l * line_height + gy * SCALE;
+                    for y in y0..(y0 + SCALE).min(box_height) {
+                        for x in x0..(x0 + SCALE).min(box_width) {
+                            let offset = (top + y) * stride + (left + x) * bytes_per_pixel;
+                            data[offset..offset + bytes_per_pixel].fill(255);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draw a dot with a dark outline, so it can be made out on any background. Colors are given as
+/// RGB, `bgr` swaps them for frames in BGR order.
+fn draw_dot(
+    data: &mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    bytes_per_pixel: usize,
+    bgr: bool,
+    dot: &Dot,
+) {
+    let radius = (width.max(height) / 120).max(4) as isize;
+    let outline = radius + (radius / 4).max(1);
+    let cx = (dot.x * width as f64) as isize;
+    let cy = (dot.y * height as f64) as isize;
+    let [r, g, b] = dot.color;
+    let color = if bgr { [b, g, r] } else { [r, g, b] };
+    for y in (cy - outline).max(0)..(cy + outline + 1).min(height as isize) {
+        for x in (cx - outline).max(0)..(cx + outline + 1).min(width as isize) {
+            let d2 = (x - cx).pow(2) + (y - cy).pow(2);
+            if d2 > outline.pow(2) {
+                continue;
+            }
+            let offset = y as usize * stride + x as usize * bytes_per_pixel;
+            if d2 > radius.pow(2) {
+                data[offset..offset + 3].fill(0);
+            } else {
+                data[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+/// Copy the frame into buffer and draw the given texts and dots into it. Frames in GPU memory are
+/// passed on without stamp.
+pub fn stamp_frame<'a>(
+    pixels: PixelProvider<'_>,
+    buffer: &'a mut Vec<u8>,
+    texts: &[TextOverlay],
+    dots: &[Dot],
+) -> PixelProvider<'a> {
+    let (width, height, stride, bytes_per_pixel, bgr, data) = match pixels {
+        PixelProvider::RGB(w, h, data) => (w, h, w * 3, 3, false, data),
+        PixelProvider::RGB0(w, h, data) => (w, h, w * 4, 4, false, data),
+        PixelProvider::BGR0(w, h, data) => (w, h, w * 4, 4, true, data),
+        PixelProvider::BGR0S(w, h, stride, data) => (w, h, stride, 4, true, data),
+        PixelProvider::RGB0S(w, h, stride, data) => (w, h, stride, 4, false, data),
+        PixelProvider::DmaBuf(frame) => return PixelProvider::DmaBuf(frame),
+    };
+    buffer.clear();
+    buffer.extend_from_slice(data);
+    // the last row of cropped frames is not padded
+    if height > 0 && buffer.len() >= stride * (height - 1) + width * bytes_per_pixel {
+        for text in texts {
+            draw_text(buffer, width, height, stride, bytes_per_pixel, text);
+        }
+        for dot in dots {
+            draw_dot(buffer, width, height, stride, bytes_per_pixel, bgr, dot);
+        }
+    }
+    match pixels {
+        PixelProvider::RGB(w, h, _) => PixelProvider::RGB(w, h, buffer),
+        PixelProvider::RGB0(w, h, _) => PixelProvider::RGB0(w, h, buffer),
+        PixelProvider::BGR0(w, h, _) => PixelProvider::BGR0(w, h, buffer),
+        PixelProvider::BGR0S(w, h, stride, _) => PixelProvider::BGR0S(w, h, stride, buffer),
+        PixelProvider::RGB0S(w, h, stride, _) => PixelProvider::RGB0S(w, h, stride, buffer),
+        PixelProvider::DmaBuf(frame) => PixelProvider::DmaBuf(frame),
+    }
+}
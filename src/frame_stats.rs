@@ -0,0 +1,265 @@
+//! Count the frames passing through the stages of the video pipeline and the time spent in each
+//! of them, so stuttering video can be attributed to capturing, encoding or the network.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+/// Interval over which the statistics are collected before they are reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Share of the time the video thread may wait for the send queue before the network is
+/// considered the bottleneck.
+const SEND_BLOCKED_THRESHOLD: f64 = 0.1;
+
+/// Statistics of the video of a client, measured over the last report interval.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    pub captured: u64,
+    pub encoded: u64,
+    pub sent: u64,
+    /// Frames skipped as capturing and encoding the previous frame took too long.
+    pub dropped_late: u64,
+    /// Frames that failed to be captured.
+    pub dropped_capture: u64,
+    /// Encoded frames that failed to be sent.
+    pub dropped_send: u64,
+    /// Average time capturing a frame took in milliseconds.
+    pub capture_ms: f64,
+    /// Average time encoding a frame took in milliseconds.
+    pub encode_ms: f64,
+    /// Share of the time the video thread waited for the send queue to have room.
+    pub send_blocked: f64,
+    /// Largest number of messages waiting in the send queue.
+    pub queue_depth: usize,
+    /// Length of the interval in seconds.
+    pub interval: f64,
+}
+
+/// The stage of the pipeline that limits the frame rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bottleneck {
+    Capture,
+    Encode,
+    Network,
+}
+
+impl Bottleneck {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Capture => "capture",
+            Self::Encode => "encode",
+            Self::Network => "network",
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn per_second(&self, frames: u64) -> f64 {
+        if self.interval > 0.0 {
+            frames as f64 / self.interval
+        } else {
+            0.0
+        }
+    }
+
+    /// Add the statistics of the following interval, averages are weighted by their frames.
+    pub fn merge(&mut self, other: &FrameStats) {
+        let weighted = |a: f64, n: u64, b: f64, m: u64| {
+            if n + m > 0 {
+                (a * n as f64 + b * m as f64) / (n + m) as f64
+            } else {
+                0.0
+            }
+        };
+        self.capture_ms = weighted(
+            self.capture_ms,
+            self.captured,
+            other.capture_ms,
+            other.captured,
+        );
+        self.encode_ms = weighted(self.encode_ms, self.encoded, other.encode_ms, other.encoded);
+        let interval = self.interval + other.interval;
+        if interval > 0.0 {
+            self.send_blocked = (self.send_blocked * self.interval
+                + other.send_blocked * other.interval)
+                / interval;
+        }
+        self.interval = interval;
+        self.captured += other.captured;
+        self.encoded += other.encoded;
+        self.sent += other.sent;
+        self.dropped_late += other.dropped_late;
+        self.dropped_capture += other.dropped_capture;
+        self.dropped_send += other.dropped_send;
+        self.queue_depth = self.queue_depth.max(other.queue_depth);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped_late + self.dropped_capture + self.dropped_send
+    }
+
+    /// Guess which stage of the pipeline is responsible for dropped frames, None if there were
+    /// none.
+    pub fn bottleneck(&self) -> Option<Bottleneck> {
+        if self.dropped_send > 0 || self.send_blocked > SEND_BLOCKED_THRESHOLD {
+            Some(Bottleneck::Network)
+        } else if self.dropped_late == 0 && self.dropped_capture == 0 {
+            None
+        } else if self.dropped_capture > 0 || self.capture_ms >= self.encode_ms {
+            Some(Bottleneck::Capture)
+        } else {
+            Some(Bottleneck::Encode)
+        }
+    }
+
+    /// Log the statistics as structured fields, only at info level if frames were dropped.
+    pub fn log(&self, address: SocketAddr) {
+        let bottleneck = self.bottleneck();
+        macro_rules! log_stats {
+            ($level:ident) => {
+                $level!(
+                    address = %address,
+                    captured = self.captured,
+                    encoded = self.encoded,
+                    sent = self.sent,
+                    dropped_late = self.dropped_late,
+                    dropped_capture = self.dropped_capture,
+                    dropped_send = self.dropped_send,
+                    capture_ms = %format_args!("{:.1}", self.capture_ms),
+                    encode_ms = %format_args!("{:.1}", self.encode_ms),
+                    send_blocked = %format_args!("{:.2}", self.send_blocked),
+                    queue_depth = self.queue_depth,
+                    bottleneck = bottleneck.map_or("none", Bottleneck::as_str),
+                    "Video statistics."
+                )
+            };
+        }
+        if bottleneck.is_some() {
+            log_stats!(info);
+        } else {
+            log_stats!(debug);
+        }
+    }
+}
+
+/// Counters of the send stage, these are shared with the callback of the video encoder.
+#[derive(Default)]
+pub struct SendCounters {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    blocked_us: AtomicU64,
+    queue_depth: AtomicUsize,
+}
+
+impl SendCounters {
+    /// Count a frame handed to the sender, `blocked` is the time it took to queue it and
+    /// `queue_depth` the number of messages that were waiting before it.
+    pub fn record(&self, sent: bool, blocked: Duration, queue_depth: usize) {
+        if sent {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.blocked_us
+            .fetch_add(blocked.as_micros() as u64, Ordering::Relaxed);
+        self.queue_depth.fetch_max(queue_depth, Ordering::Relaxed);
+    }
+}
+
+/// Counts the frames of the video thread of a client.
+pub struct FrameCounter {
+    send: Arc<SendCounters>,
+    interval_start: Instant,
+    captured: u64,
+    encoded: u64,
+    dropped_late: u64,
+    dropped_capture: u64,
+    capture_time: Duration,
+    encode_time: Duration,
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self {
+            send: Arc::new(SendCounters::default()),
+            interval_start: Instant::now(),
+            captured: 0,
+            encoded: 0,
+            dropped_late: 0,
+            dropped_capture: 0,
+            capture_time: Duration::ZERO,
+            encode_time: Duration::ZERO,
+        }
+    }
+}
+
+impl FrameCounter {
+    pub fn send_counters(&self) -> Arc<SendCounters> {
+        self.send.clone()
+    }
+
+    pub fn late(&mut self, frames: u32) {
+        self.dropped_late += frames as u64;
+    }
+
+    pub fn captured(&mut self, duration: Duration) {
+        self.captured += 1;
+        self.capture_time += duration;
+    }
+
+    pub fn capture_failed(&mut self) {
+        self.dropped_capture += 1;
+    }
+
+    pub fn encoded(&mut self, duration: Duration) {
+        self.encoded += 1;
+        self.encode_time += duration;
+    }
+
+    /// Returns the statistics once per report interval and starts the next one.
+    pub fn stats(&mut self) -> Option<FrameStats> {
+        if self.interval_start.elapsed() < REPORT_INTERVAL {
+            return None;
+        }
+        Some(self.take())
+    }
+
+    /// The statistics of the interval so far, e.g. once the video ends, and start the next one.
+    pub fn take(&mut self) -> FrameStats {
+        let elapsed = self.interval_start.elapsed();
+        let average_ms = |time: Duration, frames: u64| {
+            if frames > 0 {
+                time.as_secs_f64() * 1000.0 / frames as f64
+            } else {
+                0.0
+            }
+        };
+        let blocked = Duration::from_micros(self.send.blocked_us.swap(0, Ordering::Relaxed));
+        let stats = FrameStats {
+            captured: self.captured,
+            encoded: self.encoded,
+            sent: self.send.sent.swap(0, Ordering::Relaxed),
+            dropped_late: self.dropped_late,
+            dropped_capture: self.dropped_capture,
+            dropped_send: self.send.failed.swap(0, Ordering::Relaxed),
+            capture_ms: average_ms(self.capture_time, self.captured),
+            encode_ms: average_ms(self.encode_time, self.encoded),
+            send_blocked: blocked.as_secs_f64() / elapsed.as_secs_f64(),
+            queue_depth: self.send.queue_depth.swap(0, Ordering::Relaxed),
+            interval: elapsed.as_secs_f64(),
+        };
+        self.interval_start = Instant::now();
+        self.captured = 0;
+        self.encoded = 0;
+        self.dropped_late = 0;
+        self.dropped_capture = 0;
+        self.capture_time = Duration::ZERO;
+        self.encode_time = Duration::ZERO;
+        stats
+    }
+}
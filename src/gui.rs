@@ -1,783 +0,0 @@
-use std::cmp::min;
-use std::io::Cursor;
-use std::iter::Iterator;
-use std::net::{IpAddr, SocketAddr};
-use std::sync::atomic::AtomicBool;
-
-use fltk::app;
-use fltk::enums::{FrameType, LabelType};
-use fltk::image::PngImage;
-use fltk::menu::Choice;
-use std::sync::{mpsc, Arc, Mutex};
-use tracing::{error, info, warn};
-
-use fltk::{
-    app::{awake_callback, App},
-    button::{Button, CheckButton},
-    frame::Frame,
-    input::{Input, IntInput},
-    output::Output,
-    prelude::*,
-    text::{TextBuffer, TextDisplay},
-    window::Window,
-};
-
-#[cfg(not(target_os = "windows"))]
-use pnet_datalink as datalink;
-
-use crate::config::{write_config, Config, ThemeType};
-use crate::protocol::{CustomInputAreas, Rect};
-use crate::web::Web2UiMessage::UInputInaccessible;
-
-pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
-    let width = 200;
-    let height = 30;
-    let padding = 10;
-
-    let app = App::default().with_scheme(fltk::app::AppScheme::Gtk);
-    config.gui_theme.map(|th| th.apply());
-    let mut wind = Window::default()
-        .with_size(660, 600)
-        .center_screen()
-        .with_label(&format!("Weylus - {}", env!("CARGO_PKG_VERSION")));
-    wind.set_xclass("weylus");
-    wind.set_callback(move |_win| app.quit());
-
-    let mut input_access_code = Input::default()
-        .with_pos(130, 30)
-        .with_size(width, height)
-        .with_label("Access code");
-    input_access_code.set_tooltip(
-        "Restrict who can control your computer with an access code. Note that this does NOT do \
-        any kind of encryption and it is advised to only run Weylus inside trusted networks! Do \
-        NOT reuse any of your passwords! If left blank, no code is required to access Weylus \
-        remotely.",
-    );
-    if let Some(code) = config.access_code.as_ref() {
-        input_access_code.set_value(code);
-    }
-
-    let mut input_bind_addr = Input::default()
-        .with_size(width, height)
-        .below_of(&input_access_code, padding)
-        .with_label("Bind Address");
-    input_bind_addr.set_value(&config.bind_address.to_string());
-
-    let mut input_port = IntInput::default()
-        .with_size(width, height)
-        .below_of(&input_bind_addr, padding)
-        .with_label("Port");
-    input_port.set_value(&config.web_port.to_string());
-
-    let mut check_auto_start = CheckButton::default()
-        .with_size(70, height)
-        .below_of(&input_port, padding + 5)
-        .with_label("Auto Start");
-    check_auto_start.set_tooltip("Start Weylus server immediately on program start.");
-    check_auto_start.set_checked(config.auto_start);
-
-    #[cfg(target_os = "linux")]
-    let mut check_wayland = CheckButton::default()
-        .with_size(70, height)
-        .right_of(&check_auto_start, 3 * padding)
-        .with_label("Wayland/\nPipeWire\nSupport");
-    #[cfg(target_os = "linux")]
-    {
-        check_wayland.set_tooltip(
-            "EXPERIMENTAL! This may crash your desktop! Enables screen \
-        capturing for Wayland using PipeWire and GStreamer.",
-        );
-        check_wayland.set_checked(config.wayland_support);
-    }
-
-    let mut label_hw_accel = Frame::default()
-        .with_size(width, height)
-        .below_of(&check_auto_start, padding)
-        .with_label("Try Hardware acceleration");
-    label_hw_accel.set_tooltip(
-        "On many systems video encoding can be done with hardware \
-        acceleration. By default this is disabled as the quality and stability of video encoding \
-        varies greatly among hardware and drivers. Currently this is only supported on Linux.",
-    );
-
-    let mut check_native_hw_accel = CheckButton::default()
-        .with_size(70, height)
-        .below_of(&label_hw_accel, 0);
-
-    #[cfg(target_os = "linux")]
-    {
-        check_native_hw_accel.set_label("VAAPI");
-        check_native_hw_accel
-            .set_tooltip("Try to use hardware acceleration through the Video Acceleration API.");
-        check_native_hw_accel.set_checked(config.try_vaapi);
-    }
-
-    #[cfg(target_os = "macos")]
-    {
-        check_native_hw_accel.set_label("VideoToolbox");
-        check_native_hw_accel
-            .set_tooltip("Try to use hardware acceleration through the VideoToolbox API.");
-        check_native_hw_accel.set_checked(config.try_videotoolbox);
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        check_native_hw_accel.set_label("Media-\nFoundation");
-        check_native_hw_accel
-            .set_tooltip("Try to use hardware acceleration through the MediaFoundation API.");
-        check_native_hw_accel.set_checked(config.try_mediafoundation);
-    }
-
-    let mut check_nvenc = CheckButton::default()
-        .with_size(70, height)
-        .right_of(&check_native_hw_accel, 2 * padding)
-        .with_label("NVENC");
-    check_nvenc.set_tooltip("Try to use Nvidia's NVENC to encode the video via GPU.");
-
-    #[cfg(any(target_os = "linux", target_os = "windows"))]
-    check_nvenc.set_checked(config.try_nvenc);
-
-    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
-    {
-        check_nvenc.deactivate();
-        check_nvenc.hide();
-    }
-
-    let mut but_toggle = Button::default()
-        .with_size(width, height)
-        .below_of(&check_native_hw_accel, 2 * padding)
-        .with_label("Start");
-
-    let mut output_server_addr = Output::default()
-        .with_size(500, height)
-        .below_of(&but_toggle, 3 * padding)
-        .with_label("Connect your\ntablet to:");
-    output_server_addr.hide();
-
-    let output_buf = TextBuffer::default();
-    let mut output = TextDisplay::default().with_size(600, 6 * height).with_pos(
-        30,
-        output_server_addr.y() + output_server_addr.height() + padding,
-    );
-    output.set_buffer(output_buf);
-    let output_buf = output.buffer().unwrap();
-
-    let mut choice_theme = Choice::default()
-        .with_size(width, height)
-        .right_of(&input_access_code, padding);
-
-    for theme in ThemeType::themes() {
-        choice_theme.add_choice(&theme.name());
-    }
-    choice_theme.set_value(config.gui_theme.unwrap_or(ThemeType::default()).to_index());
-
-    let mut qr_frame = Frame::default()
-        .with_size(235, 235)
-        .right_of(&input_bind_addr, padding);
-
-    qr_frame.hide();
-
-    wind.make_resizable(true);
-    wind.end();
-    wind.show();
-
-    let output_buf = Arc::new(Mutex::new(output_buf));
-
-    std::thread::spawn(move || {
-        while let Ok(log_message) = log_receiver.recv() {
-            let mut output_buf = output_buf.lock().unwrap();
-            output_buf.append(&log_message);
-        }
-    });
-
-    let mut weylus = crate::weylus::Weylus::new();
-    let mut is_server_running = false;
-    let auto_start = config.auto_start;
-    let config = Arc::new(Mutex::new(config.clone()));
-
-    {
-        let config = config.clone();
-        choice_theme.set_callback(move |c| {
-            let v = c.value();
-            if v >= 0 {
-                ThemeType::from_index(v).apply();
-                config.lock().unwrap().gui_theme = Some(ThemeType::from_index(v));
-                write_config(&config.lock().unwrap());
-            }
-        });
-    }
-
-    let mut toggle_server = move |but: &mut Button| {
-        if let Err(err) = || -> Result<(), Box<dyn std::error::Error>> {
-            let mut config = config.lock().unwrap();
-            if !is_server_running {
-                {
-                    let access_code_string = input_access_code.value();
-                    let access_code = match access_code_string.as_str() {
-                        "" => None,
-                        code => Some(code),
-                    };
-                    let bind_addr: IpAddr = input_bind_addr.value().parse()?;
-                    let web_port: u16 = input_port.value().parse()?;
-
-                    config.access_code = access_code.map(|s| s.to_string());
-                    config.web_port = web_port;
-                    config.bind_address = bind_addr;
-                    config.auto_start = check_auto_start.is_checked();
-                    config.gui_theme = Some(ThemeType::from_index(choice_theme.value()));
-                    #[cfg(target_os = "linux")]
-                    {
-                        config.try_vaapi = check_native_hw_accel.is_checked();
-                        config.wayland_support = check_wayland.is_checked();
-                    }
-                    #[cfg(any(target_os = "linux", target_os = "windows"))]
-                    {
-                        config.try_nvenc = check_nvenc.is_checked();
-                    }
-                    #[cfg(target_os = "macos")]
-                    {
-                        config.try_videotoolbox = check_native_hw_accel.is_checked();
-                    }
-                    #[cfg(target_os = "windows")]
-                    {
-                        config.try_mediafoundation = check_native_hw_accel.is_checked();
-                    }
-                }
-                if !weylus.start(&config, |message| match message {
-                    UInputInaccessible => awake_callback(move || {
-                        let w = 500;
-                        let h = 300;
-                        let mut pop_up = Window::default()
-                            .with_size(w, h)
-                            .center_screen()
-                            .with_label("Weylus - UInput inaccessible!");
-                        pop_up.set_xclass("weylus");
-
-                        let buf = TextBuffer::default();
-                        let mut pop_up_text = TextDisplay::default().with_size(w, h);
-                        pop_up_text.set_buffer(buf);
-                        pop_up_text.wrap_mode(fltk::text::WrapMode::AtBounds, 5);
-                        let mut buf = pop_up_text.buffer().unwrap();
-                        buf.set_text(std::include_str!("strings/uinput_error.txt"));
-
-                        pop_up.end();
-                        pop_up.make_modal(true);
-                        pop_up.show();
-                    }),
-                }) {
-                    return Ok(());
-                }
-                is_server_running = true;
-
-                write_config(&config);
-
-                let mut web_sock = SocketAddr::new(config.bind_address, config.web_port);
-
-                #[cfg(not(target_os = "windows"))]
-                {
-                    if web_sock.ip().is_unspecified() {
-                        // try to guess an ip
-                        let mut ips = Vec::<IpAddr>::new();
-                        for iface in datalink::interfaces()
-                            .iter()
-                            .filter(|iface| iface.is_up() && !iface.is_loopback())
-                        {
-                            for ipnetw in &iface.ips {
-                                if (ipnetw.is_ipv4() && web_sock.ip().is_ipv4())
-                                    || (ipnetw.is_ipv6() && web_sock.ip().is_ipv6())
-                                {
-                                    // filtering ipv6 unicast requires nightly or more fiddling,
-                                    // lets wait for nightlies to stabilize...
-                                    ips.push(ipnetw.ip())
-                                }
-                            }
-                        }
-                        if !ips.is_empty() {
-                            web_sock.set_ip(ips[0]);
-                        }
-                        if ips.len() > 1 {
-                            info!("Found more than one IP address for browsers to connect to,");
-                            info!("other urls are:");
-                            for ip in &ips[1..] {
-                                info!("http://{}", SocketAddr::new(*ip, config.web_port));
-                            }
-                        }
-                    }
-                }
-
-                #[cfg(not(target_os = "windows"))]
-                {
-                    use image::Luma;
-                    use qrcode::QrCode;
-                    let addr_string = format!("http://{}", web_sock);
-                    output_server_addr.set_value(&addr_string);
-                    let mut url_string = addr_string;
-                    if let Some(access_code) = &config.access_code {
-                        url_string.push_str("?access_code=");
-                        url_string.push_str(
-                            &percent_encoding::utf8_percent_encode(
-                                access_code,
-                                percent_encoding::NON_ALPHANUMERIC,
-                            )
-                            .to_string(),
-                        );
-                    }
-
-                    let cb = move |qr_frame: &mut Frame, _, _, w, h| {
-                        let code = QrCode::new(&url_string).unwrap();
-                        let img_buf = code.render::<Luma<u8>>().build();
-                        let image = image::DynamicImage::ImageLuma8(img_buf);
-                        let dims = min(w, h) as u32;
-                        let image =
-                            image.resize_exact(dims, dims, image::imageops::FilterType::Nearest);
-                        let mut buf = vec![];
-                        let mut cursor = Cursor::new(&mut buf);
-                        image
-                            .write_to(&mut cursor, image::ImageFormat::Png)
-                            .unwrap();
-                        let png = PngImage::from_data(&buf).unwrap();
-                        qr_frame.set_image(Some(png));
-                    };
-
-                    let x = qr_frame.x();
-                    let y = qr_frame.y();
-                    let w = qr_frame.width();
-                    let h = qr_frame.height();
-                    cb(&mut qr_frame, x, y, w, h);
-                    qr_frame.resize_callback(cb);
-                    qr_frame.show();
-                }
-                #[cfg(target_os = "windows")]
-                {
-                    if web_sock.ip().is_unspecified() {
-                        output_server_addr.set_value("http://<your ip address>");
-                    } else {
-                        output_server_addr.set_value(&format!("http://{}", web_sock.to_string()));
-                    }
-                }
-                output_server_addr.show();
-                but.set_label("Stop");
-            } else {
-                weylus.stop();
-                but.set_label("Start");
-                output_server_addr.hide();
-                qr_frame.resize_callback(|_, _, _, _, _| {});
-                qr_frame.hide();
-                is_server_running = false;
-            }
-            Ok(())
-        }() {
-            error!("{}", err);
-        };
-    };
-
-    if auto_start {
-        toggle_server(&mut but_toggle);
-    }
-
-    but_toggle.set_callback(toggle_server);
-
-    app.run().expect("Failed to run Gui!");
-
-    // TODO: Remove when https://github.com/fltk-rs/fltk-rs/issues/1480 is fixed
-    // this is required to drop the callback and do a graceful shutdown of the web server
-    but_toggle.set_callback(|_| ());
-}
-
-const BORDER: i32 = 30;
-static WINCTX: Mutex<Option<InputAreaWindowContext>> = Mutex::new(None);
-
-struct InputAreaWindowContext {
-    win: Window,
-    choice_mouse: Choice,
-    choice_touch: Choice,
-    choice_pen: Choice,
-    workspaces: Vec<Rect>,
-}
-
-pub fn get_input_area(
-    no_gui: bool,
-    output_sender: std::sync::mpsc::Sender<crate::protocol::CustomInputAreas>,
-) {
-    // If no gui is running there is no event loop and windows can not be created.
-    // That's why we initialize the fltk app here one the first call.
-    if no_gui {
-        static GUI_INITIALIZED: AtomicBool = AtomicBool::new(false);
-
-        if !GUI_INITIALIZED.swap(true, std::sync::atomic::Ordering::Relaxed) {
-            std::thread::spawn(move || {
-                let _app = App::default().with_scheme(fltk::app::AppScheme::Gtk);
-                let mut winctx = create_custom_input_area_window();
-                custom_input_area_window_handle_events(&mut winctx.win, output_sender.clone());
-                show_overlay_window(&mut winctx);
-                WINCTX.lock().unwrap().replace(winctx);
-                loop {
-                    // calling wait_for ensures that the fltk event loop keeps running even if
-                    // there is no window shown
-                    if let Err(err) = app::wait_for(1.0) {
-                        warn!("Error waiting for fltk events: {err}.");
-                    }
-                }
-            });
-        } else {
-            fltk::app::awake_callback(move || {
-                let mut winctx = WINCTX.lock().unwrap();
-                let winctx = winctx.as_mut().unwrap();
-                custom_input_area_window_handle_events(&mut winctx.win, output_sender.clone());
-                show_overlay_window(winctx);
-            });
-        }
-    } else {
-        fltk::app::awake_callback(move || {
-            let mut winctx = WINCTX.lock().unwrap();
-            if winctx.is_none() {
-                winctx.replace(create_custom_input_area_window());
-            }
-
-            let winctx = winctx.as_mut().unwrap();
-            custom_input_area_window_handle_events(&mut winctx.win, output_sender.clone());
-            show_overlay_window(winctx);
-        });
-    }
-}
-
-fn create_custom_input_area_window() -> InputAreaWindowContext {
-    let mut win = Window::default().with_size(600, 600).center_screen();
-    win.make_resizable(true);
-    win.set_border(false);
-    win.set_frame(FrameType::FlatBox);
-    win.set_color(fltk::enums::Color::from_rgb(240, 240, 240));
-    let mut frame = Frame::default()
-        .with_size(win.w() - 2 * BORDER, win.h() - 2 * BORDER)
-        .center_of_parent()
-        .with_label(
-            "Press Enter to submit\ncurrent selection as\ncustom input area,\nEscape to abort.",
-        );
-    frame.set_label_type(LabelType::Normal);
-    frame.set_label_size(20);
-    frame.set_color(fltk::enums::Color::Black);
-    frame.set_frame(FrameType::BorderFrame);
-    frame.set_label_font(fltk::enums::Font::HelveticaBold);
-    let width = 200;
-    let height = 30;
-    let padding = 10;
-    let tool_tip = "Some systems may have the input device mapped to a specific screen, this screen has to be selected here. Otherwise input mapping will be wrong. Selecting None disables any mapping.";
-    let mut choice_mouse = Choice::default()
-        .with_size(width, height)
-        .with_pos(padding, 4 * padding)
-        .center_x(&frame)
-        .with_id("choice_mouse")
-        .with_label("Map Mouse from:");
-    choice_mouse.set_tooltip(tool_tip);
-    let mut choice_touch = Choice::default()
-        .with_size(width, height)
-        .below_of(&choice_mouse, padding)
-        .with_id("choice_touch")
-        .with_label("Map Touch from:");
-    choice_touch.set_tooltip(tool_tip);
-    let mut choice_pen = Choice::default()
-        .with_size(width, height)
-        .below_of(&choice_touch, padding)
-        .with_id("choice_pen")
-        .with_label("Map Pen from:");
-    choice_pen.set_tooltip(tool_tip);
-
-    frame.handle(|frame, event| match event {
-        fltk::enums::Event::Push => {
-            if app::event_clicks() {
-                if let Some(mut win) = frame.window() {
-                    win.fullscreen(!win.fullscreen_active());
-                }
-                true
-            } else {
-                false
-            }
-        }
-        _ => false,
-    });
-    win.resize_callback(move |_win, _x, _y, w, h| {
-        frame.resize(BORDER, BORDER, w - 2 * BORDER, h - 2 * BORDER)
-    });
-    win.end();
-    InputAreaWindowContext {
-        win,
-        choice_mouse,
-        choice_touch,
-        choice_pen,
-        workspaces: Vec::new(),
-    }
-}
-
-fn custom_input_area_window_handle_events(
-    win: &mut Window,
-    sender: std::sync::mpsc::Sender<crate::protocol::CustomInputAreas>,
-) {
-    #[derive(Debug)]
-    enum MouseFlags {
-        All,
-        Edge(bool, bool),
-        Corner(bool, bool),
-    }
-    fn get_mouse_flags(win: &Window, x: i32, y: i32) -> MouseFlags {
-        let dx0 = (win.x() - x).abs();
-        let dy0 = (win.y() - y).abs();
-        let dx1 = (win.x() + win.w() - x).abs();
-        let dy1 = (win.y() + win.h() - y).abs();
-        let dx = min(dx0, dx1);
-        let dy = min(dy0, dy1);
-        let d = min(dx, dy);
-        if d <= BORDER {
-            if dx <= BORDER && dy <= BORDER {
-                MouseFlags::Corner(dx0 <= dx1, dy0 <= dy1)
-            } else {
-                MouseFlags::Edge(dx <= dy, if dx <= dy { dx0 <= dx1 } else { dy0 <= dy1 })
-            }
-        } else {
-            MouseFlags::All
-        }
-    }
-    fn get_screen_coords_from_event_coords(win: &Window, (x, y): (i32, i32)) -> (i32, i32) {
-        (x + win.x(), y + win.y())
-    }
-    fn set_cursor(
-        win: &mut Window,
-        current_cursor: &mut fltk::enums::Cursor,
-        flags: Option<MouseFlags>,
-    ) {
-        let cursor = match flags {
-            Some(MouseFlags::All) => fltk::enums::Cursor::Move,
-            Some(MouseFlags::Edge(bx, by)) => match (bx, by) {
-                (true, true) => fltk::enums::Cursor::W,
-                (true, false) => fltk::enums::Cursor::E,
-                (false, true) => fltk::enums::Cursor::N,
-                (false, false) => fltk::enums::Cursor::S,
-            },
-            Some(MouseFlags::Corner(bx, by)) => match (bx, by) {
-                (true, true) => fltk::enums::Cursor::NWSE,
-                (true, false) => fltk::enums::Cursor::NESW,
-                (false, true) => fltk::enums::Cursor::NESW,
-                (false, false) => fltk::enums::Cursor::NWSE,
-            },
-            None => fltk::enums::Cursor::Default,
-        };
-        if *current_cursor != cursor {
-            *current_cursor = cursor;
-            win.set_cursor(cursor);
-        }
-    }
-
-    let mut drag_flags = MouseFlags::All;
-    let mut current_cursor = fltk::enums::Cursor::Default;
-    let mut x = 0;
-    let mut y = 0;
-    let mut win_x_drag_start = 0;
-    let mut win_y_drag_start = 0;
-    let mut win_w_drag_start = 0;
-    let mut win_h_drag_start = 0;
-    win.handle(move |win, event| {
-        match event {
-            fltk::enums::Event::Move => {
-                let (x, y) = get_screen_coords_from_event_coords(&win, app::event_coords());
-                let flags = get_mouse_flags(&win, x, y);
-                set_cursor(win, &mut current_cursor, Some(flags));
-                true
-            }
-            fltk::enums::Event::Leave => {
-                win.set_cursor(fltk::enums::Cursor::Default);
-                true
-            }
-            fltk::enums::Event::Push => {
-                (x, y) = get_screen_coords_from_event_coords(&win, app::event_coords());
-                win_x_drag_start = win.x();
-                win_y_drag_start = win.y();
-                win_w_drag_start = win.w();
-                win_h_drag_start = win.h();
-                drag_flags = get_mouse_flags(&win, x, y);
-                true
-            }
-            fltk::enums::Event::Drag => {
-                if win.opacity() == 1.0 {
-                    win.set_opacity(0.5);
-                }
-                let (x_new, y_new) = get_screen_coords_from_event_coords(&win, app::event_coords());
-                let dx = x_new - x;
-                let dy = y_new - y;
-                match drag_flags {
-                    MouseFlags::All => win.set_pos(win_x_drag_start + dx, win_y_drag_start + dy),
-                    MouseFlags::Edge(bx, by) => match (bx, by) {
-                        (true, true) => win.resize(
-                            win_x_drag_start + dx,
-                            win_y_drag_start,
-                            win_w_drag_start - dx,
-                            win_h_drag_start,
-                        ),
-                        (true, false) => win.resize(
-                            win_x_drag_start,
-                            win_y_drag_start,
-                            win_w_drag_start + dx,
-                            win_h_drag_start,
-                        ),
-                        (false, true) => win.resize(
-                            win_x_drag_start,
-                            win_y_drag_start + dy,
-                            win_w_drag_start,
-                            win_h_drag_start - dy,
-                        ),
-                        (false, false) => win.resize(
-                            win_x_drag_start,
-                            win_y_drag_start,
-                            win_w_drag_start,
-                            win_h_drag_start + dy,
-                        ),
-                    },
-                    MouseFlags::Corner(bx, by) => match (bx, by) {
-                        (true, true) => win.resize(
-                            win_x_drag_start + dx,
-                            win_y_drag_start + dy,
-                            win_w_drag_start - dx,
-                            win_h_drag_start - dy,
-                        ),
-
-                        (true, false) => win.resize(
-                            win_x_drag_start + dx,
-                            win_y_drag_start,
-                            win_w_drag_start - dx,
-                            win_h_drag_start + dy,
-                        ),
-                        (false, true) => win.resize(
-                            win_x_drag_start,
-                            win_y_drag_start + dy,
-                            win_w_drag_start + dx,
-                            win_h_drag_start - dy,
-                        ),
-                        (false, false) => win.resize(
-                            win_x_drag_start,
-                            win_y_drag_start,
-                            win_w_drag_start + dx,
-                            win_h_drag_start + dy,
-                        ),
-                    },
-                }
-                true
-            }
-            fltk::enums::Event::Released => {
-                if win.opacity() != 1.0 {
-                    win.set_opacity(1.0);
-                }
-                true
-            }
-            fltk::enums::Event::KeyDown => match app::event_key() {
-                fltk::enums::Key::Enter => {
-                    fn relative_rect(win: &Window, workspace: &Rect) -> Rect {
-                        // clamp rect to workspace and ensure it has non-zero area
-                        let mut rect = crate::protocol::Rect {
-                            x: (win.x() as f64 - workspace.x).min(workspace.w) / workspace.w,
-                            y: (win.y() as f64 - workspace.y).min(workspace.h) / workspace.h,
-                            w: win.w().max(1) as f64 / workspace.w,
-                            h: win.h().max(1) as f64 / workspace.h,
-                        };
-                        rect.w = rect.w.min(1.0 - rect.x);
-                        rect.h = rect.h.min(1.0 - rect.y);
-                        rect
-                    }
-                    win.set_cursor(fltk::enums::Cursor::Default);
-                    win.hide();
-                    let mut areas = CustomInputAreas::default();
-                    let workspaces = WINCTX.lock().unwrap().as_ref().unwrap().workspaces.clone();
-                    for (name, area) in [
-                        ("choice_mouse", &mut areas.mouse),
-                        ("choice_touch", &mut areas.touch),
-                        ("choice_pen", &mut areas.pen),
-                    ] {
-                        let c: Choice = fltk::app::widget_from_id(name).unwrap();
-                        match c.value() {
-                            0 => (),
-                            v @ 1.. if (v as usize) <= workspaces.len() => {
-                                let workspace = workspaces[v as usize - 1];
-                                *area = Some(relative_rect(win, &workspace))
-                            }
-                            v => warn!("Unexpected value in {name}: {v}!"),
-                        }
-                    }
-                    sender.send(areas).unwrap();
-                    true
-                }
-                fltk::enums::Key::Escape => {
-                    win.set_cursor(fltk::enums::Cursor::Default);
-                    win.hide();
-                    true
-                }
-                _ => false,
-            },
-            _ => false,
-        }
-    });
-}
-
-fn show_overlay_window(winctx: &mut InputAreaWindowContext) {
-    let win = &mut winctx.win;
-    if win.shown() {
-        return;
-    }
-    let screens = fltk::app::Screen::all_screens();
-    winctx.workspaces.clear();
-    winctx.workspaces.push(get_full_workspace_rect());
-    for screen in &screens {
-        let fltk::draw::Rect { x, y, w, h } = screen.work_area();
-        winctx.workspaces.push(Rect {
-            x: x as f64,
-            y: y as f64,
-            w: w as f64,
-            h: h as f64,
-        });
-    }
-    for c in [
-        &mut winctx.choice_mouse,
-        &mut winctx.choice_touch,
-        &mut winctx.choice_pen,
-    ] {
-        let v = c.value();
-        c.clear();
-        c.add_choice("None");
-        c.add_choice("Full Workspace");
-        for screen in &screens {
-            c.add_choice(&format!(
-                "Screen {n} at {w}x{h}+{x}+{y}",
-                n = screen.n,
-                w = screen.w(),
-                h = screen.h(),
-                x = screen.x(),
-                y = screen.y()
-            ));
-        }
-        if v >= 0 && (v as usize) < 2 + screens.len() {
-            c.set_value(v);
-        } else {
-            c.set_value(0);
-        }
-    }
-    if win.fullscreen_active() {
-        win.set_size(600, 600);
-        let n = win.screen_num();
-        let screen = app::Screen::new(n).unwrap();
-        win.set_pos(
-            screen.x() + (screen.w() - 600) / 2,
-            screen.y() + (screen.h() - 600) / 2,
-        );
-    }
-    win.show();
-    win.set_on_top();
-    win.set_visible_focus();
-}
-
-pub fn get_full_workspace_rect() -> Rect {
-    let mut rect = Rect::default();
-    for screen in fltk::app::Screen::all_screens() {
-        let fltk::draw::Rect { x, y, w, h } = screen.work_area();
-        rect.x = (x as f64).min(rect.x);
-        rect.y = (y as f64).min(rect.y);
-        rect.w = ((x + w) as f64).max(rect.w);
-        rect.h = ((y + h) as f64).max(rect.h);
-    }
-    rect
-}
@@ -0,0 +1,40 @@
+//! Keeps the window of the gui out of captures, otherwise capturing the screen that shows it
+//! mirrors the video of the capture endlessly.
+
+#[cfg(target_os = "windows")]
+pub fn exclude_from_capture(frame: &eframe::Frame, exclude: bool) {
+    use eframe::egui_winit::winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    use tracing::warn;
+    use winapi::shared::windef::HWND;
+    use winapi::um::winuser::{SetWindowDisplayAffinity, WDA_NONE};
+
+    // not defined by winapi, supported since Windows 10 version 2004
+    const WDA_EXCLUDEFROMCAPTURE: u32 = 0x11;
+
+    let handle = match frame.window_handle() {
+        Ok(handle) => handle,
+        Err(err) => {
+            warn!("Failed to get window handle of the gui: {err}");
+            return;
+        }
+    };
+    let RawWindowHandle::Win32(handle) = handle.as_raw() else {
+        return;
+    };
+    let affinity = if exclude {
+        WDA_EXCLUDEFROMCAPTURE
+    } else {
+        WDA_NONE
+    };
+    if unsafe { SetWindowDisplayAffinity(handle.hwnd.get() as HWND, affinity) } == 0 {
+        warn!(
+            "Failed to change whether the gui shows up in captures: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// On macOS ScreenCaptureKit leaves out the windows of Weylus itself, the capture backends of
+/// Linux can not leave out single windows.
+#[cfg(not(target_os = "windows"))]
+pub fn exclude_from_capture(_frame: &eframe::Frame, _exclude: bool) {}
@@ -0,0 +1,383 @@
+//! Window to choose custom input areas: the user places the window over the part of the screen
+//! input from the client should be mapped to.
+
+use std::sync::{mpsc, Mutex, OnceLock};
+
+use eframe::egui;
+use egui::viewport::ResizeDirection;
+use egui::{Color32, CursorIcon, Key, Pos2, Stroke, ViewportCommand};
+use tracing::{error, warn};
+
+use crate::protocol::{CustomInputAreas, Rect};
+
+/// Width of the border that resizes the window instead of moving it.
+const BORDER: f32 = 30.0;
+const DEVICES: [&str; 3] = ["Mouse", "Touch", "Pen"];
+
+/// Index into the list of workspaces for every device, 0 disables the mapping.
+struct PickerState {
+    sender: Option<mpsc::Sender<CustomInputAreas>>,
+    choices: [usize; 3],
+}
+
+static STATE: Mutex<PickerState> = Mutex::new(PickerState {
+    sender: None,
+    choices: [0; 3],
+});
+
+/// Context of the running gui, the picker is shown as one of its viewports.
+static GUI_CONTEXT: OnceLock<egui::Context> = OnceLock::new();
+
+/// Sender waking the thread showing the picker if there is no gui.
+static PICKER_THREAD: Mutex<Option<mpsc::Sender<()>>> = Mutex::new(None);
+
+pub(super) fn set_gui_context(ctx: egui::Context) {
+    GUI_CONTEXT.set(ctx).ok();
+}
+
+/// Whether a client waits for input areas, the gui then shows the picker.
+pub(super) fn is_pending() -> bool {
+    STATE.lock().unwrap().sender.is_some()
+}
+
+pub fn get_input_area(no_gui: bool, output_sender: mpsc::Sender<CustomInputAreas>) {
+    // a new request takes over a picker that is already shown
+    STATE.lock().unwrap().sender = Some(output_sender);
+    if !no_gui {
+        if let Some(ctx) = GUI_CONTEXT.get() {
+            ctx.request_repaint();
+        }
+        return;
+    }
+    let mut picker_thread = PICKER_THREAD.lock().unwrap();
+    if picker_thread.is_none() {
+        // If no gui is running there is no event loop and windows can not be created, so a
+        // thread running one is started on the first call. The event loop can not be recreated
+        // and thus the thread is kept for all later requests.
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || run_picker_loop(receiver));
+        *picker_thread = Some(sender);
+    }
+    picker_thread.as_ref().unwrap().send(()).ok();
+}
+
+fn run_picker_loop(receiver: mpsc::Receiver<()>) {
+    while receiver.recv().is_ok() {
+        // requests that arrived while the picker was shown have already been handled
+        while receiver.try_recv().is_ok() {}
+        if !is_pending() {
+            continue;
+        }
+        let options = eframe::NativeOptions {
+            viewport: picker_viewport(),
+            centered: true,
+            // keep the event loop around to show the picker again on the next request
+            run_and_return: true,
+            #[cfg(target_os = "linux")]
+            event_loop_builder: Some(Box::new(|builder| {
+                use eframe::egui_winit::winit::platform::x11::EventLoopBuilderExtX11;
+                builder.with_any_thread(true);
+            })),
+            ..Default::default()
+        };
+        if let Err(err) = eframe::run_native(
+            "Weylus - Custom Input Areas",
+            options,
+            Box::new(|_cc| Ok(Box::new(PickerApp::new()))),
+        ) {
+            error!("Failed to show window to choose custom input areas: {err}");
+            STATE.lock().unwrap().sender = None;
+        }
+    }
+}
+
+fn picker_viewport() -> egui::ViewportBuilder {
+    egui::ViewportBuilder::default()
+        .with_title("Weylus - Custom Input Areas")
+        .with_app_id("weylus")
+        .with_inner_size([600.0, 600.0])
+        .with_decorations(false)
+        .with_transparent(true)
+        .with_always_on_top()
+}
+
+/// Picker used as root window if no gui is running.
+struct PickerApp {
+    picker: InputAreaPicker,
+}
+
+impl PickerApp {
+    fn new() -> Self {
+        Self {
+            picker: InputAreaPicker::new(),
+        }
+    }
+}
+
+impl eframe::App for PickerApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.picker.show(ctx) {
+            ctx.send_viewport_cmd(ViewportCommand::Close);
+        }
+    }
+
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        [0.0; 4]
+    }
+}
+
+/// Show the picker as viewport of the gui while a client waits for input areas.
+pub(super) fn show_viewport(ctx: &egui::Context, picker: &mut Option<InputAreaPicker>) {
+    if !is_pending() {
+        *picker = None;
+        return;
+    }
+    let picker = picker.get_or_insert_with(InputAreaPicker::new);
+    ctx.show_viewport_immediate(
+        egui::ViewportId::from_hash_of("custom_input_areas"),
+        picker_viewport(),
+        |ctx, _class| {
+            picker.show(ctx);
+        },
+    );
+}
+
+pub(super) struct InputAreaPicker {
+    workspaces: Vec<(String, Rect)>,
+}
+
+impl InputAreaPicker {
+    fn new() -> Self {
+        let mut workspaces = vec![("Full Workspace".to_string(), get_full_workspace_rect())];
+        for (n, screen) in screens().into_iter().enumerate() {
+            workspaces.push((
+                format!(
+                    "Screen {n} at {w}x{h}+{x}+{y}",
+                    w = screen.w,
+                    h = screen.h,
+                    x = screen.x,
+                    y = screen.y
+                ),
+                screen,
+            ));
+        }
+        let mut state = STATE.lock().unwrap();
+        for choice in state.choices.iter_mut() {
+            if *choice > workspaces.len() {
+                *choice = 0;
+            }
+        }
+        Self { workspaces }
+    }
+
+    /// Draw the picker into the current viewport, returns true once it is done.
+    fn show(&mut self, ctx: &egui::Context) -> bool {
+        let (submit, abort) = ctx.input(|i| {
+            (
+                i.key_pressed(Key::Enter),
+                i.key_pressed(Key::Escape) || i.viewport().close_requested(),
+            )
+        });
+        if submit {
+            let areas = self.areas(ctx);
+            if let Some(sender) = STATE.lock().unwrap().sender.take() {
+                sender.send(areas).ok();
+            }
+            return true;
+        }
+        if abort {
+            STATE.lock().unwrap().sender = None;
+            return true;
+        }
+
+        let frame = egui::Frame::new()
+            .fill(Color32::from_rgba_unmultiplied(240, 240, 240, 160))
+            .stroke(Stroke::new(2.0, Color32::BLACK))
+            .inner_margin(BORDER);
+        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
+            let rect = ctx.screen_rect();
+            let background = ui.interact(
+                rect,
+                egui::Id::new("input_area_background"),
+                egui::Sense::click_and_drag(),
+            );
+            let pointer = ctx.input(|i| i.pointer.hover_pos());
+            let direction = pointer.and_then(|pos| resize_direction(rect, pos));
+            if background.hovered() {
+                ctx.set_cursor_icon(cursor_icon(direction));
+                if ctx.input(|i| i.pointer.primary_pressed()) {
+                    ctx.send_viewport_cmd(match direction {
+                        Some(direction) => ViewportCommand::BeginResize(direction),
+                        None => ViewportCommand::StartDrag,
+                    });
+                }
+            }
+            if background.double_clicked() {
+                let fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+                ctx.send_viewport_cmd(ViewportCommand::Fullscreen(!fullscreen));
+            }
+
+            let tool_tip = "Some systems may have the input device mapped to a specific screen, \
+                this screen has to be selected here. Otherwise input mapping will be wrong. \
+                Selecting None disables any mapping.";
+            let mut state = STATE.lock().unwrap();
+            egui::Grid::new("input_area_choices")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (device, choice) in DEVICES.iter().zip(state.choices.iter_mut()) {
+                        ui.label(format!("Map {device} from:"));
+                        let selected = match *choice {
+                            0 => "None",
+                            i => self.workspaces[i - 1].0.as_str(),
+                        };
+                        egui::ComboBox::from_id_salt(device)
+                            .selected_text(selected)
+                            .width(240.0)
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(choice, 0, "None");
+                                for (i, (name, _)) in self.workspaces.iter().enumerate() {
+                                    ui.selectable_value(choice, i + 1, name);
+                                }
+                            })
+                            .response
+                            .on_hover_text(tool_tip);
+                        ui.end_row();
+                    }
+                });
+            ui.centered_and_justified(|ui| {
+                ui.heading(
+                    "Press Enter to submit\ncurrent selection as\ncustom input area,\nEscape to \
+                    abort.",
+                );
+            });
+        });
+        false
+    }
+
+    fn areas(&self, ctx: &egui::Context) -> CustomInputAreas {
+        let mut areas = CustomInputAreas::default();
+        let Some(window) = window_rect(ctx) else {
+            warn!(
+                "The window system does not tell the position of windows, custom input areas \
+                can not be determined."
+            );
+            return areas;
+        };
+        let choices = STATE.lock().unwrap().choices;
+        for (choice, area) in
+            choices
+                .into_iter()
+                .zip([&mut areas.mouse, &mut areas.touch, &mut areas.pen])
+        {
+            match choice.checked_sub(1).map(|i| &self.workspaces[i].1) {
+                Some(workspace) if workspace.w > 0.0 && workspace.h > 0.0 => {
+                    *area = Some(relative_rect(&window, workspace))
+                }
+                Some(_) => warn!("Size of the screen is unknown, ignoring custom input area."),
+                None => (),
+            }
+        }
+        areas
+    }
+}
+
+/// The window in physical pixels, like the screens.
+fn window_rect(ctx: &egui::Context) -> Option<Rect> {
+    ctx.input(|i| {
+        let outer = i.viewport().outer_rect?;
+        let scale = i.viewport().native_pixels_per_point.unwrap_or(1.0) as f64;
+        Some(Rect {
+            x: outer.min.x as f64 * scale,
+            y: outer.min.y as f64 * scale,
+            w: outer.width() as f64 * scale,
+            h: outer.height() as f64 * scale,
+        })
+    })
+}
+
+fn relative_rect(win: &Rect, workspace: &Rect) -> Rect {
+    // clamp rect to workspace and ensure it has non-zero area
+    let mut rect = Rect {
+        x: ((win.x - workspace.x).min(workspace.w) / workspace.w).max(0.0),
+        y: ((win.y - workspace.y).min(workspace.h) / workspace.h).max(0.0),
+        w: win.w.max(1.0) / workspace.w,
+        h: win.h.max(1.0) / workspace.h,
+    };
+    rect.w = rect.w.min(1.0 - rect.x);
+    rect.h = rect.h.min(1.0 - rect.y);
+    rect
+}
+
+fn resize_direction(rect: egui::Rect, pos: Pos2) -> Option<ResizeDirection> {
+    let left = pos.x - rect.left() <= BORDER;
+    let right = rect.right() - pos.x <= BORDER;
+    let top = pos.y - rect.top() <= BORDER;
+    let bottom = rect.bottom() - pos.y <= BORDER;
+    match (left, right, top, bottom) {
+        (true, _, true, _) => Some(ResizeDirection::NorthWest),
+        (true, _, _, true) => Some(ResizeDirection::SouthWest),
+        (_, true, true, _) => Some(ResizeDirection::NorthEast),
+        (_, true, _, true) => Some(ResizeDirection::SouthEast),
+        (true, _, _, _) => Some(ResizeDirection::West),
+        (_, true, _, _) => Some(ResizeDirection::East),
+        (_, _, true, _) => Some(ResizeDirection::North),
+        (_, _, _, true) => Some(ResizeDirection::South),
+        _ => None,
+    }
+}
+
+fn cursor_icon(direction: Option<ResizeDirection>) -> CursorIcon {
+    match direction {
+        None => CursorIcon::Move,
+        Some(ResizeDirection::North) => CursorIcon::ResizeNorth,
+        Some(ResizeDirection::South) => CursorIcon::ResizeSouth,
+        Some(ResizeDirection::East) => CursorIcon::ResizeEast,
+        Some(ResizeDirection::West) => CursorIcon::ResizeWest,
+        Some(ResizeDirection::NorthEast) => CursorIcon::ResizeNorthEast,
+        Some(ResizeDirection::NorthWest) => CursorIcon::ResizeNorthWest,
+        Some(ResizeDirection::SouthEast) => CursorIcon::ResizeSouthEast,
+        Some(ResizeDirection::SouthWest) => CursorIcon::ResizeSouthWest,
+    }
+}
+
+/// Geometry of all screens in physical pixels.
+fn screens() -> Vec<Rect> {
+    match display_info::DisplayInfo::all() {
+        Ok(displays) => displays
+            .into_iter()
+            .map(|d| Rect {
+                x: d.x as f64,
+                y: d.y as f64,
+                w: d.width as f64,
+                h: d.height as f64,
+            })
+            .collect(),
+        Err(err) => {
+            warn!("Failed to get list of screens: {err}");
+            Vec::new()
+        }
+    }
+}
+
+fn get_full_workspace_rect() -> Rect {
+    let screens = screens();
+    if screens.is_empty() {
+        return Rect::default();
+    }
+    let x = screens.iter().map(|s| s.x).fold(f64::INFINITY, f64::min);
+    let y = screens.iter().map(|s| s.y).fold(f64::INFINITY, f64::min);
+    let right = screens
+        .iter()
+        .map(|s| s.x + s.w)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let bottom = screens
+        .iter()
+        .map(|s| s.y + s.h)
+        .fold(f64::NEG_INFINITY, f64::max);
+    Rect {
+        x,
+        y,
+        w: right - x,
+        h: bottom - y,
+    }
+}
@@ -0,0 +1,1018 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use egui::{Key, KeyboardShortcut, Modifiers, RichText};
+use tracing::{error, info};
+
+use crate::approval::{Decision, PendingApproval};
+use crate::config::{write_config, Config, ThemeType, MAIN_INSTANCE};
+use crate::control::SharedClientStatus;
+#[cfg(target_os = "linux")]
+#[cfg(target_os = "linux")]
+use crate::input::device::InputBackend;
+use crate::input::indicator::IndicatorStyle;
+use crate::invite::InviteOptions;
+use crate::oidc::random_string;
+#[cfg(target_os = "macos")]
+use crate::permissions::{Permission, Permissions};
+use crate::video::LatencyPreset;
+use crate::web::Web2UiMessage::UInputInaccessible;
+use crate::websocket::ClientRole;
+use crate::weylus::{Reloader, Weylus};
+
+mod capture_exclusion;
+mod input_area;
+mod preview;
+
+pub use input_area::get_input_area;
+
+/// The log keeps at most this many bytes, older messages are dropped.
+const MAX_LOG_SIZE: usize = 1 << 20;
+
+/// The list of clients is refreshed periodically as there is no notification about clients
+/// connecting or disconnecting.
+const CLIENT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+const TOGGLE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Enter);
+const APPLY_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::S);
+
+pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title(format!("Weylus - {}", env!("CARGO_PKG_VERSION")))
+            .with_app_id("weylus")
+            .with_inner_size([760.0, 720.0])
+            .with_min_inner_size([520.0, 480.0]),
+        centered: true,
+        ..Default::default()
+    };
+    let config = config.clone();
+    if let Err(err) = eframe::run_native(
+        "weylus",
+        options,
+        Box::new(move |cc| Ok(Box::new(WeylusApp::new(cc, config, log_receiver)))),
+    ) {
+        error!("Failed to run Gui: {err}");
+    }
+}
+
+struct WeylusApp {
+    config: Config,
+    access_code: String,
+    bind_address: String,
+    web_port: String,
+    weylus: Weylus,
+    reloader: Option<Reloader>,
+    /// Address and code of the QR code to connect to, set while the server is running.
+    server: Option<(String, Option<egui::TextureHandle>)>,
+    log: Arc<Mutex<String>>,
+    clients: Vec<SharedClientStatus>,
+    selected_client: Option<SocketAddr>,
+    last_client_refresh: Instant,
+    preview: preview::Preview,
+    uinput_inaccessible: Arc<AtomicBool>,
+    /// Devices waiting for approval, refreshed along with the clients.
+    approvals: Vec<PendingApproval>,
+    input_area_picker: Option<input_area::InputAreaPicker>,
+    invite_options: InviteOptions,
+    /// Url of the last invite created.
+    invite: Option<String>,
+    /// Whether the window is currently left out of captures, None until it has been set.
+    excluded_from_capture: Option<bool>,
+    /// The server does not accept connections right now, refreshed along with the clients.
+    closed_by_schedule: bool,
+    /// Permissions granted by the system, refreshed along with the clients.
+    #[cfg(target_os = "macos")]
+    permissions: Option<Permissions>,
+    /// Rules the Windows Firewall lacks for the ports of the running server.
+    #[cfg(target_os = "windows")]
+    firewall_missing: Vec<crate::firewall::Rule>,
+}
+
+impl WeylusApp {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        config: Config,
+        log_receiver: mpsc::Receiver<String>,
+    ) -> Self {
+        let ctx = cc.egui_ctx.clone();
+        ctx.set_theme(theme_preference(config.gui_theme.unwrap_or_default()));
+        input_area::set_gui_context(ctx.clone());
+
+        let log = Arc::new(Mutex::new(String::new()));
+        {
+            let log = log.clone();
+            std::thread::spawn(move || {
+                while let Ok(log_message) = log_receiver.recv() {
+                    let mut log = log.lock().unwrap();
+                    log.push_str(&log_message);
+                    if log.len() > MAX_LOG_SIZE {
+                        let mut cut = log.len() - MAX_LOG_SIZE / 2;
+                        while !log.is_char_boundary(cut) {
+                            cut += 1;
+                        }
+                        log.drain(..cut);
+                    }
+                    ctx.request_repaint();
+                }
+            });
+        }
+
+        let mut app = Self {
+            access_code: config.access_code.clone().unwrap_or_default(),
+            bind_address: config.bind_address.to_string(),
+            web_port: config.web_port.to_string(),
+            config,
+            weylus: Weylus::new(),
+            reloader: None,
+            server: None,
+            log,
+            clients: Vec::new(),
+            selected_client: None,
+            last_client_refresh: Instant::now(),
+            preview: preview::Preview::new(),
+            uinput_inaccessible: Arc::new(AtomicBool::new(false)),
+            approvals: Vec::new(),
+            input_area_picker: None,
+            invite_options: InviteOptions {
+                role: ClientRole::Controller,
+                valid_for: Some(Duration::from_secs(3600)),
+                uses: Some(1),
+            },
+            invite: None,
+            excluded_from_capture: None,
+            closed_by_schedule: false,
+            #[cfg(target_os = "macos")]
+            permissions: crate::permissions::check(),
+            #[cfg(target_os = "windows")]
+            firewall_missing: Vec::new(),
+        };
+        if app.config.auto_start {
+            app.toggle_server(&cc.egui_ctx);
+        }
+        app
+    }
+
+    fn update_config(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let bind_addr: IpAddr = self.bind_address.trim().parse()?;
+        let web_port: u16 = self.web_port.trim().parse()?;
+        self.config.access_code = match self.access_code.as_str() {
+            "" => None,
+            code => Some(code.to_string()),
+        };
+        self.config.web_port = web_port;
+        self.config.bind_address = bind_addr;
+        Ok(())
+    }
+
+    fn apply(&mut self) {
+        if let Err(err) = self.update_config() {
+            error!("{}", err);
+            return;
+        }
+        if let Some(reloader) = self.reloader.as_ref() {
+            if reloader.reload(&self.config) {
+                write_config(&self.config);
+            }
+        }
+    }
+
+    fn toggle_server(&mut self, ctx: &egui::Context) {
+        if self.server.is_some() {
+            self.reloader = None;
+            self.weylus.stop();
+            self.server = None;
+            return;
+        }
+        if let Err(err) = self.update_config() {
+            error!("{}", err);
+            return;
+        }
+        let uinput_inaccessible = self.uinput_inaccessible.clone();
+        let repaint_ctx = ctx.clone();
+        if let Err(err) = self
+            .weylus
+            .start(&self.config, move |message| match message {
+                UInputInaccessible => {
+                    uinput_inaccessible.store(true, Ordering::Relaxed);
+                    repaint_ctx.request_repaint();
+                }
+            })
+        {
+            error!("{}", err);
+            return;
+        }
+        self.reloader = self.weylus.reloader();
+        write_config(&self.config);
+        #[cfg(target_os = "windows")]
+        if !self.config.no_firewall_prompt {
+            self.firewall_missing = crate::firewall::missing_rules(&self.config);
+        }
+
+        // the port differs from the configured one if it was in use and a free one was chosen
+        let addr = self
+            .weylus
+            .address()
+            .unwrap_or_else(|| SocketAddr::new(self.config.bind_address, self.config.web_port));
+        let addr_string = crate::web::server_url(addr);
+        let qr = qr_code(ctx, &addr_string, &self.config);
+        self.server = Some((addr_string, qr));
+    }
+
+    fn refresh_clients(&mut self, ctx: &egui::Context) {
+        if self.last_client_refresh.elapsed() >= CLIENT_REFRESH_INTERVAL {
+            self.clients = crate::control::clients();
+            self.approvals = crate::approval::pending();
+            self.closed_by_schedule = crate::control::instance_statuses()
+                .iter()
+                .any(|i| i.closed_by_schedule);
+            #[cfg(target_os = "macos")]
+            {
+                self.permissions = crate::permissions::check();
+            }
+            self.last_client_refresh = Instant::now();
+        }
+        ctx.request_repaint_after(CLIENT_REFRESH_INTERVAL);
+        let capturable = self
+            .selected()
+            .and_then(|client| client.lock().unwrap().capturable.clone());
+        self.preview.set_capturable(capturable, ctx);
+    }
+
+    fn selected(&self) -> Option<SharedClientStatus> {
+        self.clients
+            .iter()
+            .find(|c| Some(c.lock().unwrap().address) == self.selected_client)
+            .cloned()
+    }
+
+    /// Warn about permissions that have not been granted, only needed on macOS.
+    #[cfg(target_os = "macos")]
+    fn permissions_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(permissions) = self.permissions else {
+            return;
+        };
+        for permission in permissions.missing() {
+            ui.horizontal(|ui| {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("{} not granted", permission.name()),
+                )
+                .on_hover_text(match permission {
+                    Permission::ScreenRecording => {
+                        "Weylus can not capture the screen until it is granted the Screen \
+                        Recording permission, Weylus has to be restarted afterwards."
+                    }
+                    Permission::Accessibility => {
+                        "Input from clients is ignored until Weylus is granted the Accessibility \
+                        permission."
+                    }
+                });
+                if ui.button("Open System Settings").clicked() {
+                    permission.open_settings();
+                }
+            });
+        }
+        ui.add_space(8.0);
+    }
+
+    /// Offer to allow the ports in the Windows Firewall instead of relying on the popup Windows
+    /// shows once.
+    #[cfg(target_os = "windows")]
+    fn firewall_ui(&mut self, ui: &mut egui::Ui) {
+        if self.firewall_missing.is_empty() {
+            return;
+        }
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            format!(
+                "The Windows Firewall may block connections to {}.",
+                crate::firewall::describe(&self.firewall_missing)
+            ),
+        );
+        ui.horizontal(|ui| {
+            if ui
+                .button("Allow in Firewall")
+                .on_hover_text(
+                    "Create inbound rules for these ports, Windows asks for administrator rights. \
+                    `weylus firewall remove` removes them again.",
+                )
+                .clicked()
+            {
+                match crate::firewall::add_rules(&self.firewall_missing) {
+                    Ok(()) => {
+                        info!(
+                            "Allowed {} in the Windows Firewall.",
+                            crate::firewall::describe(&self.firewall_missing)
+                        );
+                        self.firewall_missing.clear();
+                    }
+                    Err(err) => error!("Failed to create firewall rules: {err}"),
+                }
+            }
+            if ui.button("Don't ask again").clicked() {
+                self.config.no_firewall_prompt = true;
+                write_config(&self.config);
+                self.firewall_missing.clear();
+            }
+        });
+        ui.add_space(8.0);
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        #[cfg(target_os = "macos")]
+        self.permissions_ui(ui);
+        #[cfg(target_os = "windows")]
+        self.firewall_ui(ui);
+        ui.heading("Settings");
+        egui::Grid::new("settings")
+            .num_columns(2)
+            .spacing([12.0, 8.0])
+            .show(ui, |ui| {
+                ui.label("Access code");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.access_code)
+                            .password(true)
+                            .desired_width(140.0),
+                    )
+                    .on_hover_text(
+                        "Restrict who can control your computer with an access code. Note that \
+                        this does NOT do any kind of encryption and it is advised to only run \
+                        Weylus inside trusted networks! Do NOT reuse any of your passwords! If \
+                        left blank, no code is required to access Weylus remotely.",
+                    );
+                    if ui
+                        .button("Generate")
+                        .on_hover_text(
+                            "Replace the access code with a new random one, apply it to lock out \
+                            everyone who knows the old code.",
+                        )
+                        .clicked()
+                    {
+                        self.access_code = random_string(12);
+                    }
+                });
+                ui.end_row();
+
+                ui.label("New Devices");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.config.approve_new_clients, "Ask for Approval")
+                        .on_hover_text(
+                            "Devices connecting for the first time have to be approved here, even \
+                            if they know the access code.",
+                        );
+                    if ui
+                        .button("Forget")
+                        .on_hover_text(
+                            "Forget all approved devices, they have to be approved again.",
+                        )
+                        .clicked()
+                    {
+                        crate::approval::forget_all();
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Bind Address");
+                ui.text_edit_singleline(&mut self.bind_address);
+                ui.end_row();
+
+                ui.label("Port");
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.web_port);
+                    ui.checkbox(&mut self.config.find_free_port, "Find Free Port")
+                        .on_hover_text("Listen on the next free port if this one is in use.");
+                });
+                ui.end_row();
+
+                ui.label("Theme");
+                let mut theme = self.config.gui_theme.unwrap_or_default();
+                egui::ComboBox::from_id_salt("theme")
+                    .selected_text(theme.name())
+                    .show_ui(ui, |ui| {
+                        for th in ThemeType::themes() {
+                            ui.selectable_value(&mut theme, *th, th.name());
+                        }
+                    });
+                if theme != self.config.gui_theme.unwrap_or_default() {
+                    ui.ctx().set_theme(theme_preference(theme));
+                    self.config.gui_theme = Some(theme);
+                    write_config(&self.config);
+                }
+                ui.end_row();
+
+                ui.label("Latency").on_hover_text(
+                    "Tune the encoder and the buffering of clients for the lowest latency, a \
+                    balance of latency and quality or the best quality. Applies to video streams \
+                    started afterwards.",
+                );
+                let mut preset = self.config.latency_preset.unwrap_or_default();
+                egui::ComboBox::from_id_salt("latency_preset")
+                    .selected_text(latency_preset_name(preset))
+                    .show_ui(ui, |ui| {
+                        for p in [
+                            LatencyPreset::LowestLatency,
+                            LatencyPreset::Balanced,
+                            LatencyPreset::BestQuality,
+                        ] {
+                            ui.selectable_value(&mut preset, p, latency_preset_name(p));
+                        }
+                    });
+                if preset != self.config.latency_preset.unwrap_or_default() {
+                    self.config.latency_preset = Some(preset);
+                }
+                ui.end_row();
+
+                ui.label("Keyframes").on_hover_text(
+                    "Frames between keyframes, by default those of the latency preset. With \
+                    intra-refresh the picture is refreshed gradually instead, which avoids spikes \
+                    in bitrate on constrained links. Applies to video streams started afterwards.",
+                );
+                ui.horizontal(|ui| {
+                    let mut custom = self.config.keyframe_interval.is_some();
+                    ui.checkbox(&mut custom, "Every");
+                    let mut frames = self.config.keyframe_interval.unwrap_or_else(|| {
+                        self.config
+                            .latency_preset
+                            .unwrap_or_default()
+                            .tuning()
+                            .gop_size as u32
+                    });
+                    ui.add_enabled(
+                        custom,
+                        egui::DragValue::new(&mut frames)
+                            .range(1..=3000)
+                            .suffix(" frames"),
+                    );
+                    self.config.keyframe_interval = custom.then_some(frames);
+                    ui.checkbox(&mut self.config.intra_refresh, "Intra-refresh");
+                });
+                ui.end_row();
+
+                #[cfg(target_os = "linux")]
+                {
+                    ui.label("Control Indicator").on_hover_text(
+                        "Show a border around the screen or a badge in its corner while a client \
+                        has control, applies to clients connecting afterwards.",
+                    );
+                    let mut style = self.config.control_indicator.unwrap_or_default();
+                    egui::ComboBox::from_id_salt("control_indicator")
+                        .selected_text(format!("{style:?}"))
+                        .show_ui(ui, |ui| {
+                            for s in [
+                                IndicatorStyle::Off,
+                                IndicatorStyle::Border,
+                                IndicatorStyle::Badge,
+                            ] {
+                                ui.selectable_value(&mut style, s, format!("{s:?}"));
+                            }
+                        });
+                    if style != self.config.control_indicator.unwrap_or_default() {
+                        self.config.control_indicator = Some(style);
+                    }
+                    ui.end_row();
+                }
+            });
+
+        ui.add_space(8.0);
+        ui.checkbox(&mut self.config.auto_start, "Auto Start")
+            .on_hover_text("Start Weylus server immediately on program start.");
+        #[cfg(target_os = "linux")]
+        ui.checkbox(&mut self.config.wayland_support, "Wayland/PipeWire Support")
+            .on_hover_text(
+                "EXPERIMENTAL! This may crash your desktop! Enables screen capturing for Wayland \
+                using PipeWire and GStreamer.",
+            );
+        #[cfg(target_os = "windows")]
+        ui.checkbox(&mut self.config.capture_self, "Show Weylus in Captures")
+            .on_hover_text(
+                "By default this window is left out of captures so capturing the screen it is on \
+                does not mirror the video endlessly.",
+            );
+
+        ui.add_space(8.0);
+        ui.label("Try Hardware acceleration").on_hover_text(
+            "On many systems video encoding can be done with hardware acceleration. By default \
+            this is disabled as the quality and stability of video encoding varies greatly among \
+            hardware and drivers.",
+        );
+        ui.horizontal(|ui| {
+            #[cfg(all(target_os = "linux", feature = "vaapi"))]
+            ui.checkbox(&mut self.config.try_vaapi, "VAAPI")
+                .on_hover_text(
+                    "Try to use hardware acceleration through the Video Acceleration API.",
+                );
+            #[cfg(target_os = "macos")]
+            ui.checkbox(&mut self.config.try_videotoolbox, "VideoToolbox")
+                .on_hover_text("Try to use hardware acceleration through the VideoToolbox API.");
+            #[cfg(target_os = "windows")]
+            ui.checkbox(&mut self.config.try_mediafoundation, "MediaFoundation")
+                .on_hover_text("Try to use hardware acceleration through the MediaFoundation API.");
+            #[cfg(all(
+                any(
+                    target_os = "linux",
+                    all(target_os = "windows", target_arch = "x86_64")
+                ),
+                feature = "nvenc"
+            ))]
+            ui.checkbox(&mut self.config.try_nvenc, "NVENC")
+                .on_hover_text("Try to use Nvidia's NVENC to encode the video via GPU.");
+        });
+
+        ui.add_space(12.0);
+        let running = self.server.is_some();
+        let mut toggle = false;
+        let mut apply = false;
+        ui.horizontal(|ui| {
+            toggle = ui
+                .button(if running { "Stop" } else { "Start" })
+                .on_hover_text(format!(
+                    "{} the server ({}).",
+                    if running { "Stop" } else { "Start" },
+                    ui.ctx().format_shortcut(&TOGGLE_SHORTCUT)
+                ))
+                .clicked();
+            apply = ui
+                .add_enabled(running, egui::Button::new("Apply"))
+                .on_hover_text(format!(
+                    "Apply changed settings to the running server ({}). Changes of the access \
+                    code and hardware acceleration take effect immediately, changes of the \
+                    address or port require a restart.",
+                    ui.ctx().format_shortcut(&APPLY_SHORTCUT)
+                ))
+                .clicked();
+        });
+        let (toggle_key, apply_key) = ui.ctx().input_mut(|i| {
+            (
+                i.consume_shortcut(&TOGGLE_SHORTCUT),
+                i.consume_shortcut(&APPLY_SHORTCUT),
+            )
+        });
+        if toggle || toggle_key {
+            self.toggle_server(ui.ctx());
+        } else if (apply || apply_key) && running {
+            self.apply();
+        }
+        if running && (!self.config.schedule.is_empty() || self.config.schedule_override.is_some())
+        {
+            self.schedule_ui(ui);
+        }
+
+        if let Some((addr, qr)) = &self.server {
+            ui.add_space(12.0);
+            ui.label("Connect your tablet to:");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut addr.as_str()).desired_width(220.0));
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(addr.clone());
+                }
+            });
+            if let Some(qr) = qr {
+                let size = ui.available_width().min(240.0);
+                ui.add(egui::Image::from_texture(egui::load::SizedTexture::new(
+                    qr.id(),
+                    [size, size],
+                )));
+            }
+            self.invite_ui(ui);
+        }
+    }
+
+    fn schedule_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add_space(8.0);
+        ui.horizontal(|ui| {
+            if self.closed_by_schedule {
+                ui.colored_label(ui.visuals().warn_fg_color, "Off")
+                    .on_hover_text("No new connections are accepted, connected clients stay.");
+            } else {
+                ui.label("On");
+            }
+            let mut schedule_override = self.config.schedule_override;
+            egui::ComboBox::from_id_salt("schedule_override")
+                .selected_text(match schedule_override {
+                    None => "Follow schedule",
+                    Some(true) => "Keep open",
+                    Some(false) => "Keep closed",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut schedule_override, None, "Follow schedule");
+                    ui.selectable_value(&mut schedule_override, Some(true), "Keep open");
+                    ui.selectable_value(&mut schedule_override, Some(false), "Keep closed");
+                })
+                .response
+                .on_hover_text(format!(
+                    "Accept connections only during the scheduled time windows ({}) or override \
+                    the schedule until Weylus is restarted.",
+                    self.schedule_text()
+                ));
+            if schedule_override != self.config.schedule_override {
+                self.config.schedule_override = schedule_override;
+                if let Some(reloader) = self.reloader.as_ref() {
+                    reloader.reload(&self.config);
+                }
+            }
+        });
+    }
+
+    fn schedule_text(&self) -> String {
+        if self.config.schedule.is_empty() {
+            return "none configured".into();
+        }
+        self.config
+            .schedule
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn invite_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add_space(12.0);
+        ui.label("Invite a guest").on_hover_text(
+            "Invite links grant access without revealing the access code, they stop working \
+            once used up or expired.",
+        );
+        let options = &mut self.invite_options;
+        ui.horizontal(|ui| {
+            let mut view_only = options.role == ClientRole::Viewer;
+            ui.checkbox(&mut view_only, "View only");
+            options.role = if view_only {
+                ClientRole::Viewer
+            } else {
+                ClientRole::Controller
+            };
+            let mut one_time = options.uses.is_some();
+            ui.checkbox(&mut one_time, "One-time");
+            options.uses = one_time.then_some(1);
+        });
+        ui.horizontal(|ui| {
+            let mut expires = options.valid_for.is_some();
+            ui.checkbox(&mut expires, "Valid for");
+            let mut minutes = options.valid_for.map_or(60, |d| d.as_secs() / 60);
+            ui.add_enabled(
+                expires,
+                egui::DragValue::new(&mut minutes)
+                    .range(1..=7 * 24 * 60)
+                    .suffix(" min"),
+            );
+            options.valid_for = expires.then(|| Duration::from_secs(minutes * 60));
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Create invite link").clicked() {
+                // the GUI shows the address of the main instance, invite to that one too
+                match crate::control::create_invite(Some(MAIN_INSTANCE), &self.invite_options) {
+                    Ok(url) => self.invite = Some(url),
+                    Err(err) => error!("Failed to create invite: {err}"),
+                }
+            }
+            if ui
+                .button("Revoke all")
+                .on_hover_text("Invalidate all invites and end the sessions of invited guests.")
+                .clicked()
+            {
+                let revoked = crate::control::revoke_invites(Some(MAIN_INSTANCE));
+                info!("Revoked {revoked} invites.");
+                self.invite = None;
+            }
+        });
+        if let Some(invite) = &self.invite {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut invite.as_str()).desired_width(220.0));
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(invite.clone());
+                }
+            });
+        }
+    }
+
+    fn clients_ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Clients");
+        if self.clients.is_empty() {
+            ui.weak("No clients connected.");
+        }
+        let broadcasting = self.clients.iter().any(|c| c.lock().unwrap().broadcasting);
+        if broadcasting {
+            let viewers = self
+                .clients
+                .iter()
+                .filter(|c| c.lock().unwrap().watching_broadcast)
+                .count();
+            ui.label(format!(
+                "Broadcasting to {viewers} viewer{}.",
+                if viewers == 1 { "" } else { "s" }
+            ));
+        }
+        egui::ScrollArea::vertical()
+            .id_salt("clients")
+            .max_height(140.0)
+            .show(ui, |ui| {
+                for client in &self.clients {
+                    let status = client.lock().unwrap();
+                    let mut line = status.address.to_string();
+                    if let Some(name) = &status.name {
+                        line.push_str(&format!(" - {name}"));
+                    }
+                    if status.presentation {
+                        line.push_str(" (presenting)");
+                    }
+                    if status.broadcasting {
+                        line.push_str(" (broadcasting)");
+                    } else if status.watching_broadcast {
+                        line.push_str(" (watching)");
+                    }
+                    let mut hover = "Select the client to change its settings.".to_string();
+                    if let Some(battery) = status.device.and_then(|d| d.describe()) {
+                        hover.push_str(&format!("\nBattery: {battery}"));
+                    }
+                    let low = status.device.map(|d| d.low_batteries()).unwrap_or_default();
+                    let mut text = RichText::new(line);
+                    if !low.is_empty() {
+                        text = text.color(ui.visuals().warn_fg_color);
+                    }
+                    let selected = self.selected_client == Some(status.address);
+                    if ui
+                        .selectable_label(selected, text)
+                        .on_hover_text(hover)
+                        .clicked()
+                    {
+                        self.selected_client = (!selected).then_some(status.address);
+                    }
+                    for (battery, level) in low {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!(
+                                "The {battery} battery is at {}.",
+                                crate::battery::percent(level)
+                            ),
+                        );
+                    }
+                }
+            });
+
+        let Some(client) = self.selected() else {
+            return;
+        };
+        ui.separator();
+        let (role, capturable, encoder, input_device, input_rate, dropped, video, device) = {
+            let status = client.lock().unwrap();
+            (
+                status.role,
+                status.capturable.clone(),
+                status.encoder.clone(),
+                status.input_device.clone(),
+                status.input_rate,
+                status.dropped_input_events,
+                status.video,
+                status.device,
+            )
+        };
+        egui::Grid::new("client_stats")
+            .num_columns(2)
+            .spacing([12.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("Role");
+                ui.label(format!("{role:?}"));
+                ui.end_row();
+                ui.label("Capturing");
+                ui.label(capturable.as_deref().unwrap_or("-"));
+                ui.end_row();
+                ui.label("Encoder");
+                ui.label(encoder.as_deref().unwrap_or("-"));
+                ui.end_row();
+                ui.label("Input");
+                ui.label(format!("{input_rate:.0} events/s, {dropped} dropped"));
+                ui.end_row();
+                ui.label("Input device");
+                ui.label(input_device.as_deref().unwrap_or("-"));
+                ui.end_row();
+                ui.label("Frames");
+                ui.label(format!(
+                    "{:.0} captured, {:.0} encoded, {:.0} sent per second",
+                    video.per_second(video.captured),
+                    video.per_second(video.encoded),
+                    video.per_second(video.sent),
+                ))
+                .on_hover_text(format!(
+                    "Capturing takes {:.1} ms, encoding {:.1} ms per frame.",
+                    video.capture_ms, video.encode_ms
+                ));
+                ui.end_row();
+                ui.label("Dropped frames");
+                ui.label(format!(
+                    "{} late, {} capture, {} send, queue depth {}",
+                    video.dropped_late,
+                    video.dropped_capture,
+                    video.dropped_send,
+                    video.queue_depth
+                ))
+                .on_hover_text(match video.bottleneck() {
+                    Some(bottleneck) => format!("Limited by {}.", bottleneck.as_str()),
+                    None => "No frames dropped recently.".into(),
+                });
+                ui.end_row();
+                ui.label("Battery");
+                ui.label(device.and_then(|d| d.describe()).as_deref().unwrap_or("-"))
+                    .on_hover_text("As reported by the browser of the client, if it does.");
+                ui.end_row();
+            });
+
+        // viewers can not send any input anyway
+        let mut presentation = client.lock().unwrap().presentation;
+        if ui
+            .add_enabled(
+                role == ClientRole::Controller,
+                egui::Checkbox::new(&mut presentation, "Presentation mode"),
+            )
+            .on_hover_text(
+                "The selected client may only watch and point at things: its pointer is shown as \
+                a highlight instead of moving the cursor and keyboard input is ignored.",
+            )
+            .changed()
+        {
+            client.lock().unwrap().presentation = presentation;
+        }
+        #[cfg(target_os = "linux")]
+        ui.add_enabled_ui(role == ClientRole::Controller, |ui| {
+            ui.horizontal(|ui| {
+                let current = client.lock().unwrap().input_backend;
+                let mut backend = current;
+                ui.label("Input backend");
+                egui::ComboBox::from_id_salt("input_backend")
+                    .selected_text(backend.name())
+                    .show_ui(ui, |ui| {
+                        for b in InputBackend::ALL {
+                            ui.selectable_value(&mut backend, b, b.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Switch how input of the selected client is sent, the client stays \
+                        connected. Buttons and keys held down are released.",
+                    );
+                if backend != current {
+                    client.lock().unwrap().input_backend = backend;
+                }
+            });
+        });
+
+        ui.separator();
+        ui.label(RichText::new("Preview").strong());
+        self.preview.ui(ui);
+    }
+
+    fn uinput_error_ui(&mut self, ctx: &egui::Context) {
+        if !self.uinput_inaccessible.load(Ordering::Relaxed) {
+            return;
+        }
+        let modal = egui::Modal::new(egui::Id::new("uinput_inaccessible")).show(ctx, |ui| {
+            ui.set_max_width(500.0);
+            ui.heading("UInput inaccessible!");
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    ui.label(std::include_str!("../strings/uinput_error.txt"));
+                });
+            ui.button("Close").clicked()
+        });
+        if modal.inner || modal.should_close() {
+            self.uinput_inaccessible.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn approval_ui(&mut self, ctx: &egui::Context) {
+        let Some(approval) = self.approvals.first() else {
+            return;
+        };
+        let id = approval.id;
+        let modal = egui::Modal::new(egui::Id::new("approval")).show(ctx, |ui| {
+            ui.set_max_width(400.0);
+            ui.heading("New Device");
+            ui.label(format!(
+                "{} connects to {} for the first time.",
+                approval.address, approval.instance
+            ));
+            if let Some(user_agent) = &approval.user_agent {
+                ui.weak(user_agent);
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Allow").clicked() {
+                    return Some(Decision::Allow);
+                }
+                if ui
+                    .button("Allow and Remember")
+                    .on_hover_text("Do not ask again when this device connects.")
+                    .clicked()
+                {
+                    return Some(Decision::Remember);
+                }
+                ui.button("Deny").clicked().then_some(Decision::Deny)
+            })
+            .inner
+        });
+        let decision = match modal.inner {
+            Some(decision) => decision,
+            None if modal.should_close() => Decision::Deny,
+            None => return,
+        };
+        crate::approval::decide(id, decision);
+        self.approvals.retain(|a| a.id != id);
+    }
+}
+
+impl eframe::App for WeylusApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.refresh_clients(ctx);
+        let exclude = !self.config.capture_self;
+        if self.excluded_from_capture != Some(exclude) {
+            capture_exclusion::exclude_from_capture(frame, exclude);
+            self.excluded_from_capture = Some(exclude);
+        }
+
+        egui::SidePanel::left("settings_panel")
+            .resizable(false)
+            .default_width(300.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| self.settings_ui(ui));
+            });
+        egui::TopBottomPanel::bottom("log_panel")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Log").strong());
+                egui::ScrollArea::vertical()
+                    .id_salt("log")
+                    .stick_to_bottom(true)
+                    .auto_shrink([false; 2])
+                    .show(ui, |ui| {
+                        ui.label(RichText::new(self.log.lock().unwrap().as_str()).monospace());
+                    });
+            });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .id_salt("central")
+                .show(ui, |ui| self.clients_ui(ui));
+        });
+
+        self.uinput_error_ui(ctx);
+        self.approval_ui(ctx);
+        input_area::show_viewport(ctx, &mut self.input_area_picker);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // gracefully shut down the web server
+        self.weylus.stop();
+    }
+}
+
+fn theme_preference(theme: ThemeType) -> egui::ThemePreference {
+    match theme {
+        ThemeType::System => egui::ThemePreference::System,
+        ThemeType::Light => egui::ThemePreference::Light,
+        ThemeType::Dark => egui::ThemePreference::Dark,
+    }
+}
+
+fn latency_preset_name(preset: LatencyPreset) -> &'static str {
+    match preset {
+        LatencyPreset::LowestLatency => "Lowest latency",
+        LatencyPreset::Balanced => "Balanced",
+        LatencyPreset::BestQuality => "Best quality",
+    }
+}
+
+/// QR code of the address including the access code, None if the address is unknown.
+fn qr_code(ctx: &egui::Context, addr: &str, config: &Config) -> Option<egui::TextureHandle> {
+    use qrcode::QrCode;
+
+    if config.bind_address.is_unspecified() && cfg!(target_os = "windows") {
+        return None;
+    }
+    let mut url_string = addr.to_string();
+    if let Some(access_code) = &config.access_code {
+        // the fragment is not sent to the server, the page reads it to encrypt the websocket
+        url_string.push_str(if config.encrypt_websocket {
+            "#access_code="
+        } else {
+            "?access_code="
+        });
+        url_string.push_str(
+            &percent_encoding::utf8_percent_encode(access_code, percent_encoding::NON_ALPHANUMERIC)
+                .to_string(),
+        );
+    }
+    let code = match QrCode::new(&url_string) {
+        Ok(code) => code,
+        Err(err) => {
+            error!("Failed to create QR code: {err}");
+            return None;
+        }
+    };
+    let image = code.render::<image::Luma<u8>>().build();
+    let rgb: Vec<u8> = image.pixels().flat_map(|p| [p.0[0]; 3]).collect();
+    let image = egui::ColorImage::from_rgb([image.width() as usize, image.height() as usize], &rgb);
+    Some(ctx.load_texture("qr_code", image, egui::TextureOptions::NEAREST))
+}
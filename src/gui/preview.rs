@@ -0,0 +1,159 @@
+//! Small preview of what the selected client is watching, captured independently of the client's
+//! video stream at a low rate.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use eframe::egui;
+use tracing::debug;
+
+use crate::capturable::{get_capturables, Capturable};
+use crate::video::PixelProvider;
+
+const PREVIEW_WIDTH: usize = 320;
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(500);
+
+enum PreviewUpdate {
+    Frame(egui::ColorImage),
+    Unavailable(String),
+}
+
+pub struct Preview {
+    capturable: Option<String>,
+    receiver: Option<mpsc::Receiver<PreviewUpdate>>,
+    stop: Arc<AtomicBool>,
+    texture: Option<egui::TextureHandle>,
+    message: Option<String>,
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        Self {
+            capturable: None,
+            receiver: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            texture: None,
+            message: None,
+        }
+    }
+
+    /// Preview the capturable with the given name, restarts capturing if it changed.
+    pub fn set_capturable(&mut self, name: Option<String>, ctx: &egui::Context) {
+        if name == self.capturable {
+            return;
+        }
+        self.stop.store(true, Ordering::Relaxed);
+        self.texture = None;
+        self.message = None;
+        self.receiver = None;
+        self.capturable = name.clone();
+        let Some(name) = name else {
+            return;
+        };
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop = stop.clone();
+        self.receiver = Some(receiver);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || capture_preview(&name, sender, stop, ctx));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(receiver) = &self.receiver {
+            while let Ok(update) = receiver.try_recv() {
+                match update {
+                    PreviewUpdate::Frame(image) => match &mut self.texture {
+                        Some(texture) => texture.set(image, egui::TextureOptions::LINEAR),
+                        None => {
+                            self.texture = Some(ui.ctx().load_texture(
+                                "preview",
+                                image,
+                                egui::TextureOptions::LINEAR,
+                            ))
+                        }
+                    },
+                    PreviewUpdate::Unavailable(message) => self.message = Some(message),
+                }
+            }
+        }
+        match (&self.texture, &self.message) {
+            (_, Some(message)) => {
+                ui.weak(message);
+            }
+            (Some(texture), None) => {
+                let size = texture.size_vec2();
+                let size = size * (ui.available_width() / size.x).min(1.0);
+                ui.add(egui::Image::from_texture(egui::load::SizedTexture::new(
+                    texture.id(),
+                    size,
+                )));
+            }
+            (None, None) if self.capturable.is_some() => {
+                ui.spinner();
+            }
+            (None, None) => {
+                ui.weak("Select a client to preview what it is watching.");
+            }
+        }
+    }
+}
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn find_capturable(name: &str) -> Option<Box<dyn Capturable>> {
+    // PipeWire streams are not listed as requesting them asks the user for permission again
+    #[cfg(target_os = "linux")]
+    let capturables = get_capturables(false, false, Default::default());
+    #[cfg(not(target_os = "linux"))]
+    let capturables = get_capturables();
+    capturables.into_iter().find(|c| c.name() == name)
+}
+
+fn capture_preview(
+    name: &str,
+    sender: mpsc::SyncSender<PreviewUpdate>,
+    stop: Arc<AtomicBool>,
+    ctx: egui::Context,
+) {
+    let unavailable = |message: String| {
+        sender.send(PreviewUpdate::Unavailable(message)).ok();
+        ctx.request_repaint();
+    };
+    let Some(capturable) = find_capturable(name) else {
+        unavailable(format!("No preview available for {name}."));
+        return;
+    };
+    let mut recorder = match capturable.recorder(false) {
+        Ok(recorder) => recorder,
+        Err(err) => {
+            unavailable(format!("Failed to capture {name}: {err}"));
+            return;
+        }
+    };
+    while !stop.load(Ordering::Relaxed) {
+        let image = match recorder.capture() {
+            Ok(pixels) => downscale(&pixels),
+            Err(err) => {
+                debug!("Failed to capture preview of {name}: {err}");
+                None
+            }
+        };
+        if let Some(image) = image {
+            match sender.try_send(PreviewUpdate::Frame(image)) {
+                Err(mpsc::TrySendError::Disconnected(_)) => return,
+                _ => ctx.request_repaint(),
+            }
+        }
+        std::thread::sleep(PREVIEW_INTERVAL);
+    }
+}
+
+fn downscale(pixels: &PixelProvider) -> Option<egui::ColorImage> {
+    let (width, height, rgb) = crate::thumbnail::downscale(pixels, PREVIEW_WIDTH)?;
+    Some(egui::ColorImage::from_rgb([width, height], &rgb))
+}
@@ -0,0 +1,223 @@
+//! Limits of the levels of H.264. Decoders support frames up to the size their level allows and
+//! fail silently on larger ones, so captures exceeding the highest level allowed, e.g. 8K ones,
+//! are scaled down and the level a video needs is set on the encoder, which puts it into the init
+//! segment for clients to check.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct H264Level {
+    /// level_idc, ten times the level, e.g. 51 for level 5.1.
+    pub idc: u8,
+    /// Macroblocks per second.
+    max_mbps: u64,
+    /// Macroblocks per frame.
+    max_fs: u64,
+}
+
+/// Table A-1 of the H.264 specification, levels below 3 are too small to be of any use here.
+const LEVELS: [H264Level; 12] = [
+    H264Level::new(30, 40_500, 1_620),
+    H264Level::new(31, 108_000, 3_600),
+    H264Level::new(32, 216_000, 5_120),
+    H264Level::new(40, 245_760, 8_192),
+    H264Level::new(41, 245_760, 8_192),
+    H264Level::new(42, 522_240, 8_704),
+    H264Level::new(50, 589_824, 22_080),
+    H264Level::new(51, 983_040, 36_864),
+    H264Level::new(52, 2_073_600, 36_864),
+    H264Level::new(60, 4_177_920, 139_264),
+    H264Level::new(61, 8_355_840, 139_264),
+    H264Level::new(62, 16_711_680, 139_264),
+];
+
+/// Macroblocks of a frame of the given size.
+fn macroblocks(width: usize, height: usize) -> u64 {
+    (width.div_ceil(16) * height.div_ceil(16)) as u64
+}
+
+impl H264Level {
+    const fn new(idc: u8, max_mbps: u64, max_fs: u64) -> Self {
+        Self {
+            idc,
+            max_mbps,
+            max_fs,
+        }
+    }
+
+    /// Level 5.2, which hardware decoders of phones and tablets support nowadays. It fits 4K and
+    /// 5120x1440 but not 8K.
+    pub fn default_max() -> Self {
+        LEVELS[8]
+    }
+
+    /// Whether frames of the given size fit the level, besides the number of macroblocks neither
+    /// side may exceed the square root of 8 times the maximum frame size in macroblocks.
+    pub fn fits(&self, width: usize, height: usize) -> bool {
+        let max_side = ((8 * self.max_fs) as f64).sqrt() as u64;
+        macroblocks(width, height) <= self.max_fs
+            && width.div_ceil(16) as u64 <= max_side
+            && height.div_ceil(16) as u64 <= max_side
+    }
+
+    /// The lowest level up to `max` fitting frames of the given size at the given frame rate.
+    /// Frames too large for all levels up to `max` get None, if only the frame rate is too high
+    /// `max` is returned, the frame rate is dropped by the decoder then.
+    pub fn required(width: usize, height: usize, frame_rate: f64, max: Self) -> Option<Self> {
+        let fitting = || {
+            LEVELS
+                .into_iter()
+                .filter(move |l| l.idc <= max.idc && l.fits(width, height))
+        };
+        let rate = macroblocks(width, height) as f64 * frame_rate;
+        fitting()
+            .find(|l| rate <= l.max_mbps as f64)
+            .or_else(|| fitting().last())
+    }
+
+    /// Factor of at most 1 frames of the given size have to be scaled by to fit the level.
+    pub fn scale_to_fit(&self, width: usize, height: usize) -> f64 {
+        if width == 0 || height == 0 || self.fits(width, height) {
+            return 1.0;
+        }
+        let max_side = (((8 * self.max_fs) as f64).sqrt() * 16.0).floor();
+        let mut scale = (self.max_fs as f64 / macroblocks(width, height) as f64)
+            .sqrt()
+            .min(max_side / width as f64)
+            .min(max_side / height as f64);
+        // rounding up to whole macroblocks may still exceed the level
+        while !self.fits(
+            (width as f64 * scale) as usize,
+            (height as f64 * scale) as usize,
+        ) {
+            scale *= 0.99;
+        }
+        scale
+    }
+}
+
+impl fmt::Display for H264Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.idc / 10, self.idc % 10)
+    }
+}
+
+impl FromStr for H264Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let levels = || {
+            LEVELS
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let idc = match s.split_once('.') {
+            Some((major, minor)) => format!("{major}{minor}"),
+            None => format!("{s}0"),
+        };
+        LEVELS
+            .into_iter()
+            .find(|l| l.idc.to_string() == idc)
+            .ok_or_else(|| format!("Unknown H.264 level {s}, supported are: {}.", levels()))
+    }
+}
+
+impl TryFrom<String> for H264Level {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<H264Level> for String {
+    fn from(level: H264Level) -> Self {
+        level.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(s: &str) -> H264Level {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!(level("5.2").idc, 52);
+        assert_eq!(level("6").idc, 60);
+        assert_eq!(level("4.1").to_string(), "4.1");
+        assert!("5.3".parse::<H264Level>().is_err());
+        assert!("".parse::<H264Level>().is_err());
+    }
+
+    #[test]
+    fn ultra_wide_fits_default_level() {
+        let max = H264Level::default_max();
+        assert!(max.fits(5120, 1440));
+        assert_eq!(max.scale_to_fit(5120, 1440), 1.0);
+        assert_eq!(
+            H264Level::required(5120, 1440, 30.0, max),
+            Some(level("5.1"))
+        );
+        assert_eq!(
+            H264Level::required(5120, 1440, 60.0, max),
+            Some(level("5.2"))
+        );
+    }
+
+    #[test]
+    fn uhd_levels() {
+        let max = H264Level::default_max();
+        assert_eq!(
+            H264Level::required(3840, 2160, 30.0, max),
+            Some(level("5.1"))
+        );
+        assert_eq!(
+            H264Level::required(1920, 1080, 60.0, max),
+            Some(level("4.2"))
+        );
+        // the frame rate only picks the level, it never rules out a size
+        assert_eq!(H264Level::required(3840, 2160, 240.0, max), Some(max));
+    }
+
+    #[test]
+    fn eight_k_is_scaled_down() {
+        let max = H264Level::default_max();
+        assert!(!max.fits(7680, 4320));
+        assert_eq!(H264Level::required(7680, 4320, 30.0, max), None);
+        let scale = max.scale_to_fit(7680, 4320);
+        assert!(scale < 1.0);
+        let (width, height) = ((7680.0 * scale) as usize, (4320.0 * scale) as usize);
+        assert!(max.fits(width, height));
+        // not scaled down further than needed
+        assert!(width > 3800 && height > 2100);
+    }
+
+    #[test]
+    fn eight_k_fits_level_6() {
+        let max = level("6.2");
+        assert_eq!(max.scale_to_fit(7680, 4320), 1.0);
+        assert_eq!(
+            H264Level::required(7680, 4320, 30.0, max),
+            Some(level("6.0"))
+        );
+    }
+
+    #[test]
+    fn extreme_aspect_ratios_respect_the_side_limit() {
+        let max = H264Level::default_max();
+        // few macroblocks but wider than any side allowed
+        assert!(!max.fits(15360, 400));
+        let scale = max.scale_to_fit(15360, 400);
+        assert!(max.fits((15360.0 * scale) as usize, (400.0 * scale) as usize));
+    }
+}
@@ -0,0 +1,146 @@
+//! Commands run and webhooks called on events of a session, so for example the audio output can
+//! be switched or OBS started once the tablet connects.
+//!
+//! Hooks are configured in the configuration file only:
+//!
+//! ```toml
+//! [[hooks]]
+//! event = "client-connected"
+//! command = "pactl set-default-sink tablet"
+//!
+//! [[hooks]]
+//! event = "stream-started"
+//! url = "http://localhost:8080/weylus"
+//! ```
+//!
+//! Commands get the details of the event as environment variables and as JSON in
+//! `WEYLUS_EVENT_JSON`, webhooks get the JSON as body of a POST request.
+
+use std::process::Command;
+use std::thread::spawn;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::control::ClientStatus;
+use crate::websocket::ClientRole;
+
+/// Webhooks that do not answer within this time are given up.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEvent {
+    /// The client sent its first configuration.
+    ClientConnected,
+    /// A client that sent its configuration disconnected.
+    ClientDisconnected,
+    /// The client may control this computer, after connecting or once presentation mode ends.
+    ControlGranted,
+    /// The video of a capturable started, again whenever the client chooses another one.
+    StreamStarted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub event: HookEvent,
+    /// Run by the shell, `sh -c` or `cmd /C` on Windows.
+    pub command: Option<String>,
+    /// Url the details of the event are POSTed to as JSON.
+    pub url: Option<String>,
+}
+
+/// Passed to hooks.
+#[derive(Serialize, Debug)]
+struct EventDetails<'a> {
+    event: HookEvent,
+    address: String,
+    name: Option<&'a str>,
+    role: ClientRole,
+    capturable: Option<&'a str>,
+}
+
+impl EventDetails<'_> {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let event = serde_json::to_value(self.event).unwrap();
+        let role = serde_json::to_value(self.role).unwrap();
+        let mut env = vec![
+            (
+                "WEYLUS_EVENT",
+                event.as_str().unwrap_or_default().to_string(),
+            ),
+            ("WEYLUS_CLIENT_ADDRESS", self.address.clone()),
+            (
+                "WEYLUS_CLIENT_ROLE",
+                role.as_str().unwrap_or_default().to_string(),
+            ),
+            ("WEYLUS_EVENT_JSON", serde_json::to_string(self).unwrap()),
+        ];
+        if let Some(name) = self.name {
+            env.push(("WEYLUS_CLIENT_NAME", name.to_string()));
+        }
+        if let Some(capturable) = self.capturable {
+            env.push(("WEYLUS_CAPTURABLE", capturable.to_string()));
+        }
+        env
+    }
+}
+
+fn run_command(command: &str, env: &[(&str, String)]) {
+    #[cfg(target_os = "windows")]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    match cmd.envs(env.iter().map(|(k, v)| (*k, v.as_str()))).status() {
+        Ok(status) if status.success() => debug!("Hook {command:?} finished."),
+        Ok(status) => warn!("Hook {command:?} failed: {status}."),
+        Err(err) => warn!("Failed to run hook {command:?}: {err}."),
+    }
+}
+
+fn call_webhook(url: &str, body: &str) {
+    let result = ureq::post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(body);
+    match result {
+        Ok(_) => debug!("Called webhook {url}."),
+        Err(err) => warn!("Failed to call webhook {url}: {err}."),
+    }
+}
+
+/// Run the hooks of the event for the client in the background, hooks that fail are only logged.
+pub fn fire(hooks: &[Hook], event: HookEvent, status: &ClientStatus) {
+    let mut hooks = hooks.iter().filter(|hook| hook.event == event).peekable();
+    if hooks.peek().is_none() {
+        return;
+    }
+    let details = EventDetails {
+        event,
+        address: status.address.to_string(),
+        name: status.name.as_deref(),
+        role: status.role,
+        capturable: status.capturable.as_deref(),
+    };
+    let env = details.env();
+    let body = serde_json::to_string(&details).unwrap();
+    for hook in hooks {
+        if let Some(command) = hook.command.clone() {
+            let env = env.clone();
+            spawn(move || run_command(&command, &env));
+        }
+        if let Some(url) = hook.url.clone() {
+            let body = body.clone();
+            spawn(move || call_webhook(&url, &body));
+        }
+    }
+}
@@ -1,13 +1,21 @@
 use autopilot::geometry::Size;
+use autopilot::key::KeyCode;
 use autopilot::mouse;
 use autopilot::mouse::ScrollDirection;
 use autopilot::screen::size as screen_size;
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
 use tracing::warn;
 
-use crate::input::device::{InputDevice, InputDeviceType};
-use crate::protocol::{Button, KeyboardEvent, KeyboardEventType, PointerEvent, PointerEventType, WheelEvent};
+use crate::input::cleanup::{self, CleanupGuard};
+use crate::input::device::{ActivationPolicy, EraserPolicy, InputDevice, InputDeviceType};
+use crate::protocol::{
+    Button, KeyboardEvent, KeyboardEventType, PointerEvent, PointerEventType, WheelEvent,
+};
 
+use crate::capturable::transform::Rect;
 use crate::capturable::{Capturable, Geometry};
 
 #[cfg(target_os = "macos")]
@@ -24,6 +32,8 @@ const MOUSE_EVENT_SUBTYPE_TABLET_POINT: i64 = 1;
 // Tablet pointer types
 #[cfg(target_os = "macos")]
 const TABLET_POINTER_TYPE_PEN: i64 = 1;
+#[cfg(target_os = "macos")]
+const TABLET_POINTER_TYPE_ERASER: i64 = 3;
 
 // Capability mask bits from IOLLEvent.h - indicates what data the tablet provides
 // NX_TABLET_CAPABILITY_ABSXMASK = 0x0002
@@ -40,25 +50,120 @@ const VIRTUAL_TABLET_ID: i64 = 0x0001;
 #[cfg(target_os = "macos")]
 const VIRTUAL_DEVICE_ID: i64 = 1;
 
+fn map_key(code: &str) -> Option<KeyCode> {
+    match code {
+        "Escape" => Some(KeyCode::Escape),
+        "Enter" => Some(KeyCode::Return),
+        "Backspace" => Some(KeyCode::Backspace),
+        "Tab" => Some(KeyCode::Tab),
+        "Space" => Some(KeyCode::Space),
+        "CapsLock" => Some(KeyCode::CapsLock),
+        "F1" => Some(KeyCode::F1),
+        "F2" => Some(KeyCode::F2),
+        "F3" => Some(KeyCode::F3),
+        "F4" => Some(KeyCode::F4),
+        "F5" => Some(KeyCode::F5),
+        "F6" => Some(KeyCode::F6),
+        "F7" => Some(KeyCode::F7),
+        "F8" => Some(KeyCode::F8),
+        "F9" => Some(KeyCode::F9),
+        "F10" => Some(KeyCode::F10),
+        "F11" => Some(KeyCode::F11),
+        "F12" => Some(KeyCode::F12),
+        "F13" => Some(KeyCode::F13),
+        "F14" => Some(KeyCode::F14),
+        "F15" => Some(KeyCode::F15),
+        "F16" => Some(KeyCode::F16),
+        "F17" => Some(KeyCode::F17),
+        "F18" => Some(KeyCode::F18),
+        "F19" => Some(KeyCode::F19),
+        "F20" => Some(KeyCode::F20),
+        "F21" => Some(KeyCode::F21),
+        "F22" => Some(KeyCode::F22),
+        "F23" => Some(KeyCode::F23),
+        "F24" => Some(KeyCode::F24),
+        "Home" => Some(KeyCode::Home),
+        "ArrowUp" => Some(KeyCode::UpArrow),
+        "PageUp" => Some(KeyCode::PageUp),
+        "ArrowLeft" => Some(KeyCode::LeftArrow),
+        "ArrowRight" => Some(KeyCode::RightArrow),
+        "End" => Some(KeyCode::End),
+        "ArrowDown" => Some(KeyCode::DownArrow),
+        "PageDown" => Some(KeyCode::PageDown),
+        "Delete" => Some(KeyCode::Delete),
+        "ControlLeft" | "ControlRight" => Some(KeyCode::Control),
+        "AltLeft" | "AltRight" => Some(KeyCode::Alt),
+        "MetaLeft" | "MetaRight" => Some(KeyCode::Meta),
+        "ShiftLeft" | "ShiftRight" => Some(KeyCode::Shift),
+        _ => None,
+    }
+}
+
+/// Keys and buttons currently held down, these are released once the device is dropped or
+/// Weylus exits unexpectedly.
+#[derive(Default)]
+struct HeldInput {
+    left_button: bool,
+    right_button: bool,
+    /// Maps the code of a key to the characters it produced.
+    keys: HashMap<String, String>,
+}
+
+impl HeldInput {
+    fn release(&mut self) {
+        use autopilot::key::{Character, Code};
+
+        if self.left_button {
+            self.left_button = false;
+            mouse::toggle(mouse::Button::Left, false);
+        }
+        if self.right_button {
+            self.right_button = false;
+            mouse::toggle(mouse::Button::Right, false);
+        }
+        for (code, key) in self.keys.drain() {
+            match map_key(&code) {
+                Some(key) => autopilot::key::toggle(&Code(key), false, &[], 0),
+                None => {
+                    for c in key.chars() {
+                        autopilot::key::toggle(&Character(c), false, &[], 0);
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub struct AutoPilotDevice {
     capturable: Box<dyn Capturable>,
-    left_button_down: bool,
+    held: Arc<Mutex<HeldInput>>,
+    cleanup: CleanupGuard,
+    eraser_policy: EraserPolicy,
+    activation_policy: ActivationPolicy,
     #[cfg(target_os = "macos")]
     in_proximity: bool,
 }
 
 impl AutoPilotDevice {
     pub fn new(capturable: Box<dyn Capturable>) -> Self {
+        let held = Arc::new(Mutex::new(HeldInput::default()));
+        let cleanup = {
+            let held = held.clone();
+            cleanup::register(move || held.lock().unwrap_or_else(|err| err.into_inner()).release())
+        };
         Self {
             capturable,
-            left_button_down: false,
+            held,
+            cleanup,
+            eraser_policy: EraserPolicy::Tool,
+            activation_policy: ActivationPolicy::Always,
             #[cfg(target_os = "macos")]
             in_proximity: false,
         }
     }
 
     #[cfg(target_os = "macos")]
-    fn send_tablet_proximity_event(&self, entering: bool) {
+    fn send_tablet_proximity_event(&self, entering: bool, eraser: bool) {
         if let Ok(source) = CGEventSource::new(CGEventSourceStateID::HIDSystemState) {
             if let Ok(event) = CGEvent::new(source) {
                 event.set_type(CGEventType::TabletProximity);
@@ -66,22 +171,48 @@ impl AutoPilotDevice {
                 // Set proximity state (1 = entering, 0 = leaving)
                 event.set_integer_value_field(
                     EventField::TABLET_PROXIMITY_EVENT_ENTER_PROXIMITY,
-                    if entering { 1 } else { 0 }
+                    if entering { 1 } else { 0 },
                 );
 
                 // Set device identification
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_VENDOR_ID, VIRTUAL_VENDOR_ID);
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_TABLET_ID, VIRTUAL_TABLET_ID);
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_DEVICE_ID, VIRTUAL_DEVICE_ID);
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_SYSTEM_TABLET_ID, VIRTUAL_DEVICE_ID);
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_VENDOR_ID,
+                    VIRTUAL_VENDOR_ID,
+                );
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_TABLET_ID,
+                    VIRTUAL_TABLET_ID,
+                );
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_DEVICE_ID,
+                    VIRTUAL_DEVICE_ID,
+                );
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_SYSTEM_TABLET_ID,
+                    VIRTUAL_DEVICE_ID,
+                );
                 event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_POINTER_ID, 1);
 
-                // Set pointer type to pen
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_POINTER_TYPE, TABLET_POINTER_TYPE_PEN);
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_VENDOR_POINTER_TYPE, TABLET_POINTER_TYPE_PEN);
+                // Set pointer type to pen or eraser
+                let pointer_type = if eraser {
+                    TABLET_POINTER_TYPE_ERASER
+                } else {
+                    TABLET_POINTER_TYPE_PEN
+                };
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_POINTER_TYPE,
+                    pointer_type,
+                );
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_VENDOR_POINTER_TYPE,
+                    pointer_type,
+                );
 
                 // Set capability mask (indicates pressure support)
-                event.set_integer_value_field(EventField::TABLET_PROXIMITY_EVENT_CAPABILITY_MASK, TABLET_CAPABILITY_MASK);
+                event.set_integer_value_field(
+                    EventField::TABLET_PROXIMITY_EVENT_CAPABILITY_MASK,
+                    TABLET_CAPABILITY_MASK,
+                );
 
                 event.post(CGEventTapLocation::HID);
             }
@@ -91,17 +222,27 @@ impl AutoPilotDevice {
     #[cfg(target_os = "macos")]
     fn send_mouse_event(&self, event_type: CGEventType, x: f64, y: f64, pressure: f64) {
         let point = CGPoint::new(x, y);
+        let button = match event_type {
+            CGEventType::RightMouseDown
+            | CGEventType::RightMouseUp
+            | CGEventType::RightMouseDragged => CGMouseButton::Right,
+            _ => CGMouseButton::Left,
+        };
         if let Ok(source) = CGEventSource::new(CGEventSourceStateID::HIDSystemState) {
-            if let Ok(event) = CGEvent::new_mouse_event(source, event_type, point, CGMouseButton::Left) {
+            if let Ok(event) = CGEvent::new_mouse_event(source, event_type, point, button) {
                 // Set tablet subtype for pen-like input
-                event.set_integer_value_field(EventField::MOUSE_EVENT_SUB_TYPE, MOUSE_EVENT_SUBTYPE_TABLET_POINT);
+                event.set_integer_value_field(
+                    EventField::MOUSE_EVENT_SUB_TYPE,
+                    MOUSE_EVENT_SUBTYPE_TABLET_POINT,
+                );
 
                 // Set pressure values
                 event.set_double_value_field(EventField::MOUSE_EVENT_PRESSURE, pressure);
                 event.set_double_value_field(EventField::TABLET_EVENT_POINT_PRESSURE, pressure);
 
                 // Link to the virtual tablet device
-                event.set_integer_value_field(EventField::TABLET_EVENT_DEVICE_ID, VIRTUAL_DEVICE_ID);
+                event
+                    .set_integer_value_field(EventField::TABLET_EVENT_DEVICE_ID, VIRTUAL_DEVICE_ID);
 
                 event.post(CGEventTapLocation::HID);
             }
@@ -123,14 +264,17 @@ impl InputDevice for AutoPilotDevice {
         if !event.is_primary {
             return;
         }
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self
+            .activation_policy
+            .activate(event.event_type == PointerEventType::DOWN);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        let (x_rel, y_rel, width_rel, height_rel) = match self.capturable.geometry().unwrap() {
-            Geometry::Relative(x, y, width, height) => (x, y, width, height),
+        let area = match self.capturable.geometry().unwrap() {
+            Geometry::Relative(x, y, width, height) => Rect::new(x, y, width, height),
         };
-        let (_, _, width, height) = match crate::capturable::core_graphics::screen_coordsys() {
+        let desktop = match crate::capturable::core_graphics::screen_coordsys() {
             Ok(bounds) => bounds,
             Err(err) => {
                 warn!("Could not determine global coordinate system: {}", err);
@@ -138,40 +282,82 @@ impl InputDevice for AutoPilotDevice {
             }
         };
 
-        let screen_x = (event.x * width_rel + x_rel) * width;
-        let screen_y = (event.y * height_rel + y_rel) * height;
+        // displays left of or above the main display have negative coordinates
+        let (x, y) = area.to_outer(event.x, event.y);
+        let (screen_x, screen_y) = desktop.to_outer(x, y);
 
         // Use CoreGraphics directly for proper drag support on macOS
         // Pressure from stylus (0.0 to 1.0)
         let pressure = event.pressure;
 
+        let eraser = event.buttons.contains(Button::ERASER) || event.button == Button::ERASER;
+
+        let mut held = self.held.lock().unwrap();
         match event.event_type {
             PointerEventType::DOWN => {
-                if !self.left_button_down {
+                if !held.left_button && !held.right_button {
                     // Send tablet proximity enter event before first touch
                     if !self.in_proximity {
                         self.in_proximity = true;
-                        self.send_tablet_proximity_event(true);
+                        self.send_tablet_proximity_event(
+                            true,
+                            eraser && self.eraser_policy == EraserPolicy::Tool,
+                        );
+                    }
+                    if eraser && self.eraser_policy == EraserPolicy::Button {
+                        held.right_button = true;
+                        self.send_mouse_event(
+                            CGEventType::RightMouseDown,
+                            screen_x,
+                            screen_y,
+                            pressure,
+                        );
+                    } else {
+                        held.left_button = true;
+                        self.send_mouse_event(
+                            CGEventType::LeftMouseDown,
+                            screen_x,
+                            screen_y,
+                            pressure,
+                        );
                     }
-                    self.left_button_down = true;
-                    self.send_mouse_event(CGEventType::LeftMouseDown, screen_x, screen_y, pressure);
                 }
             }
-            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE | PointerEventType::OUT => {
-                if self.left_button_down {
-                    self.left_button_down = false;
-                    self.send_mouse_event(CGEventType::LeftMouseUp, screen_x, screen_y, 0.0);
+            PointerEventType::UP
+            | PointerEventType::CANCEL
+            | PointerEventType::LEAVE
+            | PointerEventType::OUT => {
+                if held.left_button || held.right_button {
+                    if held.right_button {
+                        held.right_button = false;
+                        self.send_mouse_event(CGEventType::RightMouseUp, screen_x, screen_y, 0.0);
+                    } else {
+                        held.left_button = false;
+                        self.send_mouse_event(CGEventType::LeftMouseUp, screen_x, screen_y, 0.0);
+                    }
                     // Send tablet proximity leave event after pen lifts
                     if self.in_proximity {
                         self.in_proximity = false;
-                        self.send_tablet_proximity_event(false);
+                        self.send_tablet_proximity_event(false, false);
                     }
                 }
             }
             PointerEventType::MOVE | PointerEventType::OVER | PointerEventType::ENTER => {
                 // Key fix: use LeftMouseDragged when button is held, MouseMoved otherwise
-                if self.left_button_down {
-                    self.send_mouse_event(CGEventType::LeftMouseDragged, screen_x, screen_y, pressure);
+                if held.right_button {
+                    self.send_mouse_event(
+                        CGEventType::RightMouseDragged,
+                        screen_x,
+                        screen_y,
+                        pressure,
+                    );
+                } else if held.left_button {
+                    self.send_mouse_event(
+                        CGEventType::LeftMouseDragged,
+                        screen_x,
+                        screen_y,
+                        pressure,
+                    );
                 } else {
                     self.send_mouse_event(CGEventType::MouseMoved, screen_x, screen_y, 0.0);
                 }
@@ -184,12 +370,15 @@ impl InputDevice for AutoPilotDevice {
         if !event.is_primary {
             return;
         }
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self
+            .activation_policy
+            .activate(event.event_type == PointerEventType::DOWN);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        let (x_rel, y_rel, width_rel, height_rel) = match self.capturable.geometry().unwrap() {
-            Geometry::Relative(x, y, width, height) => (x, y, width, height),
+        let area = match self.capturable.geometry().unwrap() {
+            Geometry::Relative(x, y, width, height) => Rect::new(x, y, width, height),
             #[cfg(target_os = "windows")]
             _ => {
                 warn!("Failed to get window geometry, sending no input");
@@ -197,32 +386,45 @@ impl InputDevice for AutoPilotDevice {
             }
         };
         let Size { width, height } = screen_size();
-        if let Err(err) = mouse::move_to(autopilot::geometry::Point::new(
-            (event.x * width_rel + x_rel) * width,
-            (event.y * height_rel + y_rel) * height,
-        )) {
+        let (x, y) = area.to_outer(event.x, event.y);
+        if let Err(err) = mouse::move_to(autopilot::geometry::Point::new(x * width, y * height)) {
             warn!("Could not move mouse: {}", err);
         }
 
+        let eraser = event.buttons.contains(Button::ERASER) || event.button == Button::ERASER;
+
+        let mut held = self.held.lock().unwrap();
         match event.event_type {
             PointerEventType::DOWN => {
-                if !self.left_button_down {
-                    self.left_button_down = true;
+                if eraser && self.eraser_policy == EraserPolicy::Button {
+                    if !held.right_button {
+                        held.right_button = true;
+                        mouse::toggle(mouse::Button::Right, true);
+                    }
+                } else if !held.left_button {
+                    held.left_button = true;
                     mouse::toggle(mouse::Button::Left, true);
                 }
             }
-            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE | PointerEventType::OUT => {
-                if self.left_button_down {
-                    self.left_button_down = false;
+            PointerEventType::UP
+            | PointerEventType::CANCEL
+            | PointerEventType::LEAVE
+            | PointerEventType::OUT => {
+                if held.left_button {
+                    held.left_button = false;
                     mouse::toggle(mouse::Button::Left, false);
                 }
+                if held.right_button {
+                    held.right_button = false;
+                    mouse::toggle(mouse::Button::Right, false);
+                }
             }
             _ => {}
         }
     }
 
     fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
-        use autopilot::key::{Character, Code, KeyCode};
+        use autopilot::key::{Character, Code};
 
         let state = match event.event_type {
             KeyboardEventType::UP => false,
@@ -231,55 +433,15 @@ impl InputDevice for AutoPilotDevice {
             KeyboardEventType::REPEAT => return,
         };
 
-        fn map_key(code: &str) -> Option<KeyCode> {
-            match code {
-                "Escape" => Some(KeyCode::Escape),
-                "Enter" => Some(KeyCode::Return),
-                "Backspace" => Some(KeyCode::Backspace),
-                "Tab" => Some(KeyCode::Tab),
-                "Space" => Some(KeyCode::Space),
-                "CapsLock" => Some(KeyCode::CapsLock),
-                "F1" => Some(KeyCode::F1),
-                "F2" => Some(KeyCode::F2),
-                "F3" => Some(KeyCode::F3),
-                "F4" => Some(KeyCode::F4),
-                "F5" => Some(KeyCode::F5),
-                "F6" => Some(KeyCode::F6),
-                "F7" => Some(KeyCode::F7),
-                "F8" => Some(KeyCode::F8),
-                "F9" => Some(KeyCode::F9),
-                "F10" => Some(KeyCode::F10),
-                "F11" => Some(KeyCode::F11),
-                "F12" => Some(KeyCode::F12),
-                "F13" => Some(KeyCode::F13),
-                "F14" => Some(KeyCode::F14),
-                "F15" => Some(KeyCode::F15),
-                "F16" => Some(KeyCode::F16),
-                "F17" => Some(KeyCode::F17),
-                "F18" => Some(KeyCode::F18),
-                "F19" => Some(KeyCode::F19),
-                "F20" => Some(KeyCode::F20),
-                "F21" => Some(KeyCode::F21),
-                "F22" => Some(KeyCode::F22),
-                "F23" => Some(KeyCode::F23),
-                "F24" => Some(KeyCode::F24),
-                "Home" => Some(KeyCode::Home),
-                "ArrowUp" => Some(KeyCode::UpArrow),
-                "PageUp" => Some(KeyCode::PageUp),
-                "ArrowLeft" => Some(KeyCode::LeftArrow),
-                "ArrowRight" => Some(KeyCode::RightArrow),
-                "End" => Some(KeyCode::End),
-                "ArrowDown" => Some(KeyCode::DownArrow),
-                "PageDown" => Some(KeyCode::PageDown),
-                "Delete" => Some(KeyCode::Delete),
-                "ControlLeft" | "ControlRight" => Some(KeyCode::Control),
-                "AltLeft" | "AltRight" => Some(KeyCode::Alt),
-                "MetaLeft" | "MetaRight" => Some(KeyCode::Meta),
-                "ShiftLeft" | "ShiftRight" => Some(KeyCode::Shift),
-                _ => None,
+        let key = map_key(&event.code);
+        {
+            let mut held = self.held.lock().unwrap();
+            if state {
+                held.keys.insert(event.code.clone(), event.key.clone());
+            } else {
+                held.keys.remove(&event.code);
             }
         }
-        let key = map_key(&event.code);
         let mut flags = Vec::new();
         if event.ctrl {
             flags.push(autopilot::key::Flag::Control);
@@ -307,7 +469,26 @@ impl InputDevice for AutoPilotDevice {
         self.capturable = capturable;
     }
 
+    fn set_eraser_policy(&mut self, policy: EraserPolicy) {
+        self.eraser_policy = policy;
+    }
+
+    fn set_activation_policy(&mut self, policy: ActivationPolicy) {
+        self.activation_policy = policy;
+    }
+
     fn device_type(&self) -> InputDeviceType {
         InputDeviceType::AutoPilotDevice
     }
 }
+
+impl Drop for AutoPilotDevice {
+    fn drop(&mut self) {
+        if self.cleanup.unregister() {
+            self.held
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .release();
+        }
+    }
+}
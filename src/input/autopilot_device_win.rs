@@ -5,11 +5,12 @@ use winapi::um::winuser::*;
 use tracing::warn;
 
 use crate::input::autopilot_device::AutoPilotDevice;
-use crate::input::device::{InputDevice, InputDeviceType};
+use crate::input::device::{ActivationPolicy, InputDevice, InputDeviceType};
 use crate::protocol::{
     Button, KeyboardEvent, PointerEvent, PointerEventType, PointerType, WheelEvent,
 };
 
+use crate::capturable::transform::{geometry_rect, Rect};
 use crate::capturable::{Capturable, Geometry};
 
 pub struct WindowsInput {
@@ -18,6 +19,7 @@ pub struct WindowsInput {
     pointer_device_handle: *mut HSYNTHETICPOINTERDEVICE__,
     touch_device_handle: *mut HSYNTHETICPOINTERDEVICE__,
     multitouch_map: std::collections::HashMap<i64, POINTER_TYPE_INFO>,
+    activation_policy: ActivationPolicy,
 }
 
 impl WindowsInput {
@@ -30,6 +32,7 @@ impl WindowsInput {
                 pointer_device_handle: CreateSyntheticPointerDevice(PT_PEN, 1, 1),
                 touch_device_handle: CreateSyntheticPointerDevice(PT_TOUCH, 5, 1),
                 multitouch_map: std::collections::HashMap::new(),
+                activation_policy: ActivationPolicy::Always,
             }
         }
     }
@@ -41,20 +44,24 @@ impl InputDevice for WindowsInput {
     }
 
     fn send_pointer_event(&mut self, event: &PointerEvent) {
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self
+            .activation_policy
+            .activate(event.event_type == PointerEventType::DOWN);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        let Geometry::VirtualScreen(offset_x, offset_y, width, height, left, top) =
-            self.capturable.geometry().unwrap()
-        else {
+        let geometry = self.capturable.geometry().unwrap();
+        let Geometry::VirtualScreen(_, _, width, height, left, top) = geometry else {
             unreachable!()
         };
 
-        let (x, y) = (
-            (event.x * width as f64) as i32 + offset_x,
-            (event.y * height as f64) as i32 + offset_y,
-        );
+        // pointer injection takes positions on the virtual screen, mouse events on the screen
+        let (x, y) = geometry_rect(&geometry).to_outer(event.x, event.y);
+        let (x, y) = (x as i32, y as i32);
+        let (screen_x, screen_y) = Rect::new(left.into(), top.into(), width.into(), height.into())
+            .to_outer(event.x, event.y);
+        let (screen_x, screen_y) = (screen_x as i32, screen_y as i32);
         let mut pointer_flags = match event.event_type {
             PointerEventType::DOWN => {
                 POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_DOWN
@@ -170,11 +177,6 @@ impl InputDevice for WindowsInput {
             PointerType::Mouse => {
                 let mut dw_flags = 0;
 
-                let (screen_x, screen_y) = (
-                    (event.x * width as f64) as i32 + left,
-                    (event.y * height as f64) as i32 + top,
-                );
-
                 match event.event_type {
                     PointerEventType::DOWN => match event.buttons {
                         Button::PRIMARY => {
@@ -221,6 +223,11 @@ impl InputDevice for WindowsInput {
         self.capturable = capturable;
     }
 
+    fn set_activation_policy(&mut self, policy: ActivationPolicy) {
+        self.activation_policy = policy;
+        self.autopilot_device.set_activation_policy(policy);
+    }
+
     fn device_type(&self) -> InputDeviceType {
         InputDeviceType::WindowsInput
     }
@@ -0,0 +1,81 @@
+//! Emergency cleanup of input devices.
+//!
+//! Input devices normally clean up after themselves once they are dropped. This does not happen if
+//! the process is terminated by a signal or a panic on the main thread, which may leave the host
+//! with a stuck key or mouse button. Devices therefore register a callback here that releases
+//! everything held down and destroys virtual devices, these callbacks are run right before exiting.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, TryLockError};
+
+use tracing::warn;
+
+type Cleanup = Box<dyn FnMut() + Send>;
+
+static CLEANUPS: Mutex<BTreeMap<u64, Cleanup>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps a cleanup callback registered, dropping the guard unregisters it.
+pub struct CleanupGuard {
+    id: u64,
+}
+
+impl CleanupGuard {
+    /// Unregister the callback without running it. Returns false if the callback has already been
+    /// run by [`run_all`], in which case the device must not be cleaned up again.
+    pub fn unregister(&self) -> bool {
+        CLEANUPS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&self.id)
+            .is_some()
+    }
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+pub fn register(cleanup: impl FnMut() + Send + 'static) -> CleanupGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    CLEANUPS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(id, Box::new(cleanup));
+    CleanupGuard { id }
+}
+
+/// Run and unregister all cleanup callbacks.
+pub fn run_all() {
+    // The lock is held while running the callbacks, so devices that are dropped concurrently
+    // can not be cleaned up twice.
+    let mut cleanups = match CLEANUPS.try_lock() {
+        Ok(cleanups) => cleanups,
+        Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        // Either another thread is cleaning up already or a panic occurred while registering a
+        // callback, waiting could deadlock in the latter case.
+        Err(TryLockError::WouldBlock) => {
+            warn!("Cleanup of input devices already in progress or impossible.");
+            return;
+        }
+    };
+    while let Some((_, mut cleanup)) = cleanups.pop_first() {
+        cleanup();
+    }
+}
+
+/// Run all cleanup callbacks if the main thread panics, the process exits right afterwards without
+/// dropping devices owned by other threads. Panics on other threads don't need special handling
+/// as their devices are dropped while unwinding.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if std::thread::current().name() == Some("main") {
+            run_all();
+        }
+    }));
+}
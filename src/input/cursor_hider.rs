@@ -0,0 +1,85 @@
+//! Hide the cursor of the host while the pen is in use, the cursor trailing the pen is
+//! distracting, especially in full screen applications.
+
+#[cfg(target_os = "linux")]
+use std::os::raw::c_void;
+
+use tracing::{debug, warn};
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn hide_cursor() -> *mut c_void;
+    fn show_cursor(disp: *mut c_void);
+}
+
+pub struct CursorHider {
+    /// Connection to the X server that hides the cursor, closing it shows the cursor again.
+    #[cfg(target_os = "linux")]
+    disp: *mut c_void,
+    #[cfg(target_os = "macos")]
+    hidden: bool,
+}
+
+// the display connection is only used by the thread owning the CursorHider
+#[cfg(target_os = "linux")]
+unsafe impl Send for CursorHider {}
+
+impl CursorHider {
+    pub fn new() -> Self {
+        #[cfg(target_os = "windows")]
+        warn!("Hiding the cursor is not supported on Windows.");
+        Self {
+            #[cfg(target_os = "linux")]
+            disp: std::ptr::null_mut(),
+            #[cfg(target_os = "macos")]
+            hidden: false,
+        }
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        #[cfg(target_os = "linux")]
+        {
+            if hidden && self.disp.is_null() {
+                self.disp = unsafe { hide_cursor() };
+                if self.disp.is_null() {
+                    warn!("Failed to hide cursor, XFixes is not available.");
+                } else {
+                    debug!("Cursor hidden.");
+                }
+            } else if !hidden && !self.disp.is_null() {
+                unsafe { show_cursor(self.disp) };
+                self.disp = std::ptr::null_mut();
+                debug!("Cursor shown.");
+            }
+        }
+        #[cfg(target_os = "macos")]
+        {
+            use core_graphics::display::CGDisplay;
+            if hidden == self.hidden {
+                return;
+            }
+            let res = if hidden {
+                CGDisplay::main().hide_cursor()
+            } else {
+                CGDisplay::main().show_cursor()
+            };
+            match res {
+                Ok(()) => {
+                    self.hidden = hidden;
+                    debug!(hidden, "Cursor visibility changed.");
+                }
+                Err(err) => warn!("Failed to change cursor visibility: {err}."),
+            }
+        }
+        #[cfg(target_os = "windows")]
+        {
+            debug!(hidden, "Hiding the cursor is not supported on Windows.");
+        }
+    }
+}
+
+impl Drop for CursorHider {
+    fn drop(&mut self) {
+        self.set_hidden(false);
+    }
+}
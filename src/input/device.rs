@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use crate::capturable::Capturable;
 use crate::protocol::{KeyboardEvent, PointerEvent, WheelEvent};
 
@@ -5,8 +7,97 @@ use crate::protocol::{KeyboardEvent, PointerEvent, WheelEvent};
 pub enum InputDeviceType {
     AutoPilotDevice,
     UInputDevice,
+    #[cfg(target_os = "linux")]
+    PortalDevice,
     #[cfg(target_os = "windows")]
     WindowsInput,
+    /// Only logs the input, see [`MockInputDevice`](crate::input::mock_device::MockInputDevice).
+    Mock,
+}
+
+impl InputDeviceType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputDeviceType::AutoPilotDevice => "autopilot",
+            InputDeviceType::UInputDevice => "uinput",
+            #[cfg(target_os = "linux")]
+            InputDeviceType::PortalDevice => "RemoteDesktop portal",
+            #[cfg(target_os = "windows")]
+            InputDeviceType::WindowsInput => "SendInput",
+            InputDeviceType::Mock => "mock",
+        }
+    }
+}
+
+/// How input is sent to the host, only Linux offers a choice. Can be switched while clients are
+/// connected, their input device is replaced then.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputBackend {
+    /// Virtual devices created via uinput if the client enables them, the RemoteDesktop portal
+    /// while running sandboxed and XTest otherwise.
+    #[default]
+    Auto,
+    /// Virtual devices created via uinput, these support pressure, tilt and multitouch.
+    UInput,
+    /// The RemoteDesktop portal, this requires capturing via PipeWire.
+    Portal,
+    /// The XTest extension of the X server, only the mouse and keyboard are simulated.
+    XTest,
+}
+
+impl InputBackend {
+    pub const ALL: [InputBackend; 4] = [
+        InputBackend::Auto,
+        InputBackend::UInput,
+        InputBackend::Portal,
+        InputBackend::XTest,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputBackend::Auto => "Automatic",
+            InputBackend::UInput => "uinput",
+            InputBackend::Portal => "RemoteDesktop portal",
+            InputBackend::XTest => "XTest",
+        }
+    }
+}
+
+/// How the eraser end of the pen is passed to applications.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EraserPolicy {
+    /// The eraser is a distinct tool.
+    #[default]
+    Tool,
+    /// The eraser acts as a button of the pen.
+    Button,
+    /// The pen is used as is and a keyboard shortcut is pressed when switching to or from the
+    /// eraser.
+    Shortcut,
+}
+
+/// When the captured window is brought to the front before input is simulated.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ActivationPolicy {
+    /// Activate the window before any input.
+    #[default]
+    Always,
+    /// Only activate the window when a button is pressed or the screen is touched, hovering,
+    /// scrolling and typing leave the focus where it is.
+    OnClick,
+    /// Never activate the window, input goes to whatever window currently has the focus.
+    Never,
+}
+
+impl ActivationPolicy {
+    /// Whether the window is to be activated for input that is a click if `click` is set.
+    pub fn activate(self, click: bool) -> bool {
+        match self {
+            ActivationPolicy::Always => true,
+            ActivationPolicy::OnClick => click,
+            ActivationPolicy::Never => false,
+        }
+    }
 }
 
 pub trait InputDevice {
@@ -14,5 +105,8 @@ pub trait InputDevice {
     fn send_pointer_event(&mut self, event: &PointerEvent);
     fn send_keyboard_event(&mut self, event: &KeyboardEvent);
     fn set_capturable(&mut self, capturable: Box<dyn Capturable>);
+    /// Devices that do not support the policy keep treating the eraser as a distinct tool.
+    fn set_eraser_policy(&mut self, _policy: EraserPolicy) {}
+    fn set_activation_policy(&mut self, _policy: ActivationPolicy) {}
     fn device_type(&self) -> InputDeviceType;
 }
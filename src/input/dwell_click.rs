@@ -0,0 +1,168 @@
+//! Dwell clicking: resting the pointer while hovering clicks, for users who find it hard to press
+//! the pen onto the screen or to hold it there while dragging.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::protocol::{Button, PointerEvent, PointerEventType};
+
+/// Pressure of the clicks made for a pen, pens with no pressure are not considered touching the
+/// screen by some applications.
+const PEN_PRESSURE: f64 = 0.5;
+
+/// What resting the pointer does.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DwellAction {
+    #[default]
+    Click,
+    DoubleClick,
+    /// The first dwell presses the button, the next one releases it.
+    Drag,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DwellClickConfig {
+    /// Time the pointer has to rest before it clicks, dwell clicking is disabled if unset.
+    pub delay: Option<Duration>,
+    /// Distance the pointer may move while resting, relative to the size of the capturable.
+    pub radius: f64,
+    pub action: DwellAction,
+}
+
+/// Where the pointer came to rest.
+struct Anchor {
+    /// The event that started resting, clicks are made from it.
+    event: PointerEvent,
+    since: Instant,
+    /// A click has been made, the pointer has to move away before it clicks again.
+    fired: bool,
+}
+
+pub struct DwellClick {
+    config: DwellClickConfig,
+    anchor: Option<Anchor>,
+    /// The button is held down for dragging.
+    dragging: bool,
+    /// Timestamp of the last event of the client and when it arrived, clicks made on a timer get
+    /// timestamps that continue from it.
+    timestamp: (u64, Instant),
+}
+
+impl DwellClick {
+    pub fn new(config: DwellClickConfig) -> Self {
+        Self {
+            config,
+            anchor: None,
+            dragging: false,
+            timestamp: (0, Instant::now()),
+        }
+    }
+
+    /// The time at which [`DwellClick::timer`] has to be called next.
+    pub fn deadline(&self) -> Option<Instant> {
+        let delay = self.config.delay?;
+        self.anchor
+            .as_ref()
+            .filter(|a| !a.fired)
+            .map(|a| a.since + delay)
+    }
+
+    /// Turn an event of the client of the given type into one made by dwell clicking.
+    fn event(&self, event_type: PointerEventType, buttons: Button) -> Option<PointerEvent> {
+        let anchor = self.anchor.as_ref()?;
+        let (timestamp, since) = self.timestamp;
+        let mut event = anchor.event.clone();
+        event.event_type = event_type;
+        event.button = Button::PRIMARY;
+        event.buttons = buttons;
+        event.timestamp = timestamp + since.elapsed().as_micros() as u64;
+        if buttons.contains(Button::PRIMARY) {
+            event.pressure = event.pressure.max(PEN_PRESSURE);
+        } else {
+            event.pressure = 0.0;
+        }
+        Some(event)
+    }
+
+    fn release(&mut self) -> Vec<PointerEvent> {
+        if !self.dragging {
+            return Vec::new();
+        }
+        self.dragging = false;
+        self.event(PointerEventType::UP, Button::NONE)
+            .into_iter()
+            .collect()
+    }
+
+    /// Track the pointer, hovering events are marked as dragging while the button is held.
+    /// Returns events that have to be sent before the event itself, e.g. to end a drag once the
+    /// pointer left.
+    pub fn filter(&mut self, event: &mut PointerEvent) -> Vec<PointerEvent> {
+        if self.config.delay.is_none() {
+            return Vec::new();
+        }
+        self.timestamp = (event.timestamp, Instant::now());
+        match event.event_type {
+            PointerEventType::MOVE if event.buttons.is_empty() => {
+                let moved = self.anchor.as_ref().map_or(true, |a| {
+                    (event.x - a.event.x).hypot(event.y - a.event.y) > self.config.radius
+                });
+                if moved {
+                    self.anchor = Some(Anchor {
+                        event: event.clone(),
+                        since: Instant::now(),
+                        fired: false,
+                    });
+                }
+                if self.dragging {
+                    event.buttons = Button::PRIMARY;
+                    event.pressure = event.pressure.max(PEN_PRESSURE);
+                }
+                Vec::new()
+            }
+            PointerEventType::LEAVE | PointerEventType::OUT | PointerEventType::CANCEL => {
+                let release = self.release();
+                self.anchor = None;
+                release
+            }
+            // the user clicks on their own
+            PointerEventType::DOWN => {
+                self.dragging = false;
+                self.anchor = None;
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The events of the click that is due, if any.
+    pub fn timer(&mut self) -> Vec<PointerEvent> {
+        let Some(deadline) = self.deadline() else {
+            return Vec::new();
+        };
+        if Instant::now() < deadline {
+            return Vec::new();
+        }
+        if let Some(anchor) = self.anchor.as_mut() {
+            anchor.fired = true;
+        }
+        debug!("Dwell click: {:?}", self.config.action);
+        let down = |s: &Self| s.event(PointerEventType::DOWN, Button::PRIMARY);
+        let up = |s: &Self| s.event(PointerEventType::UP, Button::NONE);
+        let events = match self.config.action {
+            DwellAction::Click => vec![down(self), up(self)],
+            DwellAction::DoubleClick => vec![down(self), up(self), down(self), up(self)],
+            DwellAction::Drag if self.dragging => {
+                self.dragging = false;
+                vec![up(self)]
+            }
+            DwellAction::Drag => {
+                self.dragging = true;
+                vec![down(self)]
+            }
+        };
+        events.into_iter().flatten().collect()
+    }
+}
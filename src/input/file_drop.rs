@@ -0,0 +1,55 @@
+//! Drop files onto the application under the pointer of the host, as if they had been dragged
+//! there from a file manager.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::os::raw::c_char;
+
+#[cfg(target_os = "linux")]
+use crate::cerror::CError;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn xdnd_drop_uri_list(uri_list: *const c_char, err: *mut CError);
+}
+
+#[derive(Debug)]
+pub struct FileDropError(String);
+
+impl std::fmt::Display for FileDropError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for FileDropError {}
+
+/// Drop the files at the current position of the pointer, paths must be absolute.
+pub fn drop_files(paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    #[cfg(target_os = "linux")]
+    {
+        // text/uri-list requires CRLF line endings
+        let uri_list = paths
+            .iter()
+            .map(|path| url::Url::from_file_path(path).map(|url| format!("{url}\r\n")))
+            .collect::<Result<String, _>>()
+            .map_err(|_| FileDropError("Paths of dropped files must be absolute.".into()))?;
+        let uri_list = CString::new(uri_list)?;
+        let mut err = CError::new();
+        unsafe { xdnd_drop_uri_list(uri_list.as_ptr(), &mut err) };
+        if err.is_err() {
+            return Err(err.into());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = paths;
+        Err(FileDropError("Dropping files is only supported on Linux with X11.".into()).into())
+    }
+}
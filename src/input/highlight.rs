@@ -0,0 +1,92 @@
+//! Show the pointer of a client in presentation mode as a highlight on the host, instead of moving
+//! the host's pointer.
+
+#[cfg(target_os = "linux")]
+use std::os::raw::c_void;
+
+use tracing::warn;
+
+use crate::capturable::transform::Rect;
+use crate::capturable::{Capturable, Geometry};
+use crate::protocol::{PointerEvent, PointerEventType};
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn create_highlight() -> *mut c_void;
+    fn move_highlight(highlight: *mut c_void, x: f64, y: f64);
+    fn hide_highlight(highlight: *mut c_void);
+    fn destroy_highlight(highlight: *mut c_void);
+}
+
+pub struct PointerHighlight {
+    #[cfg(target_os = "linux")]
+    highlight: *mut c_void,
+}
+
+// the highlight is only used by the thread owning the PointerHighlight
+#[cfg(target_os = "linux")]
+unsafe impl Send for PointerHighlight {}
+
+impl PointerHighlight {
+    pub fn new() -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let highlight = unsafe { create_highlight() };
+            if highlight.is_null() {
+                warn!("Failed to create pointer highlight, XShape or XFixes is not available.");
+            }
+            Self { highlight }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            warn!("Highlighting the pointer is only supported on Linux.");
+            Self {}
+        }
+    }
+
+    /// Follow the pointer of the event within the capturable, the highlight is hidden once the
+    /// pointer leaves.
+    pub fn update(&mut self, event: &PointerEvent, capturable: &dyn Capturable) {
+        if matches!(
+            event.event_type,
+            PointerEventType::LEAVE | PointerEventType::OUT | PointerEventType::CANCEL
+        ) {
+            self.hide();
+            return;
+        }
+        // e.g. the monitor below the pointer or the offset of a region
+        let (x, y) = capturable.map_position(event.x, event.y);
+        let area = match capturable.geometry() {
+            Ok(Geometry::Relative(x, y, width, height)) => Rect::new(x, y, width, height),
+            #[cfg(target_os = "windows")]
+            Ok(_) => return,
+            Err(err) => {
+                warn!("Failed to get geometry for pointer highlight: {err}");
+                return;
+            }
+        };
+        let (x, y) = area.to_outer(x, y);
+        #[cfg(target_os = "linux")]
+        if !self.highlight.is_null() {
+            unsafe { move_highlight(self.highlight, x, y) };
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (x, y);
+    }
+
+    pub fn hide(&mut self) {
+        #[cfg(target_os = "linux")]
+        if !self.highlight.is_null() {
+            unsafe { hide_highlight(self.highlight) };
+        }
+    }
+}
+
+impl Drop for PointerHighlight {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if !self.highlight.is_null() {
+            unsafe { destroy_highlight(self.highlight) };
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Show an indicator on the host while a client has control, so anyone looking at the screen knows
+//! remote input is active. The indicator is shared by all clients and hidden once the last of them
+//! gives up control.
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// How the host shows that a client has control.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndicatorStyle {
+    /// Show nothing.
+    #[default]
+    Off,
+    /// Draw a colored border around the screen.
+    Border,
+    /// Show a colored dot in the top right corner of the screen.
+    Badge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndicatorConfig {
+    pub style: IndicatorStyle,
+    /// Any color X understands, e.g. #ff3030 or red.
+    pub color: String,
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn create_indicator(color: *const c_char, badge: c_int) -> *mut c_void;
+    fn show_indicator(indicator: *mut c_void);
+    fn hide_highlight(highlight: *mut c_void);
+    fn destroy_highlight(highlight: *mut c_void);
+}
+
+struct Indicator {
+    config: IndicatorConfig,
+    #[cfg(target_os = "linux")]
+    indicator: *mut c_void,
+}
+
+// the indicator is only accessed while holding the lock of INDICATOR
+#[cfg(target_os = "linux")]
+unsafe impl Send for Indicator {}
+
+impl Indicator {
+    fn new(config: IndicatorConfig) -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let indicator = match CString::new(config.color.as_str()) {
+                Ok(color) => unsafe {
+                    create_indicator(
+                        color.as_ptr(),
+                        (config.style == IndicatorStyle::Badge).into(),
+                    )
+                },
+                Err(_) => std::ptr::null_mut(),
+            };
+            if indicator.is_null() {
+                warn!("Failed to create control indicator, XShape or XFixes is not available.");
+            }
+            Self { config, indicator }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            warn!("Showing a control indicator is only supported on Linux.");
+            Self { config }
+        }
+    }
+
+    fn show(&self) {
+        #[cfg(target_os = "linux")]
+        if !self.indicator.is_null() {
+            unsafe { show_indicator(self.indicator) };
+        }
+    }
+
+    fn hide(&self) {
+        #[cfg(target_os = "linux")]
+        if !self.indicator.is_null() {
+            unsafe { hide_highlight(self.indicator) };
+        }
+    }
+}
+
+impl Drop for Indicator {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if !self.indicator.is_null() {
+            unsafe { destroy_highlight(self.indicator) };
+        }
+    }
+}
+
+struct IndicatorState {
+    /// Number of clients that currently have control.
+    controlling: usize,
+    indicator: Option<Indicator>,
+}
+
+static INDICATOR: Mutex<IndicatorState> = Mutex::new(IndicatorState {
+    controlling: 0,
+    indicator: None,
+});
+
+/// Keeps the indicator shown while a client has control, dropping it hides the indicator unless
+/// other clients still have control.
+pub struct ControlGuard(());
+
+impl ControlGuard {
+    pub fn new(config: &IndicatorConfig) -> Option<Self> {
+        if config.style == IndicatorStyle::Off {
+            return None;
+        }
+        let mut state = INDICATOR.lock().unwrap();
+        if state
+            .indicator
+            .as_ref()
+            .map_or(true, |i| i.config != *config)
+        {
+            // drop the old indicator first, the style or color changed
+            state.indicator = None;
+            state.indicator = Some(Indicator::new(config.clone()));
+        }
+        state.controlling += 1;
+        if let Some(indicator) = state.indicator.as_ref() {
+            indicator.show();
+        }
+        Some(Self(()))
+    }
+}
+
+impl Drop for ControlGuard {
+    fn drop(&mut self) {
+        let mut state = INDICATOR.lock().unwrap();
+        state.controlling -= 1;
+        if state.controlling == 0 {
+            if let Some(indicator) = state.indicator.as_ref() {
+                indicator.hide();
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Grid of keys and macros served at `/keypad`, turning a phone into a wireless numpad or macro
+//! pad. The page shows no video, tapping a key presses its key combinations on the host one after
+//! another.
+//!
+//! Without configuration a numpad is shown, other layouts are set in the configuration file:
+//!
+//! ```toml
+//! [keypad]
+//! columns = 3
+//!
+//! [[keypad.keys]]
+//! label = "Copy"
+//! keys = "Ctrl+C"
+//!
+//! [[keypad.keys]]
+//! label = "Duplicate line"
+//! keys = ["Home", "Shift+End", "Ctrl+C", "End", "Enter", "Ctrl+V"]
+//! span = 2
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::input::shortcuts::KeyCombo;
+use crate::protocol::KeyboardEvent;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Keypad {
+    pub columns: usize,
+    pub keys: Vec<KeypadKey>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeypadKey {
+    pub label: String,
+    /// Translations of the label by language code, like the labels of shortcuts.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Pressed in order, a single combination may be given without a list.
+    #[serde(deserialize_with = "one_or_many")]
+    pub keys: Vec<KeyCombo>,
+    /// Number of columns the key spans.
+    #[serde(default = "default_span")]
+    pub span: usize,
+}
+
+fn default_span() -> usize {
+    1
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<KeyCombo>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(KeyCombo),
+        Many(Vec<KeyCombo>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(combo) => vec![combo],
+        OneOrMany::Many(combos) => combos,
+    })
+}
+
+impl KeypadKey {
+    fn new(label: &str, keys: &str, span: usize) -> Self {
+        Self {
+            label: label.to_string(),
+            labels: BTreeMap::new(),
+            keys: vec![keys.parse().unwrap()],
+            span,
+        }
+    }
+
+    pub fn events(&self) -> Vec<KeyboardEvent> {
+        self.keys.iter().flat_map(KeyCombo::events).collect()
+    }
+}
+
+impl Default for Keypad {
+    /// A numpad.
+    fn default() -> Self {
+        let keys = [
+            ("Esc", "Escape", 1),
+            ("/", "NumpadDivide", 1),
+            ("*", "NumpadMultiply", 1),
+            ("-", "NumpadSubtract", 1),
+            ("7", "Numpad7", 1),
+            ("8", "Numpad8", 1),
+            ("9", "Numpad9", 1),
+            ("+", "NumpadAdd", 1),
+            ("4", "Numpad4", 1),
+            ("5", "Numpad5", 1),
+            ("6", "Numpad6", 1),
+            ("⌫", "Backspace", 1),
+            ("1", "Numpad1", 1),
+            ("2", "Numpad2", 1),
+            ("3", "Numpad3", 1),
+            ("⏎", "Enter", 1),
+            ("0", "Numpad0", 2),
+            (".", "Period", 1),
+            ("Tab", "Tab", 1),
+        ];
+        Self {
+            columns: 4,
+            keys: keys
+                .iter()
+                .map(|(label, keys, span)| KeypadKey::new(label, keys, *span))
+                .collect(),
+        }
+    }
+}
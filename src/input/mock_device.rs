@@ -0,0 +1,77 @@
+use tracing::info;
+
+use crate::capturable::Capturable;
+use crate::input::device::{ActivationPolicy, EraserPolicy, InputDevice, InputDeviceType};
+use crate::protocol::{KeyboardEvent, PointerEvent, WheelEvent};
+
+/// Logs the input it gets instead of simulating it, used when replaying recorded sessions. The
+/// events are logged as they reach the device, after filtering and mapping.
+pub struct MockInputDevice {
+    capturable: Box<dyn Capturable>,
+    eraser_policy: EraserPolicy,
+    activation_policy: ActivationPolicy,
+}
+
+impl MockInputDevice {
+    pub fn new(capturable: Box<dyn Capturable>) -> Self {
+        info!("Mock input device for {}.", capturable.name());
+        Self {
+            capturable,
+            eraser_policy: EraserPolicy::default(),
+            activation_policy: ActivationPolicy::default(),
+        }
+    }
+}
+
+impl InputDevice for MockInputDevice {
+    fn send_wheel_event(&mut self, event: &WheelEvent) {
+        info!("Wheel: dx: {}, dy: {}", event.dx, event.dy);
+    }
+
+    fn send_pointer_event(&mut self, event: &PointerEvent) {
+        info!(
+            "Pointer: {:?} {:?} id: {}, x: {:.4}, y: {:.4}, pressure: {:.3}, tilt: ({}, {}), \
+            button: {:?}, buttons: {:?}",
+            event.pointer_type,
+            event.event_type,
+            event.pointer_id,
+            event.x,
+            event.y,
+            event.pressure,
+            event.tilt_x,
+            event.tilt_y,
+            event.button,
+            event.buttons
+        );
+    }
+
+    fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
+        info!(
+            "Key: {:?} {} ({}) alt: {}, ctrl: {}, shift: {}, meta: {}",
+            event.event_type, event.code, event.key, event.alt, event.ctrl, event.shift, event.meta
+        );
+    }
+
+    fn set_capturable(&mut self, capturable: Box<dyn Capturable>) {
+        info!("Mock input device switched to {}.", capturable.name());
+        self.capturable = capturable;
+    }
+
+    fn set_eraser_policy(&mut self, policy: EraserPolicy) {
+        if policy != self.eraser_policy {
+            info!("Eraser policy: {policy:?}");
+            self.eraser_policy = policy;
+        }
+    }
+
+    fn set_activation_policy(&mut self, policy: ActivationPolicy) {
+        if policy != self.activation_policy {
+            info!("Activation policy: {policy:?}");
+            self.activation_policy = policy;
+        }
+    }
+
+    fn device_type(&self) -> InputDeviceType {
+        InputDeviceType::Mock
+    }
+}
@@ -1,10 +1,34 @@
 pub mod autopilot_device;
+pub mod cleanup;
+pub mod cursor_hider;
 pub mod device;
+pub mod dwell_click;
+pub mod file_drop;
+pub mod highlight;
+pub mod indicator;
+pub mod keypad;
+pub mod mock_device;
+pub mod pointer_filter;
+pub mod precision;
+pub mod prediction;
+pub mod profiles;
+pub mod rate_limit;
+pub mod sensors;
+pub mod shortcuts;
+pub mod switch_control;
+pub mod text_filter;
+pub mod viewport;
 
 #[cfg(target_os = "windows")]
 pub mod autopilot_device_win;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub mod portal_device;
 #[cfg(target_os = "linux")]
 pub mod uinput_device;
 #[cfg(target_os = "linux")]
+pub mod uinput_holder;
+#[cfg(target_os = "linux")]
 #[allow(dead_code)]
 pub mod uinput_keys;
+#[cfg(target_os = "linux")]
+pub mod xi2_master;
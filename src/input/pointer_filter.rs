@@ -0,0 +1,200 @@
+//! Filters applied to pointer events before they are passed to the input devices, these work the
+//! same for all backends and do not rely on heuristics of the client.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::protocol::{Button, PointerEvent, PointerEventType, PointerType, WheelEvent};
+
+/// Vertical distance the pen has to move for a single step of the scroll wheel, relative to the
+/// height of the capturable.
+const PEN_SCROLL_STEP: f64 = 0.02;
+
+/// A button of the pen that can be assigned an action.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PenButton {
+    /// The button on the barrel of the pen.
+    Barrel,
+    /// The eraser end of the pen.
+    Eraser,
+}
+
+impl PenButton {
+    pub fn button(self) -> Button {
+        match self {
+            Self::Barrel => Button::SECONDARY,
+            Self::Eraser => Button::ERASER,
+        }
+    }
+}
+
+/// The result of filtering a pointer event.
+pub enum FilteredEvent {
+    Pointer(PointerEvent),
+    Wheel(WheelEvent),
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct PointerFilterConfig {
+    /// Touch contacts starting within this time after the last pen event are ignored.
+    pub palm_rejection: Option<Duration>,
+    /// Only ignore touch contacts within this distance of the pen, relative to the size of the
+    /// capturable. If unset touch contacts are ignored everywhere.
+    pub palm_rejection_radius: Option<f64>,
+    /// The pen only counts as touching the screen once its pressure reaches this threshold.
+    pub pen_down_pressure: f64,
+    /// Pressure below this value is reported as zero.
+    pub pen_hover_pressure: f64,
+    /// Holding this button turns vertical movement of the pen into scroll wheel events.
+    pub pen_scroll_button: Option<PenButton>,
+}
+
+pub struct PointerFilter {
+    config: PointerFilterConfig,
+    /// Time and position of the last pen event.
+    last_pen_event: Option<(Instant, f64, f64)>,
+    /// Touch contacts that have been rejected, all their events are dropped until they end.
+    rejected_touches: HashSet<i64>,
+    /// The client reports the pen as touching the screen.
+    pen_pressed: bool,
+    /// A pointerdown event has been passed on for the pen.
+    pen_down: bool,
+    /// Vertical position of the pen at the last scroll step while scrolling.
+    scroll_origin: Option<f64>,
+}
+
+impl PointerFilter {
+    pub fn new(config: PointerFilterConfig) -> Self {
+        Self {
+            config,
+            last_pen_event: None,
+            rejected_touches: HashSet::new(),
+            pen_pressed: false,
+            pen_down: false,
+            scroll_origin: None,
+        }
+    }
+
+    pub fn set_config(&mut self, config: PointerFilterConfig) {
+        self.config = config;
+    }
+
+    /// Apply the pressure thresholds, light touches below the pen down pressure are turned into
+    /// hover events until the pressure is high enough.
+    fn apply_pressure_thresholds(&mut self, event: &mut PointerEvent) {
+        if event.pressure < self.config.pen_hover_pressure {
+            event.pressure = 0.0;
+        }
+        let above_threshold = event.pressure >= self.config.pen_down_pressure;
+        match event.event_type {
+            PointerEventType::DOWN => {
+                self.pen_pressed = true;
+                if above_threshold {
+                    self.pen_down = true;
+                } else {
+                    event.event_type = PointerEventType::MOVE;
+                }
+            }
+            PointerEventType::MOVE => {
+                if self.pen_pressed && !self.pen_down && above_threshold {
+                    trace!(
+                        pressure = event.pressure,
+                        "Pen down after reaching threshold."
+                    );
+                    event.event_type = PointerEventType::DOWN;
+                    event.button = Button::PRIMARY;
+                    self.pen_down = true;
+                }
+            }
+            PointerEventType::UP
+            | PointerEventType::CANCEL
+            | PointerEventType::LEAVE
+            | PointerEventType::OUT => {
+                self.pen_pressed = false;
+                self.pen_down = false;
+            }
+            PointerEventType::OVER | PointerEventType::ENTER => (),
+        }
+    }
+
+    /// Returns whether the event is consumed by scrolling and the wheel event to send, if any.
+    fn pen_scroll(&mut self, event: &PointerEvent) -> (bool, Option<WheelEvent>) {
+        let Some(button) = self.config.pen_scroll_button.map(PenButton::button) else {
+            return (false, None);
+        };
+        // the release of the button itself is consumed as well
+        if !event.buttons.contains(button) && event.button != button {
+            if self.scroll_origin.take().is_some() {
+                trace!("Pen scrolling ended.");
+            }
+            return (false, None);
+        }
+        let origin = *self.scroll_origin.get_or_insert(event.y);
+        let steps = ((event.y - origin) / PEN_SCROLL_STEP).trunc();
+        if steps == 0.0 {
+            return (true, None);
+        }
+        self.scroll_origin = Some(origin + steps * PEN_SCROLL_STEP);
+        // the content follows the pen, moving the pen down scrolls up
+        let wheel_event = WheelEvent {
+            dx: 0,
+            dy: -steps as i32,
+            timestamp: event.timestamp,
+        };
+        (true, Some(wheel_event))
+    }
+
+    fn reject_touch(&self, event: &PointerEvent) -> bool {
+        let (Some(window), Some((time, x, y))) = (self.config.palm_rejection, self.last_pen_event)
+        else {
+            return false;
+        };
+        if time.elapsed() > window {
+            return false;
+        }
+        match self.config.palm_rejection_radius {
+            Some(radius) => (event.x - x).hypot(event.y - y) <= radius,
+            None => true,
+        }
+    }
+
+    /// Returns the event that should be passed to the input device, if any.
+    pub fn filter(&mut self, mut event: PointerEvent) -> Option<FilteredEvent> {
+        match event.pointer_type {
+            PointerType::Pen => {
+                self.last_pen_event = Some((Instant::now(), event.x, event.y));
+                let (consumed, wheel_event) = self.pen_scroll(&event);
+                if consumed {
+                    return wheel_event.map(FilteredEvent::Wheel);
+                }
+                self.apply_pressure_thresholds(&mut event);
+            }
+            PointerType::Touch => {
+                if self.rejected_touches.contains(&event.pointer_id) {
+                    // pointerleave is the last event of a contact
+                    if event.event_type == PointerEventType::LEAVE {
+                        self.rejected_touches.remove(&event.pointer_id);
+                    }
+                    return None;
+                }
+                let new_contact = matches!(
+                    event.event_type,
+                    PointerEventType::OVER | PointerEventType::ENTER | PointerEventType::DOWN
+                );
+                if new_contact && self.reject_touch(&event) {
+                    trace!(
+                        pointer_id = event.pointer_id,
+                        "Touch rejected near the pen."
+                    );
+                    self.rejected_touches.insert(event.pointer_id);
+                    return None;
+                }
+            }
+            PointerType::Mouse | PointerType::Unknown => (),
+        }
+        Some(FilteredEvent::Pointer(event))
+    }
+}
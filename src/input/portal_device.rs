@@ -0,0 +1,335 @@
+//! Input device sending events through the RemoteDesktop portal, this requires neither access to
+//! uinput nor to the X server and thus works inside a sandbox.
+
+use std::cmp::Ordering;
+use std::time::Duration;
+
+use dbus::arg::PropMap;
+use dbus::blocking::Proxy;
+use tracing::{debug, warn};
+
+use crate::capturable::pipewire::{get_portal, RemoteDesktopSession};
+use crate::capturable::remote_desktop_dbus::OrgFreedesktopPortalRemoteDesktop;
+use crate::capturable::Capturable;
+use crate::input::device::{InputDevice, InputDeviceType};
+use crate::input::uinput_device::map_key;
+use crate::input::uinput_keys::*;
+use crate::protocol::{
+    Button, KeyboardEvent, KeyboardEventType, PointerEvent, PointerEventType, PointerType,
+    WheelEvent,
+};
+
+// button codes of the Linux input subsystem, used by the portal
+const BTN_LEFT: i32 = 0x110;
+const BTN_RIGHT: i32 = 0x111;
+const BTN_MIDDLE: i32 = 0x112;
+
+// axes of notify_pointer_axis_discrete
+const AXIS_VERTICAL: u32 = 0;
+const AXIS_HORIZONTAL: u32 = 1;
+
+const NUM_TOUCH_SLOTS: usize = 10;
+
+pub struct PortalInputDevice {
+    session: RemoteDesktopSession,
+    capturable: Box<dyn Capturable>,
+    buttons: Button,
+    touches: [Option<i64>; NUM_TOUCH_SLOTS],
+    /// Key codes held down, released once the device is dropped.
+    keys: Vec<i32>,
+}
+
+impl PortalInputDevice {
+    pub fn new(capturable: Box<dyn Capturable>, session: RemoteDesktopSession) -> Self {
+        if session.size.is_none() {
+            warn!("The portal did not report the size of the stream, pointer input may be off.");
+        }
+        Self {
+            session,
+            capturable,
+            buttons: Button::NONE,
+            touches: Default::default(),
+            keys: Vec::new(),
+        }
+    }
+
+    fn portal(&self) -> Proxy<'_, &dbus::blocking::SyncConnection> {
+        let mut portal = get_portal(&self.session.conn);
+        portal.timeout = Duration::from_millis(100);
+        portal
+    }
+
+    /// Convert coordinates relative to the stream to the logical coordinates of the compositor.
+    fn transform(&self, x: f64, y: f64) -> (f64, f64) {
+        let (width, height) = self.session.size.unwrap_or((1.0, 1.0));
+        (x * width, y * height)
+    }
+
+    fn log_err(res: Result<(), dbus::Error>) {
+        if let Err(err) = res {
+            warn!("Failed to send input via the RemoteDesktop portal: {err}");
+        }
+    }
+
+    fn find_slot(&self, id: i64) -> Option<usize> {
+        self.touches.iter().position(|t| *t == Some(id))
+    }
+
+    fn send_touch_event(&mut self, event: &PointerEvent) {
+        let (x, y) = self.transform(event.x, event.y);
+        let session = self.session.session.clone();
+        let stream = self.session.stream;
+        match event.event_type {
+            PointerEventType::DOWN => {
+                let Some(slot) = self.touches.iter().position(Option::is_none) else {
+                    debug!("No free touch slot, ignoring touch {}.", event.pointer_id);
+                    return;
+                };
+                self.touches[slot] = Some(event.pointer_id);
+                Self::log_err(self.portal().notify_touch_down(
+                    session,
+                    PropMap::new(),
+                    stream,
+                    slot as u32,
+                    x,
+                    y,
+                ));
+            }
+            PointerEventType::MOVE => {
+                if let Some(slot) = self.find_slot(event.pointer_id) {
+                    Self::log_err(self.portal().notify_touch_motion(
+                        session,
+                        PropMap::new(),
+                        stream,
+                        slot as u32,
+                        x,
+                        y,
+                    ));
+                }
+            }
+            PointerEventType::UP
+            | PointerEventType::CANCEL
+            | PointerEventType::LEAVE
+            | PointerEventType::OUT => {
+                if let Some(slot) = self.find_slot(event.pointer_id) {
+                    self.touches[slot] = None;
+                    Self::log_err(self.portal().notify_touch_up(
+                        session,
+                        PropMap::new(),
+                        slot as u32,
+                    ));
+                }
+            }
+            PointerEventType::OVER | PointerEventType::ENTER => (),
+        }
+    }
+
+    fn send_mouse_event(&mut self, event: &PointerEvent) {
+        let (x, y) = self.transform(event.x, event.y);
+        let session = self.session.session.clone();
+        Self::log_err(self.portal().notify_pointer_motion_absolute(
+            session.clone(),
+            PropMap::new(),
+            self.session.stream,
+            x,
+            y,
+        ));
+        // a pen touching the screen acts as the primary button
+        let buttons = match event.event_type {
+            PointerEventType::CANCEL | PointerEventType::LEAVE | PointerEventType::OUT => {
+                Button::NONE
+            }
+            _ => event.buttons,
+        };
+        for (button, code) in [
+            (Button::PRIMARY, BTN_LEFT),
+            (Button::SECONDARY, BTN_RIGHT),
+            (Button::AUXILARY, BTN_MIDDLE),
+        ] {
+            let pressed = buttons.contains(button);
+            if pressed != self.buttons.contains(button) {
+                Self::log_err(self.portal().notify_pointer_button(
+                    session.clone(),
+                    PropMap::new(),
+                    code,
+                    pressed as u32,
+                ));
+            }
+        }
+        self.buttons = buttons;
+    }
+
+    fn before_input(&mut self) -> bool {
+        // windows shared through the portal are not activated, the compositor decides about focus
+        if let Err(err) = self.capturable.before_input(false) {
+            warn!("Failed to activate window, sending no input ({})", err);
+            return false;
+        }
+        // the session is set up anew if the compositor restarted
+        match self.capturable.remote_desktop_session() {
+            Some(session) if session.session != self.session.session => {
+                debug!("RemoteDesktop session changed.");
+                self.buttons = Button::NONE;
+                self.touches = Default::default();
+                self.keys.clear();
+                self.session = session;
+            }
+            _ => (),
+        }
+        true
+    }
+
+    fn send_key(&mut self, key_code: i32, pressed: bool) {
+        self.keys.retain(|k| *k != key_code);
+        if pressed {
+            self.keys.push(key_code);
+        }
+        Self::log_err(self.portal().notify_keyboard_keycode(
+            self.session.session.clone(),
+            PropMap::new(),
+            key_code,
+            pressed as u32,
+        ));
+    }
+}
+
+impl Drop for PortalInputDevice {
+    fn drop(&mut self) {
+        // release everything held down, e.g. if the input backend is switched mid-stroke
+        let session = self.session.session.clone();
+        for key_code in std::mem::take(&mut self.keys) {
+            self.send_key(key_code, false);
+        }
+        for slot in 0..NUM_TOUCH_SLOTS {
+            if self.touches[slot].take().is_some() {
+                Self::log_err(self.portal().notify_touch_up(
+                    session.clone(),
+                    PropMap::new(),
+                    slot as u32,
+                ));
+            }
+        }
+        for (button, code) in [
+            (Button::PRIMARY, BTN_LEFT),
+            (Button::SECONDARY, BTN_RIGHT),
+            (Button::AUXILARY, BTN_MIDDLE),
+        ] {
+            if self.buttons.contains(button) {
+                Self::log_err(self.portal().notify_pointer_button(
+                    session.clone(),
+                    PropMap::new(),
+                    code,
+                    0,
+                ));
+            }
+        }
+    }
+}
+
+/// Keysym of a unicode character, see Appendix A of the X Window System Protocol.
+fn unicode_keysym(c: char) -> i32 {
+    let c = c as i32;
+    if (0x20..=0x7e).contains(&c) || (0xa0..=0xff).contains(&c) {
+        c
+    } else {
+        0x0100_0000 | c
+    }
+}
+
+impl InputDevice for PortalInputDevice {
+    fn send_wheel_event(&mut self, event: &WheelEvent) {
+        if !self.before_input() {
+            return;
+        }
+        fn direction(d: i32) -> i32 {
+            match d.cmp(&0) {
+                Ordering::Equal => 0,
+                Ordering::Less => -1,
+                Ordering::Greater => 1,
+            }
+        }
+        let session = self.session.session.clone();
+        // positive steps scroll down, unlike REL_WHEEL of uinput
+        for (axis, steps) in [
+            (AXIS_VERTICAL, -direction(event.dy)),
+            (AXIS_HORIZONTAL, direction(event.dx)),
+        ] {
+            if steps != 0 {
+                Self::log_err(self.portal().notify_pointer_axis_discrete(
+                    session.clone(),
+                    PropMap::new(),
+                    axis,
+                    steps,
+                ));
+            }
+        }
+    }
+
+    fn send_pointer_event(&mut self, event: &PointerEvent) {
+        if !self.before_input() {
+            return;
+        }
+        match event.pointer_type {
+            PointerType::Touch => self.send_touch_event(event),
+            PointerType::Mouse | PointerType::Pen | PointerType::Unknown => {
+                self.send_mouse_event(event)
+            }
+        }
+    }
+
+    fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
+        if !self.before_input() {
+            return;
+        }
+        let pressed = match event.event_type {
+            KeyboardEventType::UP => false,
+            KeyboardEventType::DOWN => true,
+            // the compositor repeats keys itself
+            KeyboardEventType::REPEAT => return,
+        };
+        let key_code = map_key(&event.code, &event.location);
+        if key_code == KEY_UNKNOWN {
+            // unlike uinput the portal accepts keysyms, so unknown keys can be typed directly
+            let mut chars = event.key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Self::log_err(self.portal().notify_keyboard_keysym(
+                    self.session.session.clone(),
+                    PropMap::new(),
+                    unicode_keysym(c),
+                    pressed as u32,
+                )),
+                _ => debug!(
+                    "Got unknow key: code: {} key: {}, ignoring event.",
+                    event.code, event.key
+                ),
+            }
+            return;
+        }
+        for (active, modifier) in [
+            (event.ctrl, KEY_LEFTCTRL),
+            (event.alt, KEY_LEFTALT),
+            (event.meta, KEY_LEFTMETA),
+            (event.shift, KEY_LEFTSHIFT),
+        ] {
+            if active && modifier != key_code {
+                self.send_key(modifier, pressed);
+            }
+        }
+        self.send_key(key_code, pressed);
+    }
+
+    fn set_capturable(&mut self, capturable: Box<dyn Capturable>) {
+        match capturable.remote_desktop_session() {
+            Some(session) => self.session = session,
+            None => warn!(
+                "{} does not belong to a remote desktop session, input goes to the previous one.",
+                capturable.name()
+            ),
+        }
+        self.capturable = capturable;
+    }
+
+    fn device_type(&self) -> InputDeviceType {
+        InputDeviceType::PortalDevice
+    }
+}
@@ -0,0 +1,105 @@
+//! Precision mode in the style of Wacom tablets: the whole surface of the tablet is mapped to a
+//! small region around the position of the pointer, so strokes can be placed more accurately.
+//!
+//! Pointer positions are relative to the capturable, mapping them here works the same for all
+//! input devices.
+
+use tracing::debug;
+
+use crate::input::pointer_filter::PenButton;
+use crate::protocol::{Button, PointerEvent, PointerEventType, PointerType};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PrecisionConfig {
+    /// Pressing this button of the pen toggles precision mode.
+    pub button: Option<PenButton>,
+    /// Size of the region the tablet is mapped to in precision mode, relative to the size of the
+    /// capturable.
+    pub region_size: f64,
+}
+
+pub struct PrecisionMapping {
+    config: PrecisionConfig,
+    /// Top left corner of the region the tablet is mapped to, None if precision mode is off.
+    origin: Option<(f64, f64)>,
+    /// Last position of the pointer on the capturable, precision mode is placed around it.
+    position: (f64, f64),
+    /// The toggle button is held down.
+    button_held: bool,
+}
+
+impl PrecisionMapping {
+    pub fn new(config: PrecisionConfig) -> Self {
+        Self {
+            config,
+            origin: None,
+            position: (0.5, 0.5),
+            button_held: false,
+        }
+    }
+
+    pub fn set_config(&mut self, config: PrecisionConfig) {
+        self.config = config;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    /// Switch precision mode on or off, returns whether it is on now.
+    pub fn toggle(&mut self) -> bool {
+        if self.origin.take().is_none() {
+            let size = self.config.region_size.clamp(0.01, 1.0);
+            let (x, y) = self.position;
+            // place the region such that the pointer stays where it is, this also keeps the
+            // region within the capturable
+            let origin = (x * (1.0 - size), y * (1.0 - size));
+            debug!("Precision mode on, mapping the tablet to {size} at {origin:?}.");
+            self.origin = Some(origin);
+        } else {
+            debug!("Precision mode off.");
+        }
+        self.enabled()
+    }
+
+    /// Map the position of the event to the capturable without remembering it, as for predicted
+    /// events.
+    pub fn map(&self, event: &mut PointerEvent) {
+        if let Some((x0, y0)) = self.origin {
+            let size = self.config.region_size.clamp(0.01, 1.0);
+            event.x = x0 + event.x * size;
+            event.y = y0 + event.y * size;
+        }
+    }
+
+    /// Map the event and toggle precision mode if the toggle button is pressed, returns None if
+    /// the event is consumed by the button and whether precision mode was toggled.
+    pub fn filter(&mut self, mut event: PointerEvent) -> (Option<PointerEvent>, bool) {
+        self.map(&mut event);
+        self.position = (event.x, event.y);
+        let Some(button) = self.config.button.map(PenButton::button) else {
+            return (Some(event), false);
+        };
+        if event.pointer_type != PointerType::Pen {
+            return (Some(event), false);
+        }
+        let held = event.buttons.contains(button);
+        let toggled = held && !self.button_held;
+        self.button_held = held;
+        if toggled {
+            self.toggle();
+        }
+        event.buttons.remove(button);
+        if event.button == button {
+            // the button itself must not reach applications, only its side effect
+            if matches!(
+                event.event_type,
+                PointerEventType::DOWN | PointerEventType::UP
+            ) {
+                return (None, toggled);
+            }
+            event.button = Button::NONE;
+        }
+        (Some(event), toggled)
+    }
+}
@@ -0,0 +1,79 @@
+//! Predicted pointer events as reported by the browser's `getPredictedEvents`, these estimate
+//! where the pointer is going to be and can reduce the perceived latency of fast strokes.
+
+use tracing::debug;
+
+use crate::protocol::{Button, PointerEvent, PointerEventType, PointerType};
+
+/// Keeps track of predicted events and how far off the predictions turn out to be.
+pub struct Predictor {
+    inject: bool,
+    /// The furthest prediction not yet compared with a real event.
+    pending: Option<PointerEvent>,
+    error_sum: f64,
+    error_count: u64,
+}
+
+impl Predictor {
+    pub fn new(inject: bool) -> Self {
+        Self {
+            inject,
+            pending: None,
+            error_sum: 0.0,
+            error_count: 0,
+        }
+    }
+
+    /// Predicted events are only injected if any error is corrected by the next real event,
+    /// this is the case for hovering as the position is absolute. Anything that draws or
+    /// clicks can not be undone.
+    fn harmless(event: &PointerEvent) -> bool {
+        event.event_type == PointerEventType::MOVE
+            && event.buttons == Button::NONE
+            && matches!(event.pointer_type, PointerType::Mouse | PointerType::Pen)
+    }
+
+    /// Record the predicted events, returns the event to inject, if any.
+    pub fn predicted(&mut self, events: Vec<PointerEvent>) -> Option<PointerEvent> {
+        let event = events.into_iter().last()?;
+        self.pending = Some(event.clone());
+        if self.inject && Self::harmless(&event) {
+            Some(event)
+        } else {
+            None
+        }
+    }
+
+    /// Compare a real event with the pending prediction.
+    pub fn real(&mut self, event: &PointerEvent) {
+        if event.event_type == PointerEventType::LEAVE && self.error_count > 0 {
+            debug!(
+                predictions = self.error_count,
+                mean_error = self.mean_error(),
+                "Prediction error of pointer events."
+            );
+        }
+        let Some(predicted) = self.pending.as_ref() else {
+            return;
+        };
+        if predicted.pointer_id != event.pointer_id {
+            self.pending = None;
+            return;
+        }
+        if event.timestamp >= predicted.timestamp {
+            self.error_sum += (event.x - predicted.x).hypot(event.y - predicted.y);
+            self.error_count += 1;
+            self.pending = None;
+        }
+    }
+
+    /// Mean distance between predicted and real positions relative to the size of the
+    /// capturable.
+    fn mean_error(&self) -> f64 {
+        if self.error_count == 0 {
+            0.0
+        } else {
+            self.error_sum / self.error_count as f64
+        }
+    }
+}
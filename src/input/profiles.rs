@@ -0,0 +1,114 @@
+//! Input profiles switched automatically depending on the application in the foreground, so for
+//! example a painting application and a terminal get different pen behavior.
+//!
+//! Profiles are configured in the configuration file only:
+//!
+//! ```toml
+//! [[input_profiles]]
+//! application = "krita"
+//! pressure_curve = 0.7
+//! pen_scroll_button = "Barrel"
+//! stylus_only = true
+//! ```
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::capturable::Capturable;
+use crate::input::device::EraserPolicy;
+use crate::input::pointer_filter::PenButton;
+use crate::protocol::{PointerEvent, PointerType};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputProfile {
+    /// The profile applies if the application's name, e.g. the window class on X11 or the name of
+    /// the executable on Windows, contains this string, ignoring case.
+    pub application: String,
+    /// Exponent applied to the pressure of the pen, values below 1 make light strokes heavier.
+    pub pressure_curve: Option<f64>,
+    /// Overrides the global `--pen-scroll-button`.
+    pub pen_scroll_button: Option<PenButton>,
+    /// Overrides the global `--precision-button`.
+    pub precision_button: Option<PenButton>,
+    /// Overrides the global `--eraser`.
+    pub eraser: Option<EraserPolicy>,
+    /// Ignore touch and mouse input, only the pen is passed on.
+    #[serde(default)]
+    pub stylus_only: bool,
+}
+
+impl InputProfile {
+    fn matches(&self, application: &str) -> bool {
+        application
+            .to_lowercase()
+            .contains(&self.application.to_lowercase())
+    }
+
+    /// Whether the event is passed on at all.
+    pub fn accepts(&self, event: &PointerEvent) -> bool {
+        !self.stylus_only || event.pointer_type == PointerType::Pen
+    }
+
+    pub fn apply_pressure_curve(&self, event: &mut PointerEvent) {
+        if let Some(exponent) = self.pressure_curve {
+            if event.pointer_type == PointerType::Pen && exponent > 0.0 {
+                event.pressure = event.pressure.clamp(0.0, 1.0).powf(exponent);
+            }
+        }
+    }
+}
+
+/// Picks the profile matching the application in the foreground, the first matching profile
+/// wins.
+pub struct ProfileSwitcher {
+    profiles: Vec<InputProfile>,
+    application: Option<String>,
+    active: Option<usize>,
+    /// Used while no profile matches, e.g. the stored settings of the client.
+    fallback: Option<InputProfile>,
+}
+
+impl ProfileSwitcher {
+    pub fn new(profiles: Vec<InputProfile>) -> Self {
+        Self {
+            profiles,
+            application: None,
+            active: None,
+            fallback: None,
+        }
+    }
+
+    pub fn set_fallback(&mut self, profile: Option<InputProfile>) {
+        self.fallback = profile;
+    }
+
+    pub fn active(&self) -> Option<&InputProfile> {
+        self.active
+            .map(|i| &self.profiles[i])
+            .or(self.fallback.as_ref())
+    }
+
+    /// Look up the application in the foreground, returns whether the active profile changed.
+    pub fn check(&mut self, capturable: &dyn Capturable) -> bool {
+        if self.profiles.is_empty() {
+            return false;
+        }
+        let application = capturable.application();
+        if application == self.application {
+            return false;
+        }
+        let active = application
+            .as_deref()
+            .and_then(|a| self.profiles.iter().position(|p| p.matches(a)));
+        self.application = application;
+        if active == self.active {
+            return false;
+        }
+        self.active = active;
+        match self.active.map(|i| &self.profiles[i]) {
+            Some(profile) => info!("Switched to input profile for {}.", profile.application),
+            None => info!("Switched to the default input profile."),
+        }
+        true
+    }
+}
@@ -0,0 +1,211 @@
+//! Count the input events of a client and limit their rate, this protects the host from clients
+//! flooding it with input, be it by malice or by a bug.
+
+use std::time::{Duration, Instant};
+
+/// Interval over which the rate of events is measured.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    /// Events per second allowed on average.
+    pub rate: f64,
+    /// Number of events allowed in a burst above the average rate.
+    pub burst: f64,
+}
+
+/// Input statistics of the last measurement interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputStats {
+    pub events_per_second: f64,
+    pub dropped: u64,
+}
+
+/// Token bucket refilled at the allowed rate, each event takes a token.
+pub struct InputRateLimiter {
+    limit: Option<RateLimitConfig>,
+    tokens: f64,
+    last_event: Instant,
+    interval_start: Instant,
+    events: u64,
+    dropped: u64,
+    /// Events of the whole session.
+    total_events: u64,
+    total_dropped: u64,
+}
+
+impl InputRateLimiter {
+    pub fn new(limit: Option<RateLimitConfig>) -> Self {
+        let now = Instant::now();
+        Self {
+            limit,
+            tokens: limit.map_or(0.0, |l| l.burst),
+            last_event: now,
+            interval_start: now,
+            events: 0,
+            dropped: 0,
+            total_events: 0,
+            total_dropped: 0,
+        }
+    }
+
+    /// Count an event, returns false if it exceeds the rate limit and should be dropped.
+    /// Essential events, like releasing a button, are never dropped as that would leave buttons
+    /// or keys pressed, they still take a token.
+    pub fn allow(&mut self, essential: bool) -> bool {
+        self.allow_at(essential, Instant::now())
+    }
+
+    fn allow_at(&mut self, essential: bool, now: Instant) -> bool {
+        self.events += 1;
+        self.total_events += 1;
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let elapsed = now.saturating_duration_since(self.last_event).as_secs_f64();
+        self.last_event = now;
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        if self.tokens >= 1.0 || essential {
+            self.tokens = (self.tokens - 1.0).max(0.0);
+            true
+        } else {
+            self.dropped += 1;
+            self.total_dropped += 1;
+            false
+        }
+    }
+
+    /// Events received and dropped over the whole session.
+    pub fn totals(&self) -> (u64, u64) {
+        (self.total_events, self.total_dropped)
+    }
+
+    /// Returns the statistics once per measurement interval.
+    pub fn stats(&mut self) -> Option<InputStats> {
+        self.stats_at(Instant::now())
+    }
+
+    fn stats_at(&mut self, now: Instant) -> Option<InputStats> {
+        let elapsed = now.saturating_duration_since(self.interval_start);
+        if elapsed < MEASURE_INTERVAL {
+            return None;
+        }
+        let stats = InputStats {
+            events_per_second: self.events as f64 / elapsed.as_secs_f64(),
+            dropped: self.dropped,
+        };
+        self.interval_start = now;
+        self.events = 0;
+        self.dropped = 0;
+        Some(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimitConfig = RateLimitConfig {
+        rate: 10.0,
+        burst: 5.0,
+    };
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut limiter = InputRateLimiter::new(None);
+        let start = limiter.last_event;
+        for _ in 0..1000 {
+            assert!(limiter.allow_at(false, start));
+        }
+        assert_eq!(limiter.totals(), (1000, 0));
+    }
+
+    #[test]
+    fn burst() {
+        let mut limiter = InputRateLimiter::new(Some(LIMIT));
+        let start = limiter.last_event;
+        for _ in 0..5 {
+            assert!(limiter.allow_at(false, start));
+        }
+        assert!(!limiter.allow_at(false, start));
+        assert!(!limiter.allow_at(false, start));
+        assert_eq!(limiter.totals(), (7, 2));
+    }
+
+    #[test]
+    fn refill() {
+        let mut limiter = InputRateLimiter::new(Some(LIMIT));
+        let start = limiter.last_event;
+        for _ in 0..5 {
+            assert!(limiter.allow_at(false, start));
+        }
+        assert!(!limiter.allow_at(false, start + millis(50)));
+        // a token every 100 ms
+        assert!(limiter.allow_at(false, start + millis(110)));
+        assert!(!limiter.allow_at(false, start + millis(150)));
+        assert!(limiter.allow_at(false, start + millis(220)));
+        // the bucket holds no more than the burst after a long pause
+        let later = start + Duration::from_secs(60);
+        for _ in 0..5 {
+            assert!(limiter.allow_at(false, later));
+        }
+        assert!(!limiter.allow_at(false, later));
+    }
+
+    #[test]
+    fn sustained_rate() {
+        let mut limiter = InputRateLimiter::new(Some(LIMIT));
+        let start = limiter.last_event;
+        // 100 events per second for 10 seconds, the burst and 10 events per second pass
+        let allowed = (1..=1000)
+            .filter(|i| limiter.allow_at(false, start + millis(i * 10)))
+            .count();
+        assert!((100..=106).contains(&allowed), "{allowed} events passed");
+    }
+
+    #[test]
+    fn essential_events_always_pass() {
+        let mut limiter = InputRateLimiter::new(Some(LIMIT));
+        let start = limiter.last_event;
+        for _ in 0..5 {
+            assert!(limiter.allow_at(false, start));
+        }
+        for _ in 0..100 {
+            assert!(limiter.allow_at(true, start));
+        }
+        assert!(!limiter.allow_at(false, start));
+        // essential events do not put the bucket into debt
+        assert!(limiter.allow_at(false, start + millis(110)));
+        assert_eq!(limiter.totals(), (107, 1));
+    }
+
+    #[test]
+    fn stats_per_interval() {
+        let mut limiter = InputRateLimiter::new(Some(LIMIT));
+        let start = limiter.interval_start;
+        for _ in 0..8 {
+            limiter.allow_at(false, start);
+        }
+        assert_eq!(limiter.stats_at(start + millis(500)), None);
+        assert_eq!(
+            limiter.stats_at(start + millis(2000)),
+            Some(InputStats {
+                events_per_second: 4.0,
+                dropped: 3,
+            })
+        );
+        assert_eq!(limiter.stats_at(start + millis(2500)), None);
+        assert_eq!(
+            limiter.stats_at(start + millis(3000)),
+            Some(InputStats {
+                events_per_second: 0.0,
+                dropped: 0,
+            })
+        );
+        assert_eq!(limiter.totals(), (8, 3));
+    }
+}
@@ -0,0 +1,130 @@
+//! Tilting the client as input: the orientation reported by the sensors of the client either moves
+//! the sticks of a virtual joystick, e.g. for games, or presses the shortcuts that orbit the 3D
+//! viewport of applications like Blender.
+//!
+//! Tilting is measured against the orientation the client had when it started sending, so the
+//! device can be held any way that is comfortable.
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::shortcuts::KeyCombo;
+use crate::protocol::{DeviceOrientation, KeyboardEvent};
+
+/// Tilting less than this many degrees is ignored, no hand holds a device perfectly still.
+const DEAD_ZONE: f64 = 2.0;
+
+/// What tilting the client does.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorMode {
+    /// Move the stick of a virtual joystick, only supported on Linux.
+    Joystick,
+    /// Press a shortcut that orbits the viewport each time the client is tilted a bit further.
+    Orbit,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SensorConfig {
+    /// Sensors are not used if unset.
+    pub mode: Option<SensorMode>,
+    /// Degrees of tilt that move the stick of the joystick all the way.
+    pub range: f64,
+    /// Degrees of tilt that press the orbit shortcut once.
+    pub step: f64,
+    /// Shortcuts orbiting left, right, up and down.
+    pub orbit_keys: [KeyCombo; 4],
+}
+
+impl SensorConfig {
+    /// Orbiting with the numpad like in Blender.
+    pub fn default_orbit_keys() -> [KeyCombo; 4] {
+        ["Numpad4", "Numpad6", "Numpad8", "Numpad2"].map(|key| key.parse().unwrap())
+    }
+}
+
+/// Output of [`SensorMapping::update`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorOutput {
+    /// Position of the stick, both axes range from -1 to 1 with positive values to the right
+    /// and towards the user.
+    Stick(f64, f64),
+    Keys(Vec<KeyboardEvent>),
+}
+
+pub struct SensorMapping {
+    config: SensorConfig,
+    /// Front to back and left to right tilt in degrees the client started at.
+    reference: Option<(f64, f64)>,
+    /// Steps of orbiting done to the right and towards the user since the reference.
+    orbited: (i64, i64),
+}
+
+/// Difference of two angles in degrees, wrapped to -180 to 180.
+fn angle_diff(a: f64, b: f64) -> f64 {
+    (a - b + 540.0).rem_euclid(360.0) - 180.0
+}
+
+impl SensorMapping {
+    pub fn new(config: SensorConfig) -> Self {
+        Self {
+            config,
+            reference: None,
+            orbited: (0, 0),
+        }
+    }
+
+    pub fn mode(&self) -> Option<SensorMode> {
+        self.config.mode
+    }
+
+    /// Ignore the orientation from now on.
+    pub fn disable(&mut self) {
+        self.config.mode = None;
+    }
+
+    /// Handle the orientation of the client, None if it stopped sending it. The next
+    /// orientation is the new reference then.
+    pub fn update(&mut self, orientation: Option<DeviceOrientation>) -> Option<SensorOutput> {
+        let mode = self.config.mode?;
+        let Some(orientation) = orientation else {
+            self.reference = None;
+            self.orbited = (0, 0);
+            return (mode == SensorMode::Joystick).then_some(SensorOutput::Stick(0.0, 0.0));
+        };
+        let (beta, gamma) = (orientation.beta, orientation.gamma);
+        if !beta.is_finite() || !gamma.is_finite() {
+            return None;
+        }
+        let (reference_beta, reference_gamma) = *self.reference.get_or_insert((beta, gamma));
+        let x = angle_diff(gamma, reference_gamma);
+        let y = angle_diff(beta, reference_beta);
+        match mode {
+            SensorMode::Joystick => {
+                let axis = |tilt: f64| {
+                    if tilt.abs() < DEAD_ZONE {
+                        0.0
+                    } else {
+                        (tilt / self.config.range).clamp(-1.0, 1.0)
+                    }
+                };
+                Some(SensorOutput::Stick(axis(x), axis(y)))
+            }
+            SensorMode::Orbit => {
+                let steps = |tilt: f64| (tilt / self.config.step).trunc() as i64;
+                let (steps_x, steps_y) = (steps(x), steps(y));
+                let [left, right, up, down] = &self.config.orbit_keys;
+                let mut events = Vec::new();
+                let mut orbit = |done: &mut i64, target: i64, less: &KeyCombo, more: &KeyCombo| {
+                    while *done != target {
+                        let key = if target > *done { more } else { less };
+                        events.extend(key.events());
+                        *done += (target - *done).signum();
+                    }
+                };
+                orbit(&mut self.orbited.0, steps_x, left, right);
+                // tilting the top of the device away orbits up
+                orbit(&mut self.orbited.1, steps_y, up, down);
+                (!events.is_empty()).then_some(SensorOutput::Keys(events))
+            }
+        }
+    }
+}
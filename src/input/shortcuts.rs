@@ -0,0 +1,153 @@
+//! Named sets of keyboard shortcuts defined on the host, e.g. for Krita or Blender. Clients show
+//! the shortcuts of the chosen set as a bar of buttons, tapping one presses its keys on the host.
+//!
+//! Sets are configured in the configuration file only:
+//!
+//! ```toml
+//! [[shortcut_sets]]
+//! name = "Krita"
+//!
+//! [[shortcut_sets.shortcuts]]
+//! label = "Undo"
+//! labels = { de = "Rückgängig", fr = "Annuler" }
+//! keys = "Ctrl+Z"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{KeyboardEvent, KeyboardEventType, KeyboardLocation};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShortcutSet {
+    pub name: String,
+    pub shortcuts: Vec<Shortcut>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shortcut {
+    /// Shown on the button.
+    pub label: String,
+    /// Translations of the label by language code, clients pick the one matching the language
+    /// of their browser.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub keys: KeyCombo,
+}
+
+/// Modifiers in the order they are pressed.
+const MODIFIERS: [(&str, &str); 4] = [
+    ("Ctrl", "ControlLeft"),
+    ("Alt", "AltLeft"),
+    ("Shift", "ShiftLeft"),
+    ("Meta", "MetaLeft"),
+];
+
+/// A key together with the modifiers held while pressing it, written like `Ctrl+Shift+Z`.
+/// Keys are given as codes of the physical key, e.g. `KeyZ`, `F5` or `BracketLeft`, single
+/// letters and digits are accepted as well.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyCombo {
+    /// Indices into `MODIFIERS`.
+    modifiers: Vec<usize>,
+    code: String,
+}
+
+fn parse_modifier(s: &str) -> Option<usize> {
+    let s = s.to_lowercase();
+    let name = match s.as_str() {
+        "ctrl" | "control" => "Ctrl",
+        "alt" | "option" => "Alt",
+        "shift" => "Shift",
+        "meta" | "super" | "cmd" | "win" => "Meta",
+        _ => return None,
+    };
+    MODIFIERS.iter().position(|(n, _)| *n == name)
+}
+
+impl KeyCombo {
+    /// The keyboard events pressing the shortcut: the modifiers are pressed first and released
+    /// in reverse order after the key.
+    pub fn events(&self) -> Vec<KeyboardEvent> {
+        let mut held = [false; MODIFIERS.len()];
+        let mut events = Vec::new();
+        let mut push = |code: &str, event_type, held: &[bool; MODIFIERS.len()], modifier| {
+            let mut event = KeyboardEvent::from_code(code, event_type);
+            if modifier {
+                event.location = KeyboardLocation::LEFT;
+            }
+            event.ctrl = held[0];
+            event.alt = held[1];
+            event.shift = held[2];
+            event.meta = held[3];
+            events.push(event);
+        };
+        for &m in &self.modifiers {
+            held[m] = true;
+            push(MODIFIERS[m].1, KeyboardEventType::DOWN, &held, true);
+        }
+        push(&self.code, KeyboardEventType::DOWN, &held, false);
+        push(&self.code, KeyboardEventType::UP, &held, false);
+        for &m in self.modifiers.iter().rev() {
+            held[m] = false;
+            push(MODIFIERS[m].1, KeyboardEventType::UP, &held, true);
+        }
+        events
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        if key.is_empty() {
+            return Err(format!("The shortcut {s} lacks a key."));
+        }
+        let mut modifiers = Vec::new();
+        for part in parts {
+            let m = parse_modifier(part).ok_or_else(|| {
+                format!("Unknown modifier {part}, expected one of Ctrl, Alt, Shift or Meta.")
+            })?;
+            if !modifiers.contains(&m) {
+                modifiers.push(m);
+            }
+        }
+        modifiers.sort_unstable();
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => format!("Key{}", c.to_ascii_uppercase()),
+            (Some(c), None) if c.is_ascii_digit() => format!("Digit{c}"),
+            _ => key.to_string(),
+        };
+        Ok(Self { modifiers, code })
+    }
+}
+
+impl TryFrom<String> for KeyCombo {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &m in &self.modifiers {
+            write!(f, "{}+", MODIFIERS[m].0)?;
+        }
+        f.write_str(&self.code)
+    }
+}
+
+impl From<KeyCombo> for String {
+    fn from(combo: KeyCombo) -> Self {
+        combo.to_string()
+    }
+}
@@ -0,0 +1,259 @@
+//! Switch control for users who can not use a regular mouse: taps on the client act as switches
+//! that step a highlight across a grid laid over the capturable. Selecting a cell zooms into it,
+//! selecting a cell at the finest level clicks its center.
+//!
+//! With a scan interval the highlight moves on by itself and a single switch, the whole screen of
+//! the client, is enough. Otherwise the screen is split into zones mapped to actions, by default
+//! the left half moves the highlight on and the right half selects. Zones can be configured in the
+//! configuration file:
+//!
+//! ```toml
+//! [[switch_zones]]
+//! area = { x = 0.0, y = 0.0, w = 1.0, h = 0.8 }
+//! action = "Select"
+//!
+//! [[switch_zones]]
+//! area = { x = 0.0, y = 0.8, w = 1.0, h = 0.2 }
+//! action = "Cancel"
+//! ```
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::protocol::{Button, PointerEvent, PointerEventType, Rect};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchAction {
+    /// Move the highlight to the next cell.
+    Next,
+    /// Move the highlight to the previous cell.
+    Previous,
+    /// Zoom into the highlighted cell or click it at the finest level.
+    Select,
+    /// Go back to the whole capturable.
+    Cancel,
+    /// Click the center of the highlighted cell right away.
+    Click,
+    RightClick,
+    DoubleClick,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SwitchZone {
+    /// Area of the client's screen, relative to its size.
+    pub area: Rect,
+    pub action: SwitchAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwitchConfig {
+    pub enabled: bool,
+    /// Move the highlight on by itself at this interval.
+    pub scan_interval: Option<Duration>,
+    /// Switches act once held this long, shorter taps are ignored.
+    pub dwell: Duration,
+    /// Number of rows and columns of the grid.
+    pub grid: u32,
+    /// How often a cell can be zoomed into before selecting it clicks.
+    pub levels: u32,
+    pub zones: Vec<SwitchZone>,
+}
+
+/// What has to be done on the host or the client after a switch acted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SwitchOutput {
+    /// Show the highlight at the given area of the capturable.
+    Highlight(Rect),
+    /// Click at the given position of the capturable.
+    Click {
+        x: f64,
+        y: f64,
+        button: Button,
+        count: u32,
+        timestamp: u64,
+    },
+}
+
+/// A switch held down.
+struct Press {
+    pointer_id: i64,
+    action: SwitchAction,
+    since: Instant,
+    /// The action has been performed, it is performed once per press.
+    done: bool,
+}
+
+pub struct SwitchControl {
+    config: SwitchConfig,
+    /// Area of the capturable that is split into the grid.
+    region: Rect,
+    level: u32,
+    cell: u32,
+    press: Option<Press>,
+    next_scan: Option<Instant>,
+    /// Timestamp of the last event of the client and when it arrived, clicks made on a timer get
+    /// timestamps that continue from it.
+    timestamp: (u64, Instant),
+}
+
+impl SwitchControl {
+    pub fn new(config: SwitchConfig) -> Self {
+        let next_scan = config.scan_interval.map(|i| Instant::now() + i);
+        Self {
+            config,
+            region: Rect::default(),
+            level: 0,
+            cell: 0,
+            press: None,
+            next_scan,
+            timestamp: (0, Instant::now()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// The time at which [`SwitchControl::timer`] has to be called next.
+    pub fn deadline(&self) -> Option<Instant> {
+        if !self.config.enabled {
+            return None;
+        }
+        let dwell = self
+            .press
+            .as_ref()
+            .filter(|p| !p.done)
+            .map(|p| p.since + self.config.dwell);
+        match (dwell, self.next_scan) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The area of the capturable currently highlighted.
+    pub fn highlight(&self) -> Rect {
+        self.cell_rect()
+    }
+
+    fn grid(&self) -> u32 {
+        self.config.grid.clamp(1, 10)
+    }
+
+    fn cell_rect(&self) -> Rect {
+        let grid = self.grid();
+        let w = self.region.w / grid as f64;
+        let h = self.region.h / grid as f64;
+        Rect {
+            x: self.region.x + (self.cell % grid) as f64 * w,
+            y: self.region.y + (self.cell / grid) as f64 * h,
+            w,
+            h,
+        }
+    }
+
+    fn action_at(&self, x: f64, y: f64) -> SwitchAction {
+        let zone = self.config.zones.iter().find(|z| {
+            (z.area.x..z.area.x + z.area.w).contains(&x)
+                && (z.area.y..z.area.y + z.area.h).contains(&y)
+        });
+        match zone {
+            Some(zone) => zone.action,
+            None if !self.config.zones.is_empty() => SwitchAction::Cancel,
+            None if self.config.scan_interval.is_some() => SwitchAction::Select,
+            None if x < 0.5 => SwitchAction::Next,
+            None => SwitchAction::Select,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.region = Rect::default();
+        self.level = 0;
+        self.cell = 0;
+    }
+
+    fn click(&mut self, button: Button, count: u32) -> SwitchOutput {
+        let cell = self.cell_rect();
+        self.reset();
+        let (timestamp, since) = self.timestamp;
+        SwitchOutput::Click {
+            x: cell.x + cell.w / 2.0,
+            y: cell.y + cell.h / 2.0,
+            button,
+            count,
+            timestamp: timestamp + since.elapsed().as_micros() as u64,
+        }
+    }
+
+    fn perform(&mut self, action: SwitchAction) -> Vec<SwitchOutput> {
+        debug!("Switch control: {action:?}");
+        let cells = self.grid() * self.grid();
+        let mut output = Vec::new();
+        match action {
+            SwitchAction::Next => self.cell = (self.cell + 1) % cells,
+            SwitchAction::Previous => self.cell = (self.cell + cells - 1) % cells,
+            SwitchAction::Select if self.level + 1 < self.config.levels => {
+                self.region = self.cell_rect();
+                self.level += 1;
+                self.cell = 0;
+            }
+            SwitchAction::Select | SwitchAction::Click => {
+                output.push(self.click(Button::PRIMARY, 1))
+            }
+            SwitchAction::RightClick => output.push(self.click(Button::SECONDARY, 1)),
+            SwitchAction::DoubleClick => output.push(self.click(Button::PRIMARY, 2)),
+            SwitchAction::Cancel => self.reset(),
+        }
+        // scanning starts over from where the user acted
+        self.next_scan = self.config.scan_interval.map(|i| Instant::now() + i);
+        output.push(SwitchOutput::Highlight(self.cell_rect()));
+        output
+    }
+
+    /// Process a pointer event of the client, which is no regular input in switch control.
+    pub fn pointer(&mut self, event: &PointerEvent) -> Vec<SwitchOutput> {
+        self.timestamp = (event.timestamp, Instant::now());
+        match event.event_type {
+            PointerEventType::DOWN if event.is_primary && self.press.is_none() => {
+                let press = Press {
+                    pointer_id: event.pointer_id,
+                    action: self.action_at(event.x, event.y),
+                    since: Instant::now(),
+                    done: false,
+                };
+                self.press = Some(press);
+                self.timer()
+            }
+            PointerEventType::UP | PointerEventType::CANCEL => {
+                if self
+                    .press
+                    .as_ref()
+                    .map_or(false, |p| p.pointer_id == event.pointer_id)
+                {
+                    self.press = None;
+                }
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Perform the actions that are due: those of switches held long enough and scanning.
+    pub fn timer(&mut self) -> Vec<SwitchOutput> {
+        let now = Instant::now();
+        let mut output = Vec::new();
+        let dwell = self.config.dwell;
+        if let Some(press) = self.press.as_mut().filter(|p| !p.done) {
+            if now >= press.since + dwell {
+                press.done = true;
+                let action = press.action;
+                output.extend(self.perform(action));
+            }
+        }
+        if self.next_scan.map_or(false, |t| now >= t) {
+            output.extend(self.perform(SwitchAction::Next));
+        }
+        output
+    }
+}
@@ -0,0 +1,129 @@
+//! Detect text typed faster than humanly possible, e.g. pasted by a keyboard app or sent by a
+//! device posing as keyboard, and stop the Enter that would run it in a terminal.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::protocol::{KeyboardEvent, KeyboardEventType};
+
+/// Key presses closer together than this are considered injected.
+const INJECTION_INTERVAL: Duration = Duration::from_millis(15);
+
+/// Number of rapid key presses before an Enter for the text to count as injected.
+const MIN_INJECTED_KEYS: usize = 8;
+
+/// Events held back while waiting for confirmation, anything beyond is dropped.
+const MAX_HELD_EVENTS: usize = 4096;
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextInjectionPolicy {
+    /// Pass all keys on.
+    #[default]
+    Allow,
+    /// Drop Enter within injected text.
+    Block,
+    /// Hold injected text back from the first Enter on until the user confirms it on the client.
+    Confirm,
+}
+
+pub enum FilteredKey {
+    Pass(KeyboardEvent),
+    Drop,
+    /// The event is held back, the client has to be asked for confirmation.
+    AskConfirmation,
+}
+
+pub struct TextInjectionFilter {
+    policy: TextInjectionPolicy,
+    last_key_down: Option<Instant>,
+    /// Number of rapid key presses in a row.
+    burst: usize,
+    /// The release of a dropped Enter is dropped as well.
+    drop_enter_up: bool,
+    held: Option<Vec<KeyboardEvent>>,
+}
+
+impl TextInjectionFilter {
+    pub fn new(policy: TextInjectionPolicy) -> Self {
+        Self {
+            policy,
+            last_key_down: None,
+            burst: 0,
+            drop_enter_up: false,
+            held: None,
+        }
+    }
+
+    fn is_enter(event: &KeyboardEvent) -> bool {
+        // shift and Enter runs commands in terminals as well
+        matches!(event.code.as_str(), "Enter" | "NumpadEnter")
+            && !(event.ctrl || event.alt || event.meta)
+    }
+
+    pub fn filter(&mut self, event: KeyboardEvent) -> FilteredKey {
+        if self.policy == TextInjectionPolicy::Allow {
+            return FilteredKey::Pass(event);
+        }
+        if let Some(held) = self.held.as_mut() {
+            if held.len() < MAX_HELD_EVENTS {
+                held.push(event);
+            }
+            return FilteredKey::Drop;
+        }
+        if let KeyboardEventType::UP = event.event_type {
+            if self.drop_enter_up && Self::is_enter(&event) {
+                self.drop_enter_up = false;
+                return FilteredKey::Drop;
+            }
+            return FilteredKey::Pass(event);
+        }
+
+        let now = Instant::now();
+        let rapid = self
+            .last_key_down
+            .is_some_and(|t| now - t < INJECTION_INTERVAL);
+        self.last_key_down = Some(now);
+        self.burst = if rapid { self.burst + 1 } else { 0 };
+
+        if !Self::is_enter(&event) || self.burst < MIN_INJECTED_KEYS {
+            return FilteredKey::Pass(event);
+        }
+        match self.policy {
+            TextInjectionPolicy::Allow => FilteredKey::Pass(event),
+            TextInjectionPolicy::Block => {
+                warn!(keys = self.burst, "Dropped Enter within injected text.");
+                self.drop_enter_up = true;
+                FilteredKey::Drop
+            }
+            TextInjectionPolicy::Confirm => {
+                debug!(
+                    keys = self.burst,
+                    "Holding back injected text until confirmed."
+                );
+                self.held = Some(vec![event]);
+                FilteredKey::AskConfirmation
+            }
+        }
+    }
+
+    /// Returns the held back events if the user confirmed them.
+    pub fn confirm(&mut self, confirmed: bool) -> Vec<KeyboardEvent> {
+        let held = self.held.take().unwrap_or_default();
+        self.burst = 0;
+        if confirmed {
+            held
+        } else {
+            warn!(
+                events = held.len(),
+                "Dropped injected text, it was not confirmed."
+            );
+            // the key releases are dropped as well, make sure no key remains pressed
+            held.into_iter()
+                .filter(|event| matches!(event.event_type, KeyboardEventType::UP))
+                .filter(|event| !Self::is_enter(event))
+                .collect()
+        }
+    }
+}
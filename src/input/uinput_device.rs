@@ -3,12 +3,16 @@ use std::ffi::CString;
 use std::os::raw::{c_char, c_int};
 use std::time::{Duration, Instant};
 
+use crate::capturable::transform::{geometry_rect, Rect};
 use crate::capturable::x11::X11Context;
-use crate::capturable::{Capturable, Geometry};
-use crate::input::device::{InputDevice, InputDeviceType};
+use crate::capturable::Capturable;
+use crate::input::cleanup::{self, CleanupGuard};
+use crate::input::device::{ActivationPolicy, EraserPolicy, InputDevice, InputDeviceType};
+use crate::input::uinput_holder::{HeldDevices, NUM_DEVICES};
+use crate::input::xi2_master::MasterDevice;
 use crate::protocol::{
     Button, KeyboardEvent, KeyboardEventType, KeyboardLocation, PointerEvent, PointerEventType,
-    PointerType, Rect, WheelEvent,
+    PointerType, WheelEvent,
 };
 
 use crate::cerror::CError;
@@ -20,7 +24,9 @@ extern "C" {
     fn init_uinput_stylus(name: *const c_char, err: *mut CError) -> c_int;
     fn init_uinput_mouse(name: *const c_char, err: *mut CError) -> c_int;
     fn init_uinput_touch(name: *const c_char, err: *mut CError) -> c_int;
+    fn init_uinput_joystick(name: *const c_char, err: *mut CError) -> c_int;
     fn destroy_uinput_device(fd: c_int);
+    fn close_uinput_device(fd: c_int);
     fn send_uinput_event(device: c_int, typ: c_int, code: c_int, value: c_int, err: *mut CError);
 }
 
@@ -36,62 +42,98 @@ pub struct UInputDevice {
     touches: [Option<MultiTouch>; 5],
     tool_pen_active: bool,
     pen_touching: bool,
+    stylus2_pressed: bool,
+    eraser_policy: EraserPolicy,
+    activation_policy: ActivationPolicy,
     last_pen_event: Instant,
     capturable: Box<dyn Capturable>,
     geometry: Rect,
     name_mouse_device: String,
     name_stylus_device: String,
     name_touch_device: String,
+    name_keyboard_device: String,
     num_mouse_mapping_tries: usize,
     num_stylus_mapping_tries: usize,
     num_touch_mapping_tries: usize,
     x11ctx: Option<X11Context>,
+    master: Option<MasterDevice>,
+    cleanup: Option<CleanupGuard>,
+    /// Set if the devices are kept by the helper process.
+    held: Option<HeldDevices>,
+}
+
+/// Create a device per function and name, the devices created already are destroyed again if one
+/// of them fails.
+fn create_devices(
+    devices: [(
+        unsafe extern "C" fn(*const c_char, *mut CError) -> c_int,
+        &str,
+    ); NUM_DEVICES],
+) -> Result<[c_int; NUM_DEVICES], CError> {
+    let mut fds = [-1; NUM_DEVICES];
+    for (i, (init, name)) in devices.into_iter().enumerate() {
+        let mut err = CError::new();
+        let name = CString::new(name.as_bytes()).unwrap();
+        fds[i] = unsafe { init(name.as_ptr(), &mut err) };
+        if err.is_err() {
+            for fd in &fds[..i] {
+                unsafe { destroy_uinput_device(*fd) };
+            }
+            return Err(err);
+        }
+    }
+    Ok(fds)
 }
 
 impl UInputDevice {
-    pub fn new(capturable: Box<dyn Capturable>, id: &Option<String>) -> Result<Self, CError> {
+    /// Create the virtual devices, with `xi2_master` they get a cursor of their own on X11. With
+    /// `persist` the devices are kept by a helper process for that long once they are dropped,
+    /// see [`uinput_holder`](crate::input::uinput_holder).
+    pub fn new(
+        capturable: Box<dyn Capturable>,
+        id: &Option<String>,
+        xi2_master: bool,
+        persist: Option<Duration>,
+    ) -> Result<Self, CError> {
         let mut suffix = String::new();
         if let Some(id) = id {
             suffix = format!(" - {}", id);
         }
-        let mut err = CError::new();
         let name_stylus = format!("Weylus Stylus{}", suffix);
-        let name_stylus_c_str = CString::new(name_stylus.as_bytes()).unwrap();
-        let stylus_fd = unsafe { init_uinput_stylus(name_stylus_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            return Err(err);
-        }
-
         let name_mouse = format!("Weylus Mouse{}", suffix);
-        let name_mouse_c_str = CString::new(name_mouse.as_bytes()).unwrap();
-        let mouse_fd = unsafe { init_uinput_mouse(name_mouse_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            unsafe { destroy_uinput_device(stylus_fd) };
-            return Err(err);
-        }
-
         let name_touch = format!("Weylus Touch{}", suffix);
-        let name_touch_c_str = CString::new(name_touch.as_bytes()).unwrap();
-        let touch_fd = unsafe { init_uinput_touch(name_touch_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            unsafe {
-                destroy_uinput_device(stylus_fd);
-                destroy_uinput_device(mouse_fd);
-            }
-            return Err(err);
-        }
-
         let name_keyboard = format!("Weylus Keyboard{}", suffix);
-        let name_keyboard_c_str = CString::new(name_keyboard.as_bytes()).unwrap();
-        let keyboard_fd = unsafe { init_uinput_keyboard(name_keyboard_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            unsafe {
+        let create = || {
+            create_devices([
+                (init_uinput_stylus, &name_stylus),
+                (init_uinput_mouse, &name_mouse),
+                (init_uinput_touch, &name_touch),
+                (init_uinput_keyboard, &name_keyboard),
+            ])
+        };
+        let (held, [stylus_fd, mouse_fd, touch_fd, keyboard_fd]) = match persist {
+            Some(hold) => HeldDevices::claim(&format!("Weylus{}", suffix), hold, create)?,
+            None => (None, create()?),
+        };
+
+        // destroying the devices releases all keys and buttons held down, devices kept by the
+        // helper are released by it instead
+        let cleanup = held.is_none().then(|| {
+            cleanup::register(move || unsafe {
+                destroy_uinput_device(keyboard_fd);
                 destroy_uinput_device(stylus_fd);
                 destroy_uinput_device(mouse_fd);
                 destroy_uinput_device(touch_fd);
-            }
-            return Err(err);
-        }
+            })
+        });
+
+        // XWayland does not expose the devices created by uinput, see the screen mapping below
+        let master = if xi2_master && std::env::var("XDG_SESSION_TYPE").as_deref() != Ok("wayland")
+        {
+            MasterDevice::new(&format!("Weylus{}", suffix))
+        } else {
+            None
+        };
 
         Ok(Self {
             keyboard_fd,
@@ -101,27 +143,47 @@ impl UInputDevice {
             touches: Default::default(),
             tool_pen_active: false,
             pen_touching: false,
+            stylus2_pressed: false,
+            eraser_policy: EraserPolicy::Tool,
+            activation_policy: ActivationPolicy::Always,
             last_pen_event: Instant::now(),
             capturable,
-            geometry: Rect::default(),
+            geometry: Rect::new(0.0, 0.0, 1.0, 1.0),
             name_mouse_device: name_mouse,
             name_touch_device: name_touch,
             name_stylus_device: name_stylus,
+            name_keyboard_device: name_keyboard,
             num_mouse_mapping_tries: 0,
             num_stylus_mapping_tries: 0,
             num_touch_mapping_tries: 0,
             x11ctx: X11Context::new(),
+            master,
+            cleanup,
+            held,
         })
     }
 
+    fn attach_to_master(&mut self) {
+        if let Some(master) = self.master.as_mut() {
+            for name in [
+                &self.name_stylus_device,
+                &self.name_mouse_device,
+                &self.name_touch_device,
+                &self.name_keyboard_device,
+            ] {
+                master.attach(name);
+            }
+        }
+    }
+
     fn transform_x(&self, x: f64) -> i32 {
-        let x = (x * self.geometry.w + self.geometry.x) * ABS_MAX;
-        x as i32
+        let (x, _) = self.geometry.to_outer(x, 0.0);
+        (x * ABS_MAX) as i32
     }
 
     fn transform_y(&self, y: f64) -> i32 {
-        let y = (y * self.geometry.h + self.geometry.y) * ABS_MAX;
-        y as i32
+        let (_, y) = self.geometry.to_outer(0.0, y);
+        (y * ABS_MAX) as i32
     }
 
     fn transform_pressure(&self, p: f64) -> i32 {
@@ -161,6 +223,20 @@ impl UInputDevice {
 
 impl Drop for UInputDevice {
     fn drop(&mut self) {
+        // the helper keeping the devices releases everything held down once the connection to it
+        // is closed, right after this
+        if self.held.is_some() {
+            unsafe {
+                close_uinput_device(self.keyboard_fd);
+                close_uinput_device(self.stylus_fd);
+                close_uinput_device(self.mouse_fd);
+                close_uinput_device(self.touch_fd);
+            };
+            return;
+        }
+        if !self.cleanup.as_ref().is_some_and(CleanupGuard::unregister) {
+            return;
+        }
         unsafe {
             destroy_uinput_device(self.keyboard_fd);
             destroy_uinput_device(self.stylus_fd);
@@ -186,6 +262,7 @@ const EC_KEY_MOUSE_MIDDLE: c_int = 0x112;
 const EC_KEY_TOOL_PEN: c_int = 0x140;
 const EC_KEY_TOOL_RUBBER: c_int = 0x141;
 const EC_KEY_TOUCH: c_int = 0x14a;
+const EC_KEY_STYLUS2: c_int = 0x14c;
 const EC_KEY_TOOL_FINGER: c_int = 0x145;
 const EC_KEY_TOOL_DOUBLETAP: c_int = 0x14d;
 const EC_KEY_TOOL_TRIPLETAP: c_int = 0x14e;
@@ -220,6 +297,192 @@ const EC_MSC_TIMESTAMP: c_int = 0x05;
 // This corresponds to PointerEvent values of 1.0
 const ABS_MAX: f64 = 65535.0;
 
+/// Map the code of a key as reported by the browser to the key code of the Linux input subsystem.
+pub fn map_key(code: &str, location: &KeyboardLocation) -> c_int {
+    use crate::input::uinput_keys::*;
+    match (code, location) {
+        ("Escape", _) => KEY_ESC,
+        ("Digit0", KeyboardLocation::NUMPAD) => KEY_KP0,
+        ("Digit1", KeyboardLocation::NUMPAD) => KEY_KP1,
+        ("Digit2", KeyboardLocation::NUMPAD) => KEY_KP2,
+        ("Digit3", KeyboardLocation::NUMPAD) => KEY_KP3,
+        ("Digit4", KeyboardLocation::NUMPAD) => KEY_KP4,
+        ("Digit5", KeyboardLocation::NUMPAD) => KEY_KP5,
+        ("Digit6", KeyboardLocation::NUMPAD) => KEY_KP6,
+        ("Digit7", KeyboardLocation::NUMPAD) => KEY_KP7,
+        ("Digit8", KeyboardLocation::NUMPAD) => KEY_KP8,
+        ("Digit9", KeyboardLocation::NUMPAD) => KEY_KP9,
+        ("Minus", KeyboardLocation::NUMPAD) => KEY_KPMINUS,
+        ("Equal", KeyboardLocation::NUMPAD) => KEY_KPEQUAL,
+        ("Enter", KeyboardLocation::NUMPAD) => KEY_KPENTER,
+        ("Digit0", _) => KEY_0,
+        ("Digit1", _) => KEY_1,
+        ("Digit2", _) => KEY_2,
+        ("Digit3", _) => KEY_3,
+        ("Digit4", _) => KEY_4,
+        ("Digit5", _) => KEY_5,
+        ("Digit6", _) => KEY_6,
+        ("Digit7", _) => KEY_7,
+        ("Digit8", _) => KEY_8,
+        ("Digit9", _) => KEY_9,
+        ("Minus", _) => KEY_MINUS,
+        ("Equal", _) => KEY_EQUAL,
+        ("Enter", _) => KEY_ENTER,
+        ("Backspace", _) => KEY_BACKSPACE,
+        ("Tab", _) => KEY_TAB,
+        ("KeyA", _) => KEY_A,
+        ("KeyB", _) => KEY_B,
+        ("KeyC", _) => KEY_C,
+        ("KeyD", _) => KEY_D,
+        ("KeyE", _) => KEY_E,
+        ("KeyF", _) => KEY_F,
+        ("KeyG", _) => KEY_G,
+        ("KeyH", _) => KEY_H,
+        ("KeyI", _) => KEY_I,
+        ("KeyJ", _) => KEY_J,
+        ("KeyK", _) => KEY_K,
+        ("KeyL", _) => KEY_L,
+        ("KeyM", _) => KEY_M,
+        ("KeyN", _) => KEY_N,
+        ("KeyO", _) => KEY_O,
+        ("KeyP", _) => KEY_P,
+        ("KeyQ", _) => KEY_Q,
+        ("KeyR", _) => KEY_R,
+        ("KeyS", _) => KEY_S,
+        ("KeyT", _) => KEY_T,
+        ("KeyU", _) => KEY_U,
+        ("KeyV", _) => KEY_V,
+        ("KeyW", _) => KEY_W,
+        ("KeyX", _) => KEY_X,
+        ("KeyY", _) => KEY_Y,
+        ("KeyZ", _) => KEY_Z,
+        ("BracketLeft", _) => KEY_LEFTBRACE,
+        ("BracketRight", _) => KEY_RIGHTBRACE,
+        ("Semicolon", _) => KEY_SEMICOLON,
+        ("Quote", _) => KEY_APOSTROPHE,
+        ("Backquote", _) => KEY_GRAVE,
+        ("Backslash", _) => KEY_BACKSLASH,
+        ("Comma", _) => KEY_COMMA,
+        ("Period", _) => KEY_DOT,
+        ("Slash", _) => KEY_SLASH,
+        ("Space", _) => KEY_SPACE,
+        ("CapsLock", _) => KEY_CAPSLOCK,
+        ("NumpadMultiply", _) => KEY_KPASTERISK,
+        ("F1", _) => KEY_F1,
+        ("F2", _) => KEY_F2,
+        ("F3", _) => KEY_F3,
+        ("F4", _) => KEY_F4,
+        ("F5", _) => KEY_F5,
+        ("F6", _) => KEY_F6,
+        ("F7", _) => KEY_F7,
+        ("F8", _) => KEY_F8,
+        ("F9", _) => KEY_F9,
+        ("F10", _) => KEY_F10,
+        ("F11", _) => KEY_F11,
+        ("F12", _) => KEY_F12,
+        ("F13", _) => KEY_F13,
+        ("F14", _) => KEY_F14,
+        ("F15", _) => KEY_F15,
+        ("F16", _) => KEY_F16,
+        ("F17", _) => KEY_F17,
+        ("F18", _) => KEY_F18,
+        ("F19", _) => KEY_F19,
+        ("F20", _) => KEY_F20,
+        ("F21", _) => KEY_F21,
+        ("F22", _) => KEY_F22,
+        ("F23", _) => KEY_F23,
+        ("F24", _) => KEY_F24,
+        ("NumLock", _) => KEY_NUMLOCK,
+        ("ScrollLock", _) => KEY_SCROLLLOCK,
+        ("Numpad0", _) => KEY_KP0,
+        ("Numpad1", _) => KEY_KP1,
+        ("Numpad2", _) => KEY_KP2,
+        ("Numpad3", _) => KEY_KP3,
+        ("Numpad4", _) => KEY_KP4,
+        ("Numpad5", _) => KEY_KP5,
+        ("Numpad6", _) => KEY_KP6,
+        ("Numpad7", _) => KEY_KP7,
+        ("Numpad8", _) => KEY_KP8,
+        ("Numpad9", _) => KEY_KP9,
+        ("NumpadSubtract", _) => KEY_KPMINUS,
+        ("NumpadAdd", _) => KEY_KPPLUS,
+        // ("NumpadDecimal", _) => ?,
+        ("IntlBackslash", _) => KEY_102ND,
+        ("IntlRo", _) => KEY_RO,
+        ("NumpadEnter", _) => KEY_KPENTER,
+        ("NumpadDivide", _) => KEY_KPSLASH,
+        ("NumpadEqual", _) => KEY_KPEQUAL,
+        ("NumpadComma", _) => KEY_KPCOMMA,
+        ("NumpadParenLeft", _) => KEY_KPLEFTPAREN,
+        ("NumpadParenRight", _) => KEY_KPRIGHTPAREN,
+        // ("NumpadChangeSign", _) => ?,
+        // ("Convert", _) => ?,
+        ("KanaMode", _) => KEY_KATAKANA,
+        // ("NonConvert", _) => ?,
+        ("PrintScreen", _) => KEY_SYSRQ,
+        ("Home", _) => KEY_HOME,
+        ("ArrowUp", _) => KEY_UP,
+        ("PageUp", _) => KEY_PAGEUP,
+        ("ArrowLeft", _) => KEY_LEFT,
+        ("ArrowRight", _) => KEY_RIGHT,
+        ("End", _) => KEY_END,
+        ("ArrowDown", _) => KEY_DOWN,
+        ("PageDown", _) => KEY_PAGEDOWN,
+        ("Insert", _) => KEY_INSERT,
+        ("Delete", _) => KEY_DELETE,
+        ("VolumeMute", _) | ("AudioVolumeMute", _) => KEY_MUTE,
+        ("VolumeDown", _) | ("AudioVolumeDown", _) => KEY_VOLUMEDOWN,
+        ("VolumeUp", _) | ("AudioVolumeUp", _) => KEY_VOLUMEUP,
+        ("Pause", _) => KEY_PAUSE,
+
+        ("Lang1", _) => KEY_HANGUEL,
+        ("Lang2", _) => KEY_HANJA,
+        ("IntlYen", _) => KEY_YEN,
+        ("OSLeft", _) => KEY_LEFTMETA,
+        ("OSRight", _) => KEY_RIGHTMETA,
+        ("ContextMenu", _) => KEY_MENU,
+        // ("BrowserStop", _) => ?,
+        ("Cancel", _) => KEY_CANCEL,
+        ("Again", _) => KEY_AGAIN,
+        ("Props", _) => KEY_PROPS,
+        ("Undo", _) => KEY_UNDO,
+        // ("Select", _) => ?,
+        ("Copy", _) => KEY_COPY,
+        ("Open", _) => KEY_OPEN,
+        ("Paste", _) => KEY_PASTE,
+        ("Find", _) => KEY_FIND,
+        ("Cut", _) => KEY_CUT,
+        ("Help", _) => KEY_HELP,
+        // ("LaunchApp2", _) => ?,
+        // ("LaunchApp1", _) => ,
+        ("LaunchMail", _) => KEY_MAIL,
+        // ("BrowserFavorites", _) => ?,
+        // ("BrowserBack", _) => ?,
+        // ("BrowserForward", _) => ?,
+        ("Eject", _) => KEY_EJECTCD,
+        ("MediaTrackNext", _) => KEY_NEXTSONG,
+        ("MediaPlayPause", _) => KEY_PLAYPAUSE,
+        ("MediaTrackPrevious", _) => KEY_PREVIOUSSONG,
+        ("MediaStop", _) => KEY_STOPCD,
+        ("MediaSelect", _) | ("LaunchMediaPlayer", _) => KEY_MEDIA,
+        // ("BrowserHome", _) => ?,
+        // ("BrowserRefresh", _) => ?,
+        // ("BrowserSearch", _) => ?,
+        ("Power", _) => KEY_POWER,
+        ("Sleep", _) => KEY_SLEEP,
+        ("WakeUp", _) => KEY_WAKEUP,
+        ("ControlLeft", _) => KEY_LEFTCTRL,
+        ("ControlRight", _) => KEY_RIGHTCTRL,
+        ("AltLeft", _) => KEY_LEFTALT,
+        ("AltRight", _) => KEY_RIGHTALT,
+        ("MetaLeft", _) => KEY_LEFTMETA,
+        ("MetaRight", _) => KEY_RIGHTMETA,
+        ("ShiftLeft", _) => KEY_LEFTSHIFT,
+        ("ShiftRight", _) => KEY_RIGHTSHIFT,
+        _ => KEY_UNKNOWN,
+    }
+}
+
 // This specifies how many times it should be attempted to map the input devices created via uinput
 // to the entire screen and not only a single monitor. Actually this is a workaround because
 // apparently it is impossible to set the correct mapping in a sane way. The reason is that X needs
@@ -238,10 +501,12 @@ const MAX_SCREEN_MAPPING_TRIES: usize = 100;
 
 impl InputDevice for UInputDevice {
     fn send_wheel_event(&mut self, event: &WheelEvent) {
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self.activation_policy.activate(false);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
+        self.attach_to_master();
 
         fn direction(d: i32) -> i32 {
             match d.cmp(&0) {
@@ -276,17 +541,15 @@ impl InputDevice for UInputDevice {
     }
 
     fn send_pointer_event(&mut self, event: &PointerEvent) {
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self
+            .activation_policy
+            .activate(event.event_type == PointerEventType::DOWN);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        let (x, y, width, height) = match self.capturable.geometry().unwrap() {
-            Geometry::Relative(x, y, width, height) => (x, y, width, height),
-        };
-        self.geometry.x = x;
-        self.geometry.y = y;
-        self.geometry.w = width;
-        self.geometry.h = height;
+        self.attach_to_master();
+        self.geometry = geometry_rect(&self.capturable.geometry().unwrap());
         match event.pointer_type {
             PointerType::Touch => {
                 if self.num_touch_mapping_tries < MAX_SCREEN_MAPPING_TRIES {
@@ -499,16 +762,27 @@ impl InputDevice for UInputDevice {
                             self.pen_touching = true;
                             self.send(self.stylus_fd, ET_KEY, EC_KEY_TOUCH, 1);
                         }
-                        if !self.tool_pen_active && !event.buttons.contains(Button::ERASER) {
+                        let eraser_tool = self.eraser_policy == EraserPolicy::Tool;
+                        if !self.tool_pen_active
+                            && !(eraser_tool && event.buttons.contains(Button::ERASER))
+                        {
                             self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_PEN, 1);
                             self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_RUBBER, 0);
                             self.tool_pen_active = true;
                         }
-                        if let Button::ERASER = event.button {
+                        if eraser_tool && event.button == Button::ERASER {
                             self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_PEN, 0);
                             self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_RUBBER, 1);
                             self.tool_pen_active = false;
                         }
+                        if self.eraser_policy == EraserPolicy::Button {
+                            let pressed = event.buttons.contains(Button::ERASER)
+                                || event.button == Button::ERASER;
+                            if pressed != self.stylus2_pressed {
+                                self.send(self.stylus_fd, ET_KEY, EC_KEY_STYLUS2, pressed as i32);
+                                self.stylus2_pressed = pressed;
+                            }
+                        }
                         self.send(
                             self.stylus_fd,
                             ET_ABSOLUTE,
@@ -551,6 +825,10 @@ impl InputDevice for UInputDevice {
                         self.send(self.stylus_fd, ET_KEY, EC_KEY_TOUCH, 0);
                         self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_PEN, 0);
                         self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_RUBBER, 0);
+                        if self.stylus2_pressed {
+                            self.send(self.stylus_fd, ET_KEY, EC_KEY_STYLUS2, 0);
+                            self.stylus2_pressed = false;
+                        }
                         self.send(self.stylus_fd, ET_ABSOLUTE, EC_ABSOLUTE_PRESSURE, 0);
                         self.tool_pen_active = false;
                         self.pen_touching = false;
@@ -642,194 +920,12 @@ impl InputDevice for UInputDevice {
 
     fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
         use crate::input::uinput_keys::*;
-        if let Err(err) = self.capturable.before_input() {
+        let activate = self.activation_policy.activate(false);
+        if let Err(err) = self.capturable.before_input(activate) {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        fn map_key(code: &str, location: &KeyboardLocation) -> c_int {
-            match (code, location) {
-                ("Escape", _) => KEY_ESC,
-                ("Digit0", KeyboardLocation::NUMPAD) => KEY_KP0,
-                ("Digit1", KeyboardLocation::NUMPAD) => KEY_KP1,
-                ("Digit2", KeyboardLocation::NUMPAD) => KEY_KP2,
-                ("Digit3", KeyboardLocation::NUMPAD) => KEY_KP3,
-                ("Digit4", KeyboardLocation::NUMPAD) => KEY_KP4,
-                ("Digit5", KeyboardLocation::NUMPAD) => KEY_KP5,
-                ("Digit6", KeyboardLocation::NUMPAD) => KEY_KP6,
-                ("Digit7", KeyboardLocation::NUMPAD) => KEY_KP7,
-                ("Digit8", KeyboardLocation::NUMPAD) => KEY_KP8,
-                ("Digit9", KeyboardLocation::NUMPAD) => KEY_KP9,
-                ("Minus", KeyboardLocation::NUMPAD) => KEY_KPMINUS,
-                ("Equal", KeyboardLocation::NUMPAD) => KEY_KPEQUAL,
-                ("Enter", KeyboardLocation::NUMPAD) => KEY_KPENTER,
-                ("Digit0", _) => KEY_0,
-                ("Digit1", _) => KEY_1,
-                ("Digit2", _) => KEY_2,
-                ("Digit3", _) => KEY_3,
-                ("Digit4", _) => KEY_4,
-                ("Digit5", _) => KEY_5,
-                ("Digit6", _) => KEY_6,
-                ("Digit7", _) => KEY_7,
-                ("Digit8", _) => KEY_8,
-                ("Digit9", _) => KEY_9,
-                ("Minus", _) => KEY_MINUS,
-                ("Equal", _) => KEY_EQUAL,
-                ("Enter", _) => KEY_ENTER,
-                ("Backspace", _) => KEY_BACKSPACE,
-                ("Tab", _) => KEY_TAB,
-                ("KeyA", _) => KEY_A,
-                ("KeyB", _) => KEY_B,
-                ("KeyC", _) => KEY_C,
-                ("KeyD", _) => KEY_D,
-                ("KeyE", _) => KEY_E,
-                ("KeyF", _) => KEY_F,
-                ("KeyG", _) => KEY_G,
-                ("KeyH", _) => KEY_H,
-                ("KeyI", _) => KEY_I,
-                ("KeyJ", _) => KEY_J,
-                ("KeyK", _) => KEY_K,
-                ("KeyL", _) => KEY_L,
-                ("KeyM", _) => KEY_M,
-                ("KeyN", _) => KEY_N,
-                ("KeyO", _) => KEY_O,
-                ("KeyP", _) => KEY_P,
-                ("KeyQ", _) => KEY_Q,
-                ("KeyR", _) => KEY_R,
-                ("KeyS", _) => KEY_S,
-                ("KeyT", _) => KEY_T,
-                ("KeyU", _) => KEY_U,
-                ("KeyV", _) => KEY_V,
-                ("KeyW", _) => KEY_W,
-                ("KeyX", _) => KEY_X,
-                ("KeyY", _) => KEY_Y,
-                ("KeyZ", _) => KEY_Z,
-                ("BracketLeft", _) => KEY_LEFTBRACE,
-                ("BracketRight", _) => KEY_RIGHTBRACE,
-                ("Semicolon", _) => KEY_SEMICOLON,
-                ("Quote", _) => KEY_APOSTROPHE,
-                ("Backquote", _) => KEY_GRAVE,
-                ("Backslash", _) => KEY_BACKSLASH,
-                ("Comma", _) => KEY_COMMA,
-                ("Period", _) => KEY_DOT,
-                ("Slash", _) => KEY_SLASH,
-                ("Space", _) => KEY_SPACE,
-                ("CapsLock", _) => KEY_CAPSLOCK,
-                ("NumpadMultiply", _) => KEY_KPASTERISK,
-                ("F1", _) => KEY_F1,
-                ("F2", _) => KEY_F2,
-                ("F3", _) => KEY_F3,
-                ("F4", _) => KEY_F4,
-                ("F5", _) => KEY_F5,
-                ("F6", _) => KEY_F6,
-                ("F7", _) => KEY_F7,
-                ("F8", _) => KEY_F8,
-                ("F9", _) => KEY_F9,
-                ("F10", _) => KEY_F10,
-                ("F11", _) => KEY_F11,
-                ("F12", _) => KEY_F12,
-                ("F13", _) => KEY_F13,
-                ("F14", _) => KEY_F14,
-                ("F15", _) => KEY_F15,
-                ("F16", _) => KEY_F16,
-                ("F17", _) => KEY_F17,
-                ("F18", _) => KEY_F18,
-                ("F19", _) => KEY_F19,
-                ("F20", _) => KEY_F20,
-                ("F21", _) => KEY_F21,
-                ("F22", _) => KEY_F22,
-                ("F23", _) => KEY_F23,
-                ("F24", _) => KEY_F24,
-                ("NumLock", _) => KEY_NUMLOCK,
-                ("ScrollLock", _) => KEY_SCROLLLOCK,
-                ("Numpad0", _) => KEY_KP0,
-                ("Numpad1", _) => KEY_KP1,
-                ("Numpad2", _) => KEY_KP2,
-                ("Numpad3", _) => KEY_KP3,
-                ("Numpad4", _) => KEY_KP4,
-                ("Numpad5", _) => KEY_KP5,
-                ("Numpad6", _) => KEY_KP6,
-                ("Numpad7", _) => KEY_KP7,
-                ("Numpad8", _) => KEY_KP8,
-                ("Numpad9", _) => KEY_KP9,
-                ("NumpadSubtract", _) => KEY_KPMINUS,
-                ("NumpadAdd", _) => KEY_KPPLUS,
-                // ("NumpadDecimal", _) => ?,
-                ("IntlBackslash", _) => KEY_102ND,
-                ("IntlRo", _) => KEY_RO,
-                ("NumpadEnter", _) => KEY_KPENTER,
-                ("NumpadDivide", _) => KEY_KPSLASH,
-                ("NumpadEqual", _) => KEY_KPEQUAL,
-                ("NumpadComma", _) => KEY_KPCOMMA,
-                ("NumpadParenLeft", _) => KEY_KPLEFTPAREN,
-                ("NumpadParenRight", _) => KEY_KPRIGHTPAREN,
-                // ("NumpadChangeSign", _) => ?,
-                // ("Convert", _) => ?,
-                ("KanaMode", _) => KEY_KATAKANA,
-                // ("NonConvert", _) => ?,
-                ("PrintScreen", _) => KEY_SYSRQ,
-                ("Home", _) => KEY_HOME,
-                ("ArrowUp", _) => KEY_UP,
-                ("PageUp", _) => KEY_PAGEUP,
-                ("ArrowLeft", _) => KEY_LEFT,
-                ("ArrowRight", _) => KEY_RIGHT,
-                ("End", _) => KEY_END,
-                ("ArrowDown", _) => KEY_DOWN,
-                ("PageDown", _) => KEY_PAGEDOWN,
-                ("Insert", _) => KEY_INSERT,
-                ("Delete", _) => KEY_DELETE,
-                ("VolumeMute", _) | ("AudioVolumeMute", _) => KEY_MUTE,
-                ("VolumeDown", _) | ("AudioVolumeDown", _) => KEY_VOLUMEDOWN,
-                ("VolumeUp", _) | ("AudioVolumeUp", _) => KEY_VOLUMEUP,
-                ("Pause", _) => KEY_PAUSE,
-
-                ("Lang1", _) => KEY_HANGUEL,
-                ("Lang2", _) => KEY_HANJA,
-                ("IntlYen", _) => KEY_YEN,
-                ("OSLeft", _) => KEY_LEFTMETA,
-                ("OSRight", _) => KEY_RIGHTMETA,
-                ("ContextMenu", _) => KEY_MENU,
-                // ("BrowserStop", _) => ?,
-                ("Cancel", _) => KEY_CANCEL,
-                ("Again", _) => KEY_AGAIN,
-                ("Props", _) => KEY_PROPS,
-                ("Undo", _) => KEY_UNDO,
-                // ("Select", _) => ?,
-                ("Copy", _) => KEY_COPY,
-                ("Open", _) => KEY_OPEN,
-                ("Paste", _) => KEY_PASTE,
-                ("Find", _) => KEY_FIND,
-                ("Cut", _) => KEY_CUT,
-                ("Help", _) => KEY_HELP,
-                // ("LaunchApp2", _) => ?,
-                // ("LaunchApp1", _) => ,
-                ("LaunchMail", _) => KEY_MAIL,
-                // ("BrowserFavorites", _) => ?,
-                // ("BrowserBack", _) => ?,
-                // ("BrowserForward", _) => ?,
-                ("Eject", _) => KEY_EJECTCD,
-                ("MediaTrackNext", _) => KEY_NEXTSONG,
-                ("MediaPlayPause", _) => KEY_PLAYPAUSE,
-                ("MediaTrackPrevious", _) => KEY_PREVIOUSSONG,
-                ("MediaStop", _) => KEY_STOPCD,
-                ("MediaSelect", _) | ("LaunchMediaPlayer", _) => KEY_MEDIA,
-                // ("BrowserHome", _) => ?,
-                // ("BrowserRefresh", _) => ?,
-                // ("BrowserSearch", _) => ?,
-                ("Power", _) => KEY_POWER,
-                ("Sleep", _) => KEY_SLEEP,
-                ("WakeUp", _) => KEY_WAKEUP,
-                ("ControlLeft", _) => KEY_LEFTCTRL,
-                ("ControlRight", _) => KEY_RIGHTCTRL,
-                ("AltLeft", _) => KEY_LEFTALT,
-                ("AltRight", _) => KEY_RIGHTALT,
-                ("MetaLeft", _) => KEY_LEFTMETA,
-                ("MetaRight", _) => KEY_RIGHTMETA,
-                ("ShiftLeft", _) => KEY_LEFTSHIFT,
-                ("ShiftRight", _) => KEY_RIGHTSHIFT,
-                _ => KEY_UNKNOWN,
-            }
-        }
-
+        self.attach_to_master();
         let key_code: c_int = map_key(&event.code, &event.location);
         let state: c_int = match event.event_type {
             KeyboardEventType::UP => 0,
@@ -910,7 +1006,62 @@ impl InputDevice for UInputDevice {
         self.capturable = capturable;
     }
 
+    fn set_eraser_policy(&mut self, policy: EraserPolicy) {
+        self.eraser_policy = policy;
+    }
+
+    fn set_activation_policy(&mut self, policy: ActivationPolicy) {
+        self.activation_policy = policy;
+    }
+
     fn device_type(&self) -> InputDeviceType {
         InputDeviceType::UInputDevice
     }
 }
+
+/// Largest value of the axes of the joystick.
+const JOYSTICK_AXIS_MAX: f64 = 32767.0;
+
+/// Virtual joystick moved by tilting the client, see [`crate::input::sensors`].
+pub struct UInputJoystick {
+    fd: c_int,
+    cleanup: CleanupGuard,
+}
+
+impl UInputJoystick {
+    pub fn new(id: &Option<String>) -> Result<Self, CError> {
+        let name = match id {
+            Some(id) => format!("Weylus Joystick - {}", id),
+            None => "Weylus Joystick".to_string(),
+        };
+        let name_c_str = CString::new(name.as_bytes()).unwrap();
+        let mut err = CError::new();
+        let fd = unsafe { init_uinput_joystick(name_c_str.as_ptr(), &mut err) };
+        if err.is_err() {
+            return Err(err);
+        }
+        let cleanup = cleanup::register(move || unsafe { destroy_uinput_device(fd) });
+        Ok(Self { fd, cleanup })
+    }
+
+    /// Move the stick, both axes range from -1 to 1.
+    pub fn set_stick(&self, x: f64, y: f64) {
+        let mut err = CError::new();
+        for (code, value) in [(EC_ABSOLUTE_X, x), (EC_ABSOLUTE_Y, y)] {
+            let value = (value.clamp(-1.0, 1.0) * JOYSTICK_AXIS_MAX) as c_int;
+            unsafe { send_uinput_event(self.fd, ET_ABSOLUTE, code, value, &mut err) };
+        }
+        unsafe { send_uinput_event(self.fd, ET_SYNC, EC_SYNC_REPORT, 0, &mut err) };
+        if err.is_err() {
+            warn!("{}", err);
+        }
+    }
+}
+
+impl Drop for UInputJoystick {
+    fn drop(&mut self) {
+        if self.cleanup.unregister() {
+            unsafe { destroy_uinput_device(self.fd) };
+        }
+    }
+}
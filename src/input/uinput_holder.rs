@@ -0,0 +1,335 @@
+//! Keeps the virtual devices created via uinput alive across restarts of Weylus.
+//!
+//! A device created via uinput exists as long as the file it was created with is open, so every
+//! restart of Weylus removes the devices and creates them anew. Desktop environments then run
+//! their logic for new devices again and forget per device settings like the monitor a tablet is
+//! mapped to. With `--persist-uinput` the devices are handed to a small helper process,
+//! `weylus uinput-holder`, which keeps a copy of their file descriptors. Weylus claims the devices
+//! from the helper when it needs devices of the same name again and only creates new ones if the
+//! helper has none. Once Weylus lets go of the devices, be it because the client disconnected or
+//! because Weylus exited or crashed, the helper releases everything held down and destroys the
+//! devices if they are not claimed again within the configured time. The helper exits once it
+//! holds no devices anymore.
+//!
+//! Every set of devices is claimed via a connection of its own to the socket of the helper: Weylus
+//! sends a line with the time to keep the devices and their name, the helper answers with a single
+//! byte, along with the file descriptors if it holds devices of that name. If it does not, Weylus
+//! sends the file descriptors of the devices it created. The connection stays open while the
+//! devices are used, closing it lets go of them. Only processes of the user running Weylus may
+//! connect to the socket.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::raw::c_int;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use crate::cerror::CError;
+use crate::private_socket;
+
+/// Number of devices created per client: stylus, mouse, touch and keyboard.
+pub const NUM_DEVICES: usize = 4;
+
+/// How long Weylus waits for the helper it started to accept connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The helper keeps running for this long after starting even if it has not received any devices.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+extern "C" {
+    fn destroy_uinput_device(fd: c_int);
+    fn release_uinput_device(fd: c_int);
+    fn send_uinput_fds(sock: c_int, fds: *const c_int, n: c_int, err: *mut CError);
+    fn recv_uinput_fds(sock: c_int, fds: *mut c_int, n: c_int, err: *mut CError) -> c_int;
+    fn uinput_peer_uid(sock: c_int, err: *mut CError) -> u32;
+}
+
+fn socket_path() -> io::Result<PathBuf> {
+    private_socket::path("weylus-uinput.sock")
+}
+
+/// Only processes of the same user may claim the devices, anyone else could send input to the
+/// session.
+fn check_peer(stream: &UnixStream) -> io::Result<()> {
+    let mut err = CError::new();
+    let uid = unsafe { uinput_peer_uid(stream.as_raw_fd(), &mut err) };
+    if err.is_err() {
+        return Err(io::Error::other(err));
+    }
+    private_socket::check_peer(uid)
+}
+
+fn send_fds(stream: &UnixStream, fds: &[c_int]) -> Result<(), CError> {
+    let mut err = CError::new();
+    unsafe {
+        send_uinput_fds(
+            stream.as_raw_fd(),
+            fds.as_ptr(),
+            fds.len() as c_int,
+            &mut err,
+        )
+    };
+    if err.is_err() {
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn recv_fds(stream: &UnixStream) -> Result<Vec<c_int>, CError> {
+    let mut fds = [-1; NUM_DEVICES];
+    let mut err = CError::new();
+    let n = unsafe {
+        recv_uinput_fds(
+            stream.as_raw_fd(),
+            fds.as_mut_ptr(),
+            NUM_DEVICES as c_int,
+            &mut err,
+        )
+    };
+    if err.is_err() {
+        return Err(err);
+    }
+    Ok(fds[..n as usize].to_vec())
+}
+
+/// Connection to the helper keeping a set of devices, dropping it lets go of the devices.
+pub struct HeldDevices {
+    _stream: UnixStream,
+}
+
+impl HeldDevices {
+    /// Claim the devices of the given name from the helper, starting the helper if it is not
+    /// running yet. Returns the file descriptors of the devices if the helper holds them already,
+    /// otherwise `create` is called and the devices it creates are handed to the helper. None if
+    /// the helper can not be reached, the devices are not persisted then.
+    pub fn claim<E>(
+        name: &str,
+        hold: Duration,
+        create: impl FnOnce() -> Result<[c_int; NUM_DEVICES], E>,
+    ) -> Result<(Option<Self>, [c_int; NUM_DEVICES]), E> {
+        let Some(stream) = connect() else {
+            return create().map(|fds| (None, fds));
+        };
+        let held = writeln!(&stream, "{} {name}", hold.as_secs())
+            .map_err(|err| err.to_string())
+            .and_then(|()| recv_fds(&stream).map_err(|err| err.to_string()));
+        match held {
+            Ok(fds) if fds.len() == NUM_DEVICES => {
+                debug!("Reusing the uinput devices of {name:?} kept by the helper.");
+                let fds = fds.try_into().unwrap();
+                Ok((Some(Self { _stream: stream }), fds))
+            }
+            Ok(_) => {
+                let fds = create()?;
+                match send_fds(&stream, &fds) {
+                    Ok(()) => Ok((Some(Self { _stream: stream }), fds)),
+                    Err(err) => {
+                        warn!("Failed to hand the uinput devices to the helper: {err}");
+                        Ok((None, fds))
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("Failed to claim uinput devices from the helper: {err}");
+                create().map(|fds| (None, fds))
+            }
+        }
+    }
+}
+
+/// Connect to the helper, starting it if it is not running.
+fn connect() -> Option<UnixStream> {
+    let path = match socket_path() {
+        Ok(path) => path,
+        Err(err) => {
+            warn!("Failed to locate the socket of the helper keeping uinput devices: {err}");
+            return None;
+        }
+    };
+    if let Ok(stream) = UnixStream::connect(&path) {
+        return Some(stream);
+    }
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            warn!("Failed to start the helper keeping uinput devices: {err}");
+            return None;
+        }
+    };
+    // a process group of its own keeps the helper alive if Weylus is interrupted in a terminal
+    if let Err(err) = Command::new(exe)
+        .arg("uinput-holder")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .process_group(0)
+        .spawn()
+    {
+        warn!("Failed to start the helper keeping uinput devices: {err}");
+        return None;
+    }
+    let start = Instant::now();
+    while start.elapsed() < STARTUP_TIMEOUT {
+        sleep(Duration::from_millis(50));
+        if let Ok(stream) = UnixStream::connect(&path) {
+            return Some(stream);
+        }
+    }
+    warn!("The helper keeping uinput devices did not start, the devices are not persisted.");
+    None
+}
+
+struct Held {
+    fds: [c_int; NUM_DEVICES],
+    hold: Duration,
+    /// Whether a connection uses the devices.
+    claimed: bool,
+    released: Instant,
+}
+
+#[derive(Default)]
+struct HolderState {
+    devices: HashMap<String, Held>,
+    connections: usize,
+}
+
+/// Run the helper keeping uinput devices, this is `weylus uinput-holder`. Returns once it holds
+/// no devices anymore.
+pub fn run() -> io::Result<()> {
+    let path = socket_path()?;
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(&path).is_ok() {
+                info!("The helper keeping uinput devices is running already.");
+                return Ok(());
+            }
+            // left behind by a helper that was killed
+            std::fs::remove_file(&path)?;
+            UnixListener::bind(&path)?
+        }
+        Err(err) => return Err(err),
+    };
+    if let Err(err) = private_socket::restrict(&path) {
+        let _ = std::fs::remove_file(&path);
+        return Err(err);
+    }
+    listener.set_nonblocking(true)?;
+    let state = Arc::new(Mutex::new(HolderState::default()));
+    let start = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => match check_peer(&stream) {
+                Ok(()) => {
+                    let state = state.clone();
+                    state.lock().unwrap().connections += 1;
+                    spawn(move || {
+                        if let Err(err) = serve(&stream, &state) {
+                            warn!("Failed to serve Weylus: {err}");
+                        }
+                        state.lock().unwrap().connections -= 1;
+                    });
+                }
+                Err(err) => warn!("Refused connection: {err}"),
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                sleep(Duration::from_millis(200));
+            }
+            Err(err) => warn!("Failed to accept connection: {err}"),
+        }
+        let mut state = state.lock().unwrap();
+        state.devices.retain(|name, held| {
+            let expired = !held.claimed && held.released.elapsed() >= held.hold;
+            if expired {
+                debug!("Destroying the uinput devices of {name:?}.");
+                for fd in held.fds {
+                    unsafe { destroy_uinput_device(fd) };
+                }
+            }
+            !expired
+        });
+        if state.devices.is_empty() && state.connections == 0 && start.elapsed() > IDLE_TIMEOUT {
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// Serve a single set of devices until Weylus lets go of them.
+fn serve(stream: &UnixStream, state: &Mutex<HolderState>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    // read byte by byte, the file descriptors must not be consumed by reading ahead
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while (&*stream).read(&mut byte)? == 1 && byte[0] != b'\n' {
+        line.push(byte[0]);
+    }
+    let line = String::from_utf8_lossy(&line).into_owned();
+    let (hold, name) = line
+        .split_once(' ')
+        .and_then(|(hold, name)| Some((hold.parse().ok()?, name.to_string())))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed request"))?;
+    let hold = Duration::from_secs(hold);
+
+    let held = {
+        let mut state = state.lock().unwrap();
+        match state.devices.get_mut(&name) {
+            Some(held) if !held.claimed => {
+                held.claimed = true;
+                held.hold = hold;
+                Some(held.fds)
+            }
+            // devices of the same name are in use, the new ones are not kept
+            Some(_) => {
+                send_fds(stream, &[]).map_err(io::Error::other)?;
+                return Ok(());
+            }
+            None => None,
+        }
+    };
+    match held {
+        Some(fds) => send_fds(stream, &fds).map_err(io::Error::other)?,
+        None => {
+            send_fds(stream, &[]).map_err(io::Error::other)?;
+            let fds = recv_fds(stream).map_err(io::Error::other)?;
+            let Ok(fds) = <[c_int; NUM_DEVICES]>::try_from(fds) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected the file descriptors of all devices",
+                ));
+            };
+            state.lock().unwrap().devices.insert(
+                name.clone(),
+                Held {
+                    fds,
+                    hold,
+                    claimed: true,
+                    released: Instant::now(),
+                },
+            );
+        }
+    }
+
+    // wait until Weylus closes the connection, there is nothing else to read
+    while (&*stream).read(&mut byte).unwrap_or(0) > 0 {}
+
+    let mut state = state.lock().unwrap();
+    if let Some(held) = state.devices.get_mut(&name) {
+        // Weylus may have crashed with a key or button held down
+        for fd in held.fds {
+            unsafe { release_uinput_device(fd) };
+        }
+        held.claimed = false;
+        held.released = Instant::now();
+        debug!("Weylus let go of the uinput devices of {name:?}.");
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Keeps strokes in place while the client is rotated. Pointer positions are relative to the
+//! video as shown by the client, which is laid out anew once the device is rotated. Strokes
+//! still in progress would continue at the position the pointer has on the new layout and jump
+//! across the capturable, so pointers down while the viewport rotates are lifted and their events
+//! dropped until they are lifted on the client as well.
+
+use std::collections::{HashMap, HashSet};
+
+use tracing::debug;
+
+use crate::protocol::{Button, ClientViewport, Orientation, PointerEvent, PointerEventType};
+
+#[derive(Default)]
+pub struct ViewportMapping {
+    viewport: Option<ClientViewport>,
+    /// Last event of every pointer down on the client.
+    down: HashMap<i64, PointerEvent>,
+    /// Pointers lifted because the viewport rotated while they were down.
+    stale: HashSet<i64>,
+}
+
+impl ViewportMapping {
+    /// Set the viewport reported by the client, returns the events lifting the pointers that are
+    /// down if it has been rotated.
+    pub fn set_viewport(&mut self, viewport: ClientViewport) -> Vec<PointerEvent> {
+        let rotated = self.viewport.is_some_and(|old| {
+            old.angle != viewport.angle || old.orientation() != viewport.orientation()
+        });
+        self.viewport = Some(viewport);
+        if !rotated {
+            return Vec::new();
+        }
+        debug!(
+            "Client rotated to {:?} at {} degrees, lifting {} pointer(s).",
+            viewport.orientation(),
+            viewport.angle,
+            self.down.len()
+        );
+        self.lift_all()
+    }
+
+    /// Lift all pointers that are down, their events are dropped until they are lifted on the
+    /// client as well. Returns the events lifting them.
+    pub fn lift_all(&mut self) -> Vec<PointerEvent> {
+        self.down
+            .drain()
+            .map(|(id, mut event)| {
+                self.stale.insert(id);
+                event.event_type = PointerEventType::CANCEL;
+                event.button = Button::NONE;
+                event.buttons = Button::NONE;
+                event.pressure = 0.0;
+                event
+            })
+            .collect()
+    }
+
+    /// Whether the pointer has been lifted because of a rotation and is still down on the client.
+    pub fn is_lifted(&self, pointer_id: i64) -> bool {
+        self.stale.contains(&pointer_id)
+    }
+
+    /// Whether the event is to be processed, events of pointers lifted because of a rotation are
+    /// not.
+    pub fn filter(&mut self, event: &PointerEvent) -> bool {
+        let lifted = matches!(
+            event.event_type,
+            PointerEventType::UP
+                | PointerEventType::CANCEL
+                | PointerEventType::LEAVE
+                | PointerEventType::OUT
+        );
+        if self.stale.contains(&event.pointer_id) {
+            // a new stroke starts on the new layout
+            if event.event_type == PointerEventType::DOWN {
+                self.stale.remove(&event.pointer_id);
+            } else {
+                if lifted {
+                    self.stale.remove(&event.pointer_id);
+                }
+                return false;
+            }
+        }
+        if lifted {
+            self.down.remove(&event.pointer_id);
+        } else if event.event_type == PointerEventType::DOWN
+            || self.down.contains_key(&event.pointer_id)
+        {
+            self.down.insert(event.pointer_id, event.clone());
+        }
+        true
+    }
+}
+
+impl ClientViewport {
+    pub fn orientation(&self) -> Orientation {
+        Orientation::of(self.width, self.height)
+    }
+}
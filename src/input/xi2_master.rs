@@ -0,0 +1,93 @@
+//! Dedicated XInput2 master pointer and keyboard for the devices created via uinput.
+//!
+//! By default X attaches new devices to the core pointer and keyboard, so the pen and the host's
+//! mouse fight over a single cursor. Attached to a master device of their own, the virtual devices
+//! move a second cursor with its own keyboard focus and two people can work at the same time.
+
+use std::collections::HashMap;
+use std::os::raw::c_int;
+
+use tracing::{debug, info, warn};
+
+use crate::capturable::x11::X11Context;
+use crate::input::cleanup::{self, CleanupGuard};
+
+/// Devices created by uinput show up in X some time later, give up attaching them after this many
+/// attempts, see also MAX_SCREEN_MAPPING_TRIES.
+const MAX_ATTACH_TRIES: usize = 100;
+
+pub struct MasterDevice {
+    x11ctx: X11Context,
+    master_pointer: c_int,
+    /// Number of attempts to attach each device, None once it is attached.
+    attach_tries: HashMap<String, Option<usize>>,
+    cleanup: CleanupGuard,
+}
+
+impl MasterDevice {
+    pub fn new(name: &str) -> Option<Self> {
+        let Some(mut x11ctx) = X11Context::new() else {
+            warn!("Failed to open X display, can not create XInput2 master device.");
+            return None;
+        };
+        let master_pointer = match x11ctx.create_master_device(name) {
+            Ok(id) => id,
+            Err(err) => {
+                warn!("Failed to create XInput2 master device: {err}");
+                return None;
+            }
+        };
+        info!("Created XInput2 master device {name}.");
+        // a master device left behind would keep a second cursor on the screen
+        let cleanup = cleanup::register(move || {
+            if let Some(mut x11ctx) = X11Context::new() {
+                x11ctx.remove_master_device(master_pointer);
+            }
+        });
+        Some(Self {
+            x11ctx,
+            master_pointer,
+            attach_tries: HashMap::new(),
+            cleanup,
+        })
+    }
+
+    /// Attach the device with the given name to the master device, this is retried on every call
+    /// until the device appears in X.
+    pub fn attach(&mut self, device_name: &str) {
+        let tries = self
+            .attach_tries
+            .entry(device_name.to_string())
+            .or_insert(Some(0));
+        let Some(n) = tries else {
+            return;
+        };
+        if *n >= MAX_ATTACH_TRIES {
+            return;
+        }
+        *n += 1;
+        let err = self
+            .x11ctx
+            .attach_to_master_device(device_name, self.master_pointer);
+        if err.is_err() {
+            if *n == MAX_ATTACH_TRIES {
+                warn!("Failed to attach {device_name} to XInput2 master device: {err}");
+            }
+        } else {
+            debug!("Attached {device_name} to XInput2 master device.");
+            *tries = None;
+        }
+    }
+}
+
+impl Drop for MasterDevice {
+    fn drop(&mut self) {
+        if !self.cleanup.unregister() {
+            return;
+        }
+        let err = self.x11ctx.remove_master_device(self.master_pointer);
+        if err.is_err() {
+            warn!("Failed to remove XInput2 master device: {err}");
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Invite links give guests temporary access without revealing the access code.
+//!
+//! Opening an invite link starts a session that is kept in a cookie, the link itself stops
+//! working once it has been used up or has expired. Sessions end when their invite expires, or
+//! after a day for invites that do not expire.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::oidc::random_string;
+use crate::websocket::ClientRole;
+
+pub const INVITE_COOKIE: &str = "weylus_invite";
+
+/// Sessions started with an invite that does not expire last this long.
+const SESSION_DURATION: Duration = Duration::from_secs(3600 * 24);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InviteOptions {
+    pub role: ClientRole,
+    /// How long the invite and the sessions started with it are valid, forever if None.
+    pub valid_for: Option<Duration>,
+    /// How often the invite can be used, unlimited if None.
+    pub uses: Option<u32>,
+}
+
+struct Invite {
+    role: ClientRole,
+    expires: Option<Instant>,
+    remaining_uses: Option<u32>,
+}
+
+struct Session {
+    role: ClientRole,
+    expires: Instant,
+}
+
+/// Keeps track of the invites of a web server and of the sessions started with them.
+#[derive(Default)]
+pub struct Invites {
+    invites: Mutex<HashMap<String, Invite>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    revoked: tokio::sync::Notify,
+}
+
+impl Invites {
+    /// Create a new invite and return the token identifying it, None if it could never be used.
+    pub fn create(&self, options: &InviteOptions) -> Option<String> {
+        self.create_at(options, Instant::now())
+    }
+
+    fn create_at(&self, options: &InviteOptions, now: Instant) -> Option<String> {
+        if options.uses == Some(0) {
+            return None;
+        }
+        let token = random_string(32);
+        let mut invites = self.invites.lock().unwrap();
+        invites.retain(|_, i| i.expires.map_or(true, |e| e > now));
+        invites.insert(
+            token.clone(),
+            Invite {
+                role: options.role,
+                expires: options.valid_for.map(|d| now + d),
+                remaining_uses: options.uses,
+            },
+        );
+        Some(token)
+    }
+
+    /// Use up the invite with the given token, this starts a new session and returns the cookie
+    /// to set or None if the invite is unknown, used up or expired.
+    pub fn redeem(&self, token: &str) -> Option<String> {
+        self.redeem_at(token, Instant::now())
+    }
+
+    fn redeem_at(&self, token: &str, now: Instant) -> Option<String> {
+        let (role, expires) = {
+            let mut invites = self.invites.lock().unwrap();
+            let invite = invites.get_mut(token)?;
+            if invite.expires.is_some_and(|e| e <= now) {
+                debug!("Invite expired.");
+                invites.remove(token);
+                return None;
+            }
+            let (role, expires) = (invite.role, invite.expires);
+            let used_up = match invite.remaining_uses.as_mut() {
+                Some(0) => {
+                    invites.remove(token);
+                    return None;
+                }
+                Some(1) => true,
+                Some(n) => {
+                    *n -= 1;
+                    false
+                }
+                None => false,
+            };
+            if used_up {
+                invites.remove(token);
+            }
+            (role, expires.unwrap_or(now + SESSION_DURATION))
+        };
+        let id = random_string(32);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(id.clone(), Session { role, expires });
+        Some(format!(
+            "{INVITE_COOKIE}={id}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax",
+            (expires - now).as_secs()
+        ))
+    }
+
+    /// Looks up the invite cookie in the given headers and returns the role of the guest if the
+    /// session is valid.
+    pub fn session_role(&self, headers: &HeaderMap) -> Option<ClientRole> {
+        self.session_role_at(headers, Instant::now())
+    }
+
+    fn session_role_at(&self, headers: &HeaderMap, now: Instant) -> Option<ClientRole> {
+        let id = headers
+            .get_all(hyper::header::COOKIE)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(';'))
+            .filter_map(|c| c.trim().split_once('='))
+            .find(|(name, _)| *name == INVITE_COOKIE)
+            .map(|(_, value)| value)?;
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id)?;
+        if session.expires > now {
+            Some(session.role)
+        } else {
+            debug!("Invite session expired.");
+            None
+        }
+    }
+
+    /// Invalidate all invites and end all sessions started with them, returns the number of
+    /// invites that were still valid. Guests that are still connected are disconnected by the
+    /// web server once it notices via [`Invites::revoked`].
+    pub fn revoke_all(&self) -> usize {
+        let now = Instant::now();
+        let mut invites = self.invites.lock().unwrap();
+        let revoked = invites
+            .values()
+            .filter(|i| i.expires.map_or(true, |e| e > now))
+            .count();
+        invites.clear();
+        self.sessions.lock().unwrap().clear();
+        self.revoked.notify_waiters();
+        revoked
+    }
+
+    /// Completes once all invites are revoked.
+    pub async fn revoked(&self) {
+        self.revoked.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::{HeaderValue, COOKIE};
+
+    fn options(valid_for: Option<Duration>, uses: Option<u32>) -> InviteOptions {
+        InviteOptions {
+            role: ClientRole::Controller,
+            valid_for,
+            uses,
+        }
+    }
+
+    /// Headers sending the session cookie set by a redeemed invite.
+    fn headers(set_cookie: &str) -> HeaderMap {
+        let (session, attributes) = set_cookie.split_once(';').unwrap();
+        assert!(attributes.contains("HttpOnly"));
+        let mut headers = HeaderMap::new();
+        let cookie = format!("other=1; {session}");
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        headers
+    }
+
+    fn max_age(set_cookie: &str) -> u64 {
+        set_cookie
+            .split("; ")
+            .find_map(|a| a.strip_prefix("Max-Age="))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn use_counting() {
+        let invites = Invites::default();
+        let now = Instant::now();
+        let once = invites.create_at(&options(None, Some(1)), now).unwrap();
+        assert!(invites.redeem_at(&once, now).is_some());
+        assert!(invites.redeem_at(&once, now).is_none());
+
+        let thrice = invites.create_at(&options(None, Some(3)), now).unwrap();
+        for _ in 0..3 {
+            assert!(invites.redeem_at(&thrice, now).is_some());
+        }
+        assert!(invites.redeem_at(&thrice, now).is_none());
+
+        let unlimited = invites.create_at(&options(None, None), now).unwrap();
+        for _ in 0..100 {
+            assert!(invites.redeem_at(&unlimited, now).is_some());
+        }
+        assert!(invites.redeem_at("unknown", now).is_none());
+    }
+
+    #[test]
+    fn invites_without_uses_are_rejected() {
+        let invites = Invites::default();
+        assert!(invites.create(&options(None, Some(0))).is_none());
+        assert!(invites.invites.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expiry() {
+        let invites = Invites::default();
+        let now = Instant::now();
+        let minutes = |m| Duration::from_secs(m * 60);
+        let token = invites
+            .create_at(&options(Some(minutes(10)), None), now)
+            .unwrap();
+        let cookie = invites.redeem_at(&token, now + minutes(4)).unwrap();
+        // the session ends with the invite
+        assert_eq!(max_age(&cookie), 6 * 60);
+        let headers = headers(&cookie);
+        assert_eq!(
+            invites.session_role_at(&headers, now + minutes(9)),
+            Some(ClientRole::Controller)
+        );
+        assert_eq!(invites.session_role_at(&headers, now + minutes(10)), None);
+        assert!(invites.redeem_at(&token, now + minutes(10)).is_none());
+        // expired invites are forgotten
+        assert!(invites.invites.lock().unwrap().is_empty());
+        assert!(invites.redeem_at(&token, now).is_none());
+    }
+
+    #[test]
+    fn sessions_of_invites_that_do_not_expire() {
+        let invites = Invites::default();
+        let now = Instant::now();
+        let token = invites.create_at(&options(None, Some(1)), now).unwrap();
+        let cookie = invites.redeem_at(&token, now).unwrap();
+        assert_eq!(max_age(&cookie), SESSION_DURATION.as_secs());
+        let headers = headers(&cookie);
+        assert!(invites
+            .session_role_at(&headers, now + SESSION_DURATION / 2)
+            .is_some());
+        assert!(invites
+            .session_role_at(&headers, now + SESSION_DURATION)
+            .is_none());
+    }
+
+    #[test]
+    fn session_role() {
+        let invites = Invites::default();
+        let viewer = InviteOptions {
+            role: ClientRole::Viewer,
+            valid_for: None,
+            uses: None,
+        };
+        let token = invites.create(&viewer).unwrap();
+        let cookie = invites.redeem(&token).unwrap();
+        assert_eq!(
+            invites.session_role(&headers(&cookie)),
+            Some(ClientRole::Viewer)
+        );
+        assert_eq!(invites.session_role(&HeaderMap::new()), None);
+        let forged = format!("{INVITE_COOKIE}={token}; Path=/; HttpOnly");
+        assert_eq!(invites.session_role(&headers(&forged)), None);
+    }
+
+    #[test]
+    fn revoke_all() {
+        let invites = Invites::default();
+        let now = Instant::now();
+        let token = invites.create_at(&options(None, None), now).unwrap();
+        // expires right away and does not count as revoked
+        invites
+            .create_at(&options(Some(Duration::ZERO), None), now)
+            .unwrap();
+        let cookie = invites.redeem_at(&token, now).unwrap();
+        assert!(invites.session_role_at(&headers(&cookie), now).is_some());
+        assert_eq!(invites.revoke_all(), 1);
+        assert!(invites.redeem_at(&token, now).is_none());
+        assert!(invites.session_role_at(&headers(&cookie), now).is_none());
+        assert_eq!(invites.revoke_all(), 0);
+    }
+}
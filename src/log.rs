@@ -1,9 +1,18 @@
 use std::ffi::CStr;
 use std::io::Write;
 use std::os::raw::c_char;
-use std::sync::mpsc;
+use std::sync::{mpsc, Mutex, OnceLock};
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
 use tracing::{debug, error, info, trace, warn};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, Layer, Registry};
 
 extern "C" {
     fn init_ffmpeg_logger();
@@ -54,35 +63,43 @@ pub fn get_log_level() -> tracing::Level {
     level
 }
 
+/// Placeholder for the OTLP exporter, which can only be set up once the configuration has been
+/// read. Logging on the other hand is required right from the start.
+type OtlpLayer = Option<OpenTelemetryLayer<Registry, opentelemetry_sdk::trace::Tracer>>;
+
+static OTLP_HANDLE: OnceLock<reload::Handle<OtlpLayer, Registry>> = OnceLock::new();
+static TRACER_PROVIDER: Mutex<Option<TracerProvider>> = Mutex::new(None);
+
 pub fn setup_logging(sender: mpsc::SyncSender<String>) {
+    let level = LevelFilter::from_level(get_log_level());
+    let (otlp_layer, otlp_handle) = reload::Layer::new(None);
+    OTLP_HANDLE.set(otlp_handle).ok();
+    let gui_layer = tracing_subscriber::fmt::Layer::default()
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .compact()
+        .with_writer(GuiTracingWriterFactory { sender })
+        .with_filter(level);
+    let registry = tracing_subscriber::registry().with(otlp_layer);
     if std::env::var("WEYLUS_LOG_JSON").is_ok() {
-        let logger = tracing_subscriber::fmt()
-            .json()
-            .with_max_level(get_log_level())
-            .with_writer(std::io::stdout)
-            .finish()
+        let logger = registry
             .with(
                 tracing_subscriber::fmt::Layer::default()
-                    .with_ansi(false)
-                    .without_time()
-                    .with_target(false)
-                    .compact()
-                    .with_writer(GuiTracingWriterFactory { sender }),
-            );
+                    .json()
+                    .with_writer(std::io::stdout)
+                    .with_filter(level),
+            )
+            .with(gui_layer);
         tracing::subscriber::set_global_default(logger).expect("Failed to setup logger!");
     } else {
-        let logger = tracing_subscriber::fmt()
-            .with_max_level(get_log_level())
-            .with_writer(std::io::stderr)
-            .finish()
+        let logger = registry
             .with(
                 tracing_subscriber::fmt::Layer::default()
-                    .with_ansi(false)
-                    .without_time()
-                    .with_target(false)
-                    .compact()
-                    .with_writer(GuiTracingWriterFactory { sender }),
-            );
+                    .with_writer(std::io::stderr)
+                    .with_filter(level),
+            )
+            .with(gui_layer);
         tracing::subscriber::set_global_default(logger).expect("Failed to setup logger!");
     }
     unsafe {
@@ -90,6 +107,35 @@ pub fn setup_logging(sender: mpsc::SyncSender<String>) {
     }
 }
 
+/// Start exporting tracing spans to the given OTLP/HTTP endpoint.
+pub fn enable_otlp(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::TokioCurrentThread)
+        .with_resource(Resource::new([KeyValue::new("service.name", "weylus")]))
+        .build();
+    let tracer = provider.tracer("weylus");
+    OTLP_HANDLE
+        .get()
+        .ok_or("Logging has not been set up.")?
+        .reload(Some(tracing_opentelemetry::layer().with_tracer(tracer)))?;
+    *TRACER_PROVIDER.lock().unwrap() = Some(provider);
+    info!("Exporting traces to {endpoint}.");
+    Ok(())
+}
+
+/// Flush all pending spans and stop the OTLP exporter.
+pub fn shutdown_otlp() {
+    if let Some(provider) = TRACER_PROVIDER.lock().unwrap().take() {
+        if let Err(err) = provider.shutdown() {
+            warn!("Failed to shut down OTLP exporter: {err}");
+        }
+    }
+}
+
 #[no_mangle]
 fn log_error_rust(msg: *const c_char) {
     let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
@@ -10,31 +10,92 @@ use clap_complete::generate;
 #[cfg(unix)]
 use signal_hook::iterator::Signals;
 #[cfg(unix)]
-use signal_hook::{consts::TERM_SIGNALS, low_level::signal_name};
+use signal_hook::{
+    consts::{SIGHUP, TERM_SIGNALS},
+    low_level::signal_name,
+};
 use tracing::{error, info, warn};
 
 use std::sync::mpsc;
 
-use config::{get_config, Config};
+use config::{get_config, Command, Config};
 
+mod annotations;
+mod approval;
+mod bandwidth_cap;
+mod battery;
+mod broadcast;
+mod build_info;
 mod capturable;
 mod cerror;
+mod client_settings;
 mod config;
+mod control;
+#[cfg(not(target_os = "windows"))]
+mod direct_connect;
+mod discovery;
+mod encoder_probe;
+mod encryption;
+mod file_share;
+#[cfg(target_os = "windows")]
+mod firewall;
+mod frame_stamp;
+mod frame_stats;
+#[cfg(feature = "gui")]
 mod gui;
+mod h264_level;
+mod hooks;
 mod input;
+mod invite;
 mod log;
+mod network_quality;
+mod notifications;
+mod oidc;
+mod permissions;
+mod power;
+mod preroll;
+#[cfg(unix)]
+mod private_socket;
 mod protocol;
+mod protocol_replay;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod schedule;
+mod secrets;
+mod selftest;
+mod session_summary;
+mod thumbnail;
 mod video;
+mod wake;
+mod watermark;
 mod web;
 mod websocket;
+mod webtransport;
 mod weylus;
 
 fn main() {
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
     let (sender, receiver) = mpsc::sync_channel::<String>(100);
 
     log::setup_logging(sender);
+    input::cleanup::install_panic_hook();
+
+    #[cfg(target_os = "windows")]
+    capturable::win_graphics_capture::set_dpi_aware();
+
+    #[allow(unused_mut)]
+    let mut conf = get_config();
+
+    if let Some(endpoint) = conf.otlp_endpoint.as_ref() {
+        if let Err(err) = log::enable_otlp(endpoint) {
+            error!("Failed to set up OTLP exporter: {err}");
+        }
+    }
 
-    let conf = get_config();
+    if conf.version {
+        build_info::print_version(conf.verbose);
+        return;
+    }
 
     if let Some(shell) = conf.completions {
         generate(
@@ -46,6 +107,158 @@ fn main() {
         return;
     }
 
+    match conf.command {
+        Some(Command::Status { json }) => {
+            print_status(conf.web_port, json);
+            return;
+        }
+        Some(Command::Invite {
+            minutes,
+            uses,
+            unlimited,
+            viewer,
+            ref instance,
+        }) => {
+            let options = invite::InviteOptions {
+                role: if viewer {
+                    websocket::ClientRole::Viewer
+                } else {
+                    websocket::ClientRole::Controller
+                },
+                valid_for: (minutes > 0).then(|| std::time::Duration::from_secs(minutes * 60)),
+                uses: (!unlimited).then_some(uses),
+            };
+            let request = control::ControlRequest::CreateInvite {
+                instance: instance.clone(),
+                options,
+            };
+            match control::request(conf.web_port, &request) {
+                Ok(control::ControlResponse::Invite(url)) => println!("{url}"),
+                Ok(control::ControlResponse::Error(err)) => {
+                    error!("Failed to create invite: {err}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+                Ok(response) => {
+                    error!("Unexpected response: {response:?}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+                Err(err) => {
+                    error!("{err}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+            }
+            return;
+        }
+        Some(Command::RevokeInvites { ref instance }) => {
+            let request = control::ControlRequest::RevokeInvites {
+                instance: instance.clone(),
+            };
+            match control::request(conf.web_port, &request) {
+                Ok(control::ControlResponse::InvitesRevoked(n)) => {
+                    println!("Revoked {n} invites.")
+                }
+                Ok(response) => {
+                    error!("Unexpected response: {response:?}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+                Err(err) => {
+                    error!("{err}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+            }
+            return;
+        }
+        Some(Command::InputBackend { backend, client }) => {
+            let request = control::ControlRequest::SetInputBackend { backend, client };
+            match control::request(conf.web_port, &request) {
+                Ok(control::ControlResponse::InputBackendSet(0)) => {
+                    error!("No matching client is connected.");
+                    std::process::exit(1);
+                }
+                Ok(control::ControlResponse::InputBackendSet(n)) => {
+                    println!("Switched {n} client(s) to {}.", backend.name())
+                }
+                Ok(response) => {
+                    error!("Unexpected response: {response:?}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+                Err(err) => {
+                    error!("{err}");
+                    std::process::exit(EXIT_UNREACHABLE);
+                }
+            }
+            return;
+        }
+        Some(Command::Approve { id, remember, deny }) => {
+            approve(conf.web_port, id, remember, deny);
+            return;
+        }
+        #[cfg(target_os = "windows")]
+        Some(Command::Firewall { action }) => {
+            let res = match action {
+                config::FirewallAction::Add => {
+                    let rules = firewall::required_rules(&conf);
+                    firewall::add_rules(&rules).map(|()| {
+                        format!(
+                            "Allowed {} in the Windows Firewall.",
+                            firewall::describe(&rules)
+                        )
+                    })
+                }
+                config::FirewallAction::Remove => firewall::remove_rules()
+                    .map(|()| "Removed the rules of Weylus from the Windows Firewall.".into()),
+            };
+            match res {
+                Ok(message) => println!("{message}"),
+                Err(err) => {
+                    error!("Failed to update the Windows Firewall: {err}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        Some(Command::UinputHolder) => {
+            if let Err(err) = input::uinput_holder::run() {
+                error!("Failed to keep uinput devices: {err}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::RotateAccessCode) => {
+            // settings passed on the command line only end up in the file if there is none yet
+            let mut file_conf = config::read_config().unwrap_or_else(|| conf.clone());
+            file_conf.no_keyring = conf.no_keyring;
+            if !file_conf.no_keyring {
+                secrets::load(&mut file_conf);
+            }
+            let access_code = oidc::random_string(12);
+            file_conf.access_code = Some(access_code.clone());
+            config::write_config(&file_conf);
+            println!("{access_code}");
+            return;
+        }
+        Some(Command::Completions { shell }) => {
+            generate(
+                shell,
+                &mut Config::command(),
+                "weylus",
+                &mut std::io::stdout(),
+            );
+            return;
+        }
+        Some(Command::Manpage) => {
+            if let Err(err) =
+                clap_mangen::Man::new(Config::command()).render(&mut std::io::stdout())
+            {
+                error!("Failed to write man page: {err}");
+            }
+            return;
+        }
+        // capturing needs the setup below
+        Some(Command::Selftest) | None => (),
+    }
+
     if conf.print_index_html {
         print!("{}", web::INDEX_HTML);
         return;
@@ -63,11 +276,28 @@ fn main() {
         return;
     }
 
+    #[cfg(not(feature = "gui"))]
+    if !conf.no_gui {
+        info!("Weylus was built without gui, running without gui.");
+        conf.no_gui = true;
+    }
+
     #[cfg(target_os = "linux")]
     {
+        if let Some(display) = conf.display.as_ref() {
+            // everything talking to X, including autopilot, connects to $DISPLAY
+            std::env::set_var("DISPLAY", display);
+        }
+
         // make sure XInitThreads is called before any threading is done
         crate::capturable::x11::x11_init();
 
+        sandbox::init(conf.sandboxed);
+        if !sandbox::is_sandboxed() && crate::capturable::is_wayland_session() {
+            info!("Running in a Wayland session, screens are captured via PipeWire.");
+        }
+
+        #[cfg(feature = "wayland")]
         if let Err(err) = gstreamer::init() {
             error!(
                 "Failed to initialize gstreamer, screen capturing will most likely not work \
@@ -77,28 +307,89 @@ fn main() {
         }
     }
 
+    if let Some(path) = conf.replay_protocol.as_ref() {
+        let replayed = protocol_replay::replay(&conf, path);
+        log::shutdown_otlp();
+        std::process::exit(if replayed { 0 } else { 1 });
+    }
+
+    #[cfg(target_os = "macos")]
+    if conf.command.is_none() {
+        permissions::request_missing();
+    }
+
+    // the GUI offers to create the rules
+    #[cfg(target_os = "windows")]
+    if conf.command.is_none() && conf.no_gui && !conf.no_firewall_prompt {
+        firewall::warn_missing(&conf);
+    }
+
+    if let Some(Command::Selftest) = conf.command {
+        let passed = selftest::run(&conf);
+        log::shutdown_otlp();
+        std::process::exit(if passed { 0 } else { EXIT_SELFTEST_FAILED });
+    }
+
+    if let Some(path) = conf.record_protocol.as_ref() {
+        if let Err(err) = protocol_replay::start_recording(path) {
+            error!("Failed to record to {}: {err}", path.display());
+            log::shutdown_otlp();
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = conf.dhcp_interface.as_deref() {
+        if let Err(err) = direct_connect::serve_dhcp(interface) {
+            error!("Failed to serve DHCP on {interface}: {err}");
+            log::shutdown_otlp();
+            std::process::exit(1);
+        }
+    }
+
+    // keep the name on the session bus until Weylus exits
+    #[cfg(target_os = "linux")]
+    let _bus_name = sandbox::is_sandboxed().then(sandbox::own_bus_name);
+
     if conf.no_gui {
         let mut weylus = crate::weylus::Weylus::new();
-        weylus.start(&conf, |msg| match msg {
+        if let Err(err) = weylus.start(&conf, |msg| match msg {
             web::Web2UiMessage::UInputInaccessible => {
                 warn!(std::include_str!("strings/uinput_error.txt"))
             }
-        });
+        }) {
+            error!("Failed to start Weylus: {err}");
+            log::shutdown_otlp();
+            std::process::exit(err.exit_code());
+        }
+        if let Some(reloader) = weylus.reloader() {
+            config::watch_config(move || {
+                reloader.reload(&get_config());
+            });
+        }
         #[cfg(unix)]
         {
-            let mut signals = Signals::new(TERM_SIGNALS).unwrap();
+            let mut signals = Signals::new(TERM_SIGNALS.iter().chain([&SIGHUP])).unwrap();
             for sig in signals.forever() {
+                if sig == SIGHUP {
+                    info!("Reloading configuration after receiving SIGHUP...");
+                    if let Some(reloader) = weylus.reloader() {
+                        reloader.reload(&get_config());
+                    }
+                    continue;
+                }
                 info!(
                     "Shutting down after receiving signal {signame} ({sig})...",
                     signame = signal_name(sig).unwrap_or("UNKNOWN SIGNAL")
                 );
                 std::thread::spawn(move || {
-                    for sig in signals.forever() {
+                    for sig in signals.forever().filter(|sig| *sig != SIGHUP) {
                         warn!(
                             "Received second signal {signame} ({sig}) while shutting down \
                             gracefully, proceeding with forceful shutdown...",
                             signame = signal_name(sig).unwrap_or("UNKNOWN SIGNAL")
                         );
+                        input::cleanup::run_all();
                         std::process::exit(1);
                     }
                 });
@@ -113,8 +404,29 @@ fn main() {
             }
         }
     } else {
+        #[cfg(not(feature = "gui"))]
+        unreachable!("no_gui is set if Weylus is built without gui");
+        #[cfg(all(feature = "gui", unix))]
+        {
+            // the gui does not handle signals, make sure no keys or buttons remain pressed if
+            // Weylus is killed
+            let mut signals = Signals::new(TERM_SIGNALS).unwrap();
+            std::thread::spawn(move || {
+                if let Some(sig) = signals.forever().next() {
+                    info!(
+                        "Exiting after receiving signal {signame} ({sig})...",
+                        signame = signal_name(sig).unwrap_or("UNKNOWN SIGNAL")
+                    );
+                    input::cleanup::run_all();
+                    log::shutdown_otlp();
+                    std::process::exit(1);
+                }
+            });
+        }
+        #[cfg(feature = "gui")]
         gui::run(&conf, receiver);
     }
+    log::shutdown_otlp();
 }
 
 #[cfg(feature = "bench")]
@@ -128,7 +440,7 @@ mod tests {
     #[bench]
     fn bench_capture_x11(b: &mut Bencher) {
         let mut x11ctx = capturable::x11::X11Context::new().unwrap();
-        let root = x11ctx.capturables().unwrap().remove(0);
+        let root = x11ctx.capturables(Default::default()).unwrap().remove(0);
         let mut r = root.recorder(false).unwrap();
         b.iter(|| {
             r.capture().unwrap();
@@ -139,7 +451,7 @@ mod tests {
     #[bench]
     fn bench_video_x11(b: &mut Bencher) {
         let mut x11ctx = capturable::x11::X11Context::new().unwrap();
-        let root = x11ctx.capturables().unwrap().remove(0);
+        let root = x11ctx.capturables(Default::default()).unwrap().remove(0);
         let mut r = root.recorder(false).unwrap();
         let (width, height) = r.capture().unwrap().size();
 
@@ -148,13 +460,16 @@ mod tests {
             try_nvenc: true,
             try_videotoolbox: false,
             try_mediafoundation: false,
+            threads: 0,
+            slices: 0,
+            tuning: video::LatencyPreset::default().tuning(),
         };
         let mut encoder =
             video::VideoEncoder::new(width, height, width, height, |_| {}, opts).unwrap();
         b.iter(|| encoder.encode(r.capture().unwrap()));
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
     #[bench]
     fn bench_capture_wayland(b: &mut Bencher) {
         gstreamer::init().unwrap();
@@ -168,7 +483,7 @@ mod tests {
         });
     }
 
-    #[cfg(target_os = "linux")]
+    #[cfg(all(target_os = "linux", feature = "wayland"))]
     #[bench]
     fn bench_video_wayland(b: &mut Bencher) {
         gstreamer::init().unwrap();
@@ -183,6 +498,9 @@ mod tests {
             try_nvenc: true,
             try_videotoolbox: false,
             try_mediafoundation: false,
+            threads: 0,
+            slices: 0,
+            tuning: video::LatencyPreset::default().tuning(),
         };
         let mut encoder =
             video::VideoEncoder::new(width, height, width, height, |_| {}, opts).unwrap();
@@ -207,6 +525,9 @@ mod tests {
             try_nvenc: false,
             try_videotoolbox: false,
             try_mediafoundation: false,
+            threads: 0,
+            slices: 0,
+            tuning: video::LatencyPreset::default().tuning(),
         };
         let mut encoder =
             video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
@@ -236,6 +557,9 @@ mod tests {
             try_nvenc: false,
             try_videotoolbox: false,
             try_mediafoundation: false,
+            threads: 0,
+            slices: 0,
+            tuning: video::LatencyPreset::default().tuning(),
         };
         let mut encoder =
             video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
@@ -265,6 +589,9 @@ mod tests {
             try_nvenc: true,
             try_videotoolbox: false,
             try_mediafoundation: false,
+            threads: 0,
+            slices: 0,
+            tuning: video::LatencyPreset::default().tuning(),
         };
         let mut encoder =
             video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
@@ -276,3 +603,130 @@ mod tests {
         });
     }
 }
+
+/// Exit code if no running instance of Weylus could be reached.
+const EXIT_UNREACHABLE: i32 = 6;
+
+/// Exit code if any check of `weylus selftest` failed.
+const EXIT_SELFTEST_FAILED: i32 = 7;
+
+fn print_status(port: u16, json: bool) {
+    let status = match control::request(port, &control::ControlRequest::Status) {
+        Ok(control::ControlResponse::Status(status)) => status,
+        Ok(control::ControlResponse::Error(err)) => {
+            error!("Failed to query status: {err}");
+            std::process::exit(EXIT_UNREACHABLE);
+        }
+        Ok(response) => {
+            error!("Unexpected response: {response:?}");
+            std::process::exit(EXIT_UNREACHABLE);
+        }
+        Err(err) => {
+            error!("{err}");
+            std::process::exit(EXIT_UNREACHABLE);
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+        return;
+    }
+    println!("Weylus {} (pid {})", status.version, status.pid);
+    if let Some(permissions) = status.permissions {
+        let granted = |granted| if granted { "granted" } else { "missing" };
+        println!(
+            "Permissions: Screen Recording {}, Accessibility {}",
+            granted(permissions.screen_recording),
+            granted(permissions.accessibility)
+        );
+    }
+    for instance in status.instances {
+        println!("Instance {}", instance.name);
+        if instance.closed_by_schedule {
+            println!(
+                "  Listening on: {} (closed by schedule)",
+                instance.bind_address
+            );
+        } else {
+            println!("  Listening on: {}", instance.bind_address);
+        }
+        if let Some(addr) = instance.webtransport_address {
+            println!("  WebTransport: {addr}");
+        }
+        println!("  Clients: {}", instance.clients.len());
+        if instance.clients.iter().any(|c| c.broadcasting) {
+            let viewers = instance
+                .clients
+                .iter()
+                .filter(|c| c.watching_broadcast)
+                .count();
+            println!("  Broadcasting to {viewers} viewer(s)");
+        }
+        for client in instance.clients {
+            println!(
+                "    {} ({:?}) name: {}, capturable: {}, encoder: {}, input device: {}, input: \
+                {:.0} events/s, dropped: {}, video: {:.0} fps, dropped frames: {}, battery: {}",
+                client.address,
+                client.role,
+                client.name.as_deref().unwrap_or("-"),
+                client.capturable.as_deref().unwrap_or("-"),
+                client.encoder.as_deref().unwrap_or("-"),
+                client.input_device.as_deref().unwrap_or("-"),
+                client.input_rate,
+                client.dropped_input_events,
+                client.video.per_second(client.video.sent),
+                client.video.dropped(),
+                client
+                    .device
+                    .and_then(|device| device.describe())
+                    .as_deref()
+                    .unwrap_or("-"),
+            );
+        }
+    }
+}
+
+/// `weylus approve`: list the devices waiting for approval or decide on one of them.
+fn approve(port: u16, id: Option<u64>, remember: bool, deny: bool) {
+    let request = match id {
+        Some(id) => control::ControlRequest::DecideApproval {
+            id,
+            decision: if deny {
+                approval::Decision::Deny
+            } else if remember {
+                approval::Decision::Remember
+            } else {
+                approval::Decision::Allow
+            },
+        },
+        None => control::ControlRequest::PendingApprovals,
+    };
+    match control::request(port, &request) {
+        Ok(control::ControlResponse::PendingApprovals(pending)) => {
+            if pending.is_empty() {
+                println!("No device is waiting for approval.");
+            }
+            for p in pending {
+                println!(
+                    "{}: {} on instance {}, {}",
+                    p.id,
+                    p.address,
+                    p.instance,
+                    p.user_agent.as_deref().unwrap_or("unknown browser")
+                );
+            }
+        }
+        Ok(control::ControlResponse::ApprovalDecided(true)) => {}
+        Ok(control::ControlResponse::ApprovalDecided(false)) => {
+            error!("No device with this number is waiting for approval.");
+            std::process::exit(1);
+        }
+        Ok(response) => {
+            error!("Unexpected response: {response:?}");
+            std::process::exit(EXIT_UNREACHABLE);
+        }
+        Err(err) => {
+            error!("{err}");
+            std::process::exit(EXIT_UNREACHABLE);
+        }
+    }
+}
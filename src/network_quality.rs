@@ -0,0 +1,184 @@
+//! Measure the quality of the connection to a client, so the client can show how good the
+//! connection is and suggest a lower resolution if the network can not keep up.
+//!
+//! The sender sends numbered probes the client acknowledges right away together with the number
+//! of video bytes it received since its last acknowledgement. The time until a probe is
+//! acknowledged gives the round trip time, probes that are skipped by later acknowledgements or
+//! never acknowledged count as lost and the acknowledged bytes give the throughput.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How often probes are sent.
+const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Probes not acknowledged within this time are considered lost.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often a report is sent to the client.
+const REPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Weight of a new round trip time in the smoothed one.
+const RTT_SMOOTHING: f64 = 0.25;
+
+/// Share of the video sent the client has to receive for the network to be considered fast
+/// enough, below a lower resolution is suggested.
+const MIN_DELIVERY_RATIO: f64 = 0.8;
+
+/// Round trip times in milliseconds and loss rates from which on a bar of the signal strength is
+/// taken away.
+const RTT_LEVELS: [f64; 4] = [50.0, 100.0, 200.0, 400.0];
+const LOSS_LEVELS: [f64; 4] = [0.01, 0.03, 0.08, 0.2];
+
+/// Signal strength up to which a lower resolution is suggested.
+const LOWER_RESOLUTION_SIGNAL: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct NetworkQualityReport {
+    /// Video received by the client in kilobits per second.
+    pub throughput: f64,
+    /// Share of the probes lost over the last report interval.
+    pub loss: f64,
+    /// Smoothed round trip time in milliseconds, None until the first probe got acknowledged.
+    pub rtt: Option<f64>,
+    /// Signal strength from 0 (unusable) to 4 (excellent).
+    pub signal: u8,
+    /// Whether switching to a lower resolution is likely to improve the video.
+    pub lower_resolution: bool,
+}
+
+/// Output of [`NetworkQuality::timer`], messages to send to the client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityOutput {
+    Probe(u32),
+    Report(NetworkQualityReport),
+}
+
+pub struct NetworkQuality {
+    /// Bytes of video handed to the sender.
+    video_bytes: Arc<AtomicU64>,
+    /// Probes that have not been acknowledged yet, ordered by sequence number.
+    pending: VecDeque<(u32, Instant)>,
+    next_seq: u32,
+    next_probe: Instant,
+    rtt: Option<f64>,
+    report_start: Instant,
+    acked: u64,
+    lost: u64,
+    received_bytes: u64,
+}
+
+impl NetworkQuality {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            video_bytes: Arc::new(AtomicU64::new(0)),
+            pending: VecDeque::new(),
+            next_seq: 0,
+            next_probe: now,
+            rtt: None,
+            report_start: now,
+            acked: 0,
+            lost: 0,
+            received_bytes: 0,
+        }
+    }
+
+    /// Counter to add the bytes of video sent to.
+    pub fn video_bytes(&self) -> Arc<AtomicU64> {
+        self.video_bytes.clone()
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.next_probe.min(self.report_start + REPORT_INTERVAL)
+    }
+
+    /// Handle the acknowledgement of a probe, `bytes` is the video received since the last one.
+    /// Returns the round trip time of the probe in milliseconds, None if it was lost already.
+    pub fn ack(&mut self, seq: u32, bytes: u64) -> Option<f64> {
+        self.received_bytes += bytes;
+        let mut measured = None;
+        while let Some(&(pending_seq, sent)) = self.pending.front() {
+            if pending_seq.wrapping_sub(seq) as i32 > 0 {
+                break;
+            }
+            self.pending.pop_front();
+            if pending_seq != seq {
+                // acknowledgements arrive in order, a skipped probe is lost
+                self.lost += 1;
+                continue;
+            }
+            self.acked += 1;
+            let rtt = sent.elapsed().as_secs_f64() * 1000.0;
+            self.rtt = Some(match self.rtt {
+                Some(smoothed) => smoothed + RTT_SMOOTHING * (rtt - smoothed),
+                None => rtt,
+            });
+            measured = Some(rtt);
+        }
+        measured
+    }
+
+    /// Send probes and reports, call this once the deadline passed.
+    pub fn timer(&mut self) -> Vec<QualityOutput> {
+        let now = Instant::now();
+        let mut output = Vec::new();
+        while let Some(&(_, sent)) = self.pending.front() {
+            if now.duration_since(sent) < PROBE_TIMEOUT {
+                break;
+            }
+            self.pending.pop_front();
+            self.lost += 1;
+        }
+        if now >= self.next_probe {
+            self.pending.push_back((self.next_seq, now));
+            output.push(QualityOutput::Probe(self.next_seq));
+            self.next_seq = self.next_seq.wrapping_add(1);
+            self.next_probe = now + PROBE_INTERVAL;
+        }
+        if now >= self.report_start + REPORT_INTERVAL {
+            output.push(QualityOutput::Report(self.report(now)));
+        }
+        output
+    }
+
+    fn report(&mut self, now: Instant) -> NetworkQualityReport {
+        let interval = now.duration_since(self.report_start).as_secs_f64();
+        let sent_bytes = self.video_bytes.swap(0, Ordering::Relaxed);
+        let probes = self.acked + self.lost;
+        let loss = if probes > 0 {
+            self.lost as f64 / probes as f64
+        } else {
+            0.0
+        };
+        let level = |value: f64, levels: &[f64; 4]| levels.iter().filter(|l| value >= **l).count();
+        let mut penalty =
+            level(loss, &LOSS_LEVELS).max(self.rtt.map_or(0, |rtt| level(rtt, &RTT_LEVELS)));
+        if probes > 0 && self.acked == 0 {
+            penalty = 4;
+        }
+        // a backlog of video builds up if less arrives than is sent
+        let congested =
+            sent_bytes > 0 && (self.received_bytes as f64) < MIN_DELIVERY_RATIO * sent_bytes as f64;
+        if congested {
+            penalty = penalty.max(3);
+        }
+        let signal = 4 - penalty as u8;
+        let report = NetworkQualityReport {
+            throughput: self.received_bytes as f64 * 8.0 / 1000.0 / interval,
+            loss,
+            rtt: self.rtt,
+            signal,
+            lower_resolution: sent_bytes > 0 && signal <= LOWER_RESOLUTION_SIGNAL,
+        };
+        self.report_start = now;
+        self.acked = 0;
+        self.lost = 0;
+        self.received_bytes = 0;
+        report
+    }
+}
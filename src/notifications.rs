@@ -0,0 +1,114 @@
+//! Mirror desktop notifications of the host to clients, so chat messages arriving while drawing
+//! full-screen on the tablet are not missed.
+//!
+//! On Linux notifications are read by monitoring calls to org.freedesktop.Notifications on the
+//! session bus. Other systems do not let applications read the notifications of others.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+use serde::{Deserialize, Serialize};
+#[cfg(target_os = "linux")]
+use tracing::debug;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// Name of the application that sent the notification.
+    pub app: String,
+    pub summary: String,
+    pub body: String,
+}
+
+type Callback = Box<dyn FnMut(&Notification) + Send>;
+
+static SUBSCRIBERS: Mutex<Vec<(u64, Callback)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static MONITOR: Once = Once::new();
+
+/// Keeps calling the callback passed to [`subscribe`] until dropped.
+pub struct Subscription(u64);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().unwrap().retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Call the callback for every notification shown on the host, notifications are monitored once
+/// the first subscriber subscribes.
+pub fn subscribe(callback: impl FnMut(&Notification) + Send + 'static) -> Subscription {
+    MONITOR.call_once(|| {
+        std::thread::spawn(monitor);
+    });
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS.lock().unwrap().push((id, Box::new(callback)));
+    Subscription(id)
+}
+
+#[cfg(target_os = "linux")]
+fn publish(notification: Notification) {
+    debug!("Mirroring notification of {}.", notification.app);
+    for (_, callback) in SUBSCRIBERS.lock().unwrap().iter_mut() {
+        callback(&notification);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_notify(msg: &dbus::Message) -> Result<Notification, dbus::arg::TypeMismatchError> {
+    // Notify(app_name, replaces_id, app_icon, summary, body, actions, hints, expire_timeout)
+    let mut args = msg.iter_init();
+    let app: String = args.read()?;
+    let _replaces_id: u32 = args.read()?;
+    let _app_icon: String = args.read()?;
+    let summary: String = args.read()?;
+    let body: String = args.read()?;
+    Ok(Notification { app, summary, body })
+}
+
+#[cfg(target_os = "linux")]
+fn monitor() {
+    use std::time::Duration;
+
+    use dbus::blocking::Connection;
+    use dbus::message::{MatchRule, MessageType};
+
+    let run = || -> Result<(), dbus::Error> {
+        let conn = Connection::new_session()?;
+        let mut rule = MatchRule::new();
+        rule.msg_type = Some(MessageType::MethodCall);
+        rule.interface = Some("org.freedesktop.Notifications".into());
+        rule.member = Some("Notify".into());
+        let proxy = conn.with_proxy(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            Duration::from_secs(5),
+        );
+        proxy.method_call::<(), _, _, _>(
+            "org.freedesktop.DBus.Monitoring",
+            "BecomeMonitor",
+            (vec![rule.match_str()], 0u32),
+        )?;
+        conn.start_receive(
+            rule,
+            Box::new(|msg, _| {
+                match read_notify(&msg) {
+                    Ok(notification) => publish(notification),
+                    Err(err) => debug!("Failed to read notification: {err}"),
+                }
+                true
+            }),
+        );
+        loop {
+            conn.process(Duration::from_secs(1))?;
+        }
+    };
+    if let Err(err) = run() {
+        warn!("Failed to monitor notifications: {err}");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn monitor() {
+    warn!("Mirroring notifications is only supported on Linux.");
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use hyper::HeaderMap;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::websocket::ClientRole;
+
+pub const SESSION_COOKIE: &str = "weylus_session";
+/// Binds a login to the browser that started it.
+pub const STATE_COOKIE: &str = "weylus_oidc_state";
+
+const SESSION_DURATION: Duration = Duration::from_secs(3600 * 24);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug)]
+pub struct OidcError(String);
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for OidcError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: String,
+    pub groups_claim: String,
+    pub controller_group: Option<String>,
+    pub viewer_group: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn contains(&self, s: &str) -> bool {
+        match self {
+            Self::One(v) => v == s,
+            Self::Many(v) => v.iter().any(|v| v == s),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: OneOrMany,
+    exp: u64,
+    nonce: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, serde_json::Value>,
+}
+
+struct PendingLogin {
+    nonce: String,
+    /// PKCE code verifier, its hash is sent along with the authorization request.
+    code_verifier: String,
+    created: Instant,
+}
+
+struct Session {
+    role: ClientRole,
+    expires: Instant,
+}
+
+/// Implements the authorization code flow of OpenID Connect and keeps track of the sessions of
+/// logged in users.
+pub struct Oidc {
+    config: OidcConfig,
+    metadata: Mutex<Option<ProviderMetadata>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Value of the cookie with the given name.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value)
+}
+
+/// Random alphanumeric string of the given length, used for secrets like session ids.
+pub fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            metadata: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn metadata(&self) -> Result<ProviderMetadata, OidcError> {
+        let mut metadata = self.metadata.lock().unwrap();
+        if let Some(metadata) = metadata.as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let m: ProviderMetadata = ureq::get(&url)
+            .call()
+            .map_err(|err| OidcError(format!("Failed to fetch provider metadata: {err}")))?
+            .into_json()
+            .map_err(|err| OidcError(format!("Failed to parse provider metadata: {err}")))?;
+        *metadata = Some(m.clone());
+        Ok(m)
+    }
+
+    /// Cookies are only sent via TLS if Weylus is reachable via https, usually behind a reverse
+    /// proxy.
+    fn secure(&self) -> &'static str {
+        if self.config.redirect_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        }
+    }
+
+    /// Returns the url of the identity provider the user has to be redirected to for logging in
+    /// and the cookie that binds the login to the browser.
+    ///
+    /// This may block while the provider's metadata is fetched.
+    pub fn authorization_url(&self) -> Result<(String, String), OidcError> {
+        let metadata = self.metadata()?;
+        let state = random_string(32);
+        let nonce = random_string(32);
+        let code_verifier = random_string(64);
+        let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(code_verifier.as_bytes()));
+        let url = url::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_url.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|err| OidcError(format!("Invalid authorization endpoint: {err}")))?;
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.created.elapsed() < LOGIN_TIMEOUT);
+        let cookie = format!(
+            "{STATE_COOKIE}={state}; Max-Age={}; Path=/oidc; HttpOnly; SameSite=Lax{}",
+            LOGIN_TIMEOUT.as_secs(),
+            self.secure()
+        );
+        pending.insert(
+            state,
+            PendingLogin {
+                nonce,
+                code_verifier,
+                created: Instant::now(),
+            },
+        );
+        Ok((url.into(), cookie))
+    }
+
+    /// Redeems the authorization code and creates a new session, the id of the session is
+    /// returned. `state_cookie` is the value of [`STATE_COOKIE`] sent by the browser, it has to
+    /// match the state of the login.
+    ///
+    /// This blocks while talking to the identity provider.
+    pub fn finish_login(
+        &self,
+        code: &str,
+        state: &str,
+        state_cookie: Option<&str>,
+    ) -> Result<String, OidcError> {
+        if state_cookie != Some(state) {
+            return Err(OidcError(
+                "Login was started in another browser or its cookie expired.".into(),
+            ));
+        }
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|p| p.created.elapsed() < LOGIN_TIMEOUT)
+            .ok_or_else(|| OidcError("Unknown or expired login state.".into()))?;
+        let metadata = self.metadata()?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        if let Some(secret) = self.config.client_secret.as_ref() {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let token: TokenResponse = ureq::post(&metadata.token_endpoint)
+            .send_form(&form)
+            .map_err(|err| OidcError(format!("Failed to redeem authorization code: {err}")))?
+            .into_json()
+            .map_err(|err| OidcError(format!("Failed to parse token response: {err}")))?;
+
+        // The token has been received directly from the token endpoint via TLS, which according
+        // to the OpenID Connect specification is sufficient to trust it without validating its
+        // signature.
+        let payload = token
+            .id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| OidcError("Malformed ID token.".into()))?;
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .map_err(|err| OidcError(format!("Malformed ID token: {err}")))?;
+        let claims: IdTokenClaims = serde_json::from_slice(&payload)
+            .map_err(|err| OidcError(format!("Malformed ID token: {err}")))?;
+
+        if claims.iss.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            return Err(OidcError(format!("Unexpected issuer: {}", claims.iss)));
+        }
+        if !claims.aud.contains(&self.config.client_id) {
+            return Err(OidcError("ID token was issued for another client.".into()));
+        }
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(OidcError("Nonce mismatch.".into()));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if claims.exp < now {
+            return Err(OidcError("ID token expired.".into()));
+        }
+
+        let groups: Option<OneOrMany> = claims
+            .other
+            .get(&self.config.groups_claim)
+            .and_then(|g| serde_json::from_value(g.clone()).ok());
+        let member = |group: &str| groups.as_ref().is_some_and(|g| g.contains(group));
+        // without a controller group everyone who is not just a viewer may control
+        let role = match (&self.config.controller_group, &self.config.viewer_group) {
+            (Some(controllers), _) if member(controllers) => ClientRole::Controller,
+            (_, Some(viewers)) if member(viewers) => ClientRole::Viewer,
+            (None, _) => ClientRole::Controller,
+            _ => {
+                return Err(OidcError(format!(
+                    "User {} is not member of any permitted group.",
+                    claims.sub
+                )))
+            }
+        };
+        info!(user = %claims.sub, role = ?role, "User logged in via OIDC.");
+
+        let id = random_string(32);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, s| s.expires > Instant::now());
+        sessions.insert(
+            id.clone(),
+            Session {
+                role,
+                expires: Instant::now() + SESSION_DURATION,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Looks up the session cookie in the given headers and returns the role of the user if the
+    /// session is valid.
+    pub fn session_role(&self, headers: &HeaderMap) -> Option<ClientRole> {
+        let id = cookie(headers, SESSION_COOKIE)?;
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id)?;
+        if session.expires > Instant::now() {
+            Some(session.role)
+        } else {
+            debug!("Session expired.");
+            None
+        }
+    }
+
+    pub fn session_cookie(&self, id: &str) -> String {
+        format!(
+            "{SESSION_COOKIE}={id}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+            SESSION_DURATION.as_secs(),
+            self.secure()
+        )
+    }
+
+    /// Removes the cookie of a finished login.
+    pub fn clear_state_cookie() -> String {
+        format!("{STATE_COOKIE}=; Max-Age=0; Path=/oidc; HttpOnly; SameSite=Lax")
+    }
+}
@@ -0,0 +1,128 @@
+//! Permissions macOS requires Weylus to be granted in System Settings: Screen Recording to capture
+//! the screen, without it only the desktop background and black windows are captured, and
+//! Accessibility to simulate input, without it input is silently dropped. Other platforms do not
+//! need any permissions.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub screen_recording: bool,
+    pub accessibility: bool,
+}
+
+#[cfg(target_os = "macos")]
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGPreflightScreenCaptureAccess() -> bool;
+    fn CGRequestScreenCaptureAccess() -> bool;
+}
+
+#[cfg(target_os = "macos")]
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXIsProcessTrusted() -> bool;
+}
+
+/// The current state of the permissions, None if the platform requires none.
+#[cfg(target_os = "macos")]
+pub fn check() -> Option<Permissions> {
+    unsafe {
+        Some(Permissions {
+            screen_recording: CGPreflightScreenCaptureAccess(),
+            accessibility: AXIsProcessTrusted(),
+        })
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn check() -> Option<Permissions> {
+    None
+}
+
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ScreenRecording,
+    Accessibility,
+}
+
+#[cfg(target_os = "macos")]
+impl Permission {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ScreenRecording => "Screen Recording",
+            Self::Accessibility => "Accessibility",
+        }
+    }
+
+    /// Deep link to the pane of System Settings the permission is granted in.
+    pub fn settings_url(self) -> &'static str {
+        match self {
+            Self::ScreenRecording => {
+                "x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture"
+            }
+            Self::Accessibility => {
+                "x-apple.systempreferences:com.apple.preference.security?Privacy_Accessibility"
+            }
+        }
+    }
+
+    pub fn open_settings(self) {
+        if let Err(err) = std::process::Command::new("open")
+            .arg(self.settings_url())
+            .spawn()
+        {
+            tracing::warn!("Failed to open System Settings: {err}");
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Permissions {
+    pub fn missing(&self) -> Vec<Permission> {
+        let mut missing = Vec::new();
+        if !self.screen_recording {
+            missing.push(Permission::ScreenRecording);
+        }
+        if !self.accessibility {
+            missing.push(Permission::Accessibility);
+        }
+        missing
+    }
+}
+
+/// Warn about missing permissions and ask for Screen Recording, macOS shows its prompt only the
+/// first time this is asked for.
+#[cfg(target_os = "macos")]
+pub fn request_missing() {
+    let Some(permissions) = check() else {
+        return;
+    };
+    for permission in permissions.missing() {
+        tracing::warn!(
+            "Weylus has not been granted the {} permission, grant it in System Settings > \
+            Privacy & Security > {} and restart Weylus.",
+            permission.name(),
+            permission.name()
+        );
+    }
+    if !permissions.screen_recording {
+        unsafe { CGRequestScreenCaptureAccess() };
+    }
+}
+
+/// Error to fail capturing with instead of streaming black frames if Screen Recording has not
+/// been granted.
+#[cfg(target_os = "macos")]
+pub fn require_screen_recording() -> Result<(), Box<dyn std::error::Error>> {
+    if unsafe { CGPreflightScreenCaptureAccess() } {
+        Ok(())
+    } else {
+        Err(
+            "Weylus has not been granted the Screen Recording permission, grant it in System \
+            Settings > Privacy & Security > Screen Recording and restart Weylus."
+                .into(),
+        )
+    }
+}
@@ -0,0 +1,220 @@
+//! Lock the screen, suspend or log out of the host on request of a client. Each request has to be
+//! confirmed in a dialog on the host, so a session can be ended from the tablet but nobody can do
+//! so without the consent of whoever sits at the computer.
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// Seconds the confirmation dialog waits for an answer before the request is declined.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const CONFIRM_TIMEOUT: u32 = 60;
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    /// Lock the screen.
+    Lock,
+    Suspend,
+    /// End the session of the user.
+    Logout,
+}
+
+impl PowerAction {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Lock => "lock the screen",
+            Self::Suspend => "suspend the computer",
+            Self::Logout => "log out",
+        }
+    }
+}
+
+/// Set while a confirmation dialog is shown, further requests are declined meanwhile.
+static CONFIRMING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run(program: &str, args: &[&str]) -> bool {
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            warn!("{program} {} failed: {status}.", args.join(" "));
+            false
+        }
+        Err(err) => {
+            warn!("Failed to run {program}: {err}.");
+            false
+        }
+    }
+}
+
+/// Ask the user at the host whether to perform the action, false if no dialog could be shown.
+fn confirm(action: PowerAction, client: &str) -> bool {
+    let text = format!("{client} asks Weylus to {}. Allow this?", action.describe());
+    #[cfg(target_os = "linux")]
+    {
+        let timeout = format!("--timeout={CONFIRM_TIMEOUT}");
+        // the client chooses its name, it must not be able to format the question
+        let zenity = Command::new("zenity")
+            .args([
+                "--question",
+                "--title=Weylus",
+                "--no-markup",
+                &timeout,
+                "--text",
+                &text,
+            ])
+            .status();
+        let status = match zenity {
+            Ok(status) => Ok(status),
+            Err(_) => Command::new("kdialog")
+                .args(["--title", "Weylus", "--warningyesno", &text])
+                .spawn()
+                .and_then(wait_or_decline),
+        };
+        match status {
+            Ok(status) => status.success(),
+            Err(err) => {
+                warn!("Failed to show confirmation dialog, neither zenity nor kdialog work: {err}");
+                false
+            }
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let script = format!(
+            "display dialog \"{}\" with title \"Weylus\" buttons {{\"Deny\", \"Allow\"}} \
+            default button \"Deny\" with icon caution giving up after {CONFIRM_TIMEOUT}",
+            text.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        match Command::new("osascript").args(["-e", &script]).output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).contains("button returned:Allow"),
+            Err(err) => {
+                warn!("Failed to run osascript to show confirmation dialog: {err}");
+                false
+            }
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use winapi::um::winuser::{
+            MessageBoxW, IDYES, MB_DEFBUTTON2, MB_ICONWARNING, MB_SETFOREGROUND, MB_TOPMOST,
+            MB_YESNO,
+        };
+        let wide = |s: &str| -> Vec<u16> {
+            std::ffi::OsStr::new(s)
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect()
+        };
+        let text = wide(&text);
+        let title = wide("Weylus");
+        let answer = unsafe {
+            MessageBoxW(
+                std::ptr::null_mut(),
+                text.as_ptr(),
+                title.as_ptr(),
+                MB_YESNO | MB_DEFBUTTON2 | MB_ICONWARNING | MB_TOPMOST | MB_SETFOREGROUND,
+            )
+        };
+        answer == IDYES
+    }
+}
+
+/// Wait for the answer to a dialog that can not time out by itself, once CONFIRM_TIMEOUT passes it
+/// is closed and the request declined.
+#[cfg(target_os = "linux")]
+fn wait_or_decline(mut dialog: std::process::Child) -> std::io::Result<std::process::ExitStatus> {
+    let deadline =
+        std::time::Instant::now() + std::time::Duration::from_secs(CONFIRM_TIMEOUT.into());
+    loop {
+        if let Some(status) = dialog.try_wait()? {
+            return Ok(status);
+        }
+        if std::time::Instant::now() >= deadline {
+            info!("Confirmation dialog timed out.");
+            dialog.kill()?;
+            // killed processes do not exit successfully
+            return dialog.wait();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+}
+
+fn perform(action: PowerAction) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let session = std::env::var("XDG_SESSION_ID").ok();
+        let session_args = |command: &'static str| {
+            let mut args = vec![command];
+            args.extend(session.as_deref());
+            args
+        };
+        match action {
+            PowerAction::Lock => run("loginctl", &session_args("lock-session")),
+            PowerAction::Suspend => run("systemctl", &["suspend"]),
+            PowerAction::Logout => match session.as_deref() {
+                Some(id) => run("loginctl", &["terminate-session", id]),
+                None => {
+                    let user = std::env::var("USER").unwrap_or_default();
+                    run("loginctl", &["terminate-user", &user])
+                }
+            },
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        match action {
+            // locks the screen if a password is required right after the display sleeps
+            PowerAction::Lock => run("pmset", &["displaysleepnow"]),
+            PowerAction::Suspend => run("pmset", &["sleepnow"]),
+            PowerAction::Logout => run(
+                "osascript",
+                &["-e", "tell application \"System Events\" to log out"],
+            ),
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use winapi::um::powrprof::SetSuspendState;
+        use winapi::um::winuser::{ExitWindowsEx, LockWorkStation, EWX_LOGOFF};
+        let ok = unsafe {
+            match action {
+                PowerAction::Lock => LockWorkStation() != 0,
+                PowerAction::Suspend => SetSuspendState(0, 0, 0) != 0,
+                PowerAction::Logout => ExitWindowsEx(EWX_LOGOFF, 0) != 0,
+            }
+        };
+        if !ok {
+            warn!(
+                "Failed to {}: {}",
+                action.describe(),
+                std::io::Error::last_os_error()
+            );
+        }
+        ok
+    }
+}
+
+/// Ask for confirmation on the host and perform the action if granted. This blocks until the
+/// dialog is answered, returns whether the action was performed.
+pub fn request(action: PowerAction, client: &str) -> bool {
+    if CONFIRMING.swap(true, Ordering::Relaxed) {
+        warn!(
+            "Declining request to {} while another one awaits confirmation.",
+            action.describe()
+        );
+        return false;
+    }
+    let confirmed = confirm(action, client);
+    CONFIRMING.store(false, Ordering::Relaxed);
+    if !confirmed {
+        info!("Request of {client} to {} was declined.", action.describe());
+        return false;
+    }
+    info!("{client} requested to {}.", action.describe());
+    perform(action)
+}
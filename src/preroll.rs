@@ -0,0 +1,136 @@
+//! Cache of the most recent part of the video stream that can be decoded on its own, this lets a
+//! client start decoding right away when it resumes or switches to another transport, instead of
+//! waiting for the encoder to be restarted.
+
+use tracing::trace;
+
+/// Drop the cache if the fragments since the last keyframe grow larger than this, which only
+/// happens for very long keyframe intervals.
+const MAX_PREROLL_SIZE: usize = 16 * 1024 * 1024;
+
+/// Sample flag of fragmented MP4 marking samples that are not keyframes.
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+/// Iterate over the boxes of an MP4 stream, yielding their type and content.
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        if size < 8 || size > data.len() {
+            return None;
+        }
+        let (b, rest) = data.split_at(size);
+        data = rest;
+        Some((&b[4..8], &b[8..]))
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find(|(t, _)| *t == box_type).map(|(_, b)| b)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+/// Flags of the first sample of a movie fragment, as found in its trun or tfhd box.
+fn first_sample_flags(fragment: &[u8]) -> Option<u32> {
+    let traf = find_box(find_box(fragment, b"moof")?, b"traf")?;
+
+    let trun = find_box(traf, b"trun")?;
+    let trun_flags = read_u32(trun, 0)? & 0x00ff_ffff;
+    // version and flags, sample count
+    let mut offset = 8;
+    if trun_flags & 0x001 != 0 {
+        offset += 4;
+    }
+    if trun_flags & 0x004 != 0 {
+        return read_u32(trun, offset);
+    }
+    if trun_flags & 0x400 != 0 {
+        if trun_flags & 0x100 != 0 {
+            offset += 4;
+        }
+        if trun_flags & 0x200 != 0 {
+            offset += 4;
+        }
+        return read_u32(trun, offset);
+    }
+
+    let tfhd = find_box(traf, b"tfhd")?;
+    let tfhd_flags = read_u32(tfhd, 0)? & 0x00ff_ffff;
+    if tfhd_flags & 0x020 == 0 {
+        return None;
+    }
+    // version and flags, track id
+    let mut offset = 8;
+    for (flag, size) in [(0x01, 8), (0x02, 4), (0x08, 4), (0x10, 4)] {
+        if tfhd_flags & flag != 0 {
+            offset += size;
+        }
+    }
+    read_u32(tfhd, offset)
+}
+
+pub fn is_keyframe(fragment: &[u8]) -> bool {
+    first_sample_flags(fragment).is_some_and(|flags| flags & SAMPLE_IS_NON_SYNC == 0)
+}
+
+/// Keeps the initialization segment and the fragments since the most recent keyframe.
+#[derive(Default)]
+pub struct Preroll {
+    init_segment: Vec<u8>,
+    fragments: Vec<Vec<u8>>,
+    size: usize,
+}
+
+impl Preroll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        match data.get(4..8) {
+            Some(b"ftyp") => {
+                self.init_segment = data.to_vec();
+                self.clear();
+                return;
+            }
+            Some(b"moov") => {
+                self.init_segment.extend_from_slice(data);
+                return;
+            }
+            _ => (),
+        }
+        if is_keyframe(data) {
+            self.clear();
+        } else if self.fragments.is_empty() {
+            // without a keyframe the fragments are useless
+            return;
+        }
+        self.size += data.len();
+        if self.size > MAX_PREROLL_SIZE {
+            trace!("Preroll too large, dropping it until the next keyframe.");
+            self.clear();
+            return;
+        }
+        self.fragments.push(data.to_vec());
+    }
+
+    fn clear(&mut self) {
+        self.fragments.clear();
+        self.size = 0;
+    }
+
+    /// The initialization segment followed by the keyframe and all fragments since. Decoders
+    /// skip fragments until the next keyframe, so the initialization segment is useful on its
+    /// own if no keyframe is cached.
+    pub fn replay(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(self.init_segment.as_slice())
+            .filter(|init_segment| !init_segment.is_empty())
+            .chain(self.fragments.iter().map(Vec::as_slice))
+    }
+}
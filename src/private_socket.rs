@@ -0,0 +1,65 @@
+//! Unix sockets only the user running Weylus may use. They live in the runtime directory, which
+//! only the user may access, or if there is none in a directory of the user in the temporary
+//! directory that is created with the same permissions. Sockets are restricted to the user once
+//! bound and connections of other users are refused, as the sockets hand out control over the
+//! session.
+
+use std::fs::{DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+extern "C" {
+    fn getuid() -> u32;
+}
+
+fn uid() -> u32 {
+    unsafe { getuid() }
+}
+
+/// Directory the sockets are placed in.
+fn dir() -> io::Result<PathBuf> {
+    if let Some(dir) = dirs::runtime_dir() {
+        return Ok(dir);
+    }
+    let uid = uid();
+    let dir = std::env::temp_dir().join(format!("weylus-{uid}"));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(err) => return Err(err),
+    }
+    // anyone may have created the directory before
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory of this user", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+/// Path of the socket with the given name.
+pub fn path(name: &str) -> io::Result<PathBuf> {
+    let mut path = dir()?;
+    path.push(name);
+    Ok(path)
+}
+
+/// Allow only the user to connect to the socket bound at the given path.
+pub fn restrict(path: &Path) -> io::Result<()> {
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))
+}
+
+/// Refuse peers run by other users.
+pub fn check_peer(peer_uid: u32) -> io::Result<()> {
+    if peer_uid == uid() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("connection by another user (uid {peer_uid}) refused"),
+        ))
+    }
+}
@@ -1,6 +1,19 @@
-use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::capturable::region::CaptureRegion;
+use crate::capturable::rotation::Rotation;
+use crate::input::device::ActivationPolicy;
+use crate::input::keypad::Keypad;
+use crate::input::sensors::SensorMode;
+use crate::input::shortcuts::ShortcutSet;
+use crate::network_quality::NetworkQualityReport;
+use crate::notifications::Notification;
+use crate::power::PowerAction;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientConfiguration {
     #[cfg(target_os = "linux")]
     pub uinput_support: bool,
@@ -10,29 +23,239 @@ pub struct ClientConfiguration {
     pub max_height: usize,
     pub client_name: Option<String>,
     pub frame_rate: f64,
+    /// the client draws the cursor itself, older clients leave this to the video
+    #[serde(default)]
+    pub client_cursor: bool,
+    /// 0: lowest latency, 1: smoothest video, older clients get the lowest latency
+    #[serde(default)]
+    pub smoothness: f64,
+    /// draw frame counter and timestamps into the video
+    #[serde(default)]
+    pub frame_stamp: bool,
+    /// overrides the activation policy of the server
+    pub window_activation: Option<ActivationPolicy>,
+    /// only set up input for the capturable, without capturing it, e.g. for the keypad
+    #[serde(default)]
+    pub input_only: bool,
+    /// upper bound of the bandwidth of the video in Mbit/s, the host may cap it lower
+    #[serde(default)]
+    pub bandwidth_cap: Option<f64>,
+    /// viewers point at things with a laser pointer drawn into the video
+    #[serde(default)]
+    pub laser_pointer: bool,
+    /// only capture this rectangle of the capturable, in pixels of its frames
+    #[serde(default)]
+    pub capture_region: Option<CaptureRegion>,
+    /// clockwise rotation of the video, overrides the rotation of the server
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    /// random id the client keeps in its local storage, see client_settings
+    #[serde(default)]
+    pub client_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageInbound {
     PointerEvent(PointerEvent),
+    /// Coalesced pointer events, processed in order
+    PointerEvents(Vec<PointerEvent>),
+    /// Predicted pointer events, these are not part of the actual input
+    PredictedPointerEvents(Vec<PointerEvent>),
     WheelEvent(WheelEvent),
     KeyboardEvent(KeyboardEvent),
     GetCapturableList,
+    /// Request thumbnails of the capturables in the list last sent
+    GetCapturableThumbnails,
     Config(ClientConfiguration),
+    /// Capture the capturable with the given id instead, keeping the rest of the configuration
+    SwitchCapturable(usize),
     PauseVideo,
     ResumeVideo,
     RestartVideo,
+    /// Make the next frame a keyframe, the client lost frames sent via WebTransport
+    RequestKeyframe,
     ChooseCustomInputAreas,
+    /// Seconds of video buffered by the client
+    BufferLevel(f64),
+    /// Answer to ConfirmTextInjection, whether the held back keys are to be sent
+    ConfirmTextInjection(bool),
+    /// Switch precision mode on or off
+    TogglePrecisionMode,
+    /// Request the shortcut sets defined on the host
+    GetShortcutSets,
+    /// Press the shortcut with the given index of the set with the given index
+    RunShortcut {
+        set: usize,
+        shortcut: usize,
+    },
+    /// Lock, suspend or log out of the host once confirmed there
+    PowerAction(PowerAction),
+    /// Request the keys of the keypad
+    GetKeypad,
+    /// Press the key of the keypad with the given index
+    RunKeypadKey(usize),
+    /// Answer to Probe with the bytes of video received since the last answer
+    ProbeAck {
+        seq: u32,
+        bytes: u64,
+    },
+    /// Orientation of the client, None once it stops sending it
+    Orientation(Option<DeviceOrientation>),
+    /// Area the client shows the video in, sent on connect and whenever it changes
+    Viewport(ClientViewport),
+    /// Battery levels of the client, sent on connect and whenever they change
+    DeviceStatus(DeviceStatus),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MessageOutbound {
     CapturableList(Vec<String>),
+    /// PNG data urls in the order of the capturable list, None if capturing failed
+    CapturableThumbnails(Vec<Option<String>>),
+    /// The capturable with the given id is captured now
+    CapturableSwitched(usize),
     NewVideo,
     ConfigOk,
     CustomInputAreas(CustomInputAreas),
     ConfigError(String),
     Error(String),
+    WebTransportToken(String),
+    Batch(Vec<MessageOutbound>),
+    CursorPosition(CursorPosition),
+    CursorShape(CursorShape),
+    /// Seconds of video the client should keep buffered
+    BufferTarget(f64),
+    /// Weylus is shutting down, the client is supposed to close the connection
+    ServerShutdown,
+    /// Keys that look like injected text are held back until the user confirms them
+    ConfirmTextInjection,
+    /// The screen capture was lost and is being set up again, no video is sent meanwhile
+    CaptureInterrupted,
+    /// The screen capture is back after it was interrupted
+    CaptureResumed,
+    /// Whether precision mode is on, sent whenever it is toggled
+    PrecisionMode(bool),
+    /// Sets of keyboard shortcuts defined on the host
+    ShortcutSets(Vec<ShortcutSet>),
+    /// Keys of the keypad defined on the host
+    Keypad(Keypad),
+    /// Area of the capturable highlighted by switch control
+    SwitchHighlight(Rect),
+    /// Power actions the client may request
+    PowerActions(Vec<PowerAction>),
+    /// Desktop notification shown on the host
+    Notification(Notification),
+    /// To be answered with ProbeAck right away to measure the connection
+    Probe(u32),
+    /// Quality of the connection measured by the host
+    NetworkQuality(NetworkQualityReport),
+    /// The client is asked to send its orientation, for the given use
+    Sensors(SensorMode),
+    /// The video is scaled down to fit the highest H.264 level allowed, sent when it is started
+    ResolutionLimited(ResolutionLimit),
+    /// Shape of the capturable, sent once capturing starts and whenever its size changes
+    CapturableLayout(CapturableLayout),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Orientation {
+    /// Squares count as landscape.
+    pub fn of(width: f64, height: f64) -> Self {
+        if height > width {
+            Self::Portrait
+        } else {
+            Self::Landscape
+        }
+    }
+}
+
+/// Lets the client lay out the video for the capturable before the video arrives.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CapturableLayout {
+    pub width: usize,
+    pub height: usize,
+    /// Width divided by height
+    pub aspect_ratio: f64,
+    pub orientation: Orientation,
+}
+
+impl CapturableLayout {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            aspect_ratio: width as f64 / height.max(1) as f64,
+            orientation: Orientation::of(width as f64, height as f64),
+        }
+    }
+}
+
+/// Battery levels of the client as far as the browser exposes them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct DeviceStatus {
+    /// Charge of the battery of the device from 0 to 1
+    #[serde(default)]
+    pub battery: Option<f64>,
+    #[serde(default)]
+    pub charging: Option<bool>,
+    /// Charge of the battery of the stylus from 0 to 1, browsers do not expose it but other
+    /// clients may
+    #[serde(default)]
+    pub stylus_battery: Option<f64>,
+}
+
+/// Size of the area the client shows the video in, in CSS pixels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ClientViewport {
+    pub width: f64,
+    pub height: f64,
+    /// Degrees the screen of the client is rotated by from its natural orientation, as reported
+    /// by screen.orientation.angle
+    #[serde(default)]
+    pub angle: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResolutionLimit {
+    pub width_in: usize,
+    pub height_in: usize,
+    pub width_out: usize,
+    pub height_out: usize,
+    /// e.g. 5.2
+    pub level: String,
+}
+
+/// Orientation of the client in degrees as reported by DeviceOrientationEvent: alpha around the
+/// axis out of the screen, beta tilts the top towards or away from the user and gamma tilts to
+/// the sides.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DeviceOrientation {
+    pub alpha: f64,
+    pub beta: f64,
+    pub gamma: f64,
+}
+
+/// Position of the cursor relative to the capturable.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CursorPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Shape of the cursor, all sizes are relative to the capturable's size.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CursorShape {
+    pub width: f64,
+    pub height: f64,
+    pub xhot: f64,
+    pub yhot: f64,
+    /// PNG encoded as data url
+    pub image: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -61,7 +284,7 @@ pub struct CustomInputAreas {
     pub pen: Option<Rect>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PointerType {
     #[serde(rename = "")]
     Unknown,
@@ -73,7 +296,7 @@ pub enum PointerType {
     Touch,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PointerEventType {
     #[serde(rename = "pointerdown")]
     DOWN,
@@ -93,7 +316,7 @@ pub enum PointerEventType {
     OUT,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum KeyboardEventType {
     #[serde(rename = "down")]
     DOWN,
@@ -103,7 +326,7 @@ pub enum KeyboardEventType {
     REPEAT,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum KeyboardLocation {
     STANDARD,
     LEFT,
@@ -124,6 +347,14 @@ fn location_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyboardL
     }
 }
 
+/// Inverse of location_from, so recorded events can be read again.
+fn location_to<S: Serializer>(
+    location: &KeyboardLocation,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(*location as u8)
+}
+
 bitflags! {
     #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
     pub struct Button: u8 {
@@ -145,12 +376,16 @@ fn button_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::
     )
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn button_to<S: Serializer>(button: &Button, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(button.bits())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeyboardEvent {
     pub event_type: KeyboardEventType,
     pub code: String,
     pub key: String,
-    #[serde(deserialize_with = "location_from")]
+    #[serde(serialize_with = "location_to", deserialize_with = "location_from")]
     pub location: KeyboardLocation,
     pub alt: bool,
     pub ctrl: bool,
@@ -158,16 +393,53 @@ pub struct KeyboardEvent {
     pub meta: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl KeyboardEvent {
+    /// Create an event without modifiers for the key with the given code, e.g. KeyE or F5.
+    pub fn from_code(code: &str, event_type: KeyboardEventType) -> Self {
+        let numpad = code.strip_prefix("Numpad");
+        let key = match code
+            .strip_prefix("Key")
+            .or_else(|| code.strip_prefix("Digit"))
+            .or(numpad)
+        {
+            Some(c) if c.len() == 1 => c.to_lowercase(),
+            _ => match numpad {
+                Some("Add") => "+".into(),
+                Some("Subtract") => "-".into(),
+                Some("Multiply") => "*".into(),
+                Some("Divide") => "/".into(),
+                Some("Decimal") => ".".into(),
+                Some("Enter") => "Enter".into(),
+                _ => code.to_string(),
+            },
+        };
+        Self {
+            event_type,
+            code: code.to_string(),
+            key,
+            location: if numpad.is_some() {
+                KeyboardLocation::NUMPAD
+            } else {
+                KeyboardLocation::STANDARD
+            },
+            alt: false,
+            ctrl: false,
+            shift: false,
+            meta: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PointerEvent {
     pub event_type: PointerEventType,
     pub pointer_id: i64,
     pub timestamp: u64,
     pub is_primary: bool,
     pub pointer_type: PointerType,
-    #[serde(deserialize_with = "button_from")]
+    #[serde(serialize_with = "button_to", deserialize_with = "button_from")]
     pub button: Button,
-    #[serde(deserialize_with = "button_from")]
+    #[serde(serialize_with = "button_to", deserialize_with = "button_from")]
     pub buttons: Button,
     pub x: f64,
     pub y: f64,
@@ -181,7 +453,45 @@ pub struct PointerEvent {
     pub height: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl PointerEvent {
+    /// Create an event of the mouse, e.g. to simulate a click.
+    pub fn mouse(
+        event_type: PointerEventType,
+        x: f64,
+        y: f64,
+        button: Button,
+        buttons: Button,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            event_type,
+            pointer_id: 0,
+            timestamp,
+            is_primary: true,
+            pointer_type: PointerType::Mouse,
+            button,
+            buttons,
+            x,
+            y,
+            movement_x: 0,
+            movement_y: 0,
+            pressure: 0.0,
+            tilt_x: 0,
+            tilt_y: 0,
+            twist: 0,
+            width: 0.0,
+            height: 0.0,
+        }
+    }
+
+    /// Clamp the position to the capturable, non-finite coordinates end up at its top left corner.
+    pub fn confine(&mut self) {
+        self.x = self.x.max(0.0).min(1.0);
+        self.y = self.y.max(0.0).min(1.0);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WheelEvent {
     pub dx: i32,
     pub dy: i32,
@@ -192,8 +502,14 @@ pub trait WeylusSender {
     type Error: std::error::Error;
     fn send_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error>;
     fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+    /// Send the most recent part of the video again that is required to start decoding.
+    fn replay_video(&mut self) -> Result<(), Self::Error>;
+    /// Number of messages waiting to be sent.
+    fn queue_depth(&self) -> usize;
 }
 
 pub trait WeylusReceiver: Iterator<Item = Result<MessageInbound, Self::Error>> {
     type Error: std::error::Error;
+    /// Like next but gives up once the timeout passed.
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Self::Item, RecvTimeoutError>;
 }
@@ -0,0 +1,335 @@
+//! Recording of the messages clients send and replaying them, so input bugs reported by users can
+//! be reproduced exactly. `--record-protocol` writes every inbound message as a line of JSON along
+//! with the time it arrived, `--replay-protocol` feeds them into client handlers whose input goes
+//! to a mock device that logs it. Typed text and the names of clients are stripped while
+//! recording, keyboard shortcuts are kept.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+
+use crate::capturable::testsrc::{PixelFormat, TestCapturable};
+use crate::capturable::Capturable;
+use crate::config::Config;
+use crate::control::{ClientStatus, SharedClientStatus};
+use crate::frame_stats::FrameStats;
+use crate::input::indicator::IndicatorStyle;
+use crate::protocol::{
+    KeyboardEvent, MessageInbound, MessageOutbound, WeylusReceiver, WeylusSender,
+};
+use crate::websocket::{ClientRole, InstanceState, WeylusClientHandler};
+
+/// Timers like dwell clicking still run for this long after the last message of a client.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// Size of the test sources standing in for the capturables of the recording.
+const REPLAY_SIZE: (usize, usize) = (1920, 1080);
+
+/// A line of a recording.
+#[derive(Serialize, Deserialize, Debug)]
+struct RecordedMessage {
+    /// Number of the client in the order they connected, starting at 0.
+    client: u32,
+    role: ClientRole,
+    /// Seconds since the recording started.
+    time: f64,
+    message: MessageInbound,
+}
+
+struct Recording {
+    file: File,
+    start: Instant,
+    next_client: u32,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Record the messages of all clients connecting from now on to the file at `path`, it is
+/// overwritten if it exists.
+pub fn start_recording(path: &Path) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    *RECORDING.lock().unwrap() = Some(Recording {
+        file,
+        start: Instant::now(),
+        next_client: 0,
+    });
+    info!(
+        "Recording the messages of clients to {}, typed text is left out.",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Records the messages of a single client.
+pub struct ClientRecorder {
+    client: u32,
+    role: ClientRole,
+}
+
+impl ClientRecorder {
+    /// Returns None unless recording has been started.
+    pub fn new(role: ClientRole) -> Option<Self> {
+        let mut recording = RECORDING.lock().unwrap();
+        let recording = recording.as_mut()?;
+        let client = recording.next_client;
+        recording.next_client += 1;
+        Some(Self { client, role })
+    }
+
+    pub fn record(&self, message: &MessageInbound) {
+        let mut recording = RECORDING.lock().unwrap();
+        let Some(r) = recording.as_mut() else {
+            return;
+        };
+        let line = RecordedMessage {
+            client: self.client,
+            role: self.role,
+            time: r.start.elapsed().as_secs_f64(),
+            message: strip_secrets(message.clone()),
+        };
+        let mut line = serde_json::to_string(&line).unwrap();
+        line.push('\n');
+        if let Err(err) = r.file.write_all(line.as_bytes()) {
+            error!("Failed to write recording, recording stopped: {err}");
+            *recording = None;
+        }
+    }
+}
+
+/// Replace anything that could be a password or identify the user.
+fn strip_secrets(message: MessageInbound) -> MessageInbound {
+    match message {
+        MessageInbound::KeyboardEvent(event) => MessageInbound::KeyboardEvent(strip_text(event)),
+        MessageInbound::Config(mut config) => {
+            if config.client_name.is_some() {
+                config.client_name = Some("client".into());
+            }
+            config.client_id = None;
+            MessageInbound::Config(config)
+        }
+        message => message,
+    }
+}
+
+/// Keys typing a character become x, or X with shift, while shortcuts and keys like Enter or the
+/// arrow keys are kept as they are.
+fn strip_text(mut event: KeyboardEvent) -> KeyboardEvent {
+    let mut chars = event.key.chars();
+    let typed = matches!((chars.next(), chars.next()), (Some(_), None));
+    if typed && !(event.ctrl || event.alt || event.meta) {
+        event.key = if event.shift { "X" } else { "x" }.into();
+        event.code = "KeyX".into();
+    }
+    event
+}
+
+/// Messages sent to replayed clients are only logged.
+#[derive(Clone)]
+struct ReplaySender {
+    client: u32,
+}
+
+impl WeylusSender for ReplaySender {
+    type Error = Infallible;
+
+    fn send_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        debug!("Client {} got {message:?}", self.client);
+        Ok(())
+    }
+
+    fn send_video(&mut self, _bytes: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn replay_video(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn queue_depth(&self) -> usize {
+        0
+    }
+}
+
+/// Hands out the messages of a client at the time they were recorded at.
+struct ReplayReceiver {
+    start: Instant,
+    messages: VecDeque<(Duration, MessageInbound)>,
+    end: Duration,
+}
+
+impl Iterator for ReplayReceiver {
+    type Item = Result<MessageInbound, Infallible>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (due, message) = self.messages.pop_front()?;
+        std::thread::sleep(due.saturating_sub(self.start.elapsed()));
+        Some(Ok(message))
+    }
+}
+
+impl WeylusReceiver for ReplayReceiver {
+    type Error = Infallible;
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Self::Item, RecvTimeoutError> {
+        let due = self.messages.front().map_or(self.end, |(due, _)| *due);
+        let wait = due.saturating_sub(self.start.elapsed());
+        if wait > timeout {
+            std::thread::sleep(timeout);
+            return Err(RecvTimeoutError::Timeout);
+        }
+        std::thread::sleep(wait);
+        self.next().ok_or(RecvTimeoutError::Disconnected)
+    }
+}
+
+/// Test sources offered to replayed clients instead of the capturables of this computer.
+pub fn replay_capturables(n: usize) -> Vec<Box<dyn Capturable>> {
+    (0..n)
+        .map(|_| -> Box<dyn Capturable> {
+            Box::new(TestCapturable {
+                width: REPLAY_SIZE.0,
+                height: REPLAY_SIZE.1,
+                pixel_format: PixelFormat::BGR0,
+            })
+        })
+        .collect()
+}
+
+/// Replay the recording at `path` with the given configuration, returns false if it could not be
+/// read or replaying failed. Each client gets a handler of its own, the input they send is logged
+/// by mock devices and no video is encoded.
+pub fn replay(config: &Config, path: &Path) -> bool {
+    let recording = match fs::read_to_string(path) {
+        Ok(recording) => recording,
+        Err(err) => {
+            error!("Failed to read {}: {err}", path.display());
+            return false;
+        }
+    };
+    let mut clients: BTreeMap<u32, (ClientRole, ReplayReceiver)> = BTreeMap::new();
+    let start = Instant::now();
+    // the recorded ids of the capturables have to be valid
+    let mut capturables = 1;
+    for (i, line) in recording.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut recorded: RecordedMessage = match serde_json::from_str(line) {
+            Ok(recorded) => recorded,
+            Err(err) => {
+                error!(
+                    "Failed to parse line {} of {}: {err}",
+                    i + 1,
+                    path.display()
+                );
+                return false;
+            }
+        };
+        match &mut recorded.message {
+            MessageInbound::Config(config) => {
+                capturables = capturables.max(config.capturable_id + 1);
+                config.input_only = true;
+            }
+            MessageInbound::SwitchCapturable(id) => capturables = capturables.max(*id + 1),
+            _ => {}
+        }
+        let due = Duration::from_secs_f64(recorded.time.max(0.0));
+        let (_, receiver) = clients.entry(recorded.client).or_insert_with(|| {
+            (
+                recorded.role,
+                ReplayReceiver {
+                    start,
+                    messages: VecDeque::new(),
+                    end: Duration::ZERO,
+                },
+            )
+        });
+        receiver.messages.push_back((due, recorded.message));
+        receiver.end = due + LINGER;
+    }
+
+    let Some(mut configs) = crate::weylus::all_web_configs(config) else {
+        return false;
+    };
+    let (_, mut client_config) = configs.remove(0);
+    // nothing but the mock devices may act on this computer
+    client_config.replay_capturables = Some(capturables);
+    client_config.no_gui = true;
+    client_config.wake_on_connect = false;
+    client_config.power_actions.clear();
+    client_config.mirror_notifications = false;
+    client_config.hide_cursor_during_pen = false;
+    client_config.presentation_mode = false;
+    client_config.capturable_filter = None;
+    client_config.control_indicator.style = IndicatorStyle::Off;
+    client_config.session_summary_dir = None;
+    client_config.hooks.clear();
+    client_config.encoder_standby = false;
+
+    info!(
+        "Replaying {} client(s) from {}.",
+        clients.len(),
+        path.display()
+    );
+    let instance = InstanceState::default();
+    let threads: Vec<_> = clients
+        .into_iter()
+        .map(|(client, (role, receiver))| {
+            let client_config = client_config.clone();
+            let instance = instance.clone();
+            std::thread::spawn(move || {
+                let status: SharedClientStatus = Arc::new(Mutex::new(ClientStatus {
+                    // clients are told apart by their address, e.g. by the laser pointers
+                    address: SocketAddr::from(([127, 0, 0, 1], (client + 1) as u16)),
+                    role,
+                    name: None,
+                    capturable: None,
+                    encoder: None,
+                    presentation: false,
+                    input_rate: 0.0,
+                    dropped_input_events: 0,
+                    video: FrameStats::default(),
+                    session_video: FrameStats::default(),
+                    video_bytes: 0,
+                    input_backend: client_config.input_backend,
+                    input_device: None,
+                    broadcasting: false,
+                    watching_broadcast: false,
+                    device: None,
+                }));
+                let (_config_sender, config_receiver) = watch::channel(client_config);
+                info!("Client {client} ({role:?}) connected.");
+                WeylusClientHandler::new(
+                    ReplaySender { client },
+                    receiver,
+                    || {},
+                    config_receiver,
+                    role,
+                    status,
+                    instance,
+                )
+                .run();
+                info!("Client {client} disconnected.");
+            })
+        })
+        .collect();
+    let mut ok = true;
+    for thread in threads {
+        if thread.join().is_err() {
+            error!("Replaying a client panicked.");
+            ok = false;
+        }
+    }
+    info!("Replay finished.");
+    ok
+}
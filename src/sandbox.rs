@@ -0,0 +1,53 @@
+//! Operation inside a sandbox like Flatpak: Weylus has no access to uinput or the X server, so the
+//! screen is captured and input is sent via the RemoteDesktop portal, which asks the user for
+//! permission.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dbus::blocking::SyncConnection;
+use tracing::{debug, info, warn};
+
+/// Application ID, used as name on the session bus so Weylus can be started by D-Bus activation.
+pub const APP_ID: &str = "io.github.H_M_H.Weylus";
+
+static SANDBOXED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Weylus runs in a Flatpak sandbox, Flatpak provides this file in every sandbox.
+fn in_flatpak() -> bool {
+    std::path::Path::new("/.flatpak-info").exists()
+}
+
+/// Enable sandboxed operation if requested or if Weylus runs in a Flatpak.
+pub fn init(sandboxed: bool) {
+    let sandboxed = sandboxed || in_flatpak();
+    if sandboxed {
+        info!("Running sandboxed, screen capture and input go through xdg-desktop-portal.");
+    }
+    SANDBOXED.store(sandboxed, Ordering::Relaxed);
+}
+
+pub fn is_sandboxed() -> bool {
+    SANDBOXED.load(Ordering::Relaxed)
+}
+
+/// Own the application's name on the session bus while the returned connection is alive, this
+/// tells D-Bus activation that Weylus is running.
+pub fn own_bus_name() -> Option<SyncConnection> {
+    let conn = match SyncConnection::new_session() {
+        Ok(conn) => conn,
+        Err(err) => {
+            warn!("Failed to connect to the session bus: {err}.");
+            return None;
+        }
+    };
+    match conn.request_name(APP_ID, false, true, true) {
+        Ok(_) => {
+            debug!("Acquired {APP_ID} on the session bus.");
+            Some(conn)
+        }
+        Err(err) => {
+            warn!("Failed to acquire {APP_ID} on the session bus: {err}.");
+            None
+        }
+    }
+}
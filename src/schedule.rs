@@ -0,0 +1,290 @@
+//! Time windows during which the web server accepts connections, e.g. during classroom hours.
+//!
+//! A window is written as `[DAYS] HH:MM-HH:MM` in local time, for example `Mon-Fri 08:00-15:30`
+//! or `Sat,Sun 10:00-12:00`. Without days the window applies to every day. Windows ending before
+//! they start span midnight, e.g. `Fri 22:00-02:00` ends on Saturday morning.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Local, Timelike};
+use serde::{Deserialize, Serialize};
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const ALL_DAYS: u8 = 0b111_1111;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct AvailabilityWindow {
+    /// Bit 0 is Monday, bit 6 Sunday.
+    days: u8,
+    /// Minutes since midnight.
+    start: u32,
+    end: u32,
+}
+
+impl AvailabilityWindow {
+    fn has_day(&self, day: u32) -> bool {
+        self.days & (1 << day) != 0
+    }
+
+    /// Whether the window is open at the given minute of the given day, 0 being Monday.
+    fn contains(&self, day: u32, minute: u32) -> bool {
+        if self.start < self.end {
+            self.has_day(day) && (self.start..self.end).contains(&minute)
+        } else {
+            (self.has_day(day) && minute >= self.start)
+                || (self.has_day((day + 6) % 7) && minute < self.end)
+        }
+    }
+}
+
+fn parse_day(s: &str) -> Result<u32, String> {
+    DAYS.iter()
+        .position(|d| d.eq_ignore_ascii_case(s))
+        .map(|d| d as u32)
+        .ok_or_else(|| format!("Unknown day {s}, expected one of {}.", DAYS.join(", ")))
+}
+
+fn parse_time(s: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time {s}, expected HH:MM.");
+    let (h, m) = s.split_once(':').ok_or_else(invalid)?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    let m: u32 = m.parse().map_err(|_| invalid())?;
+    // 24:00 allows windows to end at midnight
+    if m >= 60 || h > 24 || (h == 24 && m != 0) {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+impl FromStr for AvailabilityWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (days, times) = match s.trim().rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (Some(days.trim()), times),
+            None => (None, s.trim()),
+        };
+        let mut mask = 0;
+        match days {
+            None => mask = ALL_DAYS,
+            Some(days) => {
+                for part in days.split(',') {
+                    let part = part.trim();
+                    match part.split_once('-') {
+                        Some((first, last)) => {
+                            let (first, last) = (parse_day(first)?, parse_day(last)?);
+                            // ranges may wrap around the week, e.g. Fri-Mon
+                            let mut day = first;
+                            loop {
+                                mask |= 1 << day;
+                                if day == last {
+                                    break;
+                                }
+                                day = (day + 1) % 7;
+                            }
+                        }
+                        None => mask |= 1 << parse_day(part)?,
+                    }
+                }
+            }
+        }
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid time window {times}, expected HH:MM-HH:MM."))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(format!("The time window {times} is empty."));
+        }
+        Ok(Self {
+            days: mask,
+            start: start % (24 * 60),
+            end: end % (24 * 60),
+        })
+    }
+}
+
+impl TryFrom<String> for AvailabilityWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for AvailabilityWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.days != ALL_DAYS {
+            let days: Vec<&str> = (0..7)
+                .filter(|d| self.has_day(*d))
+                .map(|d| DAYS[d as usize])
+                .collect();
+            write!(f, "{} ", days.join(","))?;
+        }
+        // windows ending at midnight are written with 24:00, 00:00 would make them empty
+        let end = if self.end == 0 { 24 * 60 } else { self.end };
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            end / 60,
+            end % 60
+        )
+    }
+}
+
+impl From<AvailabilityWindow> for String {
+    fn from(window: AvailabilityWindow) -> Self {
+        window.to_string()
+    }
+}
+
+/// Whether connections are accepted now, always if no windows are configured.
+pub fn is_open(windows: &[AvailabilityWindow]) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+    let now = Local::now();
+    let day = now.weekday().num_days_from_monday();
+    let minute = now.hour() * 60 + now.minute();
+    windows.iter().any(|w| w.contains(day, minute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MON: u32 = 0;
+    const TUE: u32 = 1;
+    const WED: u32 = 2;
+    const THU: u32 = 3;
+    const FRI: u32 = 4;
+    const SAT: u32 = 5;
+    const SUN: u32 = 6;
+
+    fn window(s: &str) -> AvailabilityWindow {
+        s.parse().unwrap()
+    }
+
+    fn minute(h: u32, m: u32) -> u32 {
+        h * 60 + m
+    }
+
+    #[test]
+    fn weekdays() {
+        let w = window("Mon-Fri 08:00-15:30");
+        assert!(w.contains(MON, minute(8, 0)));
+        assert!(w.contains(FRI, minute(15, 29)));
+        assert!(!w.contains(FRI, minute(15, 30)));
+        assert!(!w.contains(WED, minute(7, 59)));
+        assert!(!w.contains(SAT, minute(10, 0)));
+        assert_eq!(w.to_string(), "Mon,Tue,Wed,Thu,Fri 08:00-15:30");
+    }
+
+    #[test]
+    fn every_day() {
+        let w = window("10:00-12:00");
+        for day in MON..=SUN {
+            assert!(w.contains(day, minute(11, 0)));
+            assert!(!w.contains(day, minute(12, 0)));
+        }
+        assert_eq!(w.to_string(), "10:00-12:00");
+    }
+
+    #[test]
+    fn across_midnight() {
+        let w = window("Fri 22:00-02:00");
+        assert!(!w.contains(FRI, minute(21, 59)));
+        assert!(w.contains(FRI, minute(22, 0)));
+        assert!(w.contains(FRI, minute(23, 59)));
+        assert!(w.contains(SAT, minute(0, 0)));
+        assert!(w.contains(SAT, minute(1, 59)));
+        assert!(!w.contains(SAT, minute(2, 0)));
+        assert!(!w.contains(SAT, minute(22, 0)));
+        // the early hours of Friday belong to a window starting on Thursday
+        assert!(!w.contains(FRI, minute(1, 0)));
+    }
+
+    #[test]
+    fn sunday_night_ends_on_monday() {
+        let w = window("Sun 23:00-01:00");
+        assert!(w.contains(SUN, minute(23, 30)));
+        assert!(w.contains(MON, minute(0, 30)));
+        assert!(!w.contains(MON, minute(23, 30)));
+        assert!(!w.contains(SUN, minute(0, 30)));
+    }
+
+    #[test]
+    fn ending_at_midnight() {
+        let w = window("Tue 18:00-24:00");
+        assert!(w.contains(TUE, minute(18, 0)));
+        assert!(w.contains(TUE, minute(23, 59)));
+        assert!(!w.contains(WED, minute(0, 0)));
+        assert_eq!(w.to_string(), "Tue 18:00-24:00");
+        assert_eq!(window(&w.to_string()), w);
+
+        let w = window("Thu 00:00-24:00");
+        assert!(w.contains(THU, minute(0, 0)));
+        assert!(w.contains(THU, minute(23, 59)));
+        assert!(!w.contains(WED, minute(23, 59)));
+        assert!(!w.contains(FRI, minute(0, 0)));
+        assert_eq!(window(&w.to_string()), w);
+    }
+
+    #[test]
+    fn days_wrapping_around_the_week() {
+        let w = window("Fri-Mon 09:00-17:00");
+        for day in [FRI, SAT, SUN, MON] {
+            assert!(w.contains(day, minute(12, 0)));
+        }
+        for day in [TUE, WED, THU] {
+            assert!(!w.contains(day, minute(12, 0)));
+        }
+        assert_eq!(w.to_string(), "Mon,Fri,Sat,Sun 09:00-17:00");
+        assert_eq!(window(&w.to_string()), w);
+    }
+
+    #[test]
+    fn day_lists() {
+        let w = window("sat, Sun 10:00-12:00");
+        assert!(w.contains(SAT, minute(10, 0)));
+        assert!(w.contains(SUN, minute(10, 0)));
+        assert!(!w.contains(MON, minute(10, 0)));
+        assert_eq!(window("Mon-Sun 10:00-12:00"), window("10:00-12:00"));
+    }
+
+    #[test]
+    fn invalid_windows() {
+        for s in [
+            "",
+            "08:00",
+            "08:00-08:00",
+            "00:00-24:00-01:00",
+            "24:01-08:00",
+            "08:60-09:00",
+            "25:00-26:00",
+            "8-9",
+            "Mon-Fr 08:00-09:00",
+            "Someday 08:00-09:00",
+        ] {
+            assert!(
+                s.parse::<AvailabilityWindow>().is_err(),
+                "{s:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let w = window("Fri 22:00-02:00");
+        let json = serde_json::to_string(&w).unwrap();
+        assert_eq!(json, "\"Fri 22:00-02:00\"");
+        assert_eq!(
+            serde_json::from_str::<AvailabilityWindow>(&json).unwrap(),
+            w
+        );
+        assert!(serde_json::from_str::<AvailabilityWindow>("\"Fri 22:00\"").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Secrets of the configuration like access codes are kept in the keyring of the operating system
+//! instead of the configuration file, unless `--no-keyring` is set.
+//!
+//! The keyring is the source of truth for secrets not set in the configuration file or on the
+//! command line. Secrets found in the configuration file are moved to the keyring the next time
+//! it is written, this also migrates configuration files written by older versions.
+
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+/// Service name of all entries Weylus stores in the keyring.
+const KEYRING_SERVICE: &str = "weylus";
+
+fn entry(name: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, name)
+}
+
+fn get(name: &str) -> Option<String> {
+    match entry(name).and_then(|e| e.get_password()) {
+        Ok(secret) => Some(secret),
+        Err(keyring::Error::NoEntry) => None,
+        Err(err) => {
+            warn!("Failed to read {name} from keyring: {err}.");
+            None
+        }
+    }
+}
+
+/// Store or, if None, delete the secret, returns whether the keyring could be accessed.
+fn set(name: &str, secret: Option<&str>) -> bool {
+    let res = match secret {
+        Some(secret) => entry(name).and_then(|e| e.set_password(secret)),
+        None => match entry(name).and_then(|e| e.delete_credential()) {
+            Err(keyring::Error::NoEntry) => Ok(()),
+            res => res,
+        },
+    };
+    match res {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("Failed to update {name} in keyring: {err}.");
+            false
+        }
+    }
+}
+
+/// The secrets of the configuration along with the names of their keyring entries.
+fn secrets_mut(config: &mut Config) -> Vec<(String, &mut Option<String>)> {
+    let mut secrets = vec![
+        ("access-code".to_string(), &mut config.access_code),
+        (
+            "oidc-client-secret".to_string(),
+            &mut config.oidc_client_secret,
+        ),
+        ("unlock-secret".to_string(), &mut config.unlock_secret),
+    ];
+    for instance in &mut config.instances {
+        secrets.push((
+            format!("access-code-{}", instance.name),
+            &mut instance.access_code,
+        ));
+    }
+    secrets
+}
+
+/// Whether the configuration file contains any secrets that belong in the keyring.
+pub fn has_plaintext(config: &Config) -> bool {
+    config.access_code.is_some()
+        || config.oidc_client_secret.is_some()
+        || config.unlock_secret.is_some()
+        || config.instances.iter().any(|i| i.access_code.is_some())
+}
+
+/// Fill in the secrets that are not set from the keyring.
+pub fn load(config: &mut Config) {
+    for (name, secret) in secrets_mut(config) {
+        if secret.is_none() {
+            *secret = get(&name);
+            if secret.is_some() {
+                debug!("Read {name} from keyring.");
+            }
+        }
+    }
+}
+
+/// Move the secrets of the configuration to the keyring and return the configuration without
+/// them, secrets that could not be stored are kept. Secrets not set are deleted from the keyring,
+/// so the configuration has to be complete, see [`load`].
+pub fn store(config: &Config) -> Config {
+    let mut config = config.clone();
+    for (name, secret) in secrets_mut(&mut config) {
+        if set(&name, secret.as_deref()) {
+            *secret = None;
+        }
+    }
+    config
+}
@@ -0,0 +1,374 @@
+//! `weylus selftest`: exercise everything Weylus needs once, capture a frame with each capture
+//! backend, open each encoder, create the virtual input devices and bind the configured ports, and
+//! print what works. This is the first thing to run if Weylus does not work on a machine.
+
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::capturable::Capturable;
+use crate::config::Config;
+use crate::encoder_probe;
+use crate::video::{EncoderOptions, PixelProvider, VideoEncoder};
+use crate::websocket::WeylusClientConfig;
+
+/// Recorders may need a moment until the first frame arrives, e.g. via PipeWire.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Size of the frame encoded by each encoder.
+const FRAME_SIZE: (usize, usize) = (640, 480);
+
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass(details) => write!(f, "PASS  {details}"),
+            Self::Fail(details) => write!(f, "FAIL  {details}"),
+            Self::Skip(details) => write!(f, "SKIP  {details}"),
+        }
+    }
+}
+
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn add(&mut self, group: &str, name: &str, outcome: Outcome) {
+        if let Outcome::Fail(_) = outcome {
+            self.failed += 1;
+        }
+        println!("{group:<8} {name:<24} {outcome}");
+    }
+}
+
+/// Run all checks, printing one line per check. Returns whether none of them failed.
+pub fn run(config: &Config) -> bool {
+    let mut report = Report { failed: 0 };
+    let Some(configs) = crate::weylus::all_web_configs(config) else {
+        report.add("Config", "configuration", Outcome::Fail("invalid".into()));
+        return false;
+    };
+    let client_config = &configs[0].1;
+    report.add(
+        "Build",
+        "features",
+        Outcome::Pass(format!(
+            "{}, ffmpeg {}",
+            crate::build_info::describe(),
+            crate::build_info::ffmpeg()
+        )),
+    );
+    check_permissions(&mut report);
+    check_capture(&mut report, client_config);
+    check_encoders(&mut report, client_config.encoder_options);
+    check_input(&mut report);
+    for (web_config, _) in &configs {
+        let instance = &web_config.instance_name;
+        let outcome = bind(web_config.bind_addr, false, web_config.find_free_port);
+        report.add("Port", &format!("{instance} web"), outcome);
+        if let Some(port) = web_config.webtransport_port {
+            let addr = SocketAddr::new(web_config.bind_addr.ip(), port);
+            report.add(
+                "Port",
+                &format!("{instance} WebTransport"),
+                bind(addr, true, web_config.find_free_port),
+            );
+        }
+    }
+    if report.failed > 0 {
+        println!("{} check(s) failed.", report.failed);
+    } else {
+        println!("All checks passed.");
+    }
+    report.failed == 0
+}
+
+#[cfg(target_os = "macos")]
+fn check_permissions(report: &mut Report) {
+    use crate::permissions::Permission;
+
+    let Some(permissions) = crate::permissions::check() else {
+        return;
+    };
+    for (permission, granted) in [
+        (Permission::ScreenRecording, permissions.screen_recording),
+        (Permission::Accessibility, permissions.accessibility),
+    ] {
+        let outcome = if granted {
+            Outcome::Pass("granted".into())
+        } else {
+            Outcome::Fail(format!("not granted, see {}", permission.settings_url()))
+        };
+        report.add("Access", permission.name(), outcome);
+    }
+}
+
+/// Other platforms require no permissions.
+#[cfg(not(target_os = "macos"))]
+fn check_permissions(_report: &mut Report) {}
+
+/// Name of a capture backend and its capturables.
+type Backend = (&'static str, Result<Vec<Box<dyn Capturable>>, Outcome>);
+
+/// The capture backends of this platform.
+fn capture_backends(#[allow(unused_variables)] config: &WeylusClientConfig) -> Vec<Backend> {
+    let mut backends: Vec<Backend> = vec![];
+    #[cfg(target_os = "linux")]
+    {
+        #[cfg(not(feature = "wayland"))]
+        let pipewire = Err(Outcome::Skip("built without the wayland feature".into()));
+        #[cfg(feature = "wayland")]
+        let pipewire = if config.wayland_support {
+            crate::capturable::pipewire::get_capturables(false)
+                .map(|c| {
+                    c.into_iter()
+                        .map(|c| Box::new(c) as Box<dyn Capturable>)
+                        .collect()
+                })
+                .map_err(|err| Outcome::Fail(err.to_string()))
+        } else {
+            Err(Outcome::Skip("--wayland-support is not set".into()))
+        };
+        backends.push(("PipeWire", pipewire));
+        let x11 = if crate::sandbox::is_sandboxed() {
+            Err(Outcome::Skip("not used while running sandboxed".into()))
+        } else if config.wayland_support && crate::capturable::is_wayland_session() {
+            Err(Outcome::Skip("not used in a Wayland session".into()))
+        } else {
+            match crate::capturable::x11::X11Context::new() {
+                Some(mut x11ctx) => x11ctx
+                    .capturables(config.x11_windows)
+                    .map(|c| {
+                        c.into_iter()
+                            .map(|c| Box::new(c) as Box<dyn Capturable>)
+                            .collect()
+                    })
+                    .map_err(|err| Outcome::Fail(err.to_string())),
+                None => Err(Outcome::Fail("can not open the display".into())),
+            }
+        };
+        backends.push(("X11", x11));
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let displays = crate::capturable::core_graphics::get_displays()
+            .map(|c| {
+                c.into_iter()
+                    .map(|c| Box::new(c) as Box<dyn Capturable>)
+                    .collect()
+            })
+            .map_err(|err| Outcome::Fail(err.to_string()));
+        backends.push(("CoreGraphics", displays));
+        use crate::capturable::screencapturekit::{is_available, SCKCapturable};
+        let sck = if is_available() {
+            crate::capturable::core_graphics::get_displays()
+                .map(|c| {
+                    c.into_iter()
+                        .map(|c| Box::new(SCKCapturable::display(c)) as Box<dyn Capturable>)
+                        .collect()
+                })
+                .map_err(|err| Outcome::Fail(err.to_string()))
+        } else {
+            Err(Outcome::Skip("requires macOS 12.3 or later".into()))
+        };
+        backends.push(("ScreenCaptureKit", sck));
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use crate::capturable::captrs_capture::CaptrsCapturable;
+        use crate::capturable::win_ctx::WinCtx;
+        let winctx = WinCtx::new();
+        let outputs = winctx
+            .get_outputs()
+            .iter()
+            .enumerate()
+            .map(|(i, o)| {
+                Box::new(CaptrsCapturable::new(
+                    i as u8,
+                    String::from_utf16_lossy(o.DeviceName.as_ref()),
+                    o.DesktopCoordinates,
+                    winctx.get_union_rect().clone(),
+                )) as Box<dyn Capturable>
+            })
+            .collect();
+        backends.push(("DXGI", Ok(outputs)));
+        let windows = crate::capturable::win_window::get_windows()
+            .into_iter()
+            .map(|c| Box::new(c) as Box<dyn Capturable>)
+            .collect();
+        backends.push(("PrintWindow", Ok(windows)));
+        use crate::capturable::win_graphics_capture::{is_supported, GraphicsCaptureCapturable};
+        let graphics_capture = if is_supported() {
+            Ok(winctx
+                .get_outputs()
+                .iter()
+                .map(|o| {
+                    Box::new(GraphicsCaptureCapturable::monitor(
+                        o.Monitor,
+                        String::from_utf16_lossy(o.DeviceName.as_ref()),
+                        o.DesktopCoordinates,
+                    )) as Box<dyn Capturable>
+                })
+                .collect())
+        } else {
+            Err(Outcome::Skip(
+                "requires Windows 10 version 1903 or later".into(),
+            ))
+        };
+        backends.push(("Windows.Graphics.Capture", graphics_capture));
+    }
+    backends
+}
+
+/// Size of the first frame captured from the capturable.
+fn capture_frame(capturable: &dyn Capturable) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut recorder = capturable.recorder(false)?;
+    let start = Instant::now();
+    loop {
+        match recorder.capture() {
+            Ok(pixels) => return Ok(pixels.size()),
+            Err(err) if start.elapsed() > CAPTURE_TIMEOUT => return Err(err),
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+fn check_capture(report: &mut Report, config: &WeylusClientConfig) {
+    for (backend, capturables) in capture_backends(config) {
+        let outcome = match capturables {
+            Ok(capturables) => match capturables.first() {
+                Some(capturable) => match capture_frame(capturable.as_ref()) {
+                    Ok((width, height)) => {
+                        Outcome::Pass(format!("{width}x{height} from {}", capturable.name()))
+                    }
+                    Err(err) => Outcome::Fail(format!("{}: {err}", capturable.name())),
+                },
+                None => Outcome::Fail("nothing to capture".into()),
+            },
+            Err(outcome) => outcome,
+        };
+        report.add("Capture", backend, outcome);
+    }
+}
+
+fn check_encoders(report: &mut Report, configured: EncoderOptions) {
+    // every encoder of the platform is tried, not only the enabled ones
+    let all = EncoderOptions {
+        try_vaapi: cfg!(all(target_os = "linux", feature = "vaapi")),
+        try_nvenc: cfg!(all(
+            any(
+                target_os = "linux",
+                all(target_os = "windows", target_arch = "x86_64")
+            ),
+            feature = "nvenc"
+        )),
+        try_videotoolbox: cfg!(target_os = "macos"),
+        try_mediafoundation: cfg!(target_os = "windows"),
+        ..configured
+    };
+    let enabled: Vec<&str> = encoder_probe::variants(configured)
+        .into_iter()
+        .map(|(kind, _)| kind)
+        .collect();
+    for (kind, options) in encoder_probe::variants(all) {
+        let mut outcome = encode_frame(kind, options);
+        if !enabled.contains(&kind) {
+            if let Outcome::Pass(details) = outcome {
+                outcome = Outcome::Pass(format!("{details}, not enabled in the configuration"));
+            }
+        }
+        report.add("Encoder", kind, outcome);
+    }
+}
+
+fn encode_frame(kind: &str, options: EncoderOptions) -> Outcome {
+    let (width, height) = FRAME_SIZE;
+    let bytes = Arc::new(AtomicUsize::new(0));
+    let write_data = {
+        let bytes = bytes.clone();
+        move |data: &[u8]| {
+            bytes.fetch_add(data.len(), Ordering::Relaxed);
+        }
+    };
+    let mut encoder = match VideoEncoder::new(width, height, width, height, write_data, options) {
+        Ok(encoder) => encoder,
+        Err(err) => return Outcome::Fail(err.to_string()),
+    };
+    let name = encoder.name().unwrap_or_default();
+    // hardware encoders that are not available fall back to the software encoder
+    if kind != "software" && name == "libx264" {
+        return Outcome::Fail("not available".into());
+    }
+    let header = bytes.load(Ordering::Relaxed);
+    let frame = vec![0x80u8; width * height * 4];
+    encoder.encode(PixelProvider::BGR0(width, height, &frame));
+    encoder.flush();
+    if bytes.load(Ordering::Relaxed) > header {
+        Outcome::Pass(name)
+    } else {
+        Outcome::Fail(format!("{name} produced no video"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_input(report: &mut Report) {
+    use crate::capturable::testsrc::{PixelFormat, TestCapturable};
+    use crate::input::uinput_device::{UInputDevice, UInputJoystick};
+
+    if crate::sandbox::is_sandboxed() {
+        let reason = "input is sent via the RemoteDesktop portal while running sandboxed";
+        report.add("Input", "uinput", Outcome::Skip(reason.into()));
+        return;
+    }
+    let id = Some("Self-Test".to_string());
+    let capturable = Box::new(TestCapturable {
+        width: FRAME_SIZE.0,
+        height: FRAME_SIZE.1,
+        pixel_format: PixelFormat::BGR0,
+    });
+    // the devices are destroyed again right away
+    let outcome = match UInputDevice::new(capturable, &id, false, None) {
+        Ok(_) => Outcome::Pass("stylus, mouse, touch and keyboard".into()),
+        Err(err) => Outcome::Fail(err.to_string()),
+    };
+    report.add("Input", "uinput", outcome);
+    let outcome = match UInputJoystick::new(&id) {
+        Ok(_) => Outcome::Pass("joystick".into()),
+        Err(err) => Outcome::Fail(err.to_string()),
+    };
+    report.add("Input", "uinput joystick", outcome);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_input(report: &mut Report) {
+    let reason = "input is simulated via autopilot, no devices are created";
+    report.add("Input", "autopilot", Outcome::Skip(reason.into()));
+}
+
+fn bind(addr: SocketAddr, udp: bool, find_free_port: bool) -> Outcome {
+    let res = if udp {
+        UdpSocket::bind(addr).map(drop)
+    } else {
+        TcpListener::bind(addr).map(drop)
+    };
+    match res {
+        Ok(()) => Outcome::Pass(addr.to_string()),
+        Err(err) if err.kind() == ErrorKind::AddrInUse && find_free_port => Outcome::Skip(format!(
+            "{addr} is in use, the next free port is used instead"
+        )),
+        Err(err) if err.kind() == ErrorKind::AddrInUse => {
+            Outcome::Fail(format!("{addr} is in use, is Weylus running already?"))
+        }
+        Err(err) => Outcome::Fail(format!("{addr}: {err}")),
+    }
+}
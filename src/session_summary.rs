@@ -0,0 +1,126 @@
+//! Summary of a session written once the client disconnects: how long it lasted, how much video
+//! was sent and dropped, the latency of the connection and the input received. Summaries are
+//! logged and optionally written as JSON files, one per session, so the effect of changing a
+//! setting can be compared across sessions.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::control::ClientStatus;
+use crate::frame_stats::FrameStats;
+use crate::websocket::ClientRole;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionSummary {
+    pub address: String,
+    pub name: Option<String>,
+    pub role: ClientRole,
+    /// Local time the session started at, RFC 3339.
+    pub start: String,
+    /// Seconds.
+    pub duration: f64,
+    pub capturable: Option<String>,
+    pub encoder: Option<String>,
+    /// Frames of the whole session, the averages are over all frames.
+    pub frames: FrameStats,
+    pub video_bytes: u64,
+    /// Round trip times of the connection in milliseconds, None if never measured.
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_p95_ms: Option<f64>,
+    pub input_events: u64,
+    pub dropped_input_events: u64,
+}
+
+/// Collects what the status of the client does not keep track of over the whole session.
+pub struct SessionRecorder {
+    start: Instant,
+    started_at: DateTime<Local>,
+    rtts: Vec<f64>,
+}
+
+impl SessionRecorder {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            started_at: Local::now(),
+            rtts: Vec::new(),
+        }
+    }
+
+    /// Record a round trip time in milliseconds.
+    pub fn rtt(&mut self, ms: f64) {
+        self.rtts.push(ms);
+    }
+
+    pub fn finish(
+        mut self,
+        status: &ClientStatus,
+        input_events: u64,
+        dropped_input_events: u64,
+    ) -> SessionSummary {
+        self.rtts.sort_by(f64::total_cmp);
+        let rtt_avg_ms =
+            (!self.rtts.is_empty()).then(|| self.rtts.iter().sum::<f64>() / self.rtts.len() as f64);
+        let rtt_p95_ms = (!self.rtts.is_empty()).then(|| {
+            let rank = (self.rtts.len() as f64 * 0.95).ceil() as usize;
+            self.rtts[rank.clamp(1, self.rtts.len()) - 1]
+        });
+        SessionSummary {
+            address: status.address.to_string(),
+            name: status.name.clone(),
+            role: status.role,
+            start: self.started_at.to_rfc3339(),
+            duration: self.start.elapsed().as_secs_f64(),
+            capturable: status.capturable.clone(),
+            encoder: status.encoder.clone(),
+            frames: status.session_video,
+            video_bytes: status.video_bytes,
+            rtt_avg_ms,
+            rtt_p95_ms,
+            input_events,
+            dropped_input_events,
+        }
+    }
+}
+
+impl SessionSummary {
+    pub fn log(&self) {
+        let frames = &self.frames;
+        info!(
+            address = %self.address,
+            duration_s = %format_args!("{:.0}", self.duration),
+            sent = frames.sent,
+            dropped = frames.dropped(),
+            video_mb = %format_args!("{:.1}", self.video_bytes as f64 / 1e6),
+            encode_ms = %format_args!("{:.1}", frames.encode_ms),
+            rtt_avg_ms = %format_args!("{:.1}", self.rtt_avg_ms.unwrap_or(f64::NAN)),
+            rtt_p95_ms = %format_args!("{:.1}", self.rtt_p95_ms.unwrap_or(f64::NAN)),
+            input_events = self.input_events,
+            dropped_input_events = self.dropped_input_events,
+            "Session ended."
+        );
+    }
+
+    /// Write the summary to a file of its own in the directory, which is created if needed.
+    pub fn write(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let start = DateTime::parse_from_rfc3339(&self.start).map_or_else(
+            |_| "session".into(),
+            |t| t.format("%Y%m%d-%H%M%S").to_string(),
+        );
+        // colons are not allowed in file names on Windows
+        let address: String = self
+            .address
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{start}-{address}.json"));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,74 @@
+//! Small downscaled images of capturables, shown in the gui's preview and to clients choosing what
+//! to capture.
+
+use std::error::Error;
+use std::time::Duration;
+
+use base64::Engine;
+use image::ImageEncoder;
+
+use crate::capturable::Capturable;
+use crate::video::PixelProvider;
+
+/// Some recorders need a moment until the first frame is available.
+const CAPTURE_ATTEMPTS: usize = 10;
+const CAPTURE_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Scale the frame down to at most the given width by picking the nearest pixel, returns width,
+/// height and RGB data of the scaled image.
+pub fn downscale(pixels: &PixelProvider, max_width: usize) -> Option<(usize, usize, Vec<u8>)> {
+    let (width, height) = pixels.size();
+    // offset of the first pixel of a row and of every color within a pixel
+    let (data, stride, bytes_per_pixel, [r, g, b]) = match pixels {
+        PixelProvider::RGB(w, _, data) => (*data, w * 3, 3, [0, 1, 2]),
+        PixelProvider::RGB0(w, _, data) => (*data, w * 4, 4, [0, 1, 2]),
+        PixelProvider::BGR0(w, _, data) => (*data, w * 4, 4, [2, 1, 0]),
+        PixelProvider::BGR0S(_, _, stride, data) => (*data, *stride, 4, [2, 1, 0]),
+        PixelProvider::RGB0S(_, _, stride, data) => (*data, *stride, 4, [0, 1, 2]),
+        PixelProvider::DmaBuf(_) => return None,
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let scale = (max_width as f64 / width as f64).min(1.0);
+    let scaled_width = ((width as f64 * scale) as usize).max(1);
+    let scaled_height = ((height as f64 * scale) as usize).max(1);
+    let mut rgb = Vec::with_capacity(scaled_width * scaled_height * 3);
+    for y in 0..scaled_height {
+        let row = (y * height / scaled_height) * stride;
+        for x in 0..scaled_width {
+            let offset = row + (x * width / scaled_width) * bytes_per_pixel;
+            let pixel = data.get(offset..offset + bytes_per_pixel)?;
+            rgb.extend([pixel[r], pixel[g], pixel[b]]);
+        }
+    }
+    Some((scaled_width, scaled_height, rgb))
+}
+
+/// Capture a single frame of the capturable and return it as PNG encoded data url.
+pub fn thumbnail(capturable: &dyn Capturable, max_width: usize) -> Result<String, Box<dyn Error>> {
+    let mut recorder = capturable.recorder(false)?;
+    let mut last_err = None;
+    for _ in 0..CAPTURE_ATTEMPTS {
+        match recorder.capture() {
+            Ok(pixels) => {
+                let (width, height, rgb) =
+                    downscale(&pixels, max_width).ok_or("Unsupported pixel format.")?;
+                let mut png = Vec::new();
+                image::codecs::png::PngEncoder::new(&mut png).write_image(
+                    &rgb,
+                    width as u32,
+                    height as u32,
+                    image::ExtendedColorType::Rgb8,
+                )?;
+                return Ok(format!(
+                    "data:image/png;base64,{}",
+                    base64::engine::general_purpose::STANDARD.encode(png)
+                ));
+            }
+            Err(err) => last_err = Some(err),
+        }
+        std::thread::sleep(CAPTURE_RETRY_INTERVAL);
+    }
+    Err(last_err.unwrap_or_else(|| "No frame captured.".into()))
+}
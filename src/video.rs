@@ -1,9 +1,13 @@
-use std::os::raw::{c_int, c_uchar, c_void};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uchar, c_void};
+use std::sync::Mutex;
 use std::time::Instant;
 
-use tracing::warn;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, warn};
 
 use crate::cerror::CError;
+use crate::h264_level::H264Level;
 
 extern "C" {
     fn init_video_encoder(
@@ -16,33 +20,67 @@ extern "C" {
         try_nvenc: c_int,
         try_videotoolbox: c_int,
         try_mediafoundation: c_int,
+        threads: c_int,
+        slices: c_int,
+        tuning: *const EncoderTuning,
     ) -> *mut c_void;
     fn open_video(handle: *mut c_void, err: *mut CError);
     fn destroy_video_encoder(handle: *mut c_void);
     fn encode_video_frame(handle: *mut c_void, micros: c_int, err: *mut CError);
+    fn flush_video_encoder(handle: *mut c_void, err: *mut CError);
+    fn get_video_encoder_name(handle: *mut c_void) -> *const c_char;
+    fn h264_encoder_available() -> c_int;
+    fn request_keyframe(handle: *mut c_void);
 
     fn fill_rgb(ctx: *mut c_void, data: *const u8, err: *mut CError);
-    fn fill_rgb0(ctx: *mut c_void, data: *const u8, err: *mut CError);
+    fn fill_rgb0(ctx: *mut c_void, data: *const u8, stride: c_int, err: *mut CError);
     fn fill_bgr0(ctx: *mut c_void, data: *const u8, stride: c_int, err: *mut CError);
+    fn fill_dmabuf(ctx: *mut c_void, frame: *const DmaBufFrame, err: *mut CError);
 }
 
 // this is used as callback in lib/encode_video.c via ffmpegs AVIOContext
 #[no_mangle]
 fn write_video_packet(video_encoder: *mut c_void, buf: *const c_uchar, buf_size: c_int) -> c_int {
     let video_encoder = unsafe { (video_encoder as *mut VideoEncoder).as_mut().unwrap() };
-    (video_encoder.write_data)(unsafe {
-        std::slice::from_raw_parts(buf as *const u8, buf_size as usize)
-    });
+    let data = unsafe { std::slice::from_raw_parts(buf as *const u8, buf_size as usize) };
+    if video_encoder.recording_init_segment {
+        video_encoder.init_segment.extend_from_slice(data);
+    }
+    (video_encoder.write_data)(data);
     0
 }
 
+/// Frame in GPU memory shared via DMA-BUF, this lives in lib/encode_video.c as well. The file
+/// descriptor has to stay valid until the frame has been encoded.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DmaBufFrame {
+    pub fd: c_int,
+    pub size: u64,
+    pub offset: u64,
+    pub pitch: u64,
+    /// DRM fourcc code, only XRGB8888 and XBGR8888 and their alpha variants are supported.
+    pub drm_format: u32,
+    pub modifier: u64,
+    pub width: c_int,
+    pub height: c_int,
+    pub crop_x: c_int,
+    pub crop_y: c_int,
+    pub crop_width: c_int,
+    pub crop_height: c_int,
+}
+
 pub enum PixelProvider<'a> {
     // 8 bits per color
     RGB(usize, usize, &'a [u8]),
     RGB0(usize, usize, &'a [u8]),
     BGR0(usize, usize, &'a [u8]),
-    // width, height, stride
+    // width, height, stride, rows may be padded or the frame may be cropped from a larger
+    // buffer, these are handed to the encoder without copying them
     BGR0S(usize, usize, usize, &'a [u8]),
+    RGB0S(usize, usize, usize, &'a [u8]),
+    // converted and scaled on the GPU, requires VAAPI
+    DmaBuf(DmaBufFrame),
 }
 
 impl<'a> PixelProvider<'a> {
@@ -52,35 +90,195 @@ impl<'a> PixelProvider<'a> {
             PixelProvider::RGB0(w, h, _) => (*w, *h),
             PixelProvider::BGR0(w, h, _) => (*w, *h),
             PixelProvider::BGR0S(w, h, _, _) => (*w, *h),
+            PixelProvider::RGB0S(w, h, _, _) => (*w, *h),
+            PixelProvider::DmaBuf(frame) => (frame.crop_width as usize, frame.crop_height as usize),
+        }
+    }
+
+    /// Stride and pixel data, None for frames in GPU memory.
+    fn data(&self) -> Option<(usize, &[u8])> {
+        match self {
+            PixelProvider::RGB(w, _, data) => Some((w.checked_mul(3)?, data)),
+            PixelProvider::RGB0(w, _, data) | PixelProvider::BGR0(w, _, data) => {
+                Some((w.checked_mul(4)?, data))
+            }
+            PixelProvider::BGR0S(_, _, stride, data) | PixelProvider::RGB0S(_, _, stride, data) => {
+                Some((*stride, data))
+            }
+            PixelProvider::DmaBuf(_) => None,
+        }
+    }
+
+    /// Bytes the pixel data has to span given its size and stride, None if that overflows. The
+    /// last row may end right after its pixels, even if the stride is larger.
+    pub fn required_len(&self) -> Option<usize> {
+        let bytes_per_pixel = match self {
+            PixelProvider::RGB(..) => 3,
+            _ => 4,
+        };
+        let (width, height) = self.size();
+        let (stride, _) = self.data()?;
+        if height == 0 {
+            return Some(0);
+        }
+        stride
+            .checked_mul(height - 1)?
+            .checked_add(width.checked_mul(bytes_per_pixel)?)
+    }
+}
+
+/// Tradeoff between latency and quality of the encoder, this lives in lib/encode_video.c as well.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EncoderTuning {
+    /// 0 picks the fastest preset of the encoder, 2 a slower one giving better quality.
+    pub speed: c_int,
+    pub zerolatency: c_int,
+    /// Number of frames the encoder looks ahead, only without zerolatency.
+    pub lookahead: c_int,
+    pub b_frames: c_int,
+    /// Number of frames between keyframes.
+    pub gop_size: c_int,
+    /// Upper bound of the bitrate in kbit/s, 0 for no limit.
+    pub max_bitrate: c_int,
+    /// H.264 level_idc, e.g. 52 for level 5.2, 0 leaves it to the encoder.
+    pub level: c_int,
+    /// Refresh the picture column by column over `gop_size` frames instead of sending keyframes,
+    /// this avoids the spikes in bitrate keyframes cause. Only supported by libx264 and NVENC.
+    pub intra_refresh: c_int,
+}
+
+/// Named end-to-end presets setting the encoder and the buffering of the client together.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatencyPreset {
+    /// Encode every frame right away and buffer nothing, the video may stutter over bad networks.
+    #[default]
+    LowestLatency,
+    /// Less frequent keyframes and a small buffer, for most networks.
+    Balanced,
+    /// Look ahead, use B-frames and buffer more, for watching rather than drawing.
+    BestQuality,
+}
+
+impl LatencyPreset {
+    pub fn tuning(self) -> EncoderTuning {
+        match self {
+            Self::LowestLatency => EncoderTuning {
+                speed: 0,
+                zerolatency: 1,
+                lookahead: 0,
+                b_frames: 0,
+                gop_size: 12,
+                max_bitrate: 0,
+                level: 0,
+                intra_refresh: 0,
+            },
+            Self::Balanced => EncoderTuning {
+                speed: 1,
+                zerolatency: 1,
+                lookahead: 0,
+                b_frames: 0,
+                gop_size: 60,
+                max_bitrate: 0,
+                level: 0,
+                intra_refresh: 0,
+            },
+            Self::BestQuality => EncoderTuning {
+                speed: 2,
+                zerolatency: 0,
+                lookahead: 10,
+                b_frames: 2,
+                gop_size: 120,
+                max_bitrate: 0,
+                level: 0,
+                intra_refresh: 0,
+            },
+        }
+    }
+
+    /// Seconds of video clients buffer at least, on top of what they ask for themselves.
+    pub fn min_buffer(self) -> f64 {
+        match self {
+            Self::LowestLatency => 0.0,
+            Self::Balanced => 0.05,
+            Self::BestQuality => 0.2,
         }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct EncoderOptions {
     pub try_vaapi: bool,
     pub try_nvenc: bool,
     pub try_videotoolbox: bool,
     pub try_mediafoundation: bool,
+    /// Number of threads used for encoding, 0 lets the encoder decide.
+    pub threads: u32,
+    /// Number of slices each frame is split into, slices are encoded in parallel.
+    pub slices: u32,
+    pub tuning: EncoderTuning,
+}
+
+impl EncoderOptions {
+    /// Signal the lowest H.264 level up to `max` fitting a video of the given size and frame
+    /// rate, videos too large for `max` leave the level to the encoder.
+    pub fn with_level(
+        mut self,
+        width: usize,
+        height: usize,
+        frame_rate: f64,
+        max: H264Level,
+    ) -> Self {
+        self.tuning.level =
+            H264Level::required(width, height, frame_rate, max).map_or(0, |l| l.idc as c_int);
+        self
+    }
+
+    /// Threads used by default, half of the available CPUs as capturing and converting frames
+    /// happens in parallel. More than 8 threads barely speed up encoding a single frame.
+    pub fn default_threads() -> u32 {
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get()) as u32;
+        (cpus / 2).clamp(1, 8)
+    }
 }
 
+/// Check whether ffmpeg has been built with any H.264 encoder.
+pub fn h264_available() -> bool {
+    unsafe { h264_encoder_available() != 0 }
+}
+
+/// Encoder kept open after its client disconnected, see [`VideoEncoder::park`].
+static STANDBY_ENCODER: Mutex<Option<Box<VideoEncoder>>> = Mutex::new(None);
+
 pub struct VideoEncoder {
     handle: *mut c_void,
     width_in: usize,
     height_in: usize,
     width_out: usize,
     height_out: usize,
-    write_data: Box<dyn FnMut(&[u8])>,
+    write_data: Box<dyn FnMut(&[u8]) + Send>,
     start_time: Instant,
+    options: EncoderOptions,
+    /// Header of the video written when opening the encoder, needed to hand the encoder over to
+    /// another receiver.
+    init_segment: Vec<u8>,
+    recording_init_segment: bool,
+    /// The frame converted last is still held by the encoder, see
+    /// [`VideoEncoder::encode_unchanged`].
+    filled: bool,
 }
 
+// the encoder is only ever used by one thread at a time, ffmpeg's contexts can be moved between
+// threads
+unsafe impl Send for VideoEncoder {}
+
 impl VideoEncoder {
     pub fn new(
         width_in: usize,
         height_in: usize,
         width_out: usize,
         height_out: usize,
-        mut write_data: impl FnMut(&[u8]) + 'static,
+        mut write_data: impl FnMut(&[u8]) + Send + 'static,
         options: EncoderOptions,
     ) -> Result<Box<Self>, CError> {
         let mut video_encoder = Box::new(Self {
@@ -91,6 +289,10 @@ impl VideoEncoder {
             height_out,
             write_data: Box::new(move |data| write_data(data)),
             start_time: Instant::now(),
+            options,
+            init_segment: Vec::new(),
+            recording_init_segment: true,
+            filled: false,
         });
         let handle = unsafe {
             init_video_encoder(
@@ -103,6 +305,9 @@ impl VideoEncoder {
                 options.try_nvenc.into(),
                 options.try_videotoolbox.into(),
                 options.try_mediafoundation.into(),
+                options.threads as c_int,
+                options.slices as c_int,
+                &options.tuning,
             )
         };
         video_encoder.handle = handle;
@@ -112,11 +317,89 @@ impl VideoEncoder {
         if err.is_err() {
             return Err(err);
         }
+        video_encoder.recording_init_segment = false;
         Ok(video_encoder)
     }
 
+    /// Create an encoder in a separate thread and keep it as standby, this initializes the
+    /// hardware and loads the drivers before the first client connects. The encoder is only
+    /// used if the client's video has the given size.
+    pub fn warm_up(width: usize, height: usize, options: EncoderOptions) {
+        std::thread::spawn(move || {
+            match Self::new(width, height, width, height, |_| (), options) {
+                Ok(encoder) => {
+                    debug!(encoder = ?encoder.name(), "Video encoder warmed up.");
+                    encoder.park();
+                }
+                Err(err) => warn!("Failed to warm up video encoder: {err}"),
+            }
+        });
+    }
+
+    /// Keep the encoder open to be taken over by the next client via
+    /// [`VideoEncoder::take_standby`], any encoder kept previously is closed.
+    pub fn park(mut self: Box<Self>) {
+        self.write_data = Box::new(|_| ());
+        *STANDBY_ENCODER.lock().unwrap() = Some(self);
+    }
+
+    /// Take over the encoder kept as standby if it matches the given size and options. The
+    /// initialization segment is sent again and the next frame is encoded as keyframe, so the new
+    /// receiver can start decoding right away.
+    pub fn take_standby(
+        width_in: usize,
+        height_in: usize,
+        width_out: usize,
+        height_out: usize,
+        write_data: impl FnMut(&[u8]) + Send + 'static,
+        options: EncoderOptions,
+    ) -> Option<Box<Self>> {
+        let mut encoder = STANDBY_ENCODER.lock().unwrap().take()?;
+        if encoder.options != options
+            || !encoder.check_size(width_in, height_in, width_out, height_out)
+        {
+            // close it, hardware encoders may only support a limited number of sessions
+            debug!("Standby video encoder does not match, closing it.");
+            return None;
+        }
+        encoder.write_data = Box::new(write_data);
+        // the frame it still holds belongs to the previous receiver
+        encoder.filled = false;
+        (encoder.write_data)(&encoder.init_segment);
+        unsafe { request_keyframe(encoder.handle) };
+        Some(encoder)
+    }
+
+    /// Check the frame can be read safely by the encoder, which only knows the size it has been
+    /// opened for and takes strides as c_int.
+    fn check_frame(&self, pixel_provider: &PixelProvider) -> Result<(), String> {
+        let (width, height) = pixel_provider.size();
+        if (width, height) != (self.width_in, self.height_in) {
+            return Err(format!(
+                "Frame of {width}x{height} does not match the encoder's size of {}x{}.",
+                self.width_in, self.height_in
+            ));
+        }
+        let Some((stride, data)) = pixel_provider.data() else {
+            return Ok(());
+        };
+        match pixel_provider.required_len() {
+            Some(len) if len <= data.len() && stride <= c_int::MAX as usize => Ok(()),
+            _ => Err(format!(
+                "Frame of {width}x{height} with a stride of {stride} does not fit into its {} \
+                bytes.",
+                data.len()
+            )),
+        }
+    }
+
     pub fn encode(&mut self, pixel_provider: PixelProvider) {
+        if let Err(err) = self.check_frame(&pixel_provider) {
+            warn!("Skipping video frame: {err}");
+            return;
+        }
         let mut err = CError::new();
+        let convert_span = debug_span!("convert").entered();
         match pixel_provider {
             PixelProvider::BGR0(w, _, bgr0) => unsafe {
                 fill_bgr0(self.handle, bgr0.as_ptr(), (w * 4) as c_int, &mut err);
@@ -127,14 +410,38 @@ impl VideoEncoder {
             PixelProvider::RGB(_, _, rgb) => unsafe {
                 fill_rgb(self.handle, rgb.as_ptr(), &mut err);
             },
-            PixelProvider::RGB0(_, _, rgb) => unsafe {
-                fill_rgb0(self.handle, rgb.as_ptr(), &mut err);
+            PixelProvider::RGB0(w, _, rgb) => unsafe {
+                fill_rgb0(self.handle, rgb.as_ptr(), (w * 4) as c_int, &mut err);
+            },
+            PixelProvider::RGB0S(_, _, stride, rgb) => unsafe {
+                fill_rgb0(self.handle, rgb.as_ptr(), stride as c_int, &mut err);
+            },
+            PixelProvider::DmaBuf(frame) => unsafe {
+                fill_dmabuf(self.handle, &frame, &mut err);
             },
         }
+        convert_span.exit();
+        self.filled = !err.is_err();
         if err.is_err() {
             warn!("Failed to fill video frame: {}", err);
             return;
         }
+        self.encode_filled();
+    }
+
+    /// Encode the frame converted last once more instead of converting the same pixels again,
+    /// e.g. if the recorder reports no dirty rects. Returns false if there is no such frame.
+    pub fn encode_unchanged(&mut self) -> bool {
+        if !self.filled {
+            return false;
+        }
+        self.encode_filled();
+        true
+    }
+
+    fn encode_filled(&mut self) {
+        let mut err = CError::new();
+        let _encode_span = debug_span!("encode").entered();
         unsafe {
             encode_video_frame(
                 self.handle,
@@ -144,10 +451,45 @@ impl VideoEncoder {
         }
         if err.is_err() {
             warn!("Failed to encode video frame: {}", err);
-            return;
         }
     }
 
+    /// Encode and send all frames still buffered by the encoder, no more frames can be encoded
+    /// afterwards.
+    pub fn flush(&mut self) {
+        let mut err = CError::new();
+        unsafe {
+            flush_video_encoder(self.handle, &mut err);
+        }
+        if err.is_err() {
+            warn!("Failed to flush video encoder: {}", err);
+        }
+    }
+
+    /// Make the next encoded frame a keyframe, e.g. because the content changed completely.
+    pub fn request_keyframe(&mut self) {
+        unsafe { request_keyframe(self.handle) };
+    }
+
+    /// Name of the ffmpeg encoder in use, e.g. libx264 or h264_vaapi.
+    pub fn name(&self) -> Option<String> {
+        let name = unsafe { get_video_encoder_name(self.handle) };
+        if name.is_null() {
+            None
+        } else {
+            Some(
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        }
+    }
+
+    /// Whether the encoder takes frames of this size.
+    pub fn check_size_in(&self, width_in: usize, height_in: usize) -> bool {
+        self.width_in == width_in && self.height_in == height_in
+    }
+
     pub fn check_size(
         &self,
         width_in: usize,
@@ -0,0 +1,168 @@
+//! Wake the display and optionally unlock the session once a client connects, so a sleeping
+//! workstation can be brought up from the tablet.
+
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlockMethod {
+    /// Unlock the current session via `loginctl unlock-session`.
+    Loginctl,
+    /// Type the unlock secret of the configuration followed by enter, only if the session is
+    /// locked. Supported on Linux and macOS.
+    Secret,
+}
+
+fn run(program: &str, args: &[&str]) {
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => debug!("Ran {program} {}.", args.join(" ")),
+        Ok(status) => warn!("{program} {} failed: {status}.", args.join(" ")),
+        Err(err) => warn!("Failed to run {program}: {err}."),
+    }
+}
+
+pub fn wake_display() {
+    #[cfg(target_os = "linux")]
+    {
+        if std::env::var_os("DISPLAY").is_some() {
+            run("xset", &["dpms", "force", "on"]);
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        // -u declares the user as active which turns on the display
+        run("caffeinate", &["-u", "-t", "2"]);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use winapi::um::winbase::SetThreadExecutionState;
+        use winapi::um::winnt::{ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED};
+        unsafe {
+            SetThreadExecutionState(ES_DISPLAY_REQUIRED | ES_SYSTEM_REQUIRED);
+        }
+    }
+}
+
+/// Whether the current session is locked, `None` if this can not be determined.
+#[cfg(target_os = "linux")]
+fn session_locked() -> Option<bool> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".into());
+    let output = Command::new("loginctl")
+        .args(["show-session", &session, "-p", "LockedHint", "--value"])
+        .output()
+        .map_err(|err| warn!("Failed to run loginctl: {err}."))
+        .ok()?;
+    if !output.status.success() {
+        warn!("loginctl show-session failed: {}.", output.status);
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        hint => {
+            warn!("Unexpected LockedHint: {hint}.");
+            None
+        }
+    }
+}
+
+/// Whether the current session is locked, `None` if this can not be determined.
+#[cfg(target_os = "macos")]
+fn session_locked() -> Option<bool> {
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::boolean::CFBoolean;
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::string::CFString;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+    }
+
+    let dict = unsafe { CGSessionCopyCurrentDictionary() };
+    if dict.is_null() {
+        return None;
+    }
+    let dict: CFDictionary<CFString, CFType> =
+        unsafe { CFDictionary::wrap_under_create_rule(dict) };
+    // the key is only present while the screen is locked
+    Some(
+        dict.find(CFString::from_static_string("CGSSessionScreenIsLocked"))
+            .and_then(|v| v.downcast::<CFBoolean>())
+            .is_some_and(bool::from),
+    )
+}
+
+/// Whether the current session is locked, `None` if this can not be determined.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn session_locked() -> Option<bool> {
+    None
+}
+
+fn type_secret(secret: &str) {
+    use autopilot::key::{toggle, Character, Code, KeyCode};
+    for c in secret.chars() {
+        toggle(&Character(c), true, &[], 0);
+        toggle(&Character(c), false, &[], 0);
+    }
+    toggle(&Code(KeyCode::Return), true, &[], 0);
+    toggle(&Code(KeyCode::Return), false, &[], 0);
+}
+
+pub fn unlock(method: UnlockMethod, secret: Option<&str>) {
+    match method {
+        UnlockMethod::Loginctl => {
+            #[cfg(target_os = "linux")]
+            {
+                match std::env::var("XDG_SESSION_ID") {
+                    Ok(id) => run("loginctl", &["unlock-session", &id]),
+                    Err(_) => run("loginctl", &["unlock-session"]),
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            warn!("Unlocking via loginctl is only supported on Linux.");
+        }
+        UnlockMethod::Secret => {
+            let Some(secret) = secret else {
+                warn!("No unlock secret configured, set it via --unlock-secret.");
+                return;
+            };
+            // the secret must never be typed into whatever application has the focus
+            match session_locked() {
+                Some(true) => (),
+                Some(false) => {
+                    debug!("Session is not locked, not typing the unlock secret.");
+                    return;
+                }
+                None => {
+                    warn!(
+                        "Can not tell whether the session is locked, not typing the unlock secret."
+                    );
+                    return;
+                }
+            }
+            // give the lock screen some time to appear after waking the display
+            std::thread::sleep(std::time::Duration::from_millis(500));
+            // the session may have been unlocked in the meantime
+            if session_locked() == Some(true) {
+                type_secret(secret);
+            } else {
+                debug!("Session got unlocked, not typing the unlock secret.");
+            }
+        }
+    }
+}
+
+/// Wake the display and unlock the session in a separate thread.
+pub fn wake_and_unlock(unlock_method: Option<UnlockMethod>, unlock_secret: Option<String>) {
+    std::thread::spawn(move || {
+        info!("Waking display.");
+        wake_display();
+        if let Some(method) = unlock_method {
+            info!(method = ?method, "Unlocking session.");
+            unlock(method, unlock_secret.as_deref());
+        }
+    });
+}
@@ -0,0 +1,29 @@
+//! Text burned into the video sent to clients, for environments that require remotely viewed
+//! content to be marked, e.g. as confidential or with who is viewing it.
+
+use chrono::Local;
+
+use crate::frame_stamp::TextPosition;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    /// Lines are separated by newlines or `\n`, `{host}`, `{client}`, `{date}` and `{time}` are
+    /// replaced by the name of the host, the name or address of the client, the current date and
+    /// time.
+    pub text: String,
+    pub position: TextPosition,
+}
+
+impl Watermark {
+    pub fn lines(&self, host: &str, client: &str) -> Vec<String> {
+        let now = Local::now();
+        let text = self
+            .text
+            .replace("{host}", host)
+            .replace("{client}", client)
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H:%M:%S").to_string())
+            .replace("\\n", "\n");
+        text.lines().map(str::to_string).collect()
+    }
+}
@@ -2,7 +2,7 @@ use bytes::Bytes;
 use fastwebsockets::upgrade;
 use handlebars::Handlebars;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Full};
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
@@ -12,20 +12,41 @@ use serde::Serialize;
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{debug, error, info, warn};
 
-use crate::websocket::{weylus_websocket_channel, WeylusClientConfig, WeylusClientHandler};
+use crate::approval::Device;
+use crate::control::{ClientStatus, InstanceStatus, SharedClientStatus};
+use crate::discovery::{Discovery, Peer};
+use crate::file_share::serve_shared;
+use crate::frame_stats::FrameStats;
+use crate::input::file_drop::drop_files;
+use crate::invite::Invites;
+use crate::oidc::{Oidc, OidcConfig};
+use crate::protocol::{MessageOutbound, WeylusSender};
+use crate::schedule::AvailabilityWindow;
+use crate::websocket::{
+    encryption_handshake, weylus_websocket_channel, ClientRole, InstanceState, WeylusClientConfig,
+    WeylusClientHandler,
+};
+use crate::webtransport::WebTransportServer;
+
+#[cfg(not(target_os = "windows"))]
+use crate::direct_connect::{self, LinkKind};
+#[cfg(not(target_os = "windows"))]
+use pnet_datalink as datalink;
 
 #[derive(Debug)]
 pub enum WebStartUpMessage {
-    Start,
-    Error,
+    /// The web server listens on this address, it differs from the configured one if the port
+    /// was in use and a free one was chosen.
+    Start(SocketAddr),
+    Error(std::io::ErrorKind),
 }
 
 pub enum Web2UiMessage {
@@ -33,6 +54,7 @@ pub enum Web2UiMessage {
 }
 
 pub const INDEX_HTML: &str = std::include_str!("../www/templates/index.html");
+pub const KEYPAD_HTML: &str = std::include_str!("../www/templates/keypad.html");
 pub const ACCESS_HTML: &str = std::include_str!("../www/static/access_code.html");
 pub const STYLE_CSS: &str = std::include_str!("../www/static/style.css");
 pub const LIB_JS: &str = std::include_str!("../www/static/lib.js");
@@ -42,8 +64,15 @@ struct IndexTemplateContext {
     access_code: Option<String>,
     uinput_enabled: bool,
     capture_cursor_enabled: bool,
+    laser_pointer_enabled: bool,
     log_level: String,
     enable_custom_input_areas: bool,
+    peers: Vec<Peer>,
+    webtransport_port: u16,
+    webtransport_cert_hash: String,
+    files_enabled: bool,
+    default_frame_rate: u32,
+    encrypt_websocket: bool,
 }
 
 fn response_from_str(s: &str, content_type: &str) -> Response<Full<Bytes>> {
@@ -62,6 +91,34 @@ fn response_not_found() -> Response<Full<Bytes>> {
         .unwrap()
 }
 
+fn response_forbidden() -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("content-type", "text/html; charset=utf-8")
+        .body("Forbidden!".into())
+        .unwrap()
+}
+
+fn with_cookie(
+    mut response: Response<Full<Bytes>>,
+    cookie: Option<String>,
+) -> Response<Full<Bytes>> {
+    if let Some(value) = cookie.and_then(|c| c.parse().ok()) {
+        response
+            .headers_mut()
+            .insert(hyper::header::SET_COOKIE, value);
+    }
+    response
+}
+
+fn response_redirect(location: &str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header("location", location)
+        .body("".into())
+        .unwrap()
+}
+
 async fn response_from_path_or_default(
     path: Option<&PathBuf>,
     default: &str,
@@ -79,6 +136,79 @@ async fn response_from_path_or_default(
     }
 }
 
+/// Files larger than this are rejected.
+const MAX_UPLOAD_SIZE: usize = 512 * 1024 * 1024;
+
+/// Path in dir for a file of the given name that does not exist yet, the name is reduced to its
+/// last component so files can not be written outside of dir.
+fn upload_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name).file_name()?;
+    let path = dir.join(name);
+    if !path.exists() {
+        return Some(path);
+    }
+    let stem = Path::new(name).file_stem()?.to_string_lossy();
+    let extension = Path::new(name)
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..1000)
+        .map(|i| dir.join(format!("{stem} ({i}){extension}")))
+        .find(|path| !path.exists())
+}
+
+/// Save the file in the request's body to the upload directory and drop it onto the window under
+/// the pointer.
+async fn upload(
+    addr: SocketAddr,
+    req: Request<Incoming>,
+    role: Option<ClientRole>,
+    upload_dir: Option<&PathBuf>,
+) -> Response<Full<Bytes>> {
+    let Some(upload_dir) = upload_dir else {
+        return response_not_found();
+    };
+    if role != Some(ClientRole::Controller) {
+        return response_forbidden();
+    }
+    let name = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .find(|(k, _)| k == "name")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default();
+    let Some(path) = upload_path(upload_dir, &name) else {
+        warn!(address = ?addr, name, "Invalid name of uploaded file.");
+        return response_forbidden();
+    };
+    let data = match Limited::new(req.into_body(), MAX_UPLOAD_SIZE)
+        .collect()
+        .await
+    {
+        Ok(data) => data.to_bytes(),
+        Err(err) => {
+            warn!(address = ?addr, "Failed to receive uploaded file: {err}.");
+            return response_forbidden();
+        }
+    };
+    if let Err(err) = tokio::fs::create_dir_all(upload_dir).await {
+        error!("Failed to create upload directory: {err}.");
+        return response_forbidden();
+    }
+    if let Err(err) = tokio::fs::write(&path, &data).await {
+        error!("Failed to save uploaded file: {err}.");
+        return response_forbidden();
+    }
+    info!(address = ?addr, path = %path.display(), "File uploaded.");
+    let path = std::path::absolute(&path).unwrap_or(path);
+    match tokio::task::spawn_blocking(move || drop_files(&[path])).await {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) => {
+            warn!("Failed to drop uploaded file onto the window under the pointer: {err}.")
+        }
+        Err(err) => error!("Failed to drop uploaded file: {err}."),
+    }
+    response_from_str("", "text/plain")
+}
+
 async fn serve(
     addr: SocketAddr,
     mut req: Request<Incoming>,
@@ -89,9 +219,16 @@ async fn serve(
     notify_disconnect: Arc<tokio::sync::Notify>,
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, hyper::Error> {
     debug!("Got request: {:?}", req);
-    let mut authed = false;
-    if let Some(access_code) = &context.web_config.access_code {
-        if req.method() == Method::GET && (req.uri().path() == "/" || req.uri().path() == "/ws") {
+    // the configuration may be reloaded at any time, use a consistent snapshot for this request
+    let web_config = context.web_config.borrow().clone();
+    let oidc = context.oidc.read().unwrap().clone();
+    let mut role = None;
+    // with encryption the access code must not be sent in the clear, clients prove they know it
+    // during the handshake on the websocket instead
+    let encrypted = web_config.encrypt_websocket && web_config.access_code.is_some();
+    if let Some(access_code) = &web_config.access_code {
+        let path = req.uri().path();
+        if !encrypted && (matches!(path, "/" | "/ws" | "/upload") || path.starts_with("/files/")) {
             use url::form_urlencoded;
             if let Some(query) = req.uri().query() {
                 let params = form_urlencoded::parse(query.as_bytes())
@@ -99,38 +236,120 @@ async fn serve(
                     .collect::<HashMap<String, String>>();
                 if let Some(code) = params.get("access_code") {
                     if code == access_code {
-                        authed = true;
+                        role = Some(ClientRole::Controller);
                         debug!(address = ?addr, "Web-Client authenticated.");
                     }
                 }
             }
         }
+    } else if oidc.is_none() {
+        role = Some(ClientRole::Controller);
+    }
+    if role.is_none() {
+        if let Some(oidc) = oidc.as_ref() {
+            role = oidc.session_role(req.headers());
+            if role.is_some() {
+                debug!(address = ?addr, role = ?role, "Web-Client authenticated via session.");
+            }
+        }
+    }
+    let mut invited = false;
+    if role.is_none() {
+        role = context.invites.session_role(req.headers());
+        if role.is_some() {
+            invited = true;
+            debug!(address = ?addr, role = ?role, "Web-Client authenticated via invite.");
+        }
+    }
+    // guests must not learn the access code from the pages they are served
+    let access_code = if invited || encrypted {
+        None
     } else {
-        authed = true;
+        web_config.access_code.clone()
+    };
+    let device = Device::from_request(req.headers(), addr);
+    let device_approved = !web_config.approve_new_clients
+        || (web_config.approve_loopback && addr.ip().is_loopback())
+        || device.is_approved();
+    // uploads and shared files are left to approved devices, the websocket asks for approval
+    let approved_role = role.filter(|_| device_approved);
+    if req.method() == Method::POST && req.uri().path() == "/upload" {
+        return Ok(
+            upload(addr, req, approved_role, web_config.upload_dir.as_ref())
+                .await
+                .map(|r| r.boxed()),
+        );
     }
     if req.method() != Method::GET {
         return Ok(response_not_found().map(|r| r.boxed()));
     }
+    if let Some(path) = req.uri().path().strip_prefix("/files/") {
+        let Some(shared_dir) = web_config.shared_dir.as_ref() else {
+            return Ok(response_not_found().map(|r| r.boxed()));
+        };
+        if approved_role.is_none() {
+            return Ok(response_forbidden().map(|r| r.boxed()));
+        }
+        return Ok(serve_shared(shared_dir, path, access_code.as_deref()).await);
+    }
     match req.uri().path() {
-        "/" => {
-            if !authed {
-                return Ok(response_from_path_or_default(
-                    context.web_config.custom_access_html.as_ref(),
-                    ACCESS_HTML,
-                    "text/html; charset=utf-8",
+        "/" | "/keypad" => {
+            if role.is_none() && oidc.is_some() {
+                return Ok(response_redirect("/oidc/login").map(|r| r.boxed()));
+            }
+            // the device is recognized by this cookie when it asks for approval
+            let device_cookie = web_config
+                .approve_new_clients
+                .then(|| crate::approval::device_cookie(req.headers()))
+                .flatten();
+            // the page asks for the access code to encrypt the websocket with
+            if role.is_none() && !encrypted {
+                return Ok(with_cookie(
+                    response_from_path_or_default(
+                        web_config.custom_access_html.as_ref(),
+                        ACCESS_HTML,
+                        "text/html; charset=utf-8",
+                    )
+                    .await,
+                    device_cookie,
                 )
-                .await
                 .map(|r| r.boxed()));
             }
             let config = IndexTemplateContext {
-                access_code: context.web_config.access_code.clone(),
+                access_code,
                 uinput_enabled: cfg!(target_os = "linux"),
                 capture_cursor_enabled: cfg!(not(target_os = "windows")),
+                laser_pointer_enabled: role == Some(ClientRole::Viewer)
+                    && context.weylus_client_config.borrow().laser_pointers,
                 log_level: crate::log::get_log_level().to_string(),
-                enable_custom_input_areas: context.web_config.enable_custom_input_areas,
+                enable_custom_input_areas: web_config.enable_custom_input_areas,
+                peers: context
+                    .discovery
+                    .as_ref()
+                    .map(|d| d.peers())
+                    .unwrap_or_default(),
+                webtransport_port: context.webtransport.as_ref().map(|wt| wt.port).unwrap_or(0),
+                webtransport_cert_hash: context
+                    .webtransport
+                    .as_ref()
+                    .map(|wt| wt.cert_hash())
+                    .unwrap_or_default(),
+                // the file pages require authentication by other means than encryption
+                files_enabled: web_config.shared_dir.is_some() && role.is_some(),
+                default_frame_rate: crate::encoder_probe::default_frame_rate(),
+                encrypt_websocket: encrypted && role.is_none(),
             };
 
-            let html = if let Some(path) = context.web_config.custom_index_html.as_ref() {
+            let template = if req.uri().path() == "/keypad" {
+                "keypad"
+            } else {
+                "index"
+            };
+            let custom_html = web_config
+                .custom_index_html
+                .as_ref()
+                .filter(|_| template == "index");
+            let html = if let Some(path) = custom_html {
                 let mut reg = Handlebars::new();
                 if let Err(err) = reg.register_template_file("index", path) {
                     warn!("Failed to register template from path: {}", err);
@@ -139,13 +358,15 @@ async fn serve(
                     reg.render("index", &config)
                 }
             } else {
-                context.templates.render("index", &config)
+                context.templates.render(template, &config)
             };
 
             match html {
-                Ok(html) => {
-                    Ok(response_from_str(&html, "text/html; charset=utf-8").map(|r| r.boxed()))
-                }
+                Ok(html) => Ok(with_cookie(
+                    response_from_str(&html, "text/html; charset=utf-8"),
+                    device_cookie,
+                )
+                .map(|r| r.boxed())),
                 Err(err) => {
                     error!("Failed to render index template: {}", err);
                     Ok(response_not_found().map(|r| r.boxed()))
@@ -153,23 +374,133 @@ async fn serve(
             }
         }
         "/ws" => {
-            if !authed {
+            let params = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            // clients authenticated otherwise, like invited guests, use the websocket unencrypted
+            let handshake_code = match (role, params.get("encrypted")) {
+                (None, Some(_)) if encrypted => web_config.access_code.clone(),
+                _ => None,
+            };
+            let Some(role) = role.or(handshake_code.as_ref().map(|_| ClientRole::Controller))
+            else {
                 return Ok(Response::builder()
                     .status(StatusCode::UNAUTHORIZED)
                     .body("unauthorized".to_string().boxed())
                     .unwrap());
-            }
+            };
+
+            let compression = params
+                .get("compression")
+                .is_some_and(|c| c == "deflate-raw");
 
             let (response, fut) = upgrade::upgrade(&mut req).unwrap();
             num_clients.fetch_add(1, Ordering::Relaxed);
 
             let config = context.weylus_client_config.clone();
+            let instance = context.instance.clone();
+            let status: SharedClientStatus = Arc::new(Mutex::new(ClientStatus {
+                address: addr,
+                role,
+                name: None,
+                capturable: None,
+                encoder: None,
+                presentation: config.borrow().presentation_mode,
+                input_rate: 0.0,
+                dropped_input_events: 0,
+                video: FrameStats::default(),
+                session_video: FrameStats::default(),
+                video_bytes: 0,
+                input_backend: config.borrow().input_backend,
+                input_device: None,
+                broadcasting: false,
+                watching_broadcast: false,
+                device: None,
+            }));
+            let clients = context.clients.clone();
+            // devices waiting for approval are not listed as clients yet
+            let approval = (!device_approved).then(|| {
+                let user_agent = req
+                    .headers()
+                    .get(hyper::header::USER_AGENT)
+                    .and_then(|h| h.to_str().ok())
+                    .map(str::to_string);
+                (device, user_agent, web_config.instance_name.clone())
+            });
+            let webtransport_clients = context.webtransport.as_ref().map(|wt| wt.clients.clone());
+            let invites = invited.then(|| context.invites.clone());
             tokio::spawn(async move {
                 match fut.await {
-                    Ok(ws) => {
-                        let (sender, receiver) =
-                            weylus_websocket_channel(ws, semaphore_websocket_shutdown);
+                    Ok(mut ws) => {
+                        // the client counts as connected until the websocket is closed, this
+                        // way the web server waits for clients acknowledging the shutdown
+                        let on_close = move || {
+                            num_clients.fetch_sub(1, Ordering::Relaxed);
+                            notify_disconnect.notify_waiters();
+                        };
+                        let encryption = match handshake_code {
+                            Some(code) => match encryption_handshake(&mut ws, &code).await {
+                                Ok(keys) => Some(keys),
+                                Err(err) => {
+                                    warn!(address = ?addr, "Encryption handshake failed: {err}");
+                                    on_close();
+                                    return;
+                                }
+                            },
+                            None => None,
+                        };
+                        if let Some((device, user_agent, instance)) = approval {
+                            if !device.request_approval(addr, user_agent, &instance).await {
+                                warn!(address = ?addr, "Device was denied access.");
+                                on_close();
+                                return;
+                            }
+                        }
+                        {
+                            let mut clients = clients.lock().unwrap();
+                            clients.retain(|c| c.strong_count() > 0);
+                            clients.push(Arc::downgrade(&status));
+                        }
+                        let (mut sender, receiver) = weylus_websocket_channel(
+                            ws,
+                            semaphore_websocket_shutdown,
+                            compression,
+                            encryption,
+                            on_close,
+                        );
+                        let webtransport_token = webtransport_clients.as_ref().map(|clients| {
+                            let token: u128 = rand::random();
+                            let token = format!("{token:x}");
+                            clients
+                                .lock()
+                                .unwrap()
+                                .insert(token.clone(), sender.clone());
+                            token
+                        });
+                        // guests are disconnected once their invites are revoked, the watch ends
+                        // as soon as the client handler finishes and drops client_done
+                        let client_done = invites.map(|invites| {
+                            let (client_done, finished) = tokio::sync::oneshot::channel::<()>();
+                            let sender = sender.clone();
+                            tokio::spawn(async move {
+                                tokio::select! {
+                                    _ = invites.revoked() => {
+                                        info!(address = ?addr, "Invite revoked, disconnecting guest.");
+                                        sender.revoke().await;
+                                    }
+                                    _ = finished => (),
+                                }
+                            });
+                            client_done
+                        });
                         std::thread::spawn(move || {
+                            if let Some(token) = webtransport_token.as_ref() {
+                                if let Err(err) = sender
+                                    .send_message(MessageOutbound::WebTransportToken(token.clone()))
+                                {
+                                    warn!("Failed to send WebTransport token: {err}.");
+                                }
+                            }
                             let client = WeylusClientHandler::new(
                                 sender,
                                 receiver,
@@ -183,10 +514,17 @@ async fn serve(
                                     }
                                 },
                                 config,
+                                role,
+                                status,
+                                instance,
                             );
                             client.run();
-                            num_clients.fetch_sub(1, Ordering::Relaxed);
-                            notify_disconnect.notify_waiters();
+                            drop(client_done);
+                            if let (Some(clients), Some(token)) =
+                                (webtransport_clients, webtransport_token)
+                            {
+                                clients.lock().unwrap().remove(&token);
+                            }
                         });
                     }
                     Err(err) => {
@@ -199,15 +537,92 @@ async fn serve(
 
             Ok(response.map(|r| r.boxed()))
         }
+        "/invite" => {
+            let params = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            match params.get("token").and_then(|t| context.invites.redeem(t)) {
+                Some(cookie) => {
+                    info!(address = ?addr, "Invite redeemed.");
+                    Ok(Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header("location", "/")
+                        .header("set-cookie", cookie)
+                        .body(Full::from(""))
+                        .unwrap()
+                        .map(|r| r.boxed()))
+                }
+                None => {
+                    warn!(address = ?addr, "Invalid, used up or expired invite.");
+                    Ok(response_forbidden().map(|r| r.boxed()))
+                }
+            }
+        }
+        "/oidc/login" => {
+            let Some(oidc) = oidc.clone() else {
+                return Ok(response_not_found().map(|r| r.boxed()));
+            };
+            match tokio::task::spawn_blocking(move || oidc.authorization_url()).await {
+                Ok(Ok((url, cookie))) => {
+                    Ok(with_cookie(response_redirect(&url), Some(cookie)).map(|r| r.boxed()))
+                }
+                Ok(Err(err)) => {
+                    error!("Failed to start OIDC login: {err}");
+                    Ok(response_forbidden().map(|r| r.boxed()))
+                }
+                Err(err) => {
+                    error!("Failed to start OIDC login: {err}");
+                    Ok(response_forbidden().map(|r| r.boxed()))
+                }
+            }
+        }
+        "/oidc/callback" => {
+            let Some(oidc) = oidc.clone() else {
+                return Ok(response_not_found().map(|r| r.boxed()));
+            };
+            let params = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            let (Some(code), Some(state)) = (params.get("code"), params.get("state")) else {
+                warn!(address = ?addr, "OIDC callback without code or state.");
+                return Ok(response_forbidden().map(|r| r.boxed()));
+            };
+            let (code, state) = (code.clone(), state.clone());
+            let state_cookie =
+                crate::oidc::cookie(req.headers(), crate::oidc::STATE_COOKIE).map(str::to_string);
+            let login = oidc.clone();
+            match tokio::task::spawn_blocking(move || {
+                login.finish_login(&code, &state, state_cookie.as_deref())
+            })
+            .await
+            {
+                Ok(Ok(session)) => Ok(Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header("location", "/")
+                    .header("set-cookie", oidc.session_cookie(&session))
+                    .header("set-cookie", Oidc::clear_state_cookie())
+                    .body(Full::from(""))
+                    .unwrap()
+                    .map(|r| r.boxed())),
+                Ok(Err(err)) => {
+                    warn!(address = ?addr, "OIDC login failed: {err}");
+                    Ok(response_forbidden().map(|r| r.boxed()))
+                }
+                Err(err) => {
+                    error!("OIDC login failed: {err}");
+                    Ok(response_forbidden().map(|r| r.boxed()))
+                }
+            }
+        }
         "/style.css" => Ok(response_from_path_or_default(
-            context.web_config.custom_style_css.as_ref(),
+            web_config.custom_style_css.as_ref(),
             STYLE_CSS,
             "text/css; charset=utf-8",
         )
         .await
         .map(|r| r.boxed())),
         "/lib.js" => Ok(response_from_path_or_default(
-            context.web_config.custom_lib_js.as_ref(),
+            web_config.custom_lib_js.as_ref(),
             LIB_JS,
             "text/javascript; charset=utf-8",
         )
@@ -217,8 +632,9 @@ async fn serve(
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct WebServerConfig {
+    pub instance_name: String,
     pub bind_addr: SocketAddr,
     pub access_code: Option<String>,
     pub custom_index_html: Option<PathBuf>,
@@ -226,65 +642,325 @@ pub struct WebServerConfig {
     pub custom_style_css: Option<PathBuf>,
     pub custom_lib_js: Option<PathBuf>,
     pub enable_custom_input_areas: bool,
+    pub enable_mdns: bool,
+    pub oidc: Option<OidcConfig>,
+    pub webtransport_port: Option<u16>,
+    /// Listen on the next free ports if the configured ones are in use.
+    pub find_free_port: bool,
+    pub encrypt_websocket: bool,
+    /// Devices connecting for the first time have to be approved by the host.
+    pub approve_new_clients: bool,
+    /// Connections from this computer need no approval.
+    pub approve_loopback: bool,
+    pub upload_dir: Option<PathBuf>,
+    pub shared_dir: Option<PathBuf>,
+    pub schedule: Vec<AvailabilityWindow>,
+    pub schedule_override: Option<bool>,
+}
+
+impl WebServerConfig {
+    /// Whether new connections are accepted right now.
+    fn accepts_connections(&self) -> bool {
+        self.schedule_override
+            .unwrap_or_else(|| crate::schedule::is_open(&self.schedule))
+    }
 }
 
 struct Context<'a> {
-    web_config: WebServerConfig,
-    weylus_client_config: WeylusClientConfig,
+    web_config: watch::Receiver<WebServerConfig>,
+    weylus_client_config: watch::Receiver<WeylusClientConfig>,
     templates: Handlebars<'a>,
+    discovery: Option<Discovery>,
+    oidc: RwLock<Option<Arc<Oidc>>>,
+    webtransport: Option<Arc<WebTransportServer>>,
+    clients: Arc<Mutex<Vec<Weak<Mutex<ClientStatus>>>>>,
+    invites: Arc<Invites>,
+    instance: InstanceState,
+    /// The listener is closed as the current time is outside of the schedule or the GUI closed it.
+    closed_by_schedule: AtomicBool,
+}
+
+impl Context<'_> {
+    fn clients(&self) -> Vec<SharedClientStatus> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|c| c.upgrade())
+            .collect()
+    }
+}
+
+/// Url browsers can connect to, if bound to all interfaces try to guess the address of this
+/// computer.
+pub fn server_url(addr: SocketAddr) -> String {
+    #[allow(unused_mut)]
+    let mut web_sock = addr;
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        if web_sock.ip().is_unspecified() {
+            // try to guess an ip
+            let mut ips = Vec::<(Option<LinkKind>, std::net::IpAddr)>::new();
+            for iface in datalink::interfaces()
+                .iter()
+                .filter(|iface| iface.is_up() && !iface.is_loopback())
+            {
+                for ipnetw in &iface.ips {
+                    if (ipnetw.is_ipv4() && web_sock.ip().is_ipv4())
+                        || (ipnetw.is_ipv6() && web_sock.ip().is_ipv6())
+                    {
+                        // filtering ipv6 unicast requires nightly or more fiddling,
+                        // lets wait for nightlies to stabilize...
+                        let kind = direct_connect::classify(&iface.name, ipnetw.ip());
+                        ips.push((kind, ipnetw.ip()))
+                    }
+                }
+            }
+            // the tablet is sure to reach this computer via a direct link
+            ips.sort_by_key(|(kind, _)| (kind.is_none(), *kind));
+            if let Some((kind, ip)) = ips.first() {
+                web_sock.set_ip(*ip);
+                if let Some(kind) = kind {
+                    info!("Found a direct connection via {kind}, preferring its address.");
+                }
+            }
+            if ips.len() > 1 {
+                info!("Found more than one IP address for browsers to connect to,");
+                info!("other urls are:");
+                for (kind, ip) in &ips[1..] {
+                    let url = format!("http://{}", SocketAddr::new(*ip, addr.port()));
+                    match kind {
+                        Some(kind) => info!("{url} ({kind})"),
+                        None => info!("{url}"),
+                    }
+                }
+            }
+        }
+    }
+    #[cfg(target_os = "windows")]
+    if web_sock.ip().is_unspecified() {
+        return "http://<your ip address>".to_string();
+    }
+    format!("http://{}", web_sock)
 }
 
 pub fn run(
     sender_ui: tokio::sync::mpsc::Sender<Web2UiMessage>,
     sender_startup: oneshot::Sender<WebStartUpMessage>,
     notify_shutdown: Arc<tokio::sync::Notify>,
-    web_server_config: WebServerConfig,
-    weylus_client_config: WeylusClientConfig,
+    web_server_config: watch::Receiver<WebServerConfig>,
+    weylus_client_config: watch::Receiver<WeylusClientConfig>,
 ) -> std::thread::JoinHandle<()> {
     let mut templates = Handlebars::new();
     templates
         .register_template_string("index", INDEX_HTML)
         .unwrap();
+    templates
+        .register_template_string("keypad", KEYPAD_HTML)
+        .unwrap();
 
+    let oidc = web_server_config
+        .borrow()
+        .oidc
+        .clone()
+        .map(|c| Arc::new(Oidc::new(c)));
     let context = Context {
         web_config: web_server_config,
         weylus_client_config,
         templates,
+        discovery: None,
+        oidc: RwLock::new(oidc),
+        webtransport: None,
+        clients: Arc::new(Mutex::new(Vec::new())),
+        invites: Arc::new(Invites::default()),
+        instance: InstanceState::default(),
+        closed_by_schedule: AtomicBool::new(false),
     };
     std::thread::spawn(move || run_server(context, sender_ui, sender_startup, notify_shutdown))
 }
 
+/// Apply changes to the configuration that do not require restarting the web server.
+async fn apply_config_changes(context: Arc<Context<'_>>, mut current: WebServerConfig) {
+    let mut receiver = context.web_config.clone();
+    while receiver.changed().await.is_ok() {
+        let config = receiver.borrow_and_update().clone();
+        if config.access_code != current.access_code {
+            info!("Access code changed, it applies to new connections.");
+        }
+        if config.oidc != current.oidc {
+            info!("OIDC configuration changed, users need to log in again.");
+            *context.oidc.write().unwrap() = config.oidc.clone().map(|c| Arc::new(Oidc::new(c)));
+        }
+        if config.bind_addr != current.bind_addr
+            || config.enable_mdns != current.enable_mdns
+            || config.webtransport_port != current.webtransport_port
+        {
+            warn!(
+                "Changing the bind address, port, mDNS or WebTransport settings requires \
+                restarting Weylus."
+            );
+        }
+        current = config;
+    }
+}
+
+/// How often to check whether the schedule opens or closes the web server.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of ports following a port in use that are tried with `--find-free-port`.
+const PORT_SCAN_RANGE: u16 = 100;
+
+/// The ports to try binding to, the configured port first.
+fn candidate_ports(port: u16, find_free_port: bool) -> impl Iterator<Item = u16> {
+    let range = if find_free_port { PORT_SCAN_RANGE } else { 0 };
+    (0..=range).map_while(move |i| port.checked_add(i))
+}
+
+/// Bind the web server to the configured address or, if the port is in use, the next free port.
+async fn bind_listener(addr: SocketAddr, find_free_port: bool) -> std::io::Result<TcpListener> {
+    let mut first_err = None;
+    for port in candidate_ports(addr.port(), find_free_port) {
+        match TcpListener::bind(SocketAddr::new(addr.ip(), port)).await {
+            Ok(listener) => {
+                if port != addr.port() {
+                    warn!(
+                        "Port {} is in use, listening on port {port} instead.",
+                        addr.port()
+                    );
+                }
+                return Ok(listener);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                first_err.get_or_insert(err);
+            }
+            Err(err) => return Err(first_err.unwrap_or(err)),
+        }
+    }
+    Err(first_err.unwrap_or_else(|| std::io::ErrorKind::AddrInUse.into()))
+}
+
 #[tokio::main]
 async fn run_server(
-    context: Context<'static>,
+    mut context: Context<'static>,
     sender_ui: tokio::sync::mpsc::Sender<Web2UiMessage>,
     sender_startup: oneshot::Sender<WebStartUpMessage>,
     notify_shutdown: Arc<tokio::sync::Notify>,
 ) {
-    let addr = context.web_config.bind_addr;
+    let web_config = context.web_config.borrow().clone();
 
-    let listener = match TcpListener::bind(addr).await {
+    let listener = match bind_listener(web_config.bind_addr, web_config.find_free_port).await {
         Ok(listener) => listener,
         Err(err) => {
             error!("Failed to bind to socket: {err}.");
-            sender_startup.send(WebStartUpMessage::Error).unwrap();
+            sender_startup
+                .send(WebStartUpMessage::Error(err.kind()))
+                .unwrap();
             return;
         }
     };
+    // the port actually listened on, it is kept when reopening after the schedule closed it
+    let addr = listener.local_addr().unwrap_or(web_config.bind_addr);
 
-    sender_startup.send(WebStartUpMessage::Start).unwrap();
+    sender_startup.send(WebStartUpMessage::Start(addr)).unwrap();
 
-    let context = Arc::new(context);
+    if web_config.enable_mdns {
+        match Discovery::new(addr.port()) {
+            Ok(discovery) => context.discovery = Some(discovery),
+            Err(err) => warn!("Failed to announce Weylus via mDNS: {err}."),
+        }
+    }
 
     let broadcast_shutdown = Arc::new(tokio::sync::Notify::new());
 
+    if let Some(port) = web_config.webtransport_port {
+        let mut server = WebTransportServer::new(SocketAddr::new(addr.ip(), port));
+        for port in candidate_ports(port, web_config.find_free_port).skip(1) {
+            if server.is_ok() {
+                break;
+            }
+            server = WebTransportServer::new(SocketAddr::new(addr.ip(), port));
+        }
+        match server {
+            Ok(server) => {
+                if server.port != port {
+                    warn!(
+                        "Port {port} can not be used, WebTransport listens on port {} instead.",
+                        server.port
+                    );
+                }
+                let server = Arc::new(server);
+                context.webtransport = Some(server.clone());
+                let broadcast_shutdown = broadcast_shutdown.clone();
+                tokio::spawn(async move { server.run(broadcast_shutdown).await });
+            }
+            Err(err) => warn!("Failed to start WebTransport server: {err}."),
+        }
+    }
+
+    let context = Arc::new(context);
+
+    {
+        let context = context.clone();
+        tokio::spawn(async move { apply_config_changes(context, web_config).await });
+    }
+
+    let _instance_guard = {
+        let status_context = context.clone();
+        let clients_context = context.clone();
+        crate::control::register_instance(
+            move || InstanceStatus {
+                name: status_context.web_config.borrow().instance_name.clone(),
+                bind_address: addr,
+                webtransport_address: status_context
+                    .webtransport
+                    .as_ref()
+                    .map(|wt| SocketAddr::new(addr.ip(), wt.port)),
+                clients: status_context
+                    .clients()
+                    .iter()
+                    .map(|c| c.lock().unwrap().clone())
+                    .collect(),
+                closed_by_schedule: status_context.closed_by_schedule.load(Ordering::Relaxed),
+            },
+            move || clients_context.clients(),
+            context.invites.clone(),
+        )
+    };
+
     let num_clients = Arc::new(AtomicUsize::new(0));
     let notify_disconnect = Arc::new(tokio::sync::Notify::new());
     let semaphore_websocket_shutdown = Arc::new(tokio::sync::Semaphore::new(0));
 
+    // the listener is bound at startup in any case to report errors right away, it is closed
+    // below if the schedule says so
+    let mut listener = Some(listener);
+    let mut web_config_changes = context.web_config.clone();
+
     loop {
+        let open = web_config_changes.borrow_and_update().accepts_connections();
+        if open && listener.is_none() {
+            match TcpListener::bind(addr).await {
+                Ok(l) => {
+                    info!("Accepting connections again.");
+                    listener = Some(l);
+                }
+                Err(err) => warn!("Failed to bind to socket, retrying: {err}."),
+            }
+        } else if !open && listener.is_some() {
+            info!("Not accepting new connections, closed by the schedule.");
+            listener = None;
+        }
+        context.closed_by_schedule.store(!open, Ordering::Relaxed);
+
+        let accept = async {
+            match &listener {
+                Some(listener) => listener.accept().await,
+                None => std::future::pending().await,
+            }
+        };
         let (tcp, remote_address) = tokio::select! {
-            res = listener.accept() => {
+            res = accept => {
                 match res {
                     Ok(conn) => conn,
                     Err(err) => {
@@ -293,6 +969,8 @@ async fn run_server(
                     }
                 }
             },
+            _ = tokio::time::sleep(SCHEDULE_CHECK_INTERVAL) => continue,
+            Ok(()) = web_config_changes.changed() => continue,
             _ = notify_shutdown.notified() => {
                 info!("Webserver is shutting down.");
                 broadcast_shutdown.notify_waiters();
@@ -1,23 +1,86 @@
-use fastwebsockets::{FragmentCollectorRead, Frame, OpCode, WebSocket, WebSocketError};
+use base64::Engine;
+use fastwebsockets::{
+    FragmentCollectorRead, Frame, OpCode, WebSocket, WebSocketError, WebSocketWrite,
+};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use hyper::upgrade::Upgraded;
 use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{mpsc, Arc};
 use std::thread::{spawn, JoinHandle};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::channel;
-use tracing::{error, trace, warn};
+use tokio::sync::watch;
+use tracing::{debug, debug_span, error, field, info, trace, warn};
 
-use crate::capturable::{get_capturables, Capturable, Recorder};
-use crate::input::device::{InputDevice, InputDeviceType};
+use crate::annotations::{Annotations, Target};
+use crate::bandwidth_cap::{lower_cap, BandwidthCap};
+use crate::battery::{self, BatteryWatch};
+use crate::broadcast::{Broadcast, BroadcastViewer};
+use crate::capturable::geometry_check::{CheckedCapturable, GeometryCheck};
+use crate::capturable::hotplug;
+use crate::capturable::region::{CaptureRegion, RegionCapturable};
+use crate::capturable::rotation::{RotatedCapturable, Rotation};
+#[cfg(target_os = "linux")]
+use crate::capturable::x11::X11WindowOptions;
+use crate::capturable::{get_capturables, Capturable, CursorTracker, Recorder};
+use crate::client_settings::{self, ClientSettings};
+use crate::hooks::{self, Hook, HookEvent};
+use crate::input::cursor_hider::CursorHider;
+use crate::input::device::{
+    ActivationPolicy, EraserPolicy, InputBackend, InputDevice, InputDeviceType,
+};
+use crate::input::dwell_click::{DwellClick, DwellClickConfig};
+use crate::input::highlight::PointerHighlight;
+use crate::input::indicator::{ControlGuard, IndicatorConfig};
+use crate::input::keypad::Keypad;
+use crate::input::pointer_filter::{FilteredEvent, PointerFilter, PointerFilterConfig};
+use crate::input::precision::{PrecisionConfig, PrecisionMapping};
+use crate::input::prediction::Predictor;
+use crate::input::profiles::{InputProfile, ProfileSwitcher};
+use crate::input::rate_limit::{InputRateLimiter, RateLimitConfig};
+use crate::input::sensors::{SensorConfig, SensorMapping, SensorOutput};
+use crate::input::shortcuts::ShortcutSet;
+use crate::input::switch_control::{SwitchConfig, SwitchControl, SwitchOutput};
+use crate::input::text_filter::{FilteredKey, TextInjectionFilter, TextInjectionPolicy};
+use crate::input::viewport::ViewportMapping;
+use crate::network_quality::{NetworkQuality, QualityOutput};
 use crate::protocol::{
-    ClientConfiguration, KeyboardEvent, MessageInbound, MessageOutbound, PointerEvent,
-    WeylusReceiver, WeylusSender, WheelEvent,
+    Button, CapturableLayout, ClientConfiguration, ClientViewport, CursorPosition, CursorShape,
+    DeviceOrientation, DeviceStatus, KeyboardEvent, KeyboardEventType, MessageInbound,
+    MessageOutbound, PointerEvent, PointerEventType, PointerType, ResolutionLimit, WeylusReceiver,
+    WeylusSender, WheelEvent,
 };
 
 use crate::cerror::CErrorCode;
+use crate::control::SharedClientStatus;
+use crate::encoder_probe;
+use crate::encryption::{self, MessageKind, Opener, Sealer};
+use crate::frame_stamp::{self, TextOverlay, TextPosition};
+use crate::frame_stats::FrameCounter;
+use crate::h264_level::H264Level;
+use crate::notifications::{self, Subscription};
+use crate::power::PowerAction;
+use crate::preroll::Preroll;
+use crate::protocol_replay::{self, ClientRecorder};
+use crate::session_summary::SessionRecorder;
+use crate::thumbnail::thumbnail;
 use crate::video::{EncoderOptions, VideoEncoder};
+use crate::wake::UnlockMethod;
+use crate::watermark::Watermark;
+use crate::webtransport::WebTransportVideo;
+
+/// Width of the thumbnails of capturables sent to clients.
+const THUMBNAIL_WIDTH: usize = 240;
+
+/// How often the list of capturables is updated at most, if monitors or windows changed.
+const HOTPLUG_INTERVAL: Duration = Duration::from_millis(500);
 
 struct VideoConfig {
     capturable: Box<dyn Capturable>,
@@ -25,6 +88,11 @@ struct VideoConfig {
     max_width: usize,
     max_height: usize,
     frame_rate: f64,
+    frame_stamp: bool,
+    /// Cap chosen by the client in kbit/s, 0 for none.
+    bandwidth_cap: u32,
+    /// Hardware encoders are not tried, set in the settings of the client.
+    software_encoding: bool,
 }
 
 enum VideoCommands {
@@ -32,6 +100,7 @@ enum VideoCommands {
     Pause,
     Resume,
     Restart,
+    RequestKeyframe,
 }
 
 fn send_message<S>(sender: &mut S, message: MessageOutbound)
@@ -55,14 +124,204 @@ pub struct WeylusClientHandler<S, R, FnUInput> {
     capture_cursor: bool,
     client_name: Option<String>,
     video_thread: JoinHandle<()>,
+    role: ClientRole,
+    cursor_tracking_stop: Option<Arc<AtomicBool>>,
+    buffer_control: BufferControl,
+    status: SharedClientStatus,
+    cursor_hider: Option<CursorHider>,
+    pointer_filter: PointerFilter,
+    eraser_active: bool,
+    predictor: Predictor,
+    text_filter: TextInjectionFilter,
+    capturable: Option<Box<dyn Capturable>>,
+    /// The video is rotated, set along with the capturable.
+    rotation: Option<Rotation>,
+    highlight: Option<PointerHighlight>,
+    precision: PrecisionMapping,
+    profiles: ProfileSwitcher,
+    switch_control: SwitchControl,
+    dwell_click: DwellClick,
+    sensors: SensorMapping,
+    viewport: ViewportMapping,
+    battery_watch: BatteryWatch,
+    session: SessionRecorder,
+    /// Backend the input device was chosen for, see [`ClientStatus::input_backend`](crate::control::ClientStatus::input_backend).
+    input_backend: InputBackend,
+    /// Created once the client is first tilted in joystick mode.
+    #[cfg(target_os = "linux")]
+    joystick: Option<crate::input::uinput_device::UInputJoystick>,
+    /// Mirrors notifications of the host to the client while set.
+    notifications: Option<Subscription>,
+    /// Sets `capturables_changed` once the client got the list of capturables.
+    hotplug: Option<hotplug::Subscription>,
+    capturables_changed: Arc<AtomicBool>,
+    /// Shows the control indicator while the client has control.
+    control_guard: Option<ControlGuard>,
+    /// The hooks of the client getting control have been run.
+    control_granted: bool,
+    rate_limiter: InputRateLimiter,
+    /// The configuration last sent by the client.
+    client_configuration: Option<ClientConfiguration>,
+    /// Stored settings of the client and its id, see [`client_settings`].
+    client_settings: Option<(String, ClientSettings)>,
+    geometry_check: Arc<GeometryCheck>,
+    network_quality: NetworkQuality,
+    /// Set in broadcast mode.
+    broadcast: Option<Arc<Broadcast>>,
+    /// Keeps a viewer watching the broadcast.
+    broadcast_viewer: Option<BroadcastViewer>,
+    /// Set unless laser pointers are disabled.
+    annotations: Option<Arc<Annotations>>,
+    /// The client enabled its laser pointer, only viewers have one.
+    laser_pointer: bool,
+    /// Set while recording with `--record-protocol`.
+    recorder: Option<ClientRecorder>,
+}
+
+/// Decides how much video the client should buffer, based on the preference of the user and the
+/// fluctuation of the buffer levels reported by the client.
+struct BufferControl {
+    /// Buffered at least, even if the user asks for the lowest latency.
+    min_target: f64,
+    smoothness: f64,
+    jitter: f64,
+    last_level: Option<f64>,
+    target: f64,
+}
+
+impl BufferControl {
+    const MAX_TARGET: f64 = 0.5;
+    const MAX_JITTER_TARGET: f64 = 0.5;
+
+    fn new(min_target: f64) -> Self {
+        let min_target = min_target.clamp(0.0, Self::MAX_TARGET);
+        Self {
+            min_target,
+            smoothness: 0.0,
+            jitter: 0.0,
+            last_level: None,
+            target: min_target,
+        }
+    }
+
+    fn compute_target(&self) -> f64 {
+        let base = self.min_target + self.smoothness * (Self::MAX_TARGET - self.min_target);
+        // only compensate for jitter if the user does not ask for the lowest latency
+        let jitter = (2.0 * self.jitter * self.smoothness).min(Self::MAX_JITTER_TARGET);
+        base + jitter
+    }
+
+    fn set_smoothness(&mut self, smoothness: f64) -> f64 {
+        self.smoothness = smoothness.clamp(0.0, 1.0);
+        self.target = self.compute_target();
+        self.target
+    }
+
+    /// Returns the new target if it changed notably.
+    fn report_level(&mut self, level: f64) -> Option<f64> {
+        if !level.is_finite() {
+            return None;
+        }
+        if let Some(last_level) = self.last_level {
+            self.jitter = 0.9 * self.jitter + 0.1 * (level - last_level).abs();
+        }
+        self.last_level = Some(level);
+        if level < 0.01 && self.smoothness > 0.0 {
+            trace!("Client ran out of buffered video.");
+        }
+        let target = self.compute_target();
+        if (target - self.target).abs() > 0.02 {
+            self.target = target;
+            Some(target)
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+/// Determines what a client is allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRole {
+    /// Full access, the client may send input events.
+    Controller,
+    /// The client may only watch the video, all input is ignored.
+    Viewer,
+}
+
+#[derive(Clone, PartialEq)]
 pub struct WeylusClientConfig {
     pub encoder_options: EncoderOptions,
+    /// Captures are scaled down to fit this level.
+    pub max_h264_level: H264Level,
+    /// Summaries of sessions are written to this directory.
+    pub session_summary_dir: Option<PathBuf>,
+    /// Seconds of video clients buffer at least, see [`LatencyPreset`](crate::video::LatencyPreset).
+    pub min_buffer: f64,
     #[cfg(target_os = "linux")]
     pub wayland_support: bool,
+    #[cfg(target_os = "linux")]
+    pub x11_windows: X11WindowOptions,
     pub no_gui: bool,
+    /// Only capturables whose name contains this string are offered to the client.
+    pub capturable_filter: Option<String>,
+    /// Offered in addition, cut out of the first capturable offered.
+    pub capture_regions: Vec<CaptureRegion>,
+    /// Clients that do not choose a rotation themselves get this one.
+    pub rotation: Option<Rotation>,
+    pub wake_on_connect: bool,
+    pub unlock_on_connect: Option<UnlockMethod>,
+    pub unlock_secret: Option<String>,
+    pub power_actions: Vec<PowerAction>,
+    pub mirror_notifications: bool,
+    pub watermark: Option<Watermark>,
+    pub hide_cursor_during_pen: bool,
+    /// Pointer events are clamped to the capturable before they reach the input device.
+    pub confine_pointer: bool,
+    /// Attach the devices created via uinput to a master device of their own.
+    #[cfg(target_os = "linux")]
+    pub xi2_master: bool,
+    /// Devices created via uinput are kept alive by a helper for this long once they are dropped.
+    #[cfg(target_os = "linux")]
+    pub persist_uinput: Option<Duration>,
+    pub inject_predicted_events: bool,
+    pub encoder_standby: bool,
+    pub pointer_filter: PointerFilterConfig,
+    pub eraser_policy: EraserPolicy,
+    /// Backend clients start with, see [`ClientStatus::input_backend`](crate::control::ClientStatus::input_backend).
+    pub input_backend: InputBackend,
+    /// Code of the key pressed if the eraser policy is [`EraserPolicy::Shortcut`], e.g. KeyE.
+    pub eraser_shortcut: String,
+    pub text_injection_policy: TextInjectionPolicy,
+    /// Default for clients that do not choose an activation policy themselves.
+    pub activation_policy: ActivationPolicy,
+    /// Clients start in presentation mode.
+    pub presentation_mode: bool,
+    pub input_rate_limit: Option<RateLimitConfig>,
+    pub control_indicator: IndicatorConfig,
+    pub precision: PrecisionConfig,
+    /// Profiles switched to depending on the application in the foreground.
+    pub input_profiles: Vec<InputProfile>,
+    /// Run on events of the session.
+    pub hooks: Vec<Hook>,
+    pub shortcut_sets: Vec<ShortcutSet>,
+    pub keypad: Keypad,
+    pub switch_control: SwitchConfig,
+    pub dwell_click: DwellClickConfig,
+    pub sensors: SensorConfig,
+    /// Viewers watch the video of a controller instead of a video of their own.
+    pub broadcast: bool,
+    /// Viewers may point at things with a laser pointer drawn into the video.
+    pub laser_pointers: bool,
+    /// Set when replaying a recording: input goes to a mock device that only logs it and this many
+    /// test sources are offered instead of the capturables of this computer.
+    pub replay_capturables: Option<usize>,
+}
+
+/// Shared by all clients of an instance of the web server.
+#[derive(Clone, Default)]
+pub struct InstanceState {
+    pub broadcast: Arc<Broadcast>,
+    pub annotations: Arc<Annotations>,
 }
 
 impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
@@ -70,18 +329,44 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         sender: S,
         receiver: R,
         on_uinput_inaccessible: FnUInput,
-        config: WeylusClientConfig,
+        config_receiver: watch::Receiver<WeylusClientConfig>,
+        role: ClientRole,
+        status: SharedClientStatus,
+        instance: InstanceState,
     ) -> Self
     where
         R: WeylusReceiver,
         S: WeylusSender + Clone + Send + Sync + 'static,
     {
+        let config = config_receiver.borrow().clone();
+        let cursor_hider = config.hide_cursor_during_pen.then(CursorHider::new);
+        let pointer_filter = PointerFilter::new(config.pointer_filter);
+        let inject_predicted_events = config.inject_predicted_events;
+        let text_filter = TextInjectionFilter::new(config.text_injection_policy);
+        let rate_limiter = InputRateLimiter::new(config.input_rate_limit);
         let (video_sender, video_receiver) = mpsc::channel::<VideoCommands>();
+        let geometry_check = GeometryCheck::new();
+        let network_quality = NetworkQuality::new();
+        let broadcast = config.broadcast.then(|| instance.broadcast.clone());
+        let annotations = config.laser_pointers.then(|| instance.annotations.clone());
         let video_thread = {
             let sender = sender.clone();
+            let status = status.clone();
+            let geometry_check = geometry_check.clone();
+            let video_bytes = network_quality.video_bytes();
             // offload creating the videostream to another thread to avoid blocking the thread that
             // is receiving messages from the websocket
-            spawn(move || handle_video(video_receiver, sender, config.encoder_options))
+            spawn(move || {
+                handle_video(
+                    video_receiver,
+                    sender,
+                    config_receiver,
+                    status,
+                    geometry_check,
+                    video_bytes,
+                    instance,
+                )
+            })
         };
 
         Self {
@@ -96,6 +381,48 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
             capture_cursor: false,
             client_name: None,
             video_thread,
+            role,
+            cursor_tracking_stop: None,
+            buffer_control: BufferControl::new(config.min_buffer),
+            status,
+            cursor_hider,
+            pointer_filter,
+            eraser_active: false,
+            predictor: Predictor::new(inject_predicted_events),
+            text_filter,
+            capturable: None,
+            rotation: None,
+            highlight: None,
+            precision: PrecisionMapping::new(config.precision),
+            profiles: ProfileSwitcher::new(config.input_profiles.clone()),
+            // viewers have no use for switches
+            switch_control: SwitchControl::new(SwitchConfig {
+                enabled: config.switch_control.enabled && role == ClientRole::Controller,
+                ..config.switch_control.clone()
+            }),
+            dwell_click: DwellClick::new(config.dwell_click),
+            sensors: SensorMapping::new(config.sensors.clone()),
+            viewport: ViewportMapping::default(),
+            battery_watch: BatteryWatch::default(),
+            session: SessionRecorder::new(),
+            input_backend: config.input_backend,
+            #[cfg(target_os = "linux")]
+            joystick: None,
+            notifications: None,
+            hotplug: None,
+            capturables_changed: Arc::new(AtomicBool::new(false)),
+            control_guard: None,
+            control_granted: false,
+            rate_limiter,
+            client_configuration: None,
+            geometry_check,
+            network_quality,
+            broadcast,
+            broadcast_viewer: None,
+            annotations,
+            laser_pointer: false,
+            recorder: ClientRecorder::new(role),
+            client_settings: None,
         }
     }
 
@@ -105,28 +432,151 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         S: WeylusSender + Clone + Send + Sync + 'static,
         FnUInput: Fn(),
     {
-        for message in self.receiver.take().unwrap() {
+        // viewers must not be able to unlock the computer
+        if self.config.wake_on_connect && self.may_control() {
+            crate::wake::wake_and_unlock(
+                self.config.unlock_on_connect,
+                self.config.unlock_secret.clone(),
+            );
+        }
+        if !self.config.power_actions.is_empty() && self.role == ClientRole::Controller {
+            let actions = self.config.power_actions.clone();
+            self.send_message(MessageOutbound::PowerActions(actions));
+        }
+        // notifications may be private, viewers do not get them
+        if self.config.mirror_notifications && self.role == ClientRole::Controller {
+            let mut sender = self.sender.clone();
+            self.notifications = Some(notifications::subscribe(move |notification| {
+                let message = MessageOutbound::Notification(notification.clone());
+                send_message(&mut sender, message);
+            }));
+        }
+        if self.switch_control.enabled() {
+            let highlight = self.switch_control.highlight();
+            self.send_message(MessageOutbound::SwitchHighlight(highlight));
+        }
+        if let Some(mode) = self.sensors.mode() {
+            if self.role == ClientRole::Controller {
+                self.send_message(MessageOutbound::Sensors(mode));
+            }
+        }
+        let mut receiver = self.receiver.take().unwrap();
+        while let Some(message) = self.next_message(&mut receiver) {
             match message {
                 Ok(message) => {
                     trace!("Received message: {message:?}");
+                    if let Some(recorder) = self.recorder.as_ref() {
+                        recorder.record(&message);
+                    }
+                    self.update_input_stats();
+                    self.update_input_backend();
                     match message {
-                        MessageInbound::PointerEvent(event) => self.process_pointer_event(&event),
-                        MessageInbound::WheelEvent(event) => self.process_wheel_event(&event),
-                        MessageInbound::KeyboardEvent(event) => self.process_keyboard_event(&event),
+                        MessageInbound::PointerEvent(event) => {
+                            if self.rate_limiter.allow(ends_interaction(&event)) {
+                                self.process_pointer_event(&event)
+                            }
+                        }
+                        MessageInbound::PointerEvents(events) => {
+                            for event in &events {
+                                if self.rate_limiter.allow(ends_interaction(event)) {
+                                    self.process_pointer_event(event);
+                                }
+                            }
+                        }
+                        MessageInbound::PredictedPointerEvents(events) => {
+                            if self.rate_limiter.allow(false) {
+                                self.process_predicted_pointer_events(events)
+                            }
+                        }
+                        MessageInbound::WheelEvent(event) => {
+                            if self.rate_limiter.allow(false) {
+                                self.process_wheel_event(&event)
+                            }
+                        }
+                        MessageInbound::KeyboardEvent(event) => {
+                            let key_up = matches!(event.event_type, KeyboardEventType::UP);
+                            if self.rate_limiter.allow(key_up) {
+                                self.process_keyboard_event(event)
+                            }
+                        }
                         MessageInbound::GetCapturableList => self.send_capturable_list(),
+                        MessageInbound::GetShortcutSets => {
+                            let sets = self.config.shortcut_sets.clone();
+                            self.send_message(MessageOutbound::ShortcutSets(sets));
+                        }
+                        MessageInbound::RunShortcut { set, shortcut } => {
+                            self.run_shortcut(set, shortcut)
+                        }
+                        MessageInbound::GetKeypad => {
+                            let keypad = self.config.keypad.clone();
+                            self.send_message(MessageOutbound::Keypad(keypad));
+                        }
+                        MessageInbound::RunKeypadKey(key) => self.run_keypad_key(key),
+                        MessageInbound::PowerAction(action) => self.request_power_action(action),
+                        MessageInbound::GetCapturableThumbnails => {
+                            self.send_capturable_thumbnails()
+                        }
                         MessageInbound::Config(config) => self.update_config(config),
+                        MessageInbound::SwitchCapturable(id) => self.switch_capturable(id),
                         MessageInbound::PauseVideo => {
                             self.video_sender.send(VideoCommands::Pause).unwrap()
                         }
                         MessageInbound::ResumeVideo => {
-                            self.video_sender.send(VideoCommands::Resume).unwrap()
+                            self.video_sender.send(VideoCommands::Resume).unwrap();
+                            if let Err(err) = self.sender.replay_video() {
+                                warn!("Failed to replay video: {err}");
+                            }
                         }
                         MessageInbound::RestartVideo => {
                             self.video_sender.send(VideoCommands::Restart).unwrap()
                         }
+                        MessageInbound::RequestKeyframe => self
+                            .video_sender
+                            .send(VideoCommands::RequestKeyframe)
+                            .unwrap(),
+                        MessageInbound::TogglePrecisionMode => {
+                            if self.may_control() {
+                                let enabled = self.precision.toggle();
+                                self.send_message(MessageOutbound::PrecisionMode(enabled));
+                            }
+                        }
+                        MessageInbound::ConfirmTextInjection(confirmed) => {
+                            for event in self.text_filter.confirm(confirmed) {
+                                self.send_keyboard_event(&event);
+                            }
+                        }
+                        MessageInbound::Orientation(orientation) => {
+                            if self.rate_limiter.allow(orientation.is_none()) {
+                                self.process_orientation(orientation)
+                            }
+                        }
+                        MessageInbound::Viewport(viewport) => self.process_viewport(viewport),
+                        MessageInbound::DeviceStatus(device) => self.process_device_status(device),
+                        MessageInbound::ProbeAck { seq, bytes } => {
+                            if let Some(rtt) = self.network_quality.ack(seq, bytes) {
+                                self.session.rtt(rtt);
+                            }
+                        }
+                        MessageInbound::BufferLevel(level) => {
+                            if let Some(target) = self.buffer_control.report_level(level) {
+                                self.send_message(MessageOutbound::BufferTarget(target));
+                            }
+                        }
                         MessageInbound::ChooseCustomInputAreas => {
+                            if !self.may_control() {
+                                continue;
+                            }
                             let (sender, receiver) = std::sync::mpsc::channel();
+                            #[cfg(feature = "gui")]
                             crate::gui::get_input_area(self.config.no_gui, sender);
+                            #[cfg(not(feature = "gui"))]
+                            {
+                                warn!(
+                                    "Weylus was built without gui, custom input areas can not be \
+                                    chosen."
+                                );
+                                drop(sender);
+                            }
                             let mut sender = self.sender.clone();
                             spawn(move || {
                                 while let Ok(areas) = receiver.recv() {
@@ -148,147 +598,1088 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
             }
         }
 
-        drop(self.video_sender);
-        if let Err(err) = self.video_thread.join() {
-            warn!("Failed to join video thread: {err:?}");
+        self.stop_cursor_tracking();
+        if let Some(annotations) = self.annotations.as_ref() {
+            annotations.remove(self.status.lock().unwrap().address);
+        }
+        drop(self.video_sender);
+        if let Err(err) = self.video_thread.join() {
+            warn!("Failed to join video thread: {err:?}");
+        }
+        if self.client_configuration.is_some() {
+            let status = self.status.lock().unwrap();
+            hooks::fire(&self.config.hooks, HookEvent::ClientDisconnected, &status);
+        }
+        let (input_events, dropped_input_events) = self.rate_limiter.totals();
+        let summary = self.session.finish(
+            &self.status.lock().unwrap(),
+            input_events,
+            dropped_input_events,
+        );
+        summary.log();
+        if let Some(dir) = self.config.session_summary_dir.as_ref() {
+            match summary.write(dir) {
+                Ok(path) => debug!("Wrote summary of the session to {}.", path.display()),
+                Err(err) => warn!(
+                    "Failed to write summary of the session to {}: {err}",
+                    dir.display()
+                ),
+            }
+        }
+    }
+
+    fn send_message(&mut self, message: MessageOutbound)
+    where
+        S: WeylusSender,
+    {
+        send_message(&mut self.sender, message)
+    }
+
+    /// Wait for the next message of the client, meanwhile the timers of switch control, dwell
+    /// clicking and the measurement of the connection are run. Returns None once the client
+    /// disconnected.
+    fn next_message(&mut self, receiver: &mut R) -> Option<Result<MessageInbound, R::Error>>
+    where
+        R: WeylusReceiver,
+        S: WeylusSender,
+    {
+        loop {
+            if self.capturables_changed.swap(false, Ordering::Relaxed) {
+                self.refresh_capturable_list();
+            }
+            let mut deadline = [self.switch_control.deadline(), self.dwell_click.deadline()]
+                .into_iter()
+                .flatten()
+                .fold(self.network_quality.deadline(), Instant::min);
+            if self.hotplug.is_some() {
+                deadline = deadline.min(Instant::now() + HOTPLUG_INTERVAL);
+            }
+            // timers are due even if the client keeps sending messages
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                self.run_timers();
+                continue;
+            }
+            match receiver.recv_timeout(timeout) {
+                Ok(message) => return Some(message),
+                Err(RecvTimeoutError::Timeout) => self.run_timers(),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn run_timers(&mut self)
+    where
+        S: WeylusSender,
+    {
+        let output = self.switch_control.timer();
+        self.apply_switch_output(output);
+        let events = self.dwell_click.timer();
+        self.send_dwell_click_events(&events);
+        for output in self.network_quality.timer() {
+            self.send_message(match output {
+                QualityOutput::Probe(seq) => MessageOutbound::Probe(seq),
+                QualityOutput::Report(report) => MessageOutbound::NetworkQuality(report),
+            });
+        }
+    }
+
+    fn apply_switch_output(&mut self, output: Vec<SwitchOutput>)
+    where
+        S: WeylusSender,
+    {
+        for output in output {
+            match output {
+                SwitchOutput::Highlight(rect) => {
+                    self.send_message(MessageOutbound::SwitchHighlight(rect))
+                }
+                SwitchOutput::Click {
+                    x,
+                    y,
+                    button,
+                    count,
+                    timestamp,
+                } => {
+                    if !self.may_control() {
+                        continue;
+                    }
+                    let Some(device) = self.input_device.as_mut() else {
+                        warn!("Input device is not initalized, can not click!");
+                        continue;
+                    };
+                    let mut events = vec![PointerEvent::mouse(
+                        PointerEventType::MOVE,
+                        x,
+                        y,
+                        Button::NONE,
+                        Button::NONE,
+                        timestamp,
+                    )];
+                    for _ in 0..count {
+                        events.push(PointerEvent::mouse(
+                            PointerEventType::DOWN,
+                            x,
+                            y,
+                            button,
+                            button,
+                            timestamp,
+                        ));
+                        events.push(PointerEvent::mouse(
+                            PointerEventType::UP,
+                            x,
+                            y,
+                            button,
+                            Button::NONE,
+                            timestamp,
+                        ));
+                    }
+                    for event in &events {
+                        device.send_pointer_event(event);
+                    }
+                }
+            }
+        }
+    }
+
+    fn send_dwell_click_events(&mut self, events: &[PointerEvent]) {
+        if events.is_empty() || !self.may_control() {
+            return;
+        }
+        let Some(device) = self.input_device.as_mut() else {
+            warn!("Input device is not initalized, can not click!");
+            return;
+        };
+        for event in events {
+            device.send_pointer_event(event);
+        }
+    }
+
+    fn update_input_stats(&mut self) {
+        let Some(stats) = self.rate_limiter.stats() else {
+            return;
+        };
+        let mut status = self.status.lock().unwrap();
+        if stats.dropped > 0 {
+            warn!(
+                address = %status.address,
+                dropped = stats.dropped,
+                "Client exceeded the input rate limit, dropped events."
+            );
+        }
+        status.input_rate = stats.events_per_second;
+        status.dropped_input_events += stats.dropped;
+    }
+
+    /// Whether the client may send input, this is not the case for viewers and clients in
+    /// presentation mode.
+    fn may_control(&mut self) -> bool {
+        if self.role != ClientRole::Controller {
+            return false;
+        }
+        let presentation = self.status.lock().unwrap().presentation;
+        self.set_control_granted(!presentation);
+        if presentation {
+            self.control_guard = None;
+        } else {
+            // presentation mode has been switched off
+            self.highlight = None;
+            if self.control_guard.is_none() {
+                self.control_guard = ControlGuard::new(&self.config.control_indicator);
+            }
+        }
+        !presentation
+    }
+
+    /// Run the hooks of the client getting control once it connected and gains control.
+    fn set_control_granted(&mut self, granted: bool) {
+        if self.client_configuration.is_none() {
+            return;
+        }
+        if granted && !self.control_granted {
+            let status = self.status.lock().unwrap();
+            hooks::fire(&self.config.hooks, HookEvent::ControlGranted, &status);
+        }
+        self.control_granted = granted;
+    }
+
+    fn process_wheel_event(&mut self, event: &WheelEvent) {
+        if !self.may_control() {
+            return;
+        }
+        let mut event = event.clone();
+        if let Some(rotation) = self.rotation {
+            rotation.unrotate_wheel(&mut event);
+        }
+        match &mut self.input_device {
+            Some(i) => i.send_wheel_event(&event),
+            None => warn!("Input device is not initalized, can not process WheelEvent!"),
+        }
+    }
+
+    fn process_pointer_event(&mut self, event: &PointerEvent)
+    where
+        S: WeylusSender,
+    {
+        if self.role != ClientRole::Controller {
+            self.point_laser(event);
+            return;
+        }
+        if !self.may_control() {
+            if let Some(capturable) = self.capturable.as_ref() {
+                self.highlight
+                    .get_or_insert_with(PointerHighlight::new)
+                    .update(event, capturable.as_ref());
+            }
+            return;
+        }
+        if self.switch_control.enabled() {
+            let output = self.switch_control.pointer(event);
+            self.apply_switch_output(output);
+            return;
+        }
+        if self.viewport.filter(event) {
+            self.forward_pointer_event(event);
+        }
+    }
+
+    /// Move the laser pointer of a viewer, see [`crate::annotations`].
+    fn point_laser(&self, event: &PointerEvent) {
+        let Some(annotations) = self.annotations.as_ref().filter(|_| self.laser_pointer) else {
+            return;
+        };
+        if !event.is_primary {
+            return;
+        }
+        let address = self.status.lock().unwrap().address;
+        let lifted = match event.event_type {
+            PointerEventType::LEAVE | PointerEventType::OUT | PointerEventType::CANCEL => true,
+            // fingers only point while they touch the screen
+            PointerEventType::UP => event.pointer_type == PointerType::Touch,
+            _ => false,
+        };
+        if lifted {
+            annotations.hide(address);
+            return;
+        }
+        let target = if self.broadcast_viewer.is_some() {
+            Target::Broadcast
+        } else if let Some(capturable) = self.capturable.as_ref() {
+            Target::Capturable(capturable.name())
+        } else {
+            return;
+        };
+        annotations.point(address, target, event.x, event.y);
+    }
+
+    fn process_device_status(&mut self, device: DeviceStatus) {
+        for (battery, level) in self.battery_watch.update(&device) {
+            let client = self.client_name.as_deref().unwrap_or("client");
+            warn!(
+                "The {battery} battery of {client} ({}) is at {}.",
+                self.status.lock().unwrap().address,
+                battery::percent(level)
+            );
+        }
+        self.status.lock().unwrap().device = Some(device);
+    }
+
+    /// Lift pointers that are down while the client is rotated, see [`ViewportMapping`].
+    fn process_viewport(&mut self, viewport: ClientViewport)
+    where
+        S: WeylusSender,
+    {
+        let lifted = self.viewport.set_viewport(viewport);
+        if self.role != ClientRole::Controller || !self.may_control() {
+            return;
+        }
+        for event in &lifted {
+            self.forward_pointer_event(event);
+        }
+    }
+
+    /// Pass the event through the filters and mappings to the input device.
+    fn forward_pointer_event(&mut self, event: &PointerEvent)
+    where
+        S: WeylusSender,
+    {
+        if matches!(
+            event.event_type,
+            PointerEventType::DOWN | PointerEventType::ENTER
+        ) {
+            self.switch_input_profile();
+        }
+        if !self.profiles.active().map_or(true, |p| p.accepts(event)) {
+            return;
+        }
+        self.predictor.real(event);
+        let event = match self.pointer_filter.filter(event.clone()) {
+            Some(FilteredEvent::Pointer(event)) => event,
+            Some(FilteredEvent::Wheel(event)) => {
+                self.process_wheel_event(&event);
+                return;
+            }
+            None => return,
+        };
+        let (event, toggled) = self.precision.filter(event);
+        if toggled {
+            self.send_message(MessageOutbound::PrecisionMode(self.precision.enabled()));
+        }
+        let Some(mut event) = event else {
+            return;
+        };
+        if let Some(profile) = self.profiles.active() {
+            profile.apply_pressure_curve(&mut event);
+        }
+        self.calibrate(&mut event);
+        if self.eraser_policy() == EraserPolicy::Shortcut {
+            self.apply_eraser_shortcut(&mut event);
+        }
+        if let Some(cursor_hider) = self.cursor_hider.as_mut() {
+            let pen_in_use = match event.pointer_type {
+                PointerType::Pen => !matches!(
+                    event.event_type,
+                    PointerEventType::LEAVE | PointerEventType::OUT | PointerEventType::CANCEL
+                ),
+                _ => false,
+            };
+            cursor_hider.set_hidden(pen_in_use);
+        }
+        // events of dwell clicks take the position of this one and are confined and mapped with it
+        if self.config.confine_pointer {
+            event.confine();
+        }
+        self.map_position(&mut event);
+        let events = self.dwell_click.filter(&mut event);
+        self.send_dwell_click_events(&events);
+        if self.input_device.is_some() {
+            self.input_device
+                .as_mut()
+                .unwrap()
+                .send_pointer_event(&event)
+        } else {
+            warn!("Input device is not initalized, can not process PointerEvent!");
+        }
+    }
+
+    fn process_predicted_pointer_events(&mut self, events: Vec<PointerEvent>) {
+        if !self.may_control() {
+            return;
+        }
+        // predicted events bypass the pointer filter, they must not change its state
+        if let Some(mut event) = self.predictor.predicted(events) {
+            if self.viewport.is_lifted(event.pointer_id) {
+                return;
+            }
+            if let Some(profile) = self.profiles.active() {
+                if !profile.accepts(&event) {
+                    return;
+                }
+                profile.apply_pressure_curve(&mut event);
+            }
+            self.precision.map(&mut event);
+            self.calibrate(&mut event);
+            if self.config.confine_pointer {
+                event.confine();
+            }
+            self.map_position(&mut event);
+            if let Some(device) = self.input_device.as_mut() {
+                device.send_pointer_event(&event);
+            }
+        }
+    }
+
+    /// Apply the calibration stored for the client.
+    fn calibrate(&self, event: &mut PointerEvent) {
+        if let Some((_, settings)) = self.client_settings.as_ref() {
+            settings.calibrate(event);
+        }
+    }
+
+    /// Map the position in the video to the capturable, e.g. to the monitor below it if the
+    /// video shows several.
+    fn map_position(&self, event: &mut PointerEvent) {
+        if let Some(capturable) = self.capturable.as_ref() {
+            (event.x, event.y) = capturable.map_position(event.x, event.y);
+        }
+        if let Some(rotation) = self.rotation {
+            rotation.unrotate_event(event);
+        }
+    }
+
+    fn eraser_policy(&self) -> EraserPolicy {
+        self.profiles
+            .active()
+            .and_then(|p| p.eraser)
+            .unwrap_or(self.config.eraser_policy)
+    }
+
+    /// Switch to the input profile of the application in the foreground, if it changed.
+    fn switch_input_profile(&mut self) {
+        let Some(capturable) = self.capturable.as_ref() else {
+            return;
+        };
+        if !self.profiles.check(capturable.as_ref()) {
+            return;
+        }
+        let profile = self.profiles.active();
+        let mut filter_config = self.config.pointer_filter;
+        let mut precision_config = self.config.precision;
+        if let Some(profile) = profile {
+            filter_config.pen_scroll_button = profile
+                .pen_scroll_button
+                .or(filter_config.pen_scroll_button);
+            precision_config.button = profile.precision_button.or(precision_config.button);
+        }
+        self.pointer_filter.set_config(filter_config);
+        self.precision.set_config(precision_config);
+        let eraser_policy = self.eraser_policy();
+        if let Some(d) = self.input_device.as_mut() {
+            d.set_eraser_policy(eraser_policy);
+        }
+    }
+
+    /// Press the eraser shortcut whenever the pen is switched to or from the eraser and pass the
+    /// eraser on as a regular pen.
+    fn apply_eraser_shortcut(&mut self, event: &mut PointerEvent) {
+        if event.pointer_type != PointerType::Pen {
+            return;
+        }
+        let eraser = event.buttons.contains(Button::ERASER) || event.button == Button::ERASER;
+        let leaving = matches!(
+            event.event_type,
+            PointerEventType::UP
+                | PointerEventType::CANCEL
+                | PointerEventType::LEAVE
+                | PointerEventType::OUT
+        );
+        if !leaving && eraser != self.eraser_active {
+            self.eraser_active = eraser;
+            if let Some(device) = self.input_device.as_mut() {
+                let code = &self.config.eraser_shortcut;
+                for event_type in [KeyboardEventType::DOWN, KeyboardEventType::UP] {
+                    device.send_keyboard_event(&KeyboardEvent::from_code(code, event_type));
+                }
+            }
+        }
+        event.buttons.remove(Button::ERASER);
+        if event.button == Button::ERASER {
+            event.button = Button::PRIMARY;
+        }
+    }
+
+    fn process_keyboard_event(&mut self, event: KeyboardEvent)
+    where
+        S: WeylusSender,
+    {
+        if !self.may_control() {
+            return;
+        }
+        match self.text_filter.filter(event) {
+            FilteredKey::Pass(event) => self.send_keyboard_event(&event),
+            FilteredKey::Drop => (),
+            FilteredKey::AskConfirmation => {
+                self.send_message(MessageOutbound::ConfirmTextInjection)
+            }
+        }
+    }
+
+    /// Press the keys of a shortcut of the given set, the text filter is bypassed as shortcuts are
+    /// defined on the host.
+    fn run_shortcut(&mut self, set: usize, shortcut: usize) {
+        if !self.may_control() {
+            return;
+        }
+        let Some(shortcut) = self
+            .config
+            .shortcut_sets
+            .get(set)
+            .and_then(|s| s.shortcuts.get(shortcut))
+        else {
+            warn!("Got invalid id for shortcut: {set}/{shortcut}");
+            return;
+        };
+        debug!("Running shortcut {} ({}).", shortcut.label, shortcut.keys);
+        for event in shortcut.keys.events() {
+            self.send_keyboard_event(&event);
+        }
+    }
+
+    fn run_keypad_key(&mut self, key: usize) {
+        if !self.may_control() {
+            return;
+        }
+        let Some(key) = self.config.keypad.keys.get(key) else {
+            warn!("Got invalid id for key of keypad: {key}");
+            return;
+        };
+        debug!("Pressing key {} of keypad.", key.label);
+        for event in key.events() {
+            self.send_keyboard_event(&event);
+        }
+    }
+
+    /// Move the joystick or orbit the viewport as the client is tilted.
+    fn process_orientation(&mut self, orientation: Option<DeviceOrientation>)
+    where
+        S: WeylusSender,
+        FnUInput: Fn(),
+    {
+        if !self.may_control() {
+            return;
+        }
+        match self.sensors.update(orientation) {
+            Some(SensorOutput::Stick(x, y)) => self.move_joystick(x, y),
+            Some(SensorOutput::Keys(events)) => {
+                for event in &events {
+                    self.send_keyboard_event(event);
+                }
+            }
+            None => {}
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn move_joystick(&mut self, x: f64, y: f64)
+    where
+        S: WeylusSender,
+        FnUInput: Fn(),
+    {
+        if self.config.replay_capturables.is_some() {
+            info!("Joystick: x: {x:.3}, y: {y:.3}");
+            return;
+        }
+        if self.joystick.is_none() {
+            match crate::input::uinput_device::UInputJoystick::new(&self.client_name) {
+                Ok(joystick) => self.joystick = Some(joystick),
+                Err(err) => {
+                    error!("Failed to create virtual joystick: {}", err);
+                    if let CErrorCode::UInputNotAccessible = err.to_enum() {
+                        (self.on_uinput_inaccessible)();
+                    }
+                    // do not try again for every change of the orientation
+                    self.sensors.disable();
+                    self.send_message(MessageOutbound::Error(
+                        "Failed to create virtual joystick!".to_string(),
+                    ));
+                    return;
+                }
+            }
+        }
+        if let Some(joystick) = self.joystick.as_ref() {
+            joystick.set_stick(x, y);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn move_joystick(&mut self, _x: f64, _y: f64) {
+        warn!("Joysticks are only supported on Linux.");
+        self.sensors.disable();
+    }
+
+    /// Ask for confirmation on the host in the background and perform the action if granted, the
+    /// client is told if the request is declined.
+    fn request_power_action(&mut self, action: PowerAction)
+    where
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        if !self.may_control() {
+            return;
+        }
+        if !self.config.power_actions.contains(&action) {
+            warn!("Client requested power action {action:?}, which is not enabled.");
+            return;
+        }
+        let client = match self.client_name.as_ref() {
+            Some(name) => format!("{name} ({})", self.status.lock().unwrap().address),
+            None => self.status.lock().unwrap().address.to_string(),
+        };
+        let mut sender = self.sender.clone();
+        spawn(move || {
+            if !crate::power::request(action, &client) {
+                let message = MessageOutbound::Error(format!(
+                    "The request to {} was declined or failed.",
+                    action.describe()
+                ));
+                send_message(&mut sender, message);
+            }
+        });
+    }
+
+    fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
+        if self.input_device.is_some() {
+            self.input_device
+                .as_mut()
+                .unwrap()
+                .send_keyboard_event(event)
+        } else {
+            warn!("Input device is not initalized, can not process KeyboardEvent!");
+        }
+    }
+
+    fn send_capturable_list(&mut self)
+    where
+        S: WeylusSender,
+    {
+        let windows = self.list_capturables();
+        self.send_message(MessageOutbound::CapturableList(windows));
+        // the list of PipeWire is chosen in a dialog, which must not pop up by itself
+        #[cfg(target_os = "linux")]
+        let watch = !self.config.wayland_support;
+        #[cfg(not(target_os = "linux"))]
+        let watch = true;
+        if watch && self.hotplug.is_none() && self.config.replay_capturables.is_none() {
+            let changed = self.capturables_changed.clone();
+            self.hotplug = Some(hotplug::subscribe(move || {
+                changed.store(true, Ordering::Relaxed)
+            }));
+        }
+    }
+
+    /// Send the list of capturables again if monitors or windows appeared or disappeared.
+    fn refresh_capturable_list(&mut self)
+    where
+        S: WeylusSender,
+    {
+        let old: Vec<String> = self.capturables.iter().map(|c| c.name()).collect();
+        let windows = self.list_capturables();
+        if windows != old {
+            debug!("Capturables changed, sending the list again.");
+            self.send_message(MessageOutbound::CapturableList(windows));
+        }
+    }
+
+    /// Update the capturables offered to the client and return their names.
+    fn list_capturables(&mut self) -> Vec<String> {
+        self.capturables = match self.config.replay_capturables {
+            Some(n) => protocol_replay::replay_capturables(n),
+            None => get_capturables(
+                #[cfg(target_os = "linux")]
+                self.config.wayland_support,
+                #[cfg(target_os = "linux")]
+                self.capture_cursor,
+                #[cfg(target_os = "linux")]
+                self.config.x11_windows,
+            ),
+        };
+        if let Some(filter) = self.config.capturable_filter.as_ref() {
+            self.capturables
+                .retain(|c| c.name().contains(filter.as_str()));
+        }
+        if let Some(first) = self.capturables.first().cloned() {
+            for region in &self.config.capture_regions {
+                self.capturables
+                    .push(Box::new(RegionCapturable::new(first.clone(), *region)));
+            }
+        }
+        self.capturables.iter().map(|c| c.name()).collect()
+    }
+
+    /// Capture thumbnails of all capturables in a separate thread, this takes a while.
+    fn send_capturable_thumbnails(&mut self)
+    where
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        let capturables = self.capturables.clone();
+        let mut sender = self.sender.clone();
+        spawn(move || {
+            let thumbnails = capturables
+                .iter()
+                .map(|c| match thumbnail(c.as_ref(), THUMBNAIL_WIDTH) {
+                    Ok(thumbnail) => Some(thumbnail),
+                    Err(err) => {
+                        debug!("Failed to capture thumbnail of {}: {err}", c.name());
+                        None
+                    }
+                })
+                .collect();
+            send_message(
+                &mut sender,
+                MessageOutbound::CapturableThumbnails(thumbnails),
+            );
+        });
+    }
+
+    /// Switch to another capturable without the client having to send its whole configuration,
+    /// input goes to the new capturable right away.
+    fn switch_capturable(&mut self, id: usize)
+    where
+        S: WeylusSender + Clone + Send + 'static,
+        FnUInput: Fn(),
+    {
+        if self.role != ClientRole::Controller {
+            warn!("Viewers may not switch the capturable.");
+            return;
+        }
+        let Some(mut config) = self.client_configuration.clone() else {
+            warn!("Got request to switch capturable before the client sent its configuration.");
+            return;
+        };
+        if id >= self.capturables.len() {
+            error!("Got invalid id for capturable: {id}");
+            self.send_message(MessageOutbound::ConfigError(
+                "Invalid id for capturable!".to_string(),
+            ));
+            return;
+        }
+        config.capturable_id = id;
+        self.update_config(config);
+        self.send_message(MessageOutbound::CapturableSwitched(id));
+    }
+
+    fn stop_cursor_tracking(&mut self) {
+        if let Some(stop) = self.cursor_tracking_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The backend input is sent with on Linux, resolving [`InputBackend::Auto`].
+    #[cfg(target_os = "linux")]
+    fn linux_input_backend(&self, uinput_support: bool) -> InputBackend {
+        if crate::sandbox::is_sandboxed() {
+            // neither uinput nor the X server are accessible from within the sandbox
+            if !matches!(
+                self.input_backend,
+                InputBackend::Auto | InputBackend::Portal
+            ) {
+                warn!(
+                    "Only the RemoteDesktop portal can send input while running sandboxed, \
+                    ignoring the {} backend.",
+                    self.input_backend.name()
+                );
+            }
+            return InputBackend::Portal;
+        }
+        let backend = match self.input_backend {
+            InputBackend::Auto if uinput_support => InputBackend::UInput,
+            // XTest only reaches Xwayland clients
+            InputBackend::Auto if crate::capturable::is_wayland_session() => InputBackend::Portal,
+            InputBackend::Auto => InputBackend::XTest,
+            backend => backend,
+        };
+        if backend == InputBackend::UInput && crate::capturable::x11::is_remote_display() {
+            warn!("uinput can not send input to a remote X display, using XTest instead.");
+            return InputBackend::XTest;
+        }
+        backend
+    }
+
+    /// Create the input device for the current capturable or hand the capturable to the device
+    /// if it is of the right type already. Returns false if creating the device failed.
+    fn setup_input_device(
+        &mut self,
+        config: &ClientConfiguration,
+        client_name_changed: bool,
+    ) -> bool
+    where
+        S: WeylusSender,
+        FnUInput: Fn(),
+    {
+        let Some(capturable) = self.capturable.clone() else {
+            return true;
+        };
+        // input devices only see the capturable through the geometry check
+        let input_capturable: Box<dyn Capturable> = Box::new(CheckedCapturable::new(
+            capturable.clone(),
+            self.geometry_check.clone(),
+        ));
+
+        if self.config.replay_capturables.is_some() {
+            if self
+                .input_device
+                .as_ref()
+                .map_or(true, |d| d.device_type() != InputDeviceType::Mock)
+            {
+                self.input_device = Some(Box::new(
+                    crate::input::mock_device::MockInputDevice::new(input_capturable.clone()),
+                ));
+            } else if let Some(d) = self.input_device.as_mut() {
+                d.set_capturable(input_capturable.clone());
+            }
+        } else {
+            #[cfg(target_os = "linux")]
+            match self.linux_input_backend(config.uinput_support) {
+                #[cfg(feature = "wayland")]
+                InputBackend::Portal => match capturable.remote_desktop_session() {
+                    Some(_)
+                        if self
+                            .input_device
+                            .as_ref()
+                            .is_some_and(|d| d.device_type() == InputDeviceType::PortalDevice) =>
+                    {
+                        self.input_device
+                            .as_mut()
+                            .map(|d| d.set_capturable(input_capturable.clone()));
+                    }
+                    Some(session) => {
+                        self.input_device = Some(Box::new(
+                            crate::input::portal_device::PortalInputDevice::new(
+                                input_capturable.clone(),
+                                session,
+                            ),
+                        ))
+                    }
+                    None => {
+                        warn!(
+                            "{} is not captured via the RemoteDesktop portal, input can not be sent \
+                            through it.",
+                            capturable.name()
+                        );
+                        self.input_device = None;
+                    }
+                },
+                #[cfg(not(feature = "wayland"))]
+                InputBackend::Portal => {
+                    warn!(
+                        "Weylus was built without the wayland feature, input can not be sent through \
+                        the RemoteDesktop portal."
+                    );
+                    self.input_device = None;
+                }
+                InputBackend::UInput => {
+                    if self.input_device.as_ref().map_or(true, |d| {
+                        client_name_changed || d.device_type() != InputDeviceType::UInputDevice
+                    }) {
+                        if self.config.xi2_master {
+                            // remove the old master device first, it has the same name
+                            self.input_device = None;
+                        }
+                        let device = crate::input::uinput_device::UInputDevice::new(
+                            input_capturable.clone(),
+                            &self.client_name,
+                            self.config.xi2_master,
+                            self.config.persist_uinput,
+                        );
+                        match device {
+                            Ok(d) => self.input_device = Some(Box::new(d)),
+                            Err(e) => {
+                                error!("Failed to create uinput device: {}", e);
+                                if let CErrorCode::UInputNotAccessible = e.to_enum() {
+                                    (self.on_uinput_inaccessible)();
+                                }
+                                self.send_message(MessageOutbound::ConfigError(
+                                    "Failed to create uinput device!".to_string(),
+                                ));
+                                return false;
+                            }
+                        }
+                    } else if let Some(d) = self.input_device.as_mut() {
+                        d.set_capturable(input_capturable.clone());
+                    }
+                }
+                InputBackend::Auto | InputBackend::XTest => {
+                    if self.input_device.as_ref().map_or(true, |d| {
+                        d.device_type() != InputDeviceType::AutoPilotDevice
+                    }) {
+                        self.input_device = Some(Box::new(
+                            crate::input::autopilot_device::AutoPilotDevice::new(
+                                input_capturable.clone(),
+                            ),
+                        ));
+                    } else if let Some(d) = self.input_device.as_mut() {
+                        d.set_capturable(input_capturable.clone());
+                    }
+                }
+            }
+
+            #[cfg(target_os = "macos")]
+            if self.input_device.is_none() {
+                self.input_device = Some(Box::new(
+                    crate::input::autopilot_device::AutoPilotDevice::new(input_capturable.clone()),
+                ));
+            } else {
+                self.input_device
+                    .as_mut()
+                    .map(|d| d.set_capturable(input_capturable.clone()));
+            }
+            #[cfg(target_os = "windows")]
+            if self.input_device.is_none() {
+                self.input_device = Some(Box::new(
+                    crate::input::autopilot_device_win::WindowsInput::new(input_capturable.clone()),
+                ));
+            } else {
+                self.input_device
+                    .as_mut()
+                    .map(|d| d.set_capturable(input_capturable.clone()));
+            }
+        }
+
+        let eraser_policy = self.eraser_policy();
+        if let Some(d) = self.input_device.as_mut() {
+            d.set_eraser_policy(eraser_policy);
+            d.set_activation_policy(
+                config
+                    .window_activation
+                    .unwrap_or(self.config.activation_policy),
+            );
         }
+        self.status.lock().unwrap().input_device = self
+            .input_device
+            .as_ref()
+            .map(|d| d.device_type().name().to_string());
+        true
     }
 
-    fn send_message(&mut self, message: MessageOutbound)
+    /// Replace the input device once another input backend has been chosen in the GUI or via
+    /// the control socket. The old device is dropped first, releasing whatever it holds down, and
+    /// pointers down on the client are ignored until they are lifted, so the new device never
+    /// sees half a stroke.
+    fn update_input_backend(&mut self)
     where
         S: WeylusSender,
+        FnUInput: Fn(),
     {
-        send_message(&mut self.sender, message)
-    }
-
-    fn process_wheel_event(&mut self, event: &WheelEvent) {
-        match &mut self.input_device {
-            Some(i) => i.send_wheel_event(event),
-            None => warn!("Input device is not initalized, can not process WheelEvent!"),
+        let backend = self.status.lock().unwrap().input_backend;
+        if backend == self.input_backend {
+            return;
         }
+        self.input_backend = backend;
+        let Some(config) = self.client_configuration.clone() else {
+            // the device is created with the new backend once the client is configured
+            return;
+        };
+        info!(
+            address = %self.status.lock().unwrap().address,
+            "Switching input backend to {}.",
+            backend.name()
+        );
+        self.input_device = None;
+        self.viewport.lift_all();
+        self.setup_input_device(&config, false);
     }
 
-    fn process_pointer_event(&mut self, event: &PointerEvent) {
-        if self.input_device.is_some() {
-            self.input_device
-                .as_mut()
-                .unwrap()
-                .send_pointer_event(event)
-        } else {
-            warn!("Input device is not initalized, can not process PointerEvent!");
+    /// Load the stored settings if the client sent an id it did not send before.
+    fn load_client_settings(&mut self, config: &ClientConfiguration) {
+        // replayed clients must not end up in the settings of this computer
+        if self.config.replay_capturables.is_some() {
+            return;
         }
-    }
-
-    fn process_keyboard_event(&mut self, event: &KeyboardEvent) {
-        if self.input_device.is_some() {
-            self.input_device
-                .as_mut()
-                .unwrap()
-                .send_keyboard_event(event)
-        } else {
-            warn!("Input device is not initalized, can not process KeyboardEvent!");
+        let Some(id) = config.client_id.as_deref() else {
+            return;
+        };
+        if !client_settings::is_valid_id(id) {
+            warn!("Ignoring invalid client id.");
+            return;
         }
-    }
-
-    fn send_capturable_list(&mut self)
-    where
-        S: WeylusSender,
-    {
-        let mut windows = Vec::<String>::new();
-        self.capturables = get_capturables(
-            #[cfg(target_os = "linux")]
-            self.config.wayland_support,
-            #[cfg(target_os = "linux")]
-            self.capture_cursor,
-        );
-        self.capturables.iter().for_each(|c| {
-            windows.push(c.name());
-        });
-        self.send_message(MessageOutbound::CapturableList(windows));
+        if self.client_settings.as_ref().map(|(i, _)| i.as_str()) == Some(id) {
+            return;
+        }
+        let settings = client_settings::load_for(id, config.client_name.as_deref());
+        if settings.role == Some(ClientRole::Viewer) && self.role == ClientRole::Controller {
+            info!("Client {id} is stored as viewer, it may not control this computer.");
+            self.role = ClientRole::Viewer;
+            self.status.lock().unwrap().role = ClientRole::Viewer;
+            self.notifications = None;
+            self.control_guard = None;
+            self.input_device = None;
+        }
+        self.profiles.set_fallback(settings.input_profile());
+        self.client_settings = Some((id.to_string(), settings));
     }
 
     fn update_config(&mut self, config: ClientConfiguration)
     where
-        S: WeylusSender,
+        S: WeylusSender + Clone + Send + 'static,
         FnUInput: Fn(),
     {
+        self.load_client_settings(&config);
+        let connected = self.client_configuration.is_none();
+        self.client_configuration = Some(config.clone());
+        self.laser_pointer = config.laser_pointer && self.role == ClientRole::Viewer;
+        if let Some(annotations) = self.annotations.as_ref().filter(|_| !self.laser_pointer) {
+            annotations.hide(self.status.lock().unwrap().address);
+        }
         let client_name_changed = if self.client_name != config.client_name {
             self.client_name = config.client_name;
             true
         } else {
             false
         };
+        let target = self.buffer_control.set_smoothness(config.smoothness);
+        self.send_message(MessageOutbound::BufferTarget(target));
+        self.status.lock().unwrap().name = self.client_name.clone();
+        if connected {
+            let presentation = {
+                let status = self.status.lock().unwrap();
+                hooks::fire(&self.config.hooks, HookEvent::ClientConnected, &status);
+                status.presentation
+            };
+            self.set_control_granted(self.role == ClientRole::Controller && !presentation);
+        }
         if config.capturable_id < self.capturables.len() {
-            let capturable = self.capturables[config.capturable_id].clone();
-
+            let mut capturable = self.capturables[config.capturable_id].clone();
+            if let Some(region) = config.capture_region {
+                capturable = Box::new(RegionCapturable::new(capturable, region));
+            }
+            self.rotation = config
+                .rotation
+                .or(self.config.rotation)
+                .filter(|r| *r != Rotation::Deg0);
+            if let Some(rotation) = self.rotation {
+                capturable = Box::new(RotatedCapturable::new(capturable, rotation));
+            }
+            self.status.lock().unwrap().capturable = Some(capturable.name());
+            self.capturable = Some(capturable.clone());
             #[cfg(target_os = "linux")]
             {
                 self.capture_cursor = config.capture_cursor;
             }
 
-            #[cfg(target_os = "linux")]
-            if config.uinput_support {
-                if self.input_device.as_ref().map_or(true, |d| {
-                    client_name_changed || d.device_type() != InputDeviceType::UInputDevice
-                }) {
-                    let device = crate::input::uinput_device::UInputDevice::new(
-                        capturable.clone(),
-                        &self.client_name,
-                    );
-                    match device {
-                        Ok(d) => self.input_device = Some(Box::new(d)),
-                        Err(e) => {
-                            error!("Failed to create uinput device: {}", e);
-                            if let CErrorCode::UInputNotAccessible = e.to_enum() {
-                                (self.on_uinput_inaccessible)();
-                            }
-                            self.send_message(MessageOutbound::ConfigError(
-                                "Failed to create uinput device!".to_string(),
-                            ));
-                            return;
-                        }
-                    }
-                } else if let Some(d) = self.input_device.as_mut() {
-                    d.set_capturable(capturable.clone());
-                }
-            } else if self.input_device.as_ref().map_or(true, |d| {
-                d.device_type() != InputDeviceType::AutoPilotDevice
-            }) {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
-                ));
-            } else if let Some(d) = self.input_device.as_mut() {
-                d.set_capturable(capturable.clone());
+            if !self.setup_input_device(&config, client_name_changed) {
+                return;
             }
 
-            #[cfg(target_os = "macos")]
-            if self.input_device.is_none() {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
-                ));
-            } else {
-                self.input_device
-                    .as_mut()
-                    .map(|d| d.set_capturable(capturable.clone()));
+            self.stop_cursor_tracking();
+            if config.input_only {
+                self.video_sender.send(VideoCommands::Pause).unwrap();
+                self.send_message(MessageOutbound::ConfigOk);
+                return;
             }
-            #[cfg(target_os = "windows")]
-            if self.input_device.is_none() {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device_win::WindowsInput::new(capturable.clone()),
-                ));
-            } else {
-                self.input_device
-                    .as_mut()
-                    .map(|d| d.set_capturable(capturable.clone()));
+            if let Some(broadcast) = self.broadcast.as_ref() {
+                if self.role == ClientRole::Viewer {
+                    if self.broadcast_viewer.is_none() {
+                        self.broadcast_viewer = Some(broadcast.subscribe(self.sender.clone()));
+                        self.status.lock().unwrap().watching_broadcast = true;
+                    }
+                    self.send_message(MessageOutbound::ConfigOk);
+                    return;
+                }
+            }
+            let mut client_cursor = false;
+            if config.client_cursor {
+                match capturable.cursor_tracker() {
+                    Some(tracker) => {
+                        let stop = Arc::new(AtomicBool::new(false));
+                        let sender = self.sender.clone();
+                        {
+                            let stop = stop.clone();
+                            spawn(move || track_cursor(tracker, sender, stop));
+                        }
+                        self.cursor_tracking_stop = Some(stop);
+                        client_cursor = true;
+                    }
+                    None => warn!(
+                        "Tracking the cursor is not supported for this capturable, it stays in \
+                         the video."
+                    ),
+                }
             }
 
+            let settings = self.client_settings.as_ref().map(|(_, s)| s);
             self.video_sender
                 .send(VideoCommands::Start(VideoConfig {
                     capturable,
-                    capture_cursor: config.capture_cursor,
+                    // the client draws the cursor itself
+                    capture_cursor: config.capture_cursor && !client_cursor,
                     max_width: config.max_width,
                     max_height: config.max_height,
                     frame_rate: config.frame_rate,
+                    frame_stamp: config.frame_stamp,
+                    bandwidth_cap: config
+                        .bandwidth_cap
+                        .or(settings.and_then(|s| s.bandwidth_cap))
+                        .filter(|cap| *cap > 0.0)
+                        .map_or(0, |cap| (cap * 1000.0) as u32),
+                    software_encoding: settings.and_then(|s| s.hardware_encoding) == Some(false),
                 }))
                 .unwrap();
         } else {
@@ -300,15 +1691,120 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
     }
 }
 
-fn handle_video<S: WeylusSender + Clone + 'static>(
+/// Events that must not be dropped by the rate limit, they release buttons and touches.
+fn ends_interaction(event: &PointerEvent) -> bool {
+    matches!(
+        event.event_type,
+        PointerEventType::UP
+            | PointerEventType::CANCEL
+            | PointerEventType::LEAVE
+            | PointerEventType::OUT
+    )
+}
+
+fn track_cursor<S: WeylusSender>(
+    mut tracker: Box<dyn CursorTracker>,
+    mut sender: S,
+    stop: Arc<AtomicBool>,
+) {
+    const INTERVAL: Duration = Duration::from_millis(16);
+    let mut last_position = None;
+    while !stop.load(Ordering::Relaxed) {
+        match tracker.poll() {
+            Ok(cursor) => {
+                if let Some(shape) = cursor.shape {
+                    let mut png = std::io::Cursor::new(Vec::new());
+                    match shape.image.write_to(&mut png, image::ImageFormat::Png) {
+                        Ok(_) => send_message(
+                            &mut sender,
+                            MessageOutbound::CursorShape(CursorShape {
+                                width: shape.width,
+                                height: shape.height,
+                                xhot: shape.xhot,
+                                yhot: shape.yhot,
+                                image: format!(
+                                    "data:image/png;base64,{}",
+                                    base64::engine::general_purpose::STANDARD
+                                        .encode(png.into_inner())
+                                ),
+                            }),
+                        ),
+                        Err(err) => warn!("Failed to encode cursor image: {err}"),
+                    }
+                }
+                let position = CursorPosition {
+                    x: cursor.x,
+                    y: cursor.y,
+                };
+                if last_position != Some(position) {
+                    send_message(&mut sender, MessageOutbound::CursorPosition(position));
+                    last_position = Some(position);
+                }
+            }
+            Err(err) => {
+                warn!("Failed to track cursor: {err}");
+                return;
+            }
+        }
+        std::thread::sleep(INTERVAL);
+    }
+}
+
+fn handle_video<S: WeylusSender + Clone + Send + 'static>(
     receiver: mpsc::Receiver<VideoCommands>,
     mut sender: S,
-    encoder_options: EncoderOptions,
+    mut config_receiver: watch::Receiver<WeylusClientConfig>,
+    status: SharedClientStatus,
+    geometry_check: Arc<GeometryCheck>,
+    video_bytes: Arc<AtomicU64>,
+    instance: InstanceState,
 ) {
     const EFFECTIVE_INIFINITY: Duration = Duration::from_secs(3600 * 24 * 365 * 200);
+    // frames are encoded at least this often even if the content did not change
+    const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+    // a window being resized changes its size every frame, the encoder is only restarted once the
+    // size did not change for this long, or if it has been changing for longer than the maximum
+    const RESIZE_SETTLE: Duration = Duration::from_millis(200);
+    const RESIZE_MAX_DELAY: Duration = Duration::from_secs(1);
 
     let mut recorder: Option<Box<dyn Recorder>> = None;
+    // the video's own copy of the capturable, its geometry is checked against input
+    let mut capturable: Option<Box<dyn Capturable>> = None;
+    let mut capturable_name = None;
+    // the video is broadcast to viewers
+    let mut broadcasting = false;
     let mut video_encoder: Option<Box<VideoEncoder>> = None;
+    // the cap of the host is part of the encoder options, the client may choose a lower one
+    let with_client_cap = |mut options: EncoderOptions, client_cap: u32, software: bool| {
+        options.tuning.max_bitrate = lower_cap(options.tuning.max_bitrate as u32, client_cap) as _;
+        if software {
+            options.try_vaapi = false;
+            options.try_nvenc = false;
+            options.try_videotoolbox = false;
+            options.try_mediafoundation = false;
+        }
+        options
+    };
+    let mut client_cap = 0;
+    let mut software_encoding = false;
+    let mut encoder_options = with_client_cap(
+        config_receiver.borrow_and_update().encoder_options,
+        client_cap,
+        software_encoding,
+    );
+    // total of the video sent, to enforce the cap
+    let sent_bytes = Arc::new(AtomicU64::new(0));
+    let new_bandwidth_cap = |options: &EncoderOptions| {
+        let kbits = options.tuning.max_bitrate as u32;
+        (kbits > 0).then(|| BandwidthCap::new(kbits, sent_bytes.load(Ordering::Relaxed)))
+    };
+    let mut bandwidth_cap = new_bandwidth_cap(&encoder_options);
+    let encoder_standby = config_receiver.borrow().encoder_standby;
+    let max_level = config_receiver.borrow().max_h264_level;
+    // size of the capturable last sent to the client
+    let mut layout_size = None;
+    // frame size differing from the one encoded, when the size first and last changed
+    let mut resize: Option<((usize, usize), Instant, Instant)> = None;
 
     let mut max_width = 1920;
     let mut max_height = 1080;
@@ -316,7 +1812,40 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
     let mut last_frame = Instant::now();
     let mut paused = false;
 
+    let mut stamp_frames = false;
+    let mut frame_stamp_buffer = Vec::new();
+    let host = gethostname::gethostname().to_string_lossy().to_string();
+    let (address, role) = {
+        let status = status.lock().unwrap();
+        (status.address, status.role)
+    };
+    let (broadcast, annotations) = {
+        let config = config_receiver.borrow();
+        (
+            // only the video of controllers is broadcast
+            (config.broadcast && role == ClientRole::Controller).then_some(instance.broadcast),
+            config.laser_pointers.then_some(instance.annotations),
+        )
+    };
+    let mut annotations_version = 0;
+    // the frame encoded last has nothing drawn over it, so it can be encoded again as is
+    let mut last_frame_plain = false;
+    let mut frame_count: u64 = 0;
+    let mut last_encode_duration = Duration::ZERO;
+    let mut last_encode = Instant::now();
+    // set while the recorder is reconnecting to its source
+    let mut interrupted = false;
+    let mut frame_counter = FrameCounter::default();
+
     loop {
+        if let Some(stats) = frame_counter.stats() {
+            let mut status = status.lock().unwrap();
+            status.video = stats;
+            status.session_video.merge(&stats);
+            status.video_bytes = sent_bytes.load(Ordering::Relaxed);
+            stats.log(status.address);
+        }
+
         let now = Instant::now();
         let elapsed = now - last_frame;
         let frames_passed = (elapsed.as_secs_f64() / frame_duration.as_secs_f64()) as u32;
@@ -326,6 +1855,7 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
 
         if frames_passed > 0 {
             trace!("Dropped {frames_passed} frame(s)!");
+            frame_counter.late(frames_passed);
         }
 
         match receiver.recv_timeout(if paused { EFFECTIVE_INIFINITY } else { timeout }) {
@@ -342,12 +1872,40 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
                     // This shouldn't affect other Recorder trait objects.
                     recorder = None;
                 }
+                geometry_check.reset();
+                layout_size = None;
+                resize = None;
                 match config.capturable.recorder(config.capture_cursor) {
                     Ok(r) => {
                         recorder = Some(r);
+                        capturable_name = Some(config.capturable.name());
+                        capturable = Some(config.capturable);
+                        // the content may have changed completely, e.g. if another capturable
+                        // was chosen, do not make the client wait for the next keyframe
+                        if let Some(video_encoder) = video_encoder.as_mut() {
+                            video_encoder.request_keyframe();
+                        }
                         max_width = config.max_width;
                         max_height = config.max_height;
+                        stamp_frames = config.frame_stamp;
+                        if config.bandwidth_cap != client_cap
+                            || config.software_encoding != software_encoding
+                        {
+                            client_cap = config.bandwidth_cap;
+                            software_encoding = config.software_encoding;
+                            let options = config_receiver.borrow().encoder_options;
+                            encoder_options =
+                                with_client_cap(options, client_cap, software_encoding);
+                            bandwidth_cap = new_bandwidth_cap(&encoder_options);
+                            // the bitrate can only be set when opening the encoder
+                            video_encoder = None;
+                        }
                         send_message(&mut sender, MessageOutbound::ConfigOk);
+                        hooks::fire(
+                            &config_receiver.borrow().hooks,
+                            HookEvent::StreamStarted,
+                            &status.lock().unwrap(),
+                        );
                     }
                     Err(err) => {
                         warn!("Failed to init screen cast: {}!", err);
@@ -375,27 +1933,192 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
             }
             Ok(VideoCommands::Resume) => {
                 paused = false;
+                // frames are not late just because the video was paused
+                last_frame = Instant::now();
             }
             Ok(VideoCommands::Restart) => {
                 video_encoder = None;
             }
+            Ok(VideoCommands::RequestKeyframe) => {
+                if let Some(video_encoder) = video_encoder.as_mut() {
+                    video_encoder.request_keyframe();
+                }
+            }
             Err(RecvTimeoutError::Timeout) => {
+                if config_receiver.has_changed().unwrap_or(false) {
+                    let options = config_receiver.borrow_and_update().encoder_options;
+                    let options = with_client_cap(options, client_cap, software_encoding);
+                    if options != encoder_options {
+                        info!("Encoder settings changed, restarting video encoder.");
+                        encoder_options = options;
+                        bandwidth_cap = new_bandwidth_cap(&encoder_options);
+                        video_encoder = None;
+                    }
+                }
                 if recorder.is_none() {
                     warn!("Screen capture not initalized, can not send video frame!");
                     continue;
                 }
+                if let Some(cap) = bandwidth_cap.as_mut() {
+                    if !cap.allows_frame(sent_bytes.load(Ordering::Relaxed)) {
+                        trace!("Bandwidth cap reached, skipping frame.");
+                        continue;
+                    }
+                }
+                // skip frames while the content does not change, unless a new size is pending
+                if video_encoder.is_some()
+                    && resize.is_none()
+                    && last_encode.elapsed() < KEEPALIVE_INTERVAL
+                    && !recorder.as_mut().unwrap().frame_changed()
+                    && !annotations
+                        .as_ref()
+                        .is_some_and(|a| a.version() != annotations_version)
+                {
+                    trace!("Content unchanged, skipping frame.");
+                    continue;
+                }
+                // nothing changed since the last frame, e.g. the keepalive of an idle screen:
+                // encode that frame again instead of capturing and converting the same pixels
+                if last_frame_plain
+                    && resize.is_none()
+                    && !annotations
+                        .as_ref()
+                        .is_some_and(|a| a.version() != annotations_version)
+                    && recorder
+                        .as_mut()
+                        .unwrap()
+                        .dirty_rects()
+                        .is_some_and(|d| d.is_empty())
+                    && video_encoder.as_mut().is_some_and(|e| e.encode_unchanged())
+                {
+                    trace!("Content unchanged, encoded the last frame again.");
+                    last_encode = Instant::now();
+                    continue;
+                }
+                frame_count += 1;
+                let frame_span = debug_span!(
+                    "frame",
+                    frame = frame_count,
+                    capture_ms = field::Empty,
+                    encode_ms = field::Empty
+                )
+                .entered();
+                let capture_start = Instant::now();
+                let capture_span = debug_span!("capture").entered();
                 let pixel_data = recorder.as_mut().unwrap().capture();
+                capture_span.exit();
+                frame_span.record("capture_ms", capture_start.elapsed().as_secs_f64() * 1000.0);
                 if let Err(err) = pixel_data {
-                    warn!("Error capturing screen: {}", err);
+                    frame_counter.capture_failed();
+                    if recorder.as_ref().unwrap().reconnecting() {
+                        if !interrupted {
+                            interrupted = true;
+                            send_message(&mut sender, MessageOutbound::CaptureInterrupted);
+                        }
+                        trace!("{err}");
+                    } else {
+                        warn!("Error capturing screen: {}", err);
+                    }
                     continue;
                 }
-                let pixel_data = pixel_data.unwrap();
+                let mut pixel_data = pixel_data.unwrap();
+                frame_counter.captured(capture_start.elapsed());
+                if interrupted {
+                    interrupted = false;
+                    send_message(&mut sender, MessageOutbound::CaptureResumed);
+                    // the client can not continue decoding where the old stream stopped
+                    if let Some(video_encoder) = video_encoder.as_mut() {
+                        video_encoder.request_keyframe();
+                    }
+                }
+                // draw frame counter, capture time and the time it took to encode the previous
+                // frame into the frame itself, this way the latency can be measured by taking a
+                // picture of both the host's and the client's screen
+                let stamp_lines = stamp_frames.then(|| {
+                    [
+                        format!("F {frame_count}"),
+                        format!("C {}", frame_stamp::time_of_day()),
+                        format!("E {:.1}", last_encode_duration.as_secs_f64() * 1000.0),
+                    ]
+                });
+                let watermark = config_receiver.borrow().watermark.clone();
+                let watermark_lines = watermark.as_ref().map(|w| {
+                    let status = status.lock().unwrap();
+                    let client = match status.name.as_ref() {
+                        Some(name) => format!("{name} ({})", status.address),
+                        None => status.address.to_string(),
+                    };
+                    w.lines(&host, &client)
+                });
+                let mut texts = Vec::new();
+                if let Some(lines) = stamp_lines.as_ref() {
+                    texts.push(TextOverlay {
+                        position: TextPosition::TopLeft,
+                        lines,
+                    });
+                }
+                if let (Some(w), Some(lines)) = (watermark.as_ref(), watermark_lines.as_ref()) {
+                    texts.push(TextOverlay {
+                        position: w.position,
+                        lines,
+                    });
+                }
+                let dots = match annotations.as_ref() {
+                    Some(annotations) => {
+                        annotations_version = annotations.version();
+                        annotations.dots(capturable_name.as_deref(), broadcasting)
+                    }
+                    None => Vec::new(),
+                };
+                last_frame_plain = texts.is_empty() && dots.is_empty();
+                if !last_frame_plain {
+                    pixel_data = frame_stamp::stamp_frame(
+                        pixel_data,
+                        &mut frame_stamp_buffer,
+                        &texts,
+                        &dots,
+                    );
+                }
                 let (width_in, height_in) = pixel_data.size();
+                if let Some(capturable) = capturable.as_mut() {
+                    geometry_check.frame_captured(capturable.as_mut(), (width_in, height_in));
+                }
+                // keep the client showing the last frame while a window is resized instead of
+                // restarting the video for every step
+                match video_encoder.as_ref() {
+                    Some(encoder) if !encoder.check_size_in(width_in, height_in) => {
+                        let now = Instant::now();
+                        let (first, last) = match resize {
+                            Some((size, first, last)) if size == (width_in, height_in) => {
+                                (first, last)
+                            }
+                            Some((_, first, _)) => (first, now),
+                            None => (now, now),
+                        };
+                        resize = Some(((width_in, height_in), first, last));
+                        if last.elapsed() < RESIZE_SETTLE && first.elapsed() < RESIZE_MAX_DELAY {
+                            trace!("Frame size changed to {width_in}x{height_in}, waiting.");
+                            continue;
+                        }
+                    }
+                    _ => resize = None,
+                }
+                // e.g. a screen of the host has been rotated or a window resized
+                if layout_size != Some((width_in, height_in)) {
+                    layout_size = Some((width_in, height_in));
+                    send_message(
+                        &mut sender,
+                        MessageOutbound::CapturableLayout(CapturableLayout::new(
+                            width_in, height_in,
+                        )),
+                    );
+                }
                 let scale =
                     (max_width as f64 / width_in as f64).min(max_height as f64 / height_in as f64);
-                // limit video to 4K
-                let scale_max = (3840.0 / width_in as f64).min(2160.0 / height_in as f64);
-                let scale = scale.min(scale_max);
+                // decoders fail on frames larger than their level allows
+                let scale_level = max_level.scale_to_fit(width_in, height_in);
+                let limited = scale_level < scale.min(1.0);
+                let scale = scale.min(scale_level);
                 let mut width_out = width_in;
                 let mut height_out = height_in;
                 if scale < 1.0 {
@@ -409,39 +2132,139 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
                         .unwrap()
                         .check_size(width_in, height_in, width_out, height_out)
                 {
+                    let broadcast = broadcast
+                        .as_ref()
+                        .filter(|b| b.claim_source(address))
+                        .cloned();
+                    broadcasting = broadcast.is_some();
+                    status.lock().unwrap().broadcasting = broadcasting;
+                    if let Some(broadcast) = broadcast.as_ref() {
+                        broadcast.new_video();
+                    }
                     send_message(&mut sender, MessageOutbound::NewVideo);
-                    let mut sender = sender.clone();
-                    let res = VideoEncoder::new(
-                        width_in,
-                        height_in,
+                    if limited {
+                        info!(
+                            "Scaling video down from {width_in}x{height_in} to \
+                            {width_out}x{height_out} to fit H.264 level {max_level}."
+                        );
+                        send_message(
+                            &mut sender,
+                            MessageOutbound::ResolutionLimited(ResolutionLimit {
+                                width_in,
+                                height_in,
+                                width_out,
+                                height_out,
+                                level: max_level.to_string(),
+                            }),
+                        );
+                    }
+                    let encoder_options = encoder_options.with_level(
                         width_out,
                         height_out,
-                        move |data| {
-                            if let Err(err) = sender.send_video(data) {
-                                warn!("Failed to send video frame: {err}!");
-                            }
-                        },
-                        encoder_options,
+                        1.0 / frame_duration.as_secs_f64(),
+                        max_level,
                     );
+                    let mut sender = sender.clone();
+                    let send_counters = frame_counter.send_counters();
+                    let video_bytes = video_bytes.clone();
+                    let sent_bytes = sent_bytes.clone();
+                    let write_data = move |data: &[u8]| {
+                        let _send_span = debug_span!("send", bytes = data.len()).entered();
+                        let queue_depth = sender.queue_depth();
+                        let send_start = Instant::now();
+                        let res = sender.send_video(data);
+                        send_counters.record(res.is_ok(), send_start.elapsed(), queue_depth);
+                        if res.is_ok() {
+                            video_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+                            sent_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+                        }
+                        if let Err(err) = res {
+                            warn!("Failed to send video frame: {err}!");
+                        }
+                        if let Some(broadcast) = broadcast.as_ref() {
+                            broadcast.publish(data);
+                        }
+                    };
+                    let standby = if encoder_standby && video_encoder.is_none() {
+                        VideoEncoder::take_standby(
+                            width_in,
+                            height_in,
+                            width_out,
+                            height_out,
+                            write_data.clone(),
+                            encoder_options,
+                        )
+                    } else {
+                        None
+                    };
+                    let res = match standby {
+                        Some(encoder) => {
+                            debug!("Using standby video encoder.");
+                            Ok(encoder)
+                        }
+                        None => VideoEncoder::new(
+                            width_in,
+                            height_in,
+                            width_out,
+                            height_out,
+                            write_data,
+                            encoder_options,
+                        ),
+                    };
                     match res {
-                        Ok(r) => video_encoder = Some(r),
+                        Ok(r) => {
+                            let name = r.name();
+                            if let Some(name) = name.as_deref() {
+                                encoder_probe::check_frame_rate(
+                                    name,
+                                    width_out,
+                                    height_out,
+                                    1.0 / frame_duration.as_secs_f64(),
+                                );
+                            }
+                            status.lock().unwrap().encoder = name;
+                            video_encoder = Some(r);
+                        }
                         Err(e) => {
+                            status.lock().unwrap().encoder = None;
                             warn!("{}", e);
                             continue;
                         }
                     };
                 }
                 let video_encoder = video_encoder.as_mut().unwrap();
+                let encode_start = Instant::now();
                 video_encoder.encode(pixel_data);
+                last_encode = encode_start;
+                last_encode_duration = encode_start.elapsed();
+                frame_counter.encoded(last_encode_duration);
+                frame_span.record("encode_ms", last_encode_duration.as_secs_f64() * 1000.0);
             }
             // stop thread once the channel is closed
-            Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Some(mut video_encoder) = video_encoder.take() {
+                    if encoder_standby {
+                        video_encoder.park();
+                    } else {
+                        video_encoder.flush();
+                    }
+                }
+                if let Some(broadcast) = broadcast.as_ref() {
+                    broadcast.release_source(address);
+                }
+                // the rest of the last interval for the summary of the session
+                let mut status = status.lock().unwrap();
+                status.session_video.merge(&frame_counter.take());
+                status.video_bytes = sent_bytes.load(Ordering::Relaxed);
+                return;
+            }
         };
     }
 }
 
 pub struct WsWeylusReceiver {
     recv: tokio::sync::mpsc::Receiver<MessageInbound>,
+    runtime: tokio::runtime::Handle,
 }
 
 impl Iterator for WsWeylusReceiver {
@@ -454,12 +2277,24 @@ impl Iterator for WsWeylusReceiver {
 
 impl WeylusReceiver for WsWeylusReceiver {
     type Error = Infallible;
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Self::Item, RecvTimeoutError> {
+        let recv = tokio::time::timeout(timeout, self.recv.recv());
+        match self.runtime.block_on(recv) {
+            Ok(Some(message)) => Ok(Ok(message)),
+            Ok(None) => Err(RecvTimeoutError::Disconnected),
+            Err(_) => Err(RecvTimeoutError::Timeout),
+        }
+    }
 }
 
 pub enum WsMessage {
     Frame(Frame<'static>),
     Video(Vec<u8>),
+    /// Send the cached part of the video required to start decoding again.
+    ReplayVideo,
     MessageOutbound(MessageOutbound),
+    WebTransport(WebTransportVideo),
 }
 
 unsafe impl Send for WsMessage {}
@@ -480,12 +2315,150 @@ impl WeylusSender for WsWeylusSender {
     fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
         self.sender.blocking_send(WsMessage::Video(bytes.to_vec()))
     }
+
+    fn replay_video(&mut self) -> Result<(), Self::Error> {
+        self.sender.blocking_send(WsMessage::ReplayVideo)
+    }
+
+    fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+impl WsWeylusSender {
+    /// Deliver the video via the given WebTransport session instead of the websocket.
+    pub async fn attach_webtransport(&self, video: WebTransportVideo) {
+        if let Err(err) = self.sender.send(WsMessage::WebTransport(video)).await {
+            warn!("Failed to attach WebTransport session: {err}.");
+        }
+    }
+
+    /// Close the connection as the client lost access, the client handler stops once the client
+    /// acknowledges this.
+    pub async fn revoke(&self) {
+        let frame = Frame::close(CLOSE_POLICY_VIOLATION, b"access revoked");
+        if let Err(err) = self.sender.send(WsMessage::Frame(frame)).await {
+            warn!("Failed to close connection: {err}.");
+        }
+    }
+}
+
+/// Maximum number of messages combined into a single websocket frame.
+const MAX_BATCH_SIZE: usize = 64;
+
+/// Time clients are given to close the connection after being notified about the shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Close code "Going Away" as defined by RFC 6455.
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// Close code "Policy Violation" as defined by RFC 6455.
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+/// Messages shorter than this are not worth compressing.
+const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Compresses a message using raw deflate and wraps it in a box similar to the ones used by MP4.
+/// This way the client can tell compressed messages and video apart, as both are sent as binary
+/// frames.
+///
+/// fastwebsockets does not implement the permessage-deflate extension, that's why compression is
+/// negotiated and done on the application level.
+fn deflate_message(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(vec![0u8; 8], Compression::fast());
+    encoder
+        .write_all(data)
+        .expect("Writing into a Vec can not fail.");
+    let mut compressed = encoder.finish().expect("Writing into a Vec can not fail.");
+    let size = compressed.len() as u32;
+    compressed[0..4].copy_from_slice(&size.to_be_bytes());
+    compressed[4..8].copy_from_slice(b"wylz");
+    compressed
+}
+
+/// Seconds a client has to send its part of the encryption handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Agree on the keys to encrypt the websocket with, see [`encryption`].
+pub async fn encryption_handshake(
+    websocket: &mut WebSocket<TokioIo<Upgraded>>,
+    access_code: &str,
+) -> Result<(Sealer, Opener), String> {
+    let frame = tokio::time::timeout(HANDSHAKE_TIMEOUT, websocket.read_frame())
+        .await
+        .map_err(|_| "Client did not start the handshake in time.".to_string())?
+        .map_err(|err| format!("Failed to read handshake message: {err}."))?;
+    if frame.opcode != OpCode::Binary || frame.payload.len() != encryption::CLIENT_MESSAGE_SIZE {
+        return Err("Got invalid handshake message.".into());
+    }
+    let (answer, handshake) = encryption::accept(access_code, &frame.payload)?;
+    websocket
+        .write_frame(Frame::binary(answer.into()))
+        .await
+        .map_err(|err| format!("Failed to answer handshake: {err}."))?;
+    let frame = tokio::time::timeout(HANDSHAKE_TIMEOUT, websocket.read_frame())
+        .await
+        .map_err(|_| "Client did not confirm the handshake in time.".to_string())?
+        .map_err(|err| format!("Failed to read handshake confirmation: {err}."))?;
+    if frame.opcode != OpCode::Binary || frame.payload.len() != encryption::CONFIRMATION_SIZE {
+        return Err("Got invalid handshake confirmation.".into());
+    }
+    handshake.finish(&frame.payload)
+}
+
+/// Frame carrying the payload, sealed if the websocket is encrypted.
+fn sealed_frame(
+    sealer: &mut Option<Sealer>,
+    kind: MessageKind,
+    payload: Vec<u8>,
+) -> Frame<'static> {
+    match (sealer, kind) {
+        (Some(sealer), kind) => Frame::binary(sealer.seal(kind, &payload).into()),
+        (None, MessageKind::Text) => Frame::text(payload.into()),
+        (None, MessageKind::Binary) => Frame::binary(payload.into()),
+    }
+}
+
+/// Send video via WebTransport if available and via the websocket otherwise, returns false if the
+/// websocket has been closed.
+async fn send_video<S: tokio::io::AsyncWrite + Unpin>(
+    tx: &mut WebSocketWrite<S>,
+    webtransport: &mut Option<WebTransportVideo>,
+    sealer: &mut Option<Sealer>,
+    data: Vec<u8>,
+) -> bool {
+    if let Some(wt) = webtransport.as_mut() {
+        match wt.send(&data).await {
+            Ok(_) => return true,
+            Err(err) => {
+                warn!(
+                    "Failed to send video via WebTransport, falling back to \
+                    websocket: {err}"
+                );
+                *webtransport = None;
+            }
+        }
+    }
+    if let Err(err) = tx
+        .write_frame(sealed_frame(sealer, MessageKind::Binary, data))
+        .await
+    {
+        if let WebSocketError::ConnectionClosed = err {
+            return false;
+        }
+        warn!("Failed to send video frame: {err}");
+    }
+    true
 }
 
 pub fn weylus_websocket_channel(
     websocket: WebSocket<TokioIo<Upgraded>>,
     semaphore_shutdown: Arc<tokio::sync::Semaphore>,
+    compression: bool,
+    encryption: Option<(Sealer, Opener)>,
+    on_close: impl FnOnce() + Send + 'static,
 ) -> (WsWeylusSender, WsWeylusReceiver) {
+    let (mut sealer, mut opener) = encryption.unzip();
     let (rx, mut tx) = websocket.split(|ws| tokio::io::split(ws));
 
     let mut rx = FragmentCollectorRead::new(rx);
@@ -503,38 +2476,90 @@ pub fn weylus_websocket_channel(
                 Ok(())
             };
 
+            let mut sender_inbound = Some(sender_inbound);
+            let mut shutdown_deadline = None;
             loop {
                 let fut = rx.read_frame::<_, WebSocketError>(&mut send_fn);
 
-                let frame = tokio::select! {
-                    _ = semaphore_shutdown.acquire() => break,
-                    frame = fut => match frame {
-                        Ok(frame) => frame,
-                        Err(err) => {
-                            warn!("Invalid websocket frame: {err}.");
+                let frame = if let Some(deadline) = shutdown_deadline {
+                    match tokio::time::timeout_at(deadline, fut).await {
+                        Ok(Ok(frame)) => frame,
+                        Ok(Err(_)) => break,
+                        Err(_) => {
+                            debug!("Client did not acknowledge shutdown, closing connection.");
+                            let frame = Frame::close(CLOSE_GOING_AWAY, b"");
+                            sender_outbound.send(WsMessage::Frame(frame)).await.ok();
                             break;
+                        }
+                    }
+                } else {
+                    tokio::select! {
+                        _ = semaphore_shutdown.acquire() => {
+                            // Tell the client that the server is going away so it does not
+                            // treat this as a lost connection and wait for it to close the
+                            // connection. Meanwhile the client handler is stopped by closing its
+                            // channel, which flushes the video encoder.
+                            let msg = WsMessage::MessageOutbound(MessageOutbound::ServerShutdown);
+                            if sender_outbound.send(msg).await.is_err() {
+                                break;
+                            }
+                            sender_inbound = None;
+                            shutdown_deadline =
+                                Some(tokio::time::Instant::now() + SHUTDOWN_TIMEOUT);
+                            continue;
                         },
-                    },
+                        frame = fut => match frame {
+                            Ok(frame) => frame,
+                            Err(err) => {
+                                warn!("Invalid websocket frame: {err}.");
+                                break;
+                            },
+                        },
+                    }
                 };
-                match frame.opcode {
+                let text = match frame.opcode {
                     OpCode::Close => break,
-                    OpCode::Text => match serde_json::from_slice(&frame.payload) {
-                        Ok(msg) => {
-                            if let Err(err) = sender_inbound.send(msg).await {
-                                warn!("Failed to forward inbound message to WeylusClientHandler: {err}.");
-                            }
+                    OpCode::Text if opener.is_none() => frame.payload.to_vec(),
+                    OpCode::Text => {
+                        warn!("Got unencrypted message, closing connection.");
+                        break;
+                    }
+                    OpCode::Binary => match opener.as_mut().map(|o| o.open(&frame.payload)) {
+                        Some(Some((MessageKind::Text, text))) => text,
+                        Some(None) => {
+                            warn!("Failed to decrypt message, closing connection.");
+                            break;
                         }
-                        Err(err) => warn!("Failed to parse message: {err}"),
+                        _ => continue,
                     },
-                    _ => {}
+                    _ => continue,
+                };
+                match serde_json::from_slice(&text) {
+                    Ok(msg) => {
+                        let Some(sender_inbound) = sender_inbound.as_ref() else {
+                            continue;
+                        };
+                        if let Err(err) = sender_inbound.send(msg).await {
+                            warn!(
+                                "Failed to forward inbound message to WeylusClientHandler: {err}."
+                            );
+                        }
+                    }
+                    Err(err) => warn!("Failed to parse message: {err}"),
                 }
             }
+            on_close();
         });
     }
 
     tokio::spawn(async move {
-        loop {
-            let msg = if let Some(msg) = receiver_outbound.recv().await {
+        let mut webtransport: Option<WebTransportVideo> = None;
+        let mut preroll = Preroll::new();
+        let mut next_msg: Option<WsMessage> = None;
+        'send: loop {
+            let msg = if let Some(msg) = next_msg.take() {
+                msg
+            } else if let Some(msg) = receiver_outbound.recv().await {
                 msg
             } else {
                 break;
@@ -550,23 +2575,62 @@ pub fn weylus_websocket_channel(
                     }
                 }
                 WsMessage::Video(data) => {
-                    if let Err(err) = tx.write_frame(Frame::binary(data.into())).await {
-                        if let WebSocketError::ConnectionClosed = err {
-                            break;
+                    preroll.push(&data);
+                    if !send_video(&mut tx, &mut webtransport, &mut sealer, data).await {
+                        break;
+                    }
+                }
+                WsMessage::ReplayVideo => {
+                    for data in preroll.replay() {
+                        if !send_video(&mut tx, &mut webtransport, &mut sealer, data.to_vec()).await
+                        {
+                            break 'send;
                         }
-                        warn!("Failed to send video frame: {err}");
                     }
                 }
                 WsMessage::MessageOutbound(msg) => {
+                    // combine all messages that are already queued into a single frame
+                    let mut batch = vec![msg];
+                    while batch.len() < MAX_BATCH_SIZE {
+                        match receiver_outbound.try_recv() {
+                            Ok(WsMessage::MessageOutbound(msg)) => batch.push(msg),
+                            Ok(msg) => {
+                                next_msg = Some(msg);
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    let msg = if batch.len() == 1 {
+                        batch.pop().unwrap()
+                    } else {
+                        MessageOutbound::Batch(batch)
+                    };
                     let json_string = serde_json::to_string(&msg).unwrap();
-                    let data = json_string.as_bytes();
-                    if let Err(err) = tx.write_frame(Frame::text(data.into())).await {
+                    let frame = if compression && json_string.len() > COMPRESSION_THRESHOLD {
+                        let data = deflate_message(json_string.as_bytes());
+                        sealed_frame(&mut sealer, MessageKind::Binary, data)
+                    } else {
+                        sealed_frame(&mut sealer, MessageKind::Text, json_string.into_bytes())
+                    };
+                    if let Err(err) = tx.write_frame(frame).await {
                         if let WebSocketError::ConnectionClosed = err {
                             break;
                         }
                         warn!("Failed to send outbound message: {err}");
                     }
                 }
+                WsMessage::WebTransport(wt) => {
+                    debug!("Sending video via WebTransport.");
+                    webtransport = Some(wt);
+                    // the client can start decoding right away without restarting the video
+                    for data in preroll.replay() {
+                        if !send_video(&mut tx, &mut webtransport, &mut sealer, data.to_vec()).await
+                        {
+                            break 'send;
+                        }
+                    }
+                }
             }
         }
     });
@@ -577,6 +2641,86 @@ pub fn weylus_websocket_channel(
         },
         WsWeylusReceiver {
             recv: receiver_inbound,
+            runtime: tokio::runtime::Handle::current(),
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "got {value}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn buffer_target_follows_smoothness() {
+        let mut control = BufferControl::new(0.1);
+        assert_close(control.target, 0.1);
+        assert_close(control.set_smoothness(1.0), BufferControl::MAX_TARGET);
+        assert_close(control.set_smoothness(0.5), 0.3);
+        // out of range preferences are clamped
+        assert_close(control.set_smoothness(-1.0), 0.1);
+        assert_close(control.set_smoothness(2.0), BufferControl::MAX_TARGET);
+        assert_close(BufferControl::new(-1.0).target, 0.0);
+        assert_close(BufferControl::new(3.0).target, BufferControl::MAX_TARGET);
+    }
+
+    #[test]
+    fn lowest_latency_ignores_jitter() {
+        let mut control = BufferControl::new(0.05);
+        for i in 0..100 {
+            let level = if i % 2 == 0 { 0.0 } else { 1.0 };
+            assert_eq!(control.report_level(level), None);
+        }
+        assert_close(control.target, 0.05);
+    }
+
+    #[test]
+    fn jitter_raises_the_target() {
+        let mut control = BufferControl::new(0.1);
+        control.set_smoothness(0.5);
+        // the first level says nothing about jitter
+        assert_eq!(control.report_level(0.0), None);
+        let target = control.report_level(1.0).unwrap();
+        assert_close(target, 0.4);
+        let mut last = target;
+        for i in 0..100 {
+            let level = if i % 2 == 0 { 0.0 } else { 1.0 };
+            if let Some(target) = control.report_level(level) {
+                assert!(target > last);
+                last = target;
+            }
+        }
+        // compensation for jitter is limited
+        assert!(last > 0.75, "{last}");
+        assert!(last <= 0.3 + BufferControl::MAX_JITTER_TARGET + 1e-9);
+        // the target drops again once the level is steady
+        for _ in 0..200 {
+            if let Some(target) = control.report_level(0.3) {
+                assert!(target < last);
+                last = target;
+            }
+        }
+        assert!(last < 0.33, "{last}");
+    }
+
+    #[test]
+    fn small_changes_and_invalid_levels_are_not_reported() {
+        let mut control = BufferControl::new(0.1);
+        control.set_smoothness(0.5);
+        assert_eq!(control.report_level(0.2), None);
+        // raises the jitter by 0.001 and the target by as much
+        assert_eq!(control.report_level(0.21), None);
+        assert_close(control.target, 0.3);
+        for level in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(control.report_level(level), None);
+        }
+        assert_eq!(control.last_level, Some(0.21));
+        assert_close(control.jitter, 0.001);
+    }
+}
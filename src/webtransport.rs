@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+use wtransport::endpoint::endpoint_side::Server;
+use wtransport::tls::Sha256DigestFmt;
+use wtransport::{Connection, Endpoint, Identity, ServerConfig};
+
+use crate::websocket::WsWeylusSender;
+
+/// Size of the header prepended to each datagram/stream: generation (u32), index of the frame
+/// within the generation (u32), index of the fragment (u16), number of fragments (u16) and flags
+/// (u8).
+const HEADER_SIZE: usize = 13;
+
+/// Flag of frames the client can resume decoding at after losing frames.
+const FLAG_KEYFRAME: u8 = 1;
+
+/// Self signed certificates are valid for two weeks, a new one is created well before.
+const CERTIFICATE_ROTATION: Duration = Duration::from_secs(3600 * 24 * 7);
+
+/// Frames that need more datagrams than this are sent using a reliable stream instead. These are
+/// usually keyframes, loosing them would stall the video until the next keyframe arrives.
+const MAX_FRAGMENTS: usize = 64;
+
+#[derive(Debug)]
+pub struct WebTransportError(String);
+
+impl std::fmt::Display for WebTransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{}", s)
+    }
+}
+
+impl Error for WebTransportError {}
+
+/// Registry of websocket clients that may attach a WebTransport session, indexed by the token
+/// sent to them via the websocket.
+pub type WebTransportClients = Arc<Mutex<HashMap<String, WsWeylusSender>>>;
+
+pub struct WebTransportServer {
+    endpoint: Endpoint<Server>,
+    addr: SocketAddr,
+    pub port: u16,
+    cert_hash: Mutex<String>,
+    pub clients: WebTransportClients,
+}
+
+/// Configuration with a new self signed certificate and the hash of the certificate.
+fn server_config(addr: SocketAddr) -> Result<(ServerConfig, String), WebTransportError> {
+    // Browsers accept self signed certificates for WebTransport as long as they are valid for at
+    // most two weeks and the hash of the certificate is known to the client, the hash is passed
+    // to the client via index.html.
+    let identity = Identity::self_signed(["localhost"])
+        .map_err(|err| WebTransportError(format!("Failed to create certificate: {err}")))?;
+    let cert_hash = identity.certificate_chain().as_slice()[0]
+        .hash()
+        .fmt(Sha256DigestFmt::DottedHex);
+    let config = ServerConfig::builder()
+        .with_bind_address(addr)
+        .with_identity(identity)
+        .keep_alive_interval(Some(Duration::from_secs(3)))
+        .build();
+    Ok((config, cert_hash))
+}
+
+impl WebTransportServer {
+    pub fn new(addr: SocketAddr) -> Result<Self, WebTransportError> {
+        let (config, cert_hash) = server_config(addr)?;
+        let endpoint = Endpoint::server(config)
+            .map_err(|err| WebTransportError(format!("Failed to bind to socket: {err}")))?;
+        Ok(Self {
+            endpoint,
+            addr,
+            port: addr.port(),
+            cert_hash: Mutex::new(cert_hash),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Hash of the current certificate, pages loaded after the certificate was renewed get the
+    /// new one.
+    pub fn cert_hash(&self) -> String {
+        self.cert_hash.lock().unwrap().clone()
+    }
+
+    /// Replace the certificate before it expires, established sessions are kept.
+    fn renew_certificate(&self) {
+        let renewed = server_config(self.addr).and_then(|(config, cert_hash)| {
+            self.endpoint
+                .reload_config(config, false)
+                .map_err(|err| WebTransportError(format!("Failed to reload config: {err}")))?;
+            Ok(cert_hash)
+        });
+        match renewed {
+            Ok(cert_hash) => {
+                debug!("Renewed WebTransport certificate.");
+                *self.cert_hash.lock().unwrap() = cert_hash;
+            }
+            Err(err) => warn!("Failed to renew WebTransport certificate: {err}."),
+        }
+    }
+
+    pub async fn run(&self, notify_shutdown: Arc<tokio::sync::Notify>) {
+        let mut renew = tokio::time::interval_at(
+            tokio::time::Instant::now() + CERTIFICATE_ROTATION,
+            CERTIFICATE_ROTATION,
+        );
+        loop {
+            let incoming = tokio::select! {
+                incoming = self.endpoint.accept() => incoming,
+                _ = renew.tick() => {
+                    self.renew_certificate();
+                    continue;
+                }
+                _ = notify_shutdown.notified() => break,
+            };
+            let clients = self.clients.clone();
+            tokio::spawn(async move {
+                let request = match incoming.await {
+                    Ok(request) => request,
+                    Err(err) => {
+                        warn!("Failed to establish WebTransport session: {err}.");
+                        return;
+                    }
+                };
+                let token = url::Url::parse("https://localhost")
+                    .and_then(|base| base.join(request.path()))
+                    .ok()
+                    .and_then(|url| {
+                        url.query_pairs()
+                            .find(|(k, _)| k == "token")
+                            .map(|(_, v)| v.to_string())
+                    });
+                let sender = token.and_then(|t| clients.lock().unwrap().get(&t).cloned());
+                match sender {
+                    Some(sender) => match request.accept().await {
+                        Ok(connection) => {
+                            debug!(
+                                address = ?connection.remote_address(),
+                                "WebTransport session established."
+                            );
+                            sender
+                                .attach_webtransport(WebTransportVideo::new(connection))
+                                .await;
+                        }
+                        Err(err) => warn!("Failed to accept WebTransport session: {err}."),
+                    },
+                    None => {
+                        warn!("WebTransport session with invalid token.");
+                        request.forbidden().await;
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Sends the video stream of a single client via WebTransport.
+///
+/// Initialization segments and large frames are sent via unidirectional streams, all other frames
+/// are split into datagrams. Frames with missing datagrams are dropped by the client, which
+/// avoids head-of-line blocking on lossy networks. After losing a frame the client skips to the
+/// next keyframe, which it asks for.
+pub struct WebTransportVideo {
+    connection: Connection,
+    generation: u32,
+    index: u32,
+}
+
+fn is_init_segment(data: &[u8]) -> bool {
+    data.len() >= 8 && (&data[4..8] == b"ftyp" || &data[4..8] == b"moov")
+}
+
+impl WebTransportVideo {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            generation: 0,
+            index: 0,
+        }
+    }
+
+    fn header(&self, fragment: u16, fragments: u16, flags: u8) -> [u8; HEADER_SIZE] {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..4].copy_from_slice(&self.generation.to_be_bytes());
+        header[4..8].copy_from_slice(&self.index.to_be_bytes());
+        header[8..10].copy_from_slice(&fragment.to_be_bytes());
+        header[10..12].copy_from_slice(&fragments.to_be_bytes());
+        header[12] = flags;
+        header
+    }
+
+    async fn send_stream(&self, data: &[u8], flags: u8) -> Result<(), WebTransportError> {
+        let mut stream = self
+            .connection
+            .open_uni()
+            .await
+            .map_err(|err| WebTransportError(err.to_string()))?
+            .await
+            .map_err(|err| WebTransportError(err.to_string()))?;
+        stream
+            .write_all(&self.header(0, 1, flags))
+            .await
+            .map_err(|err| WebTransportError(err.to_string()))?;
+        stream
+            .write_all(data)
+            .await
+            .map_err(|err| WebTransportError(err.to_string()))?;
+        stream
+            .finish()
+            .await
+            .map_err(|err| WebTransportError(err.to_string()))
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), WebTransportError> {
+        if is_init_segment(data) {
+            self.generation = self.generation.wrapping_add(1);
+            self.index = 0;
+            return self.send_stream(data, 0).await;
+        }
+        self.index = self.index.wrapping_add(1);
+        let flags = if crate::preroll::is_keyframe(data) {
+            FLAG_KEYFRAME
+        } else {
+            0
+        };
+
+        let payload_size = self
+            .connection
+            .max_datagram_size()
+            .unwrap_or(0)
+            .saturating_sub(HEADER_SIZE);
+        if payload_size == 0 || data.len().div_ceil(payload_size) > MAX_FRAGMENTS {
+            return self.send_stream(data, flags).await;
+        }
+
+        let fragments = data.len().div_ceil(payload_size) as u16;
+        let mut datagram = Vec::with_capacity(HEADER_SIZE + payload_size);
+        for (i, chunk) in data.chunks(payload_size).enumerate() {
+            datagram.clear();
+            datagram.extend_from_slice(&self.header(i as u16, fragments, flags));
+            datagram.extend_from_slice(chunk);
+            self.connection
+                .send_datagram(&datagram)
+                .map_err(|err| WebTransportError(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
@@ -1,115 +1,284 @@
+use std::error::Error;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::error;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
 
 use crate::config::Config;
-use crate::video::EncoderOptions;
+use crate::control::ControlServer;
+use crate::h264_level::H264Level;
+use crate::input::dwell_click::DwellClickConfig;
+use crate::input::indicator::IndicatorConfig;
+use crate::input::pointer_filter::PointerFilterConfig;
+use crate::input::precision::PrecisionConfig;
+use crate::input::rate_limit::RateLimitConfig;
+use crate::input::sensors::SensorConfig;
+use crate::input::switch_control::SwitchConfig;
+use crate::oidc::OidcConfig;
+use crate::video::{EncoderOptions, EncoderTuning, VideoEncoder};
+use crate::watermark::Watermark;
 use crate::web::{Web2UiMessage, WebServerConfig, WebStartUpMessage};
 use crate::websocket::WeylusClientConfig;
 
-pub struct Weylus {
-    notify_shutdown: Arc<tokio::sync::Notify>,
-    web_thread: Option<std::thread::JoinHandle<()>>,
+/// Reasons for Weylus failing to start, each maps to a distinct exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartError {
+    InvalidConfig,
+    AddrInUse,
+    PermissionDenied,
+    NoEncoder,
+    Other,
 }
 
-impl Weylus {
-    pub fn new() -> Self {
-        Self {
-            notify_shutdown: Arc::new(tokio::sync::Notify::new()),
-            web_thread: None,
+impl StartError {
+    /// Exit code used if Weylus is run without gui, these are documented in the Readme.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::InvalidConfig => 2,
+            Self::AddrInUse => 3,
+            Self::PermissionDenied => 4,
+            Self::NoEncoder => 5,
         }
     }
+}
 
-    pub fn start(
-        &mut self,
-        config: &Config,
-        mut on_web_message: impl FnMut(Web2UiMessage) + Send + 'static,
-    ) -> bool {
-        let encoder_options = EncoderOptions {
-            #[cfg(target_os = "linux")]
-            try_vaapi: config.try_vaapi,
-            #[cfg(not(target_os = "linux"))]
-            try_vaapi: false,
-
-            #[cfg(any(target_os = "linux", target_os = "windows"))]
-            try_nvenc: config.try_nvenc,
-            #[cfg(not(any(target_os = "linux", target_os = "windows")))]
-            try_nvenc: false,
-
-            #[cfg(target_os = "macos")]
-            try_videotoolbox: config.try_videotoolbox,
-            #[cfg(not(target_os = "macos"))]
-            try_videotoolbox: false,
-
-            #[cfg(target_os = "windows")]
-            try_mediafoundation: config.try_mediafoundation,
-            #[cfg(not(target_os = "windows"))]
-            try_mediafoundation: false,
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidConfig => write!(f, "Invalid configuration."),
+            Self::AddrInUse => write!(f, "The address or port is already in use."),
+            Self::PermissionDenied => write!(f, "Permission denied."),
+            Self::NoEncoder => write!(f, "No H.264 encoder available, check your ffmpeg build."),
+            Self::Other => write!(f, "Failed to start the webserver."),
+        }
+    }
+}
+
+impl Error for StartError {}
+
+/// Configuration channels of a single running web server.
+#[derive(Clone)]
+struct InstanceReloader {
+    name: String,
+    web_config: Arc<watch::Sender<WebServerConfig>>,
+    client_config: Arc<watch::Sender<WeylusClientConfig>>,
+}
+
+/// Applies changes of the configuration to a running instance of Weylus.
+#[derive(Clone)]
+pub struct Reloader {
+    instances: Vec<InstanceReloader>,
+}
+
+impl Reloader {
+    /// Apply all settings that do not require a restart: authentication takes effect for new
+    /// connections and changed encoder settings restart the video encoders of running sessions.
+    pub fn reload(&self, config: &Config) -> bool {
+        let Some(configs) = all_web_configs(config) else {
+            return false;
         };
+        let mut changed = false;
+        for (web_server_config, weylus_client_config) in configs {
+            let Some(instance) = self
+                .instances
+                .iter()
+                .find(|i| i.name == web_server_config.instance_name)
+            else {
+                warn!(
+                    "Adding the instance {} requires restarting Weylus.",
+                    web_server_config.instance_name
+                );
+                continue;
+            };
+            changed |= instance.web_config.send_if_modified(|c| {
+                let changed = *c != web_server_config;
+                *c = web_server_config;
+                changed
+            });
+            changed |= instance.client_config.send_if_modified(|c| {
+                let changed = *c != weylus_client_config;
+                *c = weylus_client_config;
+                changed
+            });
+        }
+        if changed {
+            info!("Configuration reloaded.");
+        }
+        true
+    }
+}
+
+/// A single web server.
+struct Instance {
+    notify_shutdown: Arc<tokio::sync::Notify>,
+    web_thread: std::thread::JoinHandle<()>,
+    /// The address the web server listens on.
+    addr: SocketAddr,
+    reloader: InstanceReloader,
+}
+
+impl Instance {
+    fn start(
+        web_server_config: WebServerConfig,
+        weylus_client_config: WeylusClientConfig,
+        mut on_web_message: impl FnMut(Web2UiMessage) + Send + 'static,
+    ) -> Result<Self, StartError> {
+        let name = web_server_config.instance_name.clone();
+        let (web_config_sender, web_config_receiver) = watch::channel(web_server_config);
+        let (client_config_sender, client_config_receiver) = watch::channel(weylus_client_config);
 
         let (sender_ui, mut receiver_ui) = tokio::sync::mpsc::channel(100);
         let (sender_startup, receiver_startup) = tokio::sync::oneshot::channel();
+        let notify_shutdown = Arc::new(tokio::sync::Notify::new());
 
         let web_thread = crate::web::run(
             sender_ui,
             sender_startup,
-            self.notify_shutdown.clone(),
-            WebServerConfig {
-                bind_addr: SocketAddr::new(config.bind_address, config.web_port),
-                access_code: config.access_code.clone(),
-                custom_index_html: config.custom_index_html.clone(),
-                custom_access_html: config.custom_access_html.clone(),
-                custom_style_css: config.custom_style_css.clone(),
-                custom_lib_js: config.custom_lib_js.clone(),
-                #[cfg(target_os = "linux")]
-                enable_custom_input_areas: config.wayland_support,
-                #[cfg(not(target_os = "linux"))]
-                enable_custom_input_areas: false,
-            },
-            WeylusClientConfig {
-                encoder_options,
-                #[cfg(target_os = "linux")]
-                wayland_support: config.wayland_support,
-                no_gui: config.no_gui,
-            },
+            notify_shutdown.clone(),
+            web_config_receiver,
+            client_config_receiver,
         );
 
-        match receiver_startup.blocking_recv() {
-            Ok(WebStartUpMessage::Start) => (),
-            Ok(WebStartUpMessage::Error) => {
+        let addr = match receiver_startup.blocking_recv() {
+            Ok(WebStartUpMessage::Start(addr)) => addr,
+            Ok(WebStartUpMessage::Error(kind)) => {
                 if web_thread.join().is_err() {
                     error!("Webserver thread panicked.");
                 }
-                return false;
+                return Err(match kind {
+                    ErrorKind::AddrInUse => StartError::AddrInUse,
+                    ErrorKind::PermissionDenied => StartError::PermissionDenied,
+                    _ => StartError::Other,
+                });
             }
             Err(err) => {
                 error!("Error communicating with webserver thread: {}", err);
                 if web_thread.join().is_err() {
                     error!("Webserver thread panicked.");
                 }
-                return false;
+                return Err(StartError::Other);
             }
-        }
-        self.web_thread = Some(web_thread);
+        };
         std::thread::spawn(move || {
             while let Some(msg) = receiver_ui.blocking_recv() {
                 on_web_message(msg);
             }
         });
-        true
+        Ok(Self {
+            notify_shutdown,
+            web_thread,
+            addr,
+            reloader: InstanceReloader {
+                name,
+                web_config: Arc::new(web_config_sender),
+                client_config: Arc::new(client_config_sender),
+            },
+        })
     }
 
-    pub fn stop(&mut self) {
+    fn stop(self) {
         self.notify_shutdown.notify_one();
-        self.wait();
+        if self.web_thread.join().is_err() {
+            error!("Web thread panicked.");
+        }
+    }
+}
+
+/// Runs the main web server and all additional instances defined in the configuration, which are
+/// started and stopped together.
+pub struct Weylus {
+    instances: Vec<Instance>,
+    control_server: Option<ControlServer>,
+}
+
+impl Weylus {
+    pub fn new() -> Self {
+        Self {
+            instances: Vec::new(),
+            control_server: None,
+        }
+    }
+
+    /// The address the main web server listens on if Weylus is running, see `--find-free-port`.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.instances.first().map(|i| i.addr)
+    }
+
+    /// Returns a handle for reloading the configuration if Weylus is running.
+    pub fn reloader(&self) -> Option<Reloader> {
+        if self.instances.is_empty() {
+            return None;
+        }
+        Some(Reloader {
+            instances: self.instances.iter().map(|i| i.reloader.clone()).collect(),
+        })
     }
 
-    fn wait(&mut self) {
-        if let Some(t) = self.web_thread.take() {
-            if t.join().is_err() {
-                error!("Web thread panicked.");
+    pub fn start(
+        &mut self,
+        config: &Config,
+        on_web_message: impl FnMut(Web2UiMessage) + Clone + Send + 'static,
+    ) -> Result<(), StartError> {
+        let Some(configs) = all_web_configs(config) else {
+            return Err(StartError::InvalidConfig);
+        };
+        if !crate::video::h264_available() {
+            error!("No H.264 encoder found.");
+            return Err(StartError::NoEncoder);
+        }
+        // frames in GPU memory can not be watermarked
+        #[cfg(all(target_os = "linux", feature = "wayland"))]
+        crate::capturable::pipewire::set_dmabuf(
+            config.pipewire_dmabuf && configs.iter().all(|(_, c)| c.watermark.is_none()),
+        );
+        #[cfg(target_os = "macos")]
+        crate::capturable::screencapturekit::set_exclude_self(!config.capture_self);
+        if let Some((_, client_config)) = configs.first() {
+            if !config.no_encoder_probe {
+                crate::encoder_probe::start(client_config.encoder_options);
+            }
+            if client_config.encoder_standby {
+                // the default size and frame rate of the video requested by clients
+                let options = client_config.encoder_options.with_level(
+                    1920,
+                    1080,
+                    30.0,
+                    client_config.max_h264_level,
+                );
+                VideoEncoder::warm_up(1920, 1080, options);
+            }
+        }
+
+        for (web_server_config, weylus_client_config) in configs {
+            let name = web_server_config.instance_name.clone();
+            match Instance::start(
+                web_server_config,
+                weylus_client_config,
+                on_web_message.clone(),
+            ) {
+                Ok(instance) => {
+                    info!(instance = %name, address = %instance.addr, "Web server started.");
+                    self.instances.push(instance);
+                }
+                Err(err) => {
+                    error!("Failed to start instance {name}: {err}");
+                    self.stop();
+                    return Err(err);
+                }
             }
         }
+        self.control_server = Some(ControlServer::start(config.web_port));
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.control_server = None;
+        for instance in self.instances.drain(..) {
+            instance.stop();
+        }
     }
 }
 
@@ -118,3 +287,271 @@ impl Drop for Weylus {
         self.stop();
     }
 }
+
+/// Derive the configurations of all instances, returns None if any of them is invalid.
+pub fn all_web_configs(config: &Config) -> Option<Vec<(WebServerConfig, WeylusClientConfig)>> {
+    let configs = config.instance_configs();
+    for (i, (name, _)) in configs.iter().enumerate() {
+        if configs[..i].iter().any(|(n, _)| n == name) {
+            error!("There are multiple instances named {name}.");
+            return None;
+        }
+    }
+    configs
+        .iter()
+        .map(|(name, config)| web_configs(name, config))
+        .collect()
+}
+
+/// Derive the configuration of the web server and clients from Weylus' configuration, returns
+/// None if the configuration is invalid.
+fn web_configs(
+    instance_name: &str,
+    config: &Config,
+) -> Option<(WebServerConfig, WeylusClientConfig)> {
+    let encoder_options = EncoderOptions {
+        #[cfg(all(target_os = "linux", feature = "vaapi"))]
+        try_vaapi: config.try_vaapi,
+        #[cfg(not(all(target_os = "linux", feature = "vaapi")))]
+        try_vaapi: false,
+
+        #[cfg(all(
+            any(
+                target_os = "linux",
+                all(target_os = "windows", target_arch = "x86_64")
+            ),
+            feature = "nvenc"
+        ))]
+        try_nvenc: config.try_nvenc,
+        #[cfg(not(all(
+            any(
+                target_os = "linux",
+                all(target_os = "windows", target_arch = "x86_64")
+            ),
+            feature = "nvenc"
+        )))]
+        try_nvenc: false,
+
+        #[cfg(target_os = "macos")]
+        try_videotoolbox: config.try_videotoolbox,
+        #[cfg(not(target_os = "macos"))]
+        try_videotoolbox: false,
+
+        #[cfg(target_os = "windows")]
+        try_mediafoundation: config.try_mediafoundation,
+        #[cfg(not(target_os = "windows"))]
+        try_mediafoundation: false,
+
+        threads: config
+            .encoder_threads
+            .unwrap_or_else(EncoderOptions::default_threads),
+        slices: config
+            .encoder_slices
+            .or(config.encoder_threads)
+            .unwrap_or_else(EncoderOptions::default_threads),
+        tuning: {
+            let preset = config.latency_preset.unwrap_or_default().tuning();
+            EncoderTuning {
+                max_bitrate: config.bandwidth_cap.map_or(0, |cap| (cap * 1000.0) as _),
+                gop_size: config
+                    .keyframe_interval
+                    .map_or(preset.gop_size, |frames| frames as _),
+                intra_refresh: config.intra_refresh.into(),
+                ..preset
+            }
+        },
+    };
+    if config.keyframe_interval == Some(0) {
+        error!("The keyframe interval has to be at least 1 frame.");
+        return None;
+    }
+    if config
+        .bandwidth_cap
+        .is_some_and(|cap| cap.is_nan() || cap < 0.001)
+    {
+        error!("The bandwidth cap has to be at least 1 kbit/s.");
+        return None;
+    }
+
+    let oidc = match (
+        config.oidc_issuer.as_ref(),
+        config.oidc_client_id.as_ref(),
+        config.oidc_redirect_url.as_ref(),
+    ) {
+        (Some(issuer), Some(client_id), Some(redirect_url)) => Some(OidcConfig {
+            issuer: issuer.clone(),
+            client_id: client_id.clone(),
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url: redirect_url.clone(),
+            scopes: config
+                .oidc_scopes
+                .clone()
+                .unwrap_or_else(|| "openid profile".into()),
+            groups_claim: config
+                .oidc_groups_claim
+                .clone()
+                .unwrap_or_else(|| "groups".into()),
+            controller_group: config.oidc_controller_group.clone(),
+            viewer_group: config.oidc_viewer_group.clone(),
+        }),
+        (None, _, _) => None,
+        _ => {
+            error!("Logging in via OIDC requires a client id and a redirect url.");
+            return None;
+        }
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    if config.sensors == Some(crate::input::sensors::SensorMode::Joystick) {
+        error!("Tilting clients as a joystick is only supported on Linux.");
+        return None;
+    }
+    let orbit_keys = if config.sensor_orbit_keys.is_empty() {
+        SensorConfig::default_orbit_keys()
+    } else {
+        match config.sensor_orbit_keys.clone().try_into() {
+            Ok(keys) => keys,
+            Err(_) => {
+                error!("Exactly four shortcuts are required to orbit the viewport.");
+                return None;
+            }
+        }
+    };
+    let sensor_range = config.sensor_range.unwrap_or(30.0);
+    let sensor_step = config.sensor_step.unwrap_or(15.0);
+    if sensor_range <= 0.0 || sensor_step <= 0.0 {
+        error!("The range and step of tilting clients have to be positive.");
+        return None;
+    }
+
+    if config.encrypt_websocket && config.access_code.is_none() {
+        error!("Encrypting the websocket requires an access code.");
+        return None;
+    }
+
+    Some((
+        WebServerConfig {
+            instance_name: instance_name.to_string(),
+            bind_addr: SocketAddr::new(config.bind_address, config.web_port),
+            access_code: config.access_code.clone(),
+            custom_index_html: config.custom_index_html.clone(),
+            custom_access_html: config.custom_access_html.clone(),
+            custom_style_css: config.custom_style_css.clone(),
+            custom_lib_js: config.custom_lib_js.clone(),
+            #[cfg(target_os = "linux")]
+            enable_custom_input_areas: config.wayland_support
+                || crate::sandbox::is_sandboxed()
+                || crate::capturable::is_wayland_session(),
+            #[cfg(not(target_os = "linux"))]
+            enable_custom_input_areas: false,
+            enable_mdns: config.mdns,
+            oidc,
+            webtransport_port: config.webtransport_port,
+            find_free_port: config.find_free_port,
+            encrypt_websocket: config.encrypt_websocket,
+            approve_new_clients: config.approve_new_clients,
+            approve_loopback: config.approve_loopback,
+            upload_dir: config.upload_dir.clone(),
+            shared_dir: config.shared_dir.clone(),
+            schedule: config.schedule.clone(),
+            schedule_override: config.schedule_override,
+        },
+        WeylusClientConfig {
+            encoder_options,
+            max_h264_level: config.max_h264_level.unwrap_or_else(H264Level::default_max),
+            session_summary_dir: config.session_summary_dir.clone(),
+            min_buffer: config.latency_preset.unwrap_or_default().min_buffer(),
+            #[cfg(target_os = "linux")]
+            wayland_support: config.wayland_support
+                || crate::sandbox::is_sandboxed()
+                || crate::capturable::is_wayland_session(),
+            #[cfg(target_os = "linux")]
+            x11_windows: crate::capturable::x11::X11WindowOptions {
+                decorations: config.x11_window_decorations,
+                popups: config.x11_window_popups,
+            },
+            no_gui: config.no_gui,
+            capturable_filter: config.capturable.clone(),
+            capture_regions: config.capture_regions.clone(),
+            rotation: config.rotation,
+            wake_on_connect: config.wake_on_connect,
+            unlock_on_connect: config.unlock_on_connect,
+            unlock_secret: config.unlock_secret.clone(),
+            power_actions: config.power_actions.clone(),
+            mirror_notifications: config.mirror_notifications,
+            watermark: config.watermark.clone().map(|text| Watermark {
+                text,
+                position: config.watermark_position.unwrap_or_default(),
+            }),
+            hide_cursor_during_pen: config.hide_cursor_during_pen,
+            confine_pointer: config.confine_pointer,
+            #[cfg(target_os = "linux")]
+            xi2_master: config.xi2_master,
+            #[cfg(target_os = "linux")]
+            persist_uinput: config
+                .persist_uinput
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            inject_predicted_events: config.inject_predicted_events,
+            encoder_standby: config.encoder_standby,
+            eraser_policy: config.eraser.unwrap_or_default(),
+            input_backend: config.input_backend.unwrap_or_default(),
+            text_injection_policy: config.text_injection_filter.unwrap_or_default(),
+            activation_policy: config.window_activation.unwrap_or_default(),
+            presentation_mode: config.presentation_mode,
+            broadcast: config.broadcast,
+            laser_pointers: !config.no_laser_pointers,
+            replay_capturables: None,
+            control_indicator: IndicatorConfig {
+                style: config.control_indicator.unwrap_or_default(),
+                color: config
+                    .control_indicator_color
+                    .clone()
+                    .unwrap_or_else(|| "#ff3030".into()),
+            },
+            input_rate_limit: config.input_rate_limit.map(|rate| RateLimitConfig {
+                rate,
+                // at least one event has to fit into the bucket
+                burst: config.input_rate_burst.unwrap_or(rate).max(1.0),
+            }),
+            eraser_shortcut: config
+                .eraser_shortcut
+                .clone()
+                .unwrap_or_else(|| "KeyE".into()),
+            pointer_filter: PointerFilterConfig {
+                palm_rejection: config.palm_rejection_ms.map(Duration::from_millis),
+                palm_rejection_radius: config.palm_rejection_radius,
+                pen_down_pressure: config.pen_down_pressure.unwrap_or(0.0),
+                pen_hover_pressure: config.pen_hover_pressure.unwrap_or(0.0),
+                pen_scroll_button: config.pen_scroll_button,
+            },
+            input_profiles: config.input_profiles.clone(),
+            hooks: config.hooks.clone(),
+            shortcut_sets: config.shortcut_sets.clone(),
+            keypad: config.keypad.clone().unwrap_or_default(),
+            switch_control: SwitchConfig {
+                enabled: config.switch_control,
+                scan_interval: config.switch_scan_interval_ms.map(Duration::from_millis),
+                dwell: Duration::from_millis(config.switch_dwell_ms.unwrap_or(0)),
+                grid: config.switch_grid.unwrap_or(3),
+                levels: config.switch_levels.unwrap_or(3),
+                zones: config.switch_zones.clone(),
+            },
+            dwell_click: DwellClickConfig {
+                delay: config.dwell_click_ms.map(Duration::from_millis),
+                radius: config.dwell_click_radius.unwrap_or(0.01),
+                action: config.dwell_click_action.unwrap_or_default(),
+            },
+            sensors: SensorConfig {
+                mode: config.sensors,
+                range: sensor_range,
+                step: sensor_step,
+                orbit_keys,
+            },
+            precision: PrecisionConfig {
+                button: config.precision_button,
+                region_size: config.precision_region.unwrap_or(0.25),
+            },
+        },
+    ))
+}
@@ -2,6 +2,8 @@ interface Window {
     ManagedMediaSource: any;
 }
 
+declare var WebTransport: any;
+
 enum LogLevel {
     ERROR = 0,
     WARN,
@@ -20,12 +22,30 @@ let last_fps_calc: number = performance.now();
 
 let check_video: HTMLInputElement;
 
-function run(level: string) {
+let webtransport_port: number = 0;
+let webtransport_cert_hash: string = "";
+
+// frame rate used unless the user picked one, the server knows what its encoder can sustain
+let default_frame_rate: number = 30;
+
+// set once the server announced it is shutting down
+let server_shut_down = false;
+
+// whether the websocket has to be encrypted with keys derived from the access code
+let encrypt = false;
+
+function run(level: string, wt_port: number = 0, wt_cert_hash: string = "", frame_rate: number = 30, encrypt_websocket: boolean = false) {
+    encrypt = encrypt_websocket;
+    webtransport_port = wt_port;
+    webtransport_cert_hash = wt_cert_hash;
+    default_frame_rate = frame_rate;
     window.onload = () => {
         log_pre = document.getElementById("log") as HTMLPreElement;
         log_pre.textContent = "";
         log_level = LogLevel[level];
         fps_out = document.getElementById("fps") as HTMLOutputElement;
+        buffer_level_out = document.getElementById("buffer_level") as HTMLOutputElement;
+        buffer_target_out = document.getElementById("buffer_target") as HTMLOutputElement;
         check_video = document.getElementById("enable_video") as HTMLInputElement;
         window.addEventListener("error", (e: ErrorEvent | Event | UIEvent) => {
             if ((e as ErrorEvent).error) {
@@ -85,6 +105,17 @@ function calc_max_video_resolution(scale: number) {
     ];
 }
 
+// random id identifying this browser to the host, it keeps settings per client
+function client_id() {
+    let id = localStorage.getItem("client_id");
+    if (!id) {
+        let bytes = crypto.getRandomValues(new Uint8Array(8));
+        id = Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+        localStorage.setItem("client_id", id);
+    }
+    return id;
+}
+
 function fresh_canvas() {
     let canvas_old = document.getElementById("canvas");
     let canvas = document.createElement("canvas");
@@ -117,7 +148,12 @@ class Settings {
     scale_video_output: HTMLOutputElement;
     range_min_pressure: HTMLInputElement;
     check_aggressive_seek: HTMLInputElement;
+    range_smoothness: HTMLInputElement;
+    bandwidth_cap_input: HTMLInputElement;
     client_name_input: HTMLInputElement;
+    capture_region_input: HTMLInputElement;
+    rotation_select: HTMLSelectElement;
+    window_activation_select: HTMLSelectElement;
     visible: boolean;
     custom_input_areas: CustomInputAreas;
     settings: HTMLElement;
@@ -133,7 +169,12 @@ class Settings {
         this.scale_video_input = document.getElementById("scale_video") as HTMLInputElement;
         this.scale_video_output = this.scale_video_input.nextElementSibling as HTMLOutputElement;
         this.range_min_pressure = document.getElementById("min_pressure") as HTMLInputElement;
+        this.range_smoothness = document.getElementById("smoothness") as HTMLInputElement;
+        this.bandwidth_cap_input = document.getElementById("bandwidth_cap") as HTMLInputElement;
         this.client_name_input = document.getElementById("client_name") as HTMLInputElement;
+        this.capture_region_input = document.getElementById("capture_region") as HTMLInputElement;
+        this.rotation_select = document.getElementById("rotation") as HTMLSelectElement;
+        this.window_activation_select = document.getElementById("window_activation") as HTMLSelectElement;
         this.frame_rate_input.oninput = () => {
             this.frame_rate_output.value = Math.round(frame_rate_scale(this.frame_rate_input.valueAsNumber)).toString();
         }
@@ -227,14 +268,31 @@ class Settings {
         let upd_server_config = () => { this.save_settings(); this.send_server_config() };
         this.checks.get("uinput_support").onchange = upd_server_config;
         this.checks.get("capture_cursor").onchange = upd_server_config;
+        this.checks.get("client_cursor").onchange = () => {
+            upd_server_config();
+            cursor_overlay.update();
+        };
         this.scale_video_input.onchange = upd_server_config;
         this.client_name_input.onchange = upd_server_config;
+        this.capture_region_input.onchange = upd_server_config;
+        this.rotation_select.onchange = upd_server_config;
+        this.window_activation_select.onchange = upd_server_config;
         this.frame_rate_input.onchange = upd_server_config;
+        this.range_smoothness.onchange = upd_server_config;
+        this.bandwidth_cap_input.onchange = upd_server_config;
+        this.checks.get("frame_stamp").onchange = upd_server_config;
+        this.checks.get("laser_pointer").onchange = upd_server_config;
 
         document.getElementById("refresh").onclick = () => this.webSocket.send('"GetCapturableList"');
+        document.getElementById("show_thumbnails").onclick = () => {
+            this.webSocket.send('"GetCapturableThumbnails"');
+        };
         document.getElementById("custom_input_areas").onclick = () => {
             this.webSocket.send('"ChooseCustomInputAreas"');
         };
+        document.getElementById("precision_mode").onclick = () => {
+            this.webSocket.send('"TogglePrecisionMode"');
+        };
         this.capturable_select.onchange = () => this.send_server_config();
     }
 
@@ -243,14 +301,28 @@ class Settings {
         config["capturable_id"] = Number(this.capturable_select.value);
         for (const key of [
             "uinput_support",
-            "capture_cursor"])
+            "capture_cursor",
+            "client_cursor",
+            "frame_stamp",
+            "laser_pointer"])
             config[key] = this.checks.get(key).checked;
         let [w, h] = calc_max_video_resolution(this.scale_video_input.valueAsNumber);
         config["max_width"] = w;
         config["max_height"] = h;
         config["frame_rate"] = frame_rate_scale(this.frame_rate_input.valueAsNumber);
+        config["smoothness"] = this.range_smoothness.valueAsNumber;
+        if (this.bandwidth_cap_input.valueAsNumber > 0)
+            config["bandwidth_cap"] = this.bandwidth_cap_input.valueAsNumber;
         if (this.client_name_input.value)
             config["client_name"] = this.client_name_input.value;
+        if (this.window_activation_select.value)
+            config["window_activation"] = this.window_activation_select.value;
+        let region = this.capture_region();
+        if (region)
+            config["capture_region"] = region;
+        if (this.rotation_select.value)
+            config["rotation"] = this.rotation_select.value;
+        config["client_id"] = client_id();
         this.webSocket.send(JSON.stringify({ "Config": config }));
     }
 
@@ -261,16 +333,21 @@ class Settings {
         settings["frame_rate"] = frame_rate_scale(this.frame_rate_input.valueAsNumber).toString();
         settings["scale_video"] = this.scale_video_input.value;
         settings["min_pressure"] = this.range_min_pressure.value;
+        settings["smoothness"] = this.range_smoothness.value;
+        settings["bandwidth_cap"] = this.bandwidth_cap_input.value;
         settings["custom_input_areas"] = this.custom_input_areas;
         settings["client_name"] = this.client_name_input.value;
+        settings["capture_region"] = this.capture_region_input.value;
+        settings["rotation"] = this.rotation_select.value;
+        settings["window_activation"] = this.window_activation_select.value;
         localStorage.setItem("settings", JSON.stringify(settings));
     }
 
     load_settings() {
         let settings_string = localStorage.getItem("settings");
         if (settings_string === null) {
-            this.frame_rate_input.value = frame_rate_scale_inv(30).toString();
-            this.frame_rate_output.value = (30).toString();
+            this.frame_rate_input.value = frame_rate_scale_inv(default_frame_rate).toString();
+            this.frame_rate_output.value = default_frame_rate.toString();
             let [w, h] = calc_max_video_resolution(this.scale_video_input.valueAsNumber)
             this.scale_video_output.value = w + "x" + h;
             return;
@@ -285,7 +362,7 @@ class Settings {
             if (upd_limit)
                 this.frame_rate_input.value = frame_rate_scale_inv(upd_limit).toString();
             else
-                this.frame_rate_input.value = frame_rate_scale_inv(30).toString();
+                this.frame_rate_input.value = frame_rate_scale_inv(default_frame_rate).toString();
             this.frame_rate_output.value = Math.round(frame_rate_scale(this.frame_rate_input.valueAsNumber)).toString();
 
             let scale_video = settings["scale_video"];
@@ -298,6 +375,14 @@ class Settings {
             if (min_pressure)
                 this.range_min_pressure.value = min_pressure;
 
+            let smoothness = settings["smoothness"];
+            if (smoothness)
+                this.range_smoothness.value = smoothness;
+
+            let bandwidth_cap = settings["bandwidth_cap"];
+            if (bandwidth_cap)
+                this.bandwidth_cap_input.value = bandwidth_cap;
+
             this.custom_input_areas = settings["custom_input_areas"];
 
             if (this.checks.get("lefty").checked) {
@@ -329,12 +414,35 @@ class Settings {
             if (client_name)
                 this.client_name_input.value = client_name;
 
+            let window_activation = settings["window_activation"];
+            if (window_activation)
+                this.window_activation_select.value = window_activation;
+
+            let capture_region = settings["capture_region"];
+            if (capture_region)
+                this.capture_region_input.value = capture_region;
+
+            let rotation = settings["rotation"];
+            if (rotation)
+                this.rotation_select.value = rotation;
+
         } catch {
             log(LogLevel.DEBUG, "Failed to load settings.")
             return;
         }
     }
 
+    // region entered as X,Y,Width,Height, anything else captures everything
+    capture_region() {
+        let parts = this.capture_region_input.value.split(",").map((p) => Number(p.trim()));
+        if (parts.length != 4 || !parts.every((p) => Number.isInteger(p) && p >= 0))
+            return null;
+        let [x, y, width, height] = parts;
+        if (width == 0 || height == 0)
+            return null;
+        return { "x": x, "y": y, "width": width, "height": height };
+    }
+
     stretched_video() {
         return this.checks.get("stretch").checked
     }
@@ -374,6 +482,33 @@ class Settings {
         else if (current_selection)
             // Can't find the window, so don't select anything
             this.capturable_select.value = "";
+        // the ids of the thumbnails are outdated
+        document.getElementById("capturable_thumbnails").innerText = "";
+    }
+
+    onCapturableThumbnails(thumbnails: (string | null)[]) {
+        let container = document.getElementById("capturable_thumbnails");
+        container.innerText = "";
+        thumbnails.forEach((thumbnail, i) => {
+            let option = this.capturable_select.options[i];
+            if (!thumbnail || !option)
+                return;
+            let img = document.createElement("img");
+            img.src = thumbnail;
+            img.title = option.textContent;
+            img.dataset.id = String(i);
+            if (this.capturable_select.value === String(i))
+                img.classList.add("selected");
+            // switch without reconnecting or resending the configuration
+            img.onclick = () => this.webSocket.send(JSON.stringify({ "SwitchCapturable": i }));
+            container.appendChild(img);
+        });
+    }
+
+    onCapturableSwitched(id: number) {
+        this.capturable_select.value = String(id);
+        document.querySelectorAll<HTMLImageElement>("#capturable_thumbnails img").forEach(
+            (img) => img.classList.toggle("selected", img.dataset.id === String(id)));
     }
 
     toggle_energysaving(energysaving: boolean) {
@@ -401,6 +536,247 @@ class Settings {
 
 let settings: Settings;
 let debug_overlay: HTMLElement;
+let cursor_overlay: CursorOverlay;
+let shortcut_bar: ShortcutBar;
+
+// Buttons pressing the keyboard shortcuts of one of the sets defined on the host.
+// label of a shortcut or key of the keypad in the language of the browser
+function localized_label(item: any): string {
+    let labels = item["labels"] || {};
+    let language = navigator.language;
+    return labels[language] || labels[language.split("-")[0]] || item["label"];
+}
+
+class ShortcutBar {
+    webSocket: WebSocket;
+    bar: HTMLDivElement;
+    set_select: HTMLSelectElement;
+    buttons: HTMLSpanElement;
+    sets: any[] = [];
+
+    constructor(webSocket: WebSocket) {
+        this.webSocket = webSocket;
+        this.bar = document.getElementById("shortcut_bar") as HTMLDivElement;
+        this.set_select = document.getElementById("shortcut_set") as HTMLSelectElement;
+        this.buttons = document.getElementById("shortcuts") as HTMLSpanElement;
+        this.set_select.onchange = () => {
+            localStorage.setItem("shortcut_set", this.set_select.value);
+            this.show_set(this.set_select.selectedIndex);
+        };
+    }
+
+    set_sets(sets: any[]) {
+        this.sets = sets;
+        this.set_select.innerText = "";
+        sets.forEach((set) => {
+            let option = document.createElement("option");
+            option.value = set["name"];
+            option.innerText = set["name"];
+            this.set_select.appendChild(option);
+        });
+        let index = Math.max(sets.findIndex((set) => set["name"] == localStorage.getItem("shortcut_set")), 0);
+        this.set_select.selectedIndex = index;
+        this.set_select.classList.toggle("hide", sets.length < 2);
+        this.bar.classList.toggle("hide", sets.length == 0);
+        this.show_set(index);
+    }
+
+    show_set(index: number) {
+        this.buttons.innerText = "";
+        let set = this.sets[index];
+        if (!set)
+            return;
+        set["shortcuts"].forEach((shortcut, i) => {
+            let button = document.createElement("button");
+            button.innerText = localized_label(shortcut);
+            button.title = shortcut["keys"];
+            button.onclick = () => this.webSocket.send(JSON.stringify(
+                { "RunShortcut": { "set": index, "shortcut": i } }
+            ));
+            this.buttons.appendChild(button);
+        });
+    }
+}
+
+// Draws the cursor of the host on top of the video, this way the cursor stays responsive even if
+// the video lags behind.
+class CursorOverlay {
+    img: HTMLImageElement;
+    video: HTMLVideoElement;
+    shape: any;
+    x: number;
+    y: number;
+
+    constructor(video: HTMLVideoElement) {
+        this.img = document.getElementById("cursor") as HTMLImageElement;
+        this.video = video;
+        this.shape = null;
+        this.x = -1;
+        this.y = -1;
+    }
+
+    set_shape(shape: any) {
+        this.shape = shape;
+        this.img.src = shape["image"];
+        this.update();
+    }
+
+    set_position(position: any) {
+        this.x = position["x"];
+        this.y = position["y"];
+        this.update();
+    }
+
+    update() {
+        let visible = settings.checks.get("client_cursor").checked && this.shape !== null
+            && this.x >= 0 && this.x <= 1 && this.y >= 0 && this.y <= 1;
+        this.img.classList.toggle("hide", !visible);
+        if (!visible)
+            return;
+        let rect = this.video.getBoundingClientRect();
+        this.img.style.left = (rect.left + (this.x - this.shape["xhot"]) * rect.width) + "px";
+        this.img.style.top = (rect.top + (this.y - this.shape["yhot"]) * rect.height) + "px";
+        this.img.style.width = (this.shape["width"] * rect.width) + "px";
+        this.img.style.height = (this.shape["height"] * rect.height) + "px";
+    }
+}
+// Time a notification of the host is shown for in milliseconds.
+const NOTIFICATION_DURATION = 6000;
+
+// Shows a notification of the host as a toast, notifications may contain markup which is shown
+// as is.
+function show_notification(notification: any) {
+    let toast = document.createElement("div");
+    toast.classList.add("notification");
+    let summary = document.createElement("b");
+    summary.innerText = notification["app"] ? notification["app"] + ": " + notification["summary"] : notification["summary"];
+    let body = document.createElement("div");
+    body.innerText = notification["body"];
+    toast.appendChild(summary);
+    toast.appendChild(body);
+    toast.onclick = () => toast.remove();
+    document.getElementById("notifications").appendChild(toast);
+    setTimeout(() => toast.remove(), NOTIFICATION_DURATION);
+}
+
+const POWER_ACTION_LABELS = {
+    "Lock": "Lock Screen",
+    "Suspend": "Suspend",
+    "Logout": "Log Out",
+};
+
+// Adds a button for each power action the host allows, requests are confirmed on the host.
+function show_power_actions(webSocket: WebSocket, actions: string[]) {
+    let section = document.getElementById("power_section");
+    section.innerText = "";
+    for (const action of actions) {
+        let button = document.createElement("button");
+        button.innerText = POWER_ACTION_LABELS[action] || action;
+        button.onclick = () => {
+            webSocket.send(JSON.stringify({ "PowerAction": action }));
+            log(LogLevel.INFO, "Requested " + button.innerText + ", waiting for confirmation on the host.");
+        };
+        section.appendChild(button);
+    }
+    section.classList.toggle("hide", actions.length == 0);
+    document.getElementById("power_title").classList.toggle("hide", actions.length == 0);
+}
+
+// orientation of the device is sent at most this often, in milliseconds
+const ORIENTATION_INTERVAL = 33;
+
+// Offers to send the orientation of the device once the host asked for it, the host maps tilting
+// to a joystick or to orbiting the viewport. iOS only grants access to the sensors after a tap.
+function show_sensors(webSocket: WebSocket, mode: string) {
+    let button = document.getElementById("sensors_toggle");
+    let active = false;
+    let last_sent = 0;
+    let on_orientation = (event: DeviceOrientationEvent) => {
+        if (event.beta == null || event.gamma == null)
+            return;
+        let now = performance.now();
+        if (now - last_sent < ORIENTATION_INTERVAL)
+            return;
+        last_sent = now;
+        webSocket.send(JSON.stringify({
+            "Orientation": { "alpha": event.alpha || 0, "beta": event.beta, "gamma": event.gamma }
+        }));
+    };
+    let set_active = (enabled: boolean) => {
+        active = enabled;
+        button.innerText = enabled ? "Stop Tilting" : "Start Tilting";
+        button.classList.toggle("active", enabled);
+    };
+    button.onclick = async () => {
+        if (active) {
+            window.removeEventListener("deviceorientation", on_orientation);
+            webSocket.send(JSON.stringify({ "Orientation": null }));
+            set_active(false);
+            return;
+        }
+        let request_permission = (DeviceOrientationEvent as any).requestPermission;
+        if (typeof request_permission == "function" && await request_permission() != "granted") {
+            log(LogLevel.WARN, "Access to the orientation of the device was denied.");
+            return;
+        }
+        // the orientation at this moment is the neutral one
+        window.addEventListener("deviceorientation", on_orientation);
+        set_active(true);
+    };
+    set_active(false);
+    document.getElementById("sensors_mode").innerText =
+        mode == "Joystick" ? "Tilt the device to move a joystick." : "Tilt the device to orbit the view.";
+    document.getElementById("sensors_section").classList.remove("hide");
+    document.getElementById("sensors_title").classList.remove("hide");
+}
+
+// Shows the area of the capturable highlighted by switch control.
+// bytes of video received since the last answer to a probe of the host
+let video_bytes_received = 0;
+
+// do not nag about the resolution more often than this
+const LOWER_RESOLUTION_HINT_INTERVAL = 60000;
+let last_lower_resolution_hint = 0;
+
+function show_network_quality(report: any) {
+    let signal = document.getElementById("signal");
+    signal.classList.remove("hide");
+    signal.querySelectorAll("span").forEach((bar, i) => bar.classList.toggle("off", i >= report["signal"]));
+    let rtt = report["rtt"];
+    signal.title = Math.round(report["throughput"]) + " kbit/s, " +
+        (rtt == null ? "?" : Math.round(rtt)) + " ms round trip, " +
+        Math.round(report["loss"] * 100) + "% loss";
+    let scale = settings.scale_video_input;
+    if (!report["lower_resolution"] || scale.valueAsNumber <= Number(scale.min)
+        || performance.now() - last_lower_resolution_hint < LOWER_RESOLUTION_HINT_INTERVAL)
+        return;
+    last_lower_resolution_hint = performance.now();
+    let toast = document.createElement("div");
+    toast.classList.add("notification");
+    toast.innerText = "The connection is too slow for the video. ";
+    let button = document.createElement("button");
+    button.innerText = "Lower resolution";
+    button.onclick = () => {
+        scale.value = Math.max(Number(scale.min), scale.valueAsNumber * 0.75).toString();
+        scale.dispatchEvent(new Event("input"));
+        scale.dispatchEvent(new Event("change"));
+        toast.remove();
+    };
+    toast.appendChild(button);
+    document.getElementById("notifications").appendChild(toast);
+    setTimeout(() => toast.remove(), NOTIFICATION_DURATION);
+}
+
+function show_switch_highlight(area: any) {
+    let highlight = document.getElementById("switch_highlight");
+    let rect = document.getElementById("video").getBoundingClientRect();
+    highlight.classList.remove("hide");
+    highlight.style.left = (rect.left + area["x"] * rect.width) + "px";
+    highlight.style.top = (rect.top + area["y"] * rect.height) + "px";
+    highlight.style.width = (area["w"] * rect.width) + "px";
+    highlight.style.height = (area["h"] * rect.height) + "px";
+}
+
 let last_pointer_data: Object;
 
 class PEvent {
@@ -805,19 +1181,49 @@ class PointerHandler {
         if (this.pointerTypes.includes(event.pointerType)) {
             let rect = (event.target as HTMLElement).getBoundingClientRect();
             const events = event_type === "pointermove" && typeof event.getCoalescedEvents === 'function' ? event.getCoalescedEvents() : [event];
-            for (let event of events) {
+            if (events.length > 1) {
+                // send coalesced events in a single message to reduce the per message overhead
                 this.webSocket.send(
                     JSON.stringify(
                         {
-                            "PointerEvent": new PEvent(
+                            "PointerEvents": events.map((event) => new PEvent(
                                 event_type,
                                 event,
                                 rect
+                            ))
+                        }
+                    )
+                );
+            } else {
+                this.webSocket.send(
+                    JSON.stringify(
+                        {
+                            "PointerEvent": new PEvent(
+                                event_type,
+                                events.length > 0 ? events[0] : event,
+                                rect
                             )
                         }
                     )
                 );
             }
+            if (event_type === "pointermove"
+                && settings.checks.get("predicted_events").checked
+                && typeof event.getPredictedEvents === 'function') {
+                const predicted = event.getPredictedEvents();
+                if (predicted.length > 0)
+                    this.webSocket.send(
+                        JSON.stringify(
+                            {
+                                "PredictedPointerEvents": predicted.map((event) => new PEvent(
+                                    event_type,
+                                    event,
+                                    rect
+                                ))
+                            }
+                        )
+                    );
+            }
             if (settings.visible) {
                 settings.toggle();
             }
@@ -894,6 +1300,20 @@ class KeyboardHandler {
     }
 }
 
+// Seconds of video to keep buffered, set by the server.
+let buffer_target: number = 0;
+let buffer_level_out: HTMLOutputElement;
+let buffer_target_out: HTMLOutputElement;
+
+function report_buffer_level(webSocket: WebSocket, video: HTMLVideoElement) {
+    if (webSocket.readyState == WebSocket.OPEN && video.buffered.length > 0) {
+        let level = Math.max(0, video.buffered.end(video.buffered.length - 1) - video.currentTime);
+        buffer_level_out.value = Math.round(level * 1000).toString();
+        webSocket.send(JSON.stringify({ "BufferLevel": level }));
+    }
+    setTimeout(() => report_buffer_level(webSocket, video), 500);
+}
+
 function frame_rate_stats() {
     let t = performance.now();
     let fps = Math.round(frame_count / (t - last_fps_calc) * 10000) / 10;
@@ -903,6 +1323,21 @@ function frame_rate_stats() {
     setTimeout(() => frame_rate_stats(), 1500);
 }
 
+// Codec of the video as signaled by the avcC box of the initialization segment, e.g. avc1.640034
+// for the high profile at level 5.2.
+function avc_codec(data: BufferSource): string {
+    let bytes = data instanceof ArrayBuffer ? new Uint8Array(data) :
+        new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+    for (let i = 0; i + 8 <= bytes.length; ++i) {
+        // "avcC" followed by version 1, the profile, its constraints and the level
+        if (bytes[i] == 0x61 && bytes[i + 1] == 0x76 && bytes[i + 2] == 0x63 && bytes[i + 3] == 0x43
+            && bytes[i + 4] == 1)
+            return "avc1." + Array.from(bytes.slice(i + 5, i + 8))
+                .map((b) => b.toString(16).padStart(2, "0").toUpperCase()).join("");
+    }
+    return null;
+}
+
 function handle_messages(
     webSocket: WebSocket,
     video: HTMLVideoElement,
@@ -915,8 +1350,12 @@ function handle_messages(
     let queue = [];
     const MAX_BUFFER_LENGTH = 20;  // In seconds
     function upd_buf() {
-        if (sourceBuffer == null)
-            return;
+        if (sourceBuffer == null) {
+            // the codec is only known once the initialization segment arrived
+            if (mediaSource == null || mediaSource.readyState != "open" || queue.length == 0)
+                return;
+            add_source_buffer(queue[0]);
+        }
         if (!sourceBuffer.updating && queue.length > 0 && mediaSource.readyState == "open") {
             let buffer_length = 0;
             if (sourceBuffer.buffered.length) {
@@ -939,64 +1378,860 @@ function handle_messages(
             }
         }
     }
-    webSocket.onmessage = (event: MessageEvent) => {
-        if (typeof event.data == "string") {
-            let msg = JSON.parse(event.data);
-            if (typeof msg == "string") {
-                if (msg == "NewVideo") {
-                    let MS = window.ManagedMediaSource ? window.ManagedMediaSource : window.MediaSource;
-                    mediaSource = new MS();
-                    sourceBuffer = null;
-                    video.src = URL.createObjectURL(mediaSource);
-                    mediaSource.addEventListener("sourceopen", (_) => {
-                        let mimeType = 'video/mp4; codecs="avc1.4D403D"';
-                        if (!MS.isTypeSupported(mimeType))
-                            mimeType = "video/mp4";
-                        sourceBuffer = mediaSource.addSourceBuffer(mimeType);
-                        sourceBuffer.addEventListener("updateend", upd_buf);
-                        // try to recover from errors by restarting the video
-                        if (sourceBuffer.onerror)
-                            sourceBuffer.onerror = () => settings.send_server_config();
-                    })
-                } else if (msg == "ConfigOk") {
-                    onConfigOk();
-                }
-            } else if (typeof msg == "object") {
-                if ("CapturableList" in msg)
-                    onCapturableList(msg["CapturableList"]);
-                else if ("Error" in msg)
-                    alert(msg["Error"]);
-                else if ("ConfigError" in msg) {
-                    onConfigError(msg["ConfigError"]);
-                } else if ("CustomInputAreas" in msg) {
-                    settings.custom_input_areas = msg["CustomInputAreas"];
-                    settings.checks.get("enable_custom_input_areas").checked = true;
-                    settings.save_settings();
-                }
-            }
-
-            return;
+    function new_video() {
+        let MS = window.ManagedMediaSource ? window.ManagedMediaSource : window.MediaSource;
+        mediaSource = new MS();
+        sourceBuffer = null;
+        queue = [];
+        video.src = URL.createObjectURL(mediaSource);
+        mediaSource.addEventListener("sourceopen", (_) => upd_buf());
+    }
+    function add_source_buffer(init_segment: BufferSource) {
+        let MS = window.ManagedMediaSource ? window.ManagedMediaSource : window.MediaSource;
+        let codec = avc_codec(init_segment);
+        let mimeType = 'video/mp4; codecs="' + (codec ? codec : "avc1.4D403D") + '"';
+        if (!MS.isTypeSupported(mimeType)) {
+            if (codec)
+                log(LogLevel.ERROR, "This browser can not decode the video (" + codec + "), " +
+                    "lower the maximum resolution or start Weylus with a lower --max-h264-level.");
+            mimeType = "video/mp4";
         }
-
-        // not a string -> got a video frame
-        queue.push(event.data);
+        sourceBuffer = mediaSource.addSourceBuffer(mimeType);
+        sourceBuffer.addEventListener("updateend", upd_buf);
+        // try to recover from errors by restarting the video
+        if (sourceBuffer.onerror)
+            sourceBuffer.onerror = () => settings.send_server_config();
+    }
+    function push_video(data: BufferSource) {
+        video_bytes_received += data.byteLength;
+        queue.push(data);
         upd_buf();
         frame_count += 1;
 
         // only seek if there is data available, some browsers choke otherwise
         if (video.seekable.length > 0) {
             let seek_time = video.seekable.end(video.seekable.length - 1);
-            if (video.readyState >= (settings.check_aggressive_seek.checked ? 3 : 4)
+            // keep as much video buffered as the server asks for, more only adds latency
+            if ((video.readyState >= (settings.check_aggressive_seek.checked ? 3 : 4)
+                && seek_time - video.currentTime > buffer_target + 0.05)
                 // but make sure to catch up if the video is more than 3 seconds behind
                 || seek_time - video.currentTime > 3) {
-                if (isFinite(seek_time))
-                    video.currentTime = seek_time;
+                let t = Math.max(seek_time - buffer_target, video.currentTime);
+                if (isFinite(t))
+                    video.currentTime = t;
                 else
                     log(LogLevel.WARN, "Failed to seek to end of video.")
             }
 
         }
     }
+    let webtransport_active = false;
+    function connect_webtransport(token: string) {
+        if (!webtransport_port || !("WebTransport" in window))
+            return;
+        let hash = new Uint8Array(webtransport_cert_hash.split(":").map((x) => parseInt(x, 16)));
+        let wt = new WebTransport(
+            "https://" + window.location.hostname + ":" + webtransport_port +
+            "/video?token=" + encodeURIComponent(token),
+            { serverCertificateHashes: [{ algorithm: "sha-256", value: hash }] }
+        );
+        let receiver = new WebTransportReceiver(
+            new_video, push_video, () => webSocket.send('"RequestKeyframe"')
+        );
+        wt.ready.then(() => {
+            webtransport_active = true;
+            log(LogLevel.INFO, "Receiving video via WebTransport.");
+            receiver.read_datagrams(wt.datagrams.readable.getReader());
+            // the server sends the initialization segment and the most recent keyframe via
+            // WebTransport once the session is attached
+            receiver.read_streams(wt.incomingUnidirectionalStreams.getReader());
+        }).catch((err: any) => log(LogLevel.WARN, "Failed to connect via WebTransport: " + err));
+        wt.closed.catch(() => { }).then(() => {
+            if (webtransport_active)
+                log(LogLevel.INFO, "WebTransport session closed, receiving video via websocket.");
+            webtransport_active = false;
+        });
+    }
+    function handle_message(msg: any) {
+        if (typeof msg == "string") {
+            if (msg == "NewVideo") {
+                // via WebTransport a new video is started once the initialization segment
+                // arrives
+                if (!webtransport_active)
+                    new_video();
+            } else if (msg == "ConfigOk") {
+                onConfigOk();
+            } else if (msg == "ConfirmTextInjection") {
+                let confirmed = window.confirm(
+                    "Keys were typed faster than humanly possible, this may be pasted text " +
+                    "that runs commands once it reaches the host. Send it anyway?");
+                webSocket.send(JSON.stringify({ "ConfirmTextInjection": confirmed }));
+            } else if (msg == "CaptureInterrupted") {
+                log(LogLevel.WARN, "Screen capture was interrupted, reconnecting...");
+                document.getElementById("video").classList.add("interrupted");
+            } else if (msg == "CaptureResumed") {
+                log(LogLevel.INFO, "Screen capture resumed.");
+                document.getElementById("video").classList.remove("interrupted");
+            } else if (msg == "ServerShutdown") {
+                log(LogLevel.INFO, "Weylus is shutting down.");
+                server_shut_down = true;
+                webSocket.close(1000);
+            }
+        } else if (typeof msg == "object") {
+            if ("Batch" in msg) {
+                for (let m of msg["Batch"])
+                    handle_message(m);
+            } else if ("CapturableList" in msg)
+                onCapturableList(msg["CapturableList"]);
+            else if ("CapturableThumbnails" in msg)
+                settings.onCapturableThumbnails(msg["CapturableThumbnails"]);
+            else if ("CapturableSwitched" in msg)
+                settings.onCapturableSwitched(msg["CapturableSwitched"]);
+            else if ("Error" in msg)
+                alert(msg["Error"]);
+            else if ("ConfigError" in msg) {
+                onConfigError(msg["ConfigError"]);
+            } else if ("CustomInputAreas" in msg) {
+                settings.custom_input_areas = msg["CustomInputAreas"];
+                settings.checks.get("enable_custom_input_areas").checked = true;
+                settings.save_settings();
+            } else if ("WebTransportToken" in msg) {
+                connect_webtransport(msg["WebTransportToken"]);
+            } else if ("BufferTarget" in msg) {
+                buffer_target = msg["BufferTarget"];
+                buffer_target_out.value = Math.round(buffer_target * 1000).toString();
+            } else if ("CursorShape" in msg) {
+                cursor_overlay.set_shape(msg["CursorShape"]);
+            } else if ("CursorPosition" in msg) {
+                cursor_overlay.set_position(msg["CursorPosition"]);
+            } else if ("Notification" in msg) {
+                show_notification(msg["Notification"]);
+            } else if ("PowerActions" in msg) {
+                show_power_actions(webSocket, msg["PowerActions"]);
+            } else if ("Sensors" in msg) {
+                show_sensors(webSocket, msg["Sensors"]);
+            } else if ("Probe" in msg) {
+                webSocket.send(JSON.stringify({ "ProbeAck": { "seq": msg["Probe"], "bytes": video_bytes_received } }));
+                video_bytes_received = 0;
+            } else if ("NetworkQuality" in msg) {
+                show_network_quality(msg["NetworkQuality"]);
+            } else if ("CapturableLayout" in msg) {
+                apply_capturable_layout(msg["CapturableLayout"]);
+            } else if ("ResolutionLimited" in msg) {
+                let limit = msg["ResolutionLimited"];
+                log(LogLevel.WARN, "The video is scaled down from " + limit["width_in"] + "x" +
+                    limit["height_in"] + " to " + limit["width_out"] + "x" + limit["height_out"] +
+                    ", the largest size of H.264 level " + limit["level"] + ". If this device " +
+                    "decodes a higher level, start Weylus with a higher --max-h264-level.");
+            } else if ("SwitchHighlight" in msg) {
+                show_switch_highlight(msg["SwitchHighlight"]);
+            } else if ("ShortcutSets" in msg) {
+                shortcut_bar.set_sets(msg["ShortcutSets"]);
+            } else if ("PrecisionMode" in msg) {
+                let enabled: boolean = msg["PrecisionMode"];
+                log(LogLevel.INFO, "Precision mode " + (enabled ? "on." : "off."));
+                document.getElementById("precision_mode").classList.toggle("active", enabled);
+            }
+        }
+    }
+    // Decompressing messages is asynchronous, chain everything to keep messages and video in
+    // order.
+    let pending: Promise<void> = Promise.resolve();
+    let enqueue = (f: () => void | Promise<void>) => {
+        pending = pending.then(f).catch((err) => log(LogLevel.WARN, "Failed to process message: " + err));
+    }
+    webSocket.onmessage = (event: MessageEvent) => {
+        if (typeof event.data == "string") {
+            let msg = JSON.parse(event.data);
+            enqueue(() => handle_message(msg));
+            return;
+        }
+
+        let data = event.data as ArrayBuffer;
+        if (is_compressed_message(data)) {
+            let text = inflate(data.slice(8));
+            enqueue(async () => handle_message(JSON.parse(await text)));
+            return;
+        }
+
+        // not a string -> got a video frame
+        enqueue(() => push_video(data));
+    }
+}
+
+// Compressed messages are wrapped in a box similar to the ones used by MP4, see
+// deflate_message in websocket.rs.
+function is_compressed_message(data: ArrayBuffer) {
+    return data.byteLength >= 8 && new TextDecoder().decode(new Uint8Array(data, 4, 4)) == "wylz";
+}
+
+async function inflate(data: ArrayBuffer) {
+    let stream = new Blob([data]).stream().pipeThrough(
+        new (window as any).DecompressionStream("deflate-raw")
+    );
+    return await new Response(stream).text();
+}
+
+function concat_arrays(arrays: Uint8Array[]) {
+    let data = new Uint8Array(arrays.reduce((n, a) => n + a.byteLength, 0));
+    let offset = 0;
+    for (let a of arrays) {
+        data.set(a, offset);
+        offset += a.byteLength;
+    }
+    return data;
+}
+
+// Reassembles the video stream sent via WebTransport. Every initialization segment starts a new
+// generation, frames within a generation are numbered starting at 1. Frames that are incomplete or
+// arrive too late are dropped. Frames depend on the ones before, so after a gap frames are held
+// back until the missing one arrives, if it does not the receiver skips to the next keyframe and
+// asks for one.
+class WebTransportReceiver {
+    static readonly HEADER_SIZE = 13;
+    static readonly FLAG_KEYFRAME = 1;
+    static readonly MAX_EARLY_FRAMES = 120;
+    // frames held back while waiting for a missing one and for how long
+    static readonly MAX_HELD_FRAMES = 10;
+    static readonly MAX_GAP_MS = 200;
+    generation: number = -1;
+    index: number = 0;
+    fragments: Map<string, { received: number, parts: Uint8Array[] }>;
+    early: { generation: number, index: number, keyframe: boolean, data: Uint8Array }[];
+    held: Map<number, Uint8Array>;
+    gap_timeout: number = null;
+    waiting_for_keyframe: boolean = false;
+    on_init: () => void;
+    on_data: (data: BufferSource) => void;
+    on_gap: () => void;
+
+    constructor(on_init: () => void, on_data: (data: BufferSource) => void, on_gap: () => void) {
+        this.fragments = new Map();
+        this.early = [];
+        this.held = new Map();
+        this.on_init = on_init;
+        this.on_data = on_data;
+        this.on_gap = on_gap;
+    }
+
+    on_frame(generation: number, index: number, keyframe: boolean, data: Uint8Array) {
+        if (index == 0) {
+            this.on_init();
+            this.on_data(data);
+            this.generation = generation;
+            this.index = 0;
+            this.fragments.clear();
+            this.clear_held();
+            this.waiting_for_keyframe = false;
+            let early = this.early.filter((f) => f.generation == generation);
+            early.sort((a, b) => a.index - b.index);
+            this.early = this.early.filter((f) => f.generation > generation);
+            for (let f of early)
+                this.on_frame(f.generation, f.index, f.keyframe, f.data);
+            return;
+        }
+        if (generation == this.generation) {
+            if (index <= this.index)
+                return;
+            if (keyframe) {
+                // nothing before a keyframe is needed to decode it
+                for (let i of this.held.keys())
+                    if (i < index)
+                        this.held.delete(i);
+                this.waiting_for_keyframe = false;
+                this.push(index, data);
+            } else if (this.waiting_for_keyframe) {
+                this.index = index;
+            } else if (index == this.index + 1) {
+                this.push(index, data);
+            } else {
+                this.held.set(index, data);
+                if (this.held.size > WebTransportReceiver.MAX_HELD_FRAMES)
+                    this.skip_to_keyframe();
+                else if (this.gap_timeout === null)
+                    this.gap_timeout = window.setTimeout(
+                        () => this.skip_to_keyframe(), WebTransportReceiver.MAX_GAP_MS
+                    );
+            }
+        } else if (generation > this.generation) {
+            // the initialization segment for this frame has not arrived yet
+            this.early.push({ generation: generation, index: index, keyframe: keyframe, data: data });
+            if (this.early.length > WebTransportReceiver.MAX_EARLY_FRAMES)
+                this.early.shift();
+        }
+    }
+
+    // Pass the frame on, followed by the held back frames that continue it.
+    push(index: number, data: Uint8Array) {
+        this.index = index;
+        this.on_data(data);
+        let next = this.held.get(this.index + 1);
+        while (next) {
+            this.held.delete(this.index + 1);
+            this.index += 1;
+            this.on_data(next);
+            next = this.held.get(this.index + 1);
+        }
+        if (this.held.size == 0)
+            this.clear_held();
+    }
+
+    clear_held() {
+        this.held.clear();
+        if (this.gap_timeout !== null)
+            window.clearTimeout(this.gap_timeout);
+        this.gap_timeout = null;
+    }
+
+    // The missing frame is lost, drop the frames depending on it and wait for the next keyframe.
+    skip_to_keyframe() {
+        if (this.held.size == 0)
+            return;
+        this.index = Math.max(this.index, ...this.held.keys());
+        this.clear_held();
+        this.waiting_for_keyframe = true;
+        log(LogLevel.DEBUG, "Lost video frames, waiting for the next keyframe.");
+        this.on_gap();
+    }
+
+    async read_datagrams(reader: any) {
+        while (true) {
+            let { value, done } = await reader.read();
+            if (done)
+                return;
+            let view = new DataView(value.buffer, value.byteOffset, value.byteLength);
+            let generation = view.getUint32(0);
+            let index = view.getUint32(4);
+            let fragment = view.getUint16(8);
+            let count = view.getUint16(10);
+            let keyframe = (view.getUint8(12) & WebTransportReceiver.FLAG_KEYFRAME) != 0;
+            let payload = (value as Uint8Array).subarray(WebTransportReceiver.HEADER_SIZE);
+            if (count == 1) {
+                this.on_frame(generation, index, keyframe, payload);
+                continue;
+            }
+            let key = generation + ":" + index;
+            let f = this.fragments.get(key);
+            if (!f) {
+                f = { received: 0, parts: new Array(count) };
+                this.fragments.set(key, f);
+            }
+            if (f.parts[fragment])
+                continue;
+            f.parts[fragment] = payload;
+            f.received += 1;
+            if (f.received == count) {
+                this.fragments.delete(key);
+                this.on_frame(generation, index, keyframe, concat_arrays(f.parts));
+            }
+            // forget about incomplete frames that are outdated
+            for (let k of this.fragments.keys()) {
+                let [g, i] = k.split(":").map(Number);
+                if (g < this.generation || (g == this.generation && i <= this.index))
+                    this.fragments.delete(k);
+            }
+        }
+    }
+
+    async read_streams(reader: any) {
+        while (true) {
+            let { value, done } = await reader.read();
+            if (done)
+                return;
+            this.read_stream(value);
+        }
+    }
+
+    async read_stream(stream: any) {
+        let reader = stream.getReader();
+        let chunks: Uint8Array[] = [];
+        while (true) {
+            let { value, done } = await reader.read();
+            if (done)
+                break;
+            chunks.push(value);
+        }
+        let data = concat_arrays(chunks);
+        let view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+        this.on_frame(
+            view.getUint32(0), view.getUint32(4),
+            (view.getUint8(12) & WebTransportReceiver.FLAG_KEYFRAME) != 0,
+            data.subarray(WebTransportReceiver.HEADER_SIZE)
+        );
+    }
+}
+
+// Encryption of the websocket for connections without TLS, this mirrors encryption.rs. Pages
+// served via plain http can not use WebCrypto, so SHA-512, edwards25519 and ChaCha20-Poly1305
+// are implemented here. Only the handshake uses the slow BigInt arithmetic of the curve.
+
+const SHA512_K = [
+    "428a2f98d728ae22", "7137449123ef65cd", "b5c0fbcfec4d3b2f", "e9b5dba58189dbbc",
+    "3956c25bf348b538", "59f111f1b605d019", "923f82a4af194f9b", "ab1c5ed5da6d8118",
+    "d807aa98a3030242", "12835b0145706fbe", "243185be4ee4b28c", "550c7dc3d5ffb4e2",
+    "72be5d74f27b896f", "80deb1fe3b1696b1", "9bdc06a725c71235", "c19bf174cf692694",
+    "e49b69c19ef14ad2", "efbe4786384f25e3", "0fc19dc68b8cd5b5", "240ca1cc77ac9c65",
+    "2de92c6f592b0275", "4a7484aa6ea6e483", "5cb0a9dcbd41fbd4", "76f988da831153b5",
+    "983e5152ee66dfab", "a831c66d2db43210", "b00327c898fb213f", "bf597fc7beef0ee4",
+    "c6e00bf33da88fc2", "d5a79147930aa725", "06ca6351e003826f", "142929670a0e6e70",
+    "27b70a8546d22ffc", "2e1b21385c26c926", "4d2c6dfc5ac42aed", "53380d139d95b3df",
+    "650a73548baf63de", "766a0abb3c77b2a8", "81c2c92e47edaee6", "92722c851482353b",
+    "a2bfe8a14cf10364", "a81a664bbc423001", "c24b8b70d0f89791", "c76c51a30654be30",
+    "d192e819d6ef5218", "d69906245565a910", "f40e35855771202a", "106aa07032bbd1b8",
+    "19a4c116b8d2d0c8", "1e376c085141ab53", "2748774cdf8eeb99", "34b0bcb5e19b48a8",
+    "391c0cb3c5c95a63", "4ed8aa4ae3418acb", "5b9cca4f7763e373", "682e6ff3d6b2b8a3",
+    "748f82ee5defb2fc", "78a5636f43172f60", "84c87814a1f0ab72", "8cc702081a6439ec",
+    "90befffa23631e28", "a4506cebde82bde9", "bef9a3f7b2c67915", "c67178f2e372532b",
+    "ca273eceea26619c", "d186b8c721c0c207", "eada7dd6cde0eb1e", "f57d4f7fee6ed178",
+    "06f067aa72176fba", "0a637dc5a2c898a6", "113f9804bef90dae", "1b710b35131c471b",
+    "28db77f523047d84", "32caab7b40c72493", "3c9ebe0a15c9bebc", "431d67c49c100d4c",
+    "4cc5d4becb3e42b6", "597f299cfc657e2a", "5fcb6fab3ad6faec", "6c44198c4a475817",
+].map((k) => BigInt("0x" + k));
+
+const SHA512_IV = [
+    "6a09e667f3bcc908", "bb67ae8584caa73b", "3c6ef372fe94f82b", "a54ff53a5f1d36f1",
+    "510e527fade682d1", "9b05688c2b3e6c1f", "1f83d9abfb41bd6b", "5be0cd19137e2179",
+].map((k) => BigInt("0x" + k));
+
+function sha512(...parts: Uint8Array[]): Uint8Array {
+    let data = concat_arrays(parts);
+    let length = data.length;
+    let padded = new Uint8Array(Math.ceil((length + 17) / 128) * 128);
+    padded.set(data);
+    padded[length] = 0x80;
+    new DataView(padded.buffer).setBigUint64(padded.length - 8, BigInt(length) * 8n);
+    let rotr = (x: bigint, n: bigint) => BigInt.asUintN(64, (x >> n) | (x << (64n - n)));
+    let h = SHA512_IV.slice();
+    let w: bigint[] = new Array(80);
+    let view = new DataView(padded.buffer);
+    for (let block = 0; block < padded.length; block += 128) {
+        for (let i = 0; i < 16; i++)
+            w[i] = view.getBigUint64(block + i * 8);
+        for (let i = 16; i < 80; i++) {
+            let s0 = rotr(w[i - 15], 1n) ^ rotr(w[i - 15], 8n) ^ (w[i - 15] >> 7n);
+            let s1 = rotr(w[i - 2], 19n) ^ rotr(w[i - 2], 61n) ^ (w[i - 2] >> 6n);
+            w[i] = BigInt.asUintN(64, w[i - 16] + s0 + w[i - 7] + s1);
+        }
+        let [a, b, c, d, e, f, g, hh] = h;
+        for (let i = 0; i < 80; i++) {
+            let s1 = rotr(e, 14n) ^ rotr(e, 18n) ^ rotr(e, 41n);
+            let ch = (e & f) ^ (~e & g);
+            let t1 = BigInt.asUintN(64, hh + s1 + ch + SHA512_K[i] + w[i]);
+            let s0 = rotr(a, 28n) ^ rotr(a, 34n) ^ rotr(a, 39n);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = BigInt.asUintN(64, s0 + maj);
+            [hh, g, f, e, d, c, b, a] = [g, f, e, BigInt.asUintN(64, d + t1), c, b, a, BigInt.asUintN(64, t1 + t2)];
+        }
+        h = [a, b, c, d, e, f, g, hh].map((x, i) => BigInt.asUintN(64, x + h[i]));
+    }
+    let digest = new Uint8Array(64);
+    let digest_view = new DataView(digest.buffer);
+    h.forEach((x, i) => digest_view.setBigUint64(i * 8, x));
+    return digest;
+}
+
+// Field, group order and curve constant of edwards25519.
+const ED_P = 2n ** 255n - 19n;
+const ED_L = 2n ** 252n + 27742317777372353535851937790883648493n;
+
+function ed_mod(a: bigint): bigint {
+    let r = a % ED_P;
+    return r < 0n ? r + ED_P : r;
+}
+
+function ed_pow(base: bigint, exp: bigint): bigint {
+    let result = 1n;
+    base = ed_mod(base);
+    while (exp > 0n) {
+        if (exp & 1n)
+            result = result * base % ED_P;
+        base = base * base % ED_P;
+        exp >>= 1n;
+    }
+    return result;
+}
+
+const ED_D = ed_mod(-121665n * ed_pow(121666n, ED_P - 2n));
+const ED_SQRT_M1 = ed_pow(2n, (ED_P - 1n) / 4n);
+
+// Point in extended coordinates (X : Y : Z : T) with x = X/Z, y = Y/Z and x*y = T/Z.
+type EdPoint = [bigint, bigint, bigint, bigint];
+
+const ED_IDENTITY: EdPoint = [0n, 1n, 1n, 0n];
+
+function ed_add(p: EdPoint, q: EdPoint): EdPoint {
+    let [x1, y1, z1, t1] = p;
+    let [x2, y2, z2, t2] = q;
+    let a = ed_mod((y1 - x1) * (y2 - x2));
+    let b = ed_mod((y1 + x1) * (y2 + x2));
+    let c = ed_mod(t1 * 2n * ED_D * t2);
+    let d = ed_mod(z1 * 2n * z2);
+    let e = b - a, f = d - c, g = d + c, h = b + a;
+    return [ed_mod(e * f), ed_mod(g * h), ed_mod(f * g), ed_mod(e * h)];
+}
+
+function ed_neg(p: EdPoint): EdPoint {
+    return [ed_mod(-p[0]), p[1], p[2], ed_mod(-p[3])];
+}
+
+function ed_mul(k: bigint, p: EdPoint): EdPoint {
+    let result = ED_IDENTITY;
+    while (k > 0n) {
+        if (k & 1n)
+            result = ed_add(result, p);
+        p = ed_add(p, p);
+        k >>= 1n;
+    }
+    return result;
+}
+
+function ed_is_identity(p: EdPoint): boolean {
+    return ed_mod(p[0]) == 0n && ed_mod(p[1] - p[2]) == 0n;
+}
+
+function bytes_to_bigint(bytes: Uint8Array): bigint {
+    let n = 0n;
+    for (let i = bytes.length - 1; i >= 0; i--)
+        n = (n << 8n) | BigInt(bytes[i]);
+    return n;
+}
+
+function ed_compress(p: EdPoint): Uint8Array {
+    let z_inv = ed_pow(p[2], ED_P - 2n);
+    let x = ed_mod(p[0] * z_inv);
+    let n = ed_mod(p[1] * z_inv) | ((x & 1n) << 255n);
+    let bytes = new Uint8Array(32);
+    for (let i = 0; i < 32; i++) {
+        bytes[i] = Number(n & 0xffn);
+        n >>= 8n;
+    }
+    return bytes;
+}
+
+function ed_decompress(bytes: Uint8Array): EdPoint | null {
+    let n = bytes_to_bigint(bytes);
+    let sign = n >> 255n;
+    let y = ed_mod(n & (2n ** 255n - 1n));
+    let u = ed_mod(y * y - 1n);
+    let v = ed_mod(ED_D * y * y + 1n);
+    let x = ed_mod(u * ed_pow(v, 3n) * ed_pow(u * ed_pow(v, 7n), (ED_P - 5n) / 8n));
+    let vxx = ed_mod(v * x * x);
+    if (vxx == ed_mod(-u))
+        x = ed_mod(x * ED_SQRT_M1);
+    else if (vxx != u)
+        return null;
+    if ((x & 1n) != sign)
+        x = ed_mod(-x);
+    return [x, y, 1n, ed_mod(x * y)];
+}
+
+const ED_BASE = ed_decompress(ed_compress([ed_mod(0n), ed_mod(4n * ed_pow(5n, ED_P - 2n)), 1n, 0n]));
+
+const SPAKE2_PASSWORD_LABEL = "weylus-spake2-password";
+const SPAKE2_M_LABEL = "weylus-spake2-M";
+const SPAKE2_N_LABEL = "weylus-spake2-N";
+const SPAKE2_CONFIRMATION_KEYS_INFO = "ConfirmationKeys";
+const SPAKE2_TRAFFIC_KEYS_INFO = "weylus-spake2-traffic-keys";
+const SPAKE2_CLIENT_IDENTITY = "weylus-client";
+const SPAKE2_SERVER_IDENTITY = "weylus-server";
+
+function utf8(s: string): Uint8Array {
+    return new TextEncoder().encode(s);
+}
+
+function spake2_hash_to_point(label: string): EdPoint {
+    for (let i = 0; i < 256; i++) {
+        let point = ed_decompress(sha512(utf8(label), new Uint8Array([i])).subarray(0, 32));
+        if (point) {
+            point = ed_mul(8n, point);
+            if (!ed_is_identity(point))
+                return point;
+        }
+    }
+    throw new Error("Failed to hash to a point.");
+}
+
+function spake2_password_scalar(access_code: string): bigint {
+    return bytes_to_bigint(sha512(utf8(SPAKE2_PASSWORD_LABEL), utf8(access_code))) % ED_L;
+}
+
+function scalar_bytes(n: bigint): Uint8Array {
+    let bytes = new Uint8Array(32);
+    for (let i = 0; i < 32; i++) {
+        bytes[i] = Number(n & 0xffn);
+        n >>= 8n;
+    }
+    return bytes;
+}
+
+function hmac_sha512(key: Uint8Array, ...parts: Uint8Array[]): Uint8Array {
+    let block = new Uint8Array(128);
+    block.set(key.length > 128 ? sha512(key) : key);
+    let inner = sha512(block.map((b) => b ^ 0x36), ...parts);
+    return sha512(block.map((b) => b ^ 0x5c), inner);
+}
+
+// HKDF-SHA512 without salt, 64 bytes of output.
+function hkdf_sha512(ikm: Uint8Array, info: string): Uint8Array {
+    let prk = hmac_sha512(new Uint8Array(0), ikm);
+    return hmac_sha512(prk, utf8(info), new Uint8Array([1]));
+}
+
+// Transcript of the handshake as in RFC 9382, see encryption.rs.
+function spake2_transcript(p_a: Uint8Array, p_b: Uint8Array, k: Uint8Array, w: bigint): Uint8Array {
+    let parts = [utf8(SPAKE2_CLIENT_IDENTITY), utf8(SPAKE2_SERVER_IDENTITY), p_a, p_b, k, scalar_bytes(w)];
+    return concat_arrays(parts.flatMap((part) => {
+        let len = new Uint8Array(8);
+        new DataView(len.buffer).setBigUint64(0, BigInt(part.length), true);
+        return [len, part];
+    }));
+}
+
+function random_scalar(): bigint {
+    return bytes_to_bigint(crypto.getRandomValues(new Uint8Array(64))) % ED_L;
+}
+
+function chacha20_block(key: Uint32Array, counter: number, nonce: Uint32Array, out: Uint32Array) {
+    let s = new Uint32Array(16);
+    s.set([0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    s.set(key, 4);
+    s[12] = counter;
+    s.set(nonce, 13);
+    let x = s.slice();
+    let qr = (a: number, b: number, c: number, d: number) => {
+        x[a] += x[b]; x[d] ^= x[a]; x[d] = (x[d] << 16) | (x[d] >>> 16);
+        x[c] += x[d]; x[b] ^= x[c]; x[b] = (x[b] << 12) | (x[b] >>> 20);
+        x[a] += x[b]; x[d] ^= x[a]; x[d] = (x[d] << 8) | (x[d] >>> 24);
+        x[c] += x[d]; x[b] ^= x[c]; x[b] = (x[b] << 7) | (x[b] >>> 25);
+    };
+    for (let i = 0; i < 10; i++) {
+        qr(0, 4, 8, 12); qr(1, 5, 9, 13); qr(2, 6, 10, 14); qr(3, 7, 11, 15);
+        qr(0, 5, 10, 15); qr(1, 6, 11, 12); qr(2, 7, 8, 13); qr(3, 4, 9, 14);
+    }
+    for (let i = 0; i < 16; i++)
+        out[i] = x[i] + s[i];
+}
+
+// Little endian words of the bytes, the length has to be a multiple of 4.
+function words(bytes: Uint8Array): Uint32Array {
+    let view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+    let w = new Uint32Array(bytes.length / 4);
+    for (let i = 0; i < w.length; i++)
+        w[i] = view.getUint32(i * 4, true);
+    return w;
+}
+
+function chacha20_xor(key: Uint32Array, counter: number, nonce: Uint32Array, data: Uint8Array): Uint8Array {
+    let out = new Uint8Array(data.length);
+    let block = new Uint32Array(16);
+    let block_bytes = new Uint8Array(64);
+    let block_view = new DataView(block_bytes.buffer);
+    for (let offset = 0; offset < data.length; offset += 64) {
+        chacha20_block(key, counter++, nonce, block);
+        for (let i = 0; i < 16; i++)
+            block_view.setUint32(i * 4, block[i], true);
+        let n = Math.min(64, data.length - offset);
+        for (let i = 0; i < n; i++)
+            out[offset + i] = data[offset + i] ^ block_bytes[i];
+    }
+    return out;
+}
+
+const POLY1305_P = 2n ** 130n - 5n;
+const POLY1305_MASK = 2n ** 130n - 1n;
+
+function poly1305(key: Uint8Array, data: Uint8Array): Uint8Array {
+    let r = bytes_to_bigint(key.subarray(0, 16)) & 0x0ffffffc0ffffffc0ffffffc0fffffffn;
+    let s = bytes_to_bigint(key.subarray(16, 32));
+    let h = 0n;
+    for (let offset = 0; offset < data.length; offset += 16) {
+        let block = data.subarray(offset, Math.min(offset + 16, data.length));
+        h = (h + (bytes_to_bigint(block) | (1n << BigInt(block.length * 8)))) * r;
+        // reduce partially, 2^130 = 5 mod p
+        h = (h & POLY1305_MASK) + (h >> 130n) * 5n;
+    }
+    h = (h % POLY1305_P + s) & (2n ** 128n - 1n);
+    let tag = new Uint8Array(16);
+    for (let i = 0; i < 16; i++) {
+        tag[i] = Number(h & 0xffn);
+        h >>= 8n;
+    }
+    return tag;
+}
+
+// Input of Poly1305 as used by ChaCha20-Poly1305 without additional data.
+function poly1305_input(ciphertext: Uint8Array): Uint8Array {
+    let padded = Math.ceil(ciphertext.length / 16) * 16;
+    let input = new Uint8Array(padded + 16);
+    input.set(ciphertext);
+    new DataView(input.buffer).setBigUint64(padded + 8, BigInt(ciphertext.length), true);
+    return input;
+}
+
+// ChaCha20-Poly1305 with counters as nonces, see Sealer and Opener in encryption.rs.
+class ChaChaPoly {
+    key: Uint32Array;
+    counter: number = 0;
+
+    constructor(key: Uint8Array) {
+        this.key = words(key);
+    }
+
+    nonce(): Uint32Array {
+        let nonce = new Uint8Array(12);
+        new DataView(nonce.buffer).setBigUint64(0, BigInt(this.counter), true);
+        return words(nonce);
+    }
+
+    seal(plaintext: Uint8Array): Uint8Array {
+        let nonce = this.nonce();
+        this.counter++;
+        let poly_key = chacha20_xor(this.key, 0, nonce, new Uint8Array(32));
+        let ciphertext = chacha20_xor(this.key, 1, nonce, plaintext);
+        return concat_arrays([ciphertext, poly1305(poly_key, poly1305_input(ciphertext))]);
+    }
+
+    // Returns null if the message has not been sealed with the same key and counter.
+    open(sealed: Uint8Array): Uint8Array | null {
+        if (sealed.length < 16)
+            return null;
+        let ciphertext = sealed.subarray(0, sealed.length - 16);
+        let nonce = this.nonce();
+        let poly_key = chacha20_xor(this.key, 0, nonce, new Uint8Array(32));
+        let tag = poly1305(poly_key, poly1305_input(ciphertext));
+        let diff = 0;
+        for (let i = 0; i < 16; i++)
+            diff |= tag[i] ^ sealed[sealed.length - 16 + i];
+        if (diff != 0)
+            return null;
+        this.counter++;
+        return chacha20_xor(this.key, 1, nonce, ciphertext);
+    }
+}
+
+// Client side of the handshake, see encryption.rs.
+class Spake2Client {
+    w: bigint;
+    x: bigint;
+    t: Uint8Array;
+
+    constructor(access_code: string, x: bigint = random_scalar()) {
+        this.w = spake2_password_scalar(access_code);
+        this.x = x;
+        let m = spake2_hash_to_point(SPAKE2_M_LABEL);
+        this.t = ed_compress(ed_add(ed_mul(this.x, ED_BASE), ed_mul(this.w, m)));
+    }
+
+    // Derive the keys from the answer of the server and the confirmation to send back, returns
+    // null if the access code is wrong.
+    finish(answer: Uint8Array): { sealer: ChaChaPoly, opener: ChaChaPoly, confirmation: Uint8Array } | null {
+        if (answer.length != 96)
+            return null;
+        let p_b = answer.subarray(0, 32);
+        let s = ed_decompress(p_b);
+        if (!s)
+            return null;
+        let n = spake2_hash_to_point(SPAKE2_N_LABEL);
+        let k = ed_mul(8n, ed_mul(this.x, ed_add(s, ed_neg(ed_mul(this.w, n)))));
+        let tt = spake2_transcript(this.t, p_b, ed_compress(k), this.w);
+        let hash = sha512(tt);
+        let confirmation_keys = hkdf_sha512(hash.subarray(32, 64), SPAKE2_CONFIRMATION_KEYS_INFO);
+        let traffic_keys = hkdf_sha512(hash.subarray(0, 32), SPAKE2_TRAFFIC_KEYS_INFO);
+        let expected = hmac_sha512(confirmation_keys.subarray(32, 64), tt);
+        let diff = 0;
+        for (let i = 0; i < 64; i++)
+            diff |= expected[i] ^ answer[32 + i];
+        if (diff != 0)
+            return null;
+        return {
+            sealer: new ChaChaPoly(traffic_keys.slice(0, 32)),
+            opener: new ChaChaPoly(traffic_keys.slice(32, 64)),
+            confirmation: hmac_sha512(confirmation_keys.subarray(0, 32), tt),
+        };
+    }
+}
+
+function hex(bytes: Uint8Array): string {
+    return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+function from_hex(s: string): Uint8Array {
+    return new Uint8Array(s.match(/../g).map((b) => parseInt(b, 16)));
+}
+
+// Known answer of the handshake with the access code "weylus", shared with encryption.rs.
+const SPAKE2_TEST_CLIENT_MESSAGE = "aec72d8bbaf8d1d40e24a0cba8ae1c8ac1763fff9030ba0c4d3048167482391a";
+const SPAKE2_TEST_ANSWER = "fde2783c1e329fcd6db362e2de4bc82096d486c8054604d9625f7e5c652f3b4d"
+    + "204d099f814dc7bbddec4b1ca7a48a6373c8b86be234c1b38be3b285dc00f21a"
+    + "dab49b2f40a5c39969552b904a1851158536c4504c4537dad54596e03e2cd21a";
+const SPAKE2_TEST_CLIENT_CONFIRMATION = "3b7b63a7f8b7871472d74b138550fb6c30bb69d9d4673b293c8da6fa91c24702"
+    + "dea46cde0efbffcbefcaab5f127753de6c098db43f06bd192725ffcab3c2ee71";
+const SPAKE2_TEST_SEALED = "7793b1e3d4856489b789411b7b2cb21520611c2630c6";
+
+// Check the known answer of the tests of encryption.rs, this can be run by node after compiling:
+// node -e 'eval(require("fs").readFileSync("www/static/lib.js", "utf8")); spake2_self_test()'
+function spake2_self_test() {
+    let x = bytes_to_bigint(sha512(utf8("x"))) % ED_L;
+    let client = new Spake2Client("weylus", x);
+    let channel = client.finish(from_hex(SPAKE2_TEST_ANSWER));
+    let results = [
+        hex(client.t) == SPAKE2_TEST_CLIENT_MESSAGE,
+        channel && hex(channel.confirmation) == SPAKE2_TEST_CLIENT_CONFIRMATION,
+        channel && hex(channel.sealer.seal(concat_arrays([new Uint8Array([0]), utf8("hello")]))) == SPAKE2_TEST_SEALED,
+    ];
+    if (!results.every((ok) => ok))
+        throw new Error("SPAKE2 self test failed: " + results);
+    console.log("SPAKE2 self test passed.");
+}
+
+// Encrypt the messages of the websocket, handlers assigned to onopen and onmessage only see
+// decrypted messages once the handshake succeeded. Messages are sent as text (0) or binary (1) like
+// unencrypted ones.
+function encrypt_websocket(webSocket: WebSocket, access_code: string) {
+    let client = new Spake2Client(access_code);
+    let channel: { sealer: ChaChaPoly, opener: ChaChaPoly, confirmation: Uint8Array } = null;
+    let onopen: (event: Event) => any = null;
+    let onmessage: (event: MessageEvent) => any = null;
+    Object.defineProperty(webSocket, "onopen", { set: (f) => onopen = f, get: () => onopen });
+    Object.defineProperty(webSocket, "onmessage", { set: (f) => onmessage = f, get: () => onmessage });
+    let send = webSocket.send.bind(webSocket);
+    // like sending before the websocket is open, sending before the handshake finished fails
+    webSocket.send = (data: string | ArrayBuffer) => {
+        if (!channel)
+            return;
+        if (typeof data == "string")
+            send(channel.sealer.seal(concat_arrays([new Uint8Array([0]), utf8(data)])));
+        else
+            send(channel.sealer.seal(concat_arrays([new Uint8Array([1]), new Uint8Array(data)])));
+    };
+    webSocket.addEventListener("open", () => send(client.t));
+    webSocket.addEventListener("message", (event: MessageEvent) => {
+        let data = new Uint8Array(event.data as ArrayBuffer);
+        if (!channel) {
+            channel = client.finish(data);
+            if (!channel) {
+                sessionStorage.removeItem("access_code");
+                alert("Wrong access code.");
+                webSocket.close();
+                return;
+            }
+            sessionStorage.setItem("access_code", access_code);
+            send(channel.confirmation);
+            if (onopen)
+                onopen.call(webSocket, new Event("open"));
+            return;
+        }
+        let plaintext = channel.opener.open(data);
+        if (!plaintext) {
+            log(LogLevel.ERROR, "Failed to decrypt message, closing connection.");
+            webSocket.close();
+            return;
+        }
+        let payload = plaintext.slice(1);
+        let message = plaintext[0] == 0 ? new TextDecoder().decode(payload) : payload.buffer;
+        if (onmessage)
+            onmessage.call(webSocket, new MessageEvent("message", { data: message }));
+    });
 }
 
 function check_apis() {
@@ -1022,21 +2257,53 @@ function check_apis() {
     }
 }
 
-function init() {
-    check_apis();
+function upload_file(file: File) {
+    let params = new URLSearchParams(window.location.search);
+    params.set("name", file.name);
+    fetch("/upload?" + params.toString(), { method: "POST", body: file }).then((response) => {
+        if (response.ok)
+            log(LogLevel.INFO, "Uploaded " + file.name + ".");
+        else
+            log(LogLevel.WARN, "Failed to upload " + file.name + ": " + response.status);
+    }).catch((err) => log(LogLevel.WARN, "Failed to upload " + file.name + ": " + err));
+}
 
+function connect_websocket(compression: boolean) {
     let protocol = document.location.protocol == "https:" ? "wss://" : "ws://";
+    let params = new URLSearchParams(window.location.search);
+    if (compression && "DecompressionStream" in window)
+        params.set("compression", "deflate-raw");
+    let access_code: string = null;
+    if (encrypt) {
+        // the access code is passed in the fragment so it never reaches the network
+        access_code = new URLSearchParams(window.location.hash.slice(1)).get("access_code")
+            || sessionStorage.getItem("access_code")
+            || prompt("Access code");
+        history.replaceState(null, "", window.location.pathname + window.location.search);
+        params.set("encrypted", "1");
+    }
     let webSocket = new WebSocket(
         protocol + window.location.hostname + ":" +
-        window.location.port + "/ws" + window.location.search
+        window.location.port + "/ws?" + params.toString()
     );
     webSocket.binaryType = "arraybuffer";
+    if (encrypt)
+        encrypt_websocket(webSocket, access_code || "");
+    return webSocket;
+}
+
+function init() {
+    check_apis();
+
+    let webSocket = connect_websocket(true);
 
     debug_overlay = document.getElementById("debug_overlay");
     settings = new Settings(webSocket);
+    shortcut_bar = new ShortcutBar(webSocket);
 
     let video = document.getElementById("video") as HTMLVideoElement;
     let canvas = document.getElementById("canvas") as HTMLCanvasElement;
+    cursor_overlay = new CursorOverlay(video);
 
     video.oncontextmenu = function(event) {
         event.preventDefault();
@@ -1049,6 +2316,19 @@ function init() {
         return false;
     };
 
+    // the access code is required to browse the shared files as well
+    let shared_files = document.getElementById("shared_files") as HTMLAnchorElement;
+    if (shared_files)
+        shared_files.href = "/files/" + window.location.search;
+
+    // dropped files are uploaded and dropped onto the window under the pointer of the host
+    document.body.ondragover = (event) => event.preventDefault();
+    document.body.ondrop = (event) => {
+        event.preventDefault();
+        for (const file of Array.from(event.dataTransfer.files))
+            upload_file(file);
+    };
+
     let toggle_fullscreen_btn = document.getElementById("fullscreen") as HTMLButtonElement;
 
     if (document.exitFullscreen) {
@@ -1073,9 +2353,17 @@ function init() {
         }
     }
     webSocket.onerror = () => handle_disconnect("Lost connection.");
-    webSocket.onclose = () => handle_disconnect("Connection closed.");
+    webSocket.onclose = () => {
+        if (server_shut_down) {
+            alert("Weylus has been shut down on the host.");
+            handle_disconnect("Weylus has been shut down.");
+        } else {
+            handle_disconnect("Connection closed.");
+        }
+    }
     window.onresize = () => {
         stretch_video();
+        send_viewport(webSocket);
         canvas.width = window.innerWidth * window.devicePixelRatio;
         canvas.height = window.innerHeight * window.devicePixelRatio;
         let [w, h] = calc_max_video_resolution(settings.scale_video_input.valueAsNumber);
@@ -1086,10 +2374,15 @@ function init() {
     video.disableRemotePlayback = true;
     video.onloadeddata = () => stretch_video();
     let is_connected = false;
+    // the screen may rotate without resizing the window, e.g. by 180 degrees
+    if (screen.orientation)
+        screen.orientation.addEventListener("change", () => send_viewport(webSocket));
     handle_messages(webSocket, video, () => {
         if (!is_connected) {
             new KeyboardHandler(webSocket);
             new PointerHandler(webSocket);
+            send_viewport(webSocket);
+            report_battery(webSocket);
             is_connected = true;
         }
     },
@@ -1099,6 +2392,7 @@ function init() {
     window.onunload = () => { webSocket.close(); }
     webSocket.onopen = function(event) {
         webSocket.send('"GetCapturableList"');
+        webSocket.send('"GetShortcutSets"');
         if (!settings.video_enabled())
             webSocket.send('"PauseVideo"');
 
@@ -1113,10 +2407,119 @@ function init() {
         };
     }
     frame_rate_stats();
+    report_buffer_level(webSocket, video);
+}
+
+function run_keypad(level: string, uinput_support: boolean, encrypt_websocket: boolean = false) {
+    encrypt = encrypt_websocket;
+    window.onload = () => {
+        log_pre = document.getElementById("log") as HTMLPreElement;
+        log_pre.textContent = "";
+        log_level = LogLevel[level];
+        init_keypad(uinput_support);
+    };
+}
+
+// The keypad only sends input, it takes the first capturable as target without capturing it.
+function init_keypad(uinput_support: boolean) {
+    let webSocket = connect_websocket(false);
+    let keypad = document.getElementById("keypad");
+    let show_keypad = (pad: any) => {
+        keypad.innerText = "";
+        keypad.style.gridTemplateColumns = "repeat(" + pad["columns"] + ", 1fr)";
+        pad["keys"].forEach((key: any, i: number) => {
+            let button = document.createElement("button");
+            button.innerText = localized_label(key);
+            button.style.gridColumn = "span " + key["span"];
+            button.onclick = () => webSocket.send(JSON.stringify({ "RunKeypadKey": i }));
+            keypad.appendChild(button);
+        });
+    };
+    let handle_message = (msg: any) => {
+        if (msg == "ServerShutdown") {
+            server_shut_down = true;
+            webSocket.close(1000);
+        } else if (typeof msg != "object") {
+            return;
+        } else if ("Batch" in msg) {
+            msg["Batch"].forEach(handle_message);
+        } else if ("Keypad" in msg) {
+            show_keypad(msg["Keypad"]);
+        } else if ("CapturableList" in msg) {
+            let config = {
+                "capturable_id": 0, "uinput_support": uinput_support, "capture_cursor": false,
+                "client_cursor": false, "frame_stamp": false, "max_width": 0, "max_height": 0,
+                "frame_rate": 0, "smoothness": 0, "input_only": true,
+            };
+            webSocket.send(JSON.stringify({ "Config": config }));
+        } else if ("Probe" in msg) {
+            webSocket.send(JSON.stringify({ "ProbeAck": { "seq": msg["Probe"], "bytes": 0 } }));
+        } else if ("Error" in msg) {
+            alert(msg["Error"]);
+        } else if ("ConfigError" in msg) {
+            alert(msg["ConfigError"]);
+        }
+    };
+    webSocket.onmessage = (event: MessageEvent) => {
+        if (typeof event.data == "string")
+            handle_message(JSON.parse(event.data));
+    };
+    webSocket.onopen = () => {
+        webSocket.send('"GetKeypad"');
+        webSocket.send('"GetCapturableList"');
+    };
+    webSocket.onclose = () => {
+        keypad.classList.add("disconnected");
+        log(LogLevel.WARN, server_shut_down ? "Weylus has been shut down on the host." : "Connection closed.");
+    };
+    window.onunload = () => { webSocket.close(); }
 }
 
 // object-fit: fill; <-- this is unfortunately not supported on iOS, so we use the following
 // workaround
+// Area the video is shown in and the rotation of the screen, pointers down while the device is
+// rotated are lifted by the host so strokes do not jump to where the pointer is on the new layout.
+function send_viewport(webSocket: WebSocket) {
+    if (webSocket.readyState != WebSocket.OPEN)
+        return;
+    let angle = screen.orientation ? screen.orientation.angle : 0;
+    webSocket.send(JSON.stringify({
+        "Viewport": {
+            "width": document.body.clientWidth, "height": document.body.clientHeight, "angle": angle
+        }
+    }));
+}
+
+// Sends the battery level whenever it changes, so the host is warned before the device dies.
+// Browsers do not expose the battery of the stylus.
+function report_battery(webSocket: WebSocket) {
+    let nav = navigator as any;
+    if (typeof nav.getBattery != "function")
+        return;
+    nav.getBattery().then((battery: any) => {
+        let send = () => {
+            if (webSocket.readyState == WebSocket.OPEN)
+                webSocket.send(JSON.stringify({
+                    "DeviceStatus": { "battery": battery.level, "charging": battery.charging }
+                }));
+        };
+        battery.addEventListener("levelchange", send);
+        battery.addEventListener("chargingchange", send);
+        send();
+    }).catch((err: any) => log(LogLevel.DEBUG, "Failed to query the battery: " + err));
+}
+
+// Suggests rotating the device if it is held differently than the capturable is oriented.
+function apply_capturable_layout(layout: any) {
+    let portrait = layout["orientation"] == "Portrait";
+    stretch_video();
+    let device_portrait = document.body.clientHeight > document.body.clientWidth;
+    if (portrait != device_portrait)
+        log(LogLevel.INFO, "The capturable is " + layout["width"] + "x" + layout["height"] +
+            ", rotating this device to " + (portrait ? "portrait" : "landscape") +
+            " shows it larger.");
+}
+
 function stretch_video() {
     let video = document.getElementById("video") as HTMLVideoElement;
     if (settings.stretched_video()) {